@@ -0,0 +1,262 @@
+/*
+ * pam_passcode: challenge-response OTP authentication for Linux-PAM
+ *
+ * The module shows the user a random challenge, prompts for the OTP their
+ * authenticator computes over it and checks the answer with the key stored
+ * for the user. Keys live in <keydir>/<user>, one line of the form
+ *
+ *     <algorithm id>:<hex key>
+ *
+ * readable by root only. Module options:
+ *
+ *     keydir=<dir>         directory of key files (default /etc/passcode/keys)
+ *     challenge_len=<n>    challenge bytes, 16 to 64 (default 32)
+ *     nullok               ignore users without a key file
+ */
+
+#define _GNU_SOURCE
+
+#include <ctype.h>
+#include <errno.h>
+#include <fcntl.h>
+#include <limits.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/random.h>
+#include <sys/stat.h>
+#include <syslog.h>
+#include <unistd.h>
+
+#define PAM_SM_AUTH
+#include <security/pam_ext.h>
+#include <security/pam_modules.h>
+
+#include "passcode.h"
+
+#define DEFAULT_KEYDIR "/etc/passcode/keys"
+#define DEFAULT_CHALLENGE_LEN 32
+#define MIN_CHALLENGE_LEN 16
+#define MAX_CHALLENGE_LEN 64
+#define MAX_KEY_LEN 128
+#define MAX_OTP_LEN 256
+
+struct options {
+    const char *keydir;
+    size_t challenge_len;
+    int nullok;
+};
+
+static int parse_options(pam_handle_t *pamh, int argc, const char **argv,
+                         struct options *opts)
+{
+    opts->keydir = DEFAULT_KEYDIR;
+    opts->challenge_len = DEFAULT_CHALLENGE_LEN;
+    opts->nullok = 0;
+
+    for (int i = 0; i < argc; i++) {
+        if (strncmp(argv[i], "keydir=", 7) == 0) {
+            opts->keydir = argv[i] + 7;
+        } else if (strncmp(argv[i], "challenge_len=", 14) == 0) {
+            char *end;
+            unsigned long len = strtoul(argv[i] + 14, &end, 10);
+            if (*end != '\0' || len < MIN_CHALLENGE_LEN || len > MAX_CHALLENGE_LEN) {
+                pam_syslog(pamh, LOG_ERR, "invalid option %s", argv[i]);
+                return -1;
+            }
+            opts->challenge_len = len;
+        } else if (strcmp(argv[i], "nullok") == 0) {
+            opts->nullok = 1;
+        } else {
+            pam_syslog(pamh, LOG_ERR, "unknown option %s", argv[i]);
+            return -1;
+        }
+    }
+    return 0;
+}
+
+static int hex_value(char c)
+{
+    if (c >= '0' && c <= '9')
+        return c - '0';
+    if (c >= 'a' && c <= 'f')
+        return c - 'a' + 10;
+    if (c >= 'A' && c <= 'F')
+        return c - 'A' + 10;
+    return -1;
+}
+
+/*
+ * Reads the user's key file. Returns PAM_SUCCESS, PAM_USER_UNKNOWN when
+ * there is no key file, or PAM_AUTHINFO_UNAVAIL when it cannot be used.
+ */
+static int read_key(pam_handle_t *pamh, const struct options *opts,
+                    const char *user, uint8_t *algorithm, uint8_t *key,
+                    size_t *key_len)
+{
+    char path[PATH_MAX];
+    char line[2 * MAX_KEY_LEN + 8];
+    struct stat st;
+    int ret = PAM_AUTHINFO_UNAVAIL;
+
+    if (strchr(user, '/') != NULL || strcmp(user, ".") == 0 || strcmp(user, "..") == 0) {
+        pam_syslog(pamh, LOG_ERR, "refusing user name %s", user);
+        return PAM_AUTHINFO_UNAVAIL;
+    }
+    if (snprintf(path, sizeof(path), "%s/%s", opts->keydir, user) >= (int)sizeof(path))
+        return PAM_AUTHINFO_UNAVAIL;
+
+    int fd = open(path, O_RDONLY | O_NOFOLLOW | O_CLOEXEC);
+    if (fd < 0)
+        return errno == ENOENT ? PAM_USER_UNKNOWN : PAM_AUTHINFO_UNAVAIL;
+
+    FILE *file = fdopen(fd, "r");
+    if (file == NULL) {
+        close(fd);
+        return PAM_AUTHINFO_UNAVAIL;
+    }
+    if (fstat(fd, &st) != 0 || !S_ISREG(st.st_mode) || st.st_uid != 0 ||
+        (st.st_mode & 077) != 0) {
+        pam_syslog(pamh, LOG_ERR, "%s must be a regular file readable by root only", path);
+        goto out;
+    }
+    if (fgets(line, sizeof(line), file) == NULL)
+        goto malformed;
+    line[strcspn(line, "\r\n")] = '\0';
+
+    char *hex = strchr(line, ':');
+    if (hex == NULL || hex == line || hex - line > 3)
+        goto malformed;
+    *hex++ = '\0';
+    char *end;
+    unsigned long id = strtoul(line, &end, 10);
+    if (*end != '\0' || id > UINT8_MAX || passcode_recommended_key_len((uint8_t)id) == 0)
+        goto malformed;
+
+    size_t hex_len = strlen(hex);
+    if (hex_len == 0 || hex_len % 2 != 0 || hex_len / 2 > MAX_KEY_LEN)
+        goto malformed;
+    for (size_t i = 0; i < hex_len / 2; i++) {
+        int hi = hex_value(hex[2 * i]);
+        int lo = hex_value(hex[2 * i + 1]);
+        if (hi < 0 || lo < 0)
+            goto malformed;
+        key[i] = (uint8_t)(hi << 4 | lo);
+    }
+    *algorithm = (uint8_t)id;
+    *key_len = hex_len / 2;
+    ret = PAM_SUCCESS;
+    goto out;
+
+malformed:
+    pam_syslog(pamh, LOG_ERR, "malformed key file %s", path);
+out:
+    explicit_bzero(line, sizeof(line));
+    fclose(file);
+    return ret;
+}
+
+/* Compares in time independent of where the strings differ */
+static int otp_equal(const char *expected, size_t expected_len, const char *answer)
+{
+    size_t answer_len = strlen(answer);
+    unsigned char diff = expected_len != answer_len;
+    for (size_t i = 0; i < expected_len; i++) {
+        unsigned char a = i < answer_len ? (unsigned char)tolower((unsigned char)answer[i]) : 0;
+        diff |= (unsigned char)expected[i] ^ a;
+    }
+    return diff == 0;
+}
+
+/* Strips surrounding whitespace in place */
+static char *trim(char *s)
+{
+    while (isspace((unsigned char)*s))
+        s++;
+    size_t len = strlen(s);
+    while (len > 0 && isspace((unsigned char)s[len - 1]))
+        s[--len] = '\0';
+    return s;
+}
+
+PAM_EXTERN int pam_sm_authenticate(pam_handle_t *pamh, int flags, int argc,
+                                   const char **argv)
+{
+    struct options opts;
+    const char *user = NULL;
+    uint8_t algorithm;
+    uint8_t key[MAX_KEY_LEN];
+    size_t key_len = 0;
+    uint8_t challenge[MAX_CHALLENGE_LEN];
+    char challenge_hex[2 * MAX_CHALLENGE_LEN + 1];
+    char expected[MAX_OTP_LEN];
+    char *answer = NULL;
+    Passcode *passcode = NULL;
+    int ret;
+
+    (void)flags;
+    if (parse_options(pamh, argc, argv, &opts) != 0)
+        return PAM_SERVICE_ERR;
+    if (pam_get_user(pamh, &user, NULL) != PAM_SUCCESS || user == NULL || *user == '\0')
+        return PAM_USER_UNKNOWN;
+
+    ret = read_key(pamh, &opts, user, &algorithm, key, &key_len);
+    if (ret == PAM_USER_UNKNOWN && opts.nullok)
+        return PAM_IGNORE;
+    if (ret != PAM_SUCCESS)
+        goto out;
+
+    if (getrandom(challenge, opts.challenge_len, 0) != (ssize_t)opts.challenge_len) {
+        pam_syslog(pamh, LOG_ERR, "cannot read random bytes: %m");
+        ret = PAM_SYSTEM_ERR;
+        goto out;
+    }
+    for (size_t i = 0; i < opts.challenge_len; i++)
+        snprintf(challenge_hex + 2 * i, 3, "%02x", challenge[i]);
+
+    passcode = passcode_new(algorithm, key, key_len);
+    if (passcode == NULL) {
+        ret = PAM_AUTHINFO_UNAVAIL;
+        goto out;
+    }
+    int32_t expected_len = passcode_compute(passcode, challenge, opts.challenge_len,
+                                            (uint8_t *)expected, sizeof(expected));
+    if (expected_len < 0) {
+        ret = PAM_SYSTEM_ERR;
+        goto out;
+    }
+
+    pam_info(pamh, "Passcode challenge: %s", challenge_hex);
+    if (pam_prompt(pamh, PAM_PROMPT_ECHO_OFF, &answer, "Response: ") != PAM_SUCCESS ||
+        answer == NULL) {
+        ret = PAM_CONV_ERR;
+        goto out;
+    }
+
+    if (otp_equal(expected, (size_t)expected_len, trim(answer))) {
+        ret = PAM_SUCCESS;
+    } else {
+        pam_syslog(pamh, LOG_NOTICE, "wrong response for %s", user);
+        ret = PAM_AUTH_ERR;
+    }
+
+out:
+    if (answer != NULL) {
+        explicit_bzero(answer, strlen(answer));
+        free(answer);
+    }
+    passcode_free(passcode);
+    explicit_bzero(key, sizeof(key));
+    explicit_bzero(expected, sizeof(expected));
+    return ret;
+}
+
+PAM_EXTERN int pam_sm_setcred(pam_handle_t *pamh, int flags, int argc,
+                              const char **argv)
+{
+    (void)pamh;
+    (void)flags;
+    (void)argc;
+    (void)argv;
+    return PAM_SUCCESS;
+}
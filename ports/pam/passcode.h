@@ -0,0 +1,43 @@
+/*
+ * C declarations for the FFI of the Rust port (ports/rust/src/ffi.rs)
+ */
+
+#ifndef PASSCODE_H
+#define PASSCODE_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Algorithm identifiers, as in Algorithm::from_id */
+#define PASSCODE_SHA3_KMAC_128 0
+#define PASSCODE_SHA3_KMAC_256 1
+#define PASSCODE_BLAKE3_KEYED_128 2
+#define PASSCODE_BLAKE3_KEYED_256 3
+#define PASSCODE_SHA3_KMAC_512 4
+
+typedef struct Passcode Passcode;
+
+/* Returns NULL for an unknown algorithm. Free with passcode_free. */
+Passcode *passcode_new(uint8_t algorithm, const uint8_t *key, size_t key_len);
+
+/* Returns 0 for an unknown algorithm */
+size_t passcode_recommended_key_len(uint8_t algorithm);
+
+/*
+ * Writes the OTP over data as a NUL-terminated string into out and returns
+ * its length, -1 for a NULL argument or -2 when out is too small.
+ */
+int32_t passcode_compute(Passcode *passcode, const uint8_t *data,
+                         size_t data_len, uint8_t *out, size_t out_len);
+
+void passcode_free(Passcode *passcode);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* PASSCODE_H */
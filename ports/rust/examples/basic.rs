@@ -17,6 +17,7 @@ fn main() {
     // - Algorithm::Sha3Kmac256
     // - Algorithm::Blake3KeyedMode128
     // - Algorithm::Blake3KeyedMode256
+    // - Algorithm::Sha3Kmac512
     let passcode = Passcode::new(Algorithm::Blake3KeyedMode256, secret_key.clone());
     println!("Using algorithm: {}\n", passcode.algorithm_name());
 
@@ -49,6 +50,7 @@ fn main() {
         Algorithm::Sha3Kmac256,
         Algorithm::Blake3KeyedMode128,
         Algorithm::Blake3KeyedMode256,
+        Algorithm::Sha3Kmac512,
     ];
 
     for algo in &algorithms {
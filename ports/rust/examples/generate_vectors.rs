@@ -0,0 +1,11 @@
+//! Regenerates the shared cross-language test vectors
+//!
+//! ```text
+//! cargo run --example generate_vectors --features test-vectors > ../../test/vectors.json
+//! ```
+
+use passcode::vectors::TestVectors;
+
+fn main() {
+    print!("{}", TestVectors::generate().to_json());
+}
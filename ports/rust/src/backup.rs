@@ -0,0 +1,280 @@
+//! Password-encrypted key backups (feature `key-export`)
+//!
+//! [`Passcode::export_encrypted`] seals the algorithm and key under a
+//! password so client apps can back up the shared secret and restore it on
+//! another device with [`Passcode::import_encrypted`]. The key is encrypted
+//! with XChaCha20-Poly1305 under a key derived from the password with
+//! Argon2id; the parameters, salt and nonce travel in the clear and are
+//! authenticated with it.
+//!
+//! The backup is `passcode-key:` followed by unpadded base64url of
+//!
+//! | Field | Length |
+//! |---|---|
+//! | version, [`BACKUP_VERSION`] | 1 byte |
+//! | Argon2id memory cost in KiB, iterations, parallelism | 4 bytes each, big-endian |
+//! | salt | 16 bytes |
+//! | nonce | 24 bytes |
+//! | ciphertext of the [`Algorithm::id`] and the key, then the tag | 17 bytes + key length |
+//!
+//! # Example
+//! ```
+//! use passcode::{Algorithm, Argon2Params, Passcode};
+//!
+//! let params = Argon2Params {
+//!     memory_kib: 1024,
+//!     iterations: 1,
+//!     ..Argon2Params::default()
+//! };
+//! let passcode = Passcode::new(Algorithm::Sha3Kmac256, vec![7u8; 32]);
+//! let backup = passcode.export_encrypted_with(b"hunter2", &params).unwrap();
+//!
+//! let restored = Passcode::import_encrypted(&backup, b"hunter2").unwrap();
+//! assert_eq!(restored.key_id(), passcode.key_id());
+//! assert!(Passcode::import_encrypted(&backup, b"hunter3").is_err());
+//! ```
+
+use base64ct::{Base64UrlUnpadded, Encoding};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+
+use crate::rng::SharedRng;
+use crate::{Algorithm, Argon2Params, Error, KeyDerivation, Passcode};
+
+/// Version byte of the backup format
+pub const BACKUP_VERSION: u8 = 1;
+
+/// Prefix of an armored backup
+pub const BACKUP_PREFIX: &str = "passcode-key:";
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+const TAG_LEN: usize = 16;
+/// Version, three cost parameters, salt and nonce
+const HEADER_LEN: usize = 1 + 12 + SALT_LEN + NONCE_LEN;
+
+/// Largest Argon2id memory cost accepted on import, 1 GiB, so a crafted
+/// backup cannot exhaust memory
+const MAX_MEMORY_KIB: u32 = 1 << 20;
+/// Largest iteration count accepted on import
+const MAX_ITERATIONS: u32 = 64;
+/// Largest parallelism accepted on import
+const MAX_PARALLELISM: u32 = 16;
+
+impl Passcode {
+    /// Encrypts the algorithm and key under `password` with the default
+    /// Argon2id parameters
+    ///
+    /// Output formatting options are not part of the backup.
+    ///
+    /// # Panics
+    /// Panics if the operating system's CSPRNG fails.
+    pub fn export_encrypted(&self, password: &[u8]) -> String {
+        self.export_encrypted_with(password, &Argon2Params::default())
+            .expect("default Argon2id parameters are valid")
+    }
+
+    /// Encrypts the algorithm and key under `password` with the given
+    /// Argon2id parameters
+    ///
+    /// Fails with [`Error::KeyDerivation`] for parameters Argon2id rejects
+    /// and [`Error::InvalidFormat`] for costs above what
+    /// [`import_encrypted`](Self::import_encrypted) accepts.
+    ///
+    /// # Panics
+    /// Panics if the operating system's CSPRNG fails.
+    pub fn export_encrypted_with(
+        &self,
+        password: &[u8],
+        params: &Argon2Params,
+    ) -> Result<String, Error> {
+        if !within_limits(params) {
+            return Err(Error::InvalidFormat(
+                "Argon2id cost exceeds the import limits",
+            ));
+        }
+
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        header.push(BACKUP_VERSION);
+        header.extend_from_slice(&params.memory_kib.to_be_bytes());
+        header.extend_from_slice(&params.iterations.to_be_bytes());
+        header.extend_from_slice(&params.parallelism.to_be_bytes());
+        let mut random = [0u8; SALT_LEN + NONCE_LEN];
+        SharedRng::os()
+            .fill(&mut random)
+            .expect("operating system CSPRNG failed");
+        header.extend_from_slice(&random);
+
+        let cipher = cipher(password, params, &random[..SALT_LEN])?;
+        let mut plaintext = Vec::with_capacity(1 + self.key().len());
+        plaintext.push(self.algorithm().id());
+        plaintext.extend_from_slice(self.key());
+        let ciphertext = cipher
+            .encrypt(
+                XNonce::from_slice(&random[SALT_LEN..]),
+                Payload {
+                    msg: &plaintext,
+                    aad: &header,
+                },
+            )
+            .expect("buffer is large enough");
+
+        header.extend_from_slice(&ciphertext);
+        Ok(format!(
+            "{}{}",
+            BACKUP_PREFIX,
+            Base64UrlUnpadded::encode_string(&header)
+        ))
+    }
+
+    /// Restores a passcode from a backup written by
+    /// [`export_encrypted`](Self::export_encrypted)
+    ///
+    /// Fails with [`Error::MalformedMessage`] for a damaged or unsupported
+    /// backup and [`Error::DecryptionFailed`] for a wrong password. The
+    /// restored key is checked like [`Passcode::try_new`].
+    pub fn import_encrypted(backup: &str, password: &[u8]) -> Result<Self, Error> {
+        let encoded = backup
+            .trim()
+            .strip_prefix(BACKUP_PREFIX)
+            .ok_or(Error::MalformedMessage("not a passcode key backup"))?;
+        let bytes = Base64UrlUnpadded::decode_vec(encoded)
+            .map_err(|_| Error::MalformedMessage("invalid base64url"))?;
+        if bytes.len() < HEADER_LEN + 1 + TAG_LEN {
+            return Err(Error::MalformedMessage("truncated message"));
+        }
+        let (header, ciphertext) = bytes.split_at(HEADER_LEN);
+        if header[0] != BACKUP_VERSION {
+            return Err(Error::MalformedMessage("unsupported backup version"));
+        }
+
+        let cost = |at: usize| u32::from_be_bytes(header[at..at + 4].try_into().expect("4 bytes"));
+        let params = Argon2Params {
+            memory_kib: cost(1),
+            iterations: cost(5),
+            parallelism: cost(9),
+            ..Argon2Params::default()
+        };
+        if !within_limits(&params) {
+            return Err(Error::MalformedMessage(
+                "Argon2id cost exceeds the import limits",
+            ));
+        }
+
+        let (salt, nonce) = header[13..].split_at(SALT_LEN);
+        let plaintext = cipher(password, &params, salt)?
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: header,
+                },
+            )
+            .map_err(|_| Error::DecryptionFailed)?;
+
+        let algorithm =
+            Algorithm::from_id(plaintext[0]).ok_or(Error::MalformedMessage("unknown algorithm"))?;
+        Passcode::try_new(algorithm, plaintext[1..].to_vec())
+    }
+}
+
+fn within_limits(params: &Argon2Params) -> bool {
+    params.memory_kib <= MAX_MEMORY_KIB
+        && params.iterations <= MAX_ITERATIONS
+        && params.parallelism <= MAX_PARALLELISM
+}
+
+/// Derives the encryption key from the password
+fn cipher(password: &[u8], params: &Argon2Params, salt: &[u8]) -> Result<XChaCha20Poly1305, Error> {
+    let params = Argon2Params {
+        key_len: 32,
+        ..*params
+    };
+    let key = params.derive_key(password, salt)?;
+    Ok(XChaCha20Poly1305::new_from_slice(&key).expect("32-byte key"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params() -> Argon2Params {
+        Argon2Params {
+            memory_kib: 64,
+            iterations: 1,
+            ..Argon2Params::default()
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for algorithm in Algorithm::all() {
+            let passcode = Passcode::new(algorithm, algorithm.random_key());
+            let backup = passcode
+                .export_encrypted_with(b"password", &params())
+                .unwrap();
+            assert!(backup.starts_with(BACKUP_PREFIX));
+
+            let restored = Passcode::import_encrypted(&backup, b"password").unwrap();
+            assert_eq!(restored.algorithm(), algorithm);
+            assert_eq!(restored.compute(b"data"), passcode.compute(b"data"));
+        }
+    }
+
+    #[test]
+    fn test_backups_are_randomized() {
+        let passcode = Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+        let first = passcode
+            .export_encrypted_with(b"password", &params())
+            .unwrap();
+        let second = passcode
+            .export_encrypted_with(b"password", &params())
+            .unwrap();
+        assert_ne!(first, second);
+        assert_eq!(
+            first.len(),
+            BACKUP_PREFIX.len() + ((HEADER_LEN + 1 + 32 + TAG_LEN) * 4).div_ceil(3)
+        );
+    }
+
+    #[test]
+    fn test_rejects_wrong_password_and_tampering() {
+        let passcode = Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+        let backup = passcode
+            .export_encrypted_with(b"password", &params())
+            .unwrap();
+
+        assert_eq!(
+            Passcode::import_encrypted(&backup, b"Password").err(),
+            Some(Error::DecryptionFailed)
+        );
+
+        // Raising the stored cost changes the authenticated header
+        let mut bytes = Base64UrlUnpadded::decode_vec(&backup[BACKUP_PREFIX.len()..]).unwrap();
+        bytes[8] = 2;
+        let tampered = format!(
+            "{}{}",
+            BACKUP_PREFIX,
+            Base64UrlUnpadded::encode_string(&bytes)
+        );
+        assert_eq!(
+            Passcode::import_encrypted(&tampered, b"password").err(),
+            Some(Error::DecryptionFailed)
+        );
+
+        bytes[1] = 0xff;
+        let expensive = format!(
+            "{}{}",
+            BACKUP_PREFIX,
+            Base64UrlUnpadded::encode_string(&bytes)
+        );
+        assert_eq!(
+            Passcode::import_encrypted(&expensive, b"password").err(),
+            Some(Error::MalformedMessage(
+                "Argon2id cost exceeds the import limits"
+            ))
+        );
+        assert!(Passcode::import_encrypted("passcode-key:AAAA", b"password").is_err());
+        assert!(Passcode::import_encrypted(&backup[1..], b"password").is_err());
+    }
+}
@@ -1,7 +1,7 @@
 use blake3::Hasher;
 
 /// BLAKE3 keyed mode implementation
-fn blake3_keyed_mode(key: &[u8], data: &[u8], out_len: usize) -> Vec<u8> {
+pub(crate) fn blake3_keyed_mode(key: &[u8], data: &[u8], out_len: usize) -> Vec<u8> {
     // Hash the key first to get a 32-byte key
     let hashed_key = blake3::hash(key);
     
@@ -0,0 +1,373 @@
+//! BLE and NFC-sized messages
+//!
+//! A BLE characteristic write carries [`BLE_PAYLOAD_LEN`] bytes at the
+//! default MTU, too few for even the [`compact`](crate::compact) profile.
+//! Over a live BLE connection or an NFC tap the verifier can keep its
+//! challenge in memory, so [`TinyChallenge`] sends only a header and a
+//! nonce, and [`TinyResponse`] only a header and the MAC:
+//!
+//! | Field | Challenge | Response |
+//! |---|---|---|
+//! | header | 1 byte | 1 byte |
+//! | nonce | [`TINY_NONCE_LEN`] bytes | — |
+//! | MAC | — | [`Algorithm::otp_bytes`] bytes over [`TinyChallenge::message`] |
+//!
+//! The header is that of the compact profile with [`TINY_VERSION`] in the
+//! top two bits. Both messages fit one write and the payload of one NDEF
+//! record. Larger payloads, e.g. compact or [`wire`](crate::wire)
+//! messages, are cut into writes with [`split_chunks`] and put back
+//! together by a [`Reassembler`].
+//!
+//! # Example
+//! ```
+//! use passcode::ble::{TinyChallenge, TinyResponse, BLE_PAYLOAD_LEN};
+//! use passcode::{Algorithm, Passcode};
+//!
+//! let passcode = || Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+//!
+//! // Verifier, e.g. a door lock
+//! let challenge = TinyChallenge::issue(Algorithm::Sha3Kmac256).unwrap();
+//! let write = challenge.encode().unwrap();
+//! assert!(write.len() <= BLE_PAYLOAD_LEN);
+//!
+//! // Phone
+//! let received = TinyChallenge::decode(&write).unwrap();
+//! let answer = TinyResponse::compute(&passcode(), &received).encode().unwrap();
+//!
+//! // Verifier
+//! let response = TinyResponse::decode(&answer).unwrap();
+//! assert!(challenge.verify(&passcode(), &response).is_ok());
+//! ```
+
+use rand_core::CryptoRngCore;
+
+use crate::compact::{header, read_header};
+use crate::rng::SharedRng;
+use crate::wire::{TYPE_CHALLENGE, TYPE_RESPONSE};
+use crate::{Algorithm, Error, Passcode};
+
+/// Payload of one BLE characteristic write at the default ATT MTU of 23
+pub const BLE_PAYLOAD_LEN: usize = 20;
+
+/// Version in the top two bits of the header
+pub const TINY_VERSION: u8 = 2;
+
+/// Length of the nonce of a [`TinyChallenge`]
+pub const TINY_NONCE_LEN: usize = 16;
+
+/// Most chunks one payload can be split into
+pub const MAX_CHUNKS: usize = 128;
+
+/// Domain separator for the message a [`TinyResponse`] is computed over
+const TINY_CONTEXT: &[u8] = b"passcode/v1/tiny";
+
+/// Chunk header flag marking the last chunk of a payload
+const LAST_CHUNK: u8 = 0x80;
+
+/// Challenge that fits one BLE write
+///
+/// Nothing in it is authenticated: the verifier keeps the challenge it
+/// sent and enforces its lifetime itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TinyChallenge {
+    /// OTP algorithm the challenge expects
+    pub algorithm: Algorithm,
+    /// Random nonce
+    pub nonce: [u8; TINY_NONCE_LEN],
+}
+
+impl TinyChallenge {
+    /// Issues a challenge with a nonce from the OS CSPRNG
+    pub fn issue(algorithm: Algorithm) -> Result<Self, Error> {
+        Self::issue_with_rng(algorithm, SharedRng::os())
+    }
+
+    /// Issues a challenge with a nonce drawn from `rng`
+    pub fn issue_with_rng(
+        algorithm: Algorithm,
+        mut rng: impl CryptoRngCore,
+    ) -> Result<Self, Error> {
+        let mut nonce = [0u8; TINY_NONCE_LEN];
+        rng.try_fill_bytes(&mut nonce)
+            .map_err(|e| Error::RandomSource(e.to_string()))?;
+        Ok(Self { algorithm, nonce })
+    }
+
+    /// Builds the message the response MAC is computed over
+    pub fn message(&self) -> Vec<u8> {
+        let mut message = Vec::with_capacity(TINY_CONTEXT.len() + 1 + TINY_NONCE_LEN);
+        message.extend_from_slice(TINY_CONTEXT);
+        message.push(self.algorithm.id());
+        message.extend_from_slice(&self.nonce);
+        message
+    }
+
+    /// Checks a response to this challenge in constant time
+    ///
+    /// Fails with [`Error::OtpMismatch`] when the MAC is wrong or the
+    /// response or key uses another algorithm.
+    pub fn verify(&self, passcode: &Passcode, response: &TinyResponse) -> Result<(), Error> {
+        let algorithms_match =
+            passcode.algorithm() == self.algorithm && response.algorithm == self.algorithm;
+        if algorithms_match && passcode.verify_mac(&self.message(), &response.mac) {
+            Ok(())
+        } else {
+            Err(Error::OtpMismatch)
+        }
+    }
+
+    /// Encodes the challenge in `1 +` [`TINY_NONCE_LEN`] bytes
+    ///
+    /// Fails with [`Error::MalformedMessage`] when the algorithm ID does
+    /// not fit the header.
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        let mut encoded = Vec::with_capacity(1 + TINY_NONCE_LEN);
+        encoded.push(header(TINY_VERSION, TYPE_CHALLENGE, self.algorithm)?);
+        encoded.extend_from_slice(&self.nonce);
+        Ok(encoded)
+    }
+
+    /// Decodes a challenge written by [`encode`](Self::encode)
+    pub fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        let (&first, nonce) = bytes
+            .split_first()
+            .ok_or(Error::MalformedMessage("truncated message"))?;
+        let algorithm = read_header(first, TINY_VERSION, TYPE_CHALLENGE)?;
+        let nonce = nonce
+            .try_into()
+            .map_err(|_| Error::MalformedMessage("wrong tiny challenge length"))?;
+        Ok(Self { algorithm, nonce })
+    }
+}
+
+/// Response that fits one BLE write
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TinyResponse {
+    /// OTP algorithm the MAC was computed with
+    pub algorithm: Algorithm,
+    /// Raw OTP bytes over [`TinyChallenge::message`]
+    pub mac: Vec<u8>,
+}
+
+impl TinyResponse {
+    /// Answers a challenge with the client's key
+    pub fn compute(passcode: &Passcode, challenge: &TinyChallenge) -> Self {
+        Self {
+            algorithm: passcode.algorithm(),
+            mac: passcode.compute_mac(&challenge.message()),
+        }
+    }
+
+    /// Encodes the response in `1 +` [`Algorithm::otp_bytes`] bytes
+    ///
+    /// Fails with [`Error::MalformedMessage`] when the MAC does not have
+    /// the algorithm's length or the algorithm ID does not fit the header.
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        if self.mac.len() != self.algorithm.otp_bytes() {
+            return Err(Error::MalformedMessage("wrong tiny response MAC length"));
+        }
+        let mut encoded = Vec::with_capacity(1 + self.mac.len());
+        encoded.push(header(TINY_VERSION, TYPE_RESPONSE, self.algorithm)?);
+        encoded.extend_from_slice(&self.mac);
+        Ok(encoded)
+    }
+
+    /// Decodes a response written by [`encode`](Self::encode)
+    pub fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        let (&first, mac) = bytes
+            .split_first()
+            .ok_or(Error::MalformedMessage("truncated message"))?;
+        let algorithm = read_header(first, TINY_VERSION, TYPE_RESPONSE)?;
+        if mac.len() != algorithm.otp_bytes() {
+            return Err(Error::MalformedMessage("wrong tiny response MAC length"));
+        }
+        Ok(Self {
+            algorithm,
+            mac: mac.to_vec(),
+        })
+    }
+}
+
+/// Splits a payload into chunks of at most `chunk_len` bytes, e.g.
+/// [`BLE_PAYLOAD_LEN`], to send in order
+///
+/// Each chunk starts with a byte holding its index and, in the top bit, a
+/// flag marking the last chunk. An empty payload is one chunk holding only
+/// that byte. Fails with [`Error::MalformedMessage`] when `chunk_len` is
+/// below 2 or the payload needs more than [`MAX_CHUNKS`] chunks.
+pub fn split_chunks(payload: &[u8], chunk_len: usize) -> Result<Vec<Vec<u8>>, Error> {
+    if chunk_len < 2 {
+        return Err(Error::MalformedMessage("chunk length must be at least 2"));
+    }
+    let data_len = chunk_len - 1;
+    let count = payload.len().div_ceil(data_len).max(1);
+    if count > MAX_CHUNKS {
+        return Err(Error::MalformedMessage("payload needs too many chunks"));
+    }
+
+    let mut chunks: Vec<Vec<u8>> = payload
+        .chunks(data_len)
+        .enumerate()
+        .map(|(index, data)| [&[index as u8], data].concat())
+        .collect();
+    if chunks.is_empty() {
+        chunks.push(vec![0]);
+    }
+    chunks[count - 1][0] |= LAST_CHUNK;
+    Ok(chunks)
+}
+
+/// Puts the chunks of [`split_chunks`] back together
+///
+/// Chunks must arrive in order, as BLE writes and notifications do. Any
+/// chunk out of order, or longer than the chunk length, fails with
+/// [`Error::MalformedMessage`] and drops the partial payload, so the next
+/// payload starts cleanly with its first chunk.
+#[derive(Debug, Clone)]
+pub struct Reassembler {
+    chunk_len: usize,
+    next: usize,
+    buffer: Vec<u8>,
+}
+
+impl Reassembler {
+    /// Creates a reassembler for chunks of at most `chunk_len` bytes
+    pub fn new(chunk_len: usize) -> Self {
+        Self {
+            chunk_len,
+            next: 0,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Adds the next chunk, returning the payload once its last chunk
+    /// arrived
+    pub fn push(&mut self, chunk: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        let result = self.accept(chunk);
+        if !matches!(result, Ok(None)) {
+            self.reset();
+        }
+        result
+    }
+
+    /// Drops the partial payload, e.g. when the connection drops
+    pub fn reset(&mut self) {
+        self.next = 0;
+        self.buffer.clear();
+    }
+
+    /// Returns true while a payload is partially received
+    pub fn is_pending(&self) -> bool {
+        self.next > 0
+    }
+
+    fn accept(&mut self, chunk: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        let (&flags, data) = chunk
+            .split_first()
+            .ok_or(Error::MalformedMessage("empty chunk"))?;
+        if chunk.len() > self.chunk_len {
+            return Err(Error::MalformedMessage("chunk is too long"));
+        }
+        if usize::from(flags & !LAST_CHUNK) != self.next {
+            return Err(Error::MalformedMessage("chunk out of order"));
+        }
+        self.buffer.extend_from_slice(data);
+        if flags & LAST_CHUNK != 0 {
+            return Ok(Some(std::mem::take(&mut self.buffer)));
+        }
+        self.next += 1;
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wire::ChallengeMessage;
+
+    #[test]
+    fn test_messages_fit_one_write() {
+        for algorithm in Algorithm::all() {
+            let passcode = Passcode::new(algorithm, vec![1u8; 32]);
+            let challenge = TinyChallenge::issue(algorithm).unwrap();
+            let encoded = challenge.encode().unwrap();
+            assert!(encoded.len() <= BLE_PAYLOAD_LEN);
+            assert_eq!(TinyChallenge::decode(&encoded), Ok(challenge.clone()));
+
+            let response = TinyResponse::compute(&passcode, &challenge);
+            let encoded = response.encode().unwrap();
+            assert!(encoded.len() <= BLE_PAYLOAD_LEN);
+            assert_eq!(TinyResponse::decode(&encoded), Ok(response.clone()));
+            assert_eq!(challenge.verify(&passcode, &response), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_verify_rejects() {
+        let passcode = Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+        let challenge = TinyChallenge::issue(Algorithm::Sha3Kmac256).unwrap();
+        let other = TinyChallenge::issue(Algorithm::Sha3Kmac256).unwrap();
+        let response = TinyResponse::compute(&passcode, &other);
+        assert_eq!(
+            challenge.verify(&passcode, &response),
+            Err(Error::OtpMismatch)
+        );
+
+        let blake3 = Passcode::new(Algorithm::Blake3KeyedMode256, vec![1u8; 32]);
+        let response = TinyResponse::compute(&blake3, &challenge);
+        assert_eq!(
+            challenge.verify(&blake3, &response),
+            Err(Error::OtpMismatch)
+        );
+
+        // A compact challenge is not a tiny one
+        let encoded = challenge.encode().unwrap();
+        let mut compact = encoded.clone();
+        compact[0] = compact[0] & 0x3f | 0x40;
+        assert!(TinyChallenge::decode(&compact).is_err());
+        assert!(TinyResponse::decode(&encoded).is_err());
+        assert!(TinyChallenge::decode(&encoded[..10]).is_err());
+    }
+
+    #[test]
+    fn test_chunks_round_trip() {
+        let challenge = ChallengeMessage {
+            algorithm: Algorithm::Sha3Kmac256,
+            nonce: vec![7; 16],
+            expires_at: std::time::UNIX_EPOCH,
+            mac: vec![9; 32],
+        }
+        .encode();
+        for payload in [Vec::new(), vec![1; 19], vec![2; 20], challenge] {
+            let chunks = split_chunks(&payload, BLE_PAYLOAD_LEN).unwrap();
+            assert_eq!(chunks.len(), payload.len().div_ceil(19).max(1));
+            assert!(chunks.iter().all(|chunk| chunk.len() <= BLE_PAYLOAD_LEN));
+
+            let mut reassembler = Reassembler::new(BLE_PAYLOAD_LEN);
+            let (last, rest) = chunks.split_last().unwrap();
+            for chunk in rest {
+                assert_eq!(reassembler.push(chunk), Ok(None));
+            }
+            assert_eq!(reassembler.push(last), Ok(Some(payload)));
+            assert!(!reassembler.is_pending());
+        }
+    }
+
+    #[test]
+    fn test_chunk_limits() {
+        assert!(split_chunks(&[0; 19 * MAX_CHUNKS], BLE_PAYLOAD_LEN).is_ok());
+        assert!(split_chunks(&[0; 19 * MAX_CHUNKS + 1], BLE_PAYLOAD_LEN).is_err());
+        assert!(split_chunks(b"data", 1).is_err());
+
+        let chunks = split_chunks(&[3; 50], BLE_PAYLOAD_LEN).unwrap();
+        let mut reassembler = Reassembler::new(BLE_PAYLOAD_LEN);
+        reassembler.push(&chunks[0]).unwrap();
+        assert!(reassembler.is_pending());
+        assert_eq!(
+            reassembler.push(&chunks[2]),
+            Err(Error::MalformedMessage("chunk out of order"))
+        );
+        assert!(!reassembler.is_pending());
+        assert!(Reassembler::new(10).push(&chunks[0]).is_err());
+    }
+}
@@ -0,0 +1,206 @@
+//! Canonicalization of user-entered codes before verification
+
+use crate::format::{LetterCase, OtpFormat};
+
+/// Zero digits of the Unicode decimal digit blocks mapped to ASCII
+///
+/// Each block holds the digits 0-9 at consecutive code points.
+const DIGIT_ZEROS: [u32; 18] = [
+    0x0660, // Arabic-Indic
+    0x06F0, // Extended Arabic-Indic
+    0x07C0, // NKo
+    0x0966, // Devanagari
+    0x09E6, // Bengali
+    0x0A66, // Gurmukhi
+    0x0AE6, // Gujarati
+    0x0B66, // Oriya
+    0x0BE6, // Tamil
+    0x0C66, // Telugu
+    0x0CE6, // Kannada
+    0x0D66, // Malayalam
+    0x0E50, // Thai
+    0x0ED0, // Lao
+    0x0F20, // Tibetan
+    0x1040, // Myanmar
+    0x17E0, // Khmer
+    0x1810, // Mongolian
+];
+
+/// Rules applied to submitted codes before they are decoded and compared
+///
+/// Codes copied from emails, SMS or chat often pick up spaces, dashes,
+/// zero-width characters or fullwidth digits. Each rule can be toggled;
+/// [`Canonicalization::for_format`] gives the defaults used when none is
+/// configured on the [`PasscodeBuilder`](crate::PasscodeBuilder).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Canonicalization {
+    /// Remove leading and trailing whitespace
+    pub trim: bool,
+    /// Remove whitespace, zero-width characters and dash, underscore and
+    /// dot separators anywhere in the input
+    pub strip_separators: bool,
+    /// Convert letters to the case used by the format (no effect for
+    /// case-sensitive formats such as base58)
+    pub fold_case: bool,
+    /// Map fullwidth forms and non-ASCII decimal digits (e.g. `٣` or `３`)
+    /// to their ASCII equivalents
+    pub map_unicode: bool,
+}
+
+impl Canonicalization {
+    /// Leaves the input untouched
+    pub const NONE: Self = Self {
+        trim: false,
+        strip_separators: false,
+        fold_case: false,
+        map_unicode: false,
+    };
+
+    /// Returns the default rules for a format
+    ///
+    /// Every format trims surrounding whitespace. Separators are stripped
+    /// except for word codes, where they delimit the words, custom
+    /// alphabets, which may use them as symbols, and Bech32m, whose prefix
+    /// may contain them. Case is folded for the
+    /// hex, base32, Bech32m and word formats, and Unicode mapping is enabled
+    /// for all formats but custom alphabets.
+    pub fn for_format(format: &OtpFormat) -> Self {
+        match format {
+            OtpFormat::Custom(_) => Self {
+                trim: true,
+                ..Self::NONE
+            },
+            OtpFormat::Words(_) => Self {
+                trim: true,
+                strip_separators: false,
+                fold_case: true,
+                map_unicode: true,
+            },
+            #[cfg(feature = "bech32")]
+            OtpFormat::Bech32m { .. } => Self {
+                trim: true,
+                strip_separators: false,
+                fold_case: true,
+                map_unicode: true,
+            },
+            _ => Self {
+                trim: true,
+                strip_separators: true,
+                fold_case: format.letter_case().is_some(),
+                map_unicode: true,
+            },
+        }
+    }
+
+    /// Applies the rules to submitted input for the given format
+    pub fn apply(&self, input: &str, format: &OtpFormat) -> String {
+        let input = if self.trim { input.trim() } else { input };
+
+        input
+            .chars()
+            .map(|c| if self.map_unicode { map_unicode(c) } else { c })
+            .filter(|&c| !(self.strip_separators && is_separator(c)))
+            .map(|c| {
+                if !self.fold_case {
+                    return c;
+                }
+                match format.letter_case() {
+                    Some(LetterCase::Lower) => c.to_ascii_lowercase(),
+                    Some(LetterCase::Upper) => c.to_ascii_uppercase(),
+                    None => c,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Maps fullwidth ASCII forms and Unicode decimal digits to ASCII
+fn map_unicode(c: char) -> char {
+    let code = u32::from(c);
+
+    // Fullwidth forms U+FF01..U+FF5E mirror ASCII 0x21..0x7E
+    if (0xFF01..=0xFF5E).contains(&code) {
+        return char::from_u32(code - 0xFEE0).unwrap_or(c);
+    }
+    if c == '\u{3000}' {
+        return ' ';
+    }
+
+    DIGIT_ZEROS
+        .iter()
+        .find(|&&zero| (zero..zero + 10).contains(&code))
+        .and_then(|&zero| char::from_digit(code - zero, 10))
+        .unwrap_or(c)
+}
+
+/// Returns true for characters that never carry code content
+fn is_separator(c: char) -> bool {
+    c.is_whitespace()
+        || matches!(
+            c,
+            '-' | '_'
+                | '.'
+                | '\u{00B7}' // middle dot
+                | '\u{2010}'..='\u{2015}' // hyphens and dashes
+                | '\u{2212}' // minus sign
+                | '\u{200B}'..='\u{200D}' // zero-width space and joiners
+                | '\u{2060}' // word joiner
+                | '\u{FEFF}' // zero-width no-break space
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_rules() {
+        let decimal = Canonicalization::for_format(&OtpFormat::Decimal(6));
+        assert_eq!(
+            decimal.apply("\u{FEFF} 123\u{00A0}456\u{200B} ", &OtpFormat::Decimal(6)),
+            "123456"
+        );
+        assert_eq!(decimal.apply("123–456", &OtpFormat::Decimal(6)), "123456");
+        assert_eq!(
+            decimal.apply("１２３４５６", &OtpFormat::Decimal(6)),
+            "123456"
+        );
+        assert_eq!(decimal.apply("١٢٣٤٥٦", &OtpFormat::Decimal(6)), "123456");
+        assert_eq!(decimal.apply("१२३४५६", &OtpFormat::Decimal(6)), "123456");
+
+        let base32 = Canonicalization::for_format(&OtpFormat::Base32);
+        assert_eq!(base32.apply("mzxw-6ytb", &OtpFormat::Base32), "MZXW6YTB");
+        assert_eq!(base32.apply("ｍｚｘｗ", &OtpFormat::Base32), "MZXW");
+
+        let words = Canonicalization::for_format(&OtpFormat::Words(3));
+        assert_eq!(
+            words.apply(" Maple Rocket ", &OtpFormat::Words(3)),
+            "maple rocket"
+        );
+    }
+
+    #[test]
+    fn test_rules_can_be_disabled() {
+        let format = OtpFormat::Decimal(6);
+        assert_eq!(
+            Canonicalization::NONE.apply(" １２3-456 ", &format),
+            " １２3-456 "
+        );
+
+        let no_unicode = Canonicalization {
+            map_unicode: false,
+            ..Canonicalization::for_format(&format)
+        };
+        assert_eq!(no_unicode.apply("１２3 456", &format), "１２3456");
+    }
+
+    #[test]
+    fn test_case_sensitive_formats_are_not_folded() {
+        let rules = Canonicalization {
+            fold_case: true,
+            ..Canonicalization::NONE
+        };
+        assert_eq!(rules.apply("AbC", &OtpFormat::Base58), "AbC");
+        assert_eq!(rules.apply("AbC", &OtpFormat::Hex), "abc");
+    }
+}
@@ -0,0 +1,241 @@
+//! CBOR encoding of protocol messages (feature `cbor`)
+//!
+//! For IoT and smartcard-adjacent clients that already speak CBOR and find
+//! JSON too heavy. Each message is a definite-length array mirroring the
+//! [`wire`](crate::wire) fields:
+//!
+//! | Message | Array |
+//! |---|---|
+//! | [`ChallengeMessage`] | `[version, 1, algorithm, nonce, expires_at, mac]` |
+//! | [`ResponseMessage`] | `[version, 2, algorithm, nonce, mac]` |
+//!
+//! `version` is [`WIRE_VERSION`], `algorithm` is [`Algorithm::id`], the
+//! nonce and MAC are byte strings and `expires_at` is milliseconds since
+//! the Unix epoch.
+//!
+//! Encoding follows the core deterministic rules of RFC 8949 section 4.2:
+//! integers and lengths take their shortest form and nothing is
+//! indefinite-length. Decoders reject any other encoding of the same
+//! message, so each message has exactly one byte representation and a MAC
+//! over the encoded bytes cannot be sidestepped by re-encoding.
+//!
+//! # Example
+//! ```
+//! use passcode::challenge::SignedChallenges;
+//! use passcode::wire::{ChallengeMessage, ResponseMessage};
+//! use passcode::{Algorithm, Passcode};
+//!
+//! let passcode = || Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+//! let server = SignedChallenges::new(passcode(), b"server key");
+//! let challenge = server.issue(b"").unwrap();
+//! let sent = ChallengeMessage::from_challenge(&challenge).unwrap().encode_cbor();
+//!
+//! // Client
+//! let received = ChallengeMessage::decode_cbor(&sent).unwrap();
+//! let response = ResponseMessage::compute(&passcode(), &received).encode_cbor();
+//!
+//! // Server
+//! let response = ResponseMessage::decode_cbor(&response).unwrap();
+//! assert!(server.verify_response(&received, &response, b"").is_ok());
+//! ```
+
+use std::convert::Infallible;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use minicbor::{Decoder, Encoder};
+
+use crate::wire::{
+    ChallengeMessage, ResponseMessage, WireVersions, TYPE_CHALLENGE, TYPE_RESPONSE, WIRE_VERSION,
+};
+use crate::{Algorithm, Error};
+
+type Encoded = Encoder<Vec<u8>>;
+
+impl ChallengeMessage {
+    /// Encodes the message as deterministic CBOR
+    pub fn encode_cbor(&self) -> Vec<u8> {
+        let mut encoder = Encoder::new(Vec::new());
+        encode(&mut encoder, |e| {
+            header(e, 6, TYPE_CHALLENGE, self.algorithm)?
+                .bytes(&self.nonce)?
+                .u64(to_millis(self.expires_at))?
+                .bytes(&self.mac)?;
+            Ok(())
+        });
+        encoder.into_writer()
+    }
+
+    /// Decodes a message written by [`encode_cbor`](Self::encode_cbor)
+    pub fn decode_cbor(bytes: &[u8]) -> Result<Self, Error> {
+        let message = decode(bytes, |d| {
+            let algorithm = read_header(d, 6, TYPE_CHALLENGE)?;
+            Ok(Self {
+                algorithm,
+                nonce: d.bytes().map_err(invalid)?.to_vec(),
+                expires_at: UNIX_EPOCH + Duration::from_millis(d.u64().map_err(invalid)?),
+                mac: d.bytes().map_err(invalid)?.to_vec(),
+            })
+        })?;
+        canonical(bytes, &message.encode_cbor())?;
+        Ok(message)
+    }
+}
+
+impl ResponseMessage {
+    /// Encodes the message as deterministic CBOR
+    pub fn encode_cbor(&self) -> Vec<u8> {
+        let mut encoder = Encoder::new(Vec::new());
+        encode(&mut encoder, |e| {
+            header(e, 5, TYPE_RESPONSE, self.algorithm)?
+                .bytes(&self.nonce)?
+                .bytes(&self.mac)?;
+            Ok(())
+        });
+        encoder.into_writer()
+    }
+
+    /// Decodes a message written by [`encode_cbor`](Self::encode_cbor)
+    pub fn decode_cbor(bytes: &[u8]) -> Result<Self, Error> {
+        let message = decode(bytes, |d| {
+            let algorithm = read_header(d, 5, TYPE_RESPONSE)?;
+            Ok(Self {
+                algorithm,
+                nonce: d.bytes().map_err(invalid)?.to_vec(),
+                mac: d.bytes().map_err(invalid)?.to_vec(),
+            })
+        })?;
+        canonical(bytes, &message.encode_cbor())?;
+        Ok(message)
+    }
+}
+
+fn encode(
+    encoder: &mut Encoded,
+    write: impl FnOnce(&mut Encoded) -> Result<(), minicbor::encode::Error<Infallible>>,
+) {
+    write(encoder).expect("writing to a Vec cannot fail");
+}
+
+fn header(
+    encoder: &mut Encoded,
+    len: u64,
+    kind: u8,
+    algorithm: Algorithm,
+) -> Result<&mut Encoded, minicbor::encode::Error<Infallible>> {
+    encoder
+        .array(len)?
+        .u8(WIRE_VERSION)?
+        .u8(kind)?
+        .u8(algorithm.id())
+}
+
+fn decode<'b, T>(
+    bytes: &'b [u8],
+    read: impl FnOnce(&mut Decoder<'b>) -> Result<T, Error>,
+) -> Result<T, Error> {
+    let mut decoder = Decoder::new(bytes);
+    let message = read(&mut decoder)?;
+    if decoder.position() != bytes.len() {
+        return Err(Error::MalformedMessage("trailing bytes"));
+    }
+    Ok(message)
+}
+
+/// Reads the array header, version and type, returning the algorithm
+fn read_header(decoder: &mut Decoder<'_>, len: u64, kind: u8) -> Result<Algorithm, Error> {
+    if decoder.array().map_err(invalid)? != Some(len) {
+        return Err(Error::MalformedMessage("unexpected CBOR array length"));
+    }
+    WireVersions::supported().check(decoder.u8().map_err(invalid)?)?;
+    if decoder.u8().map_err(invalid)? != kind {
+        return Err(Error::MalformedMessage("unexpected message type"));
+    }
+    Algorithm::from_id(decoder.u8().map_err(invalid)?)
+        .ok_or(Error::MalformedMessage("unknown algorithm"))
+}
+
+/// Rejects encodings other than the deterministic one
+fn canonical(bytes: &[u8], reencoded: &[u8]) -> Result<(), Error> {
+    if bytes != reencoded {
+        return Err(Error::MalformedMessage("non-deterministic CBOR"));
+    }
+    Ok(())
+}
+
+fn invalid(_: minicbor::decode::Error) -> Error {
+    Error::MalformedMessage("invalid CBOR")
+}
+
+fn to_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn challenge() -> ChallengeMessage {
+        ChallengeMessage {
+            algorithm: Algorithm::Sha3Kmac256,
+            nonce: vec![0xaa; 2],
+            expires_at: UNIX_EPOCH + Duration::from_millis(1000),
+            mac: vec![0xbb],
+        }
+    }
+
+    #[test]
+    fn test_challenge_layout() {
+        let encoded = challenge().encode_cbor();
+        let algorithm = Algorithm::Sha3Kmac256.id();
+        assert!(algorithm < 24);
+        // array(6), then bytes(2), 1000 as a u16 and bytes(1)
+        let mut expected = vec![0x86, WIRE_VERSION, TYPE_CHALLENGE, algorithm];
+        expected.extend_from_slice(&[0x42, 0xaa, 0xaa]);
+        expected.extend_from_slice(&[0x19, 0x03, 0xe8]);
+        expected.extend_from_slice(&[0x41, 0xbb]);
+
+        assert_eq!(encoded, expected);
+        assert_eq!(ChallengeMessage::decode_cbor(&encoded), Ok(challenge()));
+    }
+
+    #[test]
+    fn test_response_round_trip() {
+        let passcode = crate::Passcode::new(Algorithm::Blake3KeyedMode256, vec![1u8; 32]);
+        let response = ResponseMessage::compute(&passcode, &challenge());
+        let encoded = response.encode_cbor();
+
+        assert_eq!(encoded[..3], [0x85, WIRE_VERSION, TYPE_RESPONSE]);
+        assert_eq!(ResponseMessage::decode_cbor(&encoded), Ok(response));
+        assert_eq!(
+            ChallengeMessage::decode_cbor(&encoded),
+            Err(Error::MalformedMessage("unexpected CBOR array length"))
+        );
+    }
+
+    #[test]
+    fn test_rejects_non_deterministic() {
+        let encoded = challenge().encode_cbor();
+
+        // 1000 padded to a u32
+        let mut long_int = encoded[..7].to_vec();
+        long_int.extend_from_slice(&[0x1a, 0, 0, 0x03, 0xe8, 0x41, 0xbb]);
+        assert_eq!(
+            ChallengeMessage::decode_cbor(&long_int),
+            Err(Error::MalformedMessage("non-deterministic CBOR"))
+        );
+
+        // Indefinite-length array
+        let mut indefinite = encoded.clone();
+        indefinite[0] = 0x9f;
+        indefinite.push(0xff);
+        assert!(ChallengeMessage::decode_cbor(&indefinite).is_err());
+
+        let mut trailing = encoded;
+        trailing.push(0);
+        assert_eq!(
+            ChallengeMessage::decode_cbor(&trailing),
+            Err(Error::MalformedMessage("trailing bytes"))
+        );
+    }
+}
@@ -0,0 +1,211 @@
+//! Challenges issued in bulk ahead of time
+
+use std::time::Duration;
+
+use super::store::{decode_record, encode_record};
+use super::{Challenge, ChallengeBinding, ChallengeId, CHALLENGE_ID_LEN};
+use crate::Error;
+
+/// Version byte at the start of a serialized [`ChallengeBundle`]
+const BUNDLE_VERSION: u8 = 1;
+
+/// Settings for [`ChallengeManager::issue_batch`](super::ChallengeManager::issue_batch)
+///
+/// By default the challenges use the manager's TTL and are unbound.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BatchPolicy {
+    pub(crate) ttl: Option<Duration>,
+    pub(crate) binding: ChallengeBinding,
+}
+
+impl BatchPolicy {
+    /// Creates the default policy
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the lifetime of the challenges, overriding the manager's TTL
+    ///
+    /// Pre-provisioned challenges usually need to live much longer than
+    /// interactive ones, e.g. for the validity of a printed sheet.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Binds every challenge in the batch to the same context
+    pub fn with_binding(mut self, binding: ChallengeBinding) -> Self {
+        self.binding = binding;
+        self
+    }
+}
+
+/// Challenges issued together by
+/// [`ChallengeManager::issue_batch`](super::ChallengeManager::issue_batch)
+///
+/// The bundle serializes with [`to_bytes`](Self::to_bytes), so it can be
+/// shipped to a kiosk or rendered into a printed sheet and read back with
+/// [`from_bytes`](Self::from_bytes).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChallengeBundle {
+    challenges: Vec<Challenge>,
+}
+
+impl ChallengeBundle {
+    pub(crate) fn new(challenges: Vec<Challenge>) -> Self {
+        Self { challenges }
+    }
+
+    /// Returns the number of challenges
+    pub fn len(&self) -> usize {
+        self.challenges.len()
+    }
+
+    /// Returns whether the bundle holds no challenges
+    pub fn is_empty(&self) -> bool {
+        self.challenges.is_empty()
+    }
+
+    /// Returns the challenges in issue order
+    pub fn challenges(&self) -> &[Challenge] {
+        &self.challenges
+    }
+
+    /// Returns the challenges, consuming the bundle
+    pub fn into_challenges(self) -> Vec<Challenge> {
+        self.challenges
+    }
+
+    /// Serializes the bundle
+    ///
+    /// The format is a version byte, the number of challenges (big-endian
+    /// u32), then per challenge its id, the length of its record (big-endian
+    /// u32) and the record. The challenge bytes are included, so the bundle
+    /// must be handled as confidentially as the challenges themselves.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![BUNDLE_VERSION];
+        bytes.extend_from_slice(&(self.challenges.len() as u32).to_be_bytes());
+        for challenge in &self.challenges {
+            let record = encode_record(challenge);
+            bytes.extend_from_slice(challenge.id.as_bytes());
+            bytes.extend_from_slice(&(record.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(&record);
+        }
+        bytes
+    }
+
+    /// Parses a bundle written by [`to_bytes`](Self::to_bytes)
+    ///
+    /// Fails with [`Error::InvalidChallenge`] for truncated input, trailing
+    /// bytes or an unknown version.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let malformed = || Error::InvalidChallenge("malformed challenge bundle");
+
+        let (&version, mut rest) = bytes.split_first().ok_or_else(malformed)?;
+        if version != BUNDLE_VERSION {
+            return Err(Error::InvalidChallenge(
+                "unsupported challenge bundle version",
+            ));
+        }
+        let count = take_u32(&mut rest).ok_or_else(malformed)?;
+
+        // Every entry takes at least an id and a length, which bounds the
+        // allocation by the input size
+        let mut challenges = Vec::with_capacity(count.min(rest.len() / (CHALLENGE_ID_LEN + 4)));
+        for _ in 0..count {
+            let id = take(&mut rest, CHALLENGE_ID_LEN).ok_or_else(malformed)?;
+            let id = ChallengeId::from_bytes(id.try_into().expect("took CHALLENGE_ID_LEN bytes"));
+            let len = take_u32(&mut rest).ok_or_else(malformed)?;
+            let record = take(&mut rest, len).ok_or_else(malformed)?;
+            challenges.push(decode_record(id, record).map_err(|_| malformed())?);
+        }
+        if !rest.is_empty() {
+            return Err(malformed());
+        }
+        Ok(Self { challenges })
+    }
+}
+
+impl IntoIterator for ChallengeBundle {
+    type Item = Challenge;
+    type IntoIter = std::vec::IntoIter<Challenge>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.challenges.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a ChallengeBundle {
+    type Item = &'a Challenge;
+    type IntoIter = std::slice::Iter<'a, Challenge>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.challenges.iter()
+    }
+}
+
+fn take<'a>(rest: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if rest.len() < len {
+        return None;
+    }
+    let (head, tail) = rest.split_at(len);
+    *rest = tail;
+    Some(head)
+}
+
+fn take_u32(rest: &mut &[u8]) -> Option<usize> {
+    let bytes = take(rest, 4)?;
+    Some(u32::from_be_bytes(bytes.try_into().expect("took 4 bytes")) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    fn challenge(id: u8, binding: ChallengeBinding) -> Challenge {
+        Challenge {
+            id: ChallengeId::from_bytes([id; CHALLENGE_ID_LEN]),
+            bytes: vec![id; 8],
+            expires_at: UNIX_EPOCH + Duration::from_millis(1_700_000_000_000 + u64::from(id)),
+            binding,
+            difficulty: id,
+        }
+    }
+
+    #[test]
+    fn test_bundle_round_trip() {
+        let bundle = ChallengeBundle::new(vec![
+            challenge(1, ChallengeBinding::default()),
+            challenge(2, ChallengeBinding::new().with_user_id("alice")),
+        ]);
+        let bytes = bundle.to_bytes();
+        assert_eq!(ChallengeBundle::from_bytes(&bytes), Ok(bundle.clone()));
+
+        let empty = ChallengeBundle::default();
+        assert_eq!(ChallengeBundle::from_bytes(&empty.to_bytes()), Ok(empty));
+
+        for len in 0..bytes.len() {
+            assert!(ChallengeBundle::from_bytes(&bytes[..len]).is_err());
+        }
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(ChallengeBundle::from_bytes(&trailing).is_err());
+
+        let mut version = bytes;
+        version[0] = 2;
+        assert_eq!(
+            ChallengeBundle::from_bytes(&version),
+            Err(Error::InvalidChallenge(
+                "unsupported challenge bundle version"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_huge_count_is_rejected() {
+        let mut bytes = vec![BUNDLE_VERSION];
+        bytes.extend_from_slice(&u32::MAX.to_be_bytes());
+        assert!(ChallengeBundle::from_bytes(&bytes).is_err());
+    }
+}
@@ -0,0 +1,249 @@
+//! Binding challenges to an identity and purpose
+
+use std::fmt;
+use std::net::IpAddr;
+
+use crate::Error;
+
+/// Domain separator between the challenge bytes and the bound fields
+const BINDING_CONTEXT: &[u8] = b"passcode/v1/binding";
+
+const TAG_USER_ID: u8 = 1;
+const TAG_DEVICE_ID: u8 = 2;
+const TAG_CLIENT_IP: u8 = 3;
+const TAG_PURPOSE: u8 = 4;
+
+/// A field of a [`ChallengeBinding`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BindingField {
+    /// The user ID
+    UserId,
+    /// The device ID
+    DeviceId,
+    /// The client's network address
+    ClientIp,
+    /// The operation the challenge authorizes
+    Purpose,
+}
+
+impl BindingField {
+    /// Returns the field name
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BindingField::UserId => "user_id",
+            BindingField::DeviceId => "device_id",
+            BindingField::ClientIp => "client_ip",
+            BindingField::Purpose => "purpose",
+        }
+    }
+}
+
+impl fmt::Display for BindingField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Identity and purpose a challenge is issued for
+///
+/// The bound fields are mixed into the message the OTP is computed over, so
+/// a response obtained for one user, device, address or purpose does not
+/// verify for another. Unset fields are left out; a binding with no fields
+/// leaves the message equal to the challenge bytes.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ChallengeBinding {
+    user_id: Option<String>,
+    device_id: Option<String>,
+    client_ip: Option<IpAddr>,
+    purpose: Option<String>,
+}
+
+impl ChallengeBinding {
+    /// Creates a binding with no fields set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Binds the challenge to a user
+    pub fn with_user_id(mut self, user_id: impl Into<String>) -> Self {
+        self.user_id = Some(user_id.into());
+        self
+    }
+
+    /// Binds the challenge to a device
+    pub fn with_device_id(mut self, device_id: impl Into<String>) -> Self {
+        self.device_id = Some(device_id.into());
+        self
+    }
+
+    /// Binds the challenge to the client's network address
+    pub fn with_client_ip(mut self, client_ip: IpAddr) -> Self {
+        self.client_ip = Some(client_ip);
+        self
+    }
+
+    /// Binds the challenge to an operation, e.g. `"login"` or `"transfer"`
+    pub fn with_purpose(mut self, purpose: impl Into<String>) -> Self {
+        self.purpose = Some(purpose.into());
+        self
+    }
+
+    /// Gets the bound user ID
+    pub fn user_id(&self) -> Option<&str> {
+        self.user_id.as_deref()
+    }
+
+    /// Gets the bound device ID
+    pub fn device_id(&self) -> Option<&str> {
+        self.device_id.as_deref()
+    }
+
+    /// Gets the bound client address
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        self.client_ip
+    }
+
+    /// Gets the bound purpose
+    pub fn purpose(&self) -> Option<&str> {
+        self.purpose.as_deref()
+    }
+
+    /// Returns true when the field is bound
+    pub fn has(&self, field: BindingField) -> bool {
+        match field {
+            BindingField::UserId => self.user_id.is_some(),
+            BindingField::DeviceId => self.device_id.is_some(),
+            BindingField::ClientIp => self.client_ip.is_some(),
+            BindingField::Purpose => self.purpose.is_some(),
+        }
+    }
+
+    /// Returns true when no field is bound
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Builds the message the OTP is computed over for the challenge bytes
+    ///
+    /// Clients use this with the challenge bytes and the binding sent by the
+    /// server.
+    pub fn message(&self, challenge: &[u8]) -> Vec<u8> {
+        if self.is_empty() {
+            return challenge.to_vec();
+        }
+
+        let fields = self.encode();
+        let mut message =
+            Vec::with_capacity(challenge.len() + BINDING_CONTEXT.len() + fields.len());
+        message.extend_from_slice(challenge);
+        message.extend_from_slice(BINDING_CONTEXT);
+        message.extend_from_slice(&fields);
+        message
+    }
+
+    /// Encodes the set fields as `tag || u32 length || value` in tag order
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let client_ip = self.client_ip.map(|ip| ip.to_string());
+        let fields = [
+            (TAG_USER_ID, self.user_id.as_deref()),
+            (TAG_DEVICE_ID, self.device_id.as_deref()),
+            (TAG_CLIENT_IP, client_ip.as_deref()),
+            (TAG_PURPOSE, self.purpose.as_deref()),
+        ];
+
+        let mut encoded = Vec::new();
+        for (tag, value) in fields {
+            if let Some(value) = value {
+                encoded.push(tag);
+                encoded.extend_from_slice(&(value.len() as u32).to_be_bytes());
+                encoded.extend_from_slice(value.as_bytes());
+            }
+        }
+        encoded
+    }
+
+    /// Parses fields written by [`encode`](Self::encode)
+    pub(crate) fn decode(mut encoded: &[u8]) -> Result<Self, Error> {
+        let malformed = || Error::ChallengeStore("malformed challenge binding".to_string());
+        let mut binding = Self::default();
+
+        while let Some((&tag, rest)) = encoded.split_first() {
+            if rest.len() < 4 {
+                return Err(malformed());
+            }
+            let (len, rest) = rest.split_at(4);
+            let len = u32::from_be_bytes(len.try_into().expect("split at 4")) as usize;
+            if rest.len() < len {
+                return Err(malformed());
+            }
+            let (value, rest) = rest.split_at(len);
+            let value = std::str::from_utf8(value)
+                .map_err(|_| malformed())?
+                .to_string();
+
+            match tag {
+                TAG_USER_ID => binding.user_id = Some(value),
+                TAG_DEVICE_ID => binding.device_id = Some(value),
+                TAG_CLIENT_IP => binding.client_ip = Some(value.parse().map_err(|_| malformed())?),
+                TAG_PURPOSE => binding.purpose = Some(value),
+                _ => return Err(malformed()),
+            }
+            encoded = rest;
+        }
+        Ok(binding)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_binding_leaves_message_unchanged() {
+        assert_eq!(ChallengeBinding::new().message(b"challenge"), b"challenge");
+        assert!(ChallengeBinding::new().encode().is_empty());
+    }
+
+    #[test]
+    fn test_fields_change_message() {
+        let alice = ChallengeBinding::new()
+            .with_user_id("alice")
+            .with_purpose("login");
+        let bob = ChallengeBinding::new()
+            .with_user_id("bob")
+            .with_purpose("login");
+        let transfer = ChallengeBinding::new()
+            .with_user_id("alice")
+            .with_purpose("transfer");
+
+        assert_ne!(alice.message(b"c"), bob.message(b"c"));
+        assert_ne!(alice.message(b"c"), transfer.message(b"c"));
+        // Fields are length-prefixed, so values cannot shift between fields
+        assert_ne!(
+            ChallengeBinding::new()
+                .with_user_id("ab")
+                .with_device_id("c")
+                .message(b""),
+            ChallengeBinding::new()
+                .with_user_id("a")
+                .with_device_id("bc")
+                .message(b"")
+        );
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let binding = ChallengeBinding::new()
+            .with_user_id("alice")
+            .with_device_id("phone-1")
+            .with_client_ip("2001:db8::1".parse().unwrap())
+            .with_purpose("transfer");
+
+        assert_eq!(
+            ChallengeBinding::decode(&binding.encode()),
+            Ok(binding.clone())
+        );
+        assert!(ChallengeBinding::decode(&binding.encode()[..5]).is_err());
+        assert!(ChallengeBinding::decode(&[9, 0, 0, 0, 0]).is_err());
+    }
+}
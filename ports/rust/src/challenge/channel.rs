@@ -0,0 +1,164 @@
+//! Binding responses to the TLS channel
+
+use std::fmt;
+
+use sha2::{Digest, Sha256};
+
+/// Domain separator between the message and the channel binding data
+const CHANNEL_CONTEXT: &[u8] = b"passcode/v1/channel";
+
+/// Exporter label for `tls-exporter` channel bindings (RFC 9266)
+pub const TLS_EXPORTER_LABEL: &str = "EXPORTER-Channel-Binding";
+
+/// Length of `tls-exporter` keying material in bytes (RFC 9266)
+pub const TLS_EXPORTER_LEN: usize = 32;
+
+/// Kind of a [`ChannelBinding`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChannelBindingType {
+    /// Exported keying material of the TLS connection (RFC 9266), unique to
+    /// each connection; requires TLS 1.3 or the extended master secret
+    TlsExporter,
+    /// Hash of the server's certificate (RFC 5929), the same for every
+    /// connection to that server
+    TlsServerEndPoint,
+}
+
+impl ChannelBindingType {
+    /// Returns the registered channel binding type name
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChannelBindingType::TlsExporter => "tls-exporter",
+            ChannelBindingType::TlsServerEndPoint => "tls-server-end-point",
+        }
+    }
+}
+
+impl fmt::Display for ChannelBindingType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The TLS channel a response is sent over
+///
+/// Each side takes the binding data from its own end of the TLS connection
+/// and mixes it into the message the OTP is computed over. Behind a
+/// phishing proxy the client's connection ends at the attacker, so its data
+/// differs from the server's and the relayed OTP does not verify. The data
+/// is never sent with the challenge: a client must not take it from the
+/// server.
+///
+/// With rustls, for example, the `tls-exporter` data is
+/// `export_keying_material` over [`TLS_EXPORTER_LABEL`] with no context,
+/// [`TLS_EXPORTER_LEN`] bytes long.
+///
+/// # Example
+/// ```
+/// use passcode::challenge::{ChallengeBinding, ChallengeManager, ChannelBinding};
+/// use passcode::{Algorithm, Passcode};
+///
+/// # pollster::block_on(async {
+/// let key = vec![0u8; 32];
+/// let server = ChallengeManager::new(Passcode::new(Algorithm::Sha3Kmac256, key.clone()));
+/// let challenge = server.issue().await.unwrap();
+///
+/// // Both ends export the same keying material from an unproxied connection
+/// let channel = ChannelBinding::tls_exporter([7u8; 32]);
+///
+/// // Client
+/// let client = Passcode::new(Algorithm::Sha3Kmac256, key);
+/// let otp = client.compute(&channel.bind(&challenge.message()));
+///
+/// // Server
+/// let binding = ChallengeBinding::new();
+/// assert!(server
+///     .verify_channel_bound(challenge.id(), &binding, &channel, &otp)
+///     .await
+///     .is_ok());
+/// # });
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ChannelBinding {
+    kind: ChannelBindingType,
+    data: Vec<u8>,
+}
+
+impl ChannelBinding {
+    /// Creates a binding from `tls-exporter` keying material
+    pub fn tls_exporter(keying_material: impl Into<Vec<u8>>) -> Self {
+        Self {
+            kind: ChannelBindingType::TlsExporter,
+            data: keying_material.into(),
+        }
+    }
+
+    /// Creates a binding from a `tls-server-end-point` certificate hash
+    ///
+    /// The hash is taken with the certificate's signature hash function,
+    /// or SHA-256 when that is MD5 or SHA-1.
+    pub fn tls_server_end_point(certificate_hash: impl Into<Vec<u8>>) -> Self {
+        Self {
+            kind: ChannelBindingType::TlsServerEndPoint,
+            data: certificate_hash.into(),
+        }
+    }
+
+    /// Creates a `tls-server-end-point` binding by hashing a DER-encoded
+    /// certificate with SHA-256, for certificates signed with SHA-256 or
+    /// weaker hashes
+    pub fn tls_server_end_point_sha256(certificate_der: &[u8]) -> Self {
+        Self::tls_server_end_point(Sha256::digest(certificate_der).to_vec())
+    }
+
+    /// Gets the kind of binding
+    pub fn kind(&self) -> ChannelBindingType {
+        self.kind
+    }
+
+    /// Gets the binding data
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Builds the message the OTP is computed over, from the message of
+    /// the challenge and its binding
+    pub fn bind(&self, message: &[u8]) -> Vec<u8> {
+        let name = self.kind.as_str().as_bytes();
+        let mut bound = Vec::with_capacity(
+            message.len() + CHANNEL_CONTEXT.len() + 1 + name.len() + 4 + self.data.len(),
+        );
+        bound.extend_from_slice(message);
+        bound.extend_from_slice(CHANNEL_CONTEXT);
+        bound.push(name.len() as u8);
+        bound.extend_from_slice(name);
+        bound.extend_from_slice(&(self.data.len() as u32).to_be_bytes());
+        bound.extend_from_slice(&self.data);
+        bound
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bind_separates_kinds_and_data() {
+        let exporter = ChannelBinding::tls_exporter([1u8; TLS_EXPORTER_LEN]);
+        let end_point = ChannelBinding::tls_server_end_point([1u8; TLS_EXPORTER_LEN]);
+        let other = ChannelBinding::tls_exporter([2u8; TLS_EXPORTER_LEN]);
+
+        let message = exporter.bind(b"challenge");
+        assert!(message.starts_with(b"challenge"));
+        assert_ne!(message, end_point.bind(b"challenge"));
+        assert_ne!(message, other.bind(b"challenge"));
+        assert_eq!(exporter.kind().to_string(), "tls-exporter");
+    }
+
+    #[test]
+    fn test_server_end_point_sha256() {
+        let binding = ChannelBinding::tls_server_end_point_sha256(b"certificate");
+        assert_eq!(binding.kind(), ChannelBindingType::TlsServerEndPoint);
+        assert_eq!(binding.data(), Sha256::digest(b"certificate").as_slice());
+    }
+}
@@ -0,0 +1,1764 @@
+//! Server-side challenge lifecycle
+//!
+//! [`ChallengeManager`] issues random challenges, keeps them in a
+//! [`ChallengeStore`] until they expire, and verifies each one at most once.
+//! Any verification attempt consumes the challenge, so a client cannot retry
+//! OTP guesses against the same challenge.
+//!
+//! The store is pluggable so that several server instances can share
+//! challenges through whatever storage the deployment already runs. The
+//! in-memory [`MemoryStore`] is used by default. [`SignedChallenges`] avoids
+//! storage altogether by authenticating each challenge with a server key.
+//!
+//! # Example
+//! ```
+//! use passcode::challenge::ChallengeManager;
+//! use passcode::{Algorithm, Passcode};
+//!
+//! # pollster::block_on(async {
+//! let key = vec![0u8; 32];
+//! let server = ChallengeManager::new(Passcode::new(Algorithm::Sha3Kmac256, key.clone()));
+//!
+//! // Server: issue a challenge and send its id and bytes to the client
+//! let challenge = server.issue().await.unwrap();
+//!
+//! // Client: compute the OTP over the challenge bytes
+//! let client = Passcode::new(Algorithm::Sha3Kmac256, key);
+//! let otp = client.compute(challenge.bytes());
+//!
+//! // Server: verify; the challenge cannot be used again
+//! assert!(server.verify(challenge.id(), &otp).await.is_ok());
+//! assert!(server.verify(challenge.id(), &otp).await.is_err());
+//! # });
+//! ```
+//!
+//! # Stores
+//!
+//! [`MemoryStore`] holds at most [`DEFAULT_CAPACITY`] challenges in one
+//! process. With the `redis-store` feature, `RedisStore` shares challenges
+//! between replicas and consumes them atomically in a Lua script; with the
+//! `sqlite-store` feature, `SqliteStore` keeps challenges and HOTP counters
+//! across restarts. Other storage plugs in through [`ChallengeStore`], whose
+//! `get_and_delete` must be atomic so a challenge is never consumed twice.
+//!
+//! # Binding and policy
+//!
+//! A [`ChallengeBinding`] mixes a user, device, client IP and purpose into
+//! the OTP message, so a response obtained for one request fails for
+//! another; a [`ChannelBinding`] does the same for the TLS connection. A
+//! [`ChallengePolicy`] makes a minimum length, a maximum lifetime and bound
+//! fields mandatory on issuance and verification.
+//!
+//! Rate limiting, lockout, anomaly detection, replay guards, client puzzles,
+//! time steps and uniform failure timing are configured on the manager with
+//! the matching `with_*` methods.
+
+mod batch;
+mod binding;
+mod channel;
+mod policy;
+mod puzzle;
+#[cfg(feature = "redis-store")]
+mod redis;
+mod replay;
+mod response;
+mod signed;
+#[cfg(feature = "sqlite-store")]
+mod sqlite;
+mod store;
+mod timed;
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::clock::{self, Clock};
+use crate::keyring::KeyRing;
+use crate::rng::{CryptoRngCore, SharedRng};
+use crate::throttle::{AnomalyDetector, Lockout, LockoutEvent, RateLimiter};
+use crate::trace;
+use crate::{Error, Passcode};
+use timed::{timed_message, TimeWindow};
+
+#[cfg(feature = "redis-store")]
+pub use self::redis::{RedisStore, DEFAULT_KEY_PREFIX};
+pub use batch::{BatchPolicy, ChallengeBundle};
+pub use binding::{BindingField, ChallengeBinding};
+pub use channel::{ChannelBinding, ChannelBindingType, TLS_EXPORTER_LABEL, TLS_EXPORTER_LEN};
+pub use policy::{ChallengePolicy, MIN_CHALLENGE_LEN};
+pub use puzzle::{check_puzzle, solve_puzzle, MAX_PUZZLE_DIFFICULTY};
+pub use replay::{ReplayGuard, DEFAULT_REPLAY_CAPACITY};
+pub use response::{ResponseToken, MAX_RESPONSE_TOKEN_LEN};
+pub use signed::{SignedChallenges, NONCE_LEN, SIGNED_CHALLENGE_LEN};
+#[cfg(feature = "sqlite-store")]
+pub use sqlite::SqliteStore;
+pub use store::{ChallengeStore, MemoryStore, DEFAULT_CAPACITY, DEFAULT_SWEEP_INTERVAL};
+pub use timed::time_step;
+
+/// Default number of random challenge bytes
+pub const DEFAULT_CHALLENGE_LEN: usize = 32;
+
+/// Default lifetime of an issued challenge
+pub const DEFAULT_TTL: Duration = Duration::from_secs(120);
+
+/// Length in bytes of a challenge identifier
+pub const CHALLENGE_ID_LEN: usize = 16;
+
+/// Random identifier of an issued challenge
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChallengeId([u8; CHALLENGE_ID_LEN]);
+
+impl ChallengeId {
+    /// Creates an identifier from its bytes
+    pub fn from_bytes(bytes: [u8; CHALLENGE_ID_LEN]) -> Self {
+        Self(bytes)
+    }
+
+    /// Gets the identifier bytes
+    pub fn as_bytes(&self) -> &[u8; CHALLENGE_ID_LEN] {
+        &self.0
+    }
+}
+
+impl fmt::Display for ChallengeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+impl FromStr for ChallengeId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let mut bytes = [0u8; CHALLENGE_ID_LEN];
+        hex::decode_to_slice(s, &mut bytes).map_err(|_| Error::ChallengeNotFound)?;
+        Ok(Self(bytes))
+    }
+}
+
+/// An issued challenge, to be sent to the client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Challenge {
+    id: ChallengeId,
+    bytes: Vec<u8>,
+    expires_at: SystemTime,
+    binding: ChallengeBinding,
+    difficulty: u8,
+}
+
+impl Challenge {
+    /// Assembles a challenge from its parts, e.g. when deserializing
+    #[cfg(any(feature = "serde", feature = "proto"))]
+    pub(crate) fn from_parts(
+        id: ChallengeId,
+        bytes: Vec<u8>,
+        expires_at: SystemTime,
+        binding: ChallengeBinding,
+        difficulty: u8,
+    ) -> Self {
+        Self {
+            id,
+            bytes,
+            expires_at,
+            binding,
+            difficulty,
+        }
+    }
+
+    /// Draws a fresh identifier and `len` challenge bytes from `rng`
+    pub(crate) fn generate(
+        rng: &SharedRng,
+        len: usize,
+        expires_at: SystemTime,
+        binding: ChallengeBinding,
+        difficulty: u8,
+    ) -> Result<Self, Error> {
+        let mut id = [0u8; CHALLENGE_ID_LEN];
+        rng.fill(&mut id)?;
+        let mut bytes = vec![0u8; len];
+        rng.fill(&mut bytes)?;
+
+        Ok(Self {
+            id: ChallengeId(id),
+            bytes,
+            expires_at,
+            binding,
+            difficulty,
+        })
+    }
+
+    /// Gets the identifier the client sends back with its OTP
+    pub fn id(&self) -> &ChallengeId {
+        &self.id
+    }
+
+    /// Gets the random challenge bytes
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Gets the identity and purpose the challenge is bound to
+    pub fn binding(&self) -> &ChallengeBinding {
+        &self.binding
+    }
+
+    /// Builds the message the client computes the OTP over
+    ///
+    /// This is the challenge bytes followed by the bound fields, or just the
+    /// challenge bytes for an unbound challenge.
+    pub fn message(&self) -> Vec<u8> {
+        self.binding.message(&self.bytes)
+    }
+
+    /// Gets the time after which the challenge is no longer accepted
+    pub fn expires_at(&self) -> SystemTime {
+        self.expires_at
+    }
+
+    /// Gets the difficulty in bits of the attached puzzle, zero if none
+    pub fn difficulty(&self) -> u8 {
+        self.difficulty
+    }
+
+    /// Solves the attached puzzle, for clients to send with their OTP
+    ///
+    /// See [`solve_puzzle`]; returns zero at once when no puzzle is attached.
+    pub fn solve_puzzle(&self) -> u64 {
+        solve_puzzle(&self.bytes, self.difficulty)
+    }
+}
+
+/// Issues, stores and verifies single-use challenges
+///
+/// A manager verifies with either one key or a [`KeyRing`], in which case
+/// the key is looked up by the user ID of the request's binding.
+pub struct ChallengeManager<S = MemoryStore> {
+    keys: Keys,
+    store: S,
+    ttl: Duration,
+    challenge_len: usize,
+    policy: Option<ChallengePolicy>,
+    replay_guard: Option<ReplayGuard>,
+    rate_limiter: Option<RateLimiter>,
+    lockout: Option<Lockout>,
+    anomaly_detector: Option<AnomalyDetector>,
+    clock: Arc<dyn Clock>,
+    /// Whether `clock` was set and is shared with the throttles
+    custom_clock: bool,
+    rng: SharedRng,
+    difficulty: u8,
+    time_window: Option<TimeWindow>,
+    uniform_timing: bool,
+    #[cfg(feature = "tokio")]
+    offload_threshold: Option<usize>,
+}
+
+/// Source of the key a response is verified with
+enum Keys {
+    Single(Arc<Passcode>),
+    Ring(KeyRing),
+}
+
+impl Keys {
+    /// Returns the keys to try for a request, each with its device
+    fn candidates(
+        &self,
+        binding: &ChallengeBinding,
+        now: SystemTime,
+    ) -> Vec<(Option<String>, Arc<Passcode>)> {
+        match (self, binding.user_id()) {
+            (Keys::Single(passcode), _) => vec![(None, Arc::clone(passcode))],
+            (Keys::Ring(keyring), Some(user_id)) => {
+                keyring.candidates(user_id, binding.device_id(), now)
+            }
+            (Keys::Ring(_), None) => Vec::new(),
+        }
+    }
+
+    /// Returns the identity a request's attempts are throttled under
+    ///
+    /// This is the binding's user ID, or for requests without one the ID of
+    /// the single key, which they all guess at. A key ring has no key for
+    /// such requests, so they have no identity and are not throttled.
+    fn identity(&self, binding: &ChallengeBinding) -> Option<String> {
+        match (self, binding.user_id()) {
+            (_, Some(user_id)) => Some(user_id.to_string()),
+            (Keys::Single(passcode), None) => Some(passcode.key_id().to_string()),
+            (Keys::Ring(_), None) => None,
+        }
+    }
+}
+
+/// A verified response
+pub(crate) struct Accepted {
+    /// Key the response was verified with
+    pub(crate) passcode: Arc<Passcode>,
+    /// Message the OTP was computed over
+    pub(crate) message: Vec<u8>,
+    /// Time steps the client's clock is ahead, zero for untimed responses
+    pub(crate) skew: i64,
+    /// Registered device whose key verified the response
+    pub(crate) device_id: Option<String>,
+}
+
+impl ChallengeManager<MemoryStore> {
+    /// Creates a manager keeping challenges in a [`MemoryStore`]
+    ///
+    /// Challenges are [`DEFAULT_CHALLENGE_LEN`] bytes and expire after
+    /// [`DEFAULT_TTL`].
+    pub fn new(passcode: Passcode) -> Self {
+        Self::with_store(passcode, MemoryStore::new())
+    }
+}
+
+impl<S: ChallengeStore> ChallengeManager<S> {
+    /// Creates a manager keeping challenges in the given store
+    pub fn with_store(passcode: Passcode, store: S) -> Self {
+        Self::with_keys(Keys::Single(Arc::new(passcode)), store)
+    }
+
+    /// Creates a manager verifying each user with their key from the ring
+    ///
+    /// Challenges must be issued and verified with a binding carrying the
+    /// user ID; responses for users without a key fail with
+    /// [`Error::OtpMismatch`]. Users with registered devices are verified
+    /// with their devices' keys, see [`verify_device`](Self::verify_device).
+    pub fn with_keyring(keyring: KeyRing, store: S) -> Self {
+        Self::with_keys(Keys::Ring(keyring), store)
+    }
+
+    fn with_keys(keys: Keys, store: S) -> Self {
+        Self {
+            keys,
+            store,
+            ttl: DEFAULT_TTL,
+            challenge_len: DEFAULT_CHALLENGE_LEN,
+            policy: None,
+            replay_guard: None,
+            rate_limiter: None,
+            lockout: None,
+            anomaly_detector: None,
+            clock: clock::system(),
+            custom_clock: false,
+            rng: SharedRng::os(),
+            difficulty: 0,
+            time_window: None,
+            uniform_timing: false,
+            #[cfg(feature = "tokio")]
+            offload_threshold: None,
+        }
+    }
+
+    /// Sets how long issued challenges remain valid
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Sets the number of random bytes per challenge
+    pub fn with_challenge_len(mut self, len: usize) -> Self {
+        self.challenge_len = len;
+        self
+    }
+
+    /// Enforces a [`ChallengePolicy`] on issued and verified challenges
+    ///
+    /// Issuing fails with [`Error::ChallengeTooShort`],
+    /// [`Error::ChallengeLifetimeTooLong`] or [`Error::BindingFieldMissing`]
+    /// when the manager's settings or the binding fall short of the policy.
+    /// Verification fails with the same errors for requests lacking a
+    /// required field, without consuming the challenge, and for stored
+    /// challenges that no longer meet the policy.
+    pub fn with_policy(mut self, policy: ChallengePolicy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Remembers accepted challenges in a [`ReplayGuard`]
+    ///
+    /// The store already consumes each challenge on its first use; the guard
+    /// additionally rejects a second acceptance of the same challenge
+    /// message, whatever the OTP's formatting, for stores whose consumption
+    /// is not strictly atomic.
+    pub fn with_replay_guard(mut self, mut guard: ReplayGuard) -> Self {
+        if self.custom_clock {
+            guard.set_clock(Arc::clone(&self.clock));
+        }
+        self.replay_guard = Some(guard);
+        self
+    }
+
+    /// Throttles verification attempts with a [`RateLimiter`]
+    ///
+    /// Attempts are counted per user ID of the request's binding. Requests
+    /// without a user ID are counted under the
+    /// [`KeyId`](crate::kdf::KeyId) of the manager's single key, as they all
+    /// guess at that key; a manager with a [`KeyRing`] cannot verify them and
+    /// does not throttle them. A throttled attempt fails with
+    /// [`Error::RateLimited`] without consuming the challenge.
+    pub fn with_rate_limiter(mut self, mut limiter: RateLimiter) -> Self {
+        if self.custom_clock {
+            limiter.set_clock(Arc::clone(&self.clock));
+        }
+        self.rate_limiter = Some(limiter);
+        self
+    }
+
+    /// Locks identities out after repeated wrong OTPs with a [`Lockout`]
+    ///
+    /// Identities are the same as for [`with_rate_limiter`](Self::with_rate_limiter).
+    /// Only [`Error::OtpMismatch`] counts as a failure, charged to the
+    /// identity the challenge was issued to; a response whose binding differs
+    /// from the challenge's counts against no one. A success clears the
+    /// identity's failures. Attempts during a lock fail with
+    /// [`Error::LockedOut`] without consuming the challenge.
+    pub fn with_lockout(mut self, mut lockout: Lockout) -> Self {
+        if self.custom_clock {
+            lockout.set_clock(Arc::clone(&self.clock));
+        }
+        self.lockout = Some(lockout);
+        self
+    }
+
+    /// Reports identities and client addresses with unusually many wrong
+    /// OTPs to an [`AnomalyDetector`]
+    ///
+    /// Failures are counted like for [`with_lockout`](Self::with_lockout),
+    /// and additionally per client IP when the request's binding carries
+    /// one. The detector only reports; it never refuses an attempt.
+    pub fn with_anomaly_detector(mut self, mut detector: AnomalyDetector) -> Self {
+        if self.custom_clock {
+            detector.set_clock(Arc::clone(&self.clock));
+        }
+        self.anomaly_detector = Some(detector);
+        self
+    }
+
+    /// Attaches a proof-of-work puzzle of `difficulty` bits to issued
+    /// challenges
+    ///
+    /// Clients must send a solution with their OTP, found with
+    /// [`Challenge::solve_puzzle`] at a cost of about `2^difficulty` hashes,
+    /// and the manager checks it with [`verify_solved`](Self::verify_solved)
+    /// before the OTP. This makes every online guess expensive for the
+    /// client while costing the server one hash. The difficulty is capped at
+    /// [`MAX_PUZZLE_DIFFICULTY`].
+    pub fn with_puzzle(mut self, difficulty: u8) -> Self {
+        self.difficulty = difficulty.min(MAX_PUZZLE_DIFFICULTY);
+        self
+    }
+
+    /// Expects OTPs over [`Challenge::message_at`] for the current time
+    /// step, accepting `window` steps on either side
+    ///
+    /// This bounds how long a response stays valid independently of the
+    /// challenge TTL, while tolerating clients whose clocks drift by up to
+    /// `window` steps. Each step in the window costs one extra hash per
+    /// verification, so keep it small. Use
+    /// [`verify_timed`](Self::verify_timed) to learn the client's skew.
+    pub fn with_time_steps(mut self, step: Duration, window: u64) -> Self {
+        self.time_window = Some(TimeWindow { step, window });
+        self
+    }
+
+    /// Spends the same hash work on every failed verification when
+    /// `enabled`
+    ///
+    /// By default an unknown, already used or expired challenge fails before
+    /// any OTP is computed, so it is answered measurably faster than a
+    /// wrong OTP, and an attacker can tell valid challenge IDs apart by
+    /// timing. With uniform timing every failure that would not compute the
+    /// OTP checks it against a placeholder challenge with the same keys and
+    /// time window first: unknown, used and expired challenges, unsolved
+    /// puzzles, policy failures, store errors and responses whose binding
+    /// differs from the challenge's. This costs the hashes of a wrong OTP on
+    /// every such failure. Lockout and rate-limit rejections are not padded,
+    /// so refusing an attempt stays cheap; they reveal nothing about the
+    /// challenge or the OTP.
+    ///
+    /// Two leaks remain. The failures still return distinct errors, so a
+    /// server must answer them all alike, e.g. with one generic rejection,
+    /// for the timing to matter. And a [`KeyRing`] without a master key has
+    /// no key to hash with for unknown users, whose responses are rejected
+    /// faster than those of known users.
+    pub fn with_uniform_timing(mut self, enabled: bool) -> Self {
+        self.uniform_timing = enabled;
+        self
+    }
+
+    /// Verifies OTPs over messages of at least `bytes` bytes on Tokio's
+    /// blocking thread pool (feature `tokio`)
+    ///
+    /// Only worthwhile for challenges of many kilobytes, e.g. with a large
+    /// [`with_challenge_len`](Self::with_challenge_len); verification must
+    /// then run inside a Tokio runtime. See [`Passcode::verify_offloaded`].
+    #[cfg(feature = "tokio")]
+    pub fn with_offload_threshold(mut self, bytes: usize) -> Self {
+        self.offload_threshold = Some(bytes);
+        self
+    }
+
+    /// Reads the time for challenge expiry from `clock` instead of the
+    /// system clock
+    ///
+    /// The clock is also given to the replay guard, rate limiter, lockout
+    /// and anomaly detector, whether they are installed before or after it.
+    /// Stores such as [`MemoryStore`] take their own clock.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        let clock: Arc<dyn Clock> = Arc::new(clock);
+        if let Some(guard) = &mut self.replay_guard {
+            guard.set_clock(Arc::clone(&clock));
+        }
+        if let Some(limiter) = &mut self.rate_limiter {
+            limiter.set_clock(Arc::clone(&clock));
+        }
+        if let Some(lockout) = &mut self.lockout {
+            lockout.set_clock(Arc::clone(&clock));
+        }
+        if let Some(detector) = &mut self.anomaly_detector {
+            detector.set_clock(Arc::clone(&clock));
+        }
+        self.clock = clock;
+        self.custom_clock = true;
+        self
+    }
+
+    /// Draws challenge IDs and bytes from `rng` instead of the operating
+    /// system's CSPRNG, e.g. a seeded generator in tests
+    pub fn with_rng(mut self, rng: impl CryptoRngCore + Send + 'static) -> Self {
+        self.rng = SharedRng::new(rng);
+        self
+    }
+
+    /// Gets the lockout tracker, to query or reset an identity's state
+    pub fn lockout(&self) -> Option<&Lockout> {
+        self.lockout.as_ref()
+    }
+
+    /// Gets the anomaly detector, to query or reset a subject's failures
+    pub fn anomaly_detector(&self) -> Option<&AnomalyDetector> {
+        self.anomaly_detector.as_ref()
+    }
+
+    /// Gets the key ring, if the manager verifies with one
+    pub fn keyring(&self) -> Option<&KeyRing> {
+        match &self.keys {
+            Keys::Single(_) => None,
+            Keys::Ring(keyring) => Some(keyring),
+        }
+    }
+
+    /// Gets the underlying store
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Gets the clock challenges expire by
+    pub fn clock(&self) -> &dyn Clock {
+        self.clock.as_ref()
+    }
+
+    /// Gets the generator challenges are drawn from
+    pub(crate) fn rng(&self) -> &SharedRng {
+        &self.rng
+    }
+
+    /// Issues a new random challenge and stores it until it expires
+    pub async fn issue(&self) -> Result<Challenge, Error> {
+        self.issue_bound(ChallengeBinding::default()).await
+    }
+
+    /// Issues a challenge bound to an identity and purpose
+    ///
+    /// The client computes its OTP over [`Challenge::message`], and the
+    /// response only verifies with [`verify_bound`](Self::verify_bound) given
+    /// the same binding.
+    pub async fn issue_bound(&self, binding: ChallengeBinding) -> Result<Challenge, Error> {
+        if let Some(policy) = &self.policy {
+            policy.check_issue(self.challenge_len, self.ttl, &binding)?;
+        }
+        let challenge = self.generate(self.clock.now() + self.ttl, binding)?;
+        let stored = self.store.put(challenge.clone()).await;
+        trace::store("put", &stored);
+        stored?;
+        trace::issued(&challenge);
+        Ok(challenge)
+    }
+
+    /// Issues `n` challenges at once, e.g. to pre-provision a kiosk or print
+    /// a challenge sheet
+    ///
+    /// All challenges are stored with a single
+    /// [`put_many`](ChallengeStore::put_many) call and use the manager's
+    /// challenge length and puzzle difficulty. The TTL and binding come from
+    /// the policy. The returned bundle can be serialized with
+    /// [`ChallengeBundle::to_bytes`]; each challenge is verified as usual.
+    pub async fn issue_batch(
+        &self,
+        n: usize,
+        policy: BatchPolicy,
+    ) -> Result<ChallengeBundle, Error> {
+        // Bundles store the expiry in whole milliseconds; truncate it here so
+        // a deserialized bundle equals the issued one
+        let ttl = policy.ttl.unwrap_or(self.ttl);
+        if let Some(challenge_policy) = &self.policy {
+            challenge_policy.check_issue(self.challenge_len, ttl, &policy.binding)?;
+        }
+        let expires_at = self.clock.now() + ttl;
+        let millis = expires_at
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        let expires_at = UNIX_EPOCH + Duration::from_millis(millis);
+        let challenges = (0..n)
+            .map(|_| self.generate(expires_at, policy.binding.clone()))
+            .collect::<Result<Vec<_>, _>>()?;
+        if !challenges.is_empty() {
+            let stored = self.store.put_many(challenges.clone()).await;
+            trace::store("put_many", &stored);
+            stored?;
+        }
+        trace::batch_issued(n, ttl);
+        Ok(ChallengeBundle::new(challenges))
+    }
+
+    /// Verifies the OTP for an unbound challenge, consuming the challenge
+    ///
+    /// Fails with [`Error::ChallengeNotFound`] for unknown or already used
+    /// challenges, [`Error::ChallengeExpired`] after the TTL and
+    /// [`Error::OtpMismatch`] for a wrong OTP. In every case the challenge
+    /// cannot be verified again. Store failures are passed through.
+    pub async fn verify(&self, id: &ChallengeId, otp: &str) -> Result<(), Error> {
+        self.verify_bound(id, &ChallengeBinding::default(), otp)
+            .await
+    }
+
+    /// Verifies the OTP for a challenge issued with
+    /// [`issue_bound`](Self::issue_bound), consuming the challenge
+    ///
+    /// `binding` describes the current request. The OTP is checked against
+    /// the message for this binding, so it fails with [`Error::OtpMismatch`]
+    /// when any field differs from the one the challenge was issued for.
+    pub async fn verify_bound(
+        &self,
+        id: &ChallengeId,
+        binding: &ChallengeBinding,
+        otp: &str,
+    ) -> Result<(), Error> {
+        self.accept(id, binding, None, None, otp).await.map(|_| ())
+    }
+
+    /// Verifies the OTP and puzzle solution for a challenge issued with a
+    /// puzzle attached, consuming the challenge
+    ///
+    /// Fails with [`Error::PuzzleUnsolved`] when the solution is wrong,
+    /// without checking or counting the OTP; otherwise behaves like
+    /// [`verify_bound`](Self::verify_bound).
+    pub async fn verify_solved(
+        &self,
+        id: &ChallengeId,
+        binding: &ChallengeBinding,
+        solution: u64,
+        otp: &str,
+    ) -> Result<(), Error> {
+        self.accept(id, binding, None, Some(solution), otp)
+            .await
+            .map(|_| ())
+    }
+
+    /// Verifies a time-bound OTP like [`verify_bound`](Self::verify_bound),
+    /// returning the detected skew in time steps
+    ///
+    /// A positive skew means the client's clock is ahead. Clients can be
+    /// told to adjust when it is not zero. Without
+    /// [`with_time_steps`](Self::with_time_steps) the skew is always zero.
+    pub async fn verify_timed(
+        &self,
+        id: &ChallengeId,
+        binding: &ChallengeBinding,
+        otp: &str,
+    ) -> Result<i64, Error> {
+        self.accept(id, binding, None, None, otp)
+            .await
+            .map(|accepted| accepted.skew)
+    }
+
+    /// Verifies the OTP like [`verify_bound`](Self::verify_bound), returning
+    /// the registered device that answered
+    ///
+    /// With a [`KeyRing`], a user who registered devices is verified with
+    /// the key of the device named in the binding, or with each registered
+    /// device's key in turn when the binding names none. Returns `None` when
+    /// the response was verified with the user's own key.
+    pub async fn verify_device(
+        &self,
+        id: &ChallengeId,
+        binding: &ChallengeBinding,
+        otp: &str,
+    ) -> Result<Option<String>, Error> {
+        self.accept(id, binding, None, None, otp)
+            .await
+            .map(|accepted| accepted.device_id)
+    }
+
+    /// Verifies an OTP computed over the message bound to the TLS channel,
+    /// consuming the challenge
+    ///
+    /// `channel` must come from the server's end of the connection the
+    /// response arrived on. Fails with [`Error::OtpMismatch`] when the
+    /// client computed the OTP for another channel, e.g. through a phishing
+    /// proxy, and with [`Error::MalformedMessage`] when the binding data is
+    /// empty; otherwise behaves like [`verify_bound`](Self::verify_bound).
+    pub async fn verify_channel_bound(
+        &self,
+        id: &ChallengeId,
+        binding: &ChallengeBinding,
+        channel: &ChannelBinding,
+        otp: &str,
+    ) -> Result<(), Error> {
+        self.accept(id, binding, Some(channel), None, otp)
+            .await
+            .map(|_| ())
+    }
+
+    /// Verifies a response, returning the key it was verified with, the
+    /// message it was computed over, the time skew and the device
+    pub(crate) async fn accept(
+        &self,
+        id: &ChallengeId,
+        binding: &ChallengeBinding,
+        channel: Option<&ChannelBinding>,
+        solution: Option<u64>,
+        otp: &str,
+    ) -> Result<Accepted, Error> {
+        self.accept_reporting(id, binding, channel, solution, otp)
+            .await
+            .0
+    }
+
+    /// Verifies a response like [`accept`](Self::accept), also returning
+    /// the lock that a failure put on the identity
+    pub(crate) async fn accept_reporting(
+        &self,
+        id: &ChallengeId,
+        binding: &ChallengeBinding,
+        channel: Option<&ChannelBinding>,
+        solution: Option<u64>,
+        otp: &str,
+    ) -> (Result<Accepted, Error>, Option<LockoutEvent>) {
+        trace::verify(id, binding, async {
+            let started = Instant::now();
+            let mut locked_out = None;
+            let result = self
+                .check_response(id, binding, channel, solution, otp, &mut locked_out)
+                .await;
+            trace::verified(&result, started.elapsed());
+            (result, locked_out)
+        })
+        .await
+    }
+
+    async fn check_response(
+        &self,
+        id: &ChallengeId,
+        binding: &ChallengeBinding,
+        channel: Option<&ChannelBinding>,
+        solution: Option<u64>,
+        otp: &str,
+        locked_out: &mut Option<LockoutEvent>,
+    ) -> Result<Accepted, Error> {
+        if channel.is_some_and(|channel| channel.data().is_empty()) {
+            return Err(Error::MalformedMessage("channel binding data is empty"));
+        }
+        if let Some(policy) = &self.policy {
+            self.padded(binding, otp, policy.check_binding(binding))
+                .await?;
+        }
+        let identity = self.keys.identity(binding);
+        // Throttle rejections are not padded: they do not depend on the OTP,
+        // and must stay cheaper than a guess
+        if let (Some(lockout), Some(identity)) = (&self.lockout, &identity) {
+            lockout.check(identity)?;
+        }
+        if let (Some(limiter), Some(identity)) = (&self.rate_limiter, &identity) {
+            limiter.check(identity)?;
+        }
+
+        let challenge = self.store.get_and_delete(id).await;
+        trace::store("get_and_delete", &challenge);
+        let Some(challenge) = self.padded(binding, otp, challenge).await? else {
+            self.pad_failure(binding, otp).await;
+            return Err(Error::ChallengeNotFound);
+        };
+
+        let now = self.clock.now();
+        if now >= challenge.expires_at {
+            self.pad_failure(binding, otp).await;
+            return Err(Error::ChallengeExpired);
+        }
+        if let Some(policy) = &self.policy {
+            self.padded(binding, otp, policy.check_stored(&challenge, now))
+                .await?;
+        }
+        let solved = challenge.difficulty == 0
+            || solution.is_some_and(|n| check_puzzle(&challenge.bytes, challenge.difficulty, n));
+        if !solved {
+            self.pad_failure(binding, otp).await;
+            return Err(Error::PuzzleUnsolved);
+        }
+        let mut message = binding.message(&challenge.bytes);
+        if let Some(channel) = channel {
+            message = channel.bind(&message);
+        }
+        let mut matched = None;
+        let bound = *binding == challenge.binding;
+        if bound {
+            for (device_id, passcode) in self.keys.candidates(binding, now) {
+                self.padded(binding, otp, passcode.check_policy()).await?;
+                if let Some((message, skew)) = self.match_otp(&passcode, &message, otp).await {
+                    matched = Some((passcode, message, skew, device_id));
+                    break;
+                }
+            }
+        } else {
+            self.pad_failure(binding, otp).await;
+        }
+        let Some((passcode, matched_message, skew, device_id)) = matched else {
+            // Charge the identity the challenge was issued to. A response
+            // claiming another binding checked no OTP and is not counted
+            // against the identity it names.
+            let Some(identity) = self
+                .keys
+                .identity(&challenge.binding)
+                .filter(|_| bound)
+            else {
+                return Err(Error::OtpMismatch);
+            };
+            if let Some(event) = self
+                .lockout
+                .as_ref()
+                .and_then(|l| l.record_failure(&identity))
+            {
+                trace::locked_out(&event);
+                *locked_out = Some(event);
+            }
+            if let Some(detector) = &self.anomaly_detector {
+                for event in detector.record_failure(&identity, binding.client_ip()) {
+                    trace::anomaly(&event);
+                }
+            }
+            return Err(Error::OtpMismatch);
+        };
+        if let Some(guard) = &self.replay_guard {
+            guard.check_key(&message)?;
+        }
+        if let (Some(lockout), Some(identity)) = (&self.lockout, &identity) {
+            lockout.record_success(identity);
+        }
+        Ok(Accepted {
+            passcode,
+            message: matched_message,
+            skew,
+            device_id,
+        })
+    }
+
+    /// Pads `result` with [`pad_failure`](Self::pad_failure) if it is an
+    /// error
+    async fn padded<T>(
+        &self,
+        binding: &ChallengeBinding,
+        otp: &str,
+        result: Result<T, Error>,
+    ) -> Result<T, Error> {
+        if result.is_err() {
+            self.pad_failure(binding, otp).await;
+        }
+        result
+    }
+
+    /// Checks the OTP against a placeholder challenge with uniform timing,
+    /// so the failure takes as long as a wrong OTP
+    async fn pad_failure(&self, binding: &ChallengeBinding, otp: &str) {
+        if !self.uniform_timing {
+            return;
+        }
+        let message = binding.message(&vec![0u8; self.challenge_len]);
+        for (_, passcode) in self.keys.candidates(binding, self.clock.now()) {
+            std::hint::black_box(self.match_otp(&passcode, &message, otp).await);
+        }
+    }
+
+    /// Finds the message the OTP was computed over, trying each time step in
+    /// the window when responses are time-bound, and returns it with the
+    /// skew
+    async fn match_otp(
+        &self,
+        passcode: &Arc<Passcode>,
+        message: &[u8],
+        otp: &str,
+    ) -> Option<(Vec<u8>, i64)> {
+        let Some(window) = &self.time_window else {
+            return self
+                .check_otp(passcode, message, otp)
+                .await
+                .then(|| (message.to_vec(), 0));
+        };
+        for (step, skew) in window.candidates(self.clock.now()) {
+            let timed = timed_message(message, step);
+            if self.check_otp(passcode, &timed, otp).await {
+                return Some((timed, skew));
+            }
+        }
+        None
+    }
+
+    /// Checks the OTP, on the blocking thread pool for large messages
+    async fn check_otp(&self, passcode: &Arc<Passcode>, message: &[u8], otp: &str) -> bool {
+        #[cfg(feature = "tokio")]
+        if self
+            .offload_threshold
+            .is_some_and(|threshold| message.len() >= threshold)
+        {
+            return Arc::clone(passcode)
+                .verify_offloaded(message.to_vec(), otp.to_string())
+                .await;
+        }
+        passcode.verify(message, otp)
+    }
+
+    /// Drops expired challenges from the store, returning how many were
+    /// removed
+    pub async fn purge_expired(&self) -> Result<usize, Error> {
+        let purged = self.store.expire(self.clock.now()).await;
+        trace::store("expire", &purged);
+        purged
+    }
+
+    /// Creates a fresh random challenge without storing it
+    fn generate(
+        &self,
+        expires_at: SystemTime,
+        binding: ChallengeBinding,
+    ) -> Result<Challenge, Error> {
+        Challenge::generate(
+            &self.rng,
+            self.challenge_len,
+            expires_at,
+            binding,
+            self.difficulty,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keyrecord::KeyRecord;
+    use crate::Algorithm;
+    use pollster::block_on;
+
+    fn manager() -> ChallengeManager {
+        ChallengeManager::new(Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]))
+    }
+
+    fn respond(challenge: &Challenge) -> String {
+        Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]).compute(challenge.bytes())
+    }
+
+    #[test]
+    fn test_issue_and_verify_once() {
+        let manager = manager();
+        let challenge = block_on(manager.issue()).unwrap();
+        let otp = respond(&challenge);
+
+        assert_eq!(challenge.bytes().len(), DEFAULT_CHALLENGE_LEN);
+        assert_eq!(manager.store().len(), 1);
+        assert_eq!(block_on(manager.verify(challenge.id(), &otp)), Ok(()));
+        assert_eq!(
+            block_on(manager.verify(challenge.id(), &otp)),
+            Err(Error::ChallengeNotFound)
+        );
+        assert!(manager.store().is_empty());
+    }
+
+    #[test]
+    fn test_wrong_otp_consumes_challenge() {
+        let manager = manager();
+        let challenge = block_on(manager.issue()).unwrap();
+
+        assert_eq!(
+            block_on(manager.verify(challenge.id(), "000000000000")),
+            Err(Error::OtpMismatch)
+        );
+        assert_eq!(
+            block_on(manager.verify(challenge.id(), &respond(&challenge))),
+            Err(Error::ChallengeNotFound)
+        );
+    }
+
+    #[test]
+    fn test_expired_challenge() {
+        let manager = manager().with_ttl(Duration::ZERO);
+        let challenge = block_on(manager.issue()).unwrap();
+
+        assert_eq!(
+            block_on(manager.verify(challenge.id(), &respond(&challenge))),
+            Err(Error::ChallengeExpired)
+        );
+
+        block_on(manager.issue()).unwrap();
+        block_on(manager.issue()).unwrap();
+        assert_eq!(block_on(manager.purge_expired()), Ok(2));
+        assert!(manager.store().is_empty());
+    }
+
+    #[test]
+    fn test_uniform_timing_keeps_outcomes() {
+        let manager = manager().with_uniform_timing(true);
+        let unknown = ChallengeId::from_bytes([7u8; CHALLENGE_ID_LEN]);
+        assert_eq!(
+            block_on(manager.verify(&unknown, "000000000000")),
+            Err(Error::ChallengeNotFound)
+        );
+
+        let challenge = block_on(manager.issue()).unwrap();
+        let alice = ChallengeBinding::new().with_user_id("alice");
+        assert_eq!(
+            block_on(manager.verify_bound(challenge.id(), &alice, &respond(&challenge))),
+            Err(Error::OtpMismatch)
+        );
+
+        let challenge = block_on(manager.issue()).unwrap();
+        assert_eq!(
+            block_on(manager.verify(challenge.id(), &respond(&challenge))),
+            Ok(())
+        );
+
+        let manager = manager.with_ttl(Duration::ZERO);
+        let challenge = block_on(manager.issue()).unwrap();
+        assert_eq!(
+            block_on(manager.verify(challenge.id(), &respond(&challenge))),
+            Err(Error::ChallengeExpired)
+        );
+    }
+
+    #[test]
+    fn test_uniform_timing_pads_every_failure() {
+        use crate::kdf::KeyId;
+        use crate::MacBackend;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        /// HMAC-SHA-256 key counting its computations
+        struct Counting(Arc<AtomicUsize>);
+
+        impl MacBackend for Counting {
+            fn algorithm(&self) -> Algorithm {
+                Algorithm::HmacSha256
+            }
+
+            fn key_id(&self) -> KeyId {
+                KeyId::from_bytes([0u8; 8])
+            }
+
+            fn mac(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                Ok(crate::hotp::hmac(
+                    crate::hotp::HmacAlgorithm::Sha256,
+                    &[1u8; 32],
+                    data,
+                ))
+            }
+        }
+
+        let hashes = Arc::new(AtomicUsize::new(0));
+        let passcode = || Passcode::from_backend(Counting(Arc::clone(&hashes))).unwrap();
+        let wrong = Passcode::new(Algorithm::HmacSha256, vec![2u8; 32]).compute(b"other");
+        let unknown = ChallengeId::from_bytes([7u8; CHALLENGE_ID_LEN]);
+        let login = ChallengeBinding::new().with_purpose("login");
+
+        for uniform in [false, true] {
+            let plain = ChallengeManager::new(passcode()).with_uniform_timing(uniform);
+            let puzzles = ChallengeManager::new(passcode())
+                .with_puzzle(8)
+                .with_uniform_timing(uniform);
+            let strict = ChallengeManager::new(passcode())
+                .with_policy(ChallengePolicy::new().with_required(BindingField::Purpose))
+                .with_uniform_timing(uniform);
+            let limited = ChallengeManager::new(passcode())
+                .with_rate_limiter(RateLimiter::new(1, Duration::from_secs(60)))
+                .with_uniform_timing(uniform);
+            let challenge = block_on(puzzles.issue_bound(login.clone())).unwrap();
+            let unsolved = (0..)
+                .find(|&n| !check_puzzle(challenge.bytes(), 8, n))
+                .unwrap();
+            hashes.store(0, Ordering::SeqCst);
+
+            assert_eq!(
+                block_on(plain.verify(&unknown, &wrong)),
+                Err(Error::ChallengeNotFound)
+            );
+            assert_eq!(
+                block_on(puzzles.verify_solved(challenge.id(), &login, unsolved, &wrong)),
+                Err(Error::PuzzleUnsolved)
+            );
+            assert_eq!(
+                block_on(strict.verify(&unknown, &wrong)),
+                Err(Error::BindingFieldMissing(BindingField::Purpose))
+            );
+            assert_eq!(
+                block_on(limited.verify(&unknown, &wrong)),
+                Err(Error::ChallengeNotFound)
+            );
+            // Each failure costs the hash of a wrong OTP with uniform timing
+            let expected = if uniform { 4 } else { 0 };
+            assert_eq!(hashes.load(Ordering::SeqCst), expected);
+            // except throttle rejections
+            assert!(matches!(
+                block_on(limited.verify(&unknown, &wrong)),
+                Err(Error::RateLimited { .. })
+            ));
+            assert_eq!(hashes.load(Ordering::SeqCst), expected);
+        }
+    }
+
+    #[test]
+    fn test_challenges_are_unique() {
+        let manager = manager().with_challenge_len(16);
+        let a = block_on(manager.issue()).unwrap();
+        let b = block_on(manager.issue()).unwrap();
+
+        assert_ne!(a.id(), b.id());
+        assert_ne!(a.bytes(), b.bytes());
+        assert_eq!(a.bytes().len(), 16);
+    }
+
+    #[test]
+    fn test_custom_store() {
+        /// Store that refuses every write
+        struct ReadOnly;
+
+        impl ChallengeStore for ReadOnly {
+            async fn put(&self, _: Challenge) -> Result<(), Error> {
+                Err(Error::ChallengeStore("read-only".to_string()))
+            }
+
+            async fn get_and_delete(&self, _: &ChallengeId) -> Result<Option<Challenge>, Error> {
+                Ok(None)
+            }
+
+            async fn expire(&self, _: SystemTime) -> Result<usize, Error> {
+                Ok(0)
+            }
+        }
+
+        let manager = ChallengeManager::with_store(
+            Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]),
+            ReadOnly,
+        );
+        assert_eq!(
+            block_on(manager.issue()),
+            Err(Error::ChallengeStore("read-only".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_challenge_id_round_trip() {
+        let id = ChallengeId::from_bytes([0xab; CHALLENGE_ID_LEN]);
+        let text = id.to_string();
+
+        assert_eq!(text.len(), 2 * CHALLENGE_ID_LEN);
+        assert_eq!(text.parse::<ChallengeId>().unwrap(), id);
+        assert!("xyz".parse::<ChallengeId>().is_err());
+    }
+
+    #[test]
+    fn test_bound_challenge() {
+        let manager = manager();
+        let binding = ChallengeBinding::new()
+            .with_user_id("alice")
+            .with_client_ip("192.0.2.7".parse().unwrap())
+            .with_purpose("login");
+        let client = Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+
+        let challenge = block_on(manager.issue_bound(binding.clone())).unwrap();
+        assert_eq!(challenge.binding(), &binding);
+        let otp = client.compute(&challenge.message());
+        assert_eq!(
+            block_on(manager.verify_bound(
+                challenge.id(),
+                &binding.clone().with_purpose("transfer"),
+                &otp
+            )),
+            Err(Error::OtpMismatch)
+        );
+
+        let challenge = block_on(manager.issue_bound(binding.clone())).unwrap();
+        let otp = client.compute(&binding.message(challenge.bytes()));
+        assert_eq!(
+            block_on(manager.verify(challenge.id(), &otp)),
+            Err(Error::OtpMismatch)
+        );
+
+        let challenge = block_on(manager.issue_bound(binding.clone())).unwrap();
+        let otp = client.compute(&challenge.message());
+        assert_eq!(
+            block_on(manager.verify_bound(challenge.id(), &binding, &otp)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_replay_guard_rejects_reaccepted_challenge() {
+        /// Store whose consumption is not atomic: it never deletes
+        struct Leaky(Challenge);
+
+        impl ChallengeStore for Leaky {
+            async fn put(&self, _: Challenge) -> Result<(), Error> {
+                Ok(())
+            }
+
+            async fn get_and_delete(&self, _: &ChallengeId) -> Result<Option<Challenge>, Error> {
+                Ok(Some(self.0.clone()))
+            }
+
+            async fn expire(&self, _: SystemTime) -> Result<usize, Error> {
+                Ok(0)
+            }
+        }
+
+        let challenge = block_on(manager().issue()).unwrap();
+        let otp = respond(&challenge);
+        let manager = ChallengeManager::with_store(
+            Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]),
+            Leaky(challenge.clone()),
+        )
+        .with_replay_guard(ReplayGuard::new(DEFAULT_TTL));
+
+        assert_eq!(block_on(manager.verify(challenge.id(), &otp)), Ok(()));
+        assert_eq!(
+            block_on(manager.verify(challenge.id(), &otp.to_uppercase())),
+            Err(Error::ReplayDetected)
+        );
+    }
+
+    #[test]
+    fn test_rate_limiter_throttles_per_user() {
+        let manager = manager().with_rate_limiter(RateLimiter::new(1, Duration::from_secs(60)));
+        let alice = ChallengeBinding::new().with_user_id("alice");
+        let bob = ChallengeBinding::new().with_user_id("bob");
+
+        let first = block_on(manager.issue_bound(alice.clone())).unwrap();
+        let second = block_on(manager.issue_bound(alice.clone())).unwrap();
+        assert_eq!(
+            block_on(manager.verify_bound(first.id(), &alice, "000000000000")),
+            Err(Error::OtpMismatch)
+        );
+        assert!(matches!(
+            block_on(manager.verify_bound(second.id(), &alice, &respond(&second))),
+            Err(Error::RateLimited { .. })
+        ));
+
+        let challenge = block_on(manager.issue_bound(bob.clone())).unwrap();
+        let otp =
+            Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]).compute(&challenge.message());
+        assert_eq!(
+            block_on(manager.verify_bound(challenge.id(), &bob, &otp)),
+            Ok(())
+        );
+        // The throttled attempt did not consume alice's challenge
+        assert_eq!(manager.store().len(), 1);
+    }
+
+    #[test]
+    fn test_lockout_after_failures() {
+        use crate::throttle::LockoutPolicy;
+
+        let manager = manager().with_lockout(Lockout::new(LockoutPolicy {
+            max_failures: 2,
+            ..LockoutPolicy::default()
+        }));
+
+        for _ in 0..2 {
+            let challenge = block_on(manager.issue()).unwrap();
+            assert_eq!(
+                block_on(manager.verify(challenge.id(), "000000000000")),
+                Err(Error::OtpMismatch)
+            );
+        }
+
+        let challenge = block_on(manager.issue()).unwrap();
+        assert!(matches!(
+            block_on(manager.verify(challenge.id(), &respond(&challenge))),
+            Err(Error::LockedOut { .. })
+        ));
+
+        // Unbound attempts are counted under the manager's key
+        let key_id = Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]).key_id();
+        manager.lockout().unwrap().reset(&key_id.to_string());
+        assert_eq!(
+            block_on(manager.verify(challenge.id(), &respond(&challenge))),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_lockout_ignores_foreign_bindings() {
+        use crate::throttle::{LockoutPolicy, LockoutStatus};
+
+        let manager = manager().with_lockout(Lockout::new(LockoutPolicy {
+            max_failures: 2,
+            ..LockoutPolicy::default()
+        }));
+        let alice = ChallengeBinding::new().with_user_id("alice");
+        let mallory = ChallengeBinding::new().with_user_id("mallory");
+
+        for _ in 0..3 {
+            let challenge = block_on(manager.issue_bound(mallory.clone())).unwrap();
+            assert_eq!(
+                block_on(manager.verify_bound(challenge.id(), &alice, "000000000000")),
+                Err(Error::OtpMismatch)
+            );
+        }
+        let lockout = manager.lockout().unwrap();
+        assert_eq!(lockout.status("alice"), LockoutStatus::default());
+        assert_eq!(lockout.status("mallory"), LockoutStatus::default());
+
+        let challenge = block_on(manager.issue_bound(alice.clone())).unwrap();
+        let otp =
+            Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]).compute(&challenge.message());
+        assert_eq!(
+            block_on(manager.verify_bound(challenge.id(), &alice, &otp)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_full_lockout_admits_new_users() {
+        use crate::throttle::LockoutPolicy;
+
+        let lockout = Lockout::new(LockoutPolicy::default()).with_max_identities(2);
+        let manager = manager().with_lockout(lockout);
+
+        for user in ["mallory", "trudy"] {
+            let binding = ChallengeBinding::new().with_user_id(user);
+            let challenge = block_on(manager.issue_bound(binding.clone())).unwrap();
+            assert_eq!(
+                block_on(manager.verify_bound(challenge.id(), &binding, "000000000000")),
+                Err(Error::OtpMismatch)
+            );
+        }
+
+        let alice = ChallengeBinding::new().with_user_id("alice");
+        let challenge = block_on(manager.issue_bound(alice.clone())).unwrap();
+        let otp =
+            Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]).compute(&challenge.message());
+        assert_eq!(
+            block_on(manager.verify_bound(challenge.id(), &alice, &otp)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_clock_reaches_later_throttles() {
+        use crate::clock::ManualClock;
+        use crate::throttle::LockoutPolicy;
+
+        let clock = Arc::new(ManualClock::new(UNIX_EPOCH));
+        let manager = manager()
+            .with_clock(Arc::clone(&clock))
+            .with_lockout(Lockout::new(LockoutPolicy {
+                max_failures: 1,
+                ..LockoutPolicy::default()
+            }));
+
+        let challenge = block_on(manager.issue()).unwrap();
+        assert_eq!(
+            block_on(manager.verify(challenge.id(), "000000000000")),
+            Err(Error::OtpMismatch)
+        );
+        let key_id = Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]).key_id();
+        let lockout = manager.lockout().unwrap();
+        assert!(lockout.check(&key_id.to_string()).is_err());
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(lockout.check(&key_id.to_string()), Ok(()));
+    }
+
+    #[test]
+    fn test_anomaly_detector_counts_sources() {
+        use crate::throttle::{AnomalyPolicy, AnomalySubject};
+        use std::net::IpAddr;
+        use std::sync::Mutex;
+
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&reported);
+        let detector = AnomalyDetector::new(AnomalyPolicy {
+            source_threshold: 2,
+            ..AnomalyPolicy::default()
+        })
+        .on_anomaly(move |event| sink.lock().unwrap().push(event.subject.clone()));
+        let manager = manager().with_anomaly_detector(detector);
+        let source: IpAddr = "203.0.113.7".parse().unwrap();
+
+        for user in ["alice", "bob"] {
+            let binding = ChallengeBinding::new()
+                .with_user_id(user)
+                .with_client_ip(source);
+            let challenge = block_on(manager.issue_bound(binding.clone())).unwrap();
+            assert_eq!(
+                block_on(manager.verify_bound(challenge.id(), &binding, "000000000000")),
+                Err(Error::OtpMismatch)
+            );
+        }
+
+        assert_eq!(*reported.lock().unwrap(), [AnomalySubject::Source(source)]);
+        let alice = AnomalySubject::Identity("alice".to_string());
+        assert_eq!(manager.anomaly_detector().unwrap().failures(&alice), 1);
+    }
+
+    #[test]
+    fn test_anomaly_detector_ignores_foreign_bindings() {
+        use crate::throttle::{AnomalyPolicy, AnomalySubject};
+
+        let detector = AnomalyDetector::new(AnomalyPolicy::default());
+        let manager = manager().with_anomaly_detector(detector);
+        let alice = ChallengeBinding::new().with_user_id("alice");
+        let mallory = ChallengeBinding::new().with_user_id("mallory");
+
+        let challenge = block_on(manager.issue_bound(mallory)).unwrap();
+        assert_eq!(
+            block_on(manager.verify_bound(challenge.id(), &alice, "000000000000")),
+            Err(Error::OtpMismatch)
+        );
+        let detector = manager.anomaly_detector().unwrap();
+        for user in ["alice", "mallory"] {
+            let subject = AnomalySubject::Identity(user.to_string());
+            assert_eq!(detector.failures(&subject), 0);
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_offload_threshold() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let manager = manager()
+            .with_challenge_len(64 * 1024)
+            .with_offload_threshold(1024);
+
+        runtime.block_on(async {
+            let challenge = manager.issue().await.unwrap();
+            assert_eq!(
+                manager.verify(challenge.id(), "0").await,
+                Err(Error::OtpMismatch)
+            );
+
+            let challenge = manager.issue().await.unwrap();
+            let otp = respond(&challenge);
+            assert_eq!(manager.verify(challenge.id(), &otp).await, Ok(()));
+        });
+    }
+
+    #[test]
+    fn test_issue_batch() {
+        let manager = manager();
+        let binding = ChallengeBinding::new().with_purpose("kiosk");
+        let policy = BatchPolicy::new()
+            .with_ttl(Duration::from_secs(3600))
+            .with_binding(binding.clone());
+
+        let bundle = block_on(manager.issue_batch(3, policy)).unwrap();
+        assert_eq!(bundle.len(), 3);
+        assert_eq!(manager.store().len(), 3);
+
+        let restored = ChallengeBundle::from_bytes(&bundle.to_bytes()).unwrap();
+        assert_eq!(restored, bundle);
+        let client = Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+        for challenge in &restored {
+            assert!(challenge.expires_at() > SystemTime::now() + DEFAULT_TTL);
+            let otp = client.compute(&challenge.message());
+            assert_eq!(
+                block_on(manager.verify_bound(challenge.id(), &binding, &otp)),
+                Ok(())
+            );
+        }
+        assert!(manager.store().is_empty());
+
+        assert!(block_on(manager.issue_batch(0, BatchPolicy::default()))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_puzzle_checked_before_otp() {
+        use crate::throttle::{LockoutPolicy, LockoutStatus};
+
+        let manager = manager()
+            .with_puzzle(10)
+            .with_lockout(Lockout::new(LockoutPolicy {
+                max_failures: 1,
+                ..LockoutPolicy::default()
+            }));
+        let binding = ChallengeBinding::new();
+
+        let challenge = block_on(manager.issue()).unwrap();
+        assert_eq!(challenge.difficulty(), 10);
+        assert_eq!(
+            block_on(manager.verify(challenge.id(), "0")),
+            Err(Error::PuzzleUnsolved)
+        );
+        // The challenge is consumed, but the wrong OTP was never counted
+        let key_id = Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]).key_id();
+        let status = manager.lockout().unwrap().status(&key_id.to_string());
+        assert_eq!(status, LockoutStatus::default());
+        assert_eq!(
+            block_on(manager.verify_solved(
+                challenge.id(),
+                &binding,
+                challenge.solve_puzzle(),
+                "0"
+            )),
+            Err(Error::ChallengeNotFound)
+        );
+
+        let challenge = block_on(manager.issue()).unwrap();
+        assert_eq!(
+            block_on(manager.verify_solved(
+                challenge.id(),
+                &binding,
+                challenge.solve_puzzle(),
+                &respond(&challenge)
+            )),
+            Ok(())
+        );
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_seeded_rng_is_reproducible() {
+        use crate::rng::SeededRng;
+
+        let issue = |seed| {
+            let manager = manager().with_rng(SeededRng::new(seed));
+            block_on(manager.issue()).unwrap()
+        };
+        let (first, second) = (issue(7), issue(7));
+        assert_eq!(first.id(), second.id());
+        assert_eq!(first.bytes(), second.bytes());
+        assert_ne!(issue(8).bytes(), first.bytes());
+    }
+
+    #[test]
+    fn test_policy_on_issue() {
+        let policy = ChallengePolicy::new()
+            .with_max_age(Duration::from_secs(60))
+            .with_required(BindingField::UserId);
+        let alice = ChallengeBinding::new().with_user_id("alice");
+
+        let short = manager().with_challenge_len(8).with_policy(policy.clone());
+        assert_eq!(
+            block_on(short.issue_bound(alice.clone())),
+            Err(Error::ChallengeTooShort {
+                min: MIN_CHALLENGE_LEN,
+                actual: 8
+            })
+        );
+
+        let manager = manager()
+            .with_ttl(Duration::from_secs(30))
+            .with_policy(policy);
+        assert_eq!(
+            block_on(manager.issue()),
+            Err(Error::BindingFieldMissing(BindingField::UserId))
+        );
+        assert_eq!(
+            block_on(
+                manager.issue_batch(
+                    1,
+                    BatchPolicy::new()
+                        .with_binding(alice.clone())
+                        .with_ttl(Duration::from_secs(3600))
+                )
+            )
+            .map(|bundle| bundle.len()),
+            Err(Error::ChallengeLifetimeTooLong {
+                max: Duration::from_secs(60),
+                actual: Duration::from_secs(3600)
+            })
+        );
+        assert!(block_on(manager.issue_bound(alice)).is_ok());
+    }
+
+    #[test]
+    fn test_policy_on_verify() {
+        use crate::clock::ManualClock;
+
+        let clock = Arc::new(ManualClock::new(UNIX_EPOCH));
+        let lax = manager()
+            .with_ttl(Duration::from_secs(3600))
+            .with_clock(Arc::clone(&clock));
+        let strict = manager()
+            .with_policy(
+                ChallengePolicy::new()
+                    .with_max_age(Duration::from_secs(60))
+                    .with_required(BindingField::Purpose),
+            )
+            .with_clock(clock);
+        let login = ChallengeBinding::new().with_purpose("login");
+
+        // Issued under a laxer policy, e.g. by another instance sharing the
+        // store
+        let challenge = block_on(lax.issue_bound(login.clone())).unwrap();
+        block_on(strict.store().put(challenge.clone())).unwrap();
+        let otp =
+            Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]).compute(&challenge.message());
+
+        // A request without the required field leaves the challenge usable
+        assert_eq!(
+            block_on(strict.verify(challenge.id(), &otp)),
+            Err(Error::BindingFieldMissing(BindingField::Purpose))
+        );
+        assert_eq!(
+            block_on(strict.verify_bound(challenge.id(), &login, &otp)),
+            Err(Error::ChallengeLifetimeTooLong {
+                max: Duration::from_secs(60),
+                actual: Duration::from_secs(3600)
+            })
+        );
+    }
+
+    #[test]
+    fn test_failing_rng_source() {
+        use crate::rng::{CryptoRng, RngCore};
+        use std::num::NonZeroU32;
+
+        /// Stands in for a hardware source that has gone offline
+        struct Offline;
+
+        impl RngCore for Offline {
+            fn next_u32(&mut self) -> u32 {
+                rand_core::impls::next_u32_via_fill(self)
+            }
+
+            fn next_u64(&mut self) -> u64 {
+                rand_core::impls::next_u64_via_fill(self)
+            }
+
+            fn fill_bytes(&mut self, dest: &mut [u8]) {
+                self.try_fill_bytes(dest).unwrap();
+            }
+
+            fn try_fill_bytes(&mut self, _: &mut [u8]) -> Result<(), rand_core::Error> {
+                let code = NonZeroU32::new(rand_core::Error::CUSTOM_START).unwrap();
+                Err(rand_core::Error::from(code))
+            }
+        }
+
+        impl CryptoRng for Offline {}
+
+        let manager = manager().with_rng(Offline);
+        assert!(matches!(
+            block_on(manager.issue()),
+            Err(Error::RandomSource(_))
+        ));
+    }
+
+    #[test]
+    fn test_keyring_manager() {
+        let keyring = KeyRing::new();
+        keyring.insert(
+            "alice",
+            Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]),
+        );
+        keyring.insert("bob", Passcode::new(Algorithm::Sha3Kmac256, vec![2u8; 32]));
+        let manager = ChallengeManager::with_keyring(keyring, MemoryStore::new());
+        let alice = ChallengeBinding::new().with_user_id("alice");
+
+        let challenge = block_on(manager.issue_bound(alice.clone())).unwrap();
+        let otp =
+            Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]).compute(&challenge.message());
+        assert_eq!(
+            block_on(manager.verify_bound(challenge.id(), &alice, &otp)),
+            Ok(())
+        );
+
+        // Unbound challenges have no user to look up
+        let challenge = block_on(manager.issue()).unwrap();
+        assert_eq!(
+            block_on(manager.verify(challenge.id(), &respond(&challenge))),
+            Err(Error::OtpMismatch)
+        );
+        assert!(manager.keyring().unwrap().contains("bob"));
+    }
+
+    #[test]
+    fn test_revoked_keys_are_rejected() {
+        let passcode = || Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+        let keyring = KeyRing::new();
+        keyring
+            .insert_with_record(
+                "alice",
+                passcode(),
+                KeyRecord::from_passcode(&passcode(), SystemTime::now()),
+            )
+            .unwrap();
+        let manager = ChallengeManager::with_keyring(keyring, MemoryStore::new());
+        let alice = ChallengeBinding::new().with_user_id("alice");
+
+        let challenge = block_on(manager.issue_bound(alice.clone())).unwrap();
+        manager
+            .keyring()
+            .unwrap()
+            .update_record("alice", KeyRecord::revoke)
+            .unwrap();
+        let otp = passcode().compute(&challenge.message());
+        assert_eq!(
+            block_on(manager.verify_bound(challenge.id(), &alice, &otp)),
+            Err(Error::OtpMismatch)
+        );
+    }
+
+    #[test]
+    fn test_device_keys() {
+        let account = || Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+        let keyring = KeyRing::new();
+        keyring.insert("alice", account());
+        keyring.register_device("alice", "laptop");
+        keyring.register_device("alice", "phone");
+        let manager = ChallengeManager::with_keyring(keyring, MemoryStore::new());
+        let alice = ChallengeBinding::new().with_user_id("alice");
+
+        let challenge = block_on(manager.issue_bound(alice.clone())).unwrap();
+        let otp = account().for_device("phone").compute(&challenge.message());
+        assert_eq!(
+            block_on(manager.verify_device(challenge.id(), &alice, &otp)),
+            Ok(Some("phone".to_string()))
+        );
+
+        // The account key itself is no longer accepted
+        let challenge = block_on(manager.issue_bound(alice.clone())).unwrap();
+        let otp = account().compute(&challenge.message());
+        assert_eq!(
+            block_on(manager.verify_device(challenge.id(), &alice, &otp)),
+            Err(Error::OtpMismatch)
+        );
+
+        // Naming a device restricts verification to its key
+        let laptop = alice.clone().with_device_id("laptop");
+        let challenge = block_on(manager.issue_bound(laptop.clone())).unwrap();
+        let otp = account().for_device("phone").compute(&challenge.message());
+        assert_eq!(
+            block_on(manager.verify_device(challenge.id(), &laptop, &otp)),
+            Err(Error::OtpMismatch)
+        );
+
+        manager.keyring().unwrap().revoke_device("alice", "phone");
+        let challenge = block_on(manager.issue_bound(alice.clone())).unwrap();
+        let otp = account().for_device("phone").compute(&challenge.message());
+        assert_eq!(
+            block_on(manager.verify_device(challenge.id(), &alice, &otp)),
+            Err(Error::OtpMismatch)
+        );
+    }
+}
@@ -0,0 +1,128 @@
+//! Minimum requirements on issued and verified challenges
+
+use std::time::{Duration, SystemTime};
+
+use super::{BindingField, Challenge, ChallengeBinding};
+use crate::Error;
+
+/// Default minimum number of random challenge bytes required by a
+/// [`ChallengePolicy`]
+pub const MIN_CHALLENGE_LEN: usize = 16;
+
+/// Requirements a [`ChallengeManager`](super::ChallengeManager) enforces on
+/// the challenges it issues and verifies
+///
+/// Issuing fails when the manager is configured to hand out challenges that
+/// are too short, live too long or lack a required binding field. The same
+/// rules are checked again on verification, so challenges issued before the
+/// policy was tightened, or by another instance sharing the store, are
+/// rejected as well.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChallengePolicy {
+    min_len: usize,
+    max_age: Option<Duration>,
+    required: Vec<BindingField>,
+}
+
+impl Default for ChallengePolicy {
+    fn default() -> Self {
+        Self {
+            min_len: MIN_CHALLENGE_LEN,
+            max_age: None,
+            required: Vec::new(),
+        }
+    }
+}
+
+impl ChallengePolicy {
+    /// Creates a policy requiring [`MIN_CHALLENGE_LEN`] bytes, with no
+    /// lifetime limit and no required binding fields
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the minimum number of random challenge bytes
+    pub fn with_min_len(mut self, min_len: usize) -> Self {
+        self.min_len = min_len;
+        self
+    }
+
+    /// Sets the longest time a challenge may remain valid
+    ///
+    /// Issuing fails when the TTL exceeds it, and verification rejects
+    /// challenges that would still be valid for longer than `max_age`.
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Requires challenges and requests to carry the binding field
+    pub fn with_required(mut self, field: BindingField) -> Self {
+        if !self.required.contains(&field) {
+            self.required.push(field);
+        }
+        self
+    }
+
+    /// Gets the minimum number of random challenge bytes
+    pub fn min_len(&self) -> usize {
+        self.min_len
+    }
+
+    /// Gets the longest time a challenge may remain valid, if limited
+    pub fn max_age(&self) -> Option<Duration> {
+        self.max_age
+    }
+
+    /// Gets the binding fields challenges and requests must carry
+    pub fn required(&self) -> &[BindingField] {
+        &self.required
+    }
+
+    /// Checks the parameters of a challenge about to be issued
+    pub(crate) fn check_issue(
+        &self,
+        len: usize,
+        ttl: Duration,
+        binding: &ChallengeBinding,
+    ) -> Result<(), Error> {
+        self.check_len(len)?;
+        self.check_age(ttl)?;
+        self.check_binding(binding)
+    }
+
+    /// Checks a stored challenge at verification time `now`
+    pub(crate) fn check_stored(&self, challenge: &Challenge, now: SystemTime) -> Result<(), Error> {
+        self.check_len(challenge.bytes.len())?;
+        let remaining = challenge.expires_at.duration_since(now).unwrap_or_default();
+        self.check_age(remaining)
+    }
+
+    /// Checks that the binding carries every required field
+    pub(crate) fn check_binding(&self, binding: &ChallengeBinding) -> Result<(), Error> {
+        match self.required.iter().find(|&&field| !binding.has(field)) {
+            Some(&field) => Err(Error::BindingFieldMissing(field)),
+            None => Ok(()),
+        }
+    }
+
+    fn check_len(&self, len: usize) -> Result<(), Error> {
+        if len < self.min_len {
+            return Err(Error::ChallengeTooShort {
+                min: self.min_len,
+                actual: len,
+            });
+        }
+        Ok(())
+    }
+
+    fn check_age(&self, lifetime: Duration) -> Result<(), Error> {
+        match self.max_age {
+            Some(max) if lifetime > max => Err(Error::ChallengeLifetimeTooLong {
+                max,
+                actual: lifetime,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,54 @@
+//! Proof-of-work puzzles attached to challenges
+
+/// Context string for hashing puzzle solutions
+const PUZZLE_CONTEXT: &str = "passcode/v1/puzzle";
+
+/// Largest supported puzzle difficulty in bits
+///
+/// Each bit doubles the expected work; at 32 bits a client needs about four
+/// billion hashes.
+pub const MAX_PUZZLE_DIFFICULTY: u8 = 32;
+
+/// Finds a solution to the puzzle for the challenge bytes
+///
+/// A solution is a nonce whose hash with the challenge starts with
+/// `difficulty` zero bits, so finding one takes about `2^difficulty` hashes
+/// while checking it takes one. Difficulties above
+/// [`MAX_PUZZLE_DIFFICULTY`] are treated as the maximum.
+///
+/// # Example
+/// ```
+/// use passcode::challenge::{check_puzzle, solve_puzzle};
+///
+/// let solution = solve_puzzle(b"challenge", 8);
+/// assert!(check_puzzle(b"challenge", 8, solution));
+/// ```
+pub fn solve_puzzle(challenge: &[u8], difficulty: u8) -> u64 {
+    (0..=u64::MAX)
+        .find(|&solution| check_puzzle(challenge, difficulty, solution))
+        .expect("a solution exists for every supported difficulty")
+}
+
+/// Checks a puzzle solution for the challenge bytes
+pub fn check_puzzle(challenge: &[u8], difficulty: u8, solution: u64) -> bool {
+    let hash = blake3::Hasher::new_derive_key(PUZZLE_CONTEXT)
+        .update(challenge)
+        .update(&solution.to_be_bytes())
+        .finalize();
+    let prefix = u64::from_be_bytes(hash.as_bytes()[..8].try_into().expect("hash is 32 bytes"));
+    prefix.leading_zeros() >= u32::from(difficulty.min(MAX_PUZZLE_DIFFICULTY))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_solve_and_check() {
+        assert!(check_puzzle(b"challenge", 0, 0));
+
+        let solution = solve_puzzle(b"challenge", 12);
+        assert!(check_puzzle(b"challenge", 12, solution));
+        assert!(!check_puzzle(b"other", 12, solution));
+    }
+}
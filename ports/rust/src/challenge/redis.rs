@@ -0,0 +1,126 @@
+//! Redis-backed challenge store (feature `redis-store`)
+
+use std::time::SystemTime;
+
+use redis::aio::ConnectionManager;
+use redis::Script;
+
+use super::store::{decode_record, encode_record};
+use super::{Challenge, ChallengeId, ChallengeStore};
+use crate::Error;
+
+/// Default prefix of the Redis keys holding challenges
+pub const DEFAULT_KEY_PREFIX: &str = "passcode:challenge:";
+
+/// Atomically reads and deletes a key; works on Redis versions without
+/// `GETDEL`
+const GET_AND_DELETE: &str = r"
+local value = redis.call('GET', KEYS[1])
+if value then
+    redis.call('DEL', KEYS[1])
+end
+return value
+";
+
+/// Challenge store shared by all server instances through Redis
+///
+/// Each challenge is one key with a millisecond expiry, so Redis drops
+/// unanswered challenges itself and [`expire`](ChallengeStore::expire) is a
+/// no-op. Challenges are consumed with a Lua script that reads and deletes
+/// the key in one step, so a challenge can never be consumed by two
+/// replicas.
+#[derive(Clone)]
+pub struct RedisStore {
+    connection: ConnectionManager,
+    prefix: String,
+    get_and_delete: Script,
+}
+
+impl RedisStore {
+    /// Creates a store over an existing connection manager
+    pub fn new(connection: ConnectionManager) -> Self {
+        Self {
+            connection,
+            prefix: DEFAULT_KEY_PREFIX.to_string(),
+            get_and_delete: Script::new(GET_AND_DELETE),
+        }
+    }
+
+    /// Connects to the Redis server at `url` (e.g. `redis://127.0.0.1/`)
+    pub async fn connect(url: &str) -> Result<Self, Error> {
+        let client = redis::Client::open(url).map_err(store_error)?;
+        let connection = ConnectionManager::new(client).await.map_err(store_error)?;
+        Ok(Self::new(connection))
+    }
+
+    /// Sets the prefix of the keys holding challenges
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    fn key(&self, id: &ChallengeId) -> String {
+        format!("{}{}", self.prefix, id)
+    }
+}
+
+impl ChallengeStore for RedisStore {
+    async fn put(&self, challenge: Challenge) -> Result<(), Error> {
+        redis::cmd("SET")
+            .arg(self.key(&challenge.id))
+            .arg(encode_record(&challenge))
+            .arg("PX")
+            .arg(ttl_ms(&challenge))
+            .query_async::<()>(&mut self.connection.clone())
+            .await
+            .map_err(store_error)
+    }
+
+    /// Sends one `SET` per challenge in a single pipeline
+    async fn put_many(&self, challenges: Vec<Challenge>) -> Result<(), Error> {
+        if challenges.is_empty() {
+            return Ok(());
+        }
+        let mut pipe = redis::pipe();
+        for challenge in &challenges {
+            pipe.cmd("SET")
+                .arg(self.key(&challenge.id))
+                .arg(encode_record(challenge))
+                .arg("PX")
+                .arg(ttl_ms(challenge))
+                .ignore();
+        }
+        pipe.query_async::<()>(&mut self.connection.clone())
+            .await
+            .map_err(store_error)
+    }
+
+    async fn get_and_delete(&self, id: &ChallengeId) -> Result<Option<Challenge>, Error> {
+        let record: Option<Vec<u8>> = self
+            .get_and_delete
+            .key(self.key(id))
+            .invoke_async(&mut self.connection.clone())
+            .await
+            .map_err(store_error)?;
+
+        record.map(|record| decode_record(*id, &record)).transpose()
+    }
+
+    async fn expire(&self, _now: SystemTime) -> Result<usize, Error> {
+        Ok(0)
+    }
+}
+
+/// Returns the key expiry for a challenge in milliseconds
+fn ttl_ms(challenge: &Challenge) -> u64 {
+    // Already expired challenges are kept for a millisecond so that
+    // verification still reports them as expired rather than unknown
+    challenge
+        .expires_at
+        .duration_since(SystemTime::now())
+        .map_or(1, |d| d.as_millis().max(1) as u64)
+}
+
+fn store_error(e: redis::RedisError) -> Error {
+    Error::ChallengeStore(e.to_string())
+}
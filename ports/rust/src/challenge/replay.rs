@@ -0,0 +1,203 @@
+//! Time-bucketed cache of recently accepted responses
+
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::clock::{self, Clock};
+use crate::Error;
+
+/// Context string for hashing remembered entries
+const REPLAY_CONTEXT: &str = "passcode/v1/replay";
+
+/// Number of time buckets the window is split into
+const BUCKETS: u32 = 8;
+
+/// Default maximum number of remembered entries
+pub const DEFAULT_REPLAY_CAPACITY: usize = 10_000;
+
+/// Rejects a response presented a second time within a window
+///
+/// Entries are 32-byte BLAKE3 hashes kept in time buckets that together span
+/// the window; a whole bucket is dropped at once when it falls out of the
+/// window, so an entry is remembered for at least the window and at most
+/// one bucket (an eighth of the window) longer.
+///
+/// When the guard holds `capacity` entries that are all still inside the
+/// window, new entries are rejected rather than forgetting old ones that
+/// could then be replayed.
+///
+/// # Example
+/// ```
+/// use passcode::challenge::ReplayGuard;
+/// use passcode::Error;
+/// use std::time::Duration;
+///
+/// let guard = ReplayGuard::new(Duration::from_secs(120));
+/// assert_eq!(guard.check(b"challenge", "123456"), Ok(()));
+/// assert_eq!(guard.check(b"challenge", "123456"), Err(Error::ReplayDetected));
+/// ```
+#[derive(Debug)]
+pub struct ReplayGuard {
+    bucket_len: Duration,
+    capacity: usize,
+    buckets: Mutex<VecDeque<Bucket>>,
+    clock: Arc<dyn Clock>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    index: u64,
+    entries: HashSet<[u8; 32]>,
+}
+
+impl ReplayGuard {
+    /// Creates a guard remembering entries for `window`
+    pub fn new(window: Duration) -> Self {
+        Self {
+            bucket_len: (window / BUCKETS).max(Duration::from_millis(1)),
+            capacity: DEFAULT_REPLAY_CAPACITY,
+            buckets: Mutex::new(VecDeque::new()),
+            clock: clock::system(),
+        }
+    }
+
+    /// Sets how many entries the guard holds at most
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Reads the time from `clock` instead of the system clock
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    pub(crate) fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Records an accepted (challenge, OTP) pair, failing with
+    /// [`Error::ReplayDetected`] if it was already recorded
+    pub fn check(&self, challenge: &[u8], otp: &str) -> Result<(), Error> {
+        let mut hasher = blake3::Hasher::new_derive_key(REPLAY_CONTEXT);
+        hasher.update(&(challenge.len() as u64).to_be_bytes());
+        hasher.update(challenge);
+        hasher.update(otp.as_bytes());
+        self.insert(*hasher.finalize().as_bytes(), self.clock.now())
+    }
+
+    /// Records an arbitrary key such as a nonce, failing with
+    /// [`Error::ReplayDetected`] if it was already recorded
+    pub fn check_key(&self, key: &[u8]) -> Result<(), Error> {
+        let hash = blake3::derive_key(REPLAY_CONTEXT, key);
+        self.insert(hash, self.clock.now())
+    }
+
+    /// Returns the number of remembered entries, including any in the
+    /// oldest bucket not yet dropped
+    pub fn len(&self) -> usize {
+        self.lock().iter().map(|bucket| bucket.entries.len()).sum()
+    }
+
+    /// Returns true when no entries are remembered
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn insert(&self, hash: [u8; 32], now: SystemTime) -> Result<(), Error> {
+        let current = self.bucket_index(now);
+        let mut buckets = self.lock();
+
+        // Buckets older than the window are dropped whole
+        while buckets
+            .front()
+            .is_some_and(|bucket| bucket.index + u64::from(BUCKETS) < current)
+        {
+            buckets.pop_front();
+        }
+
+        if buckets.iter().any(|bucket| bucket.entries.contains(&hash)) {
+            return Err(Error::ReplayDetected);
+        }
+        let len: usize = buckets.iter().map(|bucket| bucket.entries.len()).sum();
+        if len >= self.capacity {
+            return Err(Error::ChallengeStore("replay cache is full".to_string()));
+        }
+
+        if buckets.back().is_none_or(|bucket| bucket.index != current) {
+            buckets.push_back(Bucket {
+                index: current,
+                entries: HashSet::new(),
+            });
+        }
+        buckets
+            .back_mut()
+            .expect("bucket pushed above")
+            .entries
+            .insert(hash);
+        Ok(())
+    }
+
+    fn bucket_index(&self, now: SystemTime) -> u64 {
+        let elapsed = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+        (elapsed.as_millis() / self.bucket_len.as_millis()) as u64
+    }
+
+    fn lock(&self) -> MutexGuard<'_, VecDeque<Bucket>> {
+        // A panic while holding the lock cannot leave the buckets inconsistent
+        self.buckets
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rejects_duplicates() {
+        let guard = ReplayGuard::new(Duration::from_secs(60));
+
+        assert_eq!(guard.check(b"challenge", "123456"), Ok(()));
+        assert_eq!(guard.check(b"challenge", "654321"), Ok(()));
+        assert_eq!(guard.check(b"other", "123456"), Ok(()));
+        assert_eq!(
+            guard.check(b"challenge", "123456"),
+            Err(Error::ReplayDetected)
+        );
+        assert_eq!(guard.check_key(b"nonce"), Ok(()));
+        assert_eq!(guard.check_key(b"nonce"), Err(Error::ReplayDetected));
+        assert_eq!(guard.len(), 4);
+    }
+
+    #[test]
+    fn test_forgets_after_window() {
+        let guard = ReplayGuard::new(Duration::from_secs(8));
+        let start = UNIX_EPOCH + Duration::from_secs(1_000_000);
+
+        guard.insert([1; 32], start).unwrap();
+        assert_eq!(
+            guard.insert([1; 32], start + Duration::from_secs(8)),
+            Err(Error::ReplayDetected)
+        );
+        assert_eq!(
+            guard.insert([1; 32], start + Duration::from_secs(10)),
+            Ok(())
+        );
+        assert_eq!(guard.len(), 1);
+    }
+
+    #[test]
+    fn test_full_guard_fails_closed() {
+        let guard = ReplayGuard::new(Duration::from_secs(60)).with_capacity(1);
+
+        assert_eq!(guard.check_key(b"first"), Ok(()));
+        assert!(matches!(
+            guard.check_key(b"second"),
+            Err(Error::ChallengeStore(_))
+        ));
+    }
+}
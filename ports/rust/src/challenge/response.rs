@@ -0,0 +1,180 @@
+//! Single-string response tokens
+
+use std::fmt;
+use std::str::FromStr;
+
+use base64ct::{Base64UrlUnpadded, Encoding};
+
+use super::{ChallengeId, CHALLENGE_ID_LEN};
+use crate::Error;
+
+/// Longest token [`ResponseToken::from_str`] accepts, in bytes
+pub const MAX_RESPONSE_TOKEN_LEN: usize = 2048;
+
+/// A response packed into one URL- and header-safe string
+///
+/// The token is `<challenge-id>.<otp>` or `<challenge-id>.<otp>.<metadata>`
+/// with each part unpadded base64url, so it can be submitted through a form
+/// field, a query string or an HTTP header without a custom concatenation
+/// scheme. Metadata is opaque to the library, e.g. a client version.
+///
+/// # Example
+/// ```
+/// use passcode::challenge::{ChallengeManager, ResponseToken};
+/// use passcode::{Algorithm, Passcode};
+///
+/// # pollster::block_on(async {
+/// let key = vec![0u8; 32];
+/// let server = ChallengeManager::new(Passcode::new(Algorithm::Sha3Kmac256, key.clone()));
+/// let challenge = server.issue().await.unwrap();
+///
+/// // Client
+/// let otp = Passcode::new(Algorithm::Sha3Kmac256, key).compute(&challenge.message());
+/// let token = ResponseToken::new(*challenge.id(), otp).to_string();
+///
+/// // Server
+/// let response: ResponseToken = token.parse().unwrap();
+/// assert!(server
+///     .verify(response.challenge_id(), response.otp())
+///     .await
+///     .is_ok());
+/// # });
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseToken {
+    challenge_id: ChallengeId,
+    otp: String,
+    metadata: Option<Vec<u8>>,
+}
+
+impl ResponseToken {
+    /// Creates a token answering the challenge with the given ID
+    pub fn new(challenge_id: ChallengeId, otp: impl Into<String>) -> Self {
+        Self {
+            challenge_id,
+            otp: otp.into(),
+            metadata: None,
+        }
+    }
+
+    /// Attaches opaque metadata as the third part
+    pub fn with_metadata(mut self, metadata: impl Into<Vec<u8>>) -> Self {
+        self.metadata = Some(metadata.into());
+        self
+    }
+
+    /// Gets the ID of the answered challenge
+    pub fn challenge_id(&self) -> &ChallengeId {
+        &self.challenge_id
+    }
+
+    /// Gets the OTP
+    pub fn otp(&self) -> &str {
+        &self.otp
+    }
+
+    /// Gets the metadata, if any was attached
+    pub fn metadata(&self) -> Option<&[u8]> {
+        self.metadata.as_deref()
+    }
+}
+
+impl fmt::Display for ResponseToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}.{}",
+            Base64UrlUnpadded::encode_string(self.challenge_id.as_bytes()),
+            Base64UrlUnpadded::encode_string(self.otp.as_bytes())
+        )?;
+        if let Some(metadata) = &self.metadata {
+            write!(f, ".{}", Base64UrlUnpadded::encode_string(metadata))?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ResponseToken {
+    type Err = Error;
+
+    /// Parses a token written by the [`Display`](fmt::Display)
+    /// implementation
+    ///
+    /// Fails with [`Error::MalformedMessage`] when the token is too long,
+    /// has the wrong number of parts, or a part is not base64url.
+    fn from_str(s: &str) -> Result<Self, Error> {
+        if s.len() > MAX_RESPONSE_TOKEN_LEN {
+            return Err(Error::MalformedMessage("response token is too long"));
+        }
+        let mut parts = s.split('.');
+        let (Some(id), Some(otp)) = (parts.next(), parts.next()) else {
+            return Err(Error::MalformedMessage("response token has too few parts"));
+        };
+        let metadata = parts.next();
+        if parts.next().is_some() {
+            return Err(Error::MalformedMessage("response token has too many parts"));
+        }
+
+        let id: [u8; CHALLENGE_ID_LEN] = decode(id)?
+            .try_into()
+            .map_err(|_| Error::MalformedMessage("invalid challenge ID"))?;
+        let otp = String::from_utf8(decode(otp)?)
+            .map_err(|_| Error::MalformedMessage("OTP is not UTF-8"))?;
+        Ok(Self {
+            challenge_id: ChallengeId::from_bytes(id),
+            otp,
+            metadata: metadata.map(decode).transpose()?,
+        })
+    }
+}
+
+fn decode(part: &str) -> Result<Vec<u8>, Error> {
+    Base64UrlUnpadded::decode_vec(part)
+        .map_err(|_| Error::MalformedMessage("response token is not base64url"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let id = ChallengeId::from_bytes([0xfb; CHALLENGE_ID_LEN]);
+        let token = ResponseToken::new(id, "3f2a-91");
+        let encoded = token.to_string();
+        assert_eq!(encoded, "-_v7-_v7-_v7-_v7-_v7-w.M2YyYS05MQ");
+        assert_eq!(encoded.parse(), Ok(token.clone()));
+
+        let token = token.with_metadata(b"v=2".to_vec());
+        let encoded = token.to_string();
+        assert!(encoded.ends_with(".dj0y"));
+        let parsed: ResponseToken = encoded.parse().unwrap();
+        assert_eq!(parsed.metadata(), Some(&b"v=2"[..]));
+    }
+
+    #[test]
+    fn test_rejects_malformed() {
+        let malformed = |s: &str| s.parse::<ResponseToken>().unwrap_err();
+
+        assert_eq!(
+            malformed("abc"),
+            Error::MalformedMessage("response token has too few parts")
+        );
+        assert_eq!(
+            malformed("a.b.c.d"),
+            Error::MalformedMessage("response token has too many parts")
+        );
+        assert_eq!(
+            malformed("AAAA.MTIz"),
+            Error::MalformedMessage("invalid challenge ID")
+        );
+        assert_eq!(
+            malformed("AAAAAAAAAAAAAAAAAAAAAA.M+Iz"),
+            Error::MalformedMessage("response token is not base64url")
+        );
+        assert_eq!(
+            malformed(&"A".repeat(MAX_RESPONSE_TOKEN_LEN + 1)),
+            Error::MalformedMessage("response token is too long")
+        );
+    }
+}
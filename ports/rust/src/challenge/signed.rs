@@ -0,0 +1,491 @@
+//! Stateless challenges authenticated by a server key
+
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64ct::{Base64UrlUnpadded, Encoding};
+
+use super::{
+    Challenge, ChallengeBinding, ChallengeId, ReplayGuard, CHALLENGE_ID_LEN,
+    DEFAULT_REPLAY_CAPACITY,
+};
+use crate::clock::{self, Clock};
+use crate::rng::{CryptoRngCore, SharedRng};
+use crate::wire::{ChallengeMessage, ResponseMessage};
+use crate::{Algorithm, Error, Passcode};
+
+/// Context string for deriving the challenge signing key
+const SIGNING_CONTEXT: &str = "passcode/v1/signed-challenge";
+
+/// Length of the random nonce, which doubles as the challenge id
+pub const NONCE_LEN: usize = CHALLENGE_ID_LEN;
+
+/// Length of the algorithm ID and expiry between the nonce and the MAC
+const HEADER_LEN: usize = 1 + 8;
+
+/// Length of a signed challenge: nonce, algorithm ID, expiry and MAC
+pub const SIGNED_CHALLENGE_LEN: usize = NONCE_LEN + HEADER_LEN + blake3::OUT_LEN;
+
+/// Issues and verifies challenges without storing them
+///
+/// A challenge is `nonce || algorithm || expiry || MAC(key, nonce ||
+/// algorithm || expiry || context)`, where the algorithm is the OTP
+/// algorithm's [`Algorithm::id`], the expiry is milliseconds since the Unix
+/// epoch and the MAC is keyed BLAKE3 under a key derived from the server
+/// key. Any verifier sharing the server key can check a challenge issued by
+/// another, so horizontally scaled servers need no shared challenge store.
+/// [`Challenge::to_token`] turns such a challenge into a compact string.
+///
+/// Challenges expire one freshness window after issuance. Nonces of
+/// challenges that passed the MAC check are remembered in a [`ReplayGuard`]
+/// for the window, so each challenge is still verified at most once per
+/// verifier. With several verifiers, route a client's response to the same
+/// one or pair this with a shared replay store.
+pub struct SignedChallenges {
+    passcode: Passcode,
+    key: [u8; blake3::KEY_LEN],
+    window: Duration,
+    capacity: usize,
+    replay: ReplayGuard,
+    clock: Arc<dyn Clock>,
+    rng: SharedRng,
+}
+
+impl SignedChallenges {
+    /// Creates an issuer and verifier with the given OTP key and server key
+    ///
+    /// The server key authenticates challenges and must differ from the
+    /// client's OTP key; it never leaves the servers.
+    pub fn new(passcode: Passcode, server_key: &[u8]) -> Self {
+        Self {
+            passcode,
+            key: blake3::derive_key(SIGNING_CONTEXT, server_key),
+            window: super::DEFAULT_TTL,
+            capacity: DEFAULT_REPLAY_CAPACITY,
+            replay: ReplayGuard::new(super::DEFAULT_TTL),
+            clock: clock::system(),
+            rng: SharedRng::os(),
+        }
+    }
+
+    /// Sets how long after issuance a challenge is accepted
+    pub fn with_window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self.reset_replay();
+        self
+    }
+
+    /// Sets how many nonces the replay cache holds at most
+    ///
+    /// When the cache is full of unexpired nonces, verification fails rather
+    /// than forgetting a nonce that could then be replayed.
+    pub fn with_replay_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self.reset_replay();
+        self
+    }
+
+    /// Reads the time from `clock` instead of the system clock
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self.replay.set_clock(Arc::clone(&self.clock));
+        self
+    }
+
+    /// Draws nonces from `rng` instead of the operating system's CSPRNG
+    pub fn with_rng(mut self, rng: impl CryptoRngCore + Send + 'static) -> Self {
+        self.rng = SharedRng::new(rng);
+        self
+    }
+
+    /// Issues a challenge bound to `context`
+    ///
+    /// The context (e.g. a session or request identifier) is not part of the
+    /// challenge bytes; the verifier must supply the same context.
+    pub fn issue(&self, context: &[u8]) -> Result<Challenge, Error> {
+        let mut nonce = [0u8; NONCE_LEN];
+        self.rng.fill(&mut nonce)?;
+        let expires_at =
+            UNIX_EPOCH + Duration::from_millis(to_millis(self.clock.now() + self.window));
+
+        let mut header = [0u8; HEADER_LEN];
+        header[0] = self.passcode.algorithm().id();
+        header[1..].copy_from_slice(&to_millis(expires_at).to_be_bytes());
+
+        let mut bytes = Vec::with_capacity(SIGNED_CHALLENGE_LEN);
+        bytes.extend_from_slice(&nonce);
+        bytes.extend_from_slice(&header);
+        bytes.extend_from_slice(self.sign(&nonce, &header, context).as_bytes());
+
+        Ok(Challenge {
+            id: ChallengeId::from_bytes(nonce),
+            bytes,
+            expires_at,
+            binding: ChallengeBinding::default(),
+            difficulty: 0,
+        })
+    }
+
+    /// Verifies the OTP for a signed challenge
+    ///
+    /// Fails with [`Error::InvalidChallenge`] for malformed or forged
+    /// challenges or ones issued for another OTP algorithm,
+    /// [`Error::ChallengeExpired`] outside the freshness window,
+    /// [`Error::ReplayDetected`] for a challenge already presented to this
+    /// verifier and [`Error::OtpMismatch`] for a wrong OTP.
+    pub fn verify(&self, challenge: &[u8], context: &[u8], otp: &str) -> Result<(), Error> {
+        self.check(challenge, context)?;
+        if !self.passcode.verify(challenge, otp) {
+            return Err(Error::OtpMismatch);
+        }
+        Ok(())
+    }
+
+    /// Verifies a [`ResponseMessage`] to a challenge received as a
+    /// [`ChallengeMessage`]
+    ///
+    /// Fails with [`Error::MalformedMessage`] when the response names
+    /// another challenge or algorithm, and otherwise like
+    /// [`verify`](Self::verify).
+    pub fn verify_response(
+        &self,
+        challenge: &ChallengeMessage,
+        response: &ResponseMessage,
+        context: &[u8],
+    ) -> Result<(), Error> {
+        self.verify_telemetry(challenge, response, b"", context)
+    }
+
+    /// Verifies a response from
+    /// [`ResponseMessage::compute_telemetry`], which also authenticates the
+    /// telemetry published with it
+    ///
+    /// Errors are those of [`verify_response`](Self::verify_response); a
+    /// response over other telemetry fails with [`Error::OtpMismatch`].
+    pub fn verify_telemetry(
+        &self,
+        challenge: &ChallengeMessage,
+        response: &ResponseMessage,
+        telemetry: &[u8],
+        context: &[u8],
+    ) -> Result<(), Error> {
+        if response.nonce != challenge.nonce {
+            return Err(Error::MalformedMessage(
+                "response answers another challenge",
+            ));
+        }
+        if response.algorithm != challenge.algorithm {
+            return Err(Error::MalformedMessage("response uses another algorithm"));
+        }
+        let challenge = challenge.challenge_bytes();
+        self.check(&challenge, context)?;
+        let mut message = challenge;
+        message.extend_from_slice(telemetry);
+        if !self.passcode.verify_mac(&message, &response.mac) {
+            return Err(Error::OtpMismatch);
+        }
+        Ok(())
+    }
+
+    /// Checks the MAC, algorithm and expiry of a challenge and records its
+    /// nonce, consuming it
+    fn check(&self, challenge: &[u8], context: &[u8]) -> Result<(), Error> {
+        if challenge.len() != SIGNED_CHALLENGE_LEN {
+            return Err(Error::InvalidChallenge("wrong length"));
+        }
+        let (nonce, rest) = challenge.split_at(NONCE_LEN);
+        let (header, mac) = rest.split_at(HEADER_LEN);
+
+        // blake3::Hash compares in constant time
+        let expected = self.sign(nonce, header, context);
+        let mac: [u8; blake3::OUT_LEN] = mac.try_into().expect("length checked above");
+        if expected != blake3::Hash::from(mac) {
+            return Err(Error::InvalidChallenge("signature mismatch"));
+        }
+        if header[0] != self.passcode.algorithm().id() {
+            return Err(Error::InvalidChallenge("issued for another algorithm"));
+        }
+
+        let expires_at = expiry(header);
+        let now = self.clock.now();
+        // Expiries beyond the window come from a differently configured issuer
+        if now >= expires_at || expires_at > now + self.window {
+            return Err(Error::ChallengeExpired);
+        }
+
+        self.replay.check_key(nonce)
+    }
+
+    fn reset_replay(&mut self) {
+        self.replay = ReplayGuard::new(self.window).with_capacity(self.capacity);
+        self.replay.set_clock(Arc::clone(&self.clock));
+    }
+
+    /// Verifies the OTP for a challenge token from [`Challenge::to_token`]
+    ///
+    /// Errors are those of [`verify`](Self::verify).
+    pub fn verify_token(&self, token: &str, context: &[u8], otp: &str) -> Result<(), Error> {
+        let challenge = Challenge::from_token(token)?;
+        self.verify(challenge.bytes(), context, otp)
+    }
+
+    fn sign(&self, nonce: &[u8], header: &[u8], context: &[u8]) -> blake3::Hash {
+        blake3::Hasher::new_keyed(&self.key)
+            .update(nonce)
+            .update(header)
+            .update(context)
+            .finalize()
+    }
+}
+
+impl Challenge {
+    /// Encodes a challenge issued by [`SignedChallenges`] as a base64url
+    /// token
+    ///
+    /// The token embeds the nonce, algorithm ID, expiry and MAC, so it can
+    /// travel in a URL, QR code or HTTP header and be verified without a
+    /// challenge lookup. Clients compute the OTP over the decoded
+    /// [`bytes`](Self::bytes).
+    ///
+    /// # Example
+    /// ```
+    /// use passcode::challenge::{Challenge, SignedChallenges};
+    /// use passcode::{Algorithm, Passcode};
+    ///
+    /// let passcode = || Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+    /// let server = SignedChallenges::new(passcode(), b"server key");
+    /// let token = server.issue(b"").unwrap().to_token();
+    ///
+    /// // Client
+    /// let challenge = Challenge::from_token(&token).unwrap();
+    /// let otp = passcode().compute(challenge.bytes());
+    ///
+    /// assert!(server.verify_token(&token, b"", &otp).is_ok());
+    /// ```
+    pub fn to_token(&self) -> String {
+        Base64UrlUnpadded::encode_string(&self.bytes)
+    }
+
+    /// Decodes a token written by [`to_token`](Self::to_token)
+    ///
+    /// Fails with [`Error::InvalidChallenge`] when the token is not valid
+    /// base64url, has the wrong length or names an unknown algorithm. The
+    /// MAC is only checked by [`SignedChallenges::verify`].
+    pub fn from_token(token: &str) -> Result<Self, Error> {
+        let bytes = Base64UrlUnpadded::decode_vec(token)
+            .map_err(|_| Error::InvalidChallenge("token is not base64url"))?;
+        if bytes.len() != SIGNED_CHALLENGE_LEN {
+            return Err(Error::InvalidChallenge("wrong length"));
+        }
+        let (nonce, rest) = bytes.split_at(NONCE_LEN);
+        let header = &rest[..HEADER_LEN];
+        if Algorithm::from_id(header[0]).is_none() {
+            return Err(Error::InvalidChallenge("unknown algorithm"));
+        }
+
+        Ok(Self {
+            id: ChallengeId::from_bytes(nonce.try_into().expect("length checked above")),
+            expires_at: expiry(header),
+            bytes,
+            binding: ChallengeBinding::default(),
+            difficulty: 0,
+        })
+    }
+
+    /// Gets the OTP algorithm a token's challenge was issued for
+    ///
+    /// Returns `None` for challenges not issued by [`SignedChallenges`].
+    pub fn token_algorithm(&self) -> Option<Algorithm> {
+        if self.bytes.len() != SIGNED_CHALLENGE_LEN {
+            return None;
+        }
+        Algorithm::from_id(self.bytes[NONCE_LEN])
+    }
+}
+
+fn to_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// Reads the expiry from a challenge header
+fn expiry(header: &[u8]) -> SystemTime {
+    let millis = u64::from_be_bytes(header[1..].try_into().expect("header is 9 bytes"));
+    UNIX_EPOCH + Duration::from_millis(millis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Algorithm;
+
+    fn passcode() -> Passcode {
+        Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32])
+    }
+
+    #[test]
+    fn test_verifies_across_instances_once() {
+        let issuer = SignedChallenges::new(passcode(), b"server key");
+        let verifier = SignedChallenges::new(passcode(), b"server key");
+
+        let challenge = issuer.issue(b"session-1").unwrap();
+        let otp = passcode().compute(challenge.bytes());
+
+        assert_eq!(challenge.bytes().len(), SIGNED_CHALLENGE_LEN);
+        assert_eq!(challenge.id().as_bytes(), &challenge.bytes()[..NONCE_LEN]);
+        assert_eq!(
+            verifier.verify(challenge.bytes(), b"session-1", &otp),
+            Ok(())
+        );
+        assert_eq!(
+            verifier.verify(challenge.bytes(), b"session-1", &otp),
+            Err(Error::ReplayDetected)
+        );
+    }
+
+    #[test]
+    fn test_rejects_forgery_and_wrong_context() {
+        let server = SignedChallenges::new(passcode(), b"server key");
+        let other = SignedChallenges::new(passcode(), b"other key");
+        let challenge = other.issue(b"ctx").unwrap();
+        let otp = passcode().compute(challenge.bytes());
+
+        assert_eq!(
+            server.verify(challenge.bytes(), b"ctx", &otp),
+            Err(Error::InvalidChallenge("signature mismatch"))
+        );
+
+        let challenge = server.issue(b"ctx").unwrap();
+        let otp = passcode().compute(challenge.bytes());
+        assert_eq!(
+            server.verify(challenge.bytes(), b"other ctx", &otp),
+            Err(Error::InvalidChallenge("signature mismatch"))
+        );
+
+        let mut tampered = challenge.bytes().to_vec();
+        tampered[NONCE_LEN] ^= 1;
+        assert_eq!(
+            server.verify(&tampered, b"ctx", &otp),
+            Err(Error::InvalidChallenge("signature mismatch"))
+        );
+        assert_eq!(
+            server.verify(&tampered[1..], b"ctx", &otp),
+            Err(Error::InvalidChallenge("wrong length"))
+        );
+    }
+
+    #[test]
+    fn test_freshness_window() {
+        let server = SignedChallenges::new(passcode(), b"server key").with_window(Duration::ZERO);
+        let challenge = server.issue(b"").unwrap();
+        let otp = passcode().compute(challenge.bytes());
+
+        assert_eq!(
+            server.verify(challenge.bytes(), b"", &otp),
+            Err(Error::ChallengeExpired)
+        );
+    }
+
+    #[test]
+    fn test_token_round_trip() {
+        let server = SignedChallenges::new(passcode(), b"server key");
+        let challenge = server.issue(b"ctx").unwrap();
+        let token = challenge.to_token();
+
+        assert!(token
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_'));
+        let decoded = Challenge::from_token(&token).unwrap();
+        assert_eq!(decoded, challenge);
+        assert_eq!(decoded.token_algorithm(), Some(Algorithm::Sha3Kmac256));
+
+        let otp = passcode().compute(decoded.bytes());
+        assert_eq!(server.verify_token(&token, b"ctx", &otp), Ok(()));
+
+        assert!(Challenge::from_token("not a token!").is_err());
+        assert!(Challenge::from_token(&token[..token.len() - 4]).is_err());
+    }
+
+    #[test]
+    fn test_rejects_other_algorithm() {
+        let blake3 = Passcode::new(Algorithm::Blake3KeyedMode256, vec![1u8; 32]);
+        let issuer = SignedChallenges::new(blake3, b"server key");
+        let server = SignedChallenges::new(passcode(), b"server key");
+        let challenge = issuer.issue(b"").unwrap();
+        let otp = passcode().compute(challenge.bytes());
+
+        assert_eq!(
+            server.verify(challenge.bytes(), b"", &otp),
+            Err(Error::InvalidChallenge("issued for another algorithm"))
+        );
+    }
+
+    #[test]
+    fn test_wrong_otp_consumes_challenge() {
+        let server = SignedChallenges::new(passcode(), b"server key");
+        let challenge = server.issue(b"").unwrap();
+        let otp = passcode().compute(challenge.bytes());
+
+        assert_eq!(
+            server.verify(challenge.bytes(), b"", "000000000000"),
+            Err(Error::OtpMismatch)
+        );
+        assert_eq!(
+            server.verify(challenge.bytes(), b"", &otp),
+            Err(Error::ReplayDetected)
+        );
+    }
+
+    #[test]
+    fn test_wire_messages() {
+        let server = SignedChallenges::new(passcode(), b"server key");
+        let challenge = server.issue(b"ctx").unwrap();
+        let message = ChallengeMessage::decode(
+            &ChallengeMessage::from_challenge(&challenge)
+                .unwrap()
+                .encode(),
+        )
+        .unwrap();
+        assert_eq!(message.challenge_bytes(), challenge.bytes());
+        assert_eq!(message.expires_at, challenge.expires_at());
+
+        let response = ResponseMessage::compute(&passcode(), &message);
+        let mut other = response.clone();
+        other.nonce[0] ^= 1;
+        assert_eq!(
+            server.verify_response(&message, &other, b"ctx"),
+            Err(Error::MalformedMessage(
+                "response answers another challenge"
+            ))
+        );
+        assert_eq!(server.verify_response(&message, &response, b"ctx"), Ok(()));
+        assert_eq!(
+            server.verify_response(&message, &response, b"ctx"),
+            Err(Error::ReplayDetected)
+        );
+
+        let unsigned = Challenge::from_token(&challenge.to_token()).map(|mut c| {
+            c.bytes.truncate(8);
+            c
+        });
+        assert_eq!(
+            ChallengeMessage::from_challenge(&unsigned.unwrap()),
+            Err(Error::InvalidChallenge("not a signed challenge"))
+        );
+    }
+
+    #[test]
+    fn test_full_replay_cache_fails_closed() {
+        let server = SignedChallenges::new(passcode(), b"server key").with_replay_capacity(1);
+        let first = server.issue(b"").unwrap();
+        let second = server.issue(b"").unwrap();
+
+        let otp = passcode().compute(first.bytes());
+        assert_eq!(server.verify(first.bytes(), b"", &otp), Ok(()));
+        let otp = passcode().compute(second.bytes());
+        assert!(matches!(
+            server.verify(second.bytes(), b"", &otp),
+            Err(Error::ChallengeStore(_))
+        ));
+    }
+}
@@ -0,0 +1,330 @@
+//! SQLite-backed challenge, counter and recovery code store (feature
+//! `sqlite-store`)
+
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use super::{Challenge, ChallengeBinding, ChallengeId, ChallengeStore};
+use crate::hotp::CounterStore;
+use crate::recovery::{CodeDigest, RecoveryStore};
+use crate::Error;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS passcode_challenges (
+    id BLOB PRIMARY KEY,
+    bytes BLOB NOT NULL,
+    expires_at INTEGER NOT NULL,
+    binding BLOB NOT NULL,
+    difficulty INTEGER NOT NULL DEFAULT 0
+);
+CREATE TABLE IF NOT EXISTS passcode_counters (
+    account TEXT PRIMARY KEY,
+    counter INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS passcode_recovery_codes (
+    account TEXT NOT NULL,
+    digest BLOB NOT NULL,
+    used INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (account, digest)
+);
+";
+
+/// Persistent store for challenges, HOTP counters and recovery codes in a
+/// SQLite database
+///
+/// Challenges, counters and recovery codes survive process restarts, which suits
+/// single-machine deployments that do not run Redis. The tables are created
+/// on open if missing.
+///
+/// SQLite calls are blocking; every operation is a single short statement
+/// on a local database, so the futures complete without yielding.
+#[derive(Debug)]
+pub struct SqliteStore {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    /// Opens or creates the database file at `path`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::from_connection(Connection::open(path).map_err(store_error)?)
+    }
+
+    /// Opens a private in-memory database, mainly for tests
+    pub fn open_in_memory() -> Result<Self, Error> {
+        Self::from_connection(Connection::open_in_memory().map_err(store_error)?)
+    }
+
+    /// Uses an existing connection, creating the tables if missing
+    pub fn from_connection(connection: Connection) -> Result<Self, Error> {
+        connection.execute_batch(SCHEMA).map_err(store_error)?;
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Connection> {
+        // A panic while holding the lock cannot leave a statement half-applied
+        self.connection
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl ChallengeStore for SqliteStore {
+    async fn put(&self, challenge: Challenge) -> Result<(), Error> {
+        insert_challenge(&self.lock(), &challenge).map_err(store_error)
+    }
+
+    /// Inserts the batch in one transaction, so either all challenges are
+    /// stored or none
+    async fn put_many(&self, challenges: Vec<Challenge>) -> Result<(), Error> {
+        let mut connection = self.lock();
+        let tx = connection.transaction().map_err(store_error)?;
+        for challenge in &challenges {
+            insert_challenge(&tx, challenge).map_err(store_error)?;
+        }
+        tx.commit().map_err(store_error)
+    }
+
+    async fn get_and_delete(&self, id: &ChallengeId) -> Result<Option<Challenge>, Error> {
+        let row: Option<(Vec<u8>, i64, Vec<u8>, u8)> = self
+            .lock()
+            .query_row(
+                "DELETE FROM passcode_challenges WHERE id = ?1
+                 RETURNING bytes, expires_at, binding, difficulty",
+                params![&id.as_bytes()[..]],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .optional()
+            .map_err(store_error)?;
+
+        row.map(|(bytes, expires_at, binding, difficulty)| {
+            Ok(Challenge {
+                id: *id,
+                bytes,
+                expires_at: from_millis(expires_at),
+                binding: ChallengeBinding::decode(&binding)?,
+                difficulty,
+            })
+        })
+        .transpose()
+    }
+
+    async fn expire(&self, now: SystemTime) -> Result<usize, Error> {
+        self.lock()
+            .execute(
+                "DELETE FROM passcode_challenges WHERE expires_at <= ?1",
+                params![to_millis(now)],
+            )
+            .map_err(store_error)
+    }
+}
+
+fn insert_challenge(connection: &Connection, challenge: &Challenge) -> rusqlite::Result<()> {
+    connection
+        .prepare_cached(
+            "INSERT OR REPLACE INTO passcode_challenges
+             (id, bytes, expires_at, binding, difficulty)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?
+        .execute(params![
+            &challenge.id.as_bytes()[..],
+            challenge.bytes,
+            to_millis(challenge.expires_at),
+            challenge.binding.encode(),
+            challenge.difficulty,
+        ])
+        .map(|_| ())
+}
+
+impl CounterStore for SqliteStore {
+    async fn get(&self, account: &str) -> Result<Option<u64>, Error> {
+        let counter: Option<i64> = self
+            .lock()
+            .query_row(
+                "SELECT counter FROM passcode_counters WHERE account = ?1",
+                params![account],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(store_error)?;
+        Ok(counter.map(|c| c as u64))
+    }
+
+    async fn advance(&self, account: &str, counter: u64) -> Result<bool, Error> {
+        // SQLite integers are signed 64-bit
+        let counter = i64::try_from(counter)
+            .map_err(|_| Error::ChallengeStore("counter exceeds i64::MAX".to_string()))?;
+
+        let changed = self
+            .lock()
+            .execute(
+                "INSERT INTO passcode_counters (account, counter) VALUES (?1, ?2)
+                 ON CONFLICT (account) DO UPDATE SET counter = excluded.counter
+                 WHERE excluded.counter > passcode_counters.counter",
+                params![account, counter],
+            )
+            .map_err(store_error)?;
+        Ok(changed == 1)
+    }
+}
+
+impl RecoveryStore for SqliteStore {
+    /// Swaps the codes in one transaction, so a failure keeps the old ones
+    async fn replace(&self, account: &str, digests: Vec<CodeDigest>) -> Result<(), Error> {
+        let mut connection = self.lock();
+        let tx = connection.transaction().map_err(store_error)?;
+        tx.execute(
+            "DELETE FROM passcode_recovery_codes WHERE account = ?1",
+            params![account],
+        )
+        .map_err(store_error)?;
+        {
+            let mut insert = tx
+                .prepare_cached(
+                    "INSERT OR IGNORE INTO passcode_recovery_codes (account, digest)
+                     VALUES (?1, ?2)",
+                )
+                .map_err(store_error)?;
+            for digest in &digests {
+                insert
+                    .execute(params![account, &digest[..]])
+                    .map_err(store_error)?;
+            }
+        }
+        tx.commit().map_err(store_error)
+    }
+
+    async fn consume(&self, account: &str, digest: &CodeDigest) -> Result<bool, Error> {
+        let changed = self
+            .lock()
+            .execute(
+                "UPDATE passcode_recovery_codes SET used = 1
+                 WHERE account = ?1 AND digest = ?2 AND used = 0",
+                params![account, &digest[..]],
+            )
+            .map_err(store_error)?;
+        Ok(changed == 1)
+    }
+
+    async fn remaining(&self, account: &str) -> Result<usize, Error> {
+        let count: i64 = self
+            .lock()
+            .query_row(
+                "SELECT COUNT(*) FROM passcode_recovery_codes WHERE account = ?1 AND used = 0",
+                params![account],
+                |row| row.get(0),
+            )
+            .map_err(store_error)?;
+        Ok(count as usize)
+    }
+}
+
+fn to_millis(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64)
+}
+
+fn from_millis(millis: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis.max(0) as u64)
+}
+
+fn store_error(e: rusqlite::Error) -> Error {
+    Error::ChallengeStore(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::challenge::{ChallengeManager, CHALLENGE_ID_LEN};
+    use crate::recovery::RecoveryCodes;
+    use crate::{Algorithm, Passcode};
+    use pollster::block_on;
+
+    #[test]
+    fn test_challenges_survive_reopen() {
+        let path = std::env::temp_dir().join(format!(
+            "passcode-sqlite-{}-{}.db",
+            std::process::id(),
+            to_millis(SystemTime::now())
+        ));
+        let passcode = || Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+
+        let challenge = {
+            let manager =
+                ChallengeManager::with_store(passcode(), SqliteStore::open(&path).unwrap());
+            block_on(manager.issue()).unwrap()
+        };
+
+        let manager = ChallengeManager::with_store(passcode(), SqliteStore::open(&path).unwrap());
+        let otp = passcode().compute(challenge.bytes());
+        assert_eq!(block_on(manager.verify(challenge.id(), &otp)), Ok(()));
+        assert_eq!(
+            block_on(manager.verify(challenge.id(), &otp)),
+            Err(Error::ChallengeNotFound)
+        );
+
+        drop(manager);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_expire() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let now = SystemTime::now();
+        let expiries = [
+            now - Duration::from_secs(1),
+            now,
+            now + Duration::from_secs(60),
+        ];
+        let challenges = expiries
+            .into_iter()
+            .enumerate()
+            .map(|(i, expires_at)| Challenge {
+                id: ChallengeId::from_bytes([i as u8; CHALLENGE_ID_LEN]),
+                bytes: vec![i as u8; 8],
+                expires_at,
+                binding: ChallengeBinding::new().with_purpose("login"),
+                difficulty: i as u8,
+            })
+            .collect();
+        block_on(store.put_many(challenges)).unwrap();
+
+        assert_eq!(block_on(store.expire(now)), Ok(2));
+        let remaining = ChallengeId::from_bytes([2; CHALLENGE_ID_LEN]);
+        let challenge = block_on(store.get_and_delete(&remaining)).unwrap().unwrap();
+        assert_eq!(challenge.binding().purpose(), Some("login"));
+        assert_eq!(challenge.difficulty(), 2);
+    }
+
+    #[test]
+    fn test_counters_only_advance() {
+        let store = SqliteStore::open_in_memory().unwrap();
+
+        assert_eq!(block_on(store.get("alice")), Ok(None));
+        assert_eq!(block_on(store.advance("alice", 5)), Ok(true));
+        assert_eq!(block_on(store.advance("alice", 5)), Ok(false));
+        assert_eq!(block_on(store.advance("alice", 4)), Ok(false));
+        assert_eq!(block_on(store.advance("alice", 9)), Ok(true));
+        assert_eq!(block_on(store.get("alice")), Ok(Some(9)));
+        assert!(block_on(store.advance("alice", u64::MAX)).is_err());
+    }
+
+    #[test]
+    fn test_recovery_codes() {
+        let codes =
+            RecoveryCodes::with_store(vec![3u8; 32], SqliteStore::open_in_memory().unwrap());
+        let issued = block_on(codes.generate("alice", 3)).unwrap();
+
+        assert_eq!(block_on(codes.verify("alice", &issued[1])), Ok(true));
+        assert_eq!(block_on(codes.verify("alice", &issued[1])), Ok(false));
+        assert_eq!(block_on(codes.remaining("alice")), Ok(2));
+
+        block_on(codes.generate("alice", 5)).unwrap();
+        assert_eq!(block_on(codes.verify("alice", &issued[0])), Ok(false));
+        assert_eq!(block_on(codes.remaining("alice")), Ok(5));
+    }
+}
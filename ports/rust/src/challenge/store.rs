@@ -0,0 +1,415 @@
+//! Storage backends for issued challenges
+
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::{Challenge, ChallengeBinding, ChallengeId};
+use crate::clock::{self, Clock};
+use crate::Error;
+
+/// Storage for issued challenges awaiting verification
+///
+/// [`ChallengeManager`](super::ChallengeManager) keeps no state of its own;
+/// it stores each issued challenge with [`put`](Self::put) and consumes it
+/// with [`get_and_delete`](Self::get_and_delete). Implementations must make
+/// `get_and_delete` atomic: when two calls race for the same id, at most one
+/// of them may return the challenge, otherwise a challenge could be verified
+/// twice.
+///
+/// The methods return `Send` futures, so implementations can be written
+/// with `async fn` and used from multi-threaded runtimes.
+pub trait ChallengeStore: Send + Sync {
+    /// Stores a newly issued challenge until it is consumed or expires
+    fn put(&self, challenge: Challenge) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Stores many newly issued challenges
+    ///
+    /// The default calls [`put`](Self::put) for each challenge in turn.
+    /// Backends should override it to store the whole batch in one round
+    /// trip. When it fails, some of the challenges may have been stored.
+    fn put_many(
+        &self,
+        challenges: Vec<Challenge>,
+    ) -> impl Future<Output = Result<(), Error>> + Send {
+        async move {
+            for challenge in challenges {
+                self.put(challenge).await?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Removes and returns the challenge with the given id, if present
+    ///
+    /// Expired challenges may be returned; the manager checks expiry itself.
+    fn get_and_delete(
+        &self,
+        id: &ChallengeId,
+    ) -> impl Future<Output = Result<Option<Challenge>, Error>> + Send;
+
+    /// Removes challenges that expired at or before `now`, returning how
+    /// many were removed
+    ///
+    /// Backends with native expiry may do nothing here and return zero.
+    fn expire(&self, now: SystemTime) -> impl Future<Output = Result<usize, Error>> + Send;
+}
+
+/// Default maximum number of challenges held by a [`MemoryStore`]
+pub const DEFAULT_CAPACITY: usize = 100_000;
+
+/// Default interval between sweeps of expired challenges
+pub const DEFAULT_SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Bounded in-memory store, the default for a [`ChallengeManager`](super::ChallengeManager)
+///
+/// Holds at most `capacity` challenges. When full, expired challenges are
+/// dropped first and then the least recently issued one is evicted, so a
+/// flood of unanswered challenges cannot grow memory without bound. Expired
+/// challenges are also swept on [`put`](ChallengeStore::put) once every
+/// sweep interval, without a background thread.
+///
+/// Challenges live only as long as the process, so this store suits a
+/// single server instance.
+#[derive(Debug)]
+pub struct MemoryStore {
+    inner: Mutex<Lru>,
+    capacity: usize,
+    sweep_interval: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+/// Challenges indexed by id and by insertion sequence
+#[derive(Debug)]
+struct Lru {
+    entries: HashMap<ChallengeId, (Challenge, u64)>,
+    order: BTreeMap<u64, ChallengeId>,
+    next_seq: u64,
+    next_sweep: SystemTime,
+}
+
+impl Lru {
+    fn insert(&mut self, challenge: Challenge) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.order.insert(seq, challenge.id);
+        if let Some((_, old_seq)) = self.entries.insert(challenge.id, (challenge, seq)) {
+            self.order.remove(&old_seq);
+        }
+    }
+
+    fn remove(&mut self, id: &ChallengeId) -> Option<Challenge> {
+        let (challenge, seq) = self.entries.remove(id)?;
+        self.order.remove(&seq);
+        Some(challenge)
+    }
+
+    fn evict_oldest(&mut self) {
+        if let Some((_, id)) = self.order.pop_first() {
+            self.entries.remove(&id);
+        }
+    }
+
+    fn expire(&mut self, now: SystemTime) -> usize {
+        let before = self.entries.len();
+        let order = &mut self.order;
+        self.entries.retain(|_, (challenge, seq)| {
+            let keep = now < challenge.expires_at;
+            if !keep {
+                order.remove(seq);
+            }
+            keep
+        });
+        before - self.entries.len()
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+}
+
+impl MemoryStore {
+    /// Creates an empty store holding up to [`DEFAULT_CAPACITY`] challenges
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty store holding up to `capacity` challenges
+    ///
+    /// A capacity of zero is treated as one.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            inner: Mutex::new(Lru {
+                entries: HashMap::new(),
+                order: BTreeMap::new(),
+                next_seq: 0,
+                next_sweep: SystemTime::now() + DEFAULT_SWEEP_INTERVAL,
+            }),
+            capacity: capacity.max(1),
+            sweep_interval: DEFAULT_SWEEP_INTERVAL,
+            clock: clock::system(),
+        }
+    }
+
+    /// Sets how often expired challenges are swept on insertion
+    pub fn with_sweep_interval(mut self, interval: Duration) -> Self {
+        self.sweep_interval = interval;
+        self.lock().next_sweep = self.clock.now() + interval;
+        self
+    }
+
+    /// Reads the time from `clock` instead of the system clock
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self.lock().next_sweep = self.clock.now() + self.sweep_interval;
+        self
+    }
+
+    /// Returns the maximum number of stored challenges
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of stored challenges
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    /// Returns true when no challenges are stored
+    pub fn is_empty(&self) -> bool {
+        self.lock().entries.is_empty()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Lru> {
+        // A panic while holding the lock cannot leave the maps inconsistent
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl ChallengeStore for MemoryStore {
+    async fn put(&self, challenge: Challenge) -> Result<(), Error> {
+        self.put_many(vec![challenge]).await
+    }
+
+    /// Stores the batch under a single lock; when the batch is larger than
+    /// the capacity, its earliest challenges are evicted
+    async fn put_many(&self, challenges: Vec<Challenge>) -> Result<(), Error> {
+        let now = self.clock.now();
+        let mut lru = self.lock();
+
+        if now >= lru.next_sweep {
+            lru.expire(now);
+            lru.next_sweep = now + self.sweep_interval;
+        }
+        for challenge in challenges {
+            // When full, make room by dropping expired challenges, or else
+            // the least recently issued one
+            if lru.entries.len() >= self.capacity
+                && !lru.entries.contains_key(&challenge.id)
+                && lru.expire(now) == 0
+            {
+                lru.evict_oldest();
+            }
+            lru.insert(challenge);
+        }
+        Ok(())
+    }
+
+    async fn get_and_delete(&self, id: &ChallengeId) -> Result<Option<Challenge>, Error> {
+        Ok(self.lock().remove(id))
+    }
+
+    async fn expire(&self, now: SystemTime) -> Result<usize, Error> {
+        Ok(self.lock().expire(now))
+    }
+}
+
+/// Serializes a challenge as `expires_at` (milliseconds since the Unix epoch,
+/// big-endian u64), the puzzle difficulty (one byte), the length of the
+/// challenge bytes (big-endian u32), the challenge bytes and the encoded
+/// binding
+pub(crate) fn encode_record(challenge: &Challenge) -> Vec<u8> {
+    let millis = challenge
+        .expires_at
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64);
+
+    let mut record = Vec::with_capacity(13 + challenge.bytes.len());
+    record.extend_from_slice(&millis.to_be_bytes());
+    record.push(challenge.difficulty);
+    record.extend_from_slice(&(challenge.bytes.len() as u32).to_be_bytes());
+    record.extend_from_slice(&challenge.bytes);
+    record.extend_from_slice(&challenge.binding.encode());
+    record
+}
+
+/// Parses a record written by [`encode_record`]
+pub(crate) fn decode_record(id: ChallengeId, record: &[u8]) -> Result<Challenge, Error> {
+    let truncated = || Error::ChallengeStore("truncated challenge record".to_string());
+    if record.len() < 13 {
+        return Err(truncated());
+    }
+    let (millis, rest) = record.split_at(8);
+    let millis = u64::from_be_bytes(millis.try_into().expect("split at 8"));
+    let (&difficulty, rest) = rest.split_first().expect("length checked above");
+    let (len, rest) = rest.split_at(4);
+    let len = u32::from_be_bytes(len.try_into().expect("split at 4")) as usize;
+    if rest.len() < len {
+        return Err(truncated());
+    }
+    let (bytes, binding) = rest.split_at(len);
+
+    Ok(Challenge {
+        id,
+        bytes: bytes.to_vec(),
+        expires_at: UNIX_EPOCH + Duration::from_millis(millis),
+        binding: ChallengeBinding::decode(binding)?,
+        difficulty,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::challenge::CHALLENGE_ID_LEN;
+    use pollster::block_on;
+
+    fn challenge(id: u8, expires_at: SystemTime) -> Challenge {
+        Challenge {
+            id: ChallengeId::from_bytes([id; CHALLENGE_ID_LEN]),
+            bytes: vec![id; 8],
+            expires_at,
+            binding: ChallengeBinding::default(),
+            difficulty: 0,
+        }
+    }
+
+    #[test]
+    fn test_get_and_delete_is_single_use() {
+        let store = MemoryStore::new();
+        let c = challenge(1, SystemTime::now());
+
+        block_on(store.put(c.clone())).unwrap();
+        assert_eq!(store.len(), 1);
+        assert_eq!(block_on(store.get_and_delete(c.id())), Ok(Some(c.clone())));
+        assert_eq!(block_on(store.get_and_delete(c.id())), Ok(None));
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn test_expire() {
+        let store = MemoryStore::new();
+        let now = SystemTime::now();
+        block_on(store.put(challenge(1, now - Duration::from_secs(1)))).unwrap();
+        block_on(store.put(challenge(2, now))).unwrap();
+        block_on(store.put(challenge(3, now + Duration::from_secs(60)))).unwrap();
+
+        assert_eq!(block_on(store.expire(now)), Ok(2));
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn test_capacity_evicts_oldest() {
+        let store = MemoryStore::with_capacity(2);
+        let later = SystemTime::now() + Duration::from_secs(60);
+        let (a, b, c) = (
+            challenge(1, later),
+            challenge(2, later),
+            challenge(3, later),
+        );
+
+        block_on(store.put(a.clone())).unwrap();
+        block_on(store.put(b.clone())).unwrap();
+        block_on(store.put(c.clone())).unwrap();
+
+        assert_eq!(store.len(), 2);
+        assert_eq!(block_on(store.get_and_delete(a.id())), Ok(None));
+        assert_eq!(block_on(store.get_and_delete(b.id())), Ok(Some(b)));
+        assert_eq!(block_on(store.get_and_delete(c.id())), Ok(Some(c)));
+    }
+
+    #[test]
+    fn test_full_store_drops_expired_before_evicting() {
+        let store = MemoryStore::with_capacity(2);
+        let now = SystemTime::now();
+        let live = challenge(1, now + Duration::from_secs(60));
+
+        block_on(store.put(live.clone())).unwrap();
+        block_on(store.put(challenge(2, now - Duration::from_secs(1)))).unwrap();
+        block_on(store.put(challenge(3, now + Duration::from_secs(60)))).unwrap();
+
+        assert_eq!(store.len(), 2);
+        assert_eq!(block_on(store.get_and_delete(live.id())), Ok(Some(live)));
+    }
+
+    #[test]
+    fn test_put_many() {
+        let store = MemoryStore::with_capacity(2);
+        let later = SystemTime::now() + Duration::from_secs(60);
+        let batch: Vec<_> = (1..=3).map(|id| challenge(id, later)).collect();
+
+        block_on(store.put_many(batch.clone())).unwrap();
+
+        assert_eq!(store.len(), 2);
+        assert_eq!(block_on(store.get_and_delete(batch[0].id())), Ok(None));
+        assert_eq!(
+            block_on(store.get_and_delete(batch[2].id())),
+            Ok(Some(batch[2].clone()))
+        );
+    }
+
+    #[test]
+    fn test_periodic_sweep_on_put() {
+        let store = MemoryStore::new().with_sweep_interval(Duration::ZERO);
+        let now = SystemTime::now();
+
+        block_on(store.put(challenge(1, now - Duration::from_secs(1)))).unwrap();
+        block_on(store.put(challenge(2, now + Duration::from_secs(60)))).unwrap();
+
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn test_concurrent_single_use() {
+        use std::sync::Arc;
+
+        let store = Arc::new(MemoryStore::new());
+        let c = challenge(1, SystemTime::now() + Duration::from_secs(60));
+        block_on(store.put(c.clone())).unwrap();
+
+        let winners: usize = (0..8)
+            .map(|_| {
+                let store = Arc::clone(&store);
+                let id = *c.id();
+                std::thread::spawn(move || block_on(store.get_and_delete(&id)).unwrap())
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|handle| handle.join().unwrap().is_some() as usize)
+            .sum();
+
+        assert_eq!(winners, 1);
+    }
+
+    #[test]
+    fn test_record_round_trip() {
+        let mut c = challenge(7, UNIX_EPOCH + Duration::from_millis(1_700_000_000_123));
+        let record = encode_record(&c);
+
+        assert_eq!(record.len(), 13 + c.bytes().len());
+        assert_eq!(decode_record(c.id, &record), Ok(c.clone()));
+        assert!(decode_record(c.id, &record[..12]).is_err());
+        assert!(decode_record(c.id, &record[..20]).is_err());
+
+        c.difficulty = 12;
+        assert_eq!(decode_record(c.id, &encode_record(&c)), Ok(c.clone()));
+
+        c.binding = ChallengeBinding::new().with_user_id("alice");
+        assert_eq!(decode_record(c.id, &encode_record(&c)), Ok(c));
+    }
+}
@@ -0,0 +1,69 @@
+//! Time-bound responses accepted within a window of time steps
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::Challenge;
+
+/// Separates the time step from the rest of a time-bound message
+const TIME_STEP_CONTEXT: &[u8] = b"passcode/v1/time-step";
+
+/// Returns the number of whole `step`s between the Unix epoch and `time`
+///
+/// Steps are counted in milliseconds; a step shorter than one millisecond is
+/// treated as one millisecond. Times before the epoch are step zero.
+pub fn time_step(time: SystemTime, step: Duration) -> u64 {
+    let elapsed = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    (elapsed.as_millis() / step.as_millis().max(1)) as u64
+}
+
+impl Challenge {
+    /// Builds the message for a time-bound response in time step `step`
+    ///
+    /// Clients of a manager configured with
+    /// [`with_time_steps`](super::ChallengeManager::with_time_steps) compute
+    /// their OTP over this message, with the step taken from their own clock
+    /// by [`time_step`].
+    pub fn message_at(&self, step: u64) -> Vec<u8> {
+        timed_message(&self.message(), step)
+    }
+}
+
+/// Appends the time step to an OTP message
+pub(crate) fn timed_message(message: &[u8], step: u64) -> Vec<u8> {
+    [message, TIME_STEP_CONTEXT, &step.to_be_bytes()].concat()
+}
+
+/// Step length and tolerance of a manager accepting time-bound responses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TimeWindow {
+    pub(crate) step: Duration,
+    pub(crate) window: u64,
+}
+
+impl TimeWindow {
+    /// Returns the steps to try at `now` with their skew, nearest first
+    pub(crate) fn candidates(&self, now: SystemTime) -> impl Iterator<Item = (u64, i64)> {
+        let current = time_step(now, self.step);
+        let window = i64::try_from(self.window).unwrap_or(i64::MAX);
+        std::iter::once(0)
+            .chain((1..=window).flat_map(|distance| [distance, -distance]))
+            .filter_map(move |skew| Some((current.checked_add_signed(skew)?, skew)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_candidates() {
+        let window = TimeWindow {
+            step: Duration::from_secs(30),
+            window: 2,
+        };
+        let steps: Vec<_> = window
+            .candidates(UNIX_EPOCH + Duration::from_secs(31))
+            .collect();
+        assert_eq!(steps, [(1, 0), (2, 1), (0, -1), (3, 2)]);
+    }
+}
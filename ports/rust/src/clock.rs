@@ -0,0 +1,112 @@
+//! Time sources for time-dependent logic
+//!
+//! Challenge expiry, TOTP, replay windows, rate limiting and lockouts read
+//! the time from a [`Clock`]. They default to [`SystemClock`]; tests can
+//! drive them with a [`ManualClock`], and targets without a usable system
+//! clock can supply their own source.
+//!
+//! # Example
+//! ```
+//! use passcode::clock::ManualClock;
+//! use passcode::throttle::{Lockout, LockoutPolicy};
+//! use std::sync::Arc;
+//! use std::time::{Duration, UNIX_EPOCH};
+//!
+//! let clock = Arc::new(ManualClock::new(UNIX_EPOCH));
+//! let lockout = Lockout::new(LockoutPolicy::default()).with_clock(Arc::clone(&clock));
+//! for _ in 0..5 {
+//!     lockout.record_failure("alice");
+//! }
+//! assert!(lockout.check("alice").is_err());
+//!
+//! clock.advance(Duration::from_secs(60));
+//! assert!(lockout.check("alice").is_ok());
+//! ```
+
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
+
+/// Source of the current time
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// Returns the current time
+    fn now(&self) -> SystemTime;
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    fn now(&self) -> SystemTime {
+        (**self).now()
+    }
+}
+
+/// The operating system's wall clock
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that only moves when told to
+#[derive(Debug)]
+pub struct ManualClock {
+    now: Mutex<SystemTime>,
+}
+
+impl ManualClock {
+    /// Creates a clock showing `start`
+    pub fn new(start: SystemTime) -> Self {
+        Self {
+            now: Mutex::new(start),
+        }
+    }
+
+    /// Sets the time shown
+    pub fn set(&self, now: SystemTime) {
+        *self.lock() = now;
+    }
+
+    /// Moves the clock forward
+    pub fn advance(&self, by: Duration) {
+        *self.lock() += by;
+    }
+
+    fn lock(&self) -> MutexGuard<'_, SystemTime> {
+        // A panic while holding the lock cannot leave the time inconsistent
+        self.now
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.lock()
+    }
+}
+
+/// Returns the default clock shared by components
+pub(crate) fn system() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn test_manual_clock() {
+        let clock = ManualClock::new(UNIX_EPOCH);
+        assert_eq!(clock.now(), UNIX_EPOCH);
+
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(clock.now(), UNIX_EPOCH + Duration::from_secs(5));
+
+        clock.set(UNIX_EPOCH + Duration::from_secs(1));
+        let shared: Arc<dyn Clock> = Arc::new(clock);
+        assert_eq!(shared.now(), UNIX_EPOCH + Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,276 @@
+//! Compact payload profile for constrained transports
+//!
+//! CoAP and MQTT publishers on constrained links count every byte. This
+//! profile encodes the [`wire`](crate::wire) messages of a
+//! [`SignedChallenges`](crate::challenge::SignedChallenges) exchange in at
+//! most [`MAX_COMPACT_LEN`] bytes: fields have fixed lengths instead of
+//! length prefixes, and the version, message type and a 4-bit algorithm ID
+//! share the first byte.
+//!
+//! | Field | Challenge | Response |
+//! |---|---|---|
+//! | header | 1 byte | 1 byte |
+//! | nonce | 16 bytes | 16 bytes, of the answered challenge |
+//! | expiry | 8 bytes, milliseconds since the Unix epoch | — |
+//! | MAC | 32 bytes, the server's MAC | [`Algorithm::otp_bytes`] bytes |
+//! | telemetry | — | the remaining bytes, possibly none |
+//!
+//! The header holds [`COMPACT_VERSION`] in the top two bits, the type
+//! (`1` challenge, `2` response) in the next two and [`Algorithm::id`] in
+//! the low four. A response can carry telemetry in the bytes the MAC leaves
+//! free, authenticated with
+//! [`ResponseMessage::compute_telemetry`], so a publisher answers the
+//! challenge and sends a reading in a single message.
+//!
+//! Decoders reject messages over [`MAX_COMPACT_LEN`] bytes, other versions
+//! and types, unknown algorithms and wrong lengths with
+//! [`Error::MalformedMessage`].
+//!
+//! # Example
+//! ```
+//! use passcode::challenge::SignedChallenges;
+//! use passcode::wire::{ChallengeMessage, ResponseMessage};
+//! use passcode::{Algorithm, Passcode};
+//!
+//! let passcode = || Passcode::new(Algorithm::Blake3KeyedMode128, vec![1u8; 32]);
+//! let server = SignedChallenges::new(passcode(), b"server key");
+//! let challenge = server.issue(b"").unwrap();
+//! let sent = ChallengeMessage::from_challenge(&challenge).unwrap().encode_compact().unwrap();
+//! assert!(sent.len() <= passcode::compact::MAX_COMPACT_LEN);
+//!
+//! // Publisher
+//! let received = ChallengeMessage::decode_compact(&sent).unwrap();
+//! let reading = b"t=21.5;h=40";
+//! let response = ResponseMessage::compute_telemetry(&passcode(), &received, reading);
+//! let published = response.encode_compact(reading).unwrap();
+//!
+//! // Server
+//! let (response, telemetry) = ResponseMessage::decode_compact(&published).unwrap();
+//! assert!(server.verify_telemetry(&received, &response, &telemetry, b"").is_ok());
+//! ```
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::challenge::NONCE_LEN;
+use crate::wire::{ChallengeMessage, ResponseMessage, TYPE_CHALLENGE, TYPE_RESPONSE};
+use crate::{Algorithm, Error};
+
+/// Longest message of the profile, in bytes
+pub const MAX_COMPACT_LEN: usize = 64;
+
+/// Version in the top two bits of the header
+pub const COMPACT_VERSION: u8 = 1;
+
+/// Length of the server's MAC in a challenge
+const SERVER_MAC_LEN: usize = blake3::OUT_LEN;
+
+/// Length of an encoded challenge
+const CHALLENGE_LEN: usize = 1 + NONCE_LEN + 8 + SERVER_MAC_LEN;
+
+/// Returns how many bytes of telemetry fit in a response for the algorithm
+pub fn max_telemetry_len(algorithm: Algorithm) -> usize {
+    MAX_COMPACT_LEN - 1 - NONCE_LEN - algorithm.otp_bytes()
+}
+
+impl ChallengeMessage {
+    /// Encodes the message in the compact profile
+    ///
+    /// Fails with [`Error::MalformedMessage`] when the nonce or MAC does not
+    /// have the length of a signed challenge.
+    pub fn encode_compact(&self) -> Result<Vec<u8>, Error> {
+        if self.nonce.len() != NONCE_LEN || self.mac.len() != SERVER_MAC_LEN {
+            return Err(Error::MalformedMessage(
+                "challenge does not fit the compact profile",
+            ));
+        }
+        let mut encoded = Vec::with_capacity(CHALLENGE_LEN);
+        encoded.push(header(COMPACT_VERSION, TYPE_CHALLENGE, self.algorithm)?);
+        encoded.extend_from_slice(&self.nonce);
+        encoded.extend_from_slice(&to_millis(self.expires_at).to_be_bytes());
+        encoded.extend_from_slice(&self.mac);
+        Ok(encoded)
+    }
+
+    /// Decodes a message written by [`encode_compact`](Self::encode_compact)
+    pub fn decode_compact(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() != CHALLENGE_LEN {
+            return Err(Error::MalformedMessage("wrong compact challenge length"));
+        }
+        let algorithm = read_header(bytes[0], COMPACT_VERSION, TYPE_CHALLENGE)?;
+        let (nonce, rest) = bytes[1..].split_at(NONCE_LEN);
+        let (expiry, mac) = rest.split_at(8);
+        Ok(Self {
+            algorithm,
+            nonce: nonce.to_vec(),
+            expires_at: UNIX_EPOCH
+                + Duration::from_millis(u64::from_be_bytes(expiry.try_into().expect("split at 8"))),
+            mac: mac.to_vec(),
+        })
+    }
+}
+
+impl ResponseMessage {
+    /// Encodes the message in the compact profile, followed by `telemetry`
+    ///
+    /// The telemetry is only authenticated if the response was computed
+    /// with [`compute_telemetry`](Self::compute_telemetry) over it. Fails
+    /// with [`Error::MalformedMessage`] when the nonce or MAC has the wrong
+    /// length, or the telemetry is longer than [`max_telemetry_len`].
+    pub fn encode_compact(&self, telemetry: &[u8]) -> Result<Vec<u8>, Error> {
+        if self.nonce.len() != NONCE_LEN || self.mac.len() != self.algorithm.otp_bytes() {
+            return Err(Error::MalformedMessage(
+                "response does not fit the compact profile",
+            ));
+        }
+        if telemetry.len() > max_telemetry_len(self.algorithm) {
+            return Err(Error::MalformedMessage(
+                "telemetry exceeds the compact profile",
+            ));
+        }
+        let mut encoded = Vec::with_capacity(1 + NONCE_LEN + self.mac.len() + telemetry.len());
+        encoded.push(header(COMPACT_VERSION, TYPE_RESPONSE, self.algorithm)?);
+        encoded.extend_from_slice(&self.nonce);
+        encoded.extend_from_slice(&self.mac);
+        encoded.extend_from_slice(telemetry);
+        Ok(encoded)
+    }
+
+    /// Decodes a message written by [`encode_compact`](Self::encode_compact),
+    /// returning the response and the telemetry that followed it
+    pub fn decode_compact(bytes: &[u8]) -> Result<(Self, Vec<u8>), Error> {
+        if bytes.len() > MAX_COMPACT_LEN {
+            return Err(Error::MalformedMessage("compact message is too long"));
+        }
+        let (&first, rest) = bytes
+            .split_first()
+            .ok_or(Error::MalformedMessage("truncated message"))?;
+        let algorithm = read_header(first, COMPACT_VERSION, TYPE_RESPONSE)?;
+        if rest.len() < NONCE_LEN + algorithm.otp_bytes() {
+            return Err(Error::MalformedMessage("truncated message"));
+        }
+        let (nonce, rest) = rest.split_at(NONCE_LEN);
+        let (mac, telemetry) = rest.split_at(algorithm.otp_bytes());
+        let response = Self {
+            algorithm,
+            nonce: nonce.to_vec(),
+            mac: mac.to_vec(),
+        };
+        Ok((response, telemetry.to_vec()))
+    }
+}
+
+/// Packs the one-byte header shared with the [`ble`](crate::ble) messages
+pub(crate) fn header(version: u8, kind: u8, algorithm: Algorithm) -> Result<u8, Error> {
+    let id = algorithm.id();
+    if id > 0x0f {
+        return Err(Error::MalformedMessage(
+            "algorithm ID does not fit the compact profile",
+        ));
+    }
+    Ok(version << 6 | kind << 4 | id)
+}
+
+pub(crate) fn read_header(header: u8, version: u8, kind: u8) -> Result<Algorithm, Error> {
+    if header >> 6 != version {
+        return Err(Error::MalformedMessage("unsupported compact version"));
+    }
+    if (header >> 4) & 0x03 != kind {
+        return Err(Error::MalformedMessage("unexpected message type"));
+    }
+    Algorithm::from_id(header & 0x0f).ok_or(Error::MalformedMessage("unknown algorithm"))
+}
+
+fn to_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::challenge::SignedChallenges;
+    use crate::Passcode;
+
+    fn challenge() -> ChallengeMessage {
+        ChallengeMessage {
+            algorithm: Algorithm::Sha3Kmac512,
+            nonce: vec![0xaa; NONCE_LEN],
+            expires_at: UNIX_EPOCH + Duration::from_millis(0x0102_0304_0506),
+            mac: vec![0xbb; SERVER_MAC_LEN],
+        }
+    }
+
+    #[test]
+    fn test_challenge_layout() {
+        let encoded = challenge().encode_compact().unwrap();
+        assert_eq!(encoded.len(), 57);
+        assert_eq!(encoded[0], 0b0101_0100);
+        assert_eq!(encoded[17..25], [0, 0, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(ChallengeMessage::decode_compact(&encoded), Ok(challenge()));
+
+        let mut long_nonce = challenge();
+        long_nonce.nonce.push(0);
+        assert!(long_nonce.encode_compact().is_err());
+    }
+
+    #[test]
+    fn test_response_fills_profile() {
+        for algorithm in Algorithm::all() {
+            let passcode = Passcode::new(algorithm, vec![1u8; 32]);
+            let telemetry = vec![0x42; max_telemetry_len(algorithm)];
+            let response = ResponseMessage::compute_telemetry(&passcode, &challenge(), &telemetry);
+
+            let encoded = response.encode_compact(&telemetry).unwrap();
+            assert_eq!(encoded.len(), MAX_COMPACT_LEN);
+            assert_eq!(
+                ResponseMessage::decode_compact(&encoded),
+                Ok((response.clone(), telemetry.clone()))
+            );
+            assert!(response
+                .encode_compact(&[telemetry, vec![0]].concat())
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn test_telemetry_is_authenticated() {
+        let passcode = || Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+        let server = SignedChallenges::new(passcode(), b"server key");
+        let received = ChallengeMessage::from_challenge(&server.issue(b"").unwrap()).unwrap();
+        let response = ResponseMessage::compute_telemetry(&passcode(), &received, b"t=21");
+
+        let tampered = response.encode_compact(b"t=99").unwrap();
+        let (response, telemetry) = ResponseMessage::decode_compact(&tampered).unwrap();
+        assert_eq!(
+            server.verify_telemetry(&received, &response, &telemetry, b""),
+            Err(Error::OtpMismatch)
+        );
+    }
+
+    #[test]
+    fn test_rejects_malformed() {
+        let encoded = challenge().encode_compact().unwrap();
+        let mut version = encoded.clone();
+        version[0] &= 0x3f;
+        assert_eq!(
+            ChallengeMessage::decode_compact(&version),
+            Err(Error::MalformedMessage("unsupported compact version"))
+        );
+        assert_eq!(
+            ResponseMessage::decode_compact(&encoded),
+            Err(Error::MalformedMessage("unexpected message type"))
+        );
+        assert_eq!(
+            ResponseMessage::decode_compact(&[0x60, 1, 2]),
+            Err(Error::MalformedMessage("truncated message"))
+        );
+        assert_eq!(
+            ResponseMessage::decode_compact(&[0x6f; 20]),
+            Err(Error::MalformedMessage("unknown algorithm"))
+        );
+        assert_eq!(
+            ResponseMessage::decode_compact(&[0x61; MAX_COMPACT_LEN + 1]),
+            Err(Error::MalformedMessage("compact message is too long"))
+        );
+    }
+}
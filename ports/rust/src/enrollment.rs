@@ -0,0 +1,333 @@
+//! Device enrollment
+//!
+//! Provisioning a new key is a three-step handshake. The server generates a
+//! key per its [`EnrollmentPolicy`] and hands the client a [`Provisioning`]
+//! payload: an `otpauth-cr://` URI, optionally shown as a QR code, and a
+//! first challenge. The client imports the key and answers the challenge.
+//! Only a correct answer activates the key in the server's [`KeyRing`], so a
+//! device that never received the key, or a mistyped payload, leaves no
+//! usable key behind.
+//!
+//! # Example
+//! ```
+//! use passcode::enrollment::{Enrollment, EnrollmentPolicy};
+//! use passcode::keyring::KeyRing;
+//! use passcode::otpauth::OtpAuthUri;
+//!
+//! # pollster::block_on(async {
+//! let enrollment = Enrollment::new(EnrollmentPolicy::default().with_issuer("Example"));
+//! let keyring = KeyRing::new();
+//!
+//! let provisioning = enrollment.begin("alice").await.unwrap();
+//! let payload = provisioning.uri().to_string();
+//!
+//! // Client side, after scanning the payload
+//! let client = payload.parse::<OtpAuthUri>().unwrap().to_passcode().unwrap();
+//! let challenge = provisioning.challenge();
+//! let otp = client.compute(&challenge.message());
+//!
+//! enrollment
+//!     .confirm("alice", challenge.id(), &otp, &keyring)
+//!     .await
+//!     .unwrap();
+//! assert!(keyring.contains("alice"));
+//! # });
+//! ```
+
+use std::time::Duration;
+
+use crate::challenge::{
+    Challenge, ChallengeBinding, ChallengeId, ChallengeManager, ChallengeStore, MemoryStore,
+};
+use crate::clock::Clock;
+use crate::keyring::KeyRing;
+use crate::otpauth::OtpAuthUri;
+#[cfg(feature = "qr")]
+use crate::qr::QrCode;
+use crate::rng::CryptoRngCore;
+use crate::{Algorithm, Error, Passcode};
+
+/// Purpose bound to confirmation challenges, so they cannot be answered
+/// with a response meant for a login
+pub const ENROLLMENT_PURPOSE: &str = "enrollment";
+
+/// Default time the client has to answer the confirmation challenge
+pub const DEFAULT_ENROLLMENT_TTL: Duration = Duration::from_secs(600);
+
+/// Default length in bytes of generated keys
+pub const DEFAULT_ENROLLMENT_KEY_LEN: usize = 32;
+
+/// Parameters of the keys handed out during enrollment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnrollmentPolicy {
+    /// Keyed hash algorithm of generated keys
+    pub algorithm: Algorithm,
+    /// Length of generated keys in bytes
+    pub key_len: usize,
+    /// Time the client has to answer each confirmation challenge
+    pub ttl: Duration,
+    /// Issuer written into provisioning URIs
+    pub issuer: Option<String>,
+}
+
+impl Default for EnrollmentPolicy {
+    fn default() -> Self {
+        Self {
+            algorithm: Algorithm::Sha3Kmac256,
+            key_len: DEFAULT_ENROLLMENT_KEY_LEN,
+            ttl: DEFAULT_ENROLLMENT_TTL,
+            issuer: None,
+        }
+    }
+}
+
+impl EnrollmentPolicy {
+    /// Sets the algorithm of generated keys
+    pub fn with_algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Sets the length of generated keys in bytes
+    pub fn with_key_len(mut self, key_len: usize) -> Self {
+        self.key_len = key_len;
+        self
+    }
+
+    /// Sets the time the client has to answer each confirmation challenge
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Sets the issuer written into provisioning URIs
+    pub fn with_issuer(mut self, issuer: impl Into<String>) -> Self {
+        self.issuer = Some(issuer.into());
+        self
+    }
+}
+
+/// What the server sends a client to enroll it
+///
+/// The URI carries the secret key, so it must only travel over a channel
+/// the user trusts, e.g. a QR code on a logged-in screen.
+#[derive(Debug, Clone)]
+pub struct Provisioning {
+    uri: OtpAuthUri,
+    challenge: Challenge,
+}
+
+impl Provisioning {
+    /// Gets the `otpauth-cr://` URI the client imports the key from
+    pub fn uri(&self) -> &OtpAuthUri {
+        &self.uri
+    }
+
+    /// Gets the challenge the client answers to confirm the enrollment
+    ///
+    /// The client computes its OTP over [`Challenge::message`].
+    pub fn challenge(&self) -> &Challenge {
+        &self.challenge
+    }
+
+    /// Renders the URI as a QR code
+    #[cfg(feature = "qr")]
+    pub fn to_qr(&self) -> Result<QrCode, Error> {
+        QrCode::new(&self.uri.to_string())
+    }
+}
+
+/// Generates keys for enrolling devices and activates them once confirmed
+///
+/// Keys awaiting confirmation are held by the enrollment, not the key ring
+/// users sign in with. Starting another enrollment for the user replaces its
+/// pending key; [`cancel`](Self::cancel) drops it.
+pub struct Enrollment<S = MemoryStore> {
+    policy: EnrollmentPolicy,
+    manager: ChallengeManager<S>,
+}
+
+impl Enrollment<MemoryStore> {
+    /// Creates an enrollment keeping confirmation challenges in a
+    /// [`MemoryStore`]
+    pub fn new(policy: EnrollmentPolicy) -> Self {
+        Self::with_store(policy, MemoryStore::new())
+    }
+}
+
+impl<S: ChallengeStore> Enrollment<S> {
+    /// Creates an enrollment keeping confirmation challenges in the given
+    /// store
+    pub fn with_store(policy: EnrollmentPolicy, store: S) -> Self {
+        let manager = ChallengeManager::with_keyring(KeyRing::new(), store).with_ttl(policy.ttl);
+        Self { policy, manager }
+    }
+
+    /// Sets the clock confirmation challenges expire by
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.manager = self.manager.with_clock(clock);
+        self
+    }
+
+    /// Draws keys and confirmation challenges from `rng` instead of the
+    /// operating system's CSPRNG
+    pub fn with_rng(mut self, rng: impl CryptoRngCore + Send + 'static) -> Self {
+        self.manager = self.manager.with_rng(rng);
+        self
+    }
+
+    /// Gets the policy
+    pub fn policy(&self) -> &EnrollmentPolicy {
+        &self.policy
+    }
+
+    /// Returns true if the user has a key awaiting confirmation
+    pub fn is_pending(&self, user_id: &str) -> bool {
+        self.pending().contains(user_id)
+    }
+
+    /// Generates a key for the user and issues the challenge confirming it
+    ///
+    /// The key is checked against the default policy like
+    /// [`Passcode::try_new`]. Any key the user had pending is replaced.
+    pub async fn begin(&self, user_id: &str) -> Result<Provisioning, Error> {
+        let mut key = vec![0u8; self.policy.key_len];
+        self.manager.rng().fill(&mut key)?;
+        let passcode = Passcode::try_new(self.policy.algorithm, key.clone())?;
+        self.pending().insert(user_id, passcode);
+
+        let challenge = self.rechallenge(user_id).await?;
+        let mut uri = OtpAuthUri::challenge_response(self.policy.algorithm, key, user_id);
+        if let Some(issuer) = &self.policy.issuer {
+            uri = uri.with_issuer(issuer);
+        }
+        Ok(Provisioning { uri, challenge })
+    }
+
+    /// Issues a new confirmation challenge for the user's pending key, e.g.
+    /// after a mistyped or expired answer
+    ///
+    /// Fails with [`Error::EnrollmentNotFound`] when no key is pending.
+    pub async fn rechallenge(&self, user_id: &str) -> Result<Challenge, Error> {
+        if !self.is_pending(user_id) {
+            return Err(Error::EnrollmentNotFound);
+        }
+        self.manager.issue_bound(binding(user_id)).await
+    }
+
+    /// Checks the answer to a confirmation challenge and, if correct, moves
+    /// the user's pending key into `keyring`
+    ///
+    /// The key replaces any the user already had in the ring. Fails with
+    /// [`Error::EnrollmentNotFound`] when no key is pending, and otherwise
+    /// like [`ChallengeManager::verify_bound`]; the key stays pending after
+    /// a failure so the client can answer a [`rechallenge`](Self::rechallenge).
+    pub async fn confirm(
+        &self,
+        user_id: &str,
+        challenge_id: &ChallengeId,
+        otp: &str,
+        keyring: &KeyRing,
+    ) -> Result<(), Error> {
+        if !self.is_pending(user_id) {
+            return Err(Error::EnrollmentNotFound);
+        }
+        self.manager
+            .verify_bound(challenge_id, &binding(user_id), otp)
+            .await?;
+        // A concurrent cancel may have dropped the key after the check
+        let passcode = self
+            .pending()
+            .remove(user_id)
+            .ok_or(Error::EnrollmentNotFound)?;
+        keyring.insert(user_id, passcode);
+        Ok(())
+    }
+
+    /// Drops the user's pending key, returning true if there was one
+    pub fn cancel(&self, user_id: &str) -> bool {
+        self.pending().remove(user_id).is_some()
+    }
+
+    fn pending(&self) -> &KeyRing {
+        self.manager
+            .keyring()
+            .expect("enrollment manager verifies with a key ring")
+    }
+}
+
+/// Binding of the user's confirmation challenges
+fn binding(user_id: &str) -> ChallengeBinding {
+    ChallengeBinding::new()
+        .with_user_id(user_id)
+        .with_purpose(ENROLLMENT_PURPOSE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pollster::block_on;
+
+    fn client(provisioning: &Provisioning) -> Passcode {
+        let uri: OtpAuthUri = provisioning.uri().to_string().parse().unwrap();
+        uri.to_passcode().unwrap()
+    }
+
+    #[test]
+    fn test_activates_only_after_confirmation() {
+        let enrollment = Enrollment::new(EnrollmentPolicy::default().with_issuer("Example"));
+        let keyring = KeyRing::new();
+
+        let provisioning = block_on(enrollment.begin("alice")).unwrap();
+        assert_eq!(provisioning.uri().issuer.as_deref(), Some("Example"));
+        assert!(enrollment.is_pending("alice"));
+        assert!(!keyring.contains("alice"));
+
+        // A wrong answer consumes the challenge but keeps the key pending
+        let challenge = provisioning.challenge();
+        assert_eq!(
+            block_on(enrollment.confirm("alice", challenge.id(), "00", &keyring)),
+            Err(Error::OtpMismatch)
+        );
+        assert!(!keyring.contains("alice"));
+
+        let challenge = block_on(enrollment.rechallenge("alice")).unwrap();
+        let otp = client(&provisioning).compute(&challenge.message());
+        assert_eq!(
+            block_on(enrollment.confirm("bob", challenge.id(), &otp, &keyring)),
+            Err(Error::EnrollmentNotFound)
+        );
+        block_on(enrollment.confirm("alice", challenge.id(), &otp, &keyring)).unwrap();
+        assert!(!enrollment.is_pending("alice"));
+
+        let activated = keyring.get("alice").unwrap();
+        assert_eq!(
+            activated.compute(b"data"),
+            client(&provisioning).compute(b"data")
+        );
+    }
+
+    #[test]
+    fn test_begin_replaces_and_cancel_drops() {
+        let enrollment = Enrollment::new(EnrollmentPolicy::default());
+        let keyring = KeyRing::new();
+
+        let first = block_on(enrollment.begin("alice")).unwrap();
+        let second = block_on(enrollment.begin("alice")).unwrap();
+        assert_ne!(first.uri().secret(), second.uri().secret());
+
+        let challenge = second.challenge();
+        let otp = client(&first).compute(&challenge.message());
+        assert_eq!(
+            block_on(enrollment.confirm("alice", challenge.id(), &otp, &keyring)),
+            Err(Error::OtpMismatch)
+        );
+
+        assert!(enrollment.cancel("alice"));
+        assert!(!enrollment.cancel("alice"));
+        assert_eq!(
+            block_on(enrollment.rechallenge("alice")),
+            Err(Error::EnrollmentNotFound)
+        );
+    }
+}
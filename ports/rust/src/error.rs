@@ -0,0 +1,240 @@
+use std::fmt;
+
+use crate::keyrecord::KeyState;
+
+/// Errors returned by the fallible parts of the library
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The requested number of OTP digits is not supported
+    InvalidDigits(u32),
+    /// The time step must be greater than zero
+    InvalidTimeStep,
+    /// Password-based key derivation failed (e.g. invalid parameters or salt)
+    KeyDerivation(String),
+    /// A known-answer self-test did not produce the expected output
+    SelfTestFailed(&'static str),
+    /// The algorithm is not approved by the active policy
+    AlgorithmNotApproved(&'static str),
+    /// The key is shorter than the required minimum length
+    KeyTooShort {
+        /// Minimum accepted length in bytes
+        min: usize,
+        /// Length of the rejected key in bytes
+        actual: usize,
+    },
+    /// The output format parameters are not supported
+    InvalidFormat(&'static str),
+    /// A QR code could not be generated (e.g. the payload is too large)
+    QrCode(String),
+    /// A provisioning URI is malformed or uses unsupported parameters
+    InvalidUri(&'static str),
+    /// The operating system's random number generator failed
+    RandomSource(String),
+    /// The challenge is unknown or has already been used
+    ChallengeNotFound,
+    /// The challenge has expired
+    ChallengeExpired,
+    /// The OTP does not match the challenge
+    OtpMismatch,
+    /// The challenge store backend failed
+    ChallengeStore(String),
+    /// A signed challenge is malformed or its signature does not match
+    InvalidChallenge(&'static str),
+    /// The challenge or OTP has already been presented
+    ReplayDetected,
+    /// Too many verification attempts for the identity
+    RateLimited {
+        /// Time until the next attempt is allowed
+        retry_after: std::time::Duration,
+    },
+    /// The identity is locked out after repeated failures
+    LockedOut {
+        /// Time until the lock ends
+        retry_after: std::time::Duration,
+    },
+    /// A protocol message arrived out of order
+    UnexpectedMessage {
+        /// Message the session was waiting for, or `None` once it is finished
+        expected: Option<crate::session::MessageKind>,
+        /// Message that arrived
+        received: crate::session::MessageKind,
+    },
+    /// A protocol message could not be decoded
+    MalformedMessage(&'static str),
+    /// The server's proof does not match the shared key
+    ServerProofMismatch,
+    /// The client puzzle attached to a challenge was not solved
+    PuzzleUnsolved,
+    /// The user has no enrollment awaiting confirmation
+    EnrollmentNotFound,
+    /// A session token is malformed, forged or no longer valid for its user
+    InvalidToken(&'static str),
+    /// A session token has expired
+    TokenExpired,
+    /// The challenge has fewer random bytes than the challenge policy
+    /// requires
+    ChallengeTooShort {
+        /// Minimum accepted length in bytes
+        min: usize,
+        /// Length of the rejected challenge in bytes
+        actual: usize,
+    },
+    /// The challenge would remain valid for longer than the challenge policy
+    /// allows
+    ChallengeLifetimeTooLong {
+        /// Longest accepted lifetime
+        max: std::time::Duration,
+        /// Lifetime of the rejected challenge
+        actual: std::time::Duration,
+    },
+    /// The binding lacks a field the challenge policy requires
+    BindingFieldMissing(crate::challenge::BindingField),
+    /// The client has no key for any algorithm the server offered
+    NoCommonAlgorithm,
+    /// The client answered with an algorithm the server did not offer
+    AlgorithmNotOffered(crate::Algorithm),
+    /// A handshake did not finish before its timeout
+    HandshakeTimeout,
+    /// A test vector's expected output differs from the computed one
+    VectorMismatch {
+        /// Name of the vector
+        name: String,
+        /// Field that differs
+        field: &'static str,
+    },
+    /// A key backup, wrapped key or escrow could not be decrypted, because
+    /// the password or key is wrong or the data was altered
+    DecryptionFailed,
+    /// Fewer distinct key shares than the threshold were given
+    InsufficientShares {
+        /// Number of shares the key was split to need
+        threshold: u8,
+        /// Number of distinct shares given
+        provided: usize,
+    },
+    /// The OS keychain is unavailable or has no such entry
+    Keychain(String),
+    /// A message uses a wire version outside the accepted range
+    UnsupportedVersion {
+        /// Version of the rejected message
+        version: u8,
+        /// Oldest accepted version
+        min: u8,
+        /// Newest accepted version
+        max: u8,
+    },
+    /// A key cannot move between these lifecycle states
+    InvalidKeyTransition {
+        /// Current state of the key
+        from: KeyState,
+        /// Requested state
+        to: KeyState,
+    },
+    /// A TPM, HSM or security key failed or refused the operation
+    Hardware(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidDigits(digits) => {
+                write!(f, "unsupported number of OTP digits: {}", digits)
+            }
+            Error::InvalidTimeStep => write!(f, "time step must be greater than zero"),
+            Error::KeyDerivation(reason) => write!(f, "key derivation failed: {}", reason),
+            Error::SelfTestFailed(algorithm) => {
+                write!(f, "known-answer self-test failed for {}", algorithm)
+            }
+            Error::AlgorithmNotApproved(algorithm) => {
+                write!(f, "algorithm {} is not approved by the active policy", algorithm)
+            }
+            Error::KeyTooShort { min, actual } => write!(
+                f,
+                "key is too short: {} bytes, at least {} required",
+                actual, min
+            ),
+            Error::InvalidFormat(reason) => write!(f, "invalid output format: {}", reason),
+            Error::QrCode(reason) => write!(f, "QR code generation failed: {}", reason),
+            Error::InvalidUri(reason) => write!(f, "invalid provisioning URI: {}", reason),
+            Error::RandomSource(reason) => {
+                write!(f, "random number generation failed: {}", reason)
+            }
+            Error::ChallengeNotFound => write!(f, "challenge not found or already used"),
+            Error::ChallengeExpired => write!(f, "challenge has expired"),
+            Error::OtpMismatch => write!(f, "OTP does not match the challenge"),
+            Error::ChallengeStore(reason) => write!(f, "challenge store failed: {}", reason),
+            Error::InvalidChallenge(reason) => write!(f, "invalid challenge: {}", reason),
+            Error::ReplayDetected => write!(f, "challenge has already been used"),
+            Error::RateLimited { retry_after } => write!(
+                f,
+                "too many attempts, retry after {} ms",
+                retry_after.as_millis()
+            ),
+            Error::LockedOut { retry_after } => write!(
+                f,
+                "locked out after repeated failures, retry after {} ms",
+                retry_after.as_millis()
+            ),
+            Error::UnexpectedMessage {
+                expected: Some(expected),
+                received,
+            } => write!(f, "unexpected {} message, expected {}", received, expected),
+            Error::UnexpectedMessage {
+                expected: None,
+                received,
+            } => write!(f, "unexpected {} message, session is finished", received),
+            Error::MalformedMessage(reason) => write!(f, "malformed message: {}", reason),
+            Error::ServerProofMismatch => write!(f, "server proof does not match"),
+            Error::PuzzleUnsolved => write!(f, "client puzzle solution is missing or wrong"),
+            Error::EnrollmentNotFound => write!(f, "no enrollment awaiting confirmation"),
+            Error::InvalidToken(reason) => write!(f, "invalid session token: {}", reason),
+            Error::TokenExpired => write!(f, "session token has expired"),
+            Error::ChallengeTooShort { min, actual } => write!(
+                f,
+                "challenge is too short: {} bytes, at least {} required",
+                actual, min
+            ),
+            Error::ChallengeLifetimeTooLong { max, actual } => write!(
+                f,
+                "challenge lifetime of {} ms exceeds the maximum of {} ms",
+                actual.as_millis(),
+                max.as_millis()
+            ),
+            Error::BindingFieldMissing(field) => {
+                write!(f, "challenge binding is missing required field {}", field)
+            }
+            Error::NoCommonAlgorithm => write!(f, "no offered algorithm is supported"),
+            Error::AlgorithmNotOffered(algorithm) => {
+                write!(f, "algorithm {} was not offered", algorithm.as_str())
+            }
+            Error::HandshakeTimeout => write!(f, "handshake timed out"),
+            Error::VectorMismatch { name, field } => {
+                write!(f, "test vector {}: {} does not match", name, field)
+            }
+            Error::DecryptionFailed => {
+                write!(f, "wrong password or key, or corrupted key")
+            }
+            Error::InsufficientShares {
+                threshold,
+                provided,
+            } => write!(
+                f,
+                "{} key shares given, {} required",
+                provided, threshold
+            ),
+            Error::Keychain(reason) => write!(f, "OS keychain failed: {}", reason),
+            Error::UnsupportedVersion { version, min, max } => write!(
+                f,
+                "unsupported wire version {}, accepted {} to {}",
+                version, min, max
+            ),
+            Error::InvalidKeyTransition { from, to } => {
+                write!(f, "cannot move key from {} to {}", from, to)
+            }
+            Error::Hardware(reason) => write!(f, "hardware token failed: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,399 @@
+//! Key escrow to a recovery public key (feature `escrow`)
+//!
+//! Organizations that must be able to recover enrollments after a device is
+//! lost can escrow each key to an X25519 [`RecoveryPublicKey`] with
+//! [`Passcode::export_escrowed`]. Only the holder of the matching
+//! [`RecoveryKey`], kept offline, can open the escrow with
+//! [`Passcode::import_escrowed`]; the server that writes escrows never holds
+//! a secret that opens them.
+//!
+//! Escrows are sealed boxes: each one uses a fresh ephemeral X25519 key, the
+//! encryption key and nonce are derived from the shared secret and both
+//! public keys with KMAC256 under [`labels::ESCROW`], and the algorithm and
+//! key are encrypted with XChaCha20-Poly1305. The escrow is `passcode-escrow:`
+//! followed by unpadded base64url of
+//!
+//! | Field | Length |
+//! |---|---|
+//! | version, [`ESCROW_VERSION`] | 1 byte |
+//! | [`KeyId`] of the recovery public key | 8 bytes |
+//! | ephemeral public key | 32 bytes |
+//! | ciphertext of the [`Algorithm::id`] and the key, then the tag | 17 bytes + key length |
+//!
+//! # Example
+//! ```
+//! use passcode::escrow::RecoveryKey;
+//! use passcode::{Algorithm, Passcode};
+//!
+//! // Kept offline by the recovery officer
+//! let recovery = RecoveryKey::generate();
+//! let public = recovery.public_key();
+//!
+//! let passcode = Passcode::new(Algorithm::Sha3Kmac256, vec![7u8; 32]);
+//! let escrow = passcode.export_escrowed(&public);
+//!
+//! let recovered = Passcode::import_escrowed(&escrow, &recovery).unwrap();
+//! assert_eq!(recovered.key_id(), passcode.key_id());
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+
+use base64ct::{Base64UrlUnpadded, Encoding};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::kdf::{derive_subkey, key_fingerprint, labels, KeyId, KEY_ID_LEN};
+use crate::rng::{CryptoRngCore, SharedRng};
+use crate::{Algorithm, Error, Passcode};
+
+/// Version byte of the escrow format
+pub const ESCROW_VERSION: u8 = 1;
+
+/// Prefix of an armored escrow
+pub const ESCROW_PREFIX: &str = "passcode-escrow:";
+
+const PUBLIC_KEY_LEN: usize = 32;
+const CIPHER_KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 24;
+const TAG_LEN: usize = 16;
+/// Version, recipient Key-ID and ephemeral public key
+const HEADER_LEN: usize = 1 + KEY_ID_LEN + PUBLIC_KEY_LEN;
+
+/// X25519 public key escrows are encrypted to
+///
+/// Displayed and parsed as 64 lowercase hex characters.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct RecoveryPublicKey(PublicKey);
+
+impl fmt::Debug for RecoveryPublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RecoveryPublicKey({})", self)
+    }
+}
+
+impl RecoveryPublicKey {
+    /// Creates a public key from its 32 bytes
+    pub fn from_bytes(bytes: [u8; PUBLIC_KEY_LEN]) -> Self {
+        Self(PublicKey::from(bytes))
+    }
+
+    /// Gets the 32 bytes of the public key
+    pub fn as_bytes(&self) -> &[u8; PUBLIC_KEY_LEN] {
+        self.0.as_bytes()
+    }
+
+    /// Gets the fingerprint of the public key, stored in every escrow to it
+    pub fn key_id(&self) -> KeyId {
+        key_fingerprint(self.as_bytes())
+    }
+}
+
+impl fmt::Display for RecoveryPublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.as_bytes() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for RecoveryPublicKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let invalid = Error::MalformedMessage("invalid recovery public key");
+        if s.len() != 2 * PUBLIC_KEY_LEN || !s.is_ascii() {
+            return Err(invalid);
+        }
+        let mut bytes = [0u8; PUBLIC_KEY_LEN];
+        for (byte, pair) in bytes.iter_mut().zip(s.as_bytes().chunks(2)) {
+            let pair = std::str::from_utf8(pair).map_err(|_| invalid.clone())?;
+            *byte = u8::from_str_radix(pair, 16).map_err(|_| invalid.clone())?;
+        }
+        Ok(Self::from_bytes(bytes))
+    }
+}
+
+/// X25519 private key that opens escrows
+pub struct RecoveryKey(StaticSecret);
+
+impl fmt::Debug for RecoveryKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Keys are never printed
+        f.debug_struct("RecoveryKey")
+            .field("public_key", &self.public_key())
+            .finish()
+    }
+}
+
+impl RecoveryKey {
+    /// Generates a recovery key from the operating system's CSPRNG
+    ///
+    /// # Panics
+    /// Panics if the operating system's CSPRNG fails.
+    pub fn generate() -> Self {
+        let mut bytes = [0u8; 32];
+        SharedRng::os()
+            .fill(&mut bytes)
+            .expect("operating system CSPRNG failed");
+        Self::from_bytes(bytes)
+    }
+
+    /// Generates a recovery key from `rng`
+    pub fn generate_with_rng(rng: &mut impl CryptoRngCore) -> Self {
+        Self(StaticSecret::random_from_rng(rng))
+    }
+
+    /// Restores a recovery key from its 32 bytes
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(StaticSecret::from(bytes))
+    }
+
+    /// Gets the 32 bytes of the recovery key, for offline storage
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0.to_bytes()
+    }
+
+    /// Gets the public key to escrow to
+    pub fn public_key(&self) -> RecoveryPublicKey {
+        RecoveryPublicKey(PublicKey::from(&self.0))
+    }
+}
+
+impl Passcode {
+    /// Encrypts the algorithm and key to a recovery public key
+    ///
+    /// Output formatting options are not part of the escrow.
+    ///
+    /// # Panics
+    /// Panics if the operating system's CSPRNG fails, or if the key is held
+    /// by a [`MacBackend`](crate::MacBackend), which never reveals it.
+    pub fn export_escrowed(&self, recovery: &RecoveryPublicKey) -> String {
+        let mut bytes = [0u8; 32];
+        SharedRng::os()
+            .fill(&mut bytes)
+            .expect("operating system CSPRNG failed");
+        self.escrow_with_ephemeral(recovery, StaticSecret::from(bytes))
+    }
+
+    /// Encrypts the algorithm and key to a recovery public key like
+    /// [`export_escrowed`](Self::export_escrowed), drawing the ephemeral
+    /// key from `rng`
+    ///
+    /// # Panics
+    /// Panics if the key is held by a [`MacBackend`](crate::MacBackend).
+    pub fn export_escrowed_with_rng(
+        &self,
+        recovery: &RecoveryPublicKey,
+        rng: &mut impl CryptoRngCore,
+    ) -> String {
+        self.escrow_with_ephemeral(recovery, StaticSecret::random_from_rng(rng))
+    }
+
+    /// Restores a passcode from an escrow written by
+    /// [`export_escrowed`](Self::export_escrowed)
+    ///
+    /// Fails with [`Error::MalformedMessage`] for a damaged or unsupported
+    /// escrow and [`Error::DecryptionFailed`] if it was escrowed to another
+    /// recovery key or altered. The restored key is checked like
+    /// [`Passcode::try_new`].
+    pub fn import_escrowed(escrow: &str, recovery: &RecoveryKey) -> Result<Self, Error> {
+        let encoded = escrow
+            .trim()
+            .strip_prefix(ESCROW_PREFIX)
+            .ok_or(Error::MalformedMessage("not a passcode key escrow"))?;
+        let bytes = Base64UrlUnpadded::decode_vec(encoded)
+            .map_err(|_| Error::MalformedMessage("invalid base64url"))?;
+        if bytes.len() < HEADER_LEN + 1 + TAG_LEN {
+            return Err(Error::MalformedMessage("truncated message"));
+        }
+        let (header, ciphertext) = bytes.split_at(HEADER_LEN);
+        if header[0] != ESCROW_VERSION {
+            return Err(Error::MalformedMessage("unsupported escrow version"));
+        }
+
+        let recipient = recovery.public_key();
+        if header[1..1 + KEY_ID_LEN] != recipient.key_id().as_bytes()[..] {
+            return Err(Error::DecryptionFailed);
+        }
+        let ephemeral: [u8; PUBLIC_KEY_LEN] =
+            header[1 + KEY_ID_LEN..].try_into().expect("32 bytes");
+        let shared = recovery.0.diffie_hellman(&PublicKey::from(ephemeral));
+        if !shared.was_contributory() {
+            return Err(Error::DecryptionFailed);
+        }
+
+        let (cipher, nonce) = cipher(shared.as_bytes(), &ephemeral, &recipient);
+        let plaintext = cipher
+            .decrypt(
+                &nonce,
+                Payload {
+                    msg: ciphertext,
+                    aad: header,
+                },
+            )
+            .map_err(|_| Error::DecryptionFailed)?;
+
+        let algorithm =
+            Algorithm::from_id(plaintext[0]).ok_or(Error::MalformedMessage("unknown algorithm"))?;
+        Passcode::try_new(algorithm, plaintext[1..].to_vec())
+    }
+
+    fn escrow_with_ephemeral(
+        &self,
+        recovery: &RecoveryPublicKey,
+        ephemeral: StaticSecret,
+    ) -> String {
+        let ephemeral_public = PublicKey::from(&ephemeral);
+        let shared = ephemeral.diffie_hellman(&recovery.0);
+
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        header.push(ESCROW_VERSION);
+        header.extend_from_slice(recovery.key_id().as_bytes());
+        header.extend_from_slice(ephemeral_public.as_bytes());
+
+        let (cipher, nonce) = cipher(shared.as_bytes(), ephemeral_public.as_bytes(), recovery);
+        let mut plaintext = Vec::with_capacity(1 + self.key().len());
+        plaintext.push(self.algorithm().id());
+        plaintext.extend_from_slice(self.key());
+        let ciphertext = cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: &plaintext,
+                    aad: &header,
+                },
+            )
+            .expect("buffer is large enough");
+
+        header.extend_from_slice(&ciphertext);
+        format!(
+            "{}{}",
+            ESCROW_PREFIX,
+            Base64UrlUnpadded::encode_string(&header)
+        )
+    }
+}
+
+/// Derives the encryption key and nonce from the shared secret and both
+/// public keys
+fn cipher(
+    shared: &[u8; 32],
+    ephemeral: &[u8; PUBLIC_KEY_LEN],
+    recipient: &RecoveryPublicKey,
+) -> (XChaCha20Poly1305, XNonce) {
+    let mut context = Vec::with_capacity(2 * PUBLIC_KEY_LEN);
+    context.extend_from_slice(ephemeral);
+    context.extend_from_slice(recipient.as_bytes());
+    let okm = derive_subkey(shared, labels::ESCROW, &context, CIPHER_KEY_LEN + NONCE_LEN);
+    let (key, nonce) = okm.split_at(CIPHER_KEY_LEN);
+    (
+        XChaCha20Poly1305::new_from_slice(key).expect("32-byte key"),
+        *XNonce::from_slice(nonce),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recovery() -> RecoveryKey {
+        RecoveryKey::from_bytes([3u8; 32])
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for algorithm in Algorithm::all() {
+            let passcode = Passcode::new(algorithm, algorithm.random_key());
+            let escrow = passcode.export_escrowed(&recovery().public_key());
+            assert!(escrow.starts_with(ESCROW_PREFIX));
+
+            let recovered = Passcode::import_escrowed(&escrow, &recovery()).unwrap();
+            assert_eq!(recovered.algorithm(), algorithm);
+            assert_eq!(recovered.compute(b"data"), passcode.compute(b"data"));
+        }
+    }
+
+    #[test]
+    fn test_escrows_are_randomized() {
+        let passcode = Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+        let first = passcode.export_escrowed(&recovery().public_key());
+        let second = passcode.export_escrowed(&recovery().public_key());
+        assert_ne!(first, second);
+        assert_eq!(
+            first.len(),
+            ESCROW_PREFIX.len() + ((HEADER_LEN + 1 + 32 + TAG_LEN) * 4).div_ceil(3)
+        );
+    }
+
+    #[test]
+    fn test_rejects_wrong_key_and_tampering() {
+        let passcode = Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+        let escrow = passcode.export_escrowed(&recovery().public_key());
+
+        let other = RecoveryKey::from_bytes([4u8; 32]);
+        assert_eq!(
+            Passcode::import_escrowed(&escrow, &other).err(),
+            Some(Error::DecryptionFailed)
+        );
+
+        let mut bytes = Base64UrlUnpadded::decode_vec(&escrow[ESCROW_PREFIX.len()..]).unwrap();
+        let reencode = |bytes: &[u8]| {
+            format!(
+                "{}{}",
+                ESCROW_PREFIX,
+                Base64UrlUnpadded::encode_string(bytes)
+            )
+        };
+
+        // A forged recipient Key-ID does not get past the tag
+        let mut forged = bytes.clone();
+        forged[1..1 + KEY_ID_LEN].copy_from_slice(other.public_key().key_id().as_bytes());
+        assert_eq!(
+            Passcode::import_escrowed(&reencode(&forged), &other).err(),
+            Some(Error::DecryptionFailed)
+        );
+
+        *bytes.last_mut().unwrap() ^= 1;
+        assert_eq!(
+            Passcode::import_escrowed(&reencode(&bytes), &recovery()).err(),
+            Some(Error::DecryptionFailed)
+        );
+        assert!(Passcode::import_escrowed("passcode-escrow:AAAA", &recovery()).is_err());
+        assert!(Passcode::import_escrowed(&escrow[1..], &recovery()).is_err());
+    }
+
+    #[test]
+    fn test_rejects_low_order_ephemeral_keys() {
+        let passcode = Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+        let escrow = passcode.export_escrowed(&recovery().public_key());
+        let mut bytes = Base64UrlUnpadded::decode_vec(&escrow[ESCROW_PREFIX.len()..]).unwrap();
+        bytes[1 + KEY_ID_LEN..HEADER_LEN].fill(0);
+        let escrow = format!(
+            "{}{}",
+            ESCROW_PREFIX,
+            Base64UrlUnpadded::encode_string(&bytes)
+        );
+        assert_eq!(
+            Passcode::import_escrowed(&escrow, &recovery()).err(),
+            Some(Error::DecryptionFailed)
+        );
+    }
+
+    #[test]
+    fn test_public_key_encoding() {
+        let public = recovery().public_key();
+        let text = public.to_string();
+        assert_eq!(text.len(), 64);
+        assert_eq!(text.parse(), Ok(public));
+        assert_eq!(
+            RecoveryKey::from_bytes(recovery().to_bytes()).public_key(),
+            public
+        );
+        assert!("00".parse::<RecoveryPublicKey>().is_err());
+        assert!("zz".repeat(32).parse::<RecoveryPublicKey>().is_err());
+        assert!(format!("{:?}", recovery()).starts_with("RecoveryKey { public_key: "));
+    }
+}
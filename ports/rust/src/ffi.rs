@@ -5,24 +5,38 @@ use crate::{Algorithm, Passcode};
 
 /// Create a new Passcode instance
 /// Returns a pointer to the Passcode instance
+///
+/// # Safety
+///
+/// `key_ptr` must point to `key_len` readable bytes.
 #[no_mangle]
-pub extern "C" fn passcode_new(algorithm: u8, key_ptr: *const u8, key_len: usize) -> *mut Passcode {
+pub unsafe extern "C" fn passcode_new(algorithm: u8, key_ptr: *const u8, key_len: usize) -> *mut Passcode {
     let key = unsafe { slice::from_raw_parts(key_ptr, key_len) }.to_vec();
-    let algo = match algorithm {
-        0 => Algorithm::Sha3Kmac128,
-        1 => Algorithm::Sha3Kmac256,
-        2 => Algorithm::Blake3KeyedMode128,
-        3 => Algorithm::Blake3KeyedMode256,
-        _ => return std::ptr::null_mut(),
+    let algo = match Algorithm::from_id(algorithm) {
+        Some(algo) => algo,
+        None => return std::ptr::null_mut(),
     };
     
     Box::into_raw(Box::new(Passcode::new(algo, key)))
 }
 
+/// Get the recommended key length in bytes for an algorithm
+/// Returns 0 for an unknown algorithm
+#[no_mangle]
+pub extern "C" fn passcode_recommended_key_len(algorithm: u8) -> usize {
+    Algorithm::from_id(algorithm).map_or(0, |algo| algo.recommended_key_len())
+}
+
 /// Compute OTP from challenge data
 /// Returns a pointer to a null-terminated string (caller must free)
+///
+/// # Safety
+///
+/// `passcode_ptr` must come from [`passcode_new`] and not be freed yet,
+/// `data_ptr` must point to `data_len` readable bytes and `out_ptr` to
+/// `out_len` writable bytes.
 #[no_mangle]
-pub extern "C" fn passcode_compute(
+pub unsafe extern "C" fn passcode_compute(
     passcode_ptr: *mut Passcode,
     data_ptr: *const u8,
     data_len: usize,
@@ -56,8 +70,13 @@ pub extern "C" fn passcode_compute(
 }
 
 /// Free a Passcode instance
+///
+/// # Safety
+///
+/// `passcode_ptr` must be null or come from [`passcode_new`], and must not
+/// be used again afterwards.
 #[no_mangle]
-pub extern "C" fn passcode_free(passcode_ptr: *mut Passcode) {
+pub unsafe extern "C" fn passcode_free(passcode_ptr: *mut Passcode) {
     if !passcode_ptr.is_null() {
         unsafe {
             let _ = Box::from_raw(passcode_ptr);
@@ -68,5 +87,5 @@ pub extern "C" fn passcode_free(passcode_ptr: *mut Passcode) {
 /// Get the last error message
 #[no_mangle]
 pub extern "C" fn passcode_get_error() -> *const u8 {
-    b"No error\0".as_ptr()
+    c"No error".as_ptr().cast()
 }
@@ -0,0 +1,990 @@
+//! Output encodings for OTP codes
+
+use crate::wordlist::WORDLIST;
+use crate::Error;
+use subtle::ConstantTimeEq;
+
+/// Supported number of decimal digits
+const DECIMAL_DIGITS: std::ops::RangeInclusive<u8> = 6..=10;
+
+/// Supported number of words
+const WORD_COUNTS: std::ops::RangeInclusive<u8> = 2..=8;
+
+/// Number of words in [`WORDLIST`], the base of each word's digit
+const WORD_BASE: u128 = 1296;
+
+/// RFC 4648 base32 alphabet
+pub(crate) const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Crockford base32 alphabet (no `I`, `L`, `O` or `U`)
+pub(crate) const CROCKFORD_ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// Crockford check symbols for values 32 to 36
+const CROCKFORD_CHECK_SYMBOLS: &[u8; 5] = b"*~$=U";
+
+/// Bitcoin base58 alphabet (no `0`, `O`, `I` or `l`)
+const BASE58_ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// Domain-separation context for the custom alphabet sampler
+const ALPHABET_CONTEXT: &str = "passcode/v1/alphabet";
+
+/// Damm quasigroup table (weakly totally anti-symmetric, order 10)
+const DAMM_TABLE: [[u8; 10]; 10] = [
+    [0, 3, 1, 7, 5, 9, 8, 6, 4, 2],
+    [7, 0, 9, 2, 1, 5, 4, 8, 6, 3],
+    [4, 2, 0, 6, 8, 7, 1, 3, 5, 9],
+    [1, 7, 5, 0, 9, 8, 3, 4, 2, 6],
+    [6, 1, 2, 3, 0, 4, 5, 9, 7, 8],
+    [3, 6, 7, 4, 2, 0, 9, 5, 8, 1],
+    [5, 8, 6, 9, 7, 2, 0, 1, 3, 4],
+    [8, 9, 4, 5, 3, 6, 2, 0, 1, 7],
+    [9, 4, 3, 8, 6, 1, 7, 2, 0, 5],
+    [2, 5, 8, 1, 4, 3, 6, 7, 9, 0],
+];
+
+/// How the truncated MAC is presented to the user
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum OtpFormat {
+    /// Lowercase hexadecimal of the leading MAC bytes (12 characters for the
+    /// 128/256-bit tiers)
+    #[default]
+    Hex,
+    /// Uppercase hexadecimal, for terminals and channels that display
+    /// uppercase only
+    HexUpper,
+    /// Decimal code with the given number of digits (6 to 10)
+    ///
+    /// The first 8 MAC bytes are read as a big-endian integer and reduced
+    /// modulo `10^digits`. The reduction bias is below `10^10 / 2^64`, i.e.
+    /// under one part in a billion, so codes are uniformly distributed for
+    /// all practical purposes.
+    Decimal(u8),
+    /// Decimal code of `digits` digits followed by one check digit
+    ///
+    /// The check digit is computed from the code itself, so it adds no
+    /// security: a 6-digit code with a check digit is still a 6-digit code.
+    /// Clients can use [`CheckDigit::is_valid`] to reject typos before
+    /// sending the code to the server.
+    CheckedDecimal {
+        /// Number of code digits, excluding the check digit (6 to 10)
+        digits: u8,
+        /// Check digit scheme
+        check: CheckDigit,
+    },
+    /// RFC 4648 base32 of the leading MAC bytes, uppercase and unpadded
+    /// (10 characters for the 128/256-bit tiers)
+    ///
+    /// Verification is case-insensitive, so codes survive channels such as
+    /// DNS labels that do not preserve case.
+    Base32,
+    /// Bitcoin-alphabet base58 of the leading MAC bytes
+    ///
+    /// The alphabet omits the ambiguous characters `0`, `O`, `I` and `l`.
+    /// Codes are variable length (up to 9 characters for the 128/256-bit
+    /// tiers); verification decodes the input back to bytes and compares
+    /// those with the expected MAC prefix.
+    Base58,
+    /// Crockford base32 of the leading MAC bytes, for codes typed by hand
+    ///
+    /// Output is uppercase without `I`, `L`, `O` or `U`. With `check` set, a
+    /// mod-37 check symbol is appended so typos are caught before the code
+    /// is sent. Verification is case-insensitive, ignores hyphens and maps
+    /// commonly confused characters (`O` to `0`, `I` and `L` to `1`).
+    Crockford {
+        /// Append a Crockford check symbol
+        check: bool,
+    },
+    /// Hyphen-separated words from the EFF short wordlist, e.g.
+    /// `"lunar-tiger-oasis"`, for codes relayed verbally (2 to 8 words)
+    ///
+    /// Each word is a base-1296 digit of the leading 16 MAC bytes, about
+    /// 10.3 bits, so 8 words carry 82 bits. The words have at most five
+    /// letters and were chosen to be easy to spell; verification accepts
+    /// any separator and ignores case.
+    Words(u8),
+    /// Code over a caller-supplied alphabet
+    ///
+    /// The code is long enough to carry at least as many bits as the hex
+    /// format (e.g. 15 characters for a 10-symbol alphabet at the 128/256-bit
+    /// tiers). Characters are drawn without modulo bias by rejection
+    /// sampling from a BLAKE3 stream keyed by the MAC. Verification is
+    /// exact apart from surrounding whitespace.
+    Custom(Alphabet),
+    /// Bech32m token of the leading MAC bytes with a human-readable prefix
+    /// (feature `bech32`), e.g. `otp1...`
+    ///
+    /// The BIP-350 checksum detects up to four character errors, so tokens
+    /// relayed through error-prone channels are rejected as malformed
+    /// rather than compared. Verification requires a valid checksum and a
+    /// matching prefix, and ignores case.
+    #[cfg(feature = "bech32")]
+    Bech32m {
+        /// Human-readable prefix (1 to 83 printable ASCII characters)
+        hrp: String,
+    },
+}
+
+/// Letter case of the canonical form of a case-insensitive format
+pub(crate) enum LetterCase {
+    Lower,
+    Upper,
+}
+
+impl OtpFormat {
+    /// Checks that the format parameters are supported
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        match self {
+            OtpFormat::Hex
+            | OtpFormat::HexUpper
+            | OtpFormat::Base32
+            | OtpFormat::Base58
+            | OtpFormat::Crockford { .. }
+            | OtpFormat::Custom(_) => Ok(()),
+            OtpFormat::Decimal(digits) | OtpFormat::CheckedDecimal { digits, .. } => {
+                if DECIMAL_DIGITS.contains(digits) {
+                    Ok(())
+                } else {
+                    Err(Error::InvalidDigits(u32::from(*digits)))
+                }
+            }
+            OtpFormat::Words(count) => {
+                if WORD_COUNTS.contains(count) {
+                    Ok(())
+                } else {
+                    Err(Error::InvalidFormat("word count must be between 2 and 8"))
+                }
+            }
+            #[cfg(feature = "bech32")]
+            OtpFormat::Bech32m { hrp } => bech32::Hrp::parse(hrp)
+                .map(|_| ())
+                .map_err(|_| Error::InvalidFormat("invalid Bech32 human-readable prefix")),
+        }
+    }
+
+    /// Encodes the MAC, keeping `otp_bytes` bytes for byte-oriented formats
+    pub(crate) fn encode(&self, mac: &[u8], otp_bytes: usize) -> String {
+        match self {
+            OtpFormat::Hex => hex::encode(&mac[..otp_bytes]),
+            OtpFormat::HexUpper => hex::encode_upper(&mac[..otp_bytes]),
+            OtpFormat::Decimal(digits) => decimal_encode(mac, *digits),
+            OtpFormat::CheckedDecimal { digits, check } => {
+                let mut code = decimal_encode(mac, *digits);
+                code.push(check.compute(&code));
+                code
+            }
+            OtpFormat::Base32 => base32_encode(&mac[..otp_bytes], BASE32_ALPHABET),
+            OtpFormat::Base58 => base58_encode(&mac[..otp_bytes]),
+            OtpFormat::Crockford { check } => {
+                let mut code = base32_encode(&mac[..otp_bytes], CROCKFORD_ALPHABET);
+                if *check {
+                    code.push(crockford_check_symbol(&code));
+                }
+                code
+            }
+            OtpFormat::Words(count) => word_indices(mac, *count)
+                .map(|index| WORDLIST[usize::from(index)])
+                .collect::<Vec<_>>()
+                .join("-"),
+            OtpFormat::Custom(alphabet) => alphabet.encode(mac, otp_bytes),
+            #[cfg(feature = "bech32")]
+            OtpFormat::Bech32m { hrp } => {
+                let hrp = bech32::Hrp::parse(hrp).expect("prefix validated on build");
+                bech32::encode::<bech32::Bech32m>(hrp, &mac[..otp_bytes])
+                    .expect("token is within the Bech32 length limit")
+            }
+        }
+    }
+
+    /// Returns the case of the canonical form, if the format ignores case
+    pub(crate) fn letter_case(&self) -> Option<LetterCase> {
+        match self {
+            OtpFormat::Hex | OtpFormat::Words(_) => Some(LetterCase::Lower),
+            OtpFormat::HexUpper | OtpFormat::Base32 | OtpFormat::Crockford { .. } => {
+                Some(LetterCase::Upper)
+            }
+            #[cfg(feature = "bech32")]
+            OtpFormat::Bech32m { .. } => Some(LetterCase::Lower),
+            _ => None,
+        }
+    }
+
+    /// Checks submitted input against the MAC in constant time
+    pub(crate) fn verify(&self, mac: &[u8], otp_bytes: usize, input: &str) -> bool {
+        let submitted = self.normalize(input);
+        match self {
+            OtpFormat::Base58 => match base58_decode(&submitted) {
+                Some(decoded) => decoded.ct_eq(&mac[..otp_bytes]).into(),
+                None => false,
+            },
+            OtpFormat::CheckedDecimal { check, .. } if !check.is_valid(&submitted) => false,
+            #[cfg(feature = "bech32")]
+            OtpFormat::Bech32m { hrp } => {
+                use bech32::primitives::decode::CheckedHrpstring;
+
+                match CheckedHrpstring::new::<bech32::Bech32m>(&submitted) {
+                    Ok(token) if token.hrp().to_lowercase() == hrp.to_ascii_lowercase() => {
+                        let decoded: Vec<u8> = token.byte_iter().collect();
+                        decoded.ct_eq(&mac[..otp_bytes]).into()
+                    }
+                    _ => false,
+                }
+            }
+            OtpFormat::Words(count) => {
+                let expected: Vec<u8> = word_indices(mac, *count)
+                    .flat_map(u16::to_be_bytes)
+                    .collect();
+                let submitted: Option<Vec<u8>> = parse_words(&submitted)
+                    .map(|indices| indices.into_iter().flat_map(u16::to_be_bytes).collect());
+                match submitted {
+                    Some(submitted) => expected.ct_eq(&submitted).into(),
+                    None => false,
+                }
+            }
+            _ => {
+                let expected = self.encode(mac, otp_bytes);
+                expected.as_bytes().ct_eq(submitted.as_bytes()).into()
+            }
+        }
+    }
+
+    /// Applies the decoding rules inherent to the format
+    ///
+    /// Configurable clean-up such as trimming or case folding is done
+    /// beforehand by [`Canonicalization`](crate::Canonicalization).
+    pub(crate) fn normalize(&self, input: &str) -> String {
+        match self {
+            OtpFormat::Hex
+            | OtpFormat::HexUpper
+            | OtpFormat::Decimal(_)
+            | OtpFormat::CheckedDecimal { .. }
+            | OtpFormat::Base32
+            | OtpFormat::Base58
+            | OtpFormat::Custom(_) => input.to_string(),
+            #[cfg(feature = "bech32")]
+            OtpFormat::Bech32m { .. } => input.to_string(),
+            // Crockford decoding is case-insensitive and ignores hyphens
+            OtpFormat::Crockford { .. } => input
+                .chars()
+                .filter(|&c| c != '-')
+                .map(|c| match c.to_ascii_uppercase() {
+                    'O' => '0',
+                    'I' | 'L' => '1',
+                    c => c,
+                })
+                .collect(),
+            OtpFormat::Words(_) => input
+                .split(|c: char| !c.is_ascii_alphabetic())
+                .filter(|word| !word.is_empty())
+                .map(str::to_ascii_lowercase)
+                .collect::<Vec<_>>()
+                .join("-"),
+        }
+    }
+
+    /// Returns the minimum number of MAC bytes this format reads
+    pub(crate) fn mac_bytes(&self, otp_bytes: usize) -> usize {
+        match self {
+            OtpFormat::Hex
+            | OtpFormat::HexUpper
+            | OtpFormat::Base32
+            | OtpFormat::Base58
+            | OtpFormat::Crockford { .. } => otp_bytes,
+            OtpFormat::Decimal(_) | OtpFormat::CheckedDecimal { .. } => 8,
+            OtpFormat::Words(_) => 16,
+            OtpFormat::Custom(_) => 32,
+            #[cfg(feature = "bech32")]
+            OtpFormat::Bech32m { .. } => otp_bytes,
+        }
+    }
+}
+
+/// A caller-supplied set of code characters for [`OtpFormat::Custom`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alphabet {
+    symbols: Vec<char>,
+}
+
+impl Alphabet {
+    /// Creates an alphabet from its characters, in order
+    ///
+    /// The alphabet must have between 2 and 256 distinct characters and
+    /// must not contain whitespace.
+    ///
+    /// # Example
+    /// ```
+    /// use passcode::{Algorithm, Alphabet, OtpFormat, Passcode};
+    ///
+    /// // Digits and consonants only, avoiding accidental words
+    /// let alphabet = Alphabet::new("23456789BCDFGHJKMNPQRSTVWXZ").unwrap();
+    /// let passcode = Passcode::builder(Algorithm::Sha3Kmac256, vec![0u8; 32])
+    ///     .format(OtpFormat::Custom(alphabet))
+    ///     .build()
+    ///     .unwrap();
+    /// assert_eq!(passcode.compute(b"challenge").chars().count(), 11);
+    /// ```
+    pub fn new(symbols: &str) -> Result<Self, Error> {
+        let symbols: Vec<char> = symbols.chars().collect();
+
+        if !(2..=256).contains(&symbols.len()) {
+            return Err(Error::InvalidFormat(
+                "alphabet must have between 2 and 256 characters",
+            ));
+        }
+        if symbols.iter().any(|c| c.is_whitespace()) {
+            return Err(Error::InvalidFormat("alphabet must not contain whitespace"));
+        }
+        let mut sorted = symbols.clone();
+        sorted.sort_unstable();
+        sorted.dedup();
+        if sorted.len() != symbols.len() {
+            return Err(Error::InvalidFormat("alphabet characters must be unique"));
+        }
+
+        Ok(Self { symbols })
+    }
+
+    /// Gets the alphabet characters
+    pub fn symbols(&self) -> &[char] {
+        &self.symbols
+    }
+
+    /// Returns the code length needed to carry `bits` bits
+    pub fn code_len(&self, bits: usize) -> usize {
+        let bits_per_symbol = (self.symbols.len() as f64).log2();
+        (bits as f64 / bits_per_symbol).ceil() as usize
+    }
+
+    /// Draws an unbiased code from a BLAKE3 stream keyed by the MAC
+    fn encode(&self, mac: &[u8], otp_bytes: usize) -> String {
+        let mut hasher = blake3::Hasher::new_derive_key(ALPHABET_CONTEXT);
+        hasher.update(&mac[..32]);
+        let mut stream = hasher.finalize_xof();
+
+        let n = self.symbols.len();
+        // Largest multiple of n that fits in a byte; larger bytes are rejected
+        let limit = 256 - 256 % n;
+        let mut byte = [0u8; 1];
+
+        (0..self.code_len(otp_bytes * 8))
+            .map(|_| loop {
+                stream.fill(&mut byte);
+                if usize::from(byte[0]) < limit {
+                    break self.symbols[usize::from(byte[0]) % n];
+                }
+            })
+            .collect()
+    }
+}
+
+/// Check digit schemes for decimal codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckDigit {
+    /// Luhn (mod 10) algorithm, as used on payment cards
+    Luhn,
+    /// Damm algorithm, which also detects all adjacent transpositions
+    Damm,
+}
+
+impl CheckDigit {
+    /// Computes the check digit for a string of decimal digits
+    ///
+    /// # Panics
+    /// Panics if `payload` contains a character that is not an ASCII digit.
+    pub fn compute(&self, payload: &str) -> char {
+        let digits = payload.bytes().map(|c| {
+            assert!(c.is_ascii_digit(), "payload must be decimal digits");
+            c - b'0'
+        });
+
+        let check = match self {
+            CheckDigit::Luhn => {
+                // Double every second digit, starting with the rightmost
+                let sum: u32 = digits
+                    .rev()
+                    .enumerate()
+                    .map(|(i, d)| {
+                        let d = u32::from(d);
+                        if i % 2 == 0 {
+                            let doubled = d * 2;
+                            if doubled > 9 {
+                                doubled - 9
+                            } else {
+                                doubled
+                            }
+                        } else {
+                            d
+                        }
+                    })
+                    .sum();
+                ((10 - sum % 10) % 10) as u8
+            }
+            CheckDigit::Damm => digits.fold(0, |interim, d| {
+                DAMM_TABLE[usize::from(interim)][usize::from(d)]
+            }),
+        };
+
+        char::from(b'0' + check)
+    }
+
+    /// Checks that the last character of `code` is its valid check digit
+    ///
+    /// # Example
+    /// ```
+    /// use passcode::CheckDigit;
+    ///
+    /// assert!(CheckDigit::Luhn.is_valid("79927398713"));
+    /// assert!(!CheckDigit::Luhn.is_valid("79927398731"));
+    /// ```
+    pub fn is_valid(&self, code: &str) -> bool {
+        if code.len() < 2 || !code.bytes().all(|c| c.is_ascii_digit()) {
+            return false;
+        }
+        let (payload, check) = code.split_at(code.len() - 1);
+        check.starts_with(self.compute(payload))
+    }
+}
+
+/// Display grouping applied to encoded codes, e.g. `abc-def` or `123 456`
+///
+/// Grouping only affects presentation: verification strips the separator
+/// and whitespace before decoding, so users may enter the code with or
+/// without it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Grouping {
+    /// Number of characters per group
+    pub size: usize,
+    /// Character inserted between groups
+    pub separator: char,
+}
+
+impl Grouping {
+    /// Creates a grouping of `size` characters joined by `separator`
+    pub fn new(size: usize, separator: char) -> Self {
+        Self { size, separator }
+    }
+
+    /// Checks that the grouping can be applied to codes of the given format
+    pub(crate) fn validate(&self, format: &OtpFormat) -> Result<(), Error> {
+        if self.size == 0 {
+            return Err(Error::InvalidFormat("group size must be greater than zero"));
+        }
+        if self.separator.is_alphanumeric() {
+            return Err(Error::InvalidFormat(
+                "group separator must not be alphanumeric",
+            ));
+        }
+        if matches!(format, OtpFormat::Words(_)) {
+            return Err(Error::InvalidFormat(
+                "word codes cannot be grouped by characters",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Inserts the separator between groups of the code
+    pub(crate) fn apply(&self, code: &str) -> String {
+        let chars: Vec<char> = code.chars().collect();
+        chars
+            .chunks(self.size)
+            .map(|group| group.iter().collect::<String>())
+            .collect::<Vec<_>>()
+            .join(&self.separator.to_string())
+    }
+
+    /// Removes the separator and whitespace from user input
+    pub(crate) fn strip(&self, input: &str) -> String {
+        input
+            .chars()
+            .filter(|&c| c != self.separator && !c.is_whitespace())
+            .collect()
+    }
+}
+
+/// Reduces the first 8 MAC bytes to a zero-padded decimal code
+fn decimal_encode(mac: &[u8], digits: u8) -> String {
+    let value = leading_u64(mac) % 10u64.pow(u32::from(digits));
+    format!("{:0width$}", value, width = usize::from(digits))
+}
+
+/// Reads the first 8 bytes as a big-endian integer
+fn leading_u64(mac: &[u8]) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&mac[..8]);
+    u64::from_be_bytes(bytes)
+}
+
+/// Splits the first 16 MAC bytes into `count` base-1296 wordlist indices,
+/// least significant digit first
+///
+/// 2^128 is not a multiple of 1296^count, which biases the digits by less
+/// than 2^-45 for 8 words.
+fn word_indices(mac: &[u8], count: u8) -> impl Iterator<Item = u16> {
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&mac[..16]);
+    let value = u128::from_be_bytes(bytes);
+
+    (0..u32::from(count)).map(move |i| (value / WORD_BASE.pow(i) % WORD_BASE) as u16)
+}
+
+/// Looks up a lowercase word
+fn word_index(word: &str) -> Option<u16> {
+    WORDLIST
+        .iter()
+        .position(|&w| w == word)
+        .map(|index| index as u16)
+}
+
+/// Parses hyphen-separated words into wordlist indices
+///
+/// Normalization splits `yo-yo` in two, so a part that is not a word is
+/// looked up again joined with the next one.
+fn parse_words(input: &str) -> Option<Vec<u16>> {
+    let mut parts = input.split('-');
+    let mut indices = Vec::new();
+
+    while let Some(part) = parts.next() {
+        let index = match word_index(part) {
+            Some(index) => index,
+            None => word_index(&format!("{}-{}", part, parts.next()?))?,
+        };
+        indices.push(index);
+    }
+    Some(indices)
+}
+
+/// Encodes bytes as unpadded base32 using the given alphabet
+pub(crate) fn base32_encode(data: &[u8], alphabet: &[u8; 32]) -> String {
+    let mut out = String::with_capacity((data.len() * 8).div_ceil(5));
+    let mut buffer = 0u16;
+    let mut bits = 0;
+
+    for &byte in data {
+        buffer = (buffer << 8) | u16::from(byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(alphabet[usize::from((buffer >> bits) & 0x1f)] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(alphabet[usize::from((buffer << (5 - bits)) & 0x1f)] as char);
+    }
+
+    out
+}
+
+/// Decodes base32 in the given alphabet, ignoring trailing `=` padding
+///
+/// Returns `None` on characters outside the alphabet.
+pub(crate) fn base32_decode(input: &str, alphabet: &[u8; 32]) -> Option<Vec<u8>> {
+    let input = input.trim_end_matches('=');
+    let mut out = Vec::with_capacity(input.len() * 5 / 8);
+    let mut buffer = 0u16;
+    let mut bits = 0;
+
+    for c in input.bytes() {
+        let value = alphabet.iter().position(|&a| a == c)? as u16;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+
+    Some(out)
+}
+
+/// Computes the Crockford mod-37 check symbol of an encoded value
+fn crockford_check_symbol(code: &str) -> char {
+    let value = code.bytes().fold(0u32, |acc, c| {
+        let digit = CROCKFORD_ALPHABET
+            .iter()
+            .position(|&a| a == c)
+            .expect("code uses the Crockford alphabet") as u32;
+        (acc * 32 + digit) % 37
+    });
+    let value = value as usize;
+
+    if value < 32 {
+        CROCKFORD_ALPHABET[value] as char
+    } else {
+        CROCKFORD_CHECK_SYMBOLS[value - 32] as char
+    }
+}
+
+/// Encodes bytes as base58, preserving leading zero bytes as `1`
+fn base58_encode(data: &[u8]) -> String {
+    let zeros = data.iter().take_while(|&&b| b == 0).count();
+
+    // Little-endian base58 digits of the big-endian input
+    let mut digits: Vec<u8> = Vec::with_capacity(data.len() * 138 / 100 + 1);
+    for &byte in &data[zeros..] {
+        let mut carry = u32::from(byte);
+        for digit in digits.iter_mut() {
+            carry += u32::from(*digit) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+
+    let mut out = String::with_capacity(zeros + digits.len());
+    out.extend(std::iter::repeat_n('1', zeros));
+    out.extend(
+        digits
+            .iter()
+            .rev()
+            .map(|&d| BASE58_ALPHABET[usize::from(d)] as char),
+    );
+    out
+}
+
+/// Decodes base58, returning `None` on characters outside the alphabet
+fn base58_decode(input: &str) -> Option<Vec<u8>> {
+    let zeros = input.bytes().take_while(|&c| c == b'1').count();
+
+    // Little-endian bytes of the decoded value
+    let mut bytes: Vec<u8> = Vec::with_capacity(input.len());
+    for c in input.bytes().skip(zeros) {
+        let mut carry = BASE58_ALPHABET.iter().position(|&a| a == c)? as u32;
+        for byte in bytes.iter_mut() {
+            carry += u32::from(*byte) * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push(carry as u8);
+            carry >>= 8;
+        }
+    }
+
+    let mut out = vec![0u8; zeros];
+    out.extend(bytes.iter().rev());
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::Canonicalization;
+
+    #[test]
+    fn test_decimal_encoding() {
+        let mac = [0xffu8; 32];
+        assert_eq!(OtpFormat::Decimal(6).encode(&mac, 6), "551615");
+        assert_eq!(OtpFormat::Decimal(8).encode(&mac, 6), "09551615");
+
+        let zero = [0u8; 32];
+        assert_eq!(OtpFormat::Decimal(6).encode(&zero, 6), "000000");
+    }
+
+    #[test]
+    fn test_hex_case() {
+        let mac = [0xab, 0xcd, 0xef, 0x01, 0x23, 0x45];
+        assert_eq!(OtpFormat::Hex.encode(&mac, 6), "abcdef012345");
+        assert_eq!(OtpFormat::HexUpper.encode(&mac, 6), "ABCDEF012345");
+
+        for format in [OtpFormat::Hex, OtpFormat::HexUpper] {
+            let rules = Canonicalization::for_format(&format);
+            for input in ["abcdef012345", "ABCDEF012345", "AbCdEf012345"] {
+                assert!(format.verify(&mac, 6, &rules.apply(input, &format)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_base32_rfc4648_vectors() {
+        // RFC 4648 section 10, without padding
+        let vectors = [
+            ("", ""),
+            ("f", "MY"),
+            ("fo", "MZXQ"),
+            ("foo", "MZXW6"),
+            ("foob", "MZXW6YQ"),
+            ("fooba", "MZXW6YTB"),
+            ("foobar", "MZXW6YTBOI"),
+        ];
+        for (input, expected) in vectors {
+            assert_eq!(base32_encode(input.as_bytes(), BASE32_ALPHABET), expected);
+        }
+
+        for (input, expected) in vectors {
+            let decoded = base32_decode(expected, BASE32_ALPHABET).unwrap();
+            assert_eq!(decoded, input.as_bytes());
+        }
+        assert_eq!(base32_decode("MZXW6===", BASE32_ALPHABET).unwrap(), b"foo");
+        assert_eq!(base32_decode("MZXW1", BASE32_ALPHABET), None);
+
+        assert_eq!(OtpFormat::Base32.encode(b"foobar", 6), "MZXW6YTBOI");
+        let rules = Canonicalization::for_format(&OtpFormat::Base32);
+        assert_eq!(
+            rules.apply(" mzxw6ytboi\n", &OtpFormat::Base32),
+            "MZXW6YTBOI"
+        );
+    }
+
+    #[test]
+    fn test_base58_round_trip() {
+        let vectors: [(&[u8], &str); 4] = [
+            (b"Hello World!", "2NEpo7TZRRrLZSi2U"),
+            (&[0, 0, 0, 0, 0x28, 0x7f, 0xb4, 0xcd], "1111233QC4"),
+            (&[0; 6], "111111"),
+            (&[0xff; 6], "3CUsUpv9t"),
+        ];
+        for (input, expected) in vectors {
+            assert_eq!(base58_encode(input), expected);
+            assert_eq!(base58_decode(expected).as_deref(), Some(input));
+        }
+
+        assert_eq!(base58_decode("0OIl"), None);
+        assert!(OtpFormat::Base58.verify(&[0xff; 6], 6, "3CUsUpv9t"));
+        assert!(!OtpFormat::Base58.verify(&[0xff; 6], 6, "13CUsUpv9t"));
+    }
+
+    #[test]
+    fn test_crockford_encoding() {
+        let plain = OtpFormat::Crockford { check: false };
+        let checked = OtpFormat::Crockford { check: true };
+
+        // 0x0000000004d2 = 1234, left-aligned in 50 bits
+        let mac = [0, 0, 0, 0, 0x04, 0xd2];
+        assert_eq!(plain.encode(&mac, 6), "00000004T8");
+        // 1234 << 2 = 4936, and 4936 mod 37 = 15 -> "F"
+        assert_eq!(checked.encode(&mac, 6), "00000004T8F");
+
+        assert_eq!(plain.normalize("ooooo-oo4t8"), "00000004T8");
+        assert_eq!(plain.normalize("iLl"), "111");
+        assert!(checked.verify(&mac, 6, "ooooo-oo4t8f"));
+        assert!(!checked.verify(&mac, 6, "00000004T80"));
+    }
+
+    #[test]
+    fn test_crockford_check_symbols() {
+        assert_eq!(crockford_check_symbol("0"), '0');
+        assert_eq!(crockford_check_symbol("Z"), 'Z');
+        assert_eq!(crockford_check_symbol("10"), '*');
+        assert_eq!(crockford_check_symbol("14"), 'U');
+        assert_eq!(crockford_check_symbol("15"), '0');
+    }
+
+    #[test]
+    fn test_words_encoding() {
+        let format = OtpFormat::Words(3);
+
+        // Digits 0 -> "acid", 1 -> "acorn", 1295 -> "zoom"
+        let value: u128 = 1296 + 1295 * 1296 * 1296;
+        let mac = value.to_be_bytes();
+        assert_eq!(format.encode(&mac, 6), "acid-acorn-zoom");
+
+        assert!(format.verify(&mac, 6, "acid-acorn-zoom"));
+        assert!(format.verify(&mac, 6, " Acid Acorn ZOOM "));
+        assert!(!format.verify(&mac, 6, "acid-acorn"));
+        assert!(!format.verify(&mac, 6, "acid-acorn-zoom-zoom"));
+        assert!(!format.verify(&mac, 6, "acid-acorn-zzz"));
+        assert!(!format.verify(&mac, 6, "aci-acorn-zoom"));
+    }
+
+    #[test]
+    fn test_hyphenated_word() {
+        let format = OtpFormat::Words(2);
+        let yo_yo = WORDLIST.iter().position(|&w| w == "yo-yo").unwrap() as u128;
+        let yoyo = WORDLIST.iter().position(|&w| w == "yoyo").unwrap() as u128;
+
+        let mac = (yo_yo + yoyo * 1296).to_be_bytes();
+        assert_eq!(format.encode(&mac, 6), "yo-yo-yoyo");
+        assert!(format.verify(&mac, 6, "yo-yo-yoyo"));
+        assert!(format.verify(&mac, 6, "Yo Yo Yoyo"));
+        assert!(!format.verify(&mac, 6, "yoyo-yo-yo"));
+        assert!(!format.verify(&mac, 6, "yo-yo-yo"));
+    }
+
+    #[test]
+    fn test_wordlist_is_sorted_and_unique() {
+        assert!(WORDLIST.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(WORDLIST.iter().all(|word| word.len() <= 5));
+    }
+
+    #[test]
+    fn test_word_count_range() {
+        assert!(OtpFormat::Words(2).validate().is_ok());
+        assert!(OtpFormat::Words(8).validate().is_ok());
+        assert!(matches!(
+            OtpFormat::Words(1).validate(),
+            Err(Error::InvalidFormat(_))
+        ));
+        assert!(matches!(
+            OtpFormat::Words(9).validate(),
+            Err(Error::InvalidFormat(_))
+        ));
+    }
+
+    #[test]
+    fn test_grouping() {
+        let dashes = Grouping::new(3, '-');
+        assert_eq!(dashes.apply("abcdefghijkl"), "abc-def-ghi-jkl");
+        assert_eq!(dashes.apply("abcdefgh"), "abc-def-gh");
+        assert_eq!(dashes.strip(" abc-def -ghi-jkl "), "abcdefghijkl");
+
+        let spaces = Grouping::new(3, ' ');
+        assert_eq!(spaces.apply("123456"), "123 456");
+        assert_eq!(spaces.strip("123 456"), "123456");
+
+        assert!(dashes.validate(&OtpFormat::Hex).is_ok());
+        assert!(Grouping::new(0, '-').validate(&OtpFormat::Hex).is_err());
+        assert!(Grouping::new(3, 'x').validate(&OtpFormat::Hex).is_err());
+        assert!(dashes.validate(&OtpFormat::Words(3)).is_err());
+    }
+
+    #[test]
+    fn test_luhn_check_digit() {
+        assert_eq!(CheckDigit::Luhn.compute("7992739871"), '3');
+        assert_eq!(CheckDigit::Luhn.compute("0"), '0');
+        assert!(CheckDigit::Luhn.is_valid("79927398713"));
+        assert!(!CheckDigit::Luhn.is_valid("79927398710"));
+        assert!(!CheckDigit::Luhn.is_valid("7"));
+        assert!(!CheckDigit::Luhn.is_valid("7992739871a"));
+    }
+
+    #[test]
+    fn test_damm_check_digit() {
+        assert_eq!(CheckDigit::Damm.compute("572"), '4');
+        assert!(CheckDigit::Damm.is_valid("5724"));
+        // Damm detects the adjacent transposition that Luhn misses (09 <-> 90)
+        assert!(CheckDigit::Luhn.is_valid("0901") && CheckDigit::Luhn.is_valid("9001"));
+        let damm = CheckDigit::Damm.compute("09");
+        assert!(!CheckDigit::Damm.is_valid(&format!("90{}", damm)));
+    }
+
+    #[test]
+    fn test_checked_decimal_format() {
+        let format = OtpFormat::CheckedDecimal {
+            digits: 6,
+            check: CheckDigit::Damm,
+        };
+        let mac = [0xffu8; 32];
+        assert_eq!(
+            format.encode(&mac, 6),
+            format!("551615{}", CheckDigit::Damm.compute("551615"))
+        );
+        assert!(format.verify(&mac, 6, &format.encode(&mac, 6)));
+        assert!(!format.verify(&mac, 6, "551615"));
+        assert!(!format.verify(&mac, 6, "5516150"));
+    }
+
+    #[test]
+    fn test_alphabet_validation() {
+        assert!(Alphabet::new("01").is_ok());
+        assert!(Alphabet::new("가나다라마바사아자차카타파하").is_ok());
+        assert!(Alphabet::new("0").is_err());
+        assert!(Alphabet::new("0120").is_err());
+        assert!(Alphabet::new("01 2").is_err());
+        assert!(Alphabet::new(&"x".repeat(257)).is_err());
+    }
+
+    #[test]
+    fn test_custom_alphabet_encoding() {
+        let alphabet = Alphabet::new("0123456789").unwrap();
+        assert_eq!(alphabet.code_len(48), 15);
+        assert_eq!(Alphabet::new("01").unwrap().code_len(48), 48);
+
+        let format = OtpFormat::Custom(alphabet);
+        let mac = [7u8; 32];
+        let code = format.encode(&mac, 6);
+        assert_eq!(code.len(), 15);
+        assert!(code.chars().all(|c| c.is_ascii_digit()));
+        assert_eq!(code, format.encode(&mac, 6));
+        let padded = Canonicalization::for_format(&format).apply(&format!(" {} ", code), &format);
+        assert!(format.verify(&mac, 6, &padded));
+        assert!(!format.verify(&mac, 6, &code[1..]));
+
+        let hangul = Alphabet::new("가나다라마바사아자차카타파하").unwrap();
+        let code = OtpFormat::Custom(hangul.clone()).encode(&mac, 6);
+        assert!(code.chars().all(|c| hangul.symbols().contains(&c)));
+    }
+
+    #[test]
+    fn test_custom_alphabet_is_unbiased() {
+        // 3 does not divide 256, so plain reduction would favour one symbol
+        let format = OtpFormat::Custom(Alphabet::new("abc").unwrap());
+        let mut counts = [0usize; 3];
+        for i in 0..200u8 {
+            let code = format.encode(&[i; 32], 6);
+            for c in code.chars() {
+                counts[(c as u8 - b'a') as usize] += 1;
+            }
+        }
+        let total: usize = counts.iter().sum();
+        for count in counts {
+            assert!(count * 3 > total * 9 / 10 && count * 3 < total * 11 / 10);
+        }
+    }
+
+    #[cfg(feature = "bech32")]
+    #[test]
+    fn test_bech32m_encoding() {
+        let format = OtpFormat::Bech32m {
+            hrp: "otp".to_string(),
+        };
+        assert!(format.validate().is_ok());
+
+        let mac = [0u8; 6];
+        let token = format.encode(&mac, 6);
+        assert!(token.starts_with("otp1"));
+        assert_eq!(token.len(), 3 + 1 + 10 + 6);
+        assert!(format.verify(&mac, 6, &token));
+
+        let rules = Canonicalization::for_format(&format);
+        assert!(format.verify(&mac, 6, &rules.apply(&token.to_uppercase(), &format)));
+
+        // A single substituted character breaks the checksum
+        let mut typo = token.clone().into_bytes();
+        typo[6] = if typo[6] == b'q' { b'p' } else { b'q' };
+        assert!(!format.verify(&mac, 6, std::str::from_utf8(&typo).unwrap()));
+
+        // Valid checksum with another prefix
+        let other = OtpFormat::Bech32m {
+            hrp: "otx".to_string(),
+        };
+        assert!(!format.verify(&mac, 6, &other.encode(&mac, 6)));
+
+        let invalid = OtpFormat::Bech32m { hrp: String::new() };
+        assert!(matches!(invalid.validate(), Err(Error::InvalidFormat(_))));
+    }
+
+    #[cfg(feature = "bech32")]
+    #[test]
+    fn test_bech32m_prefix_with_separators() {
+        for hrp in ["my-app", "a.b", "x_y"] {
+            let format = OtpFormat::Bech32m {
+                hrp: hrp.to_string(),
+            };
+            let passcode = crate::Passcode::builder(crate::Algorithm::Sha3Kmac256, vec![1u8; 32])
+                .format(format)
+                .build()
+                .unwrap();
+            let otp = passcode.compute(b"chal");
+            assert!(otp.starts_with(hrp));
+            assert!(passcode.verify(b"chal", &otp));
+        }
+    }
+
+    #[test]
+    fn test_decimal_digit_range() {
+        assert!(OtpFormat::Decimal(6).validate().is_ok());
+        assert!(OtpFormat::Decimal(10).validate().is_ok());
+        assert_eq!(
+            OtpFormat::Decimal(5).validate(),
+            Err(Error::InvalidDigits(5))
+        );
+        assert_eq!(
+            OtpFormat::Decimal(11).validate(),
+            Err(Error::InvalidDigits(11))
+        );
+    }
+}
@@ -0,0 +1,177 @@
+//! Length-prefixed framing for stream transports
+//!
+//! Raw TCP, Unix sockets and serial links deliver a byte stream, not
+//! messages. Each frame is a 4-byte big-endian length followed by that many
+//! bytes, enough to carry the encoded protocol messages, e.g.
+//! [`Message::to_bytes`](crate::session::Message::to_bytes) or the
+//! [`wire`](crate::wire) formats. With the `tokio` feature the async
+//! functions do the same over Tokio's I/O traits.
+//!
+//! Readers refuse frames longer than [`MAX_FRAME_LEN`] before allocating,
+//! so a peer cannot make the other side reserve arbitrary memory.
+//!
+//! # Example
+//! ```
+//! use passcode::frame::{read_frame, write_frame};
+//! use passcode::session::{ClientSession, Message, ServerSession};
+//! use passcode::{Algorithm, Passcode};
+//!
+//! let key = Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+//! let mut server = ServerSession::new(&key).unwrap();
+//! let mut client = ClientSession::new(&key);
+//!
+//! // The server writes the challenge to its stream...
+//! let mut stream = Vec::new();
+//! write_frame(&mut stream, &server.challenge().to_bytes()).unwrap();
+//!
+//! // ...and the client reads it from the other end
+//! let challenge = Message::from_bytes(&read_frame(&mut stream.as_slice()).unwrap()).unwrap();
+//! let response = client.handle(challenge).unwrap().unwrap();
+//! server.handle(response).unwrap();
+//! assert!(server.is_verified());
+//! ```
+
+use std::io::{self, Read, Write};
+
+/// Longest frame payload the readers accept, in bytes
+pub const MAX_FRAME_LEN: usize = 64 * 1024;
+
+/// Length of the frame header
+const HEADER_LEN: usize = 4;
+
+/// Writes one frame and flushes the writer
+///
+/// Fails with [`io::ErrorKind::InvalidInput`] when the payload is longer
+/// than [`MAX_FRAME_LEN`].
+pub fn write_frame(writer: &mut impl Write, payload: &[u8]) -> io::Result<()> {
+    writer.write_all(&header(payload)?)?;
+    writer.write_all(payload)?;
+    writer.flush()
+}
+
+/// Reads one frame
+///
+/// Fails with [`io::ErrorKind::UnexpectedEof`] when the stream ends inside
+/// a frame and with [`io::ErrorKind::InvalidData`] when the announced length
+/// exceeds [`MAX_FRAME_LEN`].
+pub fn read_frame(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut header = [0u8; HEADER_LEN];
+    reader.read_exact(&mut header)?;
+    let mut payload = vec![0u8; payload_len(header)?];
+    reader.read_exact(&mut payload)?;
+    Ok(payload)
+}
+
+/// Writes one frame and flushes the writer (feature `tokio`)
+///
+/// See [`write_frame`].
+#[cfg(feature = "tokio")]
+pub async fn write_frame_async(
+    writer: &mut (impl tokio::io::AsyncWrite + Unpin),
+    payload: &[u8],
+) -> io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    writer.write_all(&header(payload)?).await?;
+    writer.write_all(payload).await?;
+    writer.flush().await
+}
+
+/// Reads one frame (feature `tokio`)
+///
+/// See [`read_frame`].
+#[cfg(feature = "tokio")]
+pub async fn read_frame_async(
+    reader: &mut (impl tokio::io::AsyncRead + Unpin),
+) -> io::Result<Vec<u8>> {
+    use tokio::io::AsyncReadExt;
+
+    let mut header = [0u8; HEADER_LEN];
+    reader.read_exact(&mut header).await?;
+    let mut payload = vec![0u8; payload_len(header)?];
+    reader.read_exact(&mut payload).await?;
+    Ok(payload)
+}
+
+fn header(payload: &[u8]) -> io::Result<[u8; HEADER_LEN]> {
+    if payload.len() > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "frame exceeds the maximum length",
+        ));
+    }
+    Ok((payload.len() as u32).to_be_bytes())
+}
+
+fn payload_len(header: [u8; HEADER_LEN]) -> io::Result<usize> {
+    let len = u32::from_be_bytes(header) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "frame exceeds the maximum length",
+        ));
+    }
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut stream = Vec::new();
+        write_frame(&mut stream, b"first").unwrap();
+        write_frame(&mut stream, b"").unwrap();
+        assert_eq!(stream[..HEADER_LEN], [0, 0, 0, 5]);
+
+        let mut reader = stream.as_slice();
+        assert_eq!(read_frame(&mut reader).unwrap(), b"first");
+        assert_eq!(read_frame(&mut reader).unwrap(), b"");
+        assert_eq!(
+            read_frame(&mut reader).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn test_rejects_oversized() {
+        let payload = vec![0u8; MAX_FRAME_LEN + 1];
+        assert_eq!(
+            write_frame(&mut Vec::new(), &payload).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+
+        let header = ((MAX_FRAME_LEN + 1) as u32).to_be_bytes();
+        assert_eq!(
+            read_frame(&mut header.as_slice()).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        // Truncated payload
+        let mut stream = Vec::new();
+        write_frame(&mut stream, b"frame").unwrap();
+        stream.pop();
+        assert_eq!(
+            read_frame(&mut stream.as_slice()).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_async_round_trip() {
+        pollster::block_on(async {
+            let mut stream = Vec::new();
+            write_frame_async(&mut stream, b"challenge").await.unwrap();
+
+            // Both flavours produce the same bytes
+            let mut sync = Vec::new();
+            write_frame(&mut sync, b"challenge").unwrap();
+            assert_eq!(stream, sync);
+
+            let mut reader = stream.as_slice();
+            assert_eq!(read_frame_async(&mut reader).await.unwrap(), b"challenge");
+        });
+    }
+}
@@ -0,0 +1,351 @@
+//! gRPC service (feature `grpc`)
+//!
+//! [`PasscodeAuthService`] implements the `PasscodeAuth` service of
+//! `proto/passcode.proto` over a [`Verifier`] and, optionally, an
+//! [`Enrollment`], so a microservice gets a network API by mounting it on a
+//! tonic server:
+//!
+//! ```ignore
+//! tonic::transport::Server::builder()
+//!     .add_service(PasscodeAuthService::new(verifier).with_enrollment(enrollment).into_server())
+//!     .serve(addr)
+//!     .await?;
+//! ```
+//!
+//! The feature pulls in tonic's generated service code only; the transport
+//! (and TLS) is the application's choice, so enable tonic's `transport`
+//! feature in the application. [`PasscodeAuthClient`] is the generated
+//! client.
+//!
+//! As with [`Verifier::check`], a refused response is not an RPC error:
+//! `VerifyResponse` reports it in the returned `VerifyOutcome`. Malformed
+//! requests fail with `INVALID_ARGUMENT`, and store or randomness failures
+//! with `INTERNAL`.
+//!
+//! # Example
+//! ```
+//! use passcode::grpc::{PasscodeAuth, PasscodeAuthService};
+//! use passcode::keyring::KeyRing;
+//! use passcode::verifier::Verifier;
+//! use passcode::{proto, Algorithm, Passcode};
+//!
+//! # pollster::block_on(async {
+//! let keyring = KeyRing::new();
+//! keyring.insert("alice", Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]));
+//! let service = PasscodeAuthService::new(Verifier::new(keyring));
+//!
+//! let request = proto::IssueChallengeRequest { user_id: "alice".to_string() };
+//! let challenge = service
+//!     .issue_challenge(tonic::Request::new(request))
+//!     .await
+//!     .unwrap()
+//!     .into_inner();
+//!
+//! // Client side, holding alice's key
+//! let received = passcode::challenge::Challenge::try_from(challenge).unwrap();
+//! let otp = Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]).compute(&received.message());
+//!
+//! let request = proto::VerifyResponseRequest {
+//!     user_id: "alice".to_string(),
+//!     answer: Some(proto::ChallengeAnswer::new(received.id(), otp)),
+//! };
+//! let outcome = service
+//!     .verify_response(tonic::Request::new(request))
+//!     .await
+//!     .unwrap()
+//!     .into_inner();
+//! assert_eq!(outcome.kind(), proto::verify_outcome::Kind::Accepted);
+//! # });
+//! ```
+
+use std::fmt;
+
+use tonic::{Request, Response, Status};
+
+use crate::challenge::{ChallengeStore, MemoryStore};
+use crate::enrollment::Enrollment;
+use crate::proto;
+use crate::verifier::Verifier;
+use crate::Error;
+
+pub use crate::proto::passcode_auth_client::PasscodeAuthClient;
+pub use crate::proto::passcode_auth_server::{PasscodeAuth, PasscodeAuthServer};
+
+/// The `PasscodeAuth` service, backed by a [`Verifier`]
+///
+/// Without [`with_enrollment`](Self::with_enrollment), `Enroll` and
+/// `ConfirmEnrollment` fail with `UNIMPLEMENTED`. Confirmed keys are added
+/// to the verifier's key ring.
+pub struct PasscodeAuthService<S = MemoryStore, E = MemoryStore> {
+    verifier: Verifier<S>,
+    enrollment: Option<Enrollment<E>>,
+}
+
+impl<S: ChallengeStore> PasscodeAuthService<S> {
+    /// Creates the service without enrollment
+    pub fn new(verifier: Verifier<S>) -> Self {
+        Self {
+            verifier,
+            enrollment: None,
+        }
+    }
+}
+
+impl<S: ChallengeStore, E: ChallengeStore> PasscodeAuthService<S, E> {
+    /// Serves `Enroll` and `ConfirmEnrollment` through `enrollment`
+    pub fn with_enrollment<F: ChallengeStore>(
+        self,
+        enrollment: Enrollment<F>,
+    ) -> PasscodeAuthService<S, F> {
+        PasscodeAuthService {
+            verifier: self.verifier,
+            enrollment: Some(enrollment),
+        }
+    }
+
+    /// Gets the verifier
+    pub fn verifier(&self) -> &Verifier<S> {
+        &self.verifier
+    }
+
+    /// Wraps the service for mounting on a tonic server
+    pub fn into_server(self) -> PasscodeAuthServer<Self>
+    where
+        S: 'static,
+        E: 'static,
+    {
+        PasscodeAuthServer::new(self)
+    }
+}
+
+impl<S, E> fmt::Debug for PasscodeAuthService<S, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PasscodeAuthService")
+            .field("enrollment", &self.enrollment.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+#[tonic::async_trait]
+impl<S, E> PasscodeAuth for PasscodeAuthService<S, E>
+where
+    S: ChallengeStore + 'static,
+    E: ChallengeStore + 'static,
+{
+    async fn issue_challenge(
+        &self,
+        request: Request<proto::IssueChallengeRequest>,
+    ) -> Result<Response<proto::Challenge>, Status> {
+        let user_id = user_id(&request.get_ref().user_id).map_err(status)?;
+        let challenge = self
+            .verifier
+            .issue_challenge(user_id)
+            .await
+            .map_err(status)?;
+        Ok(Response::new((&challenge).into()))
+    }
+
+    async fn verify_response(
+        &self,
+        request: Request<proto::VerifyResponseRequest>,
+    ) -> Result<Response<proto::VerifyOutcome>, Status> {
+        let request = request.into_inner();
+        let user_id = user_id(&request.user_id).map_err(status)?;
+        let answer = answer(request.answer).map_err(status)?;
+        let challenge_id = answer.parsed_challenge_id().map_err(status)?;
+        let outcome = self
+            .verifier
+            .check(user_id, &challenge_id, &answer.otp)
+            .await
+            .map_err(status)?;
+        Ok(Response::new(outcome.into()))
+    }
+
+    async fn enroll(
+        &self,
+        request: Request<proto::EnrollRequest>,
+    ) -> Result<Response<proto::Provisioning>, Status> {
+        let user_id = user_id(&request.get_ref().user_id).map_err(status)?;
+        let provisioning = self
+            .enrollment
+            .as_ref()
+            .ok_or_else(no_enrollment)?
+            .begin(user_id)
+            .await
+            .map_err(status)?;
+        Ok(Response::new((&provisioning).into()))
+    }
+
+    async fn confirm_enrollment(
+        &self,
+        request: Request<proto::EnrollmentConfirmation>,
+    ) -> Result<Response<proto::EnrollmentConfirmed>, Status> {
+        let request = request.into_inner();
+        let user_id = user_id(&request.user_id).map_err(status)?;
+        let answer = answer(request.answer).map_err(status)?;
+        let challenge_id = answer.parsed_challenge_id().map_err(status)?;
+        self.enrollment
+            .as_ref()
+            .ok_or_else(no_enrollment)?
+            .confirm(user_id, &challenge_id, &answer.otp, self.verifier.keyring())
+            .await
+            .map_err(status)?;
+        Ok(Response::new(proto::EnrollmentConfirmed {}))
+    }
+}
+
+fn user_id(user_id: &str) -> Result<&str, Error> {
+    if user_id.is_empty() {
+        return Err(Error::MalformedMessage("user_id is required"));
+    }
+    Ok(user_id)
+}
+
+fn answer(answer: Option<proto::ChallengeAnswer>) -> Result<proto::ChallengeAnswer, Error> {
+    answer.ok_or(Error::MalformedMessage("answer is required"))
+}
+
+fn no_enrollment() -> Status {
+    Status::unimplemented("enrollment is not enabled")
+}
+
+/// Maps a crate error to the status reported to the client
+fn status(error: Error) -> Status {
+    match error {
+        Error::MalformedMessage(_) | Error::UnsupportedVersion { .. } => {
+            Status::invalid_argument(error.to_string())
+        }
+        Error::EnrollmentNotFound | Error::ChallengeNotFound => {
+            Status::not_found(error.to_string())
+        }
+        Error::OtpMismatch
+        | Error::ChallengeExpired
+        | Error::ReplayDetected
+        | Error::PuzzleUnsolved => Status::permission_denied(error.to_string()),
+        Error::RateLimited { .. } | Error::LockedOut { .. } => {
+            Status::resource_exhausted(error.to_string())
+        }
+        _ => Status::internal(error.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::challenge::Challenge;
+    use crate::enrollment::EnrollmentPolicy;
+    use crate::keyring::KeyRing;
+    use crate::otpauth::OtpAuthUri;
+    use crate::Passcode;
+    use tonic::Code;
+
+    fn answer_for(key: &Passcode, challenge: proto::Challenge) -> proto::ChallengeAnswer {
+        let challenge = Challenge::try_from(challenge).unwrap();
+        proto::ChallengeAnswer::new(challenge.id(), key.compute(&challenge.message()))
+    }
+
+    #[test]
+    fn test_enroll_then_verify() {
+        pollster::block_on(async {
+            let service = PasscodeAuthService::new(Verifier::new(KeyRing::new()))
+                .with_enrollment(Enrollment::new(EnrollmentPolicy::default()));
+            let enroll = proto::EnrollRequest {
+                user_id: "alice".to_string(),
+            };
+            let provisioning = service
+                .enroll(Request::new(enroll))
+                .await
+                .unwrap()
+                .into_inner();
+
+            let uri: OtpAuthUri = provisioning.uri.parse().unwrap();
+            let key = uri.to_passcode().unwrap();
+            let confirmation = proto::EnrollmentConfirmation {
+                user_id: "alice".to_string(),
+                answer: Some(answer_for(&key, provisioning.challenge.unwrap())),
+            };
+            service
+                .confirm_enrollment(Request::new(confirmation))
+                .await
+                .unwrap();
+            assert!(service.verifier().keyring().get("alice").is_some());
+
+            let issue = proto::IssueChallengeRequest {
+                user_id: "alice".to_string(),
+            };
+            let challenge = service
+                .issue_challenge(Request::new(issue))
+                .await
+                .unwrap()
+                .into_inner();
+            let verify = proto::VerifyResponseRequest {
+                user_id: "alice".to_string(),
+                answer: Some(answer_for(&key, challenge.clone())),
+            };
+            let outcome = service
+                .verify_response(Request::new(verify.clone()))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(outcome.kind(), proto::verify_outcome::Kind::Accepted);
+
+            // The challenge was consumed
+            let outcome = service
+                .verify_response(Request::new(verify))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(
+                outcome.kind(),
+                proto::verify_outcome::Kind::UnknownChallenge
+            );
+        });
+    }
+
+    fn code<T>(result: Result<Response<T>, Status>) -> Code {
+        result.map(|_| ()).unwrap_err().code()
+    }
+
+    #[test]
+    fn test_rejects_invalid_requests() {
+        pollster::block_on(async {
+            let service = PasscodeAuthService::new(Verifier::new(KeyRing::new()));
+
+            let issue = proto::IssueChallengeRequest::default();
+            assert_eq!(
+                code(service.issue_challenge(Request::new(issue)).await),
+                Code::InvalidArgument
+            );
+
+            let verify = proto::VerifyResponseRequest {
+                user_id: "alice".to_string(),
+                answer: Some(proto::ChallengeAnswer {
+                    challenge_id: vec![1, 2, 3],
+                    otp: "otp".to_string(),
+                }),
+            };
+            assert_eq!(
+                code(service.verify_response(Request::new(verify)).await),
+                Code::InvalidArgument
+            );
+
+            let enroll = proto::EnrollRequest {
+                user_id: "alice".to_string(),
+            };
+            assert_eq!(
+                code(service.enroll(Request::new(enroll)).await),
+                Code::Unimplemented
+            );
+        });
+    }
+
+    #[test]
+    fn test_status_codes() {
+        assert_eq!(status(Error::OtpMismatch).code(), Code::PermissionDenied);
+        assert_eq!(status(Error::EnrollmentNotFound).code(), Code::NotFound);
+        assert_eq!(
+            status(Error::ChallengeStore("down".to_string())).code(),
+            Code::Internal
+        );
+    }
+}
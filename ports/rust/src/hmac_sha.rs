@@ -0,0 +1,108 @@
+//! HMAC-SHA-256 and HMAC-SHA-1 passcodes, the keyed hashes that TPMs, HSMs
+//! and security keys compute
+
+use crate::hotp::{hmac, HmacAlgorithm};
+use crate::Error;
+
+/// HMAC-SHA-256 for passcode (internal use)
+pub(crate) fn hmac_sha256_for_passcode(key: &[u8], data: &[u8]) -> Vec<u8> {
+    hmac(HmacAlgorithm::Sha256, key, data)
+}
+
+/// HMAC-SHA-1 for passcode (internal use)
+pub(crate) fn hmac_sha1_for_passcode(key: &[u8], data: &[u8]) -> Vec<u8> {
+    hmac(HmacAlgorithm::Sha1, key, data)
+}
+
+/// Counter-mode KDF (NIST SP 800-108) over a keyed hash
+///
+/// Produces arbitrary-length output from
+/// `prf([i]_4 || label || 0x00 || context || [L]_4)`. Only the keyed hash
+/// itself is needed, so a key held in hardware can be expanded too.
+///
+/// Fails with [`Error::InvalidFormat`] if `out_len` bytes do not fit the
+/// 32-bit length field, i.e. for 512 MiB or more, and with the error of
+/// `prf` if it fails.
+pub(crate) fn counter_mode_expand(
+    prf: impl Fn(&[u8]) -> Result<Vec<u8>, Error>,
+    label: &[u8],
+    context: &[u8],
+    out_len: usize,
+) -> Result<Vec<u8>, Error> {
+    let bit_len = out_len
+        .checked_mul(8)
+        .and_then(|bits| u32::try_from(bits).ok())
+        .ok_or(Error::InvalidFormat("output length is too large"))?;
+    let mut output = Vec::with_capacity(out_len + 64);
+    let mut counter: u32 = 1;
+
+    while output.len() < out_len {
+        let mut block = Vec::with_capacity(9 + label.len() + context.len());
+        block.extend_from_slice(&counter.to_be_bytes());
+        block.extend_from_slice(label);
+        block.push(0);
+        block.extend_from_slice(context);
+        block.extend_from_slice(&bit_len.to_be_bytes());
+
+        let hashed = prf(&block)?;
+        if hashed.is_empty() {
+            return Err(Error::Hardware("keyed hash returned no output".to_string()));
+        }
+        output.extend_from_slice(&hashed);
+        counter += 1;
+    }
+
+    output.truncate(out_len);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rfc4231_vector() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            hex::encode(hmac_sha256_for_passcode(
+                b"Jefe",
+                b"what do ya want for nothing?"
+            )),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_rfc2202_vector() {
+        // RFC 2202 test case 2
+        assert_eq!(
+            hex::encode(hmac_sha1_for_passcode(
+                b"Jefe",
+                b"what do ya want for nothing?"
+            )),
+            "effcdf6ae5eb2fa2d27416d5f184df9c259a7c79"
+        );
+    }
+
+    #[test]
+    fn test_expand_lengths() {
+        let prf = |block: &[u8]| Ok(hmac_sha256_for_passcode(&[1u8; 32], block));
+        let short = counter_mode_expand(prf, b"label", b"data", 16).unwrap();
+        let long = counter_mode_expand(prf, b"label", b"data", 80).unwrap();
+
+        assert_eq!(short.len(), 16);
+        assert_eq!(long.len(), 80);
+        // The requested length is bound into every block
+        assert_ne!(&long[..16], &short[..]);
+    }
+
+    #[test]
+    fn test_expand_rejects_oversized_length() {
+        let prf = |block: &[u8]| Ok(hmac_sha256_for_passcode(&[1u8; 32], block));
+        // 2^29 bytes is 2^32 bits, one more than the length field holds
+        assert_eq!(
+            counter_mode_expand(prf, b"label", b"data", 1 << 29),
+            Err(Error::InvalidFormat("output length is too large"))
+        );
+    }
+}
@@ -0,0 +1,108 @@
+//! HMAC-Streebog-256 (GOST R 34.11-2012, RFC 7836)
+
+use hmac::digest::KeyInit;
+use hmac::{Hmac, Mac};
+use streebog::Streebog256;
+
+use crate::sha3_kmac::PASSCODE_CUSTOMIZATION;
+use crate::Error;
+
+type HmacStreebog256 = Hmac<Streebog256>;
+
+/// HMAC-Streebog-256 of `data` under `key`
+pub fn hmac_streebog256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    // HMAC accepts keys of any length, so this cannot fail
+    let mut mac =
+        <HmacStreebog256 as KeyInit>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// HMAC-Streebog-256 for passcode (internal use)
+pub fn hmac_streebog256_for_passcode(key: &[u8], data: &[u8]) -> Vec<u8> {
+    hmac_streebog256(key, data)
+}
+
+/// Counter-mode KDF (NIST SP 800-108) over HMAC-Streebog-256
+///
+/// Streebog has no extendable-output mode, so arbitrary-length output is
+/// produced from `HMAC(key, [i]_4 || label || 0x00 || context || [L]_4)`.
+///
+/// Fails with [`Error::InvalidFormat`] if `out_len` bytes do not fit the
+/// 32-bit length field, i.e. for 512 MiB or more.
+pub(crate) fn hmac_streebog256_expand(
+    key: &[u8],
+    label: &[u8],
+    context: &[u8],
+    out_len: usize,
+) -> Result<Vec<u8>, Error> {
+    let bit_len = out_len
+        .checked_mul(8)
+        .and_then(|bits| u32::try_from(bits).ok())
+        .ok_or(Error::InvalidFormat("output length is too large"))?;
+    let mut output = Vec::with_capacity(out_len + 32);
+    let mut counter: u32 = 1;
+
+    while output.len() < out_len {
+        let mut block = Vec::with_capacity(9 + label.len() + context.len());
+        block.extend_from_slice(&counter.to_be_bytes());
+        block.extend_from_slice(label);
+        block.push(0);
+        block.extend_from_slice(context);
+        block.extend_from_slice(&bit_len.to_be_bytes());
+
+        output.extend_from_slice(&hmac_streebog256(key, &block));
+        counter += 1;
+    }
+
+    output.truncate(out_len);
+    Ok(output)
+}
+
+/// Arbitrary-length passcode output over HMAC-Streebog-256 (internal use)
+///
+/// Fails like [`hmac_streebog256_expand`].
+pub(crate) fn hmac_streebog256_xof(
+    key: &[u8],
+    data: &[u8],
+    out_len: usize,
+) -> Result<Vec<u8>, Error> {
+    hmac_streebog256_expand(key, PASSCODE_CUSTOMIZATION, data, out_len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rfc7836_vector() {
+        let key: Vec<u8> = (0u8..32).collect();
+        let data = hex::decode("0126bdb87800af214341456563780100").unwrap();
+
+        assert_eq!(
+            hex::encode(hmac_streebog256(&key, &data)),
+            "a1aa5f7de402d7b3d323f2991c8d4534013137010a83754fd0af6d7cd4922ed9"
+        );
+    }
+
+    #[test]
+    fn test_expand_lengths() {
+        let key = [1u8; 32];
+        let short = hmac_streebog256_xof(&key, b"data", 16).unwrap();
+        let long = hmac_streebog256_xof(&key, b"data", 80).unwrap();
+
+        assert_eq!(short.len(), 16);
+        assert_eq!(long.len(), 80);
+        // The requested length is bound into every block
+        assert_ne!(&long[..16], &short[..]);
+    }
+
+    #[test]
+    fn test_expand_rejects_oversized_length() {
+        // 2^29 bytes is 2^32 bits, one more than the length field holds
+        assert_eq!(
+            hmac_streebog256_xof(&[1u8; 32], b"data", 1 << 29),
+            Err(Error::InvalidFormat("output length is too large"))
+        );
+    }
+}
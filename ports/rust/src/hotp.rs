@@ -0,0 +1,444 @@
+//! HMAC-based One-Time Passwords (RFC 4226)
+//!
+//! This is the HMAC and dynamic truncation plumbing shared by the
+//! counter-based and time-based (`totp`) modes, the [`CounterStore`] that
+//! persists the server's moving factor per account, and [`Hotp`], which
+//! verifies codes against it with a look-ahead window and resynchronizes
+//! counters that drifted further.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::sync::{Mutex, MutexGuard};
+
+use hmac::digest::KeyInit;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+use subtle::ConstantTimeEq;
+
+use crate::self_test::ensure_self_test;
+use crate::Error;
+
+/// Smallest number of digits allowed by RFC 4226
+pub const MIN_DIGITS: u32 = 6;
+/// Largest number of digits that a 31-bit truncated value can fill
+pub const MAX_DIGITS: u32 = 9;
+
+/// HMAC hash functions supported by HOTP/TOTP
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HmacAlgorithm {
+    /// HMAC-SHA1 (the default used by most authenticator apps)
+    #[default]
+    Sha1,
+    /// HMAC-SHA256
+    Sha256,
+    /// HMAC-SHA512
+    Sha512,
+}
+
+impl HmacAlgorithm {
+    /// Returns the algorithm name as used in `otpauth://` URIs
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HmacAlgorithm::Sha1 => "SHA1",
+            HmacAlgorithm::Sha256 => "SHA256",
+            HmacAlgorithm::Sha512 => "SHA512",
+        }
+    }
+}
+
+impl std::fmt::Display for HmacAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Computes HMAC over the message with the given hash function
+pub(crate) fn hmac(algorithm: HmacAlgorithm, key: &[u8], message: &[u8]) -> Vec<u8> {
+    match algorithm {
+        HmacAlgorithm::Sha1 => hmac_with::<Hmac<Sha1>>(key, message),
+        HmacAlgorithm::Sha256 => hmac_with::<Hmac<Sha256>>(key, message),
+        HmacAlgorithm::Sha512 => hmac_with::<Hmac<Sha512>>(key, message),
+    }
+}
+
+fn hmac_with<M: Mac + KeyInit>(key: &[u8], message: &[u8]) -> Vec<u8> {
+    // HMAC accepts keys of any length, so this cannot fail
+    let mut mac = <M as KeyInit>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// RFC 4226 dynamic truncation to a 31-bit integer
+pub(crate) fn dynamic_truncate(mac: &[u8]) -> u32 {
+    let offset = (mac[mac.len() - 1] & 0x0f) as usize;
+    u32::from_be_bytes([
+        mac[offset] & 0x7f,
+        mac[offset + 1],
+        mac[offset + 2],
+        mac[offset + 3],
+    ])
+}
+
+/// Validates a digit count against the supported range
+pub(crate) fn check_digits(digits: u32) -> Result<(), Error> {
+    if (MIN_DIGITS..=MAX_DIGITS).contains(&digits) {
+        Ok(())
+    } else {
+        Err(Error::InvalidDigits(digits))
+    }
+}
+
+/// Computes an HOTP value for the given counter
+///
+/// # Arguments
+/// * `algorithm` - The HMAC hash function
+/// * `key` - The shared secret key
+/// * `counter` - The moving factor
+/// * `digits` - Number of decimal digits (6 to 9)
+///
+/// # Example
+/// ```
+/// use passcode::hotp::{hotp, HmacAlgorithm};
+///
+/// // RFC 4226 Appendix D
+/// let otp = hotp(HmacAlgorithm::Sha1, b"12345678901234567890", 0, 6).unwrap();
+/// assert_eq!(otp, "755224");
+/// ```
+pub fn hotp(
+    algorithm: HmacAlgorithm,
+    key: &[u8],
+    counter: u64,
+    digits: u32,
+) -> Result<String, Error> {
+    check_digits(digits)?;
+    Ok(hotp_unchecked(algorithm, key, counter, digits))
+}
+
+/// Computes an HOTP value without validating `digits`
+pub(crate) fn hotp_unchecked(
+    algorithm: HmacAlgorithm,
+    key: &[u8],
+    counter: u64,
+    digits: u32,
+) -> String {
+    let mac = hmac(algorithm, key, &counter.to_be_bytes());
+    let value = dynamic_truncate(&mac) % 10u32.pow(digits);
+    format!("{:0width$}", value, width = digits as usize)
+}
+
+/// Storage for the last accepted HOTP counter of each account
+///
+/// A counter may only move forward: [`advance`](Self::advance) must compare
+/// and update atomically, so that two servers accepting the same OTP at the
+/// same time cannot both succeed.
+pub trait CounterStore: Send + Sync {
+    /// Returns the last accepted counter for the account, if any
+    fn get(&self, account: &str) -> impl Future<Output = Result<Option<u64>, Error>> + Send;
+
+    /// Records `counter` as accepted if it is greater than the stored one
+    ///
+    /// Returns false, leaving the store unchanged, when the account already
+    /// has an equal or greater counter.
+    fn advance(
+        &self,
+        account: &str,
+        counter: u64,
+    ) -> impl Future<Output = Result<bool, Error>> + Send;
+}
+
+/// In-memory [`CounterStore`]; counters are lost when the process exits
+#[derive(Debug, Default)]
+pub struct MemoryCounterStore {
+    counters: Mutex<HashMap<String, u64>>,
+}
+
+impl MemoryCounterStore {
+    /// Creates an empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, u64>> {
+        // A panic while holding the lock cannot leave the map inconsistent
+        self.counters
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl CounterStore for MemoryCounterStore {
+    async fn get(&self, account: &str) -> Result<Option<u64>, Error> {
+        Ok(self.lock().get(account).copied())
+    }
+
+    async fn advance(&self, account: &str, counter: u64) -> Result<bool, Error> {
+        let mut counters = self.lock();
+        match counters.get(account) {
+            Some(&stored) if stored >= counter => Ok(false),
+            _ => {
+                counters.insert(account.to_string(), counter);
+                Ok(true)
+            }
+        }
+    }
+}
+
+/// Default number of counters checked past the expected one
+pub const DEFAULT_LOOK_AHEAD: u64 = 10;
+
+/// Default number of counters searched when resynchronizing
+pub const DEFAULT_RESYNC_WINDOW: u64 = 100;
+
+/// Configuration for a [`Hotp`] instance
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HotpConfig {
+    /// HMAC hash function
+    pub algorithm: HmacAlgorithm,
+    /// Number of decimal digits (6 to 9)
+    pub digits: u32,
+    /// Counters accepted past the expected one, for codes the client
+    /// generated but never sent
+    pub look_ahead: u64,
+    /// Counters searched by [`Hotp::resync`]
+    pub resync_window: u64,
+}
+
+impl Default for HotpConfig {
+    fn default() -> Self {
+        Self {
+            algorithm: HmacAlgorithm::Sha1,
+            digits: 6,
+            look_ahead: DEFAULT_LOOK_AHEAD,
+            resync_window: DEFAULT_RESYNC_WINDOW,
+        }
+    }
+}
+
+/// Counter-based OTP verifier backed by a [`CounterStore`]
+///
+/// Each account's last accepted counter lives in the store, so a code is
+/// accepted at most once and the counter only moves forward.
+///
+/// # Example
+/// ```
+/// use passcode::hotp::{hotp, Hotp, HotpConfig, MemoryCounterStore};
+///
+/// # pollster::block_on(async {
+/// let key = b"12345678901234567890".to_vec();
+/// let server = Hotp::new(key.clone(), HotpConfig::default()).unwrap();
+/// let store = MemoryCounterStore::new();
+///
+/// // The client pressed its button 50 times without logging in
+/// let first = server.generate(50);
+/// let second = server.generate(51);
+/// assert_eq!(server.verify(&store, "alice", &first).await, Ok(None));
+///
+/// assert_eq!(server.resync(&store, "alice", &first, &second).await, Ok(Some(51)));
+/// assert_eq!(server.verify(&store, "alice", &server.generate(52)).await, Ok(Some(52)));
+/// # });
+/// ```
+#[derive(Clone)]
+pub struct Hotp {
+    key: Vec<u8>,
+    config: HotpConfig,
+}
+
+impl fmt::Debug for Hotp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The shared secret is never printed
+        f.debug_struct("Hotp")
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Hotp {
+    /// Creates a new HOTP verifier
+    ///
+    /// # Arguments
+    /// * `key` - The shared secret key
+    /// * `config` - Digits, hash function and search windows
+    pub fn new(key: Vec<u8>, config: HotpConfig) -> Result<Self, Error> {
+        ensure_self_test()?;
+        check_digits(config.digits)?;
+        Ok(Self { key, config })
+    }
+
+    /// Gets the configuration
+    pub fn config(&self) -> &HotpConfig {
+        &self.config
+    }
+
+    /// Generates the code for a counter
+    pub fn generate(&self, counter: u64) -> String {
+        hotp_unchecked(
+            self.config.algorithm,
+            &self.key,
+            counter,
+            self.config.digits,
+        )
+    }
+
+    /// Verifies a code for the account, advancing its stored counter
+    ///
+    /// Tries the counter after the last accepted one and up to
+    /// `look_ahead` counters past it. Returns the matched counter, or
+    /// `None` when no counter in the window matches or another server
+    /// accepted the same or a later counter first.
+    pub async fn verify<S: CounterStore>(
+        &self,
+        store: &S,
+        account: &str,
+        code: &str,
+    ) -> Result<Option<u64>, Error> {
+        let Some(next) = next_counter(store.get(account).await?) else {
+            return Ok(None);
+        };
+        let found = window(next, self.config.look_ahead).find(|&c| self.matches(code, c));
+        match found {
+            Some(counter) if store.advance(account, counter).await? => Ok(Some(counter)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Re-establishes the account's counter from two consecutive codes
+    ///
+    /// For clients that generated more codes than the look-ahead window
+    /// covers. Searches up to `resync_window` counters past the expected one
+    /// for a counter where `first` matches and `second` matches the next
+    /// counter, and stores the second as accepted. Returns the new counter,
+    /// or `None` when no such pair exists or the store already moved past
+    /// it.
+    pub async fn resync<S: CounterStore>(
+        &self,
+        store: &S,
+        account: &str,
+        first: &str,
+        second: &str,
+    ) -> Result<Option<u64>, Error> {
+        let Some(next) = next_counter(store.get(account).await?) else {
+            return Ok(None);
+        };
+        let found = window(next, self.config.resync_window)
+            .filter_map(|c| Some((c, c.checked_add(1)?)))
+            .find(|&(c, following)| self.matches(first, c) && self.matches(second, following));
+        match found {
+            Some((_, counter)) if store.advance(account, counter).await? => Ok(Some(counter)),
+            _ => Ok(None),
+        }
+    }
+
+    fn matches(&self, code: &str, counter: u64) -> bool {
+        let expected = self.generate(counter);
+        expected.as_bytes().ct_eq(code.trim().as_bytes()).into()
+    }
+}
+
+/// Returns the first counter not yet accepted, or `None` once exhausted
+fn next_counter(stored: Option<u64>) -> Option<u64> {
+    stored.map_or(Some(0), |counter| counter.checked_add(1))
+}
+
+/// Returns `next` and up to `ahead` counters past it
+fn window(next: u64, ahead: u64) -> impl Iterator<Item = u64> {
+    next..=next.saturating_add(ahead)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pollster::block_on;
+
+    #[test]
+    fn test_rfc4226_vectors() {
+        let key = b"12345678901234567890";
+        let expected = [
+            "755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583",
+            "399871", "520489",
+        ];
+
+        for (counter, otp) in expected.iter().enumerate() {
+            assert_eq!(
+                hotp(HmacAlgorithm::Sha1, key, counter as u64, 6).unwrap(),
+                *otp
+            );
+        }
+    }
+
+    #[test]
+    fn test_invalid_digits() {
+        assert_eq!(
+            hotp(HmacAlgorithm::Sha1, b"key", 0, 5),
+            Err(Error::InvalidDigits(5))
+        );
+        assert_eq!(
+            hotp(HmacAlgorithm::Sha1, b"key", 0, 10),
+            Err(Error::InvalidDigits(10))
+        );
+    }
+
+    #[test]
+    fn test_memory_counter_store_only_advances() {
+        let store = MemoryCounterStore::new();
+
+        assert_eq!(block_on(store.get("alice")), Ok(None));
+        assert_eq!(block_on(store.advance("alice", 5)), Ok(true));
+        assert_eq!(block_on(store.advance("alice", 5)), Ok(false));
+        assert_eq!(block_on(store.advance("alice", 3)), Ok(false));
+        assert_eq!(block_on(store.advance("alice", 6)), Ok(true));
+        assert_eq!(block_on(store.get("alice")), Ok(Some(6)));
+        assert_eq!(block_on(store.get("bob")), Ok(None));
+    }
+
+    #[test]
+    fn test_verify_look_ahead() {
+        let hotp = Hotp::new(b"12345678901234567890".to_vec(), HotpConfig::default()).unwrap();
+        let store = MemoryCounterStore::new();
+
+        assert_eq!(
+            block_on(hotp.verify(&store, "alice", "755224")),
+            Ok(Some(0))
+        );
+        assert_eq!(block_on(hotp.verify(&store, "alice", "755224")), Ok(None));
+        assert_eq!(
+            block_on(hotp.verify(&store, "alice", &hotp.generate(11))),
+            Ok(Some(11))
+        );
+        assert_eq!(
+            block_on(hotp.verify(&store, "alice", &hotp.generate(23))),
+            Ok(None)
+        );
+        assert_eq!(block_on(store.get("alice")), Ok(Some(11)));
+        assert!(!format!("{:?}", hotp).contains("49, 50"));
+    }
+
+    #[test]
+    fn test_resync() {
+        let hotp = Hotp::new(b"12345678901234567890".to_vec(), HotpConfig::default()).unwrap();
+        let store = MemoryCounterStore::new();
+        block_on(store.advance("alice", 9)).unwrap();
+
+        let (first, second) = (hotp.generate(80), hotp.generate(81));
+        assert_eq!(
+            block_on(hotp.resync(&store, "alice", &first, &hotp.generate(82))),
+            Ok(None)
+        );
+        assert_eq!(
+            block_on(hotp.resync(&store, "alice", &first, &second)),
+            Ok(Some(81))
+        );
+        assert_eq!(block_on(store.get("alice")), Ok(Some(81)));
+
+        // The same pair cannot resynchronize twice
+        assert_eq!(
+            block_on(hotp.resync(&store, "alice", &first, &second)),
+            Ok(None)
+        );
+        assert_eq!(
+            block_on(hotp.resync(&store, "alice", &hotp.generate(300), &hotp.generate(301))),
+            Ok(None)
+        );
+    }
+}
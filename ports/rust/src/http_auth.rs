@@ -0,0 +1,406 @@
+//! HTTP authentication scheme
+//!
+//! Defines the `Passcode` scheme for the HTTP `WWW-Authenticate` and
+//! `Authorization` headers, so HTTP integrations share one format:
+//!
+//! ```text
+//! WWW-Authenticate: Passcode realm="example", challenge="<id>", message="<base64url>"
+//! Authorization: Passcode challenge="<id>", response="<otp>"
+//! ```
+//!
+//! The challenge ID is hex as in [`ChallengeId`]'s `Display`, and `message`
+//! is [`Challenge::message`] in unpadded base64url. Parsing follows the
+//! auth-param grammar of RFC 9110 strictly: the scheme name is matched
+//! case-insensitively, values may be tokens or quoted strings, and unknown,
+//! duplicate or missing parameters are rejected with
+//! [`Error::MalformedMessage`]. Header values longer than
+//! [`MAX_HEADER_LEN`] are rejected before parsing.
+//!
+//! # Example
+//! ```
+//! use passcode::challenge::ChallengeManager;
+//! use passcode::http_auth::{PasscodeAuthorization, PasscodeChallenge};
+//! use passcode::{Algorithm, Passcode};
+//!
+//! # pollster::block_on(async {
+//! let key = vec![0u8; 32];
+//! let server = ChallengeManager::new(Passcode::new(Algorithm::Sha3Kmac256, key.clone()));
+//! let challenge = server.issue().await.unwrap();
+//! let www_authenticate = PasscodeChallenge::new(&challenge).with_realm("example").to_string();
+//!
+//! // Client
+//! let received: PasscodeChallenge = www_authenticate.parse().unwrap();
+//! let otp = Passcode::new(Algorithm::Sha3Kmac256, key).compute(received.message());
+//! let authorization = PasscodeAuthorization::new(*received.challenge_id(), otp).to_string();
+//!
+//! // Server
+//! let credentials: PasscodeAuthorization = authorization.parse().unwrap();
+//! assert!(server
+//!     .verify(credentials.challenge_id(), credentials.response())
+//!     .await
+//!     .is_ok());
+//! # });
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+
+use base64ct::{Base64UrlUnpadded, Encoding};
+use subtle::ConstantTimeEq;
+
+use crate::challenge::{Challenge, ChallengeId};
+use crate::Error;
+
+/// Name of the authentication scheme
+pub const AUTH_SCHEME: &str = "Passcode";
+
+/// Longest header value the parsers accept, in bytes
+pub const MAX_HEADER_LEN: usize = 4096;
+
+/// Value of a `WWW-Authenticate` header asking for a passcode response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasscodeChallenge {
+    realm: Option<String>,
+    challenge_id: ChallengeId,
+    message: Vec<u8>,
+}
+
+impl PasscodeChallenge {
+    /// Describes an issued challenge
+    pub fn new(challenge: &Challenge) -> Self {
+        Self {
+            realm: None,
+            challenge_id: *challenge.id(),
+            message: challenge.message(),
+        }
+    }
+
+    /// Sets the protection space reported to the client
+    pub fn with_realm(mut self, realm: impl Into<String>) -> Self {
+        self.realm = Some(realm.into());
+        self
+    }
+
+    /// Gets the realm, if one was set
+    pub fn realm(&self) -> Option<&str> {
+        self.realm.as_deref()
+    }
+
+    /// Gets the ID of the challenge
+    pub fn challenge_id(&self) -> &ChallengeId {
+        &self.challenge_id
+    }
+
+    /// Gets the message the client computes the OTP over
+    pub fn message(&self) -> &[u8] {
+        &self.message
+    }
+}
+
+impl fmt::Display for PasscodeChallenge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(AUTH_SCHEME)?;
+        if let Some(realm) = &self.realm {
+            write!(f, " realm={},", Quoted(realm))?;
+        }
+        write!(
+            f,
+            " challenge=\"{}\", message=\"{}\"",
+            self.challenge_id,
+            Base64UrlUnpadded::encode_string(&self.message)
+        )
+    }
+}
+
+impl FromStr for PasscodeChallenge {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let mut params = parse(s, &["realm", "challenge", "message"])?;
+        let message = Base64UrlUnpadded::decode_vec(&params.required("message")?)
+            .map_err(|_| Error::MalformedMessage("message is not base64url"))?;
+        Ok(Self {
+            challenge_id: challenge_id(&params.required("challenge")?)?,
+            realm: params.take("realm"),
+            message,
+        })
+    }
+}
+
+/// Value of an `Authorization` header carrying a passcode response
+///
+/// [`Debug`](fmt::Debug) output leaves out the response.
+#[derive(Clone, PartialEq, Eq)]
+pub struct PasscodeAuthorization {
+    challenge_id: ChallengeId,
+    response: String,
+}
+
+impl PasscodeAuthorization {
+    /// Creates credentials answering the challenge with the given ID
+    pub fn new(challenge_id: ChallengeId, response: impl Into<String>) -> Self {
+        Self {
+            challenge_id,
+            response: response.into(),
+        }
+    }
+
+    /// Gets the ID of the answered challenge
+    pub fn challenge_id(&self) -> &ChallengeId {
+        &self.challenge_id
+    }
+
+    /// Gets the OTP
+    pub fn response(&self) -> &str {
+        &self.response
+    }
+
+    /// Compares the challenge ID in constant time, e.g. against the ID kept
+    /// in the client's session
+    pub fn challenge_matches(&self, expected: &ChallengeId) -> bool {
+        bool::from(self.challenge_id.as_bytes().ct_eq(expected.as_bytes()))
+    }
+
+    /// Compares the response in constant time, for integrations that check
+    /// the OTP themselves instead of through a manager
+    pub fn response_matches(&self, expected: &str) -> bool {
+        bool::from(self.response.as_bytes().ct_eq(expected.as_bytes()))
+    }
+}
+
+impl fmt::Debug for PasscodeAuthorization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PasscodeAuthorization")
+            .field("challenge_id", &self.challenge_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Display for PasscodeAuthorization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} challenge=\"{}\", response={}",
+            AUTH_SCHEME,
+            self.challenge_id,
+            Quoted(&self.response)
+        )
+    }
+}
+
+impl FromStr for PasscodeAuthorization {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let mut params = parse(s, &["challenge", "response"])?;
+        Ok(Self {
+            challenge_id: challenge_id(&params.required("challenge")?)?,
+            response: params.required("response")?,
+        })
+    }
+}
+
+/// Writes a string as a quoted-string, escaping quotes and backslashes
+struct Quoted<'a>(&'a str);
+
+impl fmt::Display for Quoted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("\"")?;
+        for c in self.0.chars() {
+            if c == '"' || c == '\\' {
+                f.write_str("\\")?;
+            }
+            write!(f, "{}", c)?;
+        }
+        f.write_str("\"")
+    }
+}
+
+/// Parsed auth-params, lowercase names in the order of `allowed`
+struct Params {
+    values: Vec<(&'static str, String)>,
+}
+
+impl Params {
+    fn take(&mut self, name: &str) -> Option<String> {
+        let index = self.values.iter().position(|(n, _)| *n == name)?;
+        Some(self.values.swap_remove(index).1)
+    }
+
+    fn required(&mut self, name: &str) -> Result<String, Error> {
+        self.take(name)
+            .ok_or(Error::MalformedMessage("missing auth parameter"))
+    }
+}
+
+/// Parses `Passcode name=value, ...`, accepting only the `allowed` names
+fn parse(header: &str, allowed: &[&'static str]) -> Result<Params, Error> {
+    if header.len() > MAX_HEADER_LEN {
+        return Err(Error::MalformedMessage("header is too long"));
+    }
+    let header = header.trim_matches(is_ows);
+    let (scheme, mut rest) = header
+        .split_once(' ')
+        .ok_or(Error::MalformedMessage("missing auth parameters"))?;
+    if !scheme.eq_ignore_ascii_case(AUTH_SCHEME) {
+        return Err(Error::MalformedMessage("unsupported auth scheme"));
+    }
+
+    let mut params = Params { values: Vec::new() };
+    loop {
+        rest = rest.trim_start_matches(is_ows);
+        let (name, after) = rest
+            .split_once('=')
+            .ok_or(Error::MalformedMessage("auth parameter lacks a value"))?;
+        let name = name.trim_end_matches(is_ows);
+        let name = allowed
+            .iter()
+            .find(|allowed| allowed.eq_ignore_ascii_case(name))
+            .ok_or(Error::MalformedMessage("unknown auth parameter"))?;
+        if params.values.iter().any(|(n, _)| n == name) {
+            return Err(Error::MalformedMessage("duplicate auth parameter"));
+        }
+
+        let (value, after) = value(after.trim_start_matches(is_ows))?;
+        params.values.push((name, value));
+
+        rest = after.trim_start_matches(is_ows);
+        match rest.strip_prefix(',') {
+            Some(next) => rest = next,
+            None if rest.is_empty() => return Ok(params),
+            None => return Err(Error::MalformedMessage("expected a comma")),
+        }
+    }
+}
+
+/// Reads a token or quoted-string, returning it and the remaining input
+fn value(input: &str) -> Result<(String, &str), Error> {
+    let Some(quoted) = input.strip_prefix('"') else {
+        let end = input.find(|c: char| !is_tchar(c)).unwrap_or(input.len());
+        if end == 0 {
+            return Err(Error::MalformedMessage("auth parameter lacks a value"));
+        }
+        return Ok((input[..end].to_string(), &input[end..]));
+    };
+
+    let mut value = String::new();
+    let mut chars = quoted.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((value, &quoted[i + 1..])),
+            '\\' => match chars.next() {
+                Some((_, escaped)) if !escaped.is_control() || escaped == '\t' => {
+                    value.push(escaped)
+                }
+                _ => return Err(Error::MalformedMessage("invalid escape in quoted string")),
+            },
+            c if c.is_control() && c != '\t' => {
+                return Err(Error::MalformedMessage(
+                    "control character in quoted string",
+                ))
+            }
+            c => value.push(c),
+        }
+    }
+    Err(Error::MalformedMessage("unterminated quoted string"))
+}
+
+fn challenge_id(value: &str) -> Result<ChallengeId, Error> {
+    value
+        .parse()
+        .map_err(|_| Error::MalformedMessage("invalid challenge ID"))
+}
+
+fn is_ows(c: char) -> bool {
+    c == ' ' || c == '\t'
+}
+
+/// Token characters of RFC 9110
+fn is_tchar(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::challenge::CHALLENGE_ID_LEN;
+
+    fn id() -> ChallengeId {
+        ChallengeId::from_bytes([0xab; CHALLENGE_ID_LEN])
+    }
+
+    #[test]
+    fn test_authorization_round_trip() {
+        let credentials = PasscodeAuthorization::new(id(), "a\"b\\c");
+        let header = credentials.to_string();
+        assert_eq!(
+            header,
+            r#"Passcode challenge="abababababababababababababababab", response="a\"b\\c""#
+        );
+        assert_eq!(header.parse(), Ok(credentials.clone()));
+        assert!(!format!("{:?}", credentials).contains("a\\\"b"));
+
+        // Case-insensitive scheme and names, token values, extra whitespace
+        let parsed: PasscodeAuthorization =
+            "passcode  RESPONSE=123456 ,\tchallenge = \"abababababababababababababababab\""
+                .parse()
+                .unwrap();
+        assert!(parsed.challenge_matches(&id()));
+        assert!(parsed.response_matches("123456"));
+        assert!(!parsed.response_matches("123457"));
+    }
+
+    #[test]
+    fn test_challenge_round_trip() {
+        let header = PasscodeChallenge {
+            realm: Some("example".to_string()),
+            challenge_id: id(),
+            message: vec![0xfb, 0xff],
+        };
+        let value = header.to_string();
+        assert_eq!(
+            value,
+            r#"Passcode realm="example", challenge="abababababababababababababababab", message="-_8""#
+        );
+        assert_eq!(value.parse(), Ok(header));
+    }
+
+    #[test]
+    fn test_strict_parsing() {
+        let malformed = |s: &str| s.parse::<PasscodeAuthorization>().unwrap_err();
+        let challenge = "challenge=\"abababababababababababababababab\"";
+
+        assert_eq!(
+            malformed(&format!("Bearer {}, response=\"1\"", challenge)),
+            Error::MalformedMessage("unsupported auth scheme")
+        );
+        assert_eq!(
+            malformed(&format!("Passcode {}", challenge)),
+            Error::MalformedMessage("missing auth parameter")
+        );
+        assert_eq!(
+            malformed(&format!("Passcode {0}, {0}", challenge)),
+            Error::MalformedMessage("duplicate auth parameter")
+        );
+        assert_eq!(
+            malformed(&format!("Passcode {}, realm=\"x\"", challenge)),
+            Error::MalformedMessage("unknown auth parameter")
+        );
+        assert_eq!(
+            malformed(&format!("Passcode {}, response=\"1", challenge)),
+            Error::MalformedMessage("unterminated quoted string")
+        );
+        assert_eq!(
+            malformed(&format!("Passcode {} response=\"1\"", challenge)),
+            Error::MalformedMessage("expected a comma")
+        );
+        assert_eq!(
+            malformed("Passcode challenge=\"zz\", response=\"1\""),
+            Error::MalformedMessage("invalid challenge ID")
+        );
+        assert_eq!(
+            malformed(&" ".repeat(MAX_HEADER_LEN + 1)),
+            Error::MalformedMessage("header is too long")
+        );
+    }
+}
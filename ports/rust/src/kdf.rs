@@ -0,0 +1,192 @@
+//! Subkey derivation from a master key
+//!
+//! Lets a server hold a single master secret and derive independent
+//! per-user or per-device secrets from it. The `label` provides domain
+//! separation between purposes and the `context` identifies the individual
+//! subkey (a user ID, a device ID, ...). Different labels or contexts always
+//! yield unrelated keys.
+//!
+//! [`key_fingerprint`] derives a short [`KeyId`] that names a key in logs,
+//! messages and rotation records without revealing it.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::sha3_kmac::sha3_kmac256;
+use crate::Error;
+
+/// Documented domain-separation labels used by this library
+pub mod labels {
+    /// Per-user OTP secrets derived from a server master key
+    pub const USER: &str = "passcode/v1/user";
+    /// Per-device OTP secrets derived from an account key
+    pub const DEVICE: &str = "passcode/v1/device";
+    /// Session keys derived after a successful challenge-response
+    pub const SESSION: &str = "passcode/v1/session";
+    /// Proofs that the server holds the key, sent after a verified response
+    pub const SERVER_PROOF: &str = "passcode/v1/server-proof";
+    /// Keys signing session tokens minted after a verified response
+    pub const SESSION_TOKEN: &str = "passcode/v1/session-token";
+    /// Digests of recovery codes kept at rest
+    pub const RECOVERY_CODE: &str = "passcode/v1/recovery-code";
+    /// Fingerprints identifying a key
+    pub const KEY_ID: &str = "passcode/v1/key-id";
+    /// Coefficients of the device shares used for threshold approvals
+    pub const THRESHOLD: &str = "passcode/v1/threshold";
+    /// OTP keys combined from a server half and a client half
+    pub const SPLIT_KEY: &str = "passcode/v1/split-key";
+    /// Encryption keys of escrows sealed to a recovery public key
+    pub const ESCROW: &str = "passcode/v1/escrow";
+    /// Challenge-response keys derived from imported HOTP/TOTP seeds
+    pub const MIGRATION: &str = "passcode/v1/migration";
+    /// Digests of the user ID a wrapped key belongs to
+    pub const WRAP_USER: &str = "passcode/v1/wrap-user";
+}
+
+/// Length in bytes of a [`KeyId`]
+pub const KEY_ID_LEN: usize = 8;
+
+/// Short, non-secret identifier of a key
+///
+/// Displayed and parsed as 16 lowercase hex characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct KeyId([u8; KEY_ID_LEN]);
+
+impl KeyId {
+    /// Creates an identifier from its bytes
+    pub fn from_bytes(bytes: [u8; KEY_ID_LEN]) -> Self {
+        Self(bytes)
+    }
+
+    /// Gets the identifier bytes
+    pub fn as_bytes(&self) -> &[u8; KEY_ID_LEN] {
+        &self.0
+    }
+}
+
+impl fmt::Display for KeyId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+impl FromStr for KeyId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let mut bytes = [0u8; KEY_ID_LEN];
+        hex::decode_to_slice(s, &mut bytes)
+            .map_err(|_| Error::MalformedMessage("invalid key id"))?;
+        Ok(Self(bytes))
+    }
+}
+
+/// Derives the identifier of a key
+///
+/// The fingerprint is KMAC256 keyed with `key` under the [`labels::KEY_ID`]
+/// customization, truncated to [`KEY_ID_LEN`] bytes. It is unrelated to any
+/// OTP or subkey computed with the key and independent of the algorithm the
+/// key is used with. Its 64 bits tell a handful of keys apart; they are not
+/// meant to authenticate one.
+///
+/// # Example
+/// ```
+/// use passcode::kdf::key_fingerprint;
+///
+/// let id = key_fingerprint(&[7u8; 32]);
+/// assert_eq!(id.to_string().len(), 16);
+/// assert_eq!(id.to_string().parse(), Ok(id));
+/// ```
+pub fn key_fingerprint(key: &[u8]) -> KeyId {
+    let mut bytes = [0u8; KEY_ID_LEN];
+    bytes.copy_from_slice(&derive_subkey(key, labels::KEY_ID, b"", KEY_ID_LEN));
+    KeyId(bytes)
+}
+
+/// Derives a subkey with KMAC256
+///
+/// The label is used as the KMAC customization string, the context as the
+/// input and `len` is bound into the output, so requesting a different length
+/// produces an unrelated key rather than a prefix.
+///
+/// # Arguments
+/// * `master` - The master key
+/// * `label` - Domain-separation label (see [`labels`])
+/// * `context` - Identifier of the subkey, e.g. a user ID
+/// * `len` - Output length in bytes
+///
+/// # Example
+/// ```
+/// use passcode::kdf::{derive_subkey, labels};
+///
+/// let master = [7u8; 32];
+/// let alice = derive_subkey(&master, labels::USER, b"alice", 32);
+/// let bob = derive_subkey(&master, labels::USER, b"bob", 32);
+/// assert_ne!(alice, bob);
+/// ```
+pub fn derive_subkey(master: &[u8], label: &str, context: &[u8], len: usize) -> Vec<u8> {
+    sha3_kmac256(master, label.as_bytes(), context, len)
+}
+
+/// Derives a subkey with the BLAKE3 key derivation mode
+///
+/// The label is used as the BLAKE3 context string and the master key is
+/// length-prefixed before the context so the two cannot be confused. The
+/// output is unrelated to [`derive_subkey`] for the same inputs.
+pub fn derive_subkey_blake3(master: &[u8], label: &str, context: &[u8], len: usize) -> Vec<u8> {
+    let mut hasher = blake3::Hasher::new_derive_key(label);
+    hasher.update(&(master.len() as u64).to_le_bytes());
+    hasher.update(master);
+    hasher.update(context);
+
+    let mut output = vec![0u8; len];
+    hasher.finalize_xof().fill(&mut output);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_domain_separation() {
+        let master = [1u8; 32];
+
+        let user = derive_subkey(&master, labels::USER, b"id", 32);
+        let device = derive_subkey(&master, labels::DEVICE, b"id", 32);
+        assert_ne!(user, device);
+
+        let user_blake3 = derive_subkey_blake3(&master, labels::USER, b"id", 32);
+        let device_blake3 = derive_subkey_blake3(&master, labels::DEVICE, b"id", 32);
+        assert_ne!(user_blake3, device_blake3);
+        assert_ne!(user, user_blake3);
+    }
+
+    #[test]
+    fn test_deterministic_with_length() {
+        let master = [1u8; 32];
+
+        let a = derive_subkey(&master, labels::USER, b"alice", 32);
+        let b = derive_subkey(&master, labels::USER, b"alice", 32);
+        let long = derive_subkey(&master, labels::USER, b"alice", 64);
+
+        assert_eq!(a, b);
+        assert_eq!(long.len(), 64);
+        assert_ne!(&long[..32], &a[..]);
+    }
+
+    #[test]
+    fn test_key_fingerprint() {
+        let id = key_fingerprint(&[1u8; 32]);
+        assert_eq!(id, key_fingerprint(&[1u8; 32]));
+        assert_ne!(id, key_fingerprint(&[2u8; 32]));
+        assert_ne!(
+            id.as_bytes()[..],
+            derive_subkey(&[1u8; 32], labels::USER, b"", KEY_ID_LEN)[..]
+        );
+
+        assert_eq!(id.to_string().parse::<KeyId>(), Ok(id));
+        assert!("abc".parse::<KeyId>().is_err());
+        assert!("zz".repeat(KEY_ID_LEN).parse::<KeyId>().is_err());
+    }
+}
@@ -0,0 +1,98 @@
+//! OS keychain storage (feature `keyring`)
+//!
+//! Client apps keep the shared secret in the platform's credential store
+//! instead of a file: the macOS Keychain, the Windows Credential Manager or
+//! the Secret Service on Linux desktops, through the `keyring` crate. The
+//! entry holds the key as a [`KeyFile`], so the algorithm is stored with it
+//! and the Key-ID is checked on load.
+
+use keyring::Entry;
+
+use crate::keyfile::KeyFile;
+use crate::{Error, Passcode};
+
+impl Passcode {
+    /// Loads the key stored under `service` and `account` in the OS
+    /// keychain
+    ///
+    /// Fails with [`Error::Keychain`] if the keychain is unavailable or has
+    /// no such entry, [`Error::MalformedMessage`] if the entry is not a key
+    /// written by [`store_in_keyring`](Self::store_in_keyring), and like
+    /// [`Passcode::try_new`] for a key the policy rejects.
+    pub fn load_from_keyring(service: &str, account: &str) -> Result<Self, Error> {
+        load(&entry(service, account)?)
+    }
+
+    /// Stores the key under `service` and `account` in the OS keychain,
+    /// replacing any key stored there
+    ///
+    /// Output formatting options are not stored.
+    pub fn store_in_keyring(&self, service: &str, account: &str) -> Result<(), Error> {
+        store(&entry(service, account)?, self)
+    }
+
+    /// Deletes the key stored under `service` and `account` from the OS
+    /// keychain
+    pub fn delete_from_keyring(service: &str, account: &str) -> Result<(), Error> {
+        entry(service, account)?
+            .delete_credential()
+            .map_err(keychain_error)
+    }
+}
+
+fn entry(service: &str, account: &str) -> Result<Entry, Error> {
+    Entry::new(service, account).map_err(keychain_error)
+}
+
+fn load(entry: &Entry) -> Result<Passcode, Error> {
+    let text = entry.get_password().map_err(keychain_error)?;
+    text.parse::<KeyFile>()?.to_passcode()
+}
+
+fn store(entry: &Entry, passcode: &Passcode) -> Result<(), Error> {
+    entry
+        .set_password(&KeyFile::from_passcode(passcode).to_string())
+        .map_err(keychain_error)
+}
+
+fn keychain_error(error: keyring::Error) -> Error {
+    Error::Keychain(error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Algorithm;
+    use keyring::mock::MockCredential;
+
+    fn mock_entry() -> Entry {
+        Entry::new_with_credential(Box::new(MockCredential::default()))
+    }
+
+    #[test]
+    fn test_store_and_load() {
+        let entry = mock_entry();
+        assert!(matches!(load(&entry), Err(Error::Keychain(_))));
+
+        let passcode = Passcode::new(Algorithm::Blake3KeyedMode256, vec![5u8; 32]);
+        store(&entry, &passcode).unwrap();
+        assert!(entry
+            .get_password()
+            .unwrap()
+            .starts_with("-----BEGIN PASSCODE KEY-----"));
+
+        let loaded = load(&entry).unwrap();
+        assert_eq!(loaded.algorithm(), Algorithm::Blake3KeyedMode256);
+        assert_eq!(loaded.key_id(), passcode.key_id());
+    }
+
+    #[test]
+    fn test_rejects_foreign_entries() {
+        let entry = mock_entry();
+        entry.set_password("not a key").unwrap();
+        assert_eq!(
+            load(&entry).err(),
+            Some(Error::MalformedMessage("missing BEGIN PASSCODE KEY line"))
+        );
+    }
+}
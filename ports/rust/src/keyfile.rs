@@ -0,0 +1,382 @@
+//! Armored key files
+//!
+//! A [`KeyFile`] stores one key as PEM-like text, so secrets can be kept in
+//! files, diffed, checked into a secret manager and handled with standard
+//! tooling. Headers name the algorithm, the [`KeyId`] and the creation time
+//! in RFC 3339 UTC; the body is the key in base64 wrapped at 64 columns:
+//!
+//! ```text
+//! -----BEGIN PASSCODE KEY-----
+//! Algorithm: SHA3-KMAC-256
+//! Key-ID: 3f9a0c81d0e4b275
+//! Created: 2026-10-16T09:30:00Z
+//!
+//! AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=
+//! -----END PASSCODE KEY-----
+//! ```
+//!
+//! Parsing checks that the Key-ID matches the key, so a truncated or edited
+//! body is caught before the key is used. Unknown headers are ignored.
+//!
+//! # Example
+//! ```
+//! use passcode::keyfile::KeyFile;
+//! use passcode::{Algorithm, Passcode};
+//!
+//! let passcode = Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+//! let text = KeyFile::from_passcode(&passcode).to_string();
+//!
+//! let file: KeyFile = text.parse().unwrap();
+//! assert_eq!(file.key_id(), passcode.key_id());
+//! assert_eq!(file.to_passcode().unwrap().compute(b"data"), passcode.compute(b"data"));
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64ct::{Base64, Encoding};
+
+use crate::kdf::{key_fingerprint, KeyId};
+use crate::{Algorithm, Error, Passcode};
+
+/// First line of a key file
+pub const BEGIN_LINE: &str = "-----BEGIN PASSCODE KEY-----";
+
+/// Last line of a key file
+pub const END_LINE: &str = "-----END PASSCODE KEY-----";
+
+/// Width of the base64 body lines
+const LINE_WIDTH: usize = 64;
+
+/// A key with the metadata stored next to it
+#[derive(Clone, PartialEq, Eq)]
+pub struct KeyFile {
+    algorithm: Algorithm,
+    key: Vec<u8>,
+    created_at: SystemTime,
+}
+
+impl fmt::Debug for KeyFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The key is never printed
+        f.debug_struct("KeyFile")
+            .field("algorithm", &self.algorithm)
+            .field("key_id", &self.key_id())
+            .field("created_at", &self.created_at)
+            .finish()
+    }
+}
+
+impl KeyFile {
+    /// Wraps a key, created now
+    ///
+    /// The creation time is kept with one-second precision.
+    pub fn new(algorithm: Algorithm, key: Vec<u8>) -> Self {
+        Self {
+            algorithm,
+            key,
+            created_at: truncate(SystemTime::now()),
+        }
+    }
+
+    /// Wraps the key of a passcode, created now
+    pub fn from_passcode(passcode: &Passcode) -> Self {
+        Self::new(passcode.algorithm(), passcode.key().to_vec())
+    }
+
+    /// Sets the creation time, truncated to whole seconds
+    pub fn with_created_at(mut self, created_at: SystemTime) -> Self {
+        self.created_at = truncate(created_at);
+        self
+    }
+
+    /// Gets the algorithm
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// Gets the secret key
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// Gets the fingerprint of the key
+    pub fn key_id(&self) -> KeyId {
+        key_fingerprint(&self.key)
+    }
+
+    /// Gets the creation time
+    pub fn created_at(&self) -> SystemTime {
+        self.created_at
+    }
+
+    /// Checks that [`Passcode::try_new`] accepts the key, i.e. that it is
+    /// long enough and allowed by the default policy
+    pub fn validate(&self) -> Result<(), Error> {
+        self.to_passcode().map(|_| ())
+    }
+
+    /// Builds a passcode with the key, checked like [`Passcode::try_new`]
+    pub fn to_passcode(&self) -> Result<Passcode, Error> {
+        Passcode::try_new(self.algorithm, self.key.clone())
+    }
+}
+
+impl fmt::Display for KeyFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", BEGIN_LINE)?;
+        writeln!(f, "Algorithm: {}", self.algorithm.as_str())?;
+        writeln!(f, "Key-ID: {}", self.key_id())?;
+        writeln!(f, "Created: {}", format_rfc3339(self.created_at))?;
+        writeln!(f)?;
+        let body = Base64::encode_string(&self.key);
+        for line in body.as_bytes().chunks(LINE_WIDTH) {
+            writeln!(f, "{}", std::str::from_utf8(line).expect("base64 is ASCII"))?;
+        }
+        writeln!(f, "{}", END_LINE)
+    }
+}
+
+impl FromStr for KeyFile {
+    type Err = Error;
+
+    /// Parses a key file, failing with [`Error::MalformedMessage`] for a
+    /// missing header, an unknown algorithm, invalid base64 or a Key-ID that
+    /// does not match the key
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let mut lines = s.trim().lines().map(str::trim_end);
+        if lines.next() != Some(BEGIN_LINE) {
+            return Err(Error::MalformedMessage("missing BEGIN PASSCODE KEY line"));
+        }
+
+        let mut algorithm = None;
+        let mut key_id = None;
+        let mut created_at = None;
+        for line in lines.by_ref() {
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line
+                .split_once(':')
+                .ok_or(Error::MalformedMessage("invalid key file header"))?;
+            let value = value.trim();
+            match name {
+                "Algorithm" => {
+                    algorithm = Some(
+                        Algorithm::all()
+                            .find(|algorithm| algorithm.as_str() == value)
+                            .ok_or(Error::MalformedMessage("unknown algorithm"))?,
+                    )
+                }
+                "Key-ID" => key_id = Some(value.parse::<KeyId>()?),
+                "Created" => created_at = Some(parse_rfc3339(value)?),
+                _ => {}
+            }
+        }
+
+        let mut body = String::new();
+        let mut ended = false;
+        for line in lines.by_ref() {
+            if line == END_LINE {
+                ended = true;
+                break;
+            }
+            body.push_str(line.trim());
+        }
+        if !ended || lines.next().is_some() {
+            return Err(Error::MalformedMessage("missing END PASSCODE KEY line"));
+        }
+
+        let key =
+            Base64::decode_vec(&body).map_err(|_| Error::MalformedMessage("invalid base64"))?;
+        let key_id = key_id.ok_or(Error::MalformedMessage("missing Key-ID header"))?;
+        let file = Self {
+            algorithm: algorithm.ok_or(Error::MalformedMessage("missing Algorithm header"))?,
+            key,
+            created_at: created_at.ok_or(Error::MalformedMessage("missing Created header"))?,
+        };
+        if file.key_id() != key_id {
+            return Err(Error::MalformedMessage("Key-ID does not match the key"));
+        }
+        Ok(file)
+    }
+}
+
+fn truncate(time: SystemTime) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(seconds(time))
+}
+
+fn seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Formats a time as `YYYY-MM-DDTHH:MM:SSZ`
+fn format_rfc3339(time: SystemTime) -> String {
+    let secs = seconds(time);
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let rest = secs % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest / 60 % 60,
+        rest % 60
+    )
+}
+
+/// Parses the output of [`format_rfc3339`]
+fn parse_rfc3339(text: &str) -> Result<SystemTime, Error> {
+    const INVALID: Error = Error::MalformedMessage("invalid Created time");
+
+    let bytes = text.as_bytes();
+    if bytes.len() != 20
+        || [
+            (4, b'-'),
+            (7, b'-'),
+            (10, b'T'),
+            (13, b':'),
+            (16, b':'),
+            (19, b'Z'),
+        ]
+        .iter()
+        .any(|&(at, separator)| bytes[at] != separator)
+    {
+        return Err(INVALID);
+    }
+    let number = |range: std::ops::Range<usize>| -> Result<i64, Error> {
+        let digits = &text[range];
+        if !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(INVALID);
+        }
+        digits.parse().map_err(|_| INVALID)
+    };
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+
+    let days = days_from_civil(year, month, day);
+    if year < 1970
+        || !(1..=12).contains(&month)
+        || civil_from_days(days) != (year, month, day)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        return Err(INVALID);
+    }
+    let secs = days * 86_400 + hour * 3600 + minute * 60 + second;
+    Ok(UNIX_EPOCH + Duration::from_secs(secs as u64))
+}
+
+/// Converts days since the Unix epoch to a proleptic Gregorian date
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Converts a proleptic Gregorian date to days since the Unix epoch
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CREATED: u64 = 1_792_143_000; // 2026-10-16T09:30:00Z
+
+    fn file() -> KeyFile {
+        KeyFile::new(Algorithm::Sha3Kmac256, vec![1u8; 32])
+            .with_created_at(UNIX_EPOCH + Duration::from_millis(CREATED * 1000 + 999))
+    }
+
+    #[test]
+    fn test_layout() {
+        let text = file().to_string();
+        let expected = format!(
+            "{}\nAlgorithm: SHA3-KMAC-256\nKey-ID: {}\nCreated: 2026-10-16T09:30:00Z\n\n\
+             AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=\n{}\n",
+            BEGIN_LINE,
+            key_fingerprint(&[1u8; 32]),
+            END_LINE
+        );
+        assert_eq!(text, expected);
+        assert_eq!(text.parse::<KeyFile>(), Ok(file()));
+        assert_eq!(
+            file().created_at(),
+            UNIX_EPOCH + Duration::from_secs(CREATED)
+        );
+    }
+
+    #[test]
+    fn test_long_keys_wrap() {
+        let file = KeyFile::new(Algorithm::Sha3Kmac512, vec![2u8; 64]);
+        let text = file.to_string();
+        assert!(text.lines().all(|line| line.len() <= LINE_WIDTH));
+        assert_eq!(text.parse::<KeyFile>(), Ok(file));
+    }
+
+    #[test]
+    fn test_rejects_tampering() {
+        let text = file().to_string();
+
+        let edited = text.replace("AQEBAQEB", "AgEBAQEB");
+        assert_eq!(
+            edited.parse::<KeyFile>(),
+            Err(Error::MalformedMessage("Key-ID does not match the key"))
+        );
+        let algorithm = text.replace("SHA3-KMAC-256", "SHA3-KMAC-1024");
+        assert_eq!(
+            algorithm.parse::<KeyFile>(),
+            Err(Error::MalformedMessage("unknown algorithm"))
+        );
+        let truncated = &text[..text.len() - END_LINE.len() - 1];
+        assert!(truncated.parse::<KeyFile>().is_err());
+        let date = text.replace("2026-10-16", "2026-02-30");
+        assert_eq!(
+            date.parse::<KeyFile>(),
+            Err(Error::MalformedMessage("invalid Created time"))
+        );
+
+        // Unknown headers and surrounding whitespace are accepted
+        let extended = text.replace("Created:", "Comment: ops\nCreated:");
+        assert_eq!(format!("\n{}\n", extended).parse::<KeyFile>(), Ok(file()));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(file().validate(), Ok(()));
+        assert_eq!(
+            KeyFile::new(Algorithm::Sha3Kmac256, vec![1u8; 8]).validate(),
+            Err(Error::KeyTooShort { min: 32, actual: 8 })
+        );
+    }
+
+    #[test]
+    fn test_civil_dates() {
+        for (days, date) in [
+            (0, (1970, 1, 1)),
+            (11_016, (2000, 2, 29)),
+            (20_742, (2026, 10, 16)),
+            (47_540, (2100, 2, 28)),
+        ] {
+            assert_eq!(civil_from_days(days), date);
+            assert_eq!(days_from_civil(date.0, date.1, date.2), days);
+        }
+    }
+}
@@ -0,0 +1,255 @@
+//! Key metadata and lifecycle
+//!
+//! A [`KeyRecord`] is the non-secret row a server keeps next to each key:
+//! which key it is, its algorithm, when it was created, when it expires and
+//! where it is in its lifecycle. Keys move one way through the
+//! [`KeyState`]s:
+//!
+//! ```text
+//! Active ──> GracePeriod ──> Revoked
+//!    └──────────────────────────^
+//! ```
+//!
+//! A key in its grace period still verifies until its expiry, which lets
+//! clients move to a new key; a revoked or expired key never verifies.
+//! [`KeyRing::insert_with_record`](crate::keyring::KeyRing::insert_with_record)
+//! attaches a record to a user's key, and the verifier then rejects
+//! responses and session tokens made with a key the record no longer
+//! allows.
+//!
+//! # Example
+//! ```
+//! use std::time::{Duration, SystemTime};
+//!
+//! use passcode::keyrecord::{KeyRecord, KeyState};
+//! use passcode::{Algorithm, Passcode};
+//!
+//! let now = SystemTime::now();
+//! let passcode = Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+//! let mut record = KeyRecord::from_passcode(&passcode, now);
+//!
+//! record.begin_grace_period(now + Duration::from_secs(3600)).unwrap();
+//! assert_eq!(record.state(), KeyState::GracePeriod);
+//! assert!(record.is_usable_at(now));
+//! assert!(!record.is_usable_at(now + Duration::from_secs(3600)));
+//!
+//! record.revoke().unwrap();
+//! assert!(!record.is_usable_at(now));
+//! assert!(record.revoke().is_err());
+//! ```
+
+use std::fmt;
+use std::time::SystemTime;
+
+use crate::kdf::KeyId;
+use crate::{Algorithm, Error, Passcode};
+
+/// Lifecycle state of a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyState {
+    /// The key is in use
+    Active,
+    /// The key is being replaced and verifies until it expires
+    GracePeriod,
+    /// The key no longer verifies
+    Revoked,
+}
+
+impl KeyState {
+    /// Gets the name of the state, `active`, `grace_period` or `revoked`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KeyState::Active => "active",
+            KeyState::GracePeriod => "grace_period",
+            KeyState::Revoked => "revoked",
+        }
+    }
+
+    /// Parses a name returned by [`as_str`](Self::as_str)
+    pub fn from_name(name: &str) -> Option<Self> {
+        [KeyState::Active, KeyState::GracePeriod, KeyState::Revoked]
+            .into_iter()
+            .find(|state| state.as_str() == name)
+    }
+}
+
+impl fmt::Display for KeyState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Metadata of one key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyRecord {
+    key_id: KeyId,
+    algorithm: Algorithm,
+    created_at: SystemTime,
+    expires_at: Option<SystemTime>,
+    state: KeyState,
+}
+
+impl KeyRecord {
+    /// Creates the record of an active key without an expiry
+    pub fn new(key_id: KeyId, algorithm: Algorithm, created_at: SystemTime) -> Self {
+        Self {
+            key_id,
+            algorithm,
+            created_at,
+            expires_at: None,
+            state: KeyState::Active,
+        }
+    }
+
+    /// Creates the record of an active key for `passcode`
+    pub fn from_passcode(passcode: &Passcode, created_at: SystemTime) -> Self {
+        Self::new(passcode.key_id(), passcode.algorithm(), created_at)
+    }
+
+    /// Sets the time the key stops verifying
+    pub fn with_expires_at(mut self, expires_at: SystemTime) -> Self {
+        self.expires_at = Some(expires_at);
+        self
+    }
+
+    /// Sets the state, e.g. when loading a stored record
+    pub fn with_state(mut self, state: KeyState) -> Self {
+        self.state = state;
+        self
+    }
+
+    /// Gets the fingerprint of the key
+    pub fn key_id(&self) -> KeyId {
+        self.key_id
+    }
+
+    /// Gets the algorithm of the key
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// Gets the time the key was created
+    pub fn created_at(&self) -> SystemTime {
+        self.created_at
+    }
+
+    /// Gets the time the key stops verifying, if any
+    pub fn expires_at(&self) -> Option<SystemTime> {
+        self.expires_at
+    }
+
+    /// Gets the lifecycle state
+    pub fn state(&self) -> KeyState {
+        self.state
+    }
+
+    /// Moves an active key into its grace period, ending at `ends_at`
+    ///
+    /// An earlier expiry is kept. Fails with
+    /// [`Error::InvalidKeyTransition`] unless the key is active.
+    pub fn begin_grace_period(&mut self, ends_at: SystemTime) -> Result<(), Error> {
+        self.transition(KeyState::GracePeriod)?;
+        self.expires_at = Some(self.expires_at.map_or(ends_at, |at| at.min(ends_at)));
+        Ok(())
+    }
+
+    /// Revokes the key
+    ///
+    /// Fails with [`Error::InvalidKeyTransition`] if it is already revoked.
+    pub fn revoke(&mut self) -> Result<(), Error> {
+        self.transition(KeyState::Revoked)
+    }
+
+    /// Returns true if the key verifies at `now`: it is not revoked and has
+    /// not expired
+    pub fn is_usable_at(&self, now: SystemTime) -> bool {
+        self.state != KeyState::Revoked && self.expires_at.is_none_or(|at| now < at)
+    }
+
+    fn transition(&mut self, to: KeyState) -> Result<(), Error> {
+        let allowed = matches!(
+            (self.state, to),
+            (KeyState::Active, KeyState::GracePeriod)
+                | (KeyState::Active, KeyState::Revoked)
+                | (KeyState::GracePeriod, KeyState::Revoked)
+        );
+        if !allowed {
+            return Err(Error::InvalidKeyTransition {
+                from: self.state,
+                to,
+            });
+        }
+        self.state = to;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn active() -> KeyRecord {
+        let passcode = Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+        KeyRecord::from_passcode(&passcode, at(100))
+    }
+
+    #[test]
+    fn test_transitions() {
+        let mut record = active();
+        assert_eq!(record.state(), KeyState::Active);
+        assert!(record.is_usable_at(at(1_000_000)));
+
+        record.begin_grace_period(at(200)).unwrap();
+        assert_eq!(record.expires_at(), Some(at(200)));
+        assert_eq!(
+            record.begin_grace_period(at(300)),
+            Err(Error::InvalidKeyTransition {
+                from: KeyState::GracePeriod,
+                to: KeyState::GracePeriod
+            })
+        );
+        assert!(record.is_usable_at(at(199)));
+        assert!(!record.is_usable_at(at(200)));
+
+        record.revoke().unwrap();
+        assert!(!record.is_usable_at(at(150)));
+        assert!(record.revoke().is_err());
+        assert!(record.begin_grace_period(at(300)).is_err());
+
+        // Active keys can be revoked directly
+        let mut compromised = active();
+        compromised.revoke().unwrap();
+        assert_eq!(compromised.state(), KeyState::Revoked);
+    }
+
+    #[test]
+    fn test_expiry() {
+        let mut record = active().with_expires_at(at(150));
+        assert!(!record.is_usable_at(at(150)));
+
+        // The grace period does not extend an earlier expiry
+        record.begin_grace_period(at(300)).unwrap();
+        assert_eq!(record.expires_at(), Some(at(150)));
+    }
+
+    #[test]
+    fn test_state_names() {
+        for state in [KeyState::Active, KeyState::GracePeriod, KeyState::Revoked] {
+            assert_eq!(KeyState::from_name(state.as_str()), Some(state));
+        }
+        assert_eq!(KeyState::from_name("expired"), None);
+        assert_eq!(
+            Error::InvalidKeyTransition {
+                from: KeyState::Revoked,
+                to: KeyState::GracePeriod
+            }
+            .to_string(),
+            "cannot move key from revoked to grace_period"
+        );
+    }
+}
@@ -0,0 +1,539 @@
+//! Per-user OTP keys
+//!
+//! Servers verify many users, each with their own secret. [`KeyRing`] maps
+//! user IDs to configured [`Passcode`] instances and can be updated while
+//! verifiers hold it. A user with several devices can give each one a key
+//! derived from theirs with [`Passcode::for_device`]; registered devices are
+//! verified individually and can be revoked one at a time.
+//!
+//! Instead of storing a key per user, a server can hold one master key and
+//! derive each user's key from it with [`KeyRing::derive_from_master`]. A
+//! key ring created with [`KeyRing::with_master`] does so on lookup for
+//! users without a stored key.
+//!
+//! A user's key can carry a [`KeyRecord`]; expired and revoked keys are then
+//! no longer offered to the verifier.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::SystemTime;
+
+use crate::kdf::labels;
+use crate::keyrecord::KeyRecord;
+use crate::{Error, Passcode};
+
+/// Thread-safe map from user ID to that user's [`Passcode`]
+///
+/// # Example
+/// ```
+/// use passcode::keyring::KeyRing;
+/// use passcode::{Algorithm, Passcode};
+///
+/// let keyring = KeyRing::new();
+/// keyring.insert("alice", Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]));
+///
+/// assert!(keyring.get("alice").is_some());
+/// assert!(keyring.get("bob").is_none());
+/// ```
+#[derive(Default)]
+pub struct KeyRing {
+    keys: RwLock<HashMap<String, Entry>>,
+    master: Option<Arc<Passcode>>,
+}
+
+/// A user's key and its record and, once the first device is registered,
+/// the keys of their devices
+struct Entry {
+    passcode: Arc<Passcode>,
+    record: Option<KeyRecord>,
+    devices: Option<BTreeMap<String, Arc<Passcode>>>,
+}
+
+impl Entry {
+    fn register(&mut self, device_id: &str) {
+        let passcode = Arc::new(self.passcode.for_device(device_id));
+        self.devices
+            .get_or_insert_with(BTreeMap::new)
+            .insert(device_id.to_string(), passcode);
+    }
+}
+
+impl fmt::Debug for KeyRing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Keys are never printed
+        f.debug_struct("KeyRing")
+            .field("users", &self.len())
+            .field("master", &self.master.is_some())
+            .finish()
+    }
+}
+
+impl KeyRing {
+    /// Creates an empty key ring
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a key ring that derives the key of every user without a
+    /// stored key from `master`
+    ///
+    /// # Example
+    /// ```
+    /// use passcode::keyring::KeyRing;
+    /// use passcode::{Algorithm, Passcode};
+    ///
+    /// let master = Passcode::new(Algorithm::Sha3Kmac256, vec![7u8; 32]);
+    /// let alice = KeyRing::derive_from_master(&master, "alice");
+    ///
+    /// let keyring = KeyRing::with_master(master);
+    /// assert_eq!(keyring.get("alice").unwrap().key_id(), alice.key_id());
+    /// assert!(keyring.is_empty());
+    /// ```
+    pub fn with_master(master: impl Into<Arc<Passcode>>) -> Self {
+        Self {
+            keys: RwLock::default(),
+            master: Some(master.into()),
+        }
+    }
+
+    /// Derives a user's passcode from a server master key
+    ///
+    /// The key has the length of the master key and is derived with the
+    /// master's algorithm family under the [`labels::USER`] label: KMAC for
+    /// the SHA3 algorithms and the BLAKE3 key derivation mode for BLAKE3.
+    /// The same master and user ID always yield the same key, and different
+    /// user IDs yield unrelated keys. The passcode keeps the master's
+    /// algorithm, format, grouping and canonicalization.
+    pub fn derive_from_master(master: &Passcode, user_id: &str) -> Passcode {
+        master.with_key(master.derive_labeled(labels::USER, user_id.as_bytes(), master.key().len()))
+    }
+
+    /// Sets the user's passcode, returning the one it replaces
+    ///
+    /// Registered devices stay registered and get keys derived from the new
+    /// passcode, so each of them must be provisioned again.
+    pub fn insert(
+        &self,
+        user_id: impl Into<String>,
+        passcode: impl Into<Arc<Passcode>>,
+    ) -> Option<Arc<Passcode>> {
+        self.insert_entry(user_id.into(), passcode.into(), None)
+    }
+
+    /// Sets the user's passcode with its record, returning the passcode it
+    /// replaces
+    ///
+    /// Fails with [`Error::InvalidFormat`] if the record belongs to another
+    /// key. Like [`insert`](Self::insert), registered devices stay
+    /// registered; they share the record of the user's key.
+    pub fn insert_with_record(
+        &self,
+        user_id: impl Into<String>,
+        passcode: impl Into<Arc<Passcode>>,
+        record: KeyRecord,
+    ) -> Result<Option<Arc<Passcode>>, Error> {
+        let passcode = passcode.into();
+        if record.key_id() != passcode.key_id() {
+            return Err(Error::InvalidFormat("key record does not match the key"));
+        }
+        Ok(self.insert_entry(user_id.into(), passcode, Some(record)))
+    }
+
+    /// Gets the record of the user's key, if it has one
+    pub fn record(&self, user_id: &str) -> Option<KeyRecord> {
+        self.read()
+            .get(user_id)
+            .and_then(|entry| entry.record.clone())
+    }
+
+    /// Applies a state transition to the record of the user's key, returning
+    /// false if the key has no record
+    ///
+    /// # Example
+    /// ```
+    /// use std::time::SystemTime;
+    ///
+    /// use passcode::keyrecord::KeyRecord;
+    /// use passcode::keyring::KeyRing;
+    /// use passcode::{Algorithm, Passcode};
+    ///
+    /// let keyring = KeyRing::new();
+    /// let passcode = Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+    /// let record = KeyRecord::from_passcode(&passcode, SystemTime::now());
+    /// keyring.insert_with_record("alice", passcode, record).unwrap();
+    ///
+    /// assert!(keyring.update_record("alice", KeyRecord::revoke).unwrap());
+    /// assert!(!keyring.is_usable("alice", SystemTime::now()));
+    /// ```
+    pub fn update_record(
+        &self,
+        user_id: &str,
+        update: impl FnOnce(&mut KeyRecord) -> Result<(), Error>,
+    ) -> Result<bool, Error> {
+        let mut keys = self.write();
+        let Some(record) = keys
+            .get_mut(user_id)
+            .and_then(|entry| entry.record.as_mut())
+        else {
+            return Ok(false);
+        };
+        update(record)?;
+        Ok(true)
+    }
+
+    /// Returns true if the user has a key and its record, if any, lets it
+    /// verify at `now`
+    pub fn is_usable(&self, user_id: &str, now: SystemTime) -> bool {
+        match self.read().get(user_id) {
+            Some(entry) => entry
+                .record
+                .as_ref()
+                .is_none_or(|record| record.is_usable_at(now)),
+            None => self.master.is_some(),
+        }
+    }
+
+    fn insert_entry(
+        &self,
+        user_id: String,
+        passcode: Arc<Passcode>,
+        record: Option<KeyRecord>,
+    ) -> Option<Arc<Passcode>> {
+        let mut entry = Entry {
+            passcode,
+            record,
+            devices: None,
+        };
+        let mut keys = self.write();
+        let old = keys.remove(&user_id);
+        if let Some(devices) = old.as_ref().and_then(|old| old.devices.as_ref()) {
+            entry.devices = Some(BTreeMap::new());
+            for device_id in devices.keys() {
+                entry.register(device_id);
+            }
+        }
+        keys.insert(user_id, entry);
+        old.map(|old| old.passcode)
+    }
+
+    /// Sets the user's passcode unless they already have a stored key,
+    /// returning whether it was set
+    pub(crate) fn insert_new(&self, user_id: &str, passcode: Arc<Passcode>) -> bool {
+        let mut keys = self.write();
+        if keys.contains_key(user_id) {
+            return false;
+        }
+        let entry = Entry {
+            passcode,
+            record: None,
+            devices: None,
+        };
+        keys.insert(user_id.to_string(), entry);
+        true
+    }
+
+    /// Removes the user's passcode and devices, returning the passcode if
+    /// present
+    pub fn remove(&self, user_id: &str) -> Option<Arc<Passcode>> {
+        self.write().remove(user_id).map(|entry| entry.passcode)
+    }
+
+    /// Gets the user's passcode
+    ///
+    /// Derives it from the master key if the user has no stored key and the
+    /// key ring has one.
+    pub fn get(&self, user_id: &str) -> Option<Arc<Passcode>> {
+        self.read()
+            .get(user_id)
+            .map(|entry| Arc::clone(&entry.passcode))
+            .or_else(|| self.derived(user_id))
+    }
+
+    /// Registers a device of the user, returning false if the user has no
+    /// passcode
+    ///
+    /// The device answers with the key from
+    /// [`Passcode::derive_device_key`]. Once a user has registered a device,
+    /// only registered devices are accepted for them; the user's own key no
+    /// longer verifies, even after every device is revoked. A user whose key
+    /// is derived from the master key is stored from then on.
+    pub fn register_device(&self, user_id: &str, device_id: &str) -> bool {
+        let mut keys = self.write();
+        if !keys.contains_key(user_id) {
+            let Some(passcode) = self.derived(user_id) else {
+                return false;
+            };
+            keys.insert(
+                user_id.to_string(),
+                Entry {
+                    passcode,
+                    record: None,
+                    devices: None,
+                },
+            );
+        }
+        keys.get_mut(user_id)
+            .expect("entry was inserted")
+            .register(device_id);
+        true
+    }
+
+    /// Revokes one of the user's devices, returning true if it was
+    /// registered
+    pub fn revoke_device(&self, user_id: &str, device_id: &str) -> bool {
+        self.write()
+            .get_mut(user_id)
+            .and_then(|entry| entry.devices.as_mut())
+            .is_some_and(|devices| devices.remove(device_id).is_some())
+    }
+
+    /// Returns the IDs of the user's registered devices in sorted order
+    pub fn devices(&self, user_id: &str) -> Vec<String> {
+        self.read()
+            .get(user_id)
+            .and_then(|entry| entry.devices.as_ref())
+            .map_or_else(Vec::new, |devices| devices.keys().cloned().collect())
+    }
+
+    /// Returns the keys a response from the user may be computed with, each
+    /// with the device it belongs to
+    ///
+    /// Without registered devices this is the user's own key. Otherwise it
+    /// is the named device's key, or every device's key when no device is
+    /// named. There are none if the key's record does not allow it at
+    /// `now`.
+    pub(crate) fn candidates(
+        &self,
+        user_id: &str,
+        device_id: Option<&str>,
+        now: SystemTime,
+    ) -> Vec<(Option<String>, Arc<Passcode>)> {
+        let keys = self.read();
+        let Some(entry) = keys.get(user_id) else {
+            return self
+                .derived(user_id)
+                .map(|passcode| vec![(None, passcode)])
+                .unwrap_or_default();
+        };
+        if let Some(record) = &entry.record {
+            if !record.is_usable_at(now) {
+                return Vec::new();
+            }
+        }
+        let Some(devices) = &entry.devices else {
+            return vec![(None, Arc::clone(&entry.passcode))];
+        };
+        devices
+            .iter()
+            .filter(|(id, _)| device_id.is_none_or(|device_id| device_id == id.as_str()))
+            .map(|(id, passcode)| (Some(id.clone()), Arc::clone(passcode)))
+            .collect()
+    }
+
+    /// Returns true if the user has a passcode
+    ///
+    /// Always true for a key ring with a master key.
+    pub fn contains(&self, user_id: &str) -> bool {
+        self.master.is_some() || self.read().contains_key(user_id)
+    }
+
+    /// Returns the number of users with a stored key
+    pub fn len(&self) -> usize {
+        self.read().len()
+    }
+
+    /// Returns true when no user has a stored key
+    pub fn is_empty(&self) -> bool {
+        self.read().is_empty()
+    }
+
+    fn derived(&self, user_id: &str) -> Option<Arc<Passcode>> {
+        self.master
+            .as_ref()
+            .map(|master| Arc::new(Self::derive_from_master(master, user_id)))
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, HashMap<String, Entry>> {
+        // A panic while holding the lock cannot leave the map inconsistent
+        self.keys
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, HashMap<String, Entry>> {
+        self.keys
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Algorithm;
+
+    #[test]
+    fn test_insert_replace_remove() {
+        let keyring = KeyRing::new();
+        assert!(keyring.is_empty());
+
+        assert!(keyring
+            .insert(
+                "alice",
+                Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32])
+            )
+            .is_none());
+        let old = keyring
+            .insert(
+                "alice",
+                Passcode::new(Algorithm::Blake3KeyedMode256, vec![2u8; 32]),
+            )
+            .unwrap();
+        assert_eq!(old.algorithm(), Algorithm::Sha3Kmac256);
+        assert_eq!(
+            keyring.get("alice").unwrap().algorithm(),
+            Algorithm::Blake3KeyedMode256
+        );
+        assert_eq!(keyring.len(), 1);
+
+        assert!(keyring.remove("alice").is_some());
+        assert!(!keyring.contains("alice"));
+    }
+
+    #[test]
+    fn test_devices() {
+        let keyring = KeyRing::new();
+        let account = || Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+        assert!(!keyring.register_device("alice", "phone"));
+
+        keyring.insert("alice", account());
+        let candidates = keyring.candidates("alice", None, SystemTime::now());
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].0, None);
+
+        assert!(keyring.register_device("alice", "phone"));
+        assert!(keyring.register_device("alice", "laptop"));
+        assert_eq!(keyring.devices("alice"), ["laptop", "phone"]);
+        let phone = keyring.candidates("alice", Some("phone"), SystemTime::now());
+        assert_eq!(phone.len(), 1);
+        assert_eq!(phone[0].0.as_deref(), Some("phone"));
+        assert_eq!(
+            phone[0].1.compute(b"data"),
+            account().for_device("phone").compute(b"data")
+        );
+
+        // Rotating the user's key re-derives the device keys
+        keyring.insert(
+            "alice",
+            Passcode::new(Algorithm::Sha3Kmac256, vec![2u8; 32]),
+        );
+        assert_eq!(keyring.devices("alice"), ["laptop", "phone"]);
+        assert_ne!(
+            keyring.candidates("alice", Some("phone"), SystemTime::now())[0]
+                .1
+                .compute(b"data"),
+            phone[0].1.compute(b"data")
+        );
+
+        assert!(keyring.revoke_device("alice", "phone"));
+        assert!(!keyring.revoke_device("alice", "phone"));
+        assert!(keyring.revoke_device("alice", "laptop"));
+        assert!(keyring
+            .candidates("alice", None, SystemTime::now())
+            .is_empty());
+        assert!(keyring
+            .candidates("bob", None, SystemTime::now())
+            .is_empty());
+    }
+
+    #[test]
+    fn test_records() {
+        let keyring = KeyRing::new();
+        let passcode = || Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+        let created = SystemTime::UNIX_EPOCH;
+        let later = created + std::time::Duration::from_secs(60);
+        let record = KeyRecord::from_passcode(&passcode(), created);
+
+        let other = Passcode::new(Algorithm::Sha3Kmac256, vec![2u8; 32]);
+        assert!(keyring
+            .insert_with_record("alice", other, record.clone())
+            .is_err());
+        keyring
+            .insert_with_record("alice", passcode(), record.clone())
+            .unwrap();
+        assert_eq!(keyring.record("alice"), Some(record));
+        assert!(keyring.is_usable("alice", later));
+        assert!(!keyring.is_usable("bob", later));
+
+        keyring.register_device("alice", "phone");
+        assert!(keyring
+            .update_record("alice", |record| record.begin_grace_period(later))
+            .unwrap());
+        assert_eq!(keyring.candidates("alice", None, created).len(), 1);
+        assert!(keyring.candidates("alice", None, later).is_empty());
+        assert!(!keyring.is_usable("alice", later));
+
+        keyring.update_record("alice", KeyRecord::revoke).unwrap();
+        assert!(keyring.candidates("alice", None, created).is_empty());
+        assert!(keyring.update_record("alice", KeyRecord::revoke).is_err());
+
+        // Inserting a key without a record clears it
+        keyring.insert("alice", passcode());
+        assert_eq!(keyring.record("alice"), None);
+        assert!(!keyring.update_record("alice", KeyRecord::revoke).unwrap());
+        assert!(keyring.is_usable("alice", later));
+    }
+
+    #[test]
+    fn test_derive_from_master() {
+        for algorithm in Algorithm::all() {
+            let master = Passcode::new(algorithm, algorithm.random_key());
+            let alice = KeyRing::derive_from_master(&master, "alice");
+            assert_eq!(alice.algorithm(), algorithm);
+            assert_eq!(alice.key().len(), master.key().len());
+            assert_ne!(alice.key(), master.key());
+            assert_eq!(
+                alice.key(),
+                KeyRing::derive_from_master(&master, "alice").key()
+            );
+            assert_ne!(
+                alice.key(),
+                KeyRing::derive_from_master(&master, "bob").key()
+            );
+        }
+
+        let master = Passcode::new(Algorithm::Sha3Kmac256, vec![7u8; 32]);
+        assert_eq!(
+            KeyRing::derive_from_master(&master, "alice").key(),
+            crate::kdf::derive_subkey(&[7u8; 32], labels::USER, b"alice", 32)
+        );
+    }
+
+    #[test]
+    fn test_master_keyring() {
+        let master = || Passcode::new(Algorithm::Sha3Kmac256, vec![7u8; 32]);
+        let keyring = KeyRing::with_master(master());
+        let alice = KeyRing::derive_from_master(&master(), "alice");
+        assert!(keyring.contains("alice"));
+        assert_eq!(keyring.get("alice").unwrap().key(), alice.key());
+        assert_eq!(
+            keyring.candidates("alice", None, SystemTime::now()).len(),
+            1
+        );
+        assert!(keyring.is_empty());
+
+        // Stored keys take precedence over derived ones
+        keyring.insert("bob", Passcode::new(Algorithm::Sha3Kmac256, vec![2u8; 32]));
+        assert_eq!(keyring.get("bob").unwrap().key(), [2u8; 32]);
+
+        assert!(keyring.register_device("alice", "phone"));
+        assert_eq!(keyring.len(), 2);
+        assert_eq!(
+            keyring.candidates("alice", None, SystemTime::now())[0]
+                .1
+                .key(),
+            alice.for_device("phone").key()
+        );
+    }
+}
@@ -8,9 +8,15 @@
 //! ## Features
 //!
 //! - **Challenge-Response Mechanism**: Secure authentication where the server sends a random challenge
-//! - **Multiple Hash Algorithms**: SHA3-KMAC (128/256) and BLAKE3 Keyed Mode (128/256)
-//! - **Flexible Security Levels**: Choose between 128-bit and 256-bit security strengths
+//! - **Multiple Hash Algorithms**: SHA3-KMAC (128/256/512), BLAKE3 Keyed Mode (128/256),
+//!   HMAC-SHA-256, HMAC-SHA-1 and HMAC-Streebog-256 (feature `streebog`)
+//! - **Flexible Security Levels**: Choose between 128-bit, 256-bit and 512-bit security tiers
 //! - **Type-Safe API**: Leverages Rust's type system for safety
+//! - **Key Management**: Password-derived, hardware-held, rotated, shared and escrowed keys
+//! - **Server-Side Verification**: [`verifier::Verifier`] and [`challenge::ChallengeManager`] with
+//!   challenge storage, binding, rate limiting and lockout
+//! - **Protocols and Encodings**: Output formats, wire messages, sessions, SASL, HOTP/TOTP
+//!   and provisioning URIs; see the module documentation
 //!
 //! ## Example
 //!
@@ -18,27 +24,119 @@
 //! use passcode::{Passcode, Algorithm};
 //!
 //! // 1. Shared secret key between server and client
-//! let secret_key = vec![0u8; 32];
+//! let secret_key = Algorithm::Blake3KeyedMode256.random_key();
 //!
 //! // 2. Create a new Passcode instance
 //! let passcode = Passcode::new(Algorithm::Blake3KeyedMode256, secret_key);
 //!
-//! // 3. Generate a challenge
-//! let challenge = vec![0u8; 16];
+//! // 3. Generate a random challenge
+//! let challenge = passcode.issue_challenge();
 //!
 //! // 4. Compute the OTP
-//! let otp = passcode.compute(&challenge);
+//! let otp = passcode.compute(&challenge.message());
 //! println!("Generated OTP: {}", otp);
 //! ```
 
 mod blake3_keyed;
+mod canonicalize;
+mod error;
+mod format;
+#[cfg(feature = "streebog")]
+mod hmac_streebog;
+mod hmac_sha;
 mod passcode;
+mod secret;
+mod self_test;
+#[cfg(any(feature = "argon2", feature = "pbkdf2", feature = "scrypt"))]
+mod password;
 mod sha3_kmac;
+mod trace;
+mod wordlist;
 mod ffi;
+#[cfg(feature = "key-export")]
+pub mod backup;
+pub mod ble;
+#[cfg(feature = "cbor")]
+pub mod cbor;
+pub mod challenge;
+pub mod clock;
+pub mod compact;
+pub mod enrollment;
+#[cfg(feature = "escrow")]
+pub mod escrow;
+pub mod frame;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod hotp;
+pub mod http_auth;
+pub mod kdf;
+#[cfg(feature = "keyring")]
+mod keychain;
+pub mod keyfile;
+pub mod keyrecord;
+pub mod keyring;
+pub mod migration;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "msgpack")]
+pub mod msgpack;
+pub mod negotiation;
+pub mod otpauth;
+pub mod otpchain;
+#[cfg(feature = "pkcs11")]
+pub mod pkcs11;
+pub mod policy;
+pub mod provisioning;
+#[cfg(feature = "qr")]
+pub mod qr;
+#[cfg(feature = "proto")]
+pub mod proto;
+#[cfg(feature = "radius")]
+pub mod radius;
+pub mod recovery;
+pub mod rng;
+pub mod rotation;
+#[cfg(feature = "serde")]
+mod schema;
+pub mod sasl;
+pub mod session;
+pub mod shamir;
+pub mod split;
+pub mod threshold;
+pub mod throttle;
+#[cfg(feature = "session-token")]
+pub mod token;
+pub mod totp;
+#[cfg(feature = "tpm")]
+pub mod tpm;
+#[cfg(feature = "test-vectors")]
+pub mod vectors;
+pub mod verifier;
+pub mod visual;
+pub mod websocket;
+#[cfg(feature = "yubikey")]
+pub mod yubikey;
+pub mod wire;
+#[cfg(feature = "key-wrap")]
+pub mod wrap;
 
-pub use passcode::{Algorithm, Passcode};
+pub use canonicalize::Canonicalization;
+pub use error::Error;
+pub use format::{Alphabet, CheckDigit, Grouping, OtpFormat};
+pub use passcode::{Algorithm, MacBackend, Passcode, PasscodeBuilder, XofAlgorithm};
+pub use self_test::self_test;
+#[cfg(feature = "argon2")]
+pub use password::Argon2Params;
+#[cfg(any(feature = "argon2", feature = "pbkdf2", feature = "scrypt"))]
+pub use password::{KeyDerivation, DERIVED_KEY_LEN};
+#[cfg(feature = "pbkdf2")]
+pub use password::Pbkdf2Params;
+#[cfg(feature = "scrypt")]
+pub use password::ScryptParams;
 pub use blake3_keyed::{blake3_keyed_mode256, blake3_keyed_mode512};
 pub use sha3_kmac::{sha3_kmac128, sha3_kmac256};
+#[cfg(feature = "streebog")]
+pub use hmac_streebog::hmac_streebog256;
 
 // Re-export FFI functions
 pub use ffi::*;
@@ -0,0 +1,122 @@
+//! Metrics through the `metrics` facade (feature `metrics`)
+//!
+//! [`ChallengeManager`](crate::challenge::ChallengeManager), and therefore
+//! [`Verifier`](crate::verifier::Verifier), records the metrics below with
+//! whichever recorder the application installed, e.g. a Prometheus
+//! exporter. Call [`describe`] once after installing the recorder to attach
+//! units and help texts.
+//!
+//! | Metric | Kind | Labels |
+//! |---|---|---|
+//! | [`VERIFICATIONS_TOTAL`] | counter | `outcome` |
+//! | [`VERIFICATION_DURATION_SECONDS`] | histogram | `outcome` |
+//! | [`CHALLENGES_ISSUED_TOTAL`] | counter | |
+//! | [`LOCKOUTS_TOTAL`] | counter | |
+//! | [`ANOMALIES_TOTAL`] | counter | `subject` |
+//!
+//! The `outcome` label is one of `accepted`, `rejected`, `unknown_challenge`,
+//! `expired`, `replayed`, `puzzle_unsolved`, `rate_limited`, `locked_out`
+//! or `error`. A spike in `rejected` or `locked_out` suggests a brute-force
+//! attempt. The `subject` label of [`ANOMALIES_TOTAL`] is `identity` or
+//! `source`.
+
+use std::time::Duration;
+
+use ::metrics::{counter, describe_counter, describe_histogram, histogram, Unit};
+
+use crate::Error;
+
+/// Responses checked, by outcome
+pub const VERIFICATIONS_TOTAL: &str = "passcode_verifications_total";
+
+/// Time taken to check a response, by outcome
+pub const VERIFICATION_DURATION_SECONDS: &str = "passcode_verification_duration_seconds";
+
+/// Challenges issued, including those issued in batches
+pub const CHALLENGES_ISSUED_TOTAL: &str = "passcode_challenges_issued_total";
+
+/// Identities locked out after repeated failures
+pub const LOCKOUTS_TOTAL: &str = "passcode_lockouts_total";
+
+/// Identities and sources whose failure rate crossed the anomaly threshold
+pub const ANOMALIES_TOTAL: &str = "passcode_anomalies_total";
+
+/// Registers units and help texts for the metrics with the installed
+/// recorder
+pub fn describe() {
+    describe_counter!(
+        VERIFICATIONS_TOTAL,
+        Unit::Count,
+        "Responses checked, by outcome"
+    );
+    describe_histogram!(
+        VERIFICATION_DURATION_SECONDS,
+        Unit::Seconds,
+        "Time taken to check a response, by outcome"
+    );
+    describe_counter!(CHALLENGES_ISSUED_TOTAL, Unit::Count, "Challenges issued");
+    describe_counter!(
+        LOCKOUTS_TOTAL,
+        Unit::Count,
+        "Identities locked out after repeated failures"
+    );
+    describe_counter!(
+        ANOMALIES_TOTAL,
+        Unit::Count,
+        "Identities and sources whose failure rate crossed the anomaly threshold"
+    );
+}
+
+pub(crate) fn issued(count: usize) {
+    counter!(CHALLENGES_ISSUED_TOTAL).increment(count as u64);
+}
+
+pub(crate) fn verified<T>(result: &Result<T, Error>, elapsed: Duration) {
+    let outcome = outcome(result);
+    counter!(VERIFICATIONS_TOTAL, "outcome" => outcome).increment(1);
+    histogram!(VERIFICATION_DURATION_SECONDS, "outcome" => outcome).record(elapsed);
+}
+
+pub(crate) fn locked_out() {
+    counter!(LOCKOUTS_TOTAL).increment(1);
+}
+
+pub(crate) fn anomaly(subject: &'static str) {
+    counter!(ANOMALIES_TOTAL, "subject" => subject).increment(1);
+}
+
+/// Returns the `outcome` label for a verification result
+fn outcome<T>(result: &Result<T, Error>) -> &'static str {
+    match result {
+        Ok(_) => "accepted",
+        Err(Error::OtpMismatch) => "rejected",
+        Err(Error::ChallengeNotFound) => "unknown_challenge",
+        Err(Error::ChallengeExpired) => "expired",
+        Err(Error::ReplayDetected) => "replayed",
+        Err(Error::PuzzleUnsolved) => "puzzle_unsolved",
+        Err(Error::RateLimited { .. }) => "rate_limited",
+        Err(Error::LockedOut { .. }) => "locked_out",
+        Err(_) => "error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_outcome_labels() {
+        assert_eq!(outcome(&Ok(())), "accepted");
+        assert_eq!(outcome::<()>(&Err(Error::OtpMismatch)), "rejected");
+        assert_eq!(
+            outcome::<()>(&Err(Error::LockedOut {
+                retry_after: Duration::from_secs(1)
+            })),
+            "locked_out"
+        );
+        assert_eq!(
+            outcome::<()>(&Err(Error::ChallengeStore(String::new()))),
+            "error"
+        );
+    }
+}
@@ -0,0 +1,436 @@
+//! Google Authenticator export import
+//!
+//! Google Authenticator exports accounts as `otpauth-migration://offline`
+//! URIs, usually shown as QR codes, whose `data` parameter is a base64
+//! protocol buffer listing each account's seed and HOTP/TOTP parameters.
+//! [`MigrationPayload`] parses one such URI into [`OtpAuthUri`]s, and
+//! [`KeyRing::import_migration`] turns their seeds into challenge-response
+//! keys so existing users can move to this crate without re-enrolling.
+//!
+//! The challenge-response key is not the seed itself but
+//! `KMAC256(seed, "")` with the [`labels::MIGRATION`] customization, of the
+//! algorithm's recommended length, so OTPs of the two schemes are
+//! unrelated. A client that imports the same export derives the same key
+//! with [`migrated_passcode`]. The key carries no more entropy than the
+//! seed: an 80-bit seed, as many services issue, stays an 80-bit key, so
+//! re-enroll such users once they have migrated.
+//!
+//! Large exports are split across several URIs; each one is a batch,
+//! numbered by [`batch_index`](MigrationPayload::batch_index) out of
+//! [`batch_size`](MigrationPayload::batch_size).
+//!
+//! # Example
+//! ```
+//! use passcode::keyring::KeyRing;
+//! use passcode::migration::MigrationPayload;
+//! use passcode::Algorithm;
+//!
+//! let export = "otpauth-migration://offline?data=CjUKCkhlbGxvId6tvu8SGEV4YW1wbGU6YWxpY2VAZ29vZ2xlLmNvbRoHRXhhbXBsZSABKAEwAhABGAEgACjr4JKK%2Bv%2F%2F%2F%2F8B";
+//! let payload: MigrationPayload = export.parse().unwrap();
+//! assert_eq!(payload.entries()[0].account, "alice@google.com");
+//!
+//! let keyring = KeyRing::new();
+//! let import = keyring.import_migration(&payload, Algorithm::Sha3Kmac256);
+//! assert_eq!(import.imported, ["alice@google.com"]);
+//! assert!(import.conflicts.is_empty());
+//! ```
+
+use std::str::FromStr;
+
+use base64ct::{Base64, Encoding};
+
+use crate::hotp::HmacAlgorithm;
+use crate::kdf::{derive_subkey, labels};
+use crate::keyring::KeyRing;
+use crate::otpauth::{percent_decode, OtpAuthUri, OtpKind};
+use crate::{Algorithm, Error, Passcode};
+
+/// Scheme of Google Authenticator export URIs
+pub const MIGRATION_SCHEME: &str = "otpauth-migration";
+
+/// Time step of exported TOTP accounts, which the export does not carry
+const EXPORT_PERIOD: u64 = 30;
+
+/// One batch of accounts exported from Google Authenticator
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationPayload {
+    entries: Vec<OtpAuthUri>,
+    batch_size: u32,
+    batch_index: u32,
+    batch_id: i32,
+}
+
+impl MigrationPayload {
+    /// Decodes the protocol buffer carried in the `data` parameter
+    ///
+    /// Fails with [`Error::InvalidUri`] for a damaged payload or an account
+    /// with an unsupported algorithm or OTP type. Unknown fields are
+    /// skipped.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let mut payload = Self {
+            entries: Vec::new(),
+            batch_size: 1,
+            batch_index: 0,
+            batch_id: 0,
+        };
+        let mut reader = ProtoReader { rest: bytes };
+        while let Some((field, value)) = reader.next()? {
+            match (field, value) {
+                (1, Value::Bytes(entry)) => payload.entries.push(parse_entry(entry)?),
+                (3, Value::Varint(size)) => payload.batch_size = size as u32,
+                (4, Value::Varint(index)) => payload.batch_index = index as u32,
+                // int32 fields are sign-extended to 64 bits
+                (5, Value::Varint(id)) => payload.batch_id = id as i32,
+                _ => {}
+            }
+        }
+        Ok(payload)
+    }
+
+    /// Gets the exported accounts
+    pub fn entries(&self) -> &[OtpAuthUri] {
+        &self.entries
+    }
+
+    /// Gets the number of batches the export was split into
+    pub fn batch_size(&self) -> u32 {
+        self.batch_size
+    }
+
+    /// Gets the position of this batch, starting at zero
+    pub fn batch_index(&self) -> u32 {
+        self.batch_index
+    }
+
+    /// Gets the identifier shared by the batches of one export
+    pub fn batch_id(&self) -> i32 {
+        self.batch_id
+    }
+}
+
+impl FromStr for MigrationPayload {
+    type Err = Error;
+
+    /// Parses an `otpauth-migration://offline?data=...` URI
+    fn from_str(uri: &str) -> Result<Self, Error> {
+        let (scheme, rest) = uri
+            .split_once("://")
+            .ok_or(Error::InvalidUri("missing scheme"))?;
+        if !scheme.eq_ignore_ascii_case(MIGRATION_SCHEME) {
+            return Err(Error::InvalidUri("unsupported scheme"));
+        }
+        let (host, query) = rest.split_once('?').unwrap_or((rest, ""));
+        if host != "offline" {
+            return Err(Error::InvalidUri("unsupported migration type"));
+        }
+
+        let data = query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("data="))
+            .ok_or(Error::InvalidUri("missing data"))?;
+        // `+` is part of the base64 alphabet, not an encoded space
+        let data = percent_decode(data, false)?;
+        let bytes =
+            Base64::decode_vec(&data).map_err(|_| Error::InvalidUri("data is not valid base64"))?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+/// Derives the challenge-response passcode of a migrated HOTP/TOTP seed
+pub fn migrated_passcode(algorithm: Algorithm, seed: &[u8]) -> Passcode {
+    Passcode::new(
+        algorithm,
+        derive_subkey(
+            seed,
+            labels::MIGRATION,
+            &[],
+            algorithm.recommended_key_len(),
+        ),
+    )
+}
+
+/// Outcome of [`KeyRing::import_migration`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MigrationImport {
+    /// User IDs that got a key, in export order
+    pub imported: Vec<String>,
+    /// User IDs left untouched because they already had a key, in export
+    /// order
+    pub conflicts: Vec<String>,
+}
+
+impl KeyRing {
+    /// Sets a passcode derived with [`migrated_passcode`] for each exported
+    /// account whose name has no stored key yet
+    ///
+    /// The account name is the user ID. Existing users are never
+    /// overwritten: accounts named like a user with a stored key, or like
+    /// an earlier account of the export, are skipped and returned as
+    /// conflicts for the caller to resolve, e.g. by renaming and inserting
+    /// them. Users of a master key have no stored key, so importing one
+    /// replaces their derived key.
+    pub fn import_migration(
+        &self,
+        payload: &MigrationPayload,
+        algorithm: Algorithm,
+    ) -> MigrationImport {
+        let mut import = MigrationImport::default();
+        for entry in &payload.entries {
+            let passcode = migrated_passcode(algorithm, entry.secret());
+            if self.insert_new(&entry.account, passcode.into()) {
+                import.imported.push(entry.account.clone());
+            } else {
+                import.conflicts.push(entry.account.clone());
+            }
+        }
+        import
+    }
+}
+
+/// Decodes one `OtpParameters` message
+fn parse_entry(bytes: &[u8]) -> Result<OtpAuthUri, Error> {
+    let mut secret = None;
+    let mut name = String::new();
+    let mut issuer = String::new();
+    let mut algorithm = HmacAlgorithm::Sha1;
+    let mut digits = 6;
+    let mut otp_type = 0;
+    let mut counter = 0;
+
+    let mut reader = ProtoReader { rest: bytes };
+    while let Some((field, value)) = reader.next()? {
+        match (field, value) {
+            (1, Value::Bytes(bytes)) => secret = Some(bytes.to_vec()),
+            (2, Value::Bytes(bytes)) => name = text(bytes)?,
+            (3, Value::Bytes(bytes)) => issuer = text(bytes)?,
+            (4, Value::Varint(id)) => {
+                algorithm = match id {
+                    0 | 1 => HmacAlgorithm::Sha1,
+                    2 => HmacAlgorithm::Sha256,
+                    3 => HmacAlgorithm::Sha512,
+                    _ => return Err(Error::InvalidUri("unsupported algorithm")),
+                }
+            }
+            (5, Value::Varint(id)) => {
+                digits = match id {
+                    0 | 1 => 6,
+                    2 => 8,
+                    _ => return Err(Error::InvalidUri("unsupported number of digits")),
+                }
+            }
+            (6, Value::Varint(id)) => otp_type = id,
+            (7, Value::Varint(value)) => counter = value,
+            _ => {}
+        }
+    }
+
+    let secret = secret
+        .filter(|secret| !secret.is_empty())
+        .ok_or(Error::InvalidUri("missing secret"))?;
+    let kind = match otp_type {
+        1 => OtpKind::Hotp {
+            algorithm,
+            digits,
+            counter,
+        },
+        2 => OtpKind::Totp {
+            algorithm,
+            digits,
+            period: EXPORT_PERIOD,
+        },
+        _ => return Err(Error::InvalidUri("unsupported OTP type")),
+    };
+
+    // Names may carry an `Issuer:` prefix, like otpauth:// labels
+    let (label_issuer, account) = match name.split_once(':') {
+        Some((issuer, account)) => (Some(issuer), account.trim_start()),
+        None => (None, name.as_str()),
+    };
+    let mut uri = OtpAuthUri::new(kind, secret, account);
+    if !issuer.is_empty() {
+        uri = uri.with_issuer(&issuer);
+    } else if let Some(issuer) = label_issuer {
+        uri = uri.with_issuer(issuer);
+    }
+    Ok(uri)
+}
+
+fn text(bytes: &[u8]) -> Result<String, Error> {
+    String::from_utf8(bytes.to_vec()).map_err(|_| Error::InvalidUri("name is not valid UTF-8"))
+}
+
+/// A protocol buffer field value
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    /// A fixed-width value, which no recognized field uses
+    Fixed,
+}
+
+/// Reads the fields of a protocol buffer message
+struct ProtoReader<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> ProtoReader<'a> {
+    fn next(&mut self) -> Result<Option<(u64, Value<'a>)>, Error> {
+        if self.rest.is_empty() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        let value = match key & 7 {
+            0 => Value::Varint(self.varint()?),
+            1 => {
+                self.take(8)?;
+                Value::Fixed
+            }
+            2 => {
+                let len = usize::try_from(self.varint()?)
+                    .map_err(|_| Error::InvalidUri("truncated migration data"))?;
+                Value::Bytes(self.take(len)?)
+            }
+            5 => {
+                self.take(4)?;
+                Value::Fixed
+            }
+            _ => return Err(Error::InvalidUri("invalid migration data")),
+        };
+        Ok(Some((key >> 3, value)))
+    }
+
+    fn varint(&mut self) -> Result<u64, Error> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.take(1)?[0];
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Error::InvalidUri("invalid migration data"))
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.rest.len() < len {
+            return Err(Error::InvalidUri("truncated migration data"));
+        }
+        let (taken, rest) = self.rest.split_at(len);
+        self.rest = rest;
+        Ok(taken)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPORT: &str = "otpauth-migration://offline?data=CjUKCkhlbGxvId6tvu8SGEV4YW1wbGU6YWxpY2VAZ29vZ2xlLmNvbRoHRXhhbXBsZSABKAEwAhABGAEgACjr4JKK%2Bv%2F%2F%2F%2F8B";
+
+    #[test]
+    fn test_parse_export() {
+        let payload: MigrationPayload = EXPORT.parse().unwrap();
+        assert_eq!(payload.batch_size(), 1);
+        assert_eq!(payload.batch_index(), 0);
+        assert!(payload.batch_id() < 0);
+
+        let entry = &payload.entries()[0];
+        assert_eq!(entry.account, "alice@google.com");
+        assert_eq!(entry.issuer.as_deref(), Some("Example"));
+        assert_eq!(
+            entry.to_string(),
+            "otpauth://totp/Example:alice%40google.com?secret=JBSWY3DPEHPK3PXP\
+             &issuer=Example&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+
+    #[test]
+    fn test_hotp_entry() {
+        // secret, name "bob", SHA256, eight digits, HOTP, counter 300
+        let entry = [
+            0x0a, 0x02, 0xab, 0xcd, 0x12, 0x03, b'b', b'o', b'b', 0x20, 0x02, 0x28, 0x02, 0x30,
+            0x01, 0x38, 0xac, 0x02,
+        ];
+        let mut bytes = vec![0x0a, entry.len() as u8];
+        bytes.extend_from_slice(&entry);
+        // An unknown fixed32 field is skipped
+        bytes.extend_from_slice(&[0x75, 0, 0, 0, 0]);
+
+        let payload = MigrationPayload::from_bytes(&bytes).unwrap();
+        let entry = &payload.entries()[0];
+        assert_eq!(entry.account, "bob");
+        assert_eq!(entry.issuer, None);
+        assert_eq!(entry.secret(), [0xab, 0xcd]);
+        assert_eq!(
+            entry.kind,
+            OtpKind::Hotp {
+                algorithm: HmacAlgorithm::Sha256,
+                digits: 8,
+                counter: 300
+            }
+        );
+    }
+
+    #[test]
+    fn test_rejects_invalid() {
+        assert!("otpauth://offline?data=AA"
+            .parse::<MigrationPayload>()
+            .is_err());
+        assert!("otpauth-migration://online?data=AA"
+            .parse::<MigrationPayload>()
+            .is_err());
+        assert!("otpauth-migration://offline"
+            .parse::<MigrationPayload>()
+            .is_err());
+
+        // Truncated entry
+        assert!(MigrationPayload::from_bytes(&[0x0a, 0x05, 0x0a]).is_err());
+        // MD5
+        assert_eq!(
+            MigrationPayload::from_bytes(&[0x0a, 0x07, 0x0a, 0x01, 0x01, 0x20, 0x04, 0x30, 0x02])
+                .unwrap_err(),
+            Error::InvalidUri("unsupported algorithm")
+        );
+        // Missing OTP type
+        assert_eq!(
+            MigrationPayload::from_bytes(&[0x0a, 0x03, 0x0a, 0x01, 0x01]).unwrap_err(),
+            Error::InvalidUri("unsupported OTP type")
+        );
+    }
+
+    #[test]
+    fn test_import_into_keyring() {
+        let payload: MigrationPayload = EXPORT.parse().unwrap();
+        let keyring = KeyRing::new();
+        let import = keyring.import_migration(&payload, Algorithm::Blake3KeyedMode256);
+        assert_eq!(import.imported, ["alice@google.com"]);
+        assert!(import.conflicts.is_empty());
+
+        // The client derives the same key from the same export
+        let client =
+            migrated_passcode(Algorithm::Blake3KeyedMode256, payload.entries()[0].secret());
+        let server = keyring.get("alice@google.com").unwrap();
+        assert_eq!(server.key_id(), client.key_id());
+        assert_eq!(server.key().len(), 32);
+        assert_ne!(server.key(), payload.entries()[0].secret());
+    }
+
+    #[test]
+    fn test_import_keeps_existing_users() {
+        let payload: MigrationPayload = EXPORT.parse().unwrap();
+        let keyring = KeyRing::new();
+        let existing = Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+        let key_id = existing.key_id();
+        keyring.insert("alice@google.com", existing);
+
+        let import = keyring.import_migration(&payload, Algorithm::Sha3Kmac256);
+        assert!(import.imported.is_empty());
+        assert_eq!(import.conflicts, ["alice@google.com"]);
+        assert_eq!(keyring.get("alice@google.com").unwrap().key_id(), key_id);
+
+        // A second batch with the same account conflicts with the first
+        let keyring = KeyRing::new();
+        keyring.import_migration(&payload, Algorithm::Sha3Kmac256);
+        let import = keyring.import_migration(&payload, Algorithm::Sha3Kmac256);
+        assert_eq!(import.conflicts, ["alice@google.com"]);
+    }
+}
@@ -0,0 +1,76 @@
+//! MessagePack encoding of protocol messages (feature `msgpack`)
+//!
+//! Encodes the types with a serde representation, such as
+//! [`Challenge`](crate::challenge::Challenge),
+//! [`ChallengeMessage`](crate::wire::ChallengeMessage),
+//! [`ResponseMessage`](crate::wire::ResponseMessage) and
+//! [`VerifyOutcome`](crate::verifier::VerifyOutcome), as MessagePack maps
+//! with the same field names and values as their JSON layout, so a service
+//! can switch between the two without a schema change.
+//!
+//! # Example
+//! ```
+//! use passcode::msgpack;
+//! use passcode::verifier::VerifyOutcome;
+//!
+//! let encoded = msgpack::encode(&VerifyOutcome::Accepted);
+//! assert_eq!(msgpack::decode(&encoded), Ok(VerifyOutcome::Accepted));
+//! ```
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::Error;
+
+/// Encodes a message as a MessagePack map
+///
+/// # Panics
+/// Panics if the message's `Serialize` implementation fails, which the
+/// protocol types never do.
+pub fn encode<T: Serialize + ?Sized>(message: &T) -> Vec<u8> {
+    rmp_serde::to_vec_named(message).expect("protocol messages serialize")
+}
+
+/// Decodes a message written by [`encode`]
+///
+/// Fails with [`Error::MalformedMessage`] when the bytes are not a valid
+/// encoding of `T`.
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Error> {
+    rmp_serde::from_slice(bytes).map_err(|_| Error::MalformedMessage("invalid MessagePack"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verifier::VerifyOutcome;
+    use crate::wire::ResponseMessage;
+    use crate::Algorithm;
+    use std::time::Duration;
+
+    #[test]
+    fn test_same_layout_as_json() {
+        let response = ResponseMessage {
+            algorithm: Algorithm::Sha3Kmac256,
+            nonce: vec![1, 2, 3],
+            mac: vec![7],
+        };
+        let encoded = encode(&response);
+
+        // Transcoding to JSON yields the JSON representation
+        let value: serde_json::Value = rmp_serde::from_slice(&encoded).unwrap();
+        assert_eq!(value, serde_json::to_value(&response).unwrap());
+        assert_eq!(decode::<ResponseMessage>(&encoded), Ok(response));
+    }
+
+    #[test]
+    fn test_outcome_round_trip() {
+        let outcome = VerifyOutcome::LockedOut {
+            retry_after: Duration::from_secs(30),
+        };
+        assert_eq!(decode(&encode(&outcome)), Ok(outcome));
+        assert_eq!(
+            decode::<VerifyOutcome>(&[0xc1]),
+            Err(Error::MalformedMessage("invalid MessagePack"))
+        );
+    }
+}
@@ -0,0 +1,428 @@
+//! Algorithm negotiation
+//!
+//! During a migration between algorithms a user holds a key for each, and
+//! both sides must agree on which one answers the challenge. The server
+//! sends an [`Offer`] listing the algorithms it has keys for, in order of
+//! preference, together with a challenge. The client picks the first
+//! offered algorithm it also has a key for and returns an [`Answer`].
+//!
+//! The OTP is computed over the whole offer and the chosen algorithm, not
+//! just the challenge. An attacker who strips the stronger algorithms from
+//! the offer to force a weaker one changes what the client computes over,
+//! so the server rejects the answer with [`Error::OtpMismatch`]. Clients can
+//! additionally refuse algorithms below a security level with
+//! [`NegotiationClient::with_min_security_bits`].
+//!
+//! # Example
+//! ```
+//! use passcode::negotiation::{NegotiationClient, NegotiationServer};
+//! use passcode::{Algorithm, Passcode};
+//!
+//! let server_keys = [
+//!     Passcode::new(Algorithm::Sha3Kmac512, vec![1u8; 64]),
+//!     Passcode::new(Algorithm::Sha3Kmac256, vec![2u8; 32]),
+//! ];
+//! // A client that has not migrated yet
+//! let client_keys = [Passcode::new(Algorithm::Sha3Kmac256, vec![2u8; 32])];
+//!
+//! let server = NegotiationServer::new(&server_keys).unwrap();
+//! let client = NegotiationClient::new(&client_keys);
+//!
+//! let (answer, client_result) = client.answer(server.offer()).unwrap();
+//! let server_result = server.verify(&answer).unwrap();
+//! assert_eq!(server_result.algorithm(), Algorithm::Sha3Kmac256);
+//! assert_eq!(server_result.session_key(), client_result.session_key());
+//! ```
+
+use std::fmt;
+
+use crate::challenge::DEFAULT_CHALLENGE_LEN;
+use crate::rng::{CryptoRngCore, SharedRng};
+use crate::session::SESSION_KEY_LEN;
+use crate::{Algorithm, Error, Passcode};
+
+/// Domain separator at the start of the negotiated transcript
+const NEGOTIATION_CONTEXT: &[u8] = b"passcode/v1/negotiate";
+
+/// Server to client: the algorithms the server accepts and the challenge
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Offer {
+    algorithms: Vec<Algorithm>,
+    /// Identifiers as sent, including any this build does not know
+    ids: Vec<u8>,
+    challenge: Vec<u8>,
+}
+
+impl Offer {
+    /// Gets the offered algorithms this build supports, most preferred
+    /// first
+    pub fn algorithms(&self) -> &[Algorithm] {
+        &self.algorithms
+    }
+
+    /// Gets the challenge
+    pub fn challenge(&self) -> &[u8] {
+        &self.challenge
+    }
+
+    /// Encodes the offer as the number of algorithms, their
+    /// [`Algorithm::id`]s and the challenge
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut encoded = Vec::with_capacity(1 + self.ids.len() + self.challenge.len());
+        encoded.push(self.ids.len() as u8);
+        encoded.extend_from_slice(&self.ids);
+        encoded.extend_from_slice(&self.challenge);
+        encoded
+    }
+
+    /// Decodes an offer written by [`to_bytes`](Self::to_bytes)
+    ///
+    /// Unknown algorithms are skipped, so a client can still answer an
+    /// offer that also lists algorithms it was not built with; they remain
+    /// part of the transcript.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let (&count, rest) = bytes
+            .split_first()
+            .ok_or(Error::MalformedMessage("empty offer"))?;
+        if rest.len() < usize::from(count) {
+            return Err(Error::MalformedMessage("truncated offer"));
+        }
+        let (ids, challenge) = rest.split_at(usize::from(count));
+        Ok(Self {
+            algorithms: ids
+                .iter()
+                .filter_map(|&id| Algorithm::from_id(id))
+                .collect(),
+            ids: ids.to_vec(),
+            challenge: challenge.to_vec(),
+        })
+    }
+}
+
+/// Client to server: the chosen algorithm and the OTP
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Answer {
+    algorithm: Algorithm,
+    otp: String,
+}
+
+impl Answer {
+    /// Gets the algorithm the client chose
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// Gets the OTP over the negotiated transcript
+    pub fn otp(&self) -> &str {
+        &self.otp
+    }
+
+    /// Encodes the answer as the [`Algorithm::id`] followed by the OTP
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut encoded = Vec::with_capacity(1 + self.otp.len());
+        encoded.push(self.algorithm.id());
+        encoded.extend_from_slice(self.otp.as_bytes());
+        encoded
+    }
+
+    /// Decodes an answer written by [`to_bytes`](Self::to_bytes)
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let (&id, otp) = bytes
+            .split_first()
+            .ok_or(Error::MalformedMessage("empty answer"))?;
+        let algorithm =
+            Algorithm::from_id(id).ok_or(Error::MalformedMessage("unknown algorithm"))?;
+        let otp =
+            std::str::from_utf8(otp).map_err(|_| Error::MalformedMessage("answer is not UTF-8"))?;
+        Ok(Self {
+            algorithm,
+            otp: otp.to_string(),
+        })
+    }
+}
+
+/// Outcome of a successful negotiation
+pub struct Negotiated {
+    algorithm: Algorithm,
+    session_key: Vec<u8>,
+}
+
+impl Negotiated {
+    /// Gets the algorithm both sides agreed on
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// Gets a key bound to the whole negotiation, identical on both sides
+    pub fn session_key(&self) -> &[u8] {
+        &self.session_key
+    }
+}
+
+impl fmt::Debug for Negotiated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Negotiated")
+            .field("algorithm", &self.algorithm)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Server side of the negotiation
+///
+/// The server offers the algorithms of its keys in the order given, so the
+/// preferred key comes first. Each server answers one offer; start a new
+/// one for the next attempt.
+pub struct NegotiationServer<'a> {
+    keys: &'a [Passcode],
+    offer: Offer,
+}
+
+impl<'a> NegotiationServer<'a> {
+    /// Starts a negotiation with a random challenge of
+    /// [`DEFAULT_CHALLENGE_LEN`] bytes
+    pub fn new(keys: &'a [Passcode]) -> Result<Self, Error> {
+        Self::with_rng(keys, SharedRng::os())
+    }
+
+    /// Starts a negotiation with the challenge drawn from `rng`
+    pub fn with_rng(keys: &'a [Passcode], mut rng: impl CryptoRngCore) -> Result<Self, Error> {
+        let mut challenge = vec![0u8; DEFAULT_CHALLENGE_LEN];
+        rng.try_fill_bytes(&mut challenge)
+            .map_err(|e| Error::RandomSource(e.to_string()))?;
+
+        let mut algorithms = Vec::with_capacity(keys.len());
+        for key in keys {
+            if !algorithms.contains(&key.algorithm()) {
+                algorithms.push(key.algorithm());
+            }
+        }
+        let mut server = Self {
+            keys,
+            offer: Offer {
+                algorithms: Vec::new(),
+                ids: Vec::new(),
+                challenge,
+            },
+        };
+        server.set_algorithms(algorithms);
+        Ok(server)
+    }
+
+    /// Stops offering algorithms below `bits` of security, e.g. once a
+    /// migration away from them is complete
+    pub fn with_min_security_bits(mut self, bits: u32) -> Self {
+        let mut algorithms = std::mem::take(&mut self.offer.algorithms);
+        algorithms.retain(|algorithm| algorithm.security_bits() >= bits);
+        self.set_algorithms(algorithms);
+        self
+    }
+
+    fn set_algorithms(&mut self, algorithms: Vec<Algorithm>) {
+        self.offer.ids = algorithms.iter().map(Algorithm::id).collect();
+        self.offer.algorithms = algorithms;
+    }
+
+    /// Gets the offer to send to the client
+    pub fn offer(&self) -> &Offer {
+        &self.offer
+    }
+
+    /// Checks the client's answer against the offer
+    ///
+    /// Fails with [`Error::AlgorithmNotOffered`] when the client chose an
+    /// algorithm outside the offer, and with [`Error::OtpMismatch`] when
+    /// the OTP is wrong, including when the offer was tampered with on the
+    /// way to the client.
+    pub fn verify(&self, answer: &Answer) -> Result<Negotiated, Error> {
+        let key = self
+            .offer
+            .algorithms
+            .contains(&answer.algorithm)
+            .then(|| {
+                self.keys
+                    .iter()
+                    .find(|key| key.algorithm() == answer.algorithm)
+            })
+            .flatten()
+            .ok_or(Error::AlgorithmNotOffered(answer.algorithm))?;
+
+        let transcript = transcript(&self.offer, answer.algorithm);
+        if !key.verify(&transcript, &answer.otp) {
+            return Err(Error::OtpMismatch);
+        }
+        Ok(negotiated(key, &transcript))
+    }
+}
+
+impl fmt::Debug for NegotiationServer<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NegotiationServer")
+            .field("algorithms", &self.offer.algorithms)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Client side of the negotiation
+pub struct NegotiationClient<'a> {
+    keys: &'a [Passcode],
+    min_security_bits: u32,
+}
+
+impl<'a> NegotiationClient<'a> {
+    /// Creates a client answering with any of its keys
+    pub fn new(keys: &'a [Passcode]) -> Self {
+        Self {
+            keys,
+            min_security_bits: 0,
+        }
+    }
+
+    /// Refuses algorithms below `bits` of security even if the server
+    /// offers nothing stronger
+    pub fn with_min_security_bits(mut self, bits: u32) -> Self {
+        self.min_security_bits = bits;
+        self
+    }
+
+    /// Answers the offer with the first offered algorithm the client has a
+    /// key for
+    ///
+    /// Fails with [`Error::NoCommonAlgorithm`] when there is none at or
+    /// above the client's minimum security level.
+    pub fn answer(&self, offer: &Offer) -> Result<(Answer, Negotiated), Error> {
+        let key = offer
+            .algorithms
+            .iter()
+            .filter(|algorithm| algorithm.security_bits() >= self.min_security_bits)
+            .find_map(|&algorithm| self.keys.iter().find(|key| key.algorithm() == algorithm))
+            .ok_or(Error::NoCommonAlgorithm)?;
+
+        let transcript = transcript(offer, key.algorithm());
+        let answer = Answer {
+            algorithm: key.algorithm(),
+            otp: key.compute(&transcript),
+        };
+        Ok((answer, negotiated(key, &transcript)))
+    }
+}
+
+impl fmt::Debug for NegotiationClient<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NegotiationClient")
+            .field("min_security_bits", &self.min_security_bits)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Binds the full offer and the choice into the bytes the OTP covers
+fn transcript(offer: &Offer, algorithm: Algorithm) -> Vec<u8> {
+    let offer = offer.to_bytes();
+    let mut transcript = Vec::with_capacity(NEGOTIATION_CONTEXT.len() + offer.len() + 1);
+    transcript.extend_from_slice(NEGOTIATION_CONTEXT);
+    transcript.extend_from_slice(&offer);
+    transcript.push(algorithm.id());
+    transcript
+}
+
+fn negotiated(key: &Passcode, transcript: &[u8]) -> Negotiated {
+    Negotiated {
+        algorithm: key.algorithm(),
+        session_key: key.derive_session_key(transcript, SESSION_KEY_LEN),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> [Passcode; 3] {
+        [
+            Passcode::new(Algorithm::Sha3Kmac512, vec![1u8; 64]),
+            Passcode::new(Algorithm::Sha3Kmac256, vec![2u8; 32]),
+            Passcode::new(Algorithm::Sha3Kmac128, vec![3u8; 32]),
+        ]
+    }
+
+    #[test]
+    fn test_picks_first_common_algorithm() {
+        let server_keys = keys();
+        let server = NegotiationServer::new(&server_keys).unwrap();
+        assert_eq!(
+            server.offer().algorithms(),
+            [
+                Algorithm::Sha3Kmac512,
+                Algorithm::Sha3Kmac256,
+                Algorithm::Sha3Kmac128
+            ]
+        );
+
+        let [_, new, old] = keys();
+        let client_keys = [old, new];
+        let client = NegotiationClient::new(&client_keys);
+        let offer = Offer::from_bytes(&server.offer().to_bytes()).unwrap();
+        let (answer, client_result) = client.answer(&offer).unwrap();
+        assert_eq!(answer.algorithm(), Algorithm::Sha3Kmac256);
+
+        let answer = Answer::from_bytes(&answer.to_bytes()).unwrap();
+        let server_result = server.verify(&answer).unwrap();
+        assert_eq!(server_result.algorithm(), Algorithm::Sha3Kmac256);
+        assert_eq!(server_result.session_key(), client_result.session_key());
+    }
+
+    #[test]
+    fn test_detects_downgrade() {
+        let server_keys = keys();
+        let server = NegotiationServer::new(&server_keys).unwrap();
+        let client_keys = keys();
+        let client = NegotiationClient::new(&client_keys);
+
+        // An attacker strips everything but the weakest algorithm
+        let mut stripped = server.offer().to_bytes();
+        stripped.drain(1..3);
+        stripped[0] = 1;
+        let stripped = Offer::from_bytes(&stripped).unwrap();
+        assert_eq!(stripped.algorithms(), [Algorithm::Sha3Kmac128]);
+        let (answer, _) = client.answer(&stripped).unwrap();
+        assert_eq!(answer.algorithm(), Algorithm::Sha3Kmac128);
+        assert_eq!(server.verify(&answer).unwrap_err(), Error::OtpMismatch);
+
+        // A client with a floor refuses the weak offer outright
+        let strict = NegotiationClient::new(&client_keys).with_min_security_bits(256);
+        assert_eq!(
+            strict.answer(&stripped).unwrap_err(),
+            Error::NoCommonAlgorithm
+        );
+    }
+
+    #[test]
+    fn test_rejects_algorithm_outside_offer() {
+        let server_keys = keys();
+        let server = NegotiationServer::new(&server_keys)
+            .unwrap()
+            .with_min_security_bits(256);
+        assert!(!server
+            .offer()
+            .algorithms()
+            .contains(&Algorithm::Sha3Kmac128));
+
+        // The client answers over the real offer with a retired algorithm
+        let retired = &keys()[2];
+        let answer = Answer {
+            algorithm: Algorithm::Sha3Kmac128,
+            otp: retired.compute(&transcript(server.offer(), Algorithm::Sha3Kmac128)),
+        };
+        assert_eq!(
+            server.verify(&answer).unwrap_err(),
+            Error::AlgorithmNotOffered(Algorithm::Sha3Kmac128)
+        );
+        assert_eq!(
+            Offer::from_bytes(&[3, 0, 1]),
+            Err(Error::MalformedMessage("truncated offer"))
+        );
+
+        // Unknown identifiers are skipped but kept in the transcript
+        let offer = Offer::from_bytes(&[2, 0xee, 1, 9, 9]).unwrap();
+        assert_eq!(offer.algorithms(), [Algorithm::Sha3Kmac256]);
+        assert_eq!(offer.to_bytes(), [2, 0xee, 1, 9, 9]);
+    }
+}
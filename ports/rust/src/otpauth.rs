@@ -0,0 +1,494 @@
+//! `otpauth://` provisioning URIs
+//!
+//! Builds and parses the Key URI format understood by authenticator apps
+//! (`otpauth://totp/...`, `otpauth://hotp/...`) and an analogous
+//! `otpauth-cr://cr/...` scheme for challenge-response keys, so enrollment
+//! can reuse existing QR and deep-link tooling.
+//!
+//! # Example
+//! ```
+//! use passcode::otpauth::OtpAuthUri;
+//! use passcode::totp::TotpConfig;
+//!
+//! let uri = OtpAuthUri::totp(b"12345678901234567890".to_vec(), &TotpConfig::default(), "alice@example.com")
+//!     .with_issuer("Example");
+//! let text = uri.to_string();
+//! assert_eq!(
+//!     text,
+//!     "otpauth://totp/Example:alice%40example.com?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\
+//!      &issuer=Example&algorithm=SHA1&digits=6&period=30"
+//! );
+//!
+//! let parsed: OtpAuthUri = text.parse().unwrap();
+//! assert_eq!(parsed, uri);
+//! let totp = parsed.to_totp().unwrap();
+//! assert_eq!(totp.generate(59), "287082");
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::format::{base32_decode, base32_encode, BASE32_ALPHABET};
+use crate::hotp::HmacAlgorithm;
+use crate::totp::{Totp, TotpConfig};
+use crate::{Algorithm, Error, Passcode};
+
+/// Scheme of standard HOTP/TOTP URIs
+pub const SCHEME: &str = "otpauth";
+
+/// Scheme of challenge-response URIs
+pub const CR_SCHEME: &str = "otpauth-cr";
+
+/// Default number of digits when a URI omits `digits`
+const DEFAULT_DIGITS: u32 = 6;
+
+/// Default TOTP period when a URI omits `period`
+const DEFAULT_PERIOD: u64 = 30;
+
+/// The kind of one-time password a URI provisions, with its parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OtpKind {
+    /// Counter-based HOTP (RFC 4226)
+    Hotp {
+        /// HMAC hash function
+        algorithm: HmacAlgorithm,
+        /// Number of decimal digits
+        digits: u32,
+        /// Initial counter value
+        counter: u64,
+    },
+    /// Time-based TOTP (RFC 6238)
+    Totp {
+        /// HMAC hash function
+        algorithm: HmacAlgorithm,
+        /// Number of decimal digits
+        digits: u32,
+        /// Time step in seconds
+        period: u64,
+    },
+    /// Challenge-response [`Passcode`] key
+    ChallengeResponse {
+        /// Keyed hash algorithm
+        algorithm: Algorithm,
+    },
+}
+
+/// A parsed or to-be-rendered provisioning URI
+#[derive(Clone, PartialEq, Eq)]
+pub struct OtpAuthUri {
+    /// OTP kind and parameters
+    pub kind: OtpKind,
+    /// Name of the service provider, shown by authenticator apps
+    pub issuer: Option<String>,
+    /// Account name, e.g. a user's email address
+    pub account: String,
+    secret: Vec<u8>,
+}
+
+impl OtpAuthUri {
+    /// Creates a URI for the given kind, secret and account
+    pub fn new(kind: OtpKind, secret: Vec<u8>, account: &str) -> Self {
+        Self {
+            kind,
+            issuer: None,
+            account: account.to_string(),
+            secret,
+        }
+    }
+
+    /// Creates a TOTP URI from a [`TotpConfig`]
+    ///
+    /// `t0` and `window` are not part of the URI format and are not encoded.
+    pub fn totp(secret: Vec<u8>, config: &TotpConfig, account: &str) -> Self {
+        let kind = OtpKind::Totp {
+            algorithm: config.algorithm,
+            digits: config.digits,
+            period: config.step,
+        };
+        Self::new(kind, secret, account)
+    }
+
+    /// Creates a challenge-response URI for a [`Passcode`] key
+    pub fn challenge_response(algorithm: Algorithm, secret: Vec<u8>, account: &str) -> Self {
+        Self::new(OtpKind::ChallengeResponse { algorithm }, secret, account)
+    }
+
+    /// Sets the issuer
+    pub fn with_issuer(mut self, issuer: &str) -> Self {
+        self.issuer = Some(issuer.to_string());
+        self
+    }
+
+    /// Gets the shared secret
+    pub fn secret(&self) -> &[u8] {
+        &self.secret
+    }
+
+    /// Builds a [`Totp`] instance from a TOTP URI
+    pub fn to_totp(&self) -> Result<Totp, Error> {
+        match self.kind {
+            OtpKind::Totp {
+                algorithm,
+                digits,
+                period,
+            } => Totp::new(
+                self.secret.clone(),
+                TotpConfig {
+                    algorithm,
+                    digits,
+                    step: period,
+                    ..TotpConfig::default()
+                },
+            ),
+            _ => Err(Error::InvalidUri("not a TOTP URI")),
+        }
+    }
+
+    /// Builds a [`Passcode`] instance from a challenge-response URI
+    ///
+    /// The key is checked against the default policy like
+    /// [`Passcode::try_new`].
+    pub fn to_passcode(&self) -> Result<Passcode, Error> {
+        match self.kind {
+            OtpKind::ChallengeResponse { algorithm } => {
+                Passcode::try_new(algorithm, self.secret.clone())
+            }
+            _ => Err(Error::InvalidUri("not a challenge-response URI")),
+        }
+    }
+}
+
+impl fmt::Debug for OtpAuthUri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OtpAuthUri")
+            .field("kind", &self.kind)
+            .field("issuer", &self.issuer)
+            .field("account", &self.account)
+            .field("secret", &"<redacted>")
+            .finish()
+    }
+}
+
+impl fmt::Display for OtpAuthUri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (scheme, kind) = match self.kind {
+            OtpKind::Hotp { .. } => (SCHEME, "hotp"),
+            OtpKind::Totp { .. } => (SCHEME, "totp"),
+            OtpKind::ChallengeResponse { .. } => (CR_SCHEME, "cr"),
+        };
+
+        write!(f, "{}://{}/", scheme, kind)?;
+        if let Some(issuer) = &self.issuer {
+            write!(f, "{}:", percent_encode(issuer))?;
+        }
+        write!(
+            f,
+            "{}?secret={}",
+            percent_encode(&self.account),
+            base32_encode(&self.secret, BASE32_ALPHABET)
+        )?;
+        if let Some(issuer) = &self.issuer {
+            write!(f, "&issuer={}", percent_encode(issuer))?;
+        }
+
+        match self.kind {
+            OtpKind::Hotp {
+                algorithm,
+                digits,
+                counter,
+            } => write!(
+                f,
+                "&algorithm={}&digits={}&counter={}",
+                algorithm, digits, counter
+            ),
+            OtpKind::Totp {
+                algorithm,
+                digits,
+                period,
+            } => write!(
+                f,
+                "&algorithm={}&digits={}&period={}",
+                algorithm, digits, period
+            ),
+            OtpKind::ChallengeResponse { algorithm } => {
+                write!(f, "&algorithm={}", algorithm)
+            }
+        }
+    }
+}
+
+impl FromStr for OtpAuthUri {
+    type Err = Error;
+
+    fn from_str(uri: &str) -> Result<Self, Error> {
+        let (scheme, rest) = uri
+            .split_once("://")
+            .ok_or(Error::InvalidUri("missing scheme"))?;
+        let (kind, rest) = rest
+            .split_once('/')
+            .ok_or(Error::InvalidUri("missing OTP type"))?;
+        let (label, query) = rest.split_once('?').unwrap_or((rest, ""));
+
+        let label = percent_decode(label, false)?;
+        let (label_issuer, account) = match label.split_once(':') {
+            Some((issuer, account)) => (Some(issuer.to_string()), account.trim_start()),
+            None => (None, label.as_str()),
+        };
+
+        let mut params = Params::default();
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            params.set(name, percent_decode(value, true)?);
+        }
+
+        let secret = params
+            .secret
+            .take()
+            .ok_or(Error::InvalidUri("missing secret"))?;
+        let secret = base32_decode(&secret.to_ascii_uppercase(), BASE32_ALPHABET)
+            .ok_or(Error::InvalidUri("secret is not valid base32"))?;
+
+        let kind = match (scheme.to_ascii_lowercase().as_str(), kind) {
+            (SCHEME, "totp") => OtpKind::Totp {
+                algorithm: params.hmac_algorithm()?,
+                digits: params.digits()?,
+                period: parse_number(params.period.take(), DEFAULT_PERIOD, "invalid period")?,
+            },
+            (SCHEME, "hotp") => OtpKind::Hotp {
+                algorithm: params.hmac_algorithm()?,
+                digits: params.digits()?,
+                counter: params
+                    .counter
+                    .take()
+                    .ok_or(Error::InvalidUri("missing counter"))?
+                    .parse()
+                    .map_err(|_| Error::InvalidUri("invalid counter"))?,
+            },
+            (CR_SCHEME, "cr") => {
+                let name = params
+                    .algorithm
+                    .take()
+                    .ok_or(Error::InvalidUri("missing algorithm"))?;
+                let algorithm = Algorithm::all()
+                    .find(|algorithm| algorithm.as_str().eq_ignore_ascii_case(&name))
+                    .ok_or(Error::InvalidUri("unsupported algorithm"))?;
+                OtpKind::ChallengeResponse { algorithm }
+            }
+            (SCHEME, _) | (CR_SCHEME, _) => return Err(Error::InvalidUri("unsupported OTP type")),
+            _ => return Err(Error::InvalidUri("unsupported scheme")),
+        };
+
+        Ok(Self {
+            kind,
+            // The issuer parameter takes precedence over the label prefix
+            issuer: params.issuer.or(label_issuer),
+            account: account.to_string(),
+            secret,
+        })
+    }
+}
+
+/// Query parameters recognized in provisioning URIs
+#[derive(Default)]
+struct Params {
+    secret: Option<String>,
+    issuer: Option<String>,
+    algorithm: Option<String>,
+    digits: Option<String>,
+    period: Option<String>,
+    counter: Option<String>,
+}
+
+impl Params {
+    fn set(&mut self, name: &str, value: String) {
+        let slot = match name {
+            "secret" => &mut self.secret,
+            "issuer" => &mut self.issuer,
+            "algorithm" => &mut self.algorithm,
+            "digits" => &mut self.digits,
+            "period" => &mut self.period,
+            "counter" => &mut self.counter,
+            // Unknown parameters (e.g. `image`) are ignored
+            _ => return,
+        };
+        *slot = Some(value);
+    }
+
+    fn hmac_algorithm(&self) -> Result<HmacAlgorithm, Error> {
+        match self
+            .algorithm
+            .as_deref()
+            .map(str::to_ascii_uppercase)
+            .as_deref()
+        {
+            None | Some("SHA1") => Ok(HmacAlgorithm::Sha1),
+            Some("SHA256") => Ok(HmacAlgorithm::Sha256),
+            Some("SHA512") => Ok(HmacAlgorithm::Sha512),
+            Some(_) => Err(Error::InvalidUri("unsupported algorithm")),
+        }
+    }
+
+    fn digits(&self) -> Result<u32, Error> {
+        parse_number(self.digits.clone(), DEFAULT_DIGITS, "invalid digits")
+    }
+}
+
+fn parse_number<T: FromStr>(
+    value: Option<String>,
+    default: T,
+    reason: &'static str,
+) -> Result<T, Error> {
+    match value {
+        Some(value) => value.parse().map_err(|_| Error::InvalidUri(reason)),
+        None => Ok(default),
+    }
+}
+
+/// Percent-encodes everything but RFC 3986 unreserved characters
+fn percent_encode(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            out.push(byte as char);
+        } else {
+            out.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    out
+}
+
+/// Decodes percent escapes, and `+` as space in query values
+pub(crate) fn percent_decode(value: &str, plus_as_space: bool) -> Result<String, Error> {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = bytes
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or(Error::InvalidUri("invalid percent escape"))?;
+                out.push(hex);
+                i += 3;
+            }
+            b'+' if plus_as_space => {
+                out.push(b' ');
+                i += 1;
+            }
+            byte => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8(out).map_err(|_| Error::InvalidUri("label is not valid UTF-8"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_google_authenticator_example() {
+        let uri: OtpAuthUri =
+            "otpauth://totp/Example:alice@google.com?secret=JBSWY3DPEHPK3PXP&issuer=Example"
+                .parse()
+                .unwrap();
+
+        assert_eq!(uri.issuer.as_deref(), Some("Example"));
+        assert_eq!(uri.account, "alice@google.com");
+        assert_eq!(uri.secret(), b"Hello!\xde\xad\xbe\xef");
+        assert_eq!(
+            uri.kind,
+            OtpKind::Totp {
+                algorithm: HmacAlgorithm::Sha1,
+                digits: 6,
+                period: 30,
+            }
+        );
+    }
+
+    #[test]
+    fn test_hotp_round_trip() {
+        let uri = OtpAuthUri::new(
+            OtpKind::Hotp {
+                algorithm: HmacAlgorithm::Sha256,
+                digits: 8,
+                counter: 42,
+            },
+            vec![1, 2, 3, 4, 5],
+            "Bob Smith",
+        )
+        .with_issuer("ACME Co");
+        let text = uri.to_string();
+
+        assert_eq!(
+            text,
+            "otpauth://hotp/ACME%20Co:Bob%20Smith?secret=AEBAGBAF&issuer=ACME%20Co\
+             &algorithm=SHA256&digits=8&counter=42"
+        );
+        assert_eq!(text.parse::<OtpAuthUri>().unwrap(), uri);
+    }
+
+    #[test]
+    fn test_challenge_response_round_trip() {
+        let key = vec![7u8; 32];
+        let uri = OtpAuthUri::challenge_response(Algorithm::Sha3Kmac256, key.clone(), "device-1");
+        let text = uri.to_string();
+
+        assert!(text.starts_with("otpauth-cr://cr/device-1?secret="));
+        assert!(text.ends_with("&algorithm=SHA3-KMAC-256"));
+
+        let parsed: OtpAuthUri = text.parse().unwrap();
+        assert_eq!(parsed, uri);
+
+        let challenge = [9u8; 16];
+        let expected = Passcode::new(Algorithm::Sha3Kmac256, key).compute(&challenge);
+        assert_eq!(parsed.to_passcode().unwrap().compute(&challenge), expected);
+        assert!(parsed.to_totp().is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let cases = [
+            "https://totp/x?secret=AAAA",
+            "otpauth://totp/x",
+            "otpauth://totp/x?secret=1111",
+            "otpauth://hotp/x?secret=AAAA",
+            "otpauth://totp/x?secret=AAAA&algorithm=MD5",
+            "otpauth://totp/x?secret=AAAA&digits=six",
+            "otpauth://steam/x?secret=AAAA",
+            "otpauth-cr://cr/x?secret=AAAA&algorithm=SHA-1",
+            "otpauth://totp/%ZZ?secret=AAAA",
+        ];
+        for case in cases {
+            assert!(
+                matches!(case.parse::<OtpAuthUri>(), Err(Error::InvalidUri(_))),
+                "{}",
+                case
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_is_lenient() {
+        let uri: OtpAuthUri =
+            "otpauth://totp/My+Issuer%3A%20carol?secret=jbswy3dpehpk3pxp====&image=x"
+                .parse()
+                .unwrap();
+        assert_eq!(uri.issuer.as_deref(), Some("My+Issuer"));
+        assert_eq!(uri.account, "carol");
+        assert_eq!(uri.secret(), b"Hello!\xde\xad\xbe\xef");
+    }
+
+    #[test]
+    fn test_debug_redacts_secret() {
+        let uri = OtpAuthUri::challenge_response(Algorithm::Sha3Kmac256, vec![0xab; 32], "x");
+        assert!(!format!("{:?}", uri).contains("171"));
+        assert!(format!("{:?}", uri).contains("<redacted>"));
+    }
+}
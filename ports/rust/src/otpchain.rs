@@ -0,0 +1,165 @@
+//! Hash-chain (S/KEY / Lamport) one-time passwords
+//!
+//! The client keeps a secret seed and derives a chain by iterated hashing:
+//! `h(seed), h(h(seed)), ..., h^n(seed)`. The server stores only the chain
+//! head `h^n(seed)`. Passwords are revealed backwards, starting with
+//! `h^(n-1)(seed)`, and the server accepts a password when hashing it once
+//! yields the stored value, which it then replaces. Neither side needs a
+//! shared counter or clock, and the server holds nothing that can be used
+//! to produce the next password.
+
+use subtle::ConstantTimeEq;
+
+use crate::passcode::Hasher;
+use crate::Algorithm;
+
+/// Length in bytes of every link in the chain
+pub const LINK_LEN: usize = 32;
+
+/// Domain-separation key for the chain hash
+const CHAIN_KEY: &[u8] = b"passcode/v1/otpchain";
+
+fn step(hasher: Hasher, link: &[u8]) -> Vec<u8> {
+    let mut next = hasher(CHAIN_KEY, link);
+    next.truncate(LINK_LEN);
+    next
+}
+
+fn iterate(hasher: Hasher, start: &[u8], times: u32) -> Vec<u8> {
+    let mut link = start.to_vec();
+    for _ in 0..times {
+        link = step(hasher, &link);
+    }
+    link
+}
+
+/// Client side of a hash chain, holding the secret seed
+pub struct OtpChain {
+    algorithm: Algorithm,
+    seed: Vec<u8>,
+    length: u32,
+}
+
+impl OtpChain {
+    /// Creates a chain of `length` one-time passwords from a secret seed
+    ///
+    /// # Example
+    /// ```
+    /// use passcode::otpchain::{OtpChain, OtpChainVerifier};
+    /// use passcode::Algorithm;
+    ///
+    /// let chain = OtpChain::new(Algorithm::Sha3Kmac256, vec![7u8; 32], 100);
+    /// let mut verifier = OtpChainVerifier::new(Algorithm::Sha3Kmac256, chain.head(), 100);
+    ///
+    /// assert!(verifier.verify(&chain.password(1).unwrap()));
+    /// assert!(verifier.verify(&chain.password(2).unwrap()));
+    /// assert!(!verifier.verify(&chain.password(2).unwrap())); // no replay
+    /// ```
+    pub fn new(algorithm: Algorithm, seed: Vec<u8>, length: u32) -> Self {
+        Self {
+            algorithm,
+            seed,
+            length,
+        }
+    }
+
+    /// Gets the number of passwords in the chain
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+
+    /// Returns the chain head `h^n(seed)` to be registered with the server
+    pub fn head(&self) -> Vec<u8> {
+        iterate(self.algorithm.hasher(), &self.seed, self.length)
+    }
+
+    /// Returns the `index`-th password to use (1-based), `h^(n-index)(seed)`
+    ///
+    /// Returns `None` once the chain is exhausted.
+    pub fn password(&self, index: u32) -> Option<Vec<u8>> {
+        if index == 0 || index > self.length {
+            return None;
+        }
+        Some(iterate(
+            self.algorithm.hasher(),
+            &self.seed,
+            self.length - index,
+        ))
+    }
+}
+
+/// Server side of a hash chain, holding only the most recent link
+#[derive(Debug, Clone)]
+pub struct OtpChainVerifier {
+    algorithm: Algorithm,
+    current: Vec<u8>,
+    remaining: u32,
+}
+
+impl OtpChainVerifier {
+    /// Creates a verifier from a registered chain head
+    pub fn new(algorithm: Algorithm, head: Vec<u8>, length: u32) -> Self {
+        Self {
+            algorithm,
+            current: head,
+            remaining: length,
+        }
+    }
+
+    /// Gets the stored link, to be persisted after each successful verification
+    pub fn current(&self) -> &[u8] {
+        &self.current
+    }
+
+    /// Gets the number of passwords left before the chain must be re-seeded
+    pub fn remaining(&self) -> u32 {
+        self.remaining
+    }
+
+    /// Verifies the next password and advances the chain on success
+    pub fn verify(&mut self, password: &[u8]) -> bool {
+        if self.remaining == 0 {
+            return false;
+        }
+
+        let hashed = step(self.algorithm.hasher(), password);
+        if !bool::from(hashed.ct_eq(&self.current)) {
+            return false;
+        }
+
+        self.current = password.to_vec();
+        self.remaining -= 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_full_chain() {
+        let chain = OtpChain::new(Algorithm::Blake3KeyedMode256, vec![1u8; 32], 5);
+        let mut verifier = OtpChainVerifier::new(Algorithm::Blake3KeyedMode256, chain.head(), 5);
+
+        for index in 1..=5 {
+            let password = chain.password(index).unwrap();
+            assert_eq!(password.len(), LINK_LEN);
+            assert!(verifier.verify(&password));
+        }
+
+        assert_eq!(verifier.remaining(), 0);
+        assert_eq!(verifier.current(), &chain.seed[..]);
+        assert!(chain.password(6).is_none());
+    }
+
+    #[test]
+    fn test_rejects_out_of_order_password() {
+        let chain = OtpChain::new(Algorithm::Sha3Kmac128, vec![1u8; 32], 5);
+        let mut verifier = OtpChainVerifier::new(Algorithm::Sha3Kmac128, chain.head(), 5);
+
+        assert!(!verifier.verify(&chain.password(2).unwrap()));
+        assert!(verifier.verify(&chain.password(1).unwrap()));
+        assert_eq!(verifier.remaining(), 4);
+    }
+}
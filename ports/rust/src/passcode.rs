@@ -1,8 +1,31 @@
-use crate::blake3_keyed::{blake3_keyed_mode256, blake3_keyed_mode512};
-use crate::sha3_kmac::{sha3_kmac128_for_passcode, sha3_kmac256_for_passcode};
+#[cfg(feature = "streebog")]
+use crate::hmac_streebog::{
+    hmac_streebog256_expand, hmac_streebog256_for_passcode, hmac_streebog256_xof,
+};
+use crate::blake3_keyed::{blake3_keyed_mode, blake3_keyed_mode256, blake3_keyed_mode512};
+use crate::canonicalize::Canonicalization;
+use crate::challenge::{Challenge, ChallengeBinding, DEFAULT_CHALLENGE_LEN, DEFAULT_TTL};
+use crate::format::{Grouping, OtpFormat};
+use crate::hmac_sha::{counter_mode_expand, hmac_sha1_for_passcode, hmac_sha256_for_passcode};
+use crate::kdf::{derive_subkey, derive_subkey_blake3, key_fingerprint, labels, KeyId};
+#[cfg(any(feature = "argon2", feature = "pbkdf2", feature = "scrypt"))]
+use crate::password::KeyDerivation;
+use crate::sha3_kmac::{
+    sha3_kmac128, sha3_kmac128_for_passcode, sha3_kmac256, sha3_kmac256_for_passcode,
+    sha3_kmac512_for_passcode, PASSCODE_CUSTOMIZATION,
+};
+use crate::policy::{default_policy, PolicyMode};
+use crate::rng::{CryptoRngCore, SharedRng};
+use crate::secret::SecretBytes;
+use crate::self_test::ensure_self_test;
+use crate::Error;
+use subtle::ConstantTimeEq;
+use std::sync::Arc;
+use std::time::SystemTime;
 
 /// Available hash algorithms for OTP generation
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum Algorithm {
     /// SHA3-KMAC with 128-bit security
     Sha3Kmac128,
@@ -12,6 +35,17 @@ pub enum Algorithm {
     Blake3KeyedMode128,
     /// BLAKE3 Keyed Mode with 256-bit security
     Blake3KeyedMode256,
+    /// SHA3-KMAC256 with 512-bit output and a 128-bit OTP, for long-lived
+    /// machine credentials
+    Sha3Kmac512,
+    /// HMAC-Streebog-256 (GOST R 34.11-2012) with 256-bit security
+    #[cfg(feature = "streebog")]
+    HmacStreebog256,
+    /// HMAC-SHA-256 with 256-bit security, as computed by TPMs and HSMs
+    HmacSha256,
+    /// HMAC-SHA-1 with 128-bit security, as computed by YubiKey
+    /// challenge-response slots
+    HmacSha1,
 }
 
 impl Algorithm {
@@ -22,6 +56,128 @@ impl Algorithm {
             Algorithm::Sha3Kmac256 => "SHA3-KMAC-256",
             Algorithm::Blake3KeyedMode128 => "BLAKE3-Keyed-Mode-128",
             Algorithm::Blake3KeyedMode256 => "BLAKE3-Keyed-Mode-256",
+            Algorithm::Sha3Kmac512 => "SHA3-KMAC-512",
+            #[cfg(feature = "streebog")]
+            Algorithm::HmacStreebog256 => "HMAC-Streebog-256",
+            Algorithm::HmacSha256 => "HMAC-SHA-256",
+            Algorithm::HmacSha1 => "HMAC-SHA-1",
+        }
+    }
+
+    /// Returns the numeric identifier used by the FFI and WASM bindings
+    pub fn id(&self) -> u8 {
+        match self {
+            Algorithm::Sha3Kmac128 => 0,
+            Algorithm::Sha3Kmac256 => 1,
+            Algorithm::Blake3KeyedMode128 => 2,
+            Algorithm::Blake3KeyedMode256 => 3,
+            Algorithm::Sha3Kmac512 => 4,
+            #[cfg(feature = "streebog")]
+            Algorithm::HmacStreebog256 => 5,
+            Algorithm::HmacSha256 => 6,
+            Algorithm::HmacSha1 => 7,
+        }
+    }
+
+    /// Looks up an algorithm by its numeric identifier
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Algorithm::Sha3Kmac128),
+            1 => Some(Algorithm::Sha3Kmac256),
+            2 => Some(Algorithm::Blake3KeyedMode128),
+            3 => Some(Algorithm::Blake3KeyedMode256),
+            4 => Some(Algorithm::Sha3Kmac512),
+            #[cfg(feature = "streebog")]
+            5 => Some(Algorithm::HmacStreebog256),
+            6 => Some(Algorithm::HmacSha256),
+            7 => Some(Algorithm::HmacSha1),
+            _ => None,
+        }
+    }
+
+    /// Returns all algorithms enabled in this build
+    pub fn all() -> impl Iterator<Item = Algorithm> {
+        (0..=u8::MAX).filter_map(Algorithm::from_id)
+    }
+
+    /// Returns the security tier in bits
+    ///
+    /// The 512-bit tier uses KMAC256 with a 512-bit output; its resistance to
+    /// key recovery is bounded by the key length and the 256-bit capacity.
+    pub fn security_bits(&self) -> u32 {
+        match self {
+            Algorithm::Sha3Kmac128 | Algorithm::Blake3KeyedMode128 | Algorithm::HmacSha1 => 128,
+            Algorithm::Sha3Kmac256 | Algorithm::Blake3KeyedMode256 | Algorithm::HmacSha256 => 256,
+            #[cfg(feature = "streebog")]
+            Algorithm::HmacStreebog256 => 256,
+            Algorithm::Sha3Kmac512 => 512,
+        }
+    }
+
+    /// Returns the recommended secret key length in bytes for this tier
+    pub fn recommended_key_len(&self) -> usize {
+        self.security_bits() as usize / 8
+    }
+
+    /// Generates a secret key of the
+    /// [recommended length](Self::recommended_key_len) from `rng`
+    ///
+    /// # Panics
+    /// Panics if the generator fails.
+    pub fn generate_key(&self, rng: &mut impl CryptoRngCore) -> Vec<u8> {
+        let mut key = vec![0u8; self.recommended_key_len()];
+        rng.fill_bytes(&mut key);
+        key
+    }
+
+    /// Generates a secret key of the recommended length from the operating
+    /// system's CSPRNG
+    ///
+    /// # Panics
+    /// Panics if the operating system's CSPRNG fails.
+    pub fn random_key(&self) -> Vec<u8> {
+        let mut key = vec![0u8; self.recommended_key_len()];
+        SharedRng::os()
+            .fill(&mut key)
+            .expect("operating system CSPRNG failed");
+        key
+    }
+
+    /// Returns the number of MAC bytes kept in the OTP
+    pub fn otp_bytes(&self) -> usize {
+        match self {
+            Algorithm::Sha3Kmac512 => 16,
+            _ => 6,
+        }
+    }
+
+    /// Returns the extendable-output function backing this algorithm
+    pub fn xof(&self) -> XofAlgorithm {
+        match self {
+            Algorithm::Sha3Kmac128 => XofAlgorithm::Sha3Kmac128,
+            Algorithm::Sha3Kmac256 | Algorithm::Sha3Kmac512 => XofAlgorithm::Sha3Kmac256,
+            Algorithm::Blake3KeyedMode128 | Algorithm::Blake3KeyedMode256 => {
+                XofAlgorithm::Blake3Keyed
+            }
+            #[cfg(feature = "streebog")]
+            Algorithm::HmacStreebog256 => XofAlgorithm::HmacStreebog256,
+            Algorithm::HmacSha256 => XofAlgorithm::HmacSha256,
+            Algorithm::HmacSha1 => XofAlgorithm::HmacSha1,
+        }
+    }
+
+    /// Returns the keyed hash function backing this algorithm
+    pub(crate) fn hasher(&self) -> Hasher {
+        match self {
+            Algorithm::Sha3Kmac128 => sha3_kmac128_for_passcode,
+            Algorithm::Sha3Kmac256 => sha3_kmac256_for_passcode,
+            Algorithm::Blake3KeyedMode128 => blake3_keyed_mode256, // Using 256-bit output for 128-bit mode
+            Algorithm::Blake3KeyedMode256 => blake3_keyed_mode512,
+            Algorithm::Sha3Kmac512 => sha3_kmac512_for_passcode,
+            #[cfg(feature = "streebog")]
+            Algorithm::HmacStreebog256 => hmac_streebog256_for_passcode,
+            Algorithm::HmacSha256 => hmac_sha256_for_passcode,
+            Algorithm::HmacSha1 => hmac_sha1_for_passcode,
         }
     }
 }
@@ -32,14 +188,233 @@ impl std::fmt::Display for Algorithm {
     }
 }
 
+/// Extendable-output keyed hash functions
+///
+/// Unlike [`Algorithm`], which fixes the output length, these can produce
+/// output of any length. For KMAC the requested length is bound into the
+/// output, so a shorter request is not a prefix of a longer one; BLAKE3
+/// output is a prefix-consistent stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum XofAlgorithm {
+    /// KMAC128 with the passcode customization string
+    Sha3Kmac128,
+    /// KMAC256 with the passcode customization string
+    Sha3Kmac256,
+    /// BLAKE3 keyed mode
+    Blake3Keyed,
+    /// HMAC-Streebog-256 in counter mode (NIST SP 800-108)
+    #[cfg(feature = "streebog")]
+    HmacStreebog256,
+    /// HMAC-SHA-256 in counter mode (NIST SP 800-108)
+    HmacSha256,
+    /// HMAC-SHA-1 in counter mode (NIST SP 800-108)
+    HmacSha1,
+}
+
+impl XofAlgorithm {
+    /// Returns the algorithm name as a string
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            XofAlgorithm::Sha3Kmac128 => "SHA3-KMAC-128-XOF",
+            XofAlgorithm::Sha3Kmac256 => "SHA3-KMAC-256-XOF",
+            XofAlgorithm::Blake3Keyed => "BLAKE3-Keyed-XOF",
+            #[cfg(feature = "streebog")]
+            XofAlgorithm::HmacStreebog256 => "HMAC-Streebog-256-CTR",
+            XofAlgorithm::HmacSha256 => "HMAC-SHA-256-CTR",
+            XofAlgorithm::HmacSha1 => "HMAC-SHA-1-CTR",
+        }
+    }
+
+    /// Computes `out_len` bytes of keyed output over `data`
+    ///
+    /// # Panics
+    /// Panics for the counter-mode HMAC functions if `out_len` is 512 MiB or
+    /// more, which their length field cannot encode.
+    pub fn compute(&self, key: &[u8], data: &[u8], out_len: usize) -> Vec<u8> {
+        match self {
+            XofAlgorithm::Sha3Kmac128 => sha3_kmac128(key, PASSCODE_CUSTOMIZATION, data, out_len),
+            XofAlgorithm::Sha3Kmac256 => sha3_kmac256(key, PASSCODE_CUSTOMIZATION, data, out_len),
+            XofAlgorithm::Blake3Keyed => blake3_keyed_mode(key, data, out_len),
+            #[cfg(feature = "streebog")]
+            XofAlgorithm::HmacStreebog256 => hmac_streebog256_xof(key, data, out_len)
+                .expect("HMAC-Streebog-256 output is below 512 MiB"),
+            XofAlgorithm::HmacSha256 => counter_mode_expand(
+                |block| Ok(hmac_sha256_for_passcode(key, block)),
+                PASSCODE_CUSTOMIZATION,
+                data,
+                out_len,
+            )
+            .expect("HMAC-SHA-256 output is below 512 MiB"),
+            XofAlgorithm::HmacSha1 => counter_mode_expand(
+                |block| Ok(hmac_sha1_for_passcode(key, block)),
+                PASSCODE_CUSTOMIZATION,
+                data,
+                out_len,
+            )
+            .expect("HMAC-SHA-1 output is below 512 MiB"),
+        }
+    }
+
+    /// Returns true for the counter-mode HMAC functions, which only need the
+    /// keyed hash and so work with a [`MacBackend`] too
+    fn is_counter_mode(&self) -> bool {
+        match self {
+            XofAlgorithm::Sha3Kmac128 | XofAlgorithm::Sha3Kmac256 | XofAlgorithm::Blake3Keyed => {
+                false
+            }
+            #[cfg(feature = "streebog")]
+            XofAlgorithm::HmacStreebog256 => true,
+            XofAlgorithm::HmacSha256 | XofAlgorithm::HmacSha1 => true,
+        }
+    }
+}
+
+impl std::fmt::Display for XofAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
 /// Hasher function type
-type Hasher = fn(&[u8], &[u8]) -> Vec<u8>;
+pub(crate) type Hasher = fn(&[u8], &[u8]) -> Vec<u8>;
+
+/// Keyed hash computed where the key lives, e.g. in a TPM, an HSM or a
+/// security key
+///
+/// A passcode built with [`Passcode::from_backend`] hands every MAC to the
+/// backend, so the key never enters process memory. The backend must compute
+/// the same keyed hash as the software implementation of its
+/// [`algorithm`](Self::algorithm), which has to be one of the HMAC
+/// algorithms: their derived keys only need the keyed hash, while KMAC and
+/// BLAKE3 need the key itself.
+pub trait MacBackend: Send + Sync {
+    /// Algorithm the backend computes
+    fn algorithm(&self) -> Algorithm;
+
+    /// Identifies the key without revealing it
+    ///
+    /// The key cannot be fingerprinted where it cannot be read, so backends
+    /// derive this from the device's own name for the key instead, and it
+    /// differs from the [`key_fingerprint`] of the same key in software.
+    fn key_id(&self) -> KeyId;
+
+    /// Computes the full keyed hash of `data`
+    fn mac(&self, data: &[u8]) -> Result<Vec<u8>, Error>;
+}
 
 /// Passcode struct for Challenge-Response based OTP authentication
 pub struct Passcode {
     algorithm: Algorithm,
-    key: Vec<u8>,
+    key: SecretBytes,
     hasher: Hasher,
+    backend: Option<Arc<dyn MacBackend>>,
+    format: OtpFormat,
+    grouping: Option<Grouping>,
+    canonicalization: Canonicalization,
+    /// Policy the passcode was built under, the process-wide default if none
+    policy: Option<PolicyMode>,
+}
+
+/// Builder for [`Passcode`] instances with non-default options
+pub struct PasscodeBuilder {
+    algorithm: Algorithm,
+    key: Vec<u8>,
+    backend: Option<Arc<dyn MacBackend>>,
+    format: OtpFormat,
+    grouping: Option<Grouping>,
+    canonicalization: Option<Canonicalization>,
+    policy: Option<PolicyMode>,
+    allow_weak_key: bool,
+}
+
+impl PasscodeBuilder {
+    /// Sets the output format (hexadecimal by default)
+    pub fn format(mut self, format: OtpFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Groups the output for display, e.g. `Grouping::new(3, '-')`
+    pub fn grouping(mut self, grouping: Grouping) -> Self {
+        self.grouping = Some(grouping);
+        self
+    }
+
+    /// Sets the input canonicalization rules applied by `verify`
+    ///
+    /// Defaults to [`Canonicalization::for_format`] of the configured format.
+    pub fn canonicalization(mut self, canonicalization: Canonicalization) -> Self {
+        self.canonicalization = Some(canonicalization);
+        self
+    }
+
+    /// Sets the policy to enforce (the process-wide default otherwise)
+    pub fn policy(mut self, policy: PolicyMode) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Accepts keys shorter than the algorithm's
+    /// [recommended length](Algorithm::recommended_key_len)
+    ///
+    /// Meant for tests and fixed vectors; a short key caps the security of
+    /// the OTP below the algorithm's tier. The policy's own minimum still
+    /// applies.
+    pub fn allow_weak_key(mut self) -> Self {
+        self.allow_weak_key = true;
+        self
+    }
+
+    /// Validates the configuration and builds the Passcode instance
+    ///
+    /// Fails with [`Error::KeyTooShort`] for keys shorter than
+    /// [`Algorithm::recommended_key_len`], one byte per eight bits of
+    /// security, unless [`allow_weak_key`](Self::allow_weak_key) is set.
+    /// The key of a [`MacBackend`] cannot be measured, so only its algorithm
+    /// is checked against the policy.
+    pub fn build(self) -> Result<Passcode, Error> {
+        ensure_self_test()?;
+        let policy = self.policy.unwrap_or_else(default_policy);
+        if self.backend.is_some() {
+            if !self.algorithm.xof().is_counter_mode() {
+                return Err(Error::InvalidFormat(
+                    "MAC backends must compute an HMAC algorithm",
+                ));
+            }
+            if !policy.is_approved(self.algorithm) {
+                return Err(Error::AlgorithmNotApproved(self.algorithm.as_str()));
+            }
+        } else {
+            let min = self.algorithm.recommended_key_len();
+            if !self.allow_weak_key && self.key.len() < min {
+                return Err(Error::KeyTooShort {
+                    min,
+                    actual: self.key.len(),
+                });
+            }
+            policy.check(self.algorithm, self.key.len())?;
+        }
+        self.format.validate()?;
+        if let Some(grouping) = &self.grouping {
+            grouping.validate(&self.format)?;
+        }
+
+        let canonicalization = self
+            .canonicalization
+            .unwrap_or_else(|| Canonicalization::for_format(&self.format));
+
+        Ok(Passcode {
+            algorithm: self.algorithm,
+            key: SecretBytes::new(self.key),
+            hasher: self.algorithm.hasher(),
+            backend: self.backend,
+            format: self.format,
+            grouping: self.grouping,
+            canonicalization,
+            policy: self.policy,
+        })
+    }
 }
 
 impl Passcode {
@@ -57,27 +432,158 @@ impl Passcode {
     /// let passcode = Passcode::new(Algorithm::Blake3KeyedMode256, key);
     /// ```
     pub fn new(algorithm: Algorithm, key: Vec<u8>) -> Self {
-        let hasher: Hasher = match algorithm {
-            Algorithm::Sha3Kmac128 => sha3_kmac128_for_passcode,
-            Algorithm::Sha3Kmac256 => sha3_kmac256_for_passcode,
-            Algorithm::Blake3KeyedMode128 => blake3_keyed_mode256, // Using 256-bit output for 128-bit mode
-            Algorithm::Blake3KeyedMode256 => blake3_keyed_mode512,
-        };
-
         Self {
+            algorithm,
+            key: SecretBytes::new(key),
+            hasher: algorithm.hasher(),
+            backend: None,
+            format: OtpFormat::Hex,
+            grouping: None,
+            canonicalization: Canonicalization::for_format(&OtpFormat::Hex),
+            policy: None,
+        }
+    }
+
+    /// Starts building a Passcode instance with non-default options
+    ///
+    /// # Example
+    /// ```
+    /// use passcode::{Algorithm, OtpFormat, Passcode};
+    ///
+    /// let passcode = Passcode::builder(Algorithm::Sha3Kmac256, vec![0u8; 32])
+    ///     .format(OtpFormat::Decimal(6))
+    ///     .build()
+    ///     .unwrap();
+    /// let otp = passcode.compute(b"challenge");
+    /// assert_eq!(otp.len(), 6);
+    /// assert!(passcode.verify(b"challenge", &otp));
+    /// ```
+    pub fn builder(algorithm: Algorithm, key: Vec<u8>) -> PasscodeBuilder {
+        PasscodeBuilder {
             algorithm,
             key,
-            hasher,
+            backend: None,
+            format: OtpFormat::Hex,
+            grouping: None,
+            canonicalization: None,
+            policy: None,
+            allow_weak_key: false,
         }
     }
 
+    /// Creates a new Passcode instance, failing closed on integrity errors
+    ///
+    /// Keys shorter than [`Algorithm::recommended_key_len`] are rejected
+    /// with [`Error::KeyTooShort`], and the configuration is checked against
+    /// the process-wide default policy (see [`crate::policy`]). With the `power-on-self-test` feature, the
+    /// first call in a process runs [`self_test`](crate::self_test()) and
+    /// every call returns [`Error::SelfTestFailed`] if it did not pass.
+    ///
+    /// # Example
+    /// ```
+    /// use passcode::{Passcode, Algorithm};
+    ///
+    /// let passcode = Passcode::try_new(Algorithm::Sha3Kmac256, vec![0u8; 32]).unwrap();
+    /// ```
+    pub fn try_new(algorithm: Algorithm, key: Vec<u8>) -> Result<Self, Error> {
+        Self::try_new_with_policy(algorithm, key, default_policy())
+    }
+
+    /// Creates a new Passcode instance checked against an explicit policy
+    ///
+    /// # Example
+    /// ```
+    /// use passcode::policy::PolicyMode;
+    /// use passcode::{Passcode, Algorithm};
+    ///
+    /// let result = Passcode::try_new_with_policy(
+    ///     Algorithm::Sha3Kmac128,
+    ///     vec![0u8; 32],
+    ///     PolicyMode::Strict,
+    /// );
+    /// assert!(result.is_err());
+    /// ```
+    pub fn try_new_with_policy(
+        algorithm: Algorithm,
+        key: Vec<u8>,
+        policy: PolicyMode,
+    ) -> Result<Self, Error> {
+        Self::builder(algorithm, key).policy(policy).build()
+    }
+
+    /// Creates a Passcode instance whose MACs are computed by `backend`
+    ///
+    /// The configuration is checked against the process-wide default
+    /// policy like [`try_new`](Self::try_new). The key cannot be read, so
+    /// exporting, wrapping, escrowing or splitting it panics; derived keys
+    /// and [`compute_raw`](Self::compute_raw) are computed through the
+    /// backend. The infallible methods such as [`compute`](Self::compute)
+    /// panic if the backend fails, and [`verify`](Self::verify) rejects;
+    /// [`try_compute`](Self::try_compute) and
+    /// [`try_verify`](Self::try_verify) return its error instead.
+    pub fn from_backend(backend: impl MacBackend + 'static) -> Result<Self, Error> {
+        Self::builder_with_backend(backend).build()
+    }
+
+    /// Starts building a Passcode instance whose MACs are computed by
+    /// `backend`, see [`from_backend`](Self::from_backend)
+    pub fn builder_with_backend(backend: impl MacBackend + 'static) -> PasscodeBuilder {
+        let mut builder = Self::builder(backend.algorithm(), Vec::new());
+        builder.backend = Some(Arc::new(backend));
+        builder
+    }
+
+    /// Creates a new Passcode instance from a user password
+    ///
+    /// The shared secret is derived with a password-based KDF (Argon2id,
+    /// PBKDF2 or scrypt, depending on enabled features), so a raw password is
+    /// never used directly as the key. Both sides must use the same salt and
+    /// parameters. The derived key has
+    /// [`DERIVED_KEY_LEN`](crate::DERIVED_KEY_LEN) bytes, too
+    /// short for [`Algorithm::Sha3Kmac512`].
+    ///
+    /// # Arguments
+    /// * `algorithm` - The hash algorithm to use
+    /// * `password` - The user password
+    /// * `salt` - A per-user salt
+    /// * `kdf` - The key derivation function and its parameters
+    ///
+    /// # Example
+    /// ```
+    /// use passcode::{Algorithm, Argon2Params, Passcode};
+    ///
+    /// let params = Argon2Params {
+    ///     memory_kib: 1024,
+    ///     iterations: 1,
+    ///     ..Argon2Params::default()
+    /// };
+    /// let passcode = Passcode::from_password(
+    ///     Algorithm::Sha3Kmac256,
+    ///     b"correct horse battery staple",
+    ///     b"user@example.com",
+    ///     params,
+    /// ).unwrap();
+    /// assert_eq!(passcode.compute(b"challenge").len(), 12);
+    /// ```
+    #[cfg(any(feature = "argon2", feature = "pbkdf2", feature = "scrypt"))]
+    pub fn from_password(
+        algorithm: Algorithm,
+        password: &[u8],
+        salt: &[u8],
+        kdf: impl KeyDerivation,
+    ) -> Result<Self, Error> {
+        let key = kdf.derive_key(password, salt)?;
+        Self::try_new(algorithm, key)
+    }
+
     /// Computes an OTP from the given challenge data
     ///
     /// # Arguments
     /// * `data` - The challenge data (typically a random value from the server)
     ///
     /// # Returns
-    /// A 12-character hexadecimal OTP string
+    /// The OTP in the configured format; by default a 12-character
+    /// hexadecimal string (32 characters for [`Algorithm::Sha3Kmac512`])
     ///
     /// # Example
     /// ```
@@ -89,16 +595,359 @@ impl Passcode {
     /// let otp = passcode.compute(&challenge);
     /// assert_eq!(otp.len(), 12);
     /// ```
+    ///
+    /// # Panics
+    /// Panics if the [`MacBackend`] fails; see
+    /// [`try_compute`](Self::try_compute).
     pub fn compute(&self, data: &[u8]) -> String {
-        let mut hashed = (self.hasher)(&self.key, data);
+        self.try_compute(data).expect("MAC backend failed")
+    }
+
+    /// Computes an OTP like [`compute`](Self::compute), returning the error
+    /// of a failed [`MacBackend`]
+    pub fn try_compute(&self, data: &[u8]) -> Result<String, Error> {
+        let code = self
+            .format
+            .encode(&self.mac(data)?, self.algorithm.otp_bytes());
+        Ok(match &self.grouping {
+            Some(grouping) => grouping.apply(&code),
+            None => code,
+        })
+    }
+
+    /// Verifies an OTP submitted for the given challenge data
+    ///
+    /// The input is first cleaned up by the configured [`Canonicalization`]
+    /// rules (by default trimming, separator stripping and Unicode digit
+    /// mapping) and then compared in constant time. Formats with
+    /// a canonical decoding, such as [`OtpFormat::Base58`], compare the
+    /// decoded bytes instead of the text. With a [`Grouping`] configured,
+    /// the separator is optional in the input.
+    ///
+    /// # Example
+    /// ```
+    /// use passcode::{Passcode, Algorithm};
+    ///
+    /// let passcode = Passcode::new(Algorithm::Sha3Kmac256, vec![0u8; 32]);
+    /// let otp = passcode.compute(b"challenge");
+    /// assert!(passcode.verify(b"challenge", &otp));
+    /// assert!(!passcode.verify(b"other challenge", &otp));
+    /// ```
+    ///
+    /// Rejects if the [`MacBackend`] fails; see
+    /// [`try_verify`](Self::try_verify).
+    pub fn verify(&self, data: &[u8], otp: &str) -> bool {
+        self.try_verify(data, otp).unwrap_or(false)
+    }
+
+    /// Verifies an OTP like [`verify`](Self::verify), returning the error
+    /// of a failed [`MacBackend`] instead of rejecting
+    pub fn try_verify(&self, data: &[u8], otp: &str) -> Result<bool, Error> {
+        let otp = self.canonicalization.apply(otp, &self.format);
+        let otp = match &self.grouping {
+            Some(grouping) => grouping.strip(&otp),
+            None => otp,
+        };
+        Ok(self
+            .format
+            .verify(&self.mac(data)?, self.algorithm.otp_bytes(), &otp))
+    }
+
+    /// Computes the truncated MAC as an integer
+    ///
+    /// The value is the big-endian integer of the same leading bytes that
+    /// the hexadecimal format encodes, independent of the configured output
+    /// format, so for the 128/256-bit tiers it equals the hex OTP parsed in
+    /// base 16 (a 48-bit value). [`Algorithm::Sha3Kmac512`] truncates to
+    /// 16 bytes, which do not fit; its first 8 bytes are used.
+    ///
+    /// # Example
+    /// ```
+    /// use passcode::{Passcode, Algorithm};
+    ///
+    /// let passcode = Passcode::new(Algorithm::Sha3Kmac256, vec![0u8; 32]);
+    /// let value = passcode.compute_u64(b"challenge");
+    /// let otp = passcode.compute(b"challenge");
+    /// assert_eq!(value, u64::from_str_radix(&otp, 16).unwrap());
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if the [`MacBackend`] fails.
+    pub fn compute_u64(&self, data: &[u8]) -> u64 {
+        let len = self.algorithm.otp_bytes().min(8);
+        let hashed = self.full_mac(data).expect("MAC backend failed");
+
+        let mut bytes = [0u8; 8];
+        bytes[8 - len..].copy_from_slice(&hashed[..len]);
+        u64::from_be_bytes(bytes)
+    }
+
+    /// Verifies an OTP stored or entered as an integer, in constant time
+    ///
+    /// The counterpart of [`compute_u64`](Self::compute_u64); rejects if
+    /// the [`MacBackend`] fails.
+    pub fn verify_u64(&self, data: &[u8], otp: u64) -> bool {
+        let len = self.algorithm.otp_bytes().min(8);
+        let Ok(hashed) = self.full_mac(data) else {
+            return false;
+        };
+
+        let mut expected = [0u8; 8];
+        expected[8 - len..].copy_from_slice(&hashed[..len]);
+        expected.ct_eq(&otp.to_be_bytes()).into()
+    }
+
+    /// Computes the OTP as raw bytes, whatever the output format
+    ///
+    /// These are the [`Algorithm::otp_bytes`] bytes that the default
+    /// hexadecimal OTP encodes. Binary protocols such as [`crate::wire`]
+    /// carry them instead of text.
+    ///
+    /// # Example
+    /// ```
+    /// use passcode::{Passcode, Algorithm};
+    ///
+    /// let passcode = Passcode::new(Algorithm::Sha3Kmac256, vec![0u8; 32]);
+    /// let mac = passcode.compute_mac(b"challenge");
+    /// assert_eq!(hex::encode(&mac), passcode.compute(b"challenge"));
+    /// assert!(passcode.verify_mac(b"challenge", &mac));
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if the [`MacBackend`] fails.
+    pub fn compute_mac(&self, data: &[u8]) -> Vec<u8> {
+        self.try_compute_mac(data).expect("MAC backend failed")
+    }
+
+    /// Verifies raw OTP bytes from [`compute_mac`](Self::compute_mac), in
+    /// constant time; rejects if the [`MacBackend`] fails
+    pub fn verify_mac(&self, data: &[u8], mac: &[u8]) -> bool {
+        match self.try_compute_mac(data) {
+            Ok(expected) => expected.ct_eq(mac).into(),
+            Err(_) => false,
+        }
+    }
+
+    /// Computes the raw OTP bytes, returning the error of a failed backend
+    fn try_compute_mac(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut mac = self.mac(data)?;
+        mac.truncate(self.algorithm.otp_bytes());
+        Ok(mac)
+    }
+
+    /// Computes the OTP on Tokio's blocking thread pool (feature `tokio`)
+    ///
+    /// Hashing is CPU-bound, so computing over a payload of many megabytes
+    /// on an async worker stalls every task scheduled on it. Small payloads
+    /// are cheaper to hash in place with [`compute`](Self::compute).
+    ///
+    /// # Panics
+    /// Panics when called outside a Tokio runtime, or resumes the panic if
+    /// the computation panicked.
+    #[cfg(feature = "tokio")]
+    pub async fn compute_offloaded(self: Arc<Self>, data: Vec<u8>) -> String {
+        offload(move || self.compute(&data)).await
+    }
+
+    /// Verifies an OTP on Tokio's blocking thread pool (feature `tokio`)
+    ///
+    /// See [`compute_offloaded`](Self::compute_offloaded).
+    ///
+    /// # Panics
+    /// Panics when called outside a Tokio runtime, or resumes the panic if
+    /// the verification panicked.
+    #[cfg(feature = "tokio")]
+    pub async fn verify_offloaded(self: Arc<Self>, data: Vec<u8>, otp: String) -> bool {
+        offload(move || self.verify(&data, &otp)).await
+    }
+
+    /// Computes the MAC over the challenge data, padded to what the format reads
+    fn mac(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let mac_bytes = self.format.mac_bytes(self.algorithm.otp_bytes());
+        let mut hashed = self.full_mac(data)?;
+
+        // Ensure we have enough bytes for the truncated OTP
+        if hashed.len() < mac_bytes {
+            hashed.resize(mac_bytes, 0);
+        }
+
+        Ok(hashed)
+    }
+
+    /// Computes the full keyed hash, in process or through the backend
+    fn full_mac(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let Some(backend) = &self.backend else {
+            return Ok((self.hasher)(&self.key, data));
+        };
+        let hashed = backend.mac(data)?;
+        if hashed.len() < self.algorithm.otp_bytes() {
+            return Err(Error::Hardware(format!(
+                "backend returned {} MAC bytes, {} required",
+                hashed.len(),
+                self.algorithm.otp_bytes()
+            )));
+        }
+        Ok(hashed)
+    }
+
+    /// Computes `out_len` bytes of raw keyed output over the challenge data
+    ///
+    /// Uses the extendable-output variant of the configured algorithm (see
+    /// [`Algorithm::xof`]), so any length can be requested, up to the limit
+    /// documented on [`XofAlgorithm::compute`].
+    ///
+    /// # Example
+    /// ```
+    /// use passcode::{Passcode, Algorithm};
+    ///
+    /// let passcode = Passcode::new(Algorithm::Sha3Kmac256, vec![0u8; 32]);
+    /// assert_eq!(passcode.compute_raw(b"challenge", 100).len(), 100);
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if the [`MacBackend`] fails.
+    pub fn compute_raw(&self, data: &[u8], out_len: usize) -> Vec<u8> {
+        match &self.backend {
+            Some(backend) => counter_mode_expand(
+                |block| backend.mac(block),
+                PASSCODE_CUSTOMIZATION,
+                data,
+                out_len,
+            )
+            .expect("MAC backend failed"),
+            None => self.algorithm.xof().compute(&self.key, data, out_len),
+        }
+    }
+
+    /// Issues a fresh random challenge to compute an OTP over
+    ///
+    /// The challenge holds [`DEFAULT_CHALLENGE_LEN`] bytes from the
+    /// operating system's CSPRNG and is unbound, so its
+    /// [`message`](Challenge::message) is just those bytes. Nothing is
+    /// stored: the caller must remember the challenge, reject it after
+    /// [`expires_at`](Challenge::expires_at) and accept it only once. A
+    /// [`ChallengeManager`](crate::challenge::ChallengeManager) does all of
+    /// that.
+    ///
+    /// # Panics
+    /// Panics if the operating system's CSPRNG fails.
+    ///
+    /// # Example
+    /// ```
+    /// use passcode::{Passcode, Algorithm};
+    ///
+    /// let passcode = Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+    /// let challenge = passcode.issue_challenge();
+    /// let otp = passcode.compute(&challenge.message());
+    /// assert!(passcode.verify(&challenge.message(), &otp));
+    /// ```
+    pub fn issue_challenge(&self) -> Challenge {
+        Challenge::generate(
+            &SharedRng::os(),
+            DEFAULT_CHALLENGE_LEN,
+            SystemTime::now() + DEFAULT_TTL,
+            ChallengeBinding::default(),
+            0,
+        )
+        .expect("operating system CSPRNG failed")
+    }
+
+    /// Derives a session key of `len` bytes bound to the challenge
+    ///
+    /// The key is domain-separated from the OTP (using the
+    /// [`labels::SESSION`] label), so revealing the OTP does not reveal
+    /// anything about the session key.
+    pub fn derive_session_key(&self, challenge: &[u8], len: usize) -> Vec<u8> {
+        self.derive_labeled(labels::SESSION, challenge, len)
+    }
+
+    /// Derives the key of one of the account's devices
+    ///
+    /// The subkey has the length of this key and is domain-separated with
+    /// the [`labels::DEVICE`] label, so each device gets an unrelated key
+    /// that can be revoked on its own. Hand it to the device, e.g. through an
+    /// `otpauth-cr://` URI.
+    pub fn derive_device_key(&self, device_id: &str) -> Vec<u8> {
+        let len = match self.backend {
+            Some(_) => self.algorithm.recommended_key_len(),
+            None => self.key.len(),
+        };
+        self.derive_labeled(labels::DEVICE, device_id.as_bytes(), len)
+    }
+
+    /// Builds the passcode a device computes its OTPs with
+    ///
+    /// Uses the key from [`derive_device_key`](Self::derive_device_key) and
+    /// this passcode's algorithm, format, grouping and canonicalization.
+    pub fn for_device(&self, device_id: &str) -> Passcode {
+        self.with_key(self.derive_device_key(device_id))
+    }
 
-        // Ensure we have at least 6 bytes
-        if hashed.len() < 6 {
-            hashed.resize(6, 0);
+    /// Builds a passcode with `key` and this passcode's algorithm, format,
+    /// grouping and canonicalization
+    pub(crate) fn with_key(&self, key: Vec<u8>) -> Passcode {
+        Passcode {
+            algorithm: self.algorithm,
+            key: SecretBytes::new(key),
+            hasher: self.hasher,
+            backend: None,
+            format: self.format.clone(),
+            grouping: self.grouping,
+            canonicalization: self.canonicalization,
+            policy: self.policy,
         }
+    }
+
+    /// Checks the configuration against the policy it was built under, or
+    /// the process-wide default for passcodes from [`new`](Self::new)
+    ///
+    /// Verifiers call this before every verification, so keys that were
+    /// never checked at construction are rejected under
+    /// [`PolicyMode::Strict`] too.
+    pub(crate) fn check_policy(&self) -> Result<(), Error> {
+        let policy = self.policy.unwrap_or_else(default_policy);
+        if self.backend.is_some() {
+            if !policy.is_approved(self.algorithm) {
+                return Err(Error::AlgorithmNotApproved(self.algorithm.as_str()));
+            }
+            return Ok(());
+        }
+        policy.check(self.algorithm, self.key.len())
+    }
 
-        // Convert first 6 bytes to hex string
-        hex::encode(&hashed[..6])
+    /// Derives `len` bytes from the key, domain-separated by `label`
+    ///
+    /// A [`MacBackend`] derives through the keyed hash; this panics if it
+    /// fails.
+    pub(crate) fn derive_labeled(&self, label: &str, context: &[u8], len: usize) -> Vec<u8> {
+        if let Some(backend) = &self.backend {
+            return counter_mode_expand(|block| backend.mac(block), label.as_bytes(), context, len)
+                .expect("MAC backend failed");
+        }
+        match self.algorithm.xof() {
+            XofAlgorithm::Sha3Kmac128 => sha3_kmac128(&self.key, label.as_bytes(), context, len),
+            XofAlgorithm::Sha3Kmac256 => derive_subkey(&self.key, label, context, len),
+            XofAlgorithm::Blake3Keyed => derive_subkey_blake3(&self.key, label, context, len),
+            #[cfg(feature = "streebog")]
+            XofAlgorithm::HmacStreebog256 => {
+                hmac_streebog256_expand(&self.key, label.as_bytes(), context, len)
+                    .expect("derived keys are below 512 MiB")
+            }
+            XofAlgorithm::HmacSha256 => counter_mode_expand(
+                |block| Ok(hmac_sha256_for_passcode(&self.key, block)),
+                label.as_bytes(),
+                context,
+                len,
+            )
+            .expect("derived keys are below 512 MiB"),
+            XofAlgorithm::HmacSha1 => counter_mode_expand(
+                |block| Ok(hmac_sha1_for_passcode(&self.key, block)),
+                label.as_bytes(),
+                context,
+                len,
+            )
+            .expect("derived keys are below 512 MiB"),
+        }
     }
 
     /// Gets the algorithm being used
@@ -106,15 +955,75 @@ impl Passcode {
         self.algorithm
     }
 
+    /// Gets the secret key
+    ///
+    /// # Panics
+    /// Panics if the key is held by a [`MacBackend`].
+    pub(crate) fn key(&self) -> &[u8] {
+        assert!(
+            self.backend.is_none(),
+            "the key of a passcode with a MAC backend cannot be read"
+        );
+        &self.key
+    }
+
+    /// Returns true if the MACs are computed by a [`MacBackend`]
+    pub fn has_backend(&self) -> bool {
+        self.backend.is_some()
+    }
+
+    /// Returns true if the key is held in memory locked into RAM (feature
+    /// `locked-memory`)
+    ///
+    /// False when the process has reached its locked-memory limit; the key
+    /// is then kept on unlocked pages of its own and still scrubbed on drop.
+    #[cfg(feature = "locked-memory")]
+    pub fn is_memory_locked(&self) -> bool {
+        self.key.is_locked()
+    }
+
+    /// Gets the fingerprint of the key, see [`key_fingerprint`], or the
+    /// [`MacBackend::key_id`] of a key held by a backend
+    pub fn key_id(&self) -> KeyId {
+        match &self.backend {
+            Some(backend) => backend.key_id(),
+            None => key_fingerprint(&self.key),
+        }
+    }
+
     /// Gets the algorithm name as a string
     pub fn algorithm_name(&self) -> &'static str {
         self.algorithm.as_str()
     }
+
+    /// Gets the output format
+    pub fn format(&self) -> &OtpFormat {
+        &self.format
+    }
+
+    /// Gets the input canonicalization rules
+    pub fn canonicalization(&self) -> Canonicalization {
+        self.canonicalization
+    }
+
+    /// Gets the display grouping, if any
+    pub fn grouping(&self) -> Option<Grouping> {
+        self.grouping
+    }
+}
+
+/// Runs CPU-bound work on Tokio's blocking thread pool
+#[cfg(feature = "tokio")]
+async fn offload<T: Send + 'static>(work: impl FnOnce() -> T + Send + 'static) -> T {
+    tokio::task::spawn_blocking(work)
+        .await
+        .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::format::CheckDigit;
 
     #[test]
     fn test_new_passcode() {
@@ -180,4 +1089,565 @@ mod tests {
             assert_eq!(otp.len(), 12);
         }
     }
+
+    #[test]
+    fn test_512_bit_tier() {
+        let key = vec![1u8; 64];
+        let challenge = vec![2u8; 16];
+
+        let passcode = Passcode::new(Algorithm::Sha3Kmac512, key.clone());
+        let otp = passcode.compute(&challenge);
+        assert_eq!(otp.len(), 32);
+        assert!(otp.chars().all(|c| c.is_ascii_hexdigit()));
+
+        // Output length is bound into KMAC, so this is not a longer SHA3-KMAC-256 OTP
+        let kmac256 = Passcode::new(Algorithm::Sha3Kmac256, key).compute(&challenge);
+        assert_ne!(&otp[..12], kmac256);
+
+        assert_eq!(Algorithm::Sha3Kmac512.recommended_key_len(), 64);
+    }
+
+    #[test]
+    fn test_rejects_weak_keys() {
+        assert_eq!(
+            Passcode::try_new(Algorithm::Sha3Kmac128, vec![1u8; 15]).err(),
+            Some(Error::KeyTooShort {
+                min: 16,
+                actual: 15
+            })
+        );
+        assert!(Passcode::try_new(Algorithm::Sha3Kmac128, vec![1u8; 16]).is_ok());
+        assert_eq!(
+            Passcode::builder(Algorithm::Sha3Kmac512, vec![1u8; 32])
+                .build()
+                .err(),
+            Some(Error::KeyTooShort {
+                min: 64,
+                actual: 32
+            })
+        );
+
+        let weak = Passcode::builder(Algorithm::Blake3KeyedMode256, b"key".to_vec())
+            .allow_weak_key()
+            .build()
+            .unwrap();
+        assert_eq!(
+            weak.compute(b"data"),
+            Passcode::new(Algorithm::Blake3KeyedMode256, b"key".to_vec()).compute(b"data")
+        );
+    }
+
+    /// Backend computing in software, or failing like an unplugged device
+    struct TestBackend {
+        algorithm: Algorithm,
+        key: Option<Vec<u8>>,
+    }
+
+    impl MacBackend for TestBackend {
+        fn algorithm(&self) -> Algorithm {
+            self.algorithm
+        }
+
+        fn key_id(&self) -> KeyId {
+            KeyId::from_bytes([9u8; 8])
+        }
+
+        fn mac(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+            match &self.key {
+                Some(key) => Ok((self.algorithm.hasher())(key, data)),
+                None => Err(Error::Hardware("device unplugged".to_string())),
+            }
+        }
+    }
+
+    #[test]
+    fn test_backend() {
+        let backend = TestBackend {
+            algorithm: Algorithm::HmacSha256,
+            key: Some(vec![5u8; 32]),
+        };
+        let hardware = Passcode::builder_with_backend(backend)
+            .format(OtpFormat::Decimal(8))
+            .build()
+            .unwrap();
+        let software = Passcode::builder(Algorithm::HmacSha256, vec![5u8; 32])
+            .format(OtpFormat::Decimal(8))
+            .build()
+            .unwrap();
+
+        let otp = hardware.compute(b"challenge");
+        assert_eq!(otp, software.compute(b"challenge"));
+        assert!(hardware.verify(b"challenge", &otp));
+        assert_eq!(hardware.compute_u64(b"data"), software.compute_u64(b"data"));
+        assert_eq!(hardware.key_id(), KeyId::from_bytes([9u8; 8]));
+        assert_eq!(
+            hardware.derive_device_key("phone"),
+            software.derive_device_key("phone")
+        );
+    }
+
+    #[test]
+    fn test_backend_failure_rejects() {
+        let passcode = Passcode::from_backend(TestBackend {
+            algorithm: Algorithm::HmacSha256,
+            key: None,
+        })
+        .unwrap();
+        let otp = Passcode::new(Algorithm::HmacSha256, vec![5u8; 32]).compute(b"challenge");
+
+        assert_eq!(
+            passcode.try_compute(b"challenge"),
+            Err(Error::Hardware("device unplugged".to_string()))
+        );
+        assert!(passcode.try_verify(b"challenge", &otp).is_err());
+        assert!(!passcode.verify(b"challenge", &otp));
+        assert!(!passcode.verify_mac(b"challenge", &[0u8; 6]));
+        assert!(!passcode.verify_u64(b"challenge", 0));
+    }
+
+    #[test]
+    fn test_backend_configuration() {
+        // KMAC needs the key itself to derive keys
+        let kmac = TestBackend {
+            algorithm: Algorithm::Sha3Kmac256,
+            key: Some(vec![5u8; 32]),
+        };
+        assert_eq!(
+            Passcode::from_backend(kmac).err(),
+            Some(Error::InvalidFormat(
+                "MAC backends must compute an HMAC algorithm"
+            ))
+        );
+
+        let hmac = TestBackend {
+            algorithm: Algorithm::HmacSha256,
+            key: Some(vec![5u8; 32]),
+        };
+        assert_eq!(
+            Passcode::builder_with_backend(hmac)
+                .policy(PolicyMode::Strict)
+                .build()
+                .err(),
+            Some(Error::AlgorithmNotApproved("HMAC-SHA-256"))
+        );
+    }
+
+    #[test]
+    fn test_check_policy() {
+        let strict = Passcode::try_new_with_policy(
+            Algorithm::Sha3Kmac256,
+            vec![1u8; 32],
+            PolicyMode::Strict,
+        )
+        .unwrap();
+        assert_eq!(strict.check_policy(), Ok(()));
+
+        // As if built without checks under a strict policy
+        let weak = Passcode {
+            policy: Some(PolicyMode::Strict),
+            ..Passcode::new(Algorithm::Blake3KeyedMode256, vec![1u8; 32])
+        };
+        assert_eq!(
+            weak.check_policy(),
+            Err(Error::AlgorithmNotApproved("BLAKE3-Keyed-Mode-256"))
+        );
+        // Device keys keep the policy of their passcode
+        assert_eq!(
+            weak.for_device("phone").check_policy(),
+            Err(Error::AlgorithmNotApproved("BLAKE3-Keyed-Mode-256"))
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot be read")]
+    fn test_backend_key_cannot_be_read() {
+        let passcode = Passcode::from_backend(TestBackend {
+            algorithm: Algorithm::HmacSha256,
+            key: Some(vec![5u8; 32]),
+        })
+        .unwrap();
+        passcode.key();
+    }
+
+    #[test]
+    fn test_key_id() {
+        let key = vec![3u8; 32];
+        let ids: Vec<_> = Algorithm::all()
+            .map(|algorithm| Passcode::new(algorithm, key.clone()).key_id())
+            .collect();
+        assert!(ids.iter().all(|id| *id == key_fingerprint(&key)));
+        assert_ne!(
+            Passcode::new(Algorithm::Sha3Kmac256, vec![4u8; 32]).key_id(),
+            ids[0]
+        );
+    }
+
+    #[test]
+    fn test_generate_key() {
+        for algorithm in Algorithm::all() {
+            let key = algorithm.generate_key(&mut crate::rng::OsRng);
+            assert_eq!(key.len(), algorithm.recommended_key_len());
+            assert_ne!(key, algorithm.random_key());
+            assert_ne!(key, vec![0u8; key.len()]);
+        }
+    }
+
+    #[cfg(feature = "streebog")]
+    #[test]
+    fn test_streebog_cross_language_vector() {
+        let key =
+            hex::decode("0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef")
+                .unwrap();
+        let challenge = hex::decode("fedcba9876543210fedcba9876543210").unwrap();
+
+        let passcode = Passcode::new(Algorithm::HmacStreebog256, key);
+        assert_eq!(passcode.compute(&challenge), "56a4af372e36");
+        assert_eq!(Algorithm::from_id(5), Some(Algorithm::HmacStreebog256));
+    }
+
+    #[test]
+    fn test_decimal_format() {
+        let key = vec![1u8; 32];
+        let challenge = vec![2u8; 16];
+
+        for digits in [6, 8] {
+            let passcode = Passcode::builder(Algorithm::Sha3Kmac256, key.clone())
+                .format(OtpFormat::Decimal(digits))
+                .build()
+                .unwrap();
+            let otp = passcode.compute(&challenge);
+
+            assert_eq!(otp.len(), digits as usize);
+            assert!(otp.chars().all(|c| c.is_ascii_digit()));
+            assert!(passcode.verify(&challenge, &otp));
+            assert!(passcode.verify(&challenge, &format!(" {} ", otp)));
+        }
+
+        let invalid = Passcode::builder(Algorithm::Sha3Kmac256, key)
+            .format(OtpFormat::Decimal(4))
+            .build();
+        assert_eq!(invalid.err(), Some(Error::InvalidDigits(4)));
+    }
+
+    #[test]
+    fn test_base32_format() {
+        let challenge = vec![2u8; 16];
+        let passcode = Passcode::builder(Algorithm::Sha3Kmac256, vec![1u8; 32])
+            .format(OtpFormat::Base32)
+            .build()
+            .unwrap();
+        let otp = passcode.compute(&challenge);
+
+        assert_eq!(otp.len(), 10);
+        assert!(otp.bytes().all(|c| BASE32_CHARS.contains(&c)));
+        assert!(passcode.verify(&challenge, &otp));
+        assert!(passcode.verify(&challenge, &otp.to_ascii_lowercase()));
+
+        let wide = Passcode::builder(Algorithm::Sha3Kmac512, vec![1u8; 64])
+            .format(OtpFormat::Base32)
+            .build()
+            .unwrap();
+        assert_eq!(wide.compute(&challenge).len(), 26);
+    }
+
+    #[test]
+    fn test_base58_format() {
+        let challenge = vec![2u8; 16];
+        let passcode = Passcode::builder(Algorithm::Blake3KeyedMode256, vec![1u8; 32])
+            .format(OtpFormat::Base58)
+            .build()
+            .unwrap();
+        let otp = passcode.compute(&challenge);
+
+        assert!(!otp.is_empty() && otp.len() <= 9);
+        assert!(!otp.contains(['0', 'O', 'I', 'l']));
+        assert!(passcode.verify(&challenge, &otp));
+        assert!(!passcode.verify(&challenge, &format!("1{}", otp)));
+    }
+
+    #[test]
+    fn test_crockford_format() {
+        let challenge = vec![2u8; 16];
+        let passcode = Passcode::builder(Algorithm::Sha3Kmac128, vec![1u8; 16])
+            .format(OtpFormat::Crockford { check: true })
+            .build()
+            .unwrap();
+        let otp = passcode.compute(&challenge);
+
+        assert_eq!(otp.len(), 11);
+        assert!(!otp[..10].contains(['I', 'L', 'O', 'U']));
+        assert!(passcode.verify(&challenge, &otp.to_ascii_lowercase()));
+        assert!(passcode.verify(&challenge, &format!("{}-{}", &otp[..5], &otp[5..])));
+    }
+
+    #[test]
+    fn test_words_format() {
+        let challenge = vec![2u8; 16];
+        let passcode = Passcode::builder(Algorithm::Sha3Kmac256, vec![1u8; 32])
+            .format(OtpFormat::Words(3))
+            .build()
+            .unwrap();
+        let otp = passcode.compute(&challenge);
+
+        assert_eq!(otp.split('-').count(), 3);
+        assert!(passcode.verify(&challenge, &otp.replace('-', " ").to_uppercase()));
+
+        let invalid = Passcode::builder(Algorithm::Sha3Kmac256, vec![1u8; 32])
+            .format(OtpFormat::Words(12))
+            .build();
+        assert!(matches!(invalid, Err(Error::InvalidFormat(_))));
+    }
+
+    #[test]
+    fn test_grouped_output() {
+        let challenge = vec![2u8; 16];
+        let passcode = Passcode::builder(Algorithm::Sha3Kmac256, vec![1u8; 32])
+            .grouping(Grouping::new(3, '-'))
+            .build()
+            .unwrap();
+        let otp = passcode.compute(&challenge);
+
+        assert_eq!(otp.len(), 15);
+        assert_eq!(otp.matches('-').count(), 3);
+        assert!(passcode.verify(&challenge, &otp));
+        assert!(passcode.verify(&challenge, &otp.replace('-', "")));
+        assert!(passcode.verify(&challenge, &otp.replace('-', " ")));
+
+        let decimal = Passcode::builder(Algorithm::Sha3Kmac256, vec![1u8; 32])
+            .format(OtpFormat::Decimal(6))
+            .grouping(Grouping::new(3, ' '))
+            .build()
+            .unwrap();
+        let otp = decimal.compute(&challenge);
+        assert_eq!(otp.len(), 7);
+        assert_eq!(&otp[3..4], " ");
+        assert!(decimal.verify(&challenge, &otp.replace(' ', "")));
+    }
+
+    #[test]
+    fn test_checked_decimal_format() {
+        let challenge = vec![2u8; 16];
+        let passcode = Passcode::builder(Algorithm::Sha3Kmac256, vec![1u8; 32])
+            .format(OtpFormat::CheckedDecimal {
+                digits: 6,
+                check: CheckDigit::Luhn,
+            })
+            .build()
+            .unwrap();
+        let otp = passcode.compute(&challenge);
+
+        assert_eq!(otp.len(), 7);
+        assert!(CheckDigit::Luhn.is_valid(&otp));
+        assert!(passcode.verify(&challenge, &otp));
+
+        // The code digits are the same as the plain decimal format
+        let plain = Passcode::builder(Algorithm::Sha3Kmac256, vec![1u8; 32])
+            .format(OtpFormat::Decimal(6))
+            .build()
+            .unwrap();
+        assert_eq!(&otp[..6], plain.compute(&challenge));
+    }
+
+    #[test]
+    fn test_verify_canonicalizes_pasted_input() {
+        let challenge = vec![2u8; 16];
+        let passcode = Passcode::builder(Algorithm::Sha3Kmac256, vec![1u8; 32])
+            .format(OtpFormat::Decimal(6))
+            .build()
+            .unwrap();
+        let otp = passcode.compute(&challenge);
+
+        let fullwidth: String = otp
+            .chars()
+            .map(|c| char::from_u32(c as u32 + 0xFEE0).unwrap())
+            .collect();
+        let pasted = format!("\u{200B}{}\u{00A0}{}\n", &fullwidth[..9], &otp[3..]);
+        assert!(passcode.verify(&challenge, &pasted));
+
+        let strict = Passcode::builder(Algorithm::Sha3Kmac256, vec![1u8; 32])
+            .format(OtpFormat::Decimal(6))
+            .canonicalization(Canonicalization::NONE)
+            .build()
+            .unwrap();
+        assert!(strict.verify(&challenge, &otp));
+        assert!(!strict.verify(&challenge, &format!(" {}", otp)));
+        assert!(!strict.verify(&challenge, &pasted));
+    }
+
+    #[test]
+    fn test_hex_verification_ignores_case() {
+        let challenge = vec![2u8; 16];
+        let lower = Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+        let upper = Passcode::builder(Algorithm::Sha3Kmac256, vec![1u8; 32])
+            .format(OtpFormat::HexUpper)
+            .build()
+            .unwrap();
+
+        let otp = upper.compute(&challenge);
+        assert_eq!(otp, lower.compute(&challenge).to_uppercase());
+        assert!(lower.verify(&challenge, &otp));
+        assert!(upper.verify(&challenge, &otp.to_lowercase()));
+    }
+
+    #[test]
+    fn test_compute_u64() {
+        let challenge = vec![2u8; 16];
+
+        for algorithm in [Algorithm::Sha3Kmac128, Algorithm::Blake3KeyedMode256] {
+            let passcode = Passcode::new(algorithm, vec![1u8; 32]);
+            let value = passcode.compute_u64(&challenge);
+
+            assert!(value < 1 << 48);
+            assert_eq!(format!("{:012x}", value), passcode.compute(&challenge));
+            assert!(passcode.verify_u64(&challenge, value));
+            assert!(!passcode.verify_u64(&challenge, value ^ 1));
+        }
+
+        let wide = Passcode::new(Algorithm::Sha3Kmac512, vec![1u8; 64]);
+        let value = wide.compute_u64(&challenge);
+        assert_eq!(format!("{:016x}", value), wide.compute(&challenge)[..16]);
+    }
+
+    const BASE32_CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+    #[test]
+    fn test_verify_rejects_wrong_otp() {
+        let passcode = Passcode::new(Algorithm::Blake3KeyedMode256, vec![1u8; 32]);
+        let challenge = vec![2u8; 16];
+
+        assert!(passcode.verify(&challenge, &passcode.compute(&challenge)));
+        assert!(!passcode.verify(&challenge, "000000000000"));
+        assert!(!passcode.verify(&challenge, ""));
+    }
+
+    #[test]
+    fn test_algorithm_ids_round_trip() {
+        let mut variants = vec![
+            Algorithm::Sha3Kmac128,
+            Algorithm::Sha3Kmac256,
+            Algorithm::Blake3KeyedMode128,
+            Algorithm::Blake3KeyedMode256,
+            Algorithm::Sha3Kmac512,
+        ];
+        #[cfg(feature = "streebog")]
+        variants.push(Algorithm::HmacStreebog256);
+        variants.push(Algorithm::HmacSha256);
+        variants.push(Algorithm::HmacSha1);
+
+        for algorithm in &variants {
+            assert_eq!(Algorithm::from_id(algorithm.id()), Some(*algorithm));
+        }
+        assert_eq!(Algorithm::all().collect::<Vec<_>>(), variants);
+
+        let next = variants.iter().map(Algorithm::id).max().unwrap() + 1;
+        assert_eq!(Algorithm::from_id(next), None);
+    }
+
+    #[test]
+    fn test_compute_raw_lengths() {
+        let key = vec![1u8; 32];
+        let challenge = vec![2u8; 16];
+
+        for algo in [Algorithm::Sha3Kmac256, Algorithm::Blake3KeyedMode256] {
+            let passcode = Passcode::new(algo, key.clone());
+            assert_eq!(passcode.compute_raw(&challenge, 7).len(), 7);
+            assert_eq!(passcode.compute_raw(&challenge, 200).len(), 200);
+        }
+
+        // BLAKE3 output is a stream, so the OTP is a prefix of the raw output
+        let passcode = Passcode::new(Algorithm::Blake3KeyedMode256, key);
+        let raw = passcode.compute_raw(&challenge, 6);
+        assert_eq!(hex::encode(raw), passcode.compute(&challenge));
+    }
+
+    #[test]
+    fn test_session_key_separated_from_otp() {
+        let passcode = Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+        let challenge = vec![2u8; 16];
+
+        let session = passcode.derive_session_key(&challenge, 32);
+        assert_eq!(session.len(), 32);
+        assert_ne!(session, passcode.compute_raw(&challenge, 32));
+        assert_eq!(session, passcode.derive_session_key(&challenge, 32));
+    }
+
+    #[test]
+    fn test_issue_challenge() {
+        let passcode = Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+        let first = passcode.issue_challenge();
+        let second = passcode.issue_challenge();
+
+        assert_eq!(first.bytes().len(), DEFAULT_CHALLENGE_LEN);
+        assert_eq!(first.message(), first.bytes());
+        assert_ne!(first.bytes(), second.bytes());
+        assert_ne!(first.id(), second.id());
+        assert!(first.expires_at() > SystemTime::now());
+    }
+
+    #[test]
+    fn test_device_keys() {
+        let account = Passcode::builder(Algorithm::Blake3KeyedMode256, vec![1u8; 32])
+            .format(OtpFormat::Decimal(8))
+            .build()
+            .unwrap();
+        let phone = account.for_device("phone");
+        let laptop = account.for_device("laptop");
+
+        assert_eq!(account.derive_device_key("phone").len(), 32);
+        assert_ne!(phone.compute(b"data"), laptop.compute(b"data"));
+        assert_ne!(phone.compute(b"data"), account.compute(b"data"));
+        assert_eq!(phone.compute(b"data").len(), 8);
+
+        let imported = Passcode::builder(
+            Algorithm::Blake3KeyedMode256,
+            account.derive_device_key("phone"),
+        )
+        .format(OtpFormat::Decimal(8))
+        .build()
+        .unwrap();
+        assert_eq!(imported.compute(b"data"), phone.compute(b"data"));
+    }
+
+    #[cfg(feature = "argon2")]
+    #[test]
+    fn test_from_password() {
+        let params = crate::Argon2Params {
+            memory_kib: 1024,
+            iterations: 1,
+            parallelism: 1,
+            key_len: 32,
+        };
+        let challenge = vec![2u8; 16];
+
+        let passcode1 =
+            Passcode::from_password(Algorithm::Sha3Kmac256, b"password", b"saltsalt", params)
+                .unwrap();
+        let passcode2 =
+            Passcode::from_password(Algorithm::Sha3Kmac256, b"password", b"saltsalt", params)
+                .unwrap();
+        let passcode3 =
+            Passcode::from_password(Algorithm::Sha3Kmac256, b"password", b"pepper!!", params)
+                .unwrap();
+
+        assert_eq!(passcode1.compute(&challenge), passcode2.compute(&challenge));
+        assert_ne!(passcode1.compute(&challenge), passcode3.compute(&challenge));
+        assert!(Passcode::from_password(Algorithm::Sha3Kmac256, b"password", b"short", params)
+            .is_err());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_offloaded() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let passcode = Arc::new(Passcode::new(Algorithm::Sha3Kmac256, vec![0u8; 32]));
+        let data = vec![7u8; 1 << 20];
+        let otp = passcode.compute(&data);
+
+        runtime.block_on(async {
+            assert_eq!(Arc::clone(&passcode).compute_offloaded(data.clone()).await, otp);
+            assert!(Arc::clone(&passcode).verify_offloaded(data, otp).await);
+        });
+    }
 }
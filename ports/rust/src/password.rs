@@ -0,0 +1,221 @@
+//! Password-based derivation of the shared secret key
+//!
+//! All supported KDFs implement [`KeyDerivation`], so callers can choose the
+//! function by policy and hand it to [`Passcode::from_password`](crate::Passcode::from_password).
+
+#[cfg(feature = "argon2")]
+use argon2::{Argon2, Params, Version};
+
+#[cfg(feature = "pbkdf2")]
+use crate::hotp::HmacAlgorithm;
+use crate::Error;
+
+/// Default length in bytes of keys derived from passwords
+pub const DERIVED_KEY_LEN: usize = 32;
+
+/// A password-based key derivation function with fixed parameters
+pub trait KeyDerivation {
+    /// Derives the shared secret key from a password and salt
+    fn derive_key(&self, password: &[u8], salt: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+impl<K: KeyDerivation + ?Sized> KeyDerivation for &K {
+    fn derive_key(&self, password: &[u8], salt: &[u8]) -> Result<Vec<u8>, Error> {
+        (**self).derive_key(password, salt)
+    }
+}
+
+impl<K: KeyDerivation + ?Sized> KeyDerivation for Box<K> {
+    fn derive_key(&self, password: &[u8], salt: &[u8]) -> Result<Vec<u8>, Error> {
+        (**self).derive_key(password, salt)
+    }
+}
+
+/// Argon2id cost parameters
+///
+/// The defaults follow the OWASP recommendation (19 MiB, 2 iterations,
+/// 1 lane). Both sides of the exchange must use identical parameters and salt,
+/// and the salt must be at least 8 bytes.
+#[cfg(feature = "argon2")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Argon2Params {
+    /// Memory cost in KiB
+    pub memory_kib: u32,
+    /// Number of iterations
+    pub iterations: u32,
+    /// Degree of parallelism
+    pub parallelism: u32,
+    /// Length of the derived key in bytes
+    pub key_len: usize,
+}
+
+#[cfg(feature = "argon2")]
+impl Default for Argon2Params {
+    fn default() -> Self {
+        Self {
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+            key_len: DERIVED_KEY_LEN,
+        }
+    }
+}
+
+#[cfg(feature = "argon2")]
+impl KeyDerivation for Argon2Params {
+    fn derive_key(&self, password: &[u8], salt: &[u8]) -> Result<Vec<u8>, Error> {
+        let params = Params::new(
+            self.memory_kib,
+            self.iterations,
+            self.parallelism,
+            Some(self.key_len),
+        )
+        .map_err(|e| Error::KeyDerivation(e.to_string()))?;
+        let argon2 = Argon2::new(argon2::Algorithm::Argon2id, Version::V0x13, params);
+
+        let mut key = vec![0u8; self.key_len];
+        argon2
+            .hash_password_into(password, salt, &mut key)
+            .map_err(|e| Error::KeyDerivation(e.to_string()))?;
+        Ok(key)
+    }
+}
+
+/// PBKDF2 parameters for keys provisioned by legacy enrollment systems
+///
+/// The output length is configurable so keys from systems that emit e.g.
+/// 20-byte PBKDF2-HMAC-SHA1 secrets can be reproduced exactly.
+#[cfg(feature = "pbkdf2")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pbkdf2Params {
+    /// HMAC hash function used as the PRF
+    pub prf: HmacAlgorithm,
+    /// Number of iterations
+    pub iterations: u32,
+    /// Length of the derived key in bytes
+    pub key_len: usize,
+}
+
+#[cfg(feature = "pbkdf2")]
+impl Default for Pbkdf2Params {
+    fn default() -> Self {
+        Self {
+            prf: HmacAlgorithm::Sha256,
+            iterations: 600_000,
+            key_len: DERIVED_KEY_LEN,
+        }
+    }
+}
+
+#[cfg(feature = "pbkdf2")]
+impl KeyDerivation for Pbkdf2Params {
+    fn derive_key(&self, password: &[u8], salt: &[u8]) -> Result<Vec<u8>, Error> {
+        if self.iterations == 0 {
+            return Err(Error::KeyDerivation(
+                "PBKDF2 iterations must be greater than zero".to_string(),
+            ));
+        }
+
+        let mut key = vec![0u8; self.key_len];
+        match self.prf {
+            HmacAlgorithm::Sha1 => {
+                pbkdf2::pbkdf2_hmac::<sha1::Sha1>(password, salt, self.iterations, &mut key)
+            }
+            HmacAlgorithm::Sha256 => {
+                pbkdf2::pbkdf2_hmac::<sha2::Sha256>(password, salt, self.iterations, &mut key)
+            }
+            HmacAlgorithm::Sha512 => {
+                pbkdf2::pbkdf2_hmac::<sha2::Sha512>(password, salt, self.iterations, &mut key)
+            }
+        }
+        Ok(key)
+    }
+}
+
+/// scrypt cost parameters
+///
+/// The defaults follow the OWASP recommendation (N = 2^17, r = 8, p = 1).
+#[cfg(feature = "scrypt")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScryptParams {
+    /// Base-2 logarithm of the CPU/memory cost `N`
+    pub log_n: u8,
+    /// Block size
+    pub r: u32,
+    /// Parallelization
+    pub p: u32,
+    /// Length of the derived key in bytes
+    pub key_len: usize,
+}
+
+#[cfg(feature = "scrypt")]
+impl Default for ScryptParams {
+    fn default() -> Self {
+        Self {
+            log_n: 17,
+            r: 8,
+            p: 1,
+            key_len: DERIVED_KEY_LEN,
+        }
+    }
+}
+
+#[cfg(feature = "scrypt")]
+impl KeyDerivation for ScryptParams {
+    fn derive_key(&self, password: &[u8], salt: &[u8]) -> Result<Vec<u8>, Error> {
+        let params = scrypt::Params::new(self.log_n, self.r, self.p, self.key_len)
+            .map_err(|e| Error::KeyDerivation(e.to_string()))?;
+
+        let mut key = vec![0u8; self.key_len];
+        scrypt::scrypt(password, salt, &params, &mut key)
+            .map_err(|e| Error::KeyDerivation(e.to_string()))?;
+        Ok(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "pbkdf2")]
+    #[test]
+    fn test_pbkdf2_rfc6070_vector() {
+        let params = Pbkdf2Params {
+            prf: HmacAlgorithm::Sha1,
+            iterations: 4096,
+            key_len: 20,
+        };
+        let key = params.derive_key(b"password", b"salt").unwrap();
+        assert_eq!(hex::encode(key), "4b007901b765489abead49d926f721d065a429c1");
+    }
+
+    #[cfg(feature = "scrypt")]
+    #[test]
+    fn test_scrypt_rfc7914_vector() {
+        let params = ScryptParams {
+            log_n: 4,
+            r: 1,
+            p: 1,
+            key_len: 64,
+        };
+        let key = params.derive_key(b"", b"").unwrap();
+        assert_eq!(
+            hex::encode(key),
+            "77d6576238657b203b19ca42c18a0497f16b4844e3074ae8dfdffa3fede21442\
+             fcd0069ded0948f8326a753a0fc81f17e8d3e0fb2e0d3628cf35e20c38d18906"
+        );
+    }
+
+    #[cfg(feature = "argon2")]
+    #[test]
+    fn test_dyn_key_derivation() {
+        let kdf: Box<dyn KeyDerivation> = Box::new(Argon2Params {
+            memory_kib: 1024,
+            iterations: 1,
+            parallelism: 1,
+            key_len: 32,
+        });
+        let key = kdf.derive_key(b"password", b"saltsalt").unwrap();
+        assert_eq!(key.len(), 32);
+    }
+}
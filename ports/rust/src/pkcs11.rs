@@ -0,0 +1,587 @@
+//! PKCS#11 keys (feature `pkcs11`)
+//!
+//! [`Pkcs11Key`] is a [`MacBackend`] computing HMAC-SHA-256
+//! (`CKM_SHA256_HMAC`) with a secret key on a token, such as an HSM or a
+//! smart card, through the vendor's PKCS#11 module loaded at runtime.
+//!
+//! The server needs a copy of the key to verify, so import a generated key
+//! as a `CKK_GENERIC_SECRET` object allowed to sign, e.g. with OpenSC's
+//! `pkcs11-tool --write-object key.bin --type secrkey --key-type GENERIC:32
+//! --label otp-key --usage-sign`.
+//!
+//! ```no_run
+//! use passcode::pkcs11::Pkcs11Key;
+//! use passcode::Passcode;
+//!
+//! let key = Pkcs11Key::open("/usr/lib/softhsm/libsofthsm2.so", 0, b"1234", "otp-key").unwrap();
+//! let passcode = Passcode::from_backend(key).unwrap();
+//! let otp = passcode.try_compute(b"challenge").unwrap();
+//! ```
+
+use std::ffi::{c_void, OsStr};
+use std::fmt;
+use std::os::raw::c_ulong;
+use std::ptr;
+use std::sync::Mutex;
+
+use libloading::Library;
+
+use crate::kdf::{key_fingerprint, labels, KeyId};
+use crate::{Algorithm, Error, MacBackend};
+
+type CkUlong = c_ulong;
+type CkRv = CkUlong;
+
+const CKR_OK: CkRv = 0x000;
+const CKR_USER_ALREADY_LOGGED_IN: CkRv = 0x100;
+const CKR_CRYPTOKI_ALREADY_INITIALIZED: CkRv = 0x191;
+const CKF_OS_LOCKING_OK: CkUlong = 0x2;
+const CKF_SERIAL_SESSION: CkUlong = 0x4;
+const CKU_USER: CkUlong = 1;
+const CKA_CLASS: CkUlong = 0x000;
+const CKA_LABEL: CkUlong = 0x003;
+const CKO_SECRET_KEY: CkUlong = 0x4;
+const CKM_SHA256_HMAC: CkUlong = 0x251;
+/// Output length of HMAC-SHA-256
+const MAC_LEN: usize = 32;
+
+#[cfg_attr(windows, repr(C, packed))]
+#[cfg_attr(not(windows), repr(C))]
+struct CkVersion {
+    major: u8,
+    minor: u8,
+}
+
+#[cfg_attr(windows, repr(C, packed))]
+#[cfg_attr(not(windows), repr(C))]
+struct CkMechanism {
+    mechanism: CkUlong,
+    parameter: *mut c_void,
+    parameter_len: CkUlong,
+}
+
+#[cfg_attr(windows, repr(C, packed))]
+#[cfg_attr(not(windows), repr(C))]
+struct CkAttribute {
+    kind: CkUlong,
+    value: *mut c_void,
+    value_len: CkUlong,
+}
+
+#[cfg_attr(windows, repr(C, packed))]
+#[cfg_attr(not(windows), repr(C))]
+struct CkInitializeArgs {
+    mutex_callbacks: [*mut c_void; 4],
+    flags: CkUlong,
+    reserved: *mut c_void,
+}
+
+/// Slot of a function this backend never calls
+type Unused = Option<unsafe extern "C" fn()>;
+
+/// The leading entries of `CK_FUNCTION_LIST`, in the order of PKCS#11 v2
+#[cfg_attr(windows, repr(C, packed))]
+#[cfg_attr(not(windows), repr(C))]
+struct FunctionList {
+    version: CkVersion,
+    initialize: unsafe extern "C" fn(*mut c_void) -> CkRv,
+    finalize: Unused,
+    get_info: Unused,
+    get_function_list: Unused,
+    get_slot_list: Unused,
+    get_slot_info: Unused,
+    get_token_info: Unused,
+    get_mechanism_list: Unused,
+    get_mechanism_info: Unused,
+    init_token: Unused,
+    init_pin: Unused,
+    set_pin: Unused,
+    open_session: unsafe extern "C" fn(CkUlong, CkUlong, *mut c_void, Unused, *mut CkUlong) -> CkRv,
+    close_session: unsafe extern "C" fn(CkUlong) -> CkRv,
+    close_all_sessions: Unused,
+    get_session_info: Unused,
+    get_operation_state: Unused,
+    set_operation_state: Unused,
+    login: unsafe extern "C" fn(CkUlong, CkUlong, *const u8, CkUlong) -> CkRv,
+    logout: Unused,
+    create_object: Unused,
+    copy_object: Unused,
+    destroy_object: Unused,
+    get_object_size: Unused,
+    get_attribute_value: Unused,
+    set_attribute_value: Unused,
+    find_objects_init: unsafe extern "C" fn(CkUlong, *mut CkAttribute, CkUlong) -> CkRv,
+    find_objects: unsafe extern "C" fn(CkUlong, *mut CkUlong, CkUlong, *mut CkUlong) -> CkRv,
+    find_objects_final: unsafe extern "C" fn(CkUlong) -> CkRv,
+    encrypt_init: Unused,
+    encrypt: Unused,
+    encrypt_update: Unused,
+    encrypt_final: Unused,
+    decrypt_init: Unused,
+    decrypt: Unused,
+    decrypt_update: Unused,
+    decrypt_final: Unused,
+    digest_init: Unused,
+    digest: Unused,
+    digest_update: Unused,
+    digest_key: Unused,
+    digest_final: Unused,
+    sign_init: unsafe extern "C" fn(CkUlong, *mut CkMechanism, CkUlong) -> CkRv,
+    sign: unsafe extern "C" fn(CkUlong, *const u8, CkUlong, *mut u8, *mut CkUlong) -> CkRv,
+}
+
+/// Fails with [`Error::Hardware`] naming the call unless it returned `CKR_OK`
+fn check(function: &str, rv: CkRv) -> Result<(), Error> {
+    match rv {
+        CKR_OK => Ok(()),
+        _ => Err(Error::Hardware(format!(
+            "{} failed with CKR {:#x}",
+            function, rv
+        ))),
+    }
+}
+
+/// HMAC-SHA-256 key on a PKCS#11 token
+pub struct Pkcs11Key {
+    functions: &'static FunctionList,
+    // Sessions run one operation at a time
+    session: Mutex<CkUlong>,
+    key: CkUlong,
+    key_id: KeyId,
+    // Declared last so the module is unloaded after the session is closed
+    _library: Option<Library>,
+}
+
+// The module is initialized with CKF_OS_LOCKING_OK, and the session is only
+// used under its lock
+unsafe impl Send for Pkcs11Key {}
+unsafe impl Sync for Pkcs11Key {}
+
+impl Pkcs11Key {
+    /// Loads the PKCS#11 `module`, logs in to the token in `slot` with
+    /// `pin` and looks up the secret key labelled `label`
+    ///
+    /// Fails with [`Error::Hardware`] if the module cannot be loaded, a call
+    /// fails, or not exactly one secret key has the label.
+    pub fn open(
+        module: impl AsRef<OsStr>,
+        slot: u64,
+        pin: &[u8],
+        label: &str,
+    ) -> Result<Self, Error> {
+        let module = module.as_ref();
+        // Loading runs the module's initializers, which is what PKCS#11
+        // modules are for
+        let library = unsafe { Library::new(module) }.map_err(|e| {
+            Error::Hardware(format!("cannot load {}: {}", module.to_string_lossy(), e))
+        })?;
+        let functions = unsafe {
+            let get_function_list = library
+                .get::<unsafe extern "C" fn(*mut *const FunctionList) -> CkRv>(
+                    b"C_GetFunctionList\0",
+                )
+                .map_err(|e| Error::Hardware(format!("not a PKCS#11 module: {}", e)))?;
+            let mut functions = ptr::null();
+            check("C_GetFunctionList", get_function_list(&mut functions))?;
+            // The list is static data of the module, which lives as long as
+            // the key holds the library
+            functions.as_ref()
+        }
+        .ok_or_else(|| Error::Hardware("C_GetFunctionList returned no list".to_string()))?;
+
+        Self::with_functions(functions, Some(library), slot, pin, label)
+    }
+
+    fn with_functions(
+        functions: &'static FunctionList,
+        library: Option<Library>,
+        slot: u64,
+        pin: &[u8],
+        label: &str,
+    ) -> Result<Self, Error> {
+        let slot =
+            CkUlong::try_from(slot).map_err(|_| Error::Hardware(format!("no slot {}", slot)))?;
+        let mut args = CkInitializeArgs {
+            mutex_callbacks: [ptr::null_mut(); 4],
+            flags: CKF_OS_LOCKING_OK,
+            reserved: ptr::null_mut(),
+        };
+        // Another key may have initialized the module already
+        match unsafe { (functions.initialize)(&mut args as *mut _ as *mut c_void) } {
+            CKR_CRYPTOKI_ALREADY_INITIALIZED => {}
+            rv => check("C_Initialize", rv)?,
+        }
+
+        let mut session = 0;
+        check("C_OpenSession", unsafe {
+            (functions.open_session)(
+                slot,
+                CKF_SERIAL_SESSION,
+                ptr::null_mut(),
+                None,
+                &mut session,
+            )
+        })?;
+        let mut key = Self {
+            functions,
+            session: Mutex::new(session),
+            key: 0,
+            key_id: KeyId::from_bytes([0; 8]),
+            _library: library,
+        };
+
+        // Tokens share the login state among sessions
+        match unsafe { (functions.login)(session, CKU_USER, pin.as_ptr(), pin.len() as CkUlong) } {
+            CKR_USER_ALREADY_LOGGED_IN => {}
+            rv => check("C_Login", rv)?,
+        }
+        key.key = key.find(label)?;
+        // Every token holding the key reports the same ID
+        key.key_id = key_fingerprint(&key.mac(labels::KEY_ID.as_bytes())?);
+        Ok(key)
+    }
+
+    /// Finds the only secret key with the label
+    fn find(&self, label: &str) -> Result<CkUlong, Error> {
+        let session = *self.session.lock().unwrap_or_else(|e| e.into_inner());
+        let mut class = CKO_SECRET_KEY;
+        let mut template = [
+            CkAttribute {
+                kind: CKA_CLASS,
+                value: &mut class as *mut CkUlong as *mut c_void,
+                value_len: std::mem::size_of::<CkUlong>() as CkUlong,
+            },
+            CkAttribute {
+                kind: CKA_LABEL,
+                value: label.as_ptr() as *mut c_void,
+                value_len: label.len() as CkUlong,
+            },
+        ];
+        let mut objects = [0; 2];
+        let mut count = 0;
+        unsafe {
+            check(
+                "C_FindObjectsInit",
+                (self.functions.find_objects_init)(session, template.as_mut_ptr(), 2),
+            )?;
+            let found = (self.functions.find_objects)(session, objects.as_mut_ptr(), 2, &mut count);
+            check(
+                "C_FindObjectsFinal",
+                (self.functions.find_objects_final)(session),
+            )?;
+            check("C_FindObjects", found)?;
+        }
+
+        match count {
+            1 => Ok(objects[0]),
+            0 => Err(Error::Hardware(format!("no secret key labelled {}", label))),
+            _ => Err(Error::Hardware(format!(
+                "several secret keys labelled {}",
+                label
+            ))),
+        }
+    }
+}
+
+impl MacBackend for Pkcs11Key {
+    fn algorithm(&self) -> Algorithm {
+        Algorithm::HmacSha256
+    }
+
+    /// The fingerprint of the key's MAC over the key-ID label, so tokens
+    /// holding a copy of the key agree on it
+    fn key_id(&self) -> KeyId {
+        self.key_id
+    }
+
+    fn mac(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let session = self.session.lock().unwrap_or_else(|e| e.into_inner());
+        let mut mechanism = CkMechanism {
+            mechanism: CKM_SHA256_HMAC,
+            parameter: ptr::null_mut(),
+            parameter_len: 0,
+        };
+        let mut mac = vec![0u8; MAC_LEN];
+        let mut len = MAC_LEN as CkUlong;
+        unsafe {
+            check(
+                "C_SignInit",
+                (self.functions.sign_init)(*session, &mut mechanism, self.key),
+            )?;
+            check(
+                "C_Sign",
+                (self.functions.sign)(
+                    *session,
+                    data.as_ptr(),
+                    data.len() as CkUlong,
+                    mac.as_mut_ptr(),
+                    &mut len,
+                ),
+            )?;
+        }
+        mac.truncate(len as usize);
+        Ok(mac)
+    }
+}
+
+impl Drop for Pkcs11Key {
+    fn drop(&mut self) {
+        // The module stays initialized for other keys in the process
+        let session = *self.session.get_mut().unwrap_or_else(|e| e.into_inner());
+        unsafe { (self.functions.close_session)(session) };
+    }
+}
+
+impl fmt::Debug for Pkcs11Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pkcs11Key")
+            .field("key_id", &self.key_id)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hotp::{hmac, HmacAlgorithm};
+    use crate::Passcode;
+    use std::cell::{Cell, RefCell};
+    use std::slice;
+
+    const KEY: [u8; 32] = [7u8; 32];
+    const SESSION: CkUlong = 11;
+    const OBJECT: CkUlong = 22;
+    const CKR_SLOT_ID_INVALID: CkRv = 0x003;
+    const CKR_MECHANISM_INVALID: CkRv = 0x070;
+    const CKR_PIN_INCORRECT: CkRv = 0x0a0;
+    const CKR_BUFFER_TOO_SMALL: CkRv = 0x150;
+
+    thread_local! {
+        static OPEN_SESSIONS: Cell<i32> = const { Cell::new(0) };
+        static FOUND: RefCell<Vec<CkUlong>> = const { RefCell::new(Vec::new()) };
+    }
+
+    // A token in slot 0 with PIN 1234, holding KEY labelled "otp-key" and
+    // two keys labelled "twin"
+
+    unsafe extern "C" fn initialize(args: *mut c_void) -> CkRv {
+        let args = &*(args as *const CkInitializeArgs);
+        assert_eq!(args.flags & CKF_OS_LOCKING_OK, CKF_OS_LOCKING_OK);
+        CKR_CRYPTOKI_ALREADY_INITIALIZED
+    }
+
+    unsafe extern "C" fn open_session(
+        slot: CkUlong,
+        flags: CkUlong,
+        _application: *mut c_void,
+        _notify: Unused,
+        session: *mut CkUlong,
+    ) -> CkRv {
+        if slot != 0 {
+            return CKR_SLOT_ID_INVALID;
+        }
+        assert_eq!(flags & CKF_SERIAL_SESSION, CKF_SERIAL_SESSION);
+        OPEN_SESSIONS.with(|open| open.set(open.get() + 1));
+        *session = SESSION;
+        CKR_OK
+    }
+
+    unsafe extern "C" fn close_session(session: CkUlong) -> CkRv {
+        assert_eq!(session, SESSION);
+        OPEN_SESSIONS.with(|open| open.set(open.get() - 1));
+        CKR_OK
+    }
+
+    unsafe extern "C" fn login(
+        _session: CkUlong,
+        user: CkUlong,
+        pin: *const u8,
+        pin_len: CkUlong,
+    ) -> CkRv {
+        assert_eq!(user, CKU_USER);
+        match slice::from_raw_parts(pin, pin_len as usize) {
+            b"1234" => CKR_OK,
+            _ => CKR_PIN_INCORRECT,
+        }
+    }
+
+    unsafe extern "C" fn find_objects_init(
+        _session: CkUlong,
+        template: *mut CkAttribute,
+        count: CkUlong,
+    ) -> CkRv {
+        let template = slice::from_raw_parts(template, count as usize);
+        assert_eq!(template[0].kind, CKA_CLASS);
+        assert_eq!(*(template[0].value as *const CkUlong), CKO_SECRET_KEY);
+        assert_eq!(template[1].kind, CKA_LABEL);
+        let label = slice::from_raw_parts(
+            template[1].value as *const u8,
+            template[1].value_len as usize,
+        );
+        let found = match label {
+            b"otp-key" => vec![OBJECT],
+            b"twin" => vec![OBJECT + 1, OBJECT + 2],
+            _ => vec![],
+        };
+        FOUND.with(|objects| *objects.borrow_mut() = found);
+        CKR_OK
+    }
+
+    unsafe extern "C" fn find_objects(
+        _session: CkUlong,
+        objects: *mut CkUlong,
+        max: CkUlong,
+        count: *mut CkUlong,
+    ) -> CkRv {
+        let found = FOUND.with(|found| found.take());
+        let len = found.len().min(max as usize);
+        ptr::copy_nonoverlapping(found.as_ptr(), objects, len);
+        *count = len as CkUlong;
+        CKR_OK
+    }
+
+    unsafe extern "C" fn find_objects_final(_session: CkUlong) -> CkRv {
+        CKR_OK
+    }
+
+    unsafe extern "C" fn sign_init(
+        _session: CkUlong,
+        mechanism: *mut CkMechanism,
+        key: CkUlong,
+    ) -> CkRv {
+        match ((*mechanism).mechanism, key) {
+            (CKM_SHA256_HMAC, OBJECT) => CKR_OK,
+            _ => CKR_MECHANISM_INVALID,
+        }
+    }
+
+    unsafe extern "C" fn sign(
+        _session: CkUlong,
+        data: *const u8,
+        data_len: CkUlong,
+        signature: *mut u8,
+        signature_len: *mut CkUlong,
+    ) -> CkRv {
+        let mac = hmac(
+            HmacAlgorithm::Sha256,
+            &KEY,
+            slice::from_raw_parts(data, data_len as usize),
+        );
+        if (*signature_len as usize) < mac.len() {
+            return CKR_BUFFER_TOO_SMALL;
+        }
+        ptr::copy_nonoverlapping(mac.as_ptr(), signature, mac.len());
+        *signature_len = mac.len() as CkUlong;
+        CKR_OK
+    }
+
+    static MODULE: FunctionList = FunctionList {
+        version: CkVersion {
+            major: 2,
+            minor: 40,
+        },
+        initialize,
+        finalize: None,
+        get_info: None,
+        get_function_list: None,
+        get_slot_list: None,
+        get_slot_info: None,
+        get_token_info: None,
+        get_mechanism_list: None,
+        get_mechanism_info: None,
+        init_token: None,
+        init_pin: None,
+        set_pin: None,
+        open_session,
+        close_session,
+        close_all_sessions: None,
+        get_session_info: None,
+        get_operation_state: None,
+        set_operation_state: None,
+        login,
+        logout: None,
+        create_object: None,
+        copy_object: None,
+        destroy_object: None,
+        get_object_size: None,
+        get_attribute_value: None,
+        set_attribute_value: None,
+        find_objects_init,
+        find_objects,
+        find_objects_final,
+        encrypt_init: None,
+        encrypt: None,
+        encrypt_update: None,
+        encrypt_final: None,
+        decrypt_init: None,
+        decrypt: None,
+        decrypt_update: None,
+        decrypt_final: None,
+        digest_init: None,
+        digest: None,
+        digest_update: None,
+        digest_key: None,
+        digest_final: None,
+        sign_init,
+        sign,
+    };
+
+    fn open(slot: u64, pin: &[u8], label: &str) -> Result<Pkcs11Key, Error> {
+        Pkcs11Key::with_functions(&MODULE, None, slot, pin, label)
+    }
+
+    #[test]
+    fn test_matches_software_key() {
+        let key = open(0, b"1234", "otp-key").unwrap();
+        assert_eq!(
+            key.key_id(),
+            key_fingerprint(&hmac(
+                HmacAlgorithm::Sha256,
+                &KEY,
+                labels::KEY_ID.as_bytes()
+            ))
+        );
+        let hardware = Passcode::from_backend(key).unwrap();
+        let software = Passcode::new(Algorithm::HmacSha256, KEY.to_vec());
+
+        for data in [&b""[..], b"challenge", &[9u8; 4096]] {
+            assert_eq!(hardware.try_compute(data).unwrap(), software.compute(data));
+        }
+        assert_eq!(
+            hardware.derive_session_key(b"challenge", 32),
+            software.derive_session_key(b"challenge", 32)
+        );
+
+        drop(hardware);
+        assert_eq!(OPEN_SESSIONS.with(Cell::get), 0);
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |message: &str| Some(Error::Hardware(message.to_string()));
+
+        assert_eq!(
+            open(0, b"0000", "otp-key").err(),
+            error("C_Login failed with CKR 0xa0")
+        );
+        assert_eq!(
+            open(0, b"1234", "missing").err(),
+            error("no secret key labelled missing")
+        );
+        assert_eq!(
+            open(0, b"1234", "twin").err(),
+            error("several secret keys labelled twin")
+        );
+        assert_eq!(
+            open(1, b"1234", "otp-key").err(),
+            error("C_OpenSession failed with CKR 0x3")
+        );
+        // Sessions of failed lookups are closed
+        assert_eq!(OPEN_SESSIONS.with(Cell::get), 0);
+
+        assert!(matches!(
+            Pkcs11Key::open("/nonexistent/pkcs11.so", 0, b"1234", "otp-key"),
+            Err(Error::Hardware(_))
+        ));
+    }
+}
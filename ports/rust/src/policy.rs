@@ -0,0 +1,157 @@
+//! Approved-algorithm policy enforcement
+//!
+//! Compliance teams can restrict the configurations the library accepts in
+//! code rather than in review. The process-wide default applies to
+//! [`Passcode::try_new`](crate::Passcode::try_new); individual call sites can
+//! pass an explicit mode with
+//! [`Passcode::try_new_with_policy`](crate::Passcode::try_new_with_policy).
+//!
+//! Keys created with the unchecked [`Passcode::new`](crate::Passcode::new),
+//! including those in a [`KeyRing`](crate::keyring::KeyRing) or from
+//! [`migrated_passcode`](crate::migration::migrated_passcode), are checked
+//! against the default policy each time a
+//! [`ChallengeManager`](crate::challenge::ChallengeManager) or
+//! [`Verifier`](crate::verifier::Verifier) verifies with them:
+//!
+//! ```
+//! use passcode::challenge::ChallengeManager;
+//! use passcode::policy::{set_default_policy, PolicyMode};
+//! use passcode::{Algorithm, Error, Passcode};
+//!
+//! # pollster::block_on(async {
+//! let key = vec![0u8; 32];
+//! let server = ChallengeManager::new(Passcode::new(Algorithm::Blake3KeyedMode256, key.clone()));
+//! let challenge = server.issue().await.unwrap();
+//! let otp = Passcode::new(Algorithm::Blake3KeyedMode256, key).compute(challenge.bytes());
+//!
+//! set_default_policy(PolicyMode::Strict);
+//! assert_eq!(
+//!     server.verify(challenge.id(), &otp).await,
+//!     Err(Error::AlgorithmNotApproved("BLAKE3-Keyed-Mode-256"))
+//! );
+//! # });
+//! ```
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use crate::{Algorithm, Error};
+
+/// Policy applied when constructing a passcode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PolicyMode {
+    /// Every algorithm and key length is accepted
+    #[default]
+    Permissive,
+    /// Only SHA3-based algorithms with at least 256-bit security and keys of
+    /// at least [`STRICT_MIN_KEY_LEN`] bytes are accepted
+    Strict,
+}
+
+/// Minimum key length in bytes under [`PolicyMode::Strict`]
+pub const STRICT_MIN_KEY_LEN: usize = 32;
+
+impl PolicyMode {
+    /// Returns whether the algorithm is approved under this policy
+    pub fn is_approved(&self, algorithm: Algorithm) -> bool {
+        match self {
+            PolicyMode::Permissive => true,
+            PolicyMode::Strict => {
+                matches!(algorithm, Algorithm::Sha3Kmac256 | Algorithm::Sha3Kmac512)
+            }
+        }
+    }
+
+    /// Returns the minimum accepted key length in bytes for the algorithm
+    pub fn min_key_len(&self, algorithm: Algorithm) -> usize {
+        match self {
+            PolicyMode::Permissive => 0,
+            PolicyMode::Strict => STRICT_MIN_KEY_LEN.max(algorithm.recommended_key_len()),
+        }
+    }
+
+    /// Checks an algorithm and key length against this policy
+    pub fn check(&self, algorithm: Algorithm, key_len: usize) -> Result<(), Error> {
+        if !self.is_approved(algorithm) {
+            return Err(Error::AlgorithmNotApproved(algorithm.as_str()));
+        }
+
+        let min = self.min_key_len(algorithm);
+        if key_len < min {
+            return Err(Error::KeyTooShort {
+                min,
+                actual: key_len,
+            });
+        }
+        Ok(())
+    }
+}
+
+static DEFAULT_POLICY: AtomicU8 = AtomicU8::new(0);
+
+/// Sets the process-wide default policy
+///
+/// # Example
+/// ```
+/// use passcode::policy::{set_default_policy, PolicyMode};
+/// use passcode::{Algorithm, Passcode};
+///
+/// set_default_policy(PolicyMode::Strict);
+/// assert!(Passcode::try_new(Algorithm::Blake3KeyedMode256, vec![0u8; 32]).is_err());
+/// assert!(Passcode::try_new(Algorithm::Sha3Kmac256, vec![0u8; 32]).is_ok());
+/// ```
+pub fn set_default_policy(mode: PolicyMode) {
+    let value = match mode {
+        PolicyMode::Permissive => 0,
+        PolicyMode::Strict => 1,
+    };
+    DEFAULT_POLICY.store(value, Ordering::SeqCst);
+}
+
+/// Gets the process-wide default policy
+pub fn default_policy() -> PolicyMode {
+    match DEFAULT_POLICY.load(Ordering::SeqCst) {
+        0 => PolicyMode::Permissive,
+        _ => PolicyMode::Strict,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permissive_accepts_everything() {
+        for algorithm in Algorithm::all() {
+            assert_eq!(PolicyMode::Permissive.check(algorithm, 1), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_strict_rejects_non_approved() {
+        let strict = PolicyMode::Strict;
+
+        assert_eq!(
+            strict.check(Algorithm::Sha3Kmac128, 32),
+            Err(Error::AlgorithmNotApproved("SHA3-KMAC-128"))
+        );
+        assert_eq!(
+            strict.check(Algorithm::Blake3KeyedMode256, 32),
+            Err(Error::AlgorithmNotApproved("BLAKE3-Keyed-Mode-256"))
+        );
+        assert_eq!(
+            strict.check(Algorithm::Sha3Kmac256, 16),
+            Err(Error::KeyTooShort {
+                min: 32,
+                actual: 16
+            })
+        );
+        assert_eq!(
+            strict.check(Algorithm::Sha3Kmac512, 32),
+            Err(Error::KeyTooShort {
+                min: 64,
+                actual: 32
+            })
+        );
+        assert_eq!(strict.check(Algorithm::Sha3Kmac256, 32), Ok(()));
+    }
+}
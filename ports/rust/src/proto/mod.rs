@@ -0,0 +1,326 @@
+//! Protocol Buffers types (feature `proto`)
+//!
+//! The schema is published as `proto/passcode.proto` (package
+//! `passcode.v1`), so gRPC services in any language share one definition of
+//! challenges, responses, verification outcomes and enrollment messages.
+//! The types in this module are generated from it with `prost-build` and
+//! checked in, so building the crate does not need `protoc`. With the
+//! `grpc` feature the module also holds the generated `PasscodeAuth`
+//! client and server; see [`grpc`](crate::grpc).
+//!
+//! Conversions to the crate's types are fallible where the message can
+//! carry values the crate rejects, e.g. an unknown algorithm or a challenge
+//! ID of the wrong length; those fail with [`Error::MalformedMessage`].
+//!
+//! # Example
+//! ```
+//! use passcode::challenge::ChallengeManager;
+//! use passcode::{proto, Algorithm, Passcode};
+//! use prost::Message;
+//!
+//! # pollster::block_on(async {
+//! let manager = ChallengeManager::new(Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]));
+//! let challenge = manager.issue().await.unwrap();
+//! let sent = proto::Challenge::from(&challenge).encode_to_vec();
+//!
+//! // Client
+//! let received = proto::Challenge::decode(sent.as_slice()).unwrap();
+//! let received = passcode::challenge::Challenge::try_from(received).unwrap();
+//! assert_eq!(received.bytes(), challenge.bytes());
+//! # });
+//! ```
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::challenge::{self, ChallengeId, CHALLENGE_ID_LEN};
+use crate::enrollment;
+use crate::verifier;
+use crate::wire;
+use crate::{Algorithm, Error};
+
+include!("passcode.v1.rs");
+
+// Service code from the same run of tonic-build, kept in its own file so
+// the messages build without tonic
+#[cfg(feature = "grpc")]
+include!("passcode.v1.grpc.rs");
+
+impl From<&challenge::ChallengeBinding> for ChallengeBinding {
+    fn from(binding: &challenge::ChallengeBinding) -> Self {
+        Self {
+            user_id: binding.user_id().map(str::to_string),
+            device_id: binding.device_id().map(str::to_string),
+            client_ip: binding.client_ip().map(|ip| ip.to_string()),
+            purpose: binding.purpose().map(str::to_string),
+        }
+    }
+}
+
+impl TryFrom<ChallengeBinding> for challenge::ChallengeBinding {
+    type Error = Error;
+
+    fn try_from(message: ChallengeBinding) -> Result<Self, Error> {
+        let mut binding = challenge::ChallengeBinding::new();
+        if let Some(user_id) = message.user_id {
+            binding = binding.with_user_id(user_id);
+        }
+        if let Some(device_id) = message.device_id {
+            binding = binding.with_device_id(device_id);
+        }
+        if let Some(client_ip) = message.client_ip {
+            let client_ip = client_ip
+                .parse()
+                .map_err(|_| Error::MalformedMessage("invalid client IP"))?;
+            binding = binding.with_client_ip(client_ip);
+        }
+        if let Some(purpose) = message.purpose {
+            binding = binding.with_purpose(purpose);
+        }
+        Ok(binding)
+    }
+}
+
+impl From<&challenge::Challenge> for Challenge {
+    fn from(challenge: &challenge::Challenge) -> Self {
+        Self {
+            id: challenge.id().as_bytes().to_vec(),
+            challenge: challenge.bytes().to_vec(),
+            expires_at_ms: to_millis(challenge.expires_at()),
+            binding: Some(challenge.binding().into()),
+            difficulty: u32::from(challenge.difficulty()),
+        }
+    }
+}
+
+impl TryFrom<Challenge> for challenge::Challenge {
+    type Error = Error;
+
+    fn try_from(message: Challenge) -> Result<Self, Error> {
+        let binding = match message.binding {
+            Some(binding) => binding.try_into()?,
+            None => challenge::ChallengeBinding::new(),
+        };
+        let difficulty = u8::try_from(message.difficulty)
+            .map_err(|_| Error::MalformedMessage("puzzle difficulty out of range"))?;
+        Ok(challenge::Challenge::from_parts(
+            challenge_id(&message.id)?,
+            message.challenge,
+            from_millis(message.expires_at_ms),
+            binding,
+            difficulty,
+        ))
+    }
+}
+
+impl ChallengeAnswer {
+    /// Creates an answer to the challenge with the given ID
+    pub fn new(challenge_id: &ChallengeId, otp: impl Into<String>) -> Self {
+        Self {
+            challenge_id: challenge_id.as_bytes().to_vec(),
+            otp: otp.into(),
+        }
+    }
+
+    /// Parses the ID of the answered challenge
+    pub fn parsed_challenge_id(&self) -> Result<ChallengeId, Error> {
+        challenge_id(&self.challenge_id)
+    }
+}
+
+impl From<&wire::ChallengeMessage> for SignedChallenge {
+    fn from(message: &wire::ChallengeMessage) -> Self {
+        Self {
+            algorithm: u32::from(message.algorithm.id()),
+            nonce: message.nonce.clone(),
+            expires_at_ms: to_millis(message.expires_at),
+            mac: message.mac.clone(),
+        }
+    }
+}
+
+impl TryFrom<SignedChallenge> for wire::ChallengeMessage {
+    type Error = Error;
+
+    fn try_from(message: SignedChallenge) -> Result<Self, Error> {
+        Ok(Self {
+            algorithm: algorithm(message.algorithm)?,
+            nonce: message.nonce,
+            expires_at: from_millis(message.expires_at_ms),
+            mac: message.mac,
+        })
+    }
+}
+
+impl From<&wire::ResponseMessage> for SignedResponse {
+    fn from(message: &wire::ResponseMessage) -> Self {
+        Self {
+            algorithm: u32::from(message.algorithm.id()),
+            nonce: message.nonce.clone(),
+            mac: message.mac.clone(),
+        }
+    }
+}
+
+impl TryFrom<SignedResponse> for wire::ResponseMessage {
+    type Error = Error;
+
+    fn try_from(message: SignedResponse) -> Result<Self, Error> {
+        Ok(Self {
+            algorithm: algorithm(message.algorithm)?,
+            nonce: message.nonce,
+            mac: message.mac,
+        })
+    }
+}
+
+impl From<verifier::VerifyOutcome> for VerifyOutcome {
+    fn from(outcome: verifier::VerifyOutcome) -> Self {
+        use verify_outcome::Kind;
+
+        let (kind, retry_after) = match outcome {
+            verifier::VerifyOutcome::Accepted => (Kind::Accepted, Duration::ZERO),
+            verifier::VerifyOutcome::Rejected => (Kind::Rejected, Duration::ZERO),
+            verifier::VerifyOutcome::Expired => (Kind::Expired, Duration::ZERO),
+            verifier::VerifyOutcome::UnknownChallenge => (Kind::UnknownChallenge, Duration::ZERO),
+            verifier::VerifyOutcome::Replayed => (Kind::Replayed, Duration::ZERO),
+            verifier::VerifyOutcome::PuzzleUnsolved => (Kind::PuzzleUnsolved, Duration::ZERO),
+            verifier::VerifyOutcome::RateLimited { retry_after } => {
+                (Kind::RateLimited, retry_after)
+            }
+            verifier::VerifyOutcome::LockedOut { retry_after } => (Kind::LockedOut, retry_after),
+        };
+        Self {
+            kind: kind as i32,
+            retry_after_ms: retry_after.as_millis() as u64,
+        }
+    }
+}
+
+impl TryFrom<VerifyOutcome> for verifier::VerifyOutcome {
+    type Error = Error;
+
+    fn try_from(message: VerifyOutcome) -> Result<Self, Error> {
+        use verify_outcome::Kind;
+
+        let retry_after = Duration::from_millis(message.retry_after_ms);
+        match Kind::try_from(message.kind) {
+            Ok(Kind::Accepted) => Ok(Self::Accepted),
+            Ok(Kind::Rejected) => Ok(Self::Rejected),
+            Ok(Kind::Expired) => Ok(Self::Expired),
+            Ok(Kind::UnknownChallenge) => Ok(Self::UnknownChallenge),
+            Ok(Kind::Replayed) => Ok(Self::Replayed),
+            Ok(Kind::PuzzleUnsolved) => Ok(Self::PuzzleUnsolved),
+            Ok(Kind::RateLimited) => Ok(Self::RateLimited { retry_after }),
+            Ok(Kind::LockedOut) => Ok(Self::LockedOut { retry_after }),
+            Ok(Kind::Unspecified) | Err(_) => {
+                Err(Error::MalformedMessage("unknown verification outcome"))
+            }
+        }
+    }
+}
+
+impl From<&enrollment::Provisioning> for Provisioning {
+    fn from(provisioning: &enrollment::Provisioning) -> Self {
+        Self {
+            uri: provisioning.uri().to_string(),
+            challenge: Some(provisioning.challenge().into()),
+        }
+    }
+}
+
+fn challenge_id(bytes: &[u8]) -> Result<ChallengeId, Error> {
+    let bytes: [u8; CHALLENGE_ID_LEN] = bytes
+        .try_into()
+        .map_err(|_| Error::MalformedMessage("invalid challenge ID"))?;
+    Ok(ChallengeId::from_bytes(bytes))
+}
+
+fn algorithm(id: u32) -> Result<Algorithm, Error> {
+    u8::try_from(id)
+        .ok()
+        .and_then(Algorithm::from_id)
+        .ok_or(Error::MalformedMessage("unknown algorithm"))
+}
+
+fn to_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+fn from_millis(millis: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost::Message;
+
+    #[test]
+    fn test_challenge_round_trip() {
+        let challenge = challenge::Challenge::from_parts(
+            ChallengeId::from_bytes([7; CHALLENGE_ID_LEN]),
+            vec![1, 2, 3],
+            from_millis(1_700_000_000_123),
+            challenge::ChallengeBinding::new()
+                .with_user_id("alice")
+                .with_client_ip("2001:db8::1".parse().unwrap()),
+            4,
+        );
+        let encoded = Challenge::from(&challenge).encode_to_vec();
+        let decoded = Challenge::decode(encoded.as_slice()).unwrap();
+        assert_eq!(decoded.binding.as_ref().unwrap().device_id, None);
+        assert_eq!(challenge::Challenge::try_from(decoded), Ok(challenge));
+
+        let mut short_id = Challenge::from(&challenge::Challenge::from_parts(
+            ChallengeId::from_bytes([7; CHALLENGE_ID_LEN]),
+            vec![],
+            UNIX_EPOCH,
+            challenge::ChallengeBinding::new(),
+            0,
+        ));
+        short_id.id.pop();
+        assert_eq!(
+            challenge::Challenge::try_from(short_id),
+            Err(Error::MalformedMessage("invalid challenge ID"))
+        );
+    }
+
+    #[test]
+    fn test_response_layout() {
+        let response = wire::ResponseMessage {
+            algorithm: Algorithm::Sha3Kmac256,
+            nonce: vec![0xaa],
+            mac: vec![0xbb, 0xcc],
+        };
+        let encoded = SignedResponse::from(&response).encode_to_vec();
+        // Field 1 varint, field 2 and 3 length-delimited
+        assert_eq!(encoded, [0x08, 1, 0x12, 1, 0xaa, 0x1a, 2, 0xbb, 0xcc]);
+
+        let decoded = SignedResponse::decode(encoded.as_slice()).unwrap();
+        assert_eq!(wire::ResponseMessage::try_from(decoded), Ok(response));
+        assert_eq!(
+            wire::ResponseMessage::try_from(SignedResponse {
+                algorithm: 300,
+                ..Default::default()
+            }),
+            Err(Error::MalformedMessage("unknown algorithm"))
+        );
+    }
+
+    #[test]
+    fn test_outcome_round_trip() {
+        let outcome = verifier::VerifyOutcome::RateLimited {
+            retry_after: Duration::from_millis(1500),
+        };
+        let message = VerifyOutcome::from(outcome);
+        assert_eq!(message.kind(), verify_outcome::Kind::RateLimited);
+        assert_eq!(message.retry_after_ms, 1500);
+        assert_eq!(verifier::VerifyOutcome::try_from(message), Ok(outcome));
+
+        assert_eq!(
+            verifier::VerifyOutcome::try_from(VerifyOutcome::default()),
+            Err(Error::MalformedMessage("unknown verification outcome"))
+        );
+    }
+}
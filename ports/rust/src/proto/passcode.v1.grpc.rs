@@ -0,0 +1,460 @@
+// This file is @generated by tonic-build.
+/// Generated client implementations.
+pub mod passcode_auth_client {
+    #![allow(
+        unused_variables,
+        dead_code,
+        missing_docs,
+        clippy::wildcard_imports,
+        clippy::let_unit_value
+    )]
+    use tonic::codegen::http::Uri;
+    use tonic::codegen::*;
+    /// Network API over a Verifier and an Enrollment. Security outcomes of
+    /// VerifyResponse are reported in the VerifyOutcome; RPC errors are reserved
+    /// for malformed requests and server failures.
+    #[derive(Debug, Clone)]
+    pub struct PasscodeAuthClient<T> {
+        inner: tonic::client::Grpc<T>,
+    }
+    impl<T> PasscodeAuthClient<T>
+    where
+        T: tonic::client::GrpcService<tonic::body::Body>,
+        T::Error: Into<StdError>,
+        T::ResponseBody: Body<Data = Bytes> + std::marker::Send + 'static,
+        <T::ResponseBody as Body>::Error: Into<StdError> + std::marker::Send,
+    {
+        pub fn new(inner: T) -> Self {
+            let inner = tonic::client::Grpc::new(inner);
+            Self { inner }
+        }
+        pub fn with_origin(inner: T, origin: Uri) -> Self {
+            let inner = tonic::client::Grpc::with_origin(inner, origin);
+            Self { inner }
+        }
+        pub fn with_interceptor<F>(
+            inner: T,
+            interceptor: F,
+        ) -> PasscodeAuthClient<InterceptedService<T, F>>
+        where
+            F: tonic::service::Interceptor,
+            T::ResponseBody: Default,
+            T: tonic::codegen::Service<
+                http::Request<tonic::body::Body>,
+                Response = http::Response<
+                    <T as tonic::client::GrpcService<tonic::body::Body>>::ResponseBody,
+                >,
+            >,
+            <T as tonic::codegen::Service<http::Request<tonic::body::Body>>>::Error:
+                Into<StdError> + std::marker::Send + std::marker::Sync,
+        {
+            PasscodeAuthClient::new(InterceptedService::new(inner, interceptor))
+        }
+        /// Compress requests with the given encoding.
+        ///
+        /// This requires the server to support it otherwise it might respond with an
+        /// error.
+        #[must_use]
+        pub fn send_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.inner = self.inner.send_compressed(encoding);
+            self
+        }
+        /// Enable decompressing responses.
+        #[must_use]
+        pub fn accept_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.inner = self.inner.accept_compressed(encoding);
+            self
+        }
+        /// Limits the maximum size of a decoded message.
+        ///
+        /// Default: `4MB`
+        #[must_use]
+        pub fn max_decoding_message_size(mut self, limit: usize) -> Self {
+            self.inner = self.inner.max_decoding_message_size(limit);
+            self
+        }
+        /// Limits the maximum size of an encoded message.
+        ///
+        /// Default: `usize::MAX`
+        #[must_use]
+        pub fn max_encoding_message_size(mut self, limit: usize) -> Self {
+            self.inner = self.inner.max_encoding_message_size(limit);
+            self
+        }
+        /// Issues a challenge bound to the user.
+        pub async fn issue_challenge(
+            &mut self,
+            request: impl tonic::IntoRequest<super::IssueChallengeRequest>,
+        ) -> std::result::Result<tonic::Response<super::Challenge>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::unknown(format!("Service was not ready: {}", e.into()))
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path =
+                http::uri::PathAndQuery::from_static("/passcode.v1.PasscodeAuth/IssueChallenge");
+            let mut req = request.into_request();
+            req.extensions_mut().insert(GrpcMethod::new(
+                "passcode.v1.PasscodeAuth",
+                "IssueChallenge",
+            ));
+            self.inner.unary(req, path, codec).await
+        }
+        /// Checks the user's OTP, consuming the challenge.
+        pub async fn verify_response(
+            &mut self,
+            request: impl tonic::IntoRequest<super::VerifyResponseRequest>,
+        ) -> std::result::Result<tonic::Response<super::VerifyOutcome>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::unknown(format!("Service was not ready: {}", e.into()))
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path =
+                http::uri::PathAndQuery::from_static("/passcode.v1.PasscodeAuth/VerifyResponse");
+            let mut req = request.into_request();
+            req.extensions_mut().insert(GrpcMethod::new(
+                "passcode.v1.PasscodeAuth",
+                "VerifyResponse",
+            ));
+            self.inner.unary(req, path, codec).await
+        }
+        /// Generates a key for the user and returns its provisioning payload.
+        pub async fn enroll(
+            &mut self,
+            request: impl tonic::IntoRequest<super::EnrollRequest>,
+        ) -> std::result::Result<tonic::Response<super::Provisioning>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::unknown(format!("Service was not ready: {}", e.into()))
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/passcode.v1.PasscodeAuth/Enroll");
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("passcode.v1.PasscodeAuth", "Enroll"));
+            self.inner.unary(req, path, codec).await
+        }
+        /// Activates the enrolled key once the client answers its challenge.
+        pub async fn confirm_enrollment(
+            &mut self,
+            request: impl tonic::IntoRequest<super::EnrollmentConfirmation>,
+        ) -> std::result::Result<tonic::Response<super::EnrollmentConfirmed>, tonic::Status>
+        {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::unknown(format!("Service was not ready: {}", e.into()))
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path =
+                http::uri::PathAndQuery::from_static("/passcode.v1.PasscodeAuth/ConfirmEnrollment");
+            let mut req = request.into_request();
+            req.extensions_mut().insert(GrpcMethod::new(
+                "passcode.v1.PasscodeAuth",
+                "ConfirmEnrollment",
+            ));
+            self.inner.unary(req, path, codec).await
+        }
+    }
+}
+/// Generated server implementations.
+pub mod passcode_auth_server {
+    #![allow(
+        unused_variables,
+        dead_code,
+        missing_docs,
+        clippy::wildcard_imports,
+        clippy::let_unit_value
+    )]
+    use tonic::codegen::*;
+    /// Generated trait containing gRPC methods that should be implemented for use with PasscodeAuthServer.
+    #[async_trait]
+    pub trait PasscodeAuth: std::marker::Send + std::marker::Sync + 'static {
+        /// Issues a challenge bound to the user.
+        async fn issue_challenge(
+            &self,
+            request: tonic::Request<super::IssueChallengeRequest>,
+        ) -> std::result::Result<tonic::Response<super::Challenge>, tonic::Status>;
+        /// Checks the user's OTP, consuming the challenge.
+        async fn verify_response(
+            &self,
+            request: tonic::Request<super::VerifyResponseRequest>,
+        ) -> std::result::Result<tonic::Response<super::VerifyOutcome>, tonic::Status>;
+        /// Generates a key for the user and returns its provisioning payload.
+        async fn enroll(
+            &self,
+            request: tonic::Request<super::EnrollRequest>,
+        ) -> std::result::Result<tonic::Response<super::Provisioning>, tonic::Status>;
+        /// Activates the enrolled key once the client answers its challenge.
+        async fn confirm_enrollment(
+            &self,
+            request: tonic::Request<super::EnrollmentConfirmation>,
+        ) -> std::result::Result<tonic::Response<super::EnrollmentConfirmed>, tonic::Status>;
+    }
+    /// Network API over a Verifier and an Enrollment. Security outcomes of
+    /// VerifyResponse are reported in the VerifyOutcome; RPC errors are reserved
+    /// for malformed requests and server failures.
+    #[derive(Debug)]
+    pub struct PasscodeAuthServer<T> {
+        inner: Arc<T>,
+        accept_compression_encodings: EnabledCompressionEncodings,
+        send_compression_encodings: EnabledCompressionEncodings,
+        max_decoding_message_size: Option<usize>,
+        max_encoding_message_size: Option<usize>,
+    }
+    impl<T> PasscodeAuthServer<T> {
+        pub fn new(inner: T) -> Self {
+            Self::from_arc(Arc::new(inner))
+        }
+        pub fn from_arc(inner: Arc<T>) -> Self {
+            Self {
+                inner,
+                accept_compression_encodings: Default::default(),
+                send_compression_encodings: Default::default(),
+                max_decoding_message_size: None,
+                max_encoding_message_size: None,
+            }
+        }
+        pub fn with_interceptor<F>(inner: T, interceptor: F) -> InterceptedService<Self, F>
+        where
+            F: tonic::service::Interceptor,
+        {
+            InterceptedService::new(Self::new(inner), interceptor)
+        }
+        /// Enable decompressing requests with the given encoding.
+        #[must_use]
+        pub fn accept_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.accept_compression_encodings.enable(encoding);
+            self
+        }
+        /// Compress responses with the given encoding, if the client supports it.
+        #[must_use]
+        pub fn send_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.send_compression_encodings.enable(encoding);
+            self
+        }
+        /// Limits the maximum size of a decoded message.
+        ///
+        /// Default: `4MB`
+        #[must_use]
+        pub fn max_decoding_message_size(mut self, limit: usize) -> Self {
+            self.max_decoding_message_size = Some(limit);
+            self
+        }
+        /// Limits the maximum size of an encoded message.
+        ///
+        /// Default: `usize::MAX`
+        #[must_use]
+        pub fn max_encoding_message_size(mut self, limit: usize) -> Self {
+            self.max_encoding_message_size = Some(limit);
+            self
+        }
+    }
+    impl<T, B> tonic::codegen::Service<http::Request<B>> for PasscodeAuthServer<T>
+    where
+        T: PasscodeAuth,
+        B: Body + std::marker::Send + 'static,
+        B::Error: Into<StdError> + std::marker::Send + 'static,
+    {
+        type Response = http::Response<tonic::body::Body>;
+        type Error = std::convert::Infallible;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+        fn poll_ready(
+            &mut self,
+            _cx: &mut Context<'_>,
+        ) -> Poll<std::result::Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+        fn call(&mut self, req: http::Request<B>) -> Self::Future {
+            match req.uri().path() {
+                "/passcode.v1.PasscodeAuth/IssueChallenge" => {
+                    #[allow(non_camel_case_types)]
+                    struct IssueChallengeSvc<T: PasscodeAuth>(pub Arc<T>);
+                    impl<T: PasscodeAuth> tonic::server::UnaryService<super::IssueChallengeRequest>
+                        for IssueChallengeSvc<T>
+                    {
+                        type Response = super::Challenge;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::IssueChallengeRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as PasscodeAuth>::issue_challenge(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = IssueChallengeSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/passcode.v1.PasscodeAuth/VerifyResponse" => {
+                    #[allow(non_camel_case_types)]
+                    struct VerifyResponseSvc<T: PasscodeAuth>(pub Arc<T>);
+                    impl<T: PasscodeAuth> tonic::server::UnaryService<super::VerifyResponseRequest>
+                        for VerifyResponseSvc<T>
+                    {
+                        type Response = super::VerifyOutcome;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::VerifyResponseRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as PasscodeAuth>::verify_response(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = VerifyResponseSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/passcode.v1.PasscodeAuth/Enroll" => {
+                    #[allow(non_camel_case_types)]
+                    struct EnrollSvc<T: PasscodeAuth>(pub Arc<T>);
+                    impl<T: PasscodeAuth> tonic::server::UnaryService<super::EnrollRequest> for EnrollSvc<T> {
+                        type Response = super::Provisioning;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::EnrollRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut =
+                                async move { <T as PasscodeAuth>::enroll(&inner, request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = EnrollSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/passcode.v1.PasscodeAuth/ConfirmEnrollment" => {
+                    #[allow(non_camel_case_types)]
+                    struct ConfirmEnrollmentSvc<T: PasscodeAuth>(pub Arc<T>);
+                    impl<T: PasscodeAuth> tonic::server::UnaryService<super::EnrollmentConfirmation>
+                        for ConfirmEnrollmentSvc<T>
+                    {
+                        type Response = super::EnrollmentConfirmed;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::EnrollmentConfirmation>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as PasscodeAuth>::confirm_enrollment(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = ConfirmEnrollmentSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                _ => Box::pin(async move {
+                    let mut response = http::Response::new(tonic::body::Body::default());
+                    let headers = response.headers_mut();
+                    headers.insert(
+                        tonic::Status::GRPC_STATUS,
+                        (tonic::Code::Unimplemented as i32).into(),
+                    );
+                    headers.insert(
+                        http::header::CONTENT_TYPE,
+                        tonic::metadata::GRPC_CONTENT_TYPE,
+                    );
+                    Ok(response)
+                }),
+            }
+        }
+    }
+    impl<T> Clone for PasscodeAuthServer<T> {
+        fn clone(&self) -> Self {
+            let inner = self.inner.clone();
+            Self {
+                inner,
+                accept_compression_encodings: self.accept_compression_encodings,
+                send_compression_encodings: self.send_compression_encodings,
+                max_decoding_message_size: self.max_decoding_message_size,
+                max_encoding_message_size: self.max_encoding_message_size,
+            }
+        }
+    }
+    /// Generated gRPC service name
+    pub const SERVICE_NAME: &str = "passcode.v1.PasscodeAuth";
+    impl<T> tonic::server::NamedService for PasscodeAuthServer<T> {
+        const NAME: &'static str = SERVICE_NAME;
+    }
+}
@@ -0,0 +1,163 @@
+// This file is @generated by prost-build.
+/// Who or what a challenge is bound to. Unset fields are not bound.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ChallengeBinding {
+    #[prost(string, optional, tag = "1")]
+    pub user_id: ::core::option::Option<::prost::alloc::string::String>,
+    #[prost(string, optional, tag = "2")]
+    pub device_id: ::core::option::Option<::prost::alloc::string::String>,
+    /// Textual IPv4 or IPv6 address
+    #[prost(string, optional, tag = "3")]
+    pub client_ip: ::core::option::Option<::prost::alloc::string::String>,
+    #[prost(string, optional, tag = "4")]
+    pub purpose: ::core::option::Option<::prost::alloc::string::String>,
+}
+/// A challenge issued by a ChallengeManager.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Challenge {
+    /// 16 random bytes
+    #[prost(bytes = "vec", tag = "1")]
+    pub id: ::prost::alloc::vec::Vec<u8>,
+    /// Bytes the OTP is computed over, together with the binding
+    #[prost(bytes = "vec", tag = "2")]
+    pub challenge: ::prost::alloc::vec::Vec<u8>,
+    #[prost(uint64, tag = "3")]
+    pub expires_at_ms: u64,
+    #[prost(message, optional, tag = "4")]
+    pub binding: ::core::option::Option<ChallengeBinding>,
+    /// Leading zero bits of the client puzzle; 0 when there is none
+    #[prost(uint32, tag = "5")]
+    pub difficulty: u32,
+}
+/// Answer to a Challenge.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ChallengeAnswer {
+    #[prost(bytes = "vec", tag = "1")]
+    pub challenge_id: ::prost::alloc::vec::Vec<u8>,
+    #[prost(string, tag = "2")]
+    pub otp: ::prost::alloc::string::String,
+}
+/// A stateless challenge issued by SignedChallenges.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SignedChallenge {
+    #[prost(uint32, tag = "1")]
+    pub algorithm: u32,
+    #[prost(bytes = "vec", tag = "2")]
+    pub nonce: ::prost::alloc::vec::Vec<u8>,
+    #[prost(uint64, tag = "3")]
+    pub expires_at_ms: u64,
+    /// The server's MAC over the other fields
+    #[prost(bytes = "vec", tag = "4")]
+    pub mac: ::prost::alloc::vec::Vec<u8>,
+}
+/// Answer to a SignedChallenge.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SignedResponse {
+    #[prost(uint32, tag = "1")]
+    pub algorithm: u32,
+    /// Nonce of the answered challenge
+    #[prost(bytes = "vec", tag = "2")]
+    pub nonce: ::prost::alloc::vec::Vec<u8>,
+    /// Raw OTP bytes over the challenge
+    #[prost(bytes = "vec", tag = "3")]
+    pub mac: ::prost::alloc::vec::Vec<u8>,
+}
+/// Result of a verification.
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct VerifyOutcome {
+    #[prost(enumeration = "verify_outcome::Kind", tag = "1")]
+    pub kind: i32,
+    /// Set for RATE_LIMITED and LOCKED_OUT
+    #[prost(uint64, tag = "2")]
+    pub retry_after_ms: u64,
+}
+/// Nested message and enum types in `VerifyOutcome`.
+pub mod verify_outcome {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+    #[repr(i32)]
+    pub enum Kind {
+        Unspecified = 0,
+        Accepted = 1,
+        Rejected = 2,
+        Expired = 3,
+        UnknownChallenge = 4,
+        Replayed = 5,
+        PuzzleUnsolved = 6,
+        RateLimited = 7,
+        LockedOut = 8,
+    }
+    impl Kind {
+        /// String value of the enum field names used in the ProtoBuf definition.
+        ///
+        /// The values are not transformed in any way and thus are considered stable
+        /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+        pub fn as_str_name(&self) -> &'static str {
+            match self {
+                Self::Unspecified => "KIND_UNSPECIFIED",
+                Self::Accepted => "ACCEPTED",
+                Self::Rejected => "REJECTED",
+                Self::Expired => "EXPIRED",
+                Self::UnknownChallenge => "UNKNOWN_CHALLENGE",
+                Self::Replayed => "REPLAYED",
+                Self::PuzzleUnsolved => "PUZZLE_UNSOLVED",
+                Self::RateLimited => "RATE_LIMITED",
+                Self::LockedOut => "LOCKED_OUT",
+            }
+        }
+        /// Creates an enum from field names used in the ProtoBuf definition.
+        pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+            match value {
+                "KIND_UNSPECIFIED" => Some(Self::Unspecified),
+                "ACCEPTED" => Some(Self::Accepted),
+                "REJECTED" => Some(Self::Rejected),
+                "EXPIRED" => Some(Self::Expired),
+                "UNKNOWN_CHALLENGE" => Some(Self::UnknownChallenge),
+                "REPLAYED" => Some(Self::Replayed),
+                "PUZZLE_UNSOLVED" => Some(Self::PuzzleUnsolved),
+                "RATE_LIMITED" => Some(Self::RateLimited),
+                "LOCKED_OUT" => Some(Self::LockedOut),
+                _ => None,
+            }
+        }
+    }
+}
+/// What the server sends a client to enroll it.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Provisioning {
+    /// otpauth-cr:// URI carrying the secret key
+    #[prost(string, tag = "1")]
+    pub uri: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "2")]
+    pub challenge: ::core::option::Option<Challenge>,
+}
+/// The client's answer confirming an enrollment.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct EnrollmentConfirmation {
+    #[prost(string, tag = "1")]
+    pub user_id: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "2")]
+    pub answer: ::core::option::Option<ChallengeAnswer>,
+}
+/// Asks for a challenge bound to a user.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct IssueChallengeRequest {
+    #[prost(string, tag = "1")]
+    pub user_id: ::prost::alloc::string::String,
+}
+/// A user's answer to a challenge from IssueChallenge.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct VerifyResponseRequest {
+    #[prost(string, tag = "1")]
+    pub user_id: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "2")]
+    pub answer: ::core::option::Option<ChallengeAnswer>,
+}
+/// Starts enrolling a new key for a user.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct EnrollRequest {
+    #[prost(string, tag = "1")]
+    pub user_id: ::prost::alloc::string::String,
+}
+/// Acknowledges a confirmed enrollment.
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct EnrollmentConfirmed {}
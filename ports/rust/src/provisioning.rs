@@ -0,0 +1,595 @@
+//! Provisioning payloads
+//!
+//! A [`ProvisioningPayload`] carries everything a client needs to compute
+//! the same OTPs as the server: the algorithm, the key, the output format
+//! and grouping, the input canonicalization and the policy flags the key
+//! was issued under. Servers hand it out as text or a QR code and clients
+//! turn it back into a [`Passcode`] with
+//! [`to_passcode`](ProvisioningPayload::to_passcode), so neither side has to
+//! agree on the options out of band.
+//!
+//! The KMAC customization string is fixed per algorithm, so the algorithm
+//! ID also determines it.
+//!
+//! The payload is `passcode-provision:` followed by unpadded base64url of
+//!
+//! | Field | Length |
+//! |---|---|
+//! | version, [`PROVISIONING_VERSION`] | 1 byte |
+//! | [`Algorithm::id`] | 1 byte |
+//! | flags: strict policy, weak key allowed, grouping present | 1 byte |
+//! | output format tag, then its parameters | 1 byte + parameters |
+//! | canonicalization: trim, strip separators, fold case, map Unicode | 1 byte |
+//! | grouping size and separator, if present | 1 byte + field |
+//! | key, issuer, account | one field each |
+//!
+//! Each field is a 2-byte big-endian length followed by its bytes; an
+//! empty issuer or account means none. Parsing is strict: unknown versions,
+//! algorithms, formats and flags, trailing bytes and options a [`Passcode`]
+//! would reject all fail, so a client never silently computes different
+//! OTPs than the server.
+//!
+//! # Example
+//! ```
+//! use passcode::provisioning::ProvisioningPayload;
+//! use passcode::{Algorithm, OtpFormat, Passcode};
+//!
+//! // Server
+//! let passcode = Passcode::builder(Algorithm::Sha3Kmac256, vec![7u8; 32])
+//!     .format(OtpFormat::Decimal(8))
+//!     .build()
+//!     .unwrap();
+//! let payload = ProvisioningPayload::from_passcode(&passcode)
+//!     .with_issuer("Example")
+//!     .with_account("alice@example.com")
+//!     .to_string();
+//!
+//! // Client
+//! let parsed: ProvisioningPayload = payload.parse().unwrap();
+//! let client = parsed.to_passcode().unwrap();
+//! assert_eq!(client.compute(b"challenge"), passcode.compute(b"challenge"));
+//! assert_eq!(parsed.account(), Some("alice@example.com"));
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+
+use base64ct::{Base64UrlUnpadded, Encoding};
+
+use crate::format::{Alphabet, CheckDigit, Grouping, OtpFormat};
+use crate::policy::{default_policy, PolicyMode};
+#[cfg(feature = "qr")]
+use crate::qr::QrCode;
+use crate::{Algorithm, Canonicalization, Error, Passcode};
+
+/// Version byte of the payload format
+pub const PROVISIONING_VERSION: u8 = 1;
+
+/// Prefix of an armored payload
+pub const PROVISIONING_PREFIX: &str = "passcode-provision:";
+
+const FLAG_STRICT: u8 = 1;
+const FLAG_WEAK_KEY: u8 = 1 << 1;
+const FLAG_GROUPING: u8 = 1 << 2;
+
+const CANON_TRIM: u8 = 1;
+const CANON_STRIP_SEPARATORS: u8 = 1 << 1;
+const CANON_FOLD_CASE: u8 = 1 << 2;
+const CANON_MAP_UNICODE: u8 = 1 << 3;
+
+/// Everything a client needs to reproduce a server's OTPs
+#[derive(Clone)]
+pub struct ProvisioningPayload {
+    algorithm: Algorithm,
+    key: Vec<u8>,
+    format: OtpFormat,
+    grouping: Option<Grouping>,
+    canonicalization: Canonicalization,
+    policy: PolicyMode,
+    allow_weak_key: bool,
+    issuer: Option<String>,
+    account: Option<String>,
+}
+
+impl fmt::Debug for ProvisioningPayload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Keys are never printed
+        f.debug_struct("ProvisioningPayload")
+            .field("algorithm", &self.algorithm)
+            .field("format", &self.format)
+            .field("grouping", &self.grouping)
+            .field("canonicalization", &self.canonicalization)
+            .field("policy", &self.policy)
+            .field("allow_weak_key", &self.allow_weak_key)
+            .field("issuer", &self.issuer)
+            .field("account", &self.account)
+            .finish()
+    }
+}
+
+impl ProvisioningPayload {
+    /// Captures the algorithm, key and output options of `passcode`
+    ///
+    /// The policy defaults to [`PolicyMode::Permissive`].
+    pub fn from_passcode(passcode: &Passcode) -> Self {
+        Self {
+            algorithm: passcode.algorithm(),
+            key: passcode.key().to_vec(),
+            format: passcode.format().clone(),
+            grouping: passcode.grouping(),
+            canonicalization: passcode.canonicalization(),
+            policy: PolicyMode::Permissive,
+            allow_weak_key: false,
+            issuer: None,
+            account: None,
+        }
+    }
+
+    /// Sets the policy the client enforces on the key
+    pub fn with_policy(mut self, policy: PolicyMode) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Lets the client accept a key shorter than the algorithm's
+    /// recommended length, see [`PasscodeBuilder::allow_weak_key`]
+    ///
+    /// [`PasscodeBuilder::allow_weak_key`]: crate::PasscodeBuilder::allow_weak_key
+    pub fn allow_weak_key(mut self) -> Self {
+        self.allow_weak_key = true;
+        self
+    }
+
+    /// Sets the issuer, shown by the client
+    pub fn with_issuer(mut self, issuer: &str) -> Self {
+        self.issuer = Some(issuer.to_string());
+        self
+    }
+
+    /// Sets the account name, e.g. a user's email address
+    pub fn with_account(mut self, account: &str) -> Self {
+        self.account = Some(account.to_string());
+        self
+    }
+
+    /// Gets the algorithm
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// Gets the output format
+    pub fn format(&self) -> &OtpFormat {
+        &self.format
+    }
+
+    /// Gets the display grouping, if any
+    pub fn grouping(&self) -> Option<Grouping> {
+        self.grouping
+    }
+
+    /// Gets the input canonicalization rules
+    pub fn canonicalization(&self) -> Canonicalization {
+        self.canonicalization
+    }
+
+    /// Gets the policy the client enforces on the key
+    pub fn policy(&self) -> PolicyMode {
+        self.policy
+    }
+
+    /// Returns true if the client accepts a key shorter than recommended
+    pub fn allows_weak_key(&self) -> bool {
+        self.allow_weak_key
+    }
+
+    /// Gets the issuer, if any
+    pub fn issuer(&self) -> Option<&str> {
+        self.issuer.as_deref()
+    }
+
+    /// Gets the account name, if any
+    pub fn account(&self) -> Option<&str> {
+        self.account.as_deref()
+    }
+
+    /// Builds the client's [`Passcode`]
+    ///
+    /// A strict payload is enforced with [`PolicyMode::Strict`]; a
+    /// permissive one falls back to the process-wide
+    /// [`default_policy`], so a payload can tighten the client's policy but
+    /// never relax it.
+    pub fn to_passcode(&self) -> Result<Passcode, Error> {
+        let policy = match self.policy {
+            PolicyMode::Strict => PolicyMode::Strict,
+            PolicyMode::Permissive => default_policy(),
+        };
+        let mut builder = Passcode::builder(self.algorithm, self.key.clone())
+            .format(self.format.clone())
+            .canonicalization(self.canonicalization)
+            .policy(policy);
+        if let Some(grouping) = self.grouping {
+            builder = builder.grouping(grouping);
+        }
+        if self.allow_weak_key {
+            builder = builder.allow_weak_key();
+        }
+        builder.build()
+    }
+
+    /// Encodes the payload as bytes, without the text prefix
+    ///
+    /// # Panics
+    /// Panics if the key, issuer or account is longer than 65535 bytes or
+    /// the group size is above 255.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut flags = 0;
+        if self.policy == PolicyMode::Strict {
+            flags |= FLAG_STRICT;
+        }
+        if self.allow_weak_key {
+            flags |= FLAG_WEAK_KEY;
+        }
+        if self.grouping.is_some() {
+            flags |= FLAG_GROUPING;
+        }
+
+        let mut encoded = vec![PROVISIONING_VERSION, self.algorithm.id(), flags];
+        put_format(&mut encoded, &self.format);
+        encoded.push(canonicalization_bits(self.canonicalization));
+        if let Some(grouping) = &self.grouping {
+            let size = u8::try_from(grouping.size).expect("group sizes are at most 255");
+            encoded.push(size);
+            put_field(&mut encoded, grouping.separator.to_string().as_bytes());
+        }
+        put_field(&mut encoded, &self.key);
+        put_field(
+            &mut encoded,
+            self.issuer.as_deref().unwrap_or("").as_bytes(),
+        );
+        put_field(
+            &mut encoded,
+            self.account.as_deref().unwrap_or("").as_bytes(),
+        );
+        encoded
+    }
+
+    /// Parses bytes written by [`to_bytes`](Self::to_bytes)
+    ///
+    /// Fails with [`Error::MalformedMessage`] for a damaged or unsupported
+    /// payload, and with the error [`PasscodeBuilder::build`] returns for
+    /// options it rejects.
+    ///
+    /// [`PasscodeBuilder::build`]: crate::PasscodeBuilder::build
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let mut reader = Reader { rest: bytes };
+        if reader.byte()? != PROVISIONING_VERSION {
+            return Err(Error::MalformedMessage("unsupported provisioning version"));
+        }
+        let algorithm = Algorithm::from_id(reader.byte()?)
+            .ok_or(Error::MalformedMessage("unknown algorithm"))?;
+        let flags = reader.byte()?;
+        if flags & !(FLAG_STRICT | FLAG_WEAK_KEY | FLAG_GROUPING) != 0 {
+            return Err(Error::MalformedMessage("unknown provisioning flags"));
+        }
+        let format = reader.format()?;
+        let canonicalization = reader.canonicalization()?;
+        let grouping = if flags & FLAG_GROUPING != 0 {
+            let size = usize::from(reader.byte()?);
+            let separator = reader.text()?;
+            let mut chars = separator.chars();
+            match (chars.next(), chars.next()) {
+                (Some(separator), None) => Some(Grouping::new(size, separator)),
+                _ => {
+                    return Err(Error::MalformedMessage(
+                        "group separator must be one character",
+                    ))
+                }
+            }
+        } else {
+            None
+        };
+        let key = reader.field()?.to_vec();
+        let issuer = reader.text()?;
+        let account = reader.text()?;
+        if !reader.rest.is_empty() {
+            return Err(Error::MalformedMessage("trailing bytes"));
+        }
+
+        let payload = Self {
+            algorithm,
+            key,
+            format,
+            grouping,
+            canonicalization,
+            policy: if flags & FLAG_STRICT != 0 {
+                PolicyMode::Strict
+            } else {
+                PolicyMode::Permissive
+            },
+            allow_weak_key: flags & FLAG_WEAK_KEY != 0,
+            issuer: (!issuer.is_empty()).then_some(issuer),
+            account: (!account.is_empty()).then_some(account),
+        };
+        payload.to_passcode()?;
+        Ok(payload)
+    }
+
+    /// Renders the payload as a QR code
+    #[cfg(feature = "qr")]
+    pub fn to_qr(&self) -> Result<QrCode, Error> {
+        QrCode::new(&self.to_string())
+    }
+}
+
+impl fmt::Display for ProvisioningPayload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}",
+            PROVISIONING_PREFIX,
+            Base64UrlUnpadded::encode_string(&self.to_bytes())
+        )
+    }
+}
+
+impl FromStr for ProvisioningPayload {
+    type Err = Error;
+
+    /// Parses a payload written by the [`Display`](fmt::Display)
+    /// implementation, see [`from_bytes`](Self::from_bytes)
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let encoded = s
+            .strip_prefix(PROVISIONING_PREFIX)
+            .ok_or(Error::MalformedMessage(
+                "not a passcode provisioning payload",
+            ))?;
+        let bytes = Base64UrlUnpadded::decode_vec(encoded)
+            .map_err(|_| Error::MalformedMessage("invalid base64url"))?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+/// Appends a field with its two-byte length
+fn put_field(encoded: &mut Vec<u8>, field: &[u8]) {
+    let len = u16::try_from(field.len()).expect("provisioning fields are at most 65535 bytes");
+    encoded.extend_from_slice(&len.to_be_bytes());
+    encoded.extend_from_slice(field);
+}
+
+fn put_format(encoded: &mut Vec<u8>, format: &OtpFormat) {
+    match format {
+        OtpFormat::Hex => encoded.push(0),
+        OtpFormat::HexUpper => encoded.push(1),
+        OtpFormat::Decimal(digits) => encoded.extend_from_slice(&[2, *digits]),
+        OtpFormat::CheckedDecimal { digits, check } => {
+            let check = match check {
+                CheckDigit::Luhn => 0,
+                CheckDigit::Damm => 1,
+            };
+            encoded.extend_from_slice(&[3, *digits, check]);
+        }
+        OtpFormat::Base32 => encoded.push(4),
+        OtpFormat::Base58 => encoded.push(5),
+        OtpFormat::Crockford { check } => encoded.extend_from_slice(&[6, u8::from(*check)]),
+        OtpFormat::Words(count) => encoded.extend_from_slice(&[7, *count]),
+        OtpFormat::Custom(alphabet) => {
+            encoded.push(8);
+            let symbols: String = alphabet.symbols().iter().collect();
+            put_field(encoded, symbols.as_bytes());
+        }
+        #[cfg(feature = "bech32")]
+        OtpFormat::Bech32m { hrp } => {
+            encoded.push(9);
+            put_field(encoded, hrp.as_bytes());
+        }
+    }
+}
+
+fn canonicalization_bits(rules: Canonicalization) -> u8 {
+    [
+        (rules.trim, CANON_TRIM),
+        (rules.strip_separators, CANON_STRIP_SEPARATORS),
+        (rules.fold_case, CANON_FOLD_CASE),
+        (rules.map_unicode, CANON_MAP_UNICODE),
+    ]
+    .into_iter()
+    .filter(|(set, _)| *set)
+    .fold(0, |bits, (_, bit)| bits | bit)
+}
+
+struct Reader<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.rest.len() < len {
+            return Err(Error::MalformedMessage("truncated message"));
+        }
+        let (taken, rest) = self.rest.split_at(len);
+        self.rest = rest;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn flag(&mut self) -> Result<bool, Error> {
+        match self.byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Error::MalformedMessage("invalid boolean")),
+        }
+    }
+
+    fn field(&mut self) -> Result<&'a [u8], Error> {
+        let len = u16::from_be_bytes([self.byte()?, self.byte()?]);
+        self.take(usize::from(len))
+    }
+
+    fn text(&mut self) -> Result<String, Error> {
+        String::from_utf8(self.field()?.to_vec())
+            .map_err(|_| Error::MalformedMessage("text is not UTF-8"))
+    }
+
+    fn format(&mut self) -> Result<OtpFormat, Error> {
+        Ok(match self.byte()? {
+            0 => OtpFormat::Hex,
+            1 => OtpFormat::HexUpper,
+            2 => OtpFormat::Decimal(self.byte()?),
+            3 => OtpFormat::CheckedDecimal {
+                digits: self.byte()?,
+                check: match self.byte()? {
+                    0 => CheckDigit::Luhn,
+                    1 => CheckDigit::Damm,
+                    _ => return Err(Error::MalformedMessage("unknown check digit scheme")),
+                },
+            },
+            4 => OtpFormat::Base32,
+            5 => OtpFormat::Base58,
+            6 => OtpFormat::Crockford {
+                check: self.flag()?,
+            },
+            7 => OtpFormat::Words(self.byte()?),
+            8 => OtpFormat::Custom(Alphabet::new(&self.text()?)?),
+            #[cfg(feature = "bech32")]
+            9 => OtpFormat::Bech32m { hrp: self.text()? },
+            _ => return Err(Error::MalformedMessage("unsupported output format")),
+        })
+    }
+
+    fn canonicalization(&mut self) -> Result<Canonicalization, Error> {
+        let bits = self.byte()?;
+        if bits & !(CANON_TRIM | CANON_STRIP_SEPARATORS | CANON_FOLD_CASE | CANON_MAP_UNICODE) != 0
+        {
+            return Err(Error::MalformedMessage("unknown canonicalization flags"));
+        }
+        Ok(Canonicalization {
+            trim: bits & CANON_TRIM != 0,
+            strip_separators: bits & CANON_STRIP_SEPARATORS != 0,
+            fold_case: bits & CANON_FOLD_CASE != 0,
+            map_unicode: bits & CANON_MAP_UNICODE != 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn passcode(format: OtpFormat) -> Passcode {
+        let builder = Passcode::builder(Algorithm::Sha3Kmac256, vec![7u8; 32]);
+        // Word codes cannot be grouped by characters
+        let builder = match format {
+            OtpFormat::Words(_) => builder,
+            _ => builder.grouping(Grouping::new(4, '-')),
+        };
+        builder.format(format).build().unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut formats = vec![
+            OtpFormat::Hex,
+            OtpFormat::HexUpper,
+            OtpFormat::Decimal(8),
+            OtpFormat::CheckedDecimal {
+                digits: 6,
+                check: CheckDigit::Damm,
+            },
+            OtpFormat::Base32,
+            OtpFormat::Base58,
+            OtpFormat::Crockford { check: true },
+            OtpFormat::Words(4),
+            OtpFormat::Custom(Alphabet::new("23456789BCDFGHJK").unwrap()),
+        ];
+        #[cfg(feature = "bech32")]
+        formats.push(OtpFormat::Bech32m {
+            hrp: "otp".to_string(),
+        });
+
+        for format in formats.drain(..) {
+            let server = passcode(format.clone());
+            let payload = ProvisioningPayload::from_passcode(&server)
+                .with_issuer("Example")
+                .with_account("alice")
+                .to_string();
+            let parsed: ProvisioningPayload = payload.parse().unwrap();
+            assert_eq!(parsed.format(), &format);
+            assert_eq!(parsed.grouping(), server.grouping());
+            assert_eq!(parsed.issuer(), Some("Example"));
+
+            let client = parsed.to_passcode().unwrap();
+            assert_eq!(client.key_id(), server.key_id());
+            assert_eq!(client.compute(b"challenge"), server.compute(b"challenge"));
+            assert_eq!(client.canonicalization(), server.canonicalization());
+        }
+    }
+
+    #[test]
+    fn test_policy_flags() {
+        let weak = Passcode::new(Algorithm::Sha3Kmac256, vec![7u8; 16]);
+        let payload = ProvisioningPayload::from_passcode(&weak);
+        assert!(matches!(
+            payload.to_passcode(),
+            Err(Error::KeyTooShort { .. })
+        ));
+        let payload =
+            ProvisioningPayload::from_bytes(&payload.allow_weak_key().to_bytes()).unwrap();
+        assert!(payload.allows_weak_key());
+        assert!(payload.account().is_none());
+        payload.to_passcode().unwrap();
+
+        // A strict payload is enforced even under the permissive default
+        let blake3 = Passcode::new(Algorithm::Blake3KeyedMode256, vec![7u8; 32]);
+        let strict = ProvisioningPayload::from_passcode(&blake3).with_policy(PolicyMode::Strict);
+        assert_eq!(
+            ProvisioningPayload::from_bytes(&strict.to_bytes()).unwrap_err(),
+            Error::AlgorithmNotApproved("BLAKE3-Keyed-Mode-256")
+        );
+    }
+
+    #[test]
+    fn test_strict_parsing() {
+        let payload = ProvisioningPayload::from_passcode(&passcode(OtpFormat::Decimal(6)));
+        let bytes = payload.to_bytes();
+        assert!(ProvisioningPayload::from_bytes(&bytes).is_ok());
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            ProvisioningPayload::from_bytes(&trailing).unwrap_err(),
+            Error::MalformedMessage("trailing bytes")
+        );
+        assert!(ProvisioningPayload::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+        let mut tampered = bytes.clone();
+        tampered[0] = 2;
+        assert!(ProvisioningPayload::from_bytes(&tampered).is_err());
+        let mut tampered = bytes.clone();
+        tampered[2] |= 1 << 7;
+        assert!(ProvisioningPayload::from_bytes(&tampered).is_err());
+        let mut tampered = bytes.clone();
+        tampered[3] = 0xff;
+        assert!(ProvisioningPayload::from_bytes(&tampered).is_err());
+        // Decimal(6) with 5 digits
+        let mut tampered = bytes;
+        tampered[4] = 5;
+        assert_eq!(
+            ProvisioningPayload::from_bytes(&tampered).unwrap_err(),
+            Error::InvalidDigits(5)
+        );
+
+        assert!("passcode-key:AAAA".parse::<ProvisioningPayload>().is_err());
+        assert!("passcode-provision:!"
+            .parse::<ProvisioningPayload>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_debug_redacts_key() {
+        let payload = ProvisioningPayload::from_passcode(&passcode(OtpFormat::Hex));
+        assert!(!format!("{:?}", payload).contains("7, 7"));
+    }
+}
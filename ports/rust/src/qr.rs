@@ -0,0 +1,183 @@
+//! QR codes for challenges and provisioning payloads
+//!
+//! Enabled with the `qr` feature. A server can show the challenge as a QR
+//! code that the trusted device scans, computes the OTP for, and displays
+//! or sends back. Provisioning payloads such as enrollment URIs can be
+//! rendered the same way with [`QrCode::new`].
+//!
+//! # Example
+//! ```
+//! use passcode::qr::{parse_challenge_payload, QrCode};
+//!
+//! let challenge = [0x42u8; 16];
+//! let qr = QrCode::for_challenge(&challenge).unwrap();
+//! let svg = qr.to_svg();
+//! assert!(svg.starts_with("<?xml"));
+//!
+//! // On the scanning device
+//! let scanned = qr.payload();
+//! assert_eq!(parse_challenge_payload(scanned).unwrap(), challenge);
+//! ```
+
+use qrcode::render::svg;
+use qrcode::{Color, EcLevel};
+
+use crate::format::{base32_decode, base32_encode, BASE32_ALPHABET};
+use crate::Error;
+
+/// Prefix of challenge payloads
+///
+/// The prefix and the base32 body only use characters of the QR
+/// alphanumeric mode, which keeps the symbol small.
+pub const CHALLENGE_PREFIX: &str = "PASSCODE:CHALLENGE:";
+
+/// Width of the light border around the symbol, in modules
+const QUIET_ZONE: usize = 4;
+
+/// A QR code symbol with its payload
+pub struct QrCode {
+    payload: String,
+    code: qrcode::QrCode,
+}
+
+impl QrCode {
+    /// Encodes an arbitrary payload, e.g. a provisioning URI
+    ///
+    /// Uses error-correction level M, which tolerates about 15% damage.
+    pub fn new(payload: &str) -> Result<Self, Error> {
+        let code = qrcode::QrCode::with_error_correction_level(payload, EcLevel::M)
+            .map_err(|e| Error::QrCode(e.to_string()))?;
+        Ok(Self {
+            payload: payload.to_string(),
+            code,
+        })
+    }
+
+    /// Encodes challenge bytes as a [`CHALLENGE_PREFIX`] payload
+    pub fn for_challenge(challenge: &[u8]) -> Result<Self, Error> {
+        Self::new(&challenge_payload(challenge))
+    }
+
+    /// Gets the encoded payload
+    pub fn payload(&self) -> &str {
+        &self.payload
+    }
+
+    /// Gets the number of modules per side, excluding the quiet zone
+    pub fn width(&self) -> usize {
+        self.code.width()
+    }
+
+    /// Renders the symbol as an SVG document
+    pub fn to_svg(&self) -> String {
+        self.code
+            .render::<svg::Color<'_>>()
+            .min_dimensions(200, 200)
+            .quiet_zone(true)
+            .build()
+    }
+
+    /// Renders the symbol as a grayscale PNG with `module_px` pixels per module
+    pub fn to_png(&self, module_px: u32) -> Result<Vec<u8>, Error> {
+        if module_px == 0 {
+            return Err(Error::QrCode(
+                "module size must be greater than zero".to_string(),
+            ));
+        }
+
+        let scale = module_px as usize;
+        let modules = self.code.width() + 2 * QUIET_ZONE;
+        let side = modules * scale;
+        let colors = self.code.to_colors();
+
+        let mut pixels = vec![0xffu8; side * side];
+        for (i, color) in colors.iter().enumerate() {
+            if *color != Color::Dark {
+                continue;
+            }
+            let x = (i % self.code.width() + QUIET_ZONE) * scale;
+            let y = (i / self.code.width() + QUIET_ZONE) * scale;
+            for row in y..y + scale {
+                pixels[row * side + x..row * side + x + scale].fill(0);
+            }
+        }
+
+        let mut out = Vec::new();
+        let mut encoder = png::Encoder::new(&mut out, side as u32, side as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder
+            .write_header()
+            .map_err(|e| Error::QrCode(e.to_string()))?;
+        writer
+            .write_image_data(&pixels)
+            .map_err(|e| Error::QrCode(e.to_string()))?;
+        writer.finish().map_err(|e| Error::QrCode(e.to_string()))?;
+
+        Ok(out)
+    }
+}
+
+/// Builds the text payload for challenge bytes
+pub fn challenge_payload(challenge: &[u8]) -> String {
+    format!(
+        "{}{}",
+        CHALLENGE_PREFIX,
+        base32_encode(challenge, BASE32_ALPHABET)
+    )
+}
+
+/// Extracts the challenge bytes from a scanned payload
+///
+/// Returns `None` if the payload is not a challenge payload.
+pub fn parse_challenge_payload(payload: &str) -> Option<Vec<u8>> {
+    let body = payload.strip_prefix(CHALLENGE_PREFIX)?;
+    base32_decode(body, BASE32_ALPHABET)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_challenge_payload_round_trip() {
+        let challenge: Vec<u8> = (0u8..32).collect();
+        let payload = challenge_payload(&challenge);
+
+        assert!(payload.starts_with(CHALLENGE_PREFIX));
+        assert_eq!(parse_challenge_payload(&payload).unwrap(), challenge);
+        assert_eq!(parse_challenge_payload("otpauth://totp/x"), None);
+        assert_eq!(parse_challenge_payload("PASSCODE:CHALLENGE:abc"), None);
+    }
+
+    #[test]
+    fn test_png_rendering() {
+        let qr = QrCode::for_challenge(&[7u8; 16]).unwrap();
+        let png = qr.to_png(4).unwrap();
+
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        let side = ((qr.width() + 2 * QUIET_ZONE) * 4) as u32;
+        // IHDR width and height follow the signature and chunk header
+        assert_eq!(&png[16..20], side.to_be_bytes());
+        assert_eq!(&png[20..24], side.to_be_bytes());
+
+        assert!(qr.to_png(0).is_err());
+    }
+
+    #[test]
+    fn test_svg_rendering() {
+        let qr = QrCode::new("otpauth://totp/Example:alice?secret=JBSWY3DPEHPK3PXP").unwrap();
+        let svg = qr.to_svg();
+
+        assert!(svg.contains("<svg"));
+        assert_eq!(
+            qr.payload(),
+            "otpauth://totp/Example:alice?secret=JBSWY3DPEHPK3PXP"
+        );
+    }
+
+    #[test]
+    fn test_payload_too_large() {
+        assert!(QrCode::new(&"x".repeat(5000)).is_err());
+    }
+}
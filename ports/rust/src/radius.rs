@@ -0,0 +1,420 @@
+//! RADIUS attribute encoding (feature `radius`)
+//!
+//! VPN concentrators and Wi-Fi controllers ask a RADIUS server for the
+//! OTP. With these helpers the server answers a first `Access-Request` with
+//! an `Access-Challenge` carrying the challenge, and checks the OTP the
+//! client sends back in the next `Access-Request` (RFC 2865 §4.4):
+//!
+//! - `State` holds the challenge ID, which the NAS echoes unchanged.
+//! - `Reply-Message` shows the prompt and [`Challenge::message`] in
+//!   unpadded base64url, split over several attributes when longer than
+//!   [`MAX_VALUE_LEN`].
+//! - `User-Password` carries the OTP, hidden with the shared secret and the
+//!   Request Authenticator (RFC 2865 §5.2).
+//!
+//! The helpers write and read the attribute section of a packet only. The
+//! packet header, identifiers and the Response Authenticator stay with the
+//! RADIUS server implementation. Malformed attributes are rejected with
+//! [`Error::MalformedMessage`].
+//!
+//! # Example
+//! ```
+//! use passcode::challenge::ChallengeManager;
+//! use passcode::radius::{hide_user_password, AccessChallenge, AccessRequest};
+//! use passcode::{Algorithm, Passcode};
+//!
+//! # pollster::block_on(async {
+//! let key = vec![0u8; 32];
+//! let server = ChallengeManager::new(Passcode::new(Algorithm::Sha3Kmac256, key.clone()));
+//! let challenge = server.issue().await.unwrap();
+//! let attributes = AccessChallenge::new(&challenge).to_attributes();
+//!
+//! // The NAS echoes the State attribute with the user's OTP
+//! let otp = Passcode::new(Algorithm::Sha3Kmac256, key).compute(&challenge.message());
+//! let (secret, authenticator) = (b"radius secret", [7u8; 16]);
+//! let mut request = attributes[..18].to_vec();
+//! request.extend_from_slice(&[2, 18]);
+//! request.extend_from_slice(&hide_user_password(otp.as_bytes(), &authenticator, secret).unwrap());
+//!
+//! // Server
+//! let request = AccessRequest::parse(&request, &authenticator, secret).unwrap();
+//! assert!(server
+//!     .verify(request.challenge_id().unwrap(), request.response())
+//!     .await
+//!     .is_ok());
+//! # });
+//! ```
+
+use base64ct::{Base64UrlUnpadded, Encoding};
+use md5::{Digest, Md5};
+
+use crate::challenge::{Challenge, ChallengeId, CHALLENGE_ID_LEN};
+use crate::Error;
+
+/// `User-Name` attribute type
+pub const USER_NAME: u8 = 1;
+
+/// `User-Password` attribute type
+pub const USER_PASSWORD: u8 = 2;
+
+/// `Reply-Message` attribute type
+pub const REPLY_MESSAGE: u8 = 18;
+
+/// `State` attribute type
+pub const STATE: u8 = 24;
+
+/// Longest value of one attribute, in bytes
+pub const MAX_VALUE_LEN: usize = 253;
+
+/// Length of the Request Authenticator
+pub const AUTHENTICATOR_LEN: usize = 16;
+
+/// Longest hidden `User-Password` value (RFC 2865 §5.2)
+const MAX_PASSWORD_LEN: usize = 128;
+
+/// Prompt shown before the challenge unless configured otherwise
+const DEFAULT_PROMPT: &str = "Passcode challenge:";
+
+/// Attributes of an `Access-Challenge` asking for an OTP
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessChallenge {
+    challenge_id: ChallengeId,
+    message: Vec<u8>,
+    prompt: String,
+}
+
+impl AccessChallenge {
+    /// Describes an issued challenge
+    pub fn new(challenge: &Challenge) -> Self {
+        Self {
+            challenge_id: *challenge.id(),
+            message: challenge.message(),
+            prompt: DEFAULT_PROMPT.to_string(),
+        }
+    }
+
+    /// Sets the text shown to the user before the challenge
+    pub fn with_prompt(mut self, prompt: impl Into<String>) -> Self {
+        self.prompt = prompt.into();
+        self
+    }
+
+    /// Gets the text of the `Reply-Message` attributes
+    pub fn reply_message(&self) -> String {
+        format!(
+            "{} {}",
+            self.prompt,
+            Base64UrlUnpadded::encode_string(&self.message)
+        )
+    }
+
+    /// Encodes the `State` attribute followed by the `Reply-Message`
+    /// attributes
+    pub fn to_attributes(&self) -> Vec<u8> {
+        let mut attributes = Vec::new();
+        put_attribute(&mut attributes, STATE, self.challenge_id.as_bytes());
+
+        let reply = self.reply_message();
+        let mut rest = reply.as_str();
+        while !rest.is_empty() {
+            let mut end = rest.len().min(MAX_VALUE_LEN);
+            while !rest.is_char_boundary(end) {
+                end -= 1;
+            }
+            let (value, tail) = rest.split_at(end);
+            put_attribute(&mut attributes, REPLY_MESSAGE, value.as_bytes());
+            rest = tail;
+        }
+        attributes
+    }
+}
+
+/// Credentials of an `Access-Request`
+///
+/// A request without `State` starts an authentication; answer it with an
+/// [`AccessChallenge`]. A request with `State` answers the challenge it
+/// names.
+#[derive(Clone, PartialEq, Eq)]
+pub struct AccessRequest {
+    user_name: Option<String>,
+    challenge_id: Option<ChallengeId>,
+    response: String,
+}
+
+impl AccessRequest {
+    /// Parses the attribute section of an `Access-Request`, revealing
+    /// `User-Password` with the packet's Request Authenticator and the
+    /// shared secret
+    ///
+    /// Attributes other than `User-Name`, `User-Password` and `State` are
+    /// skipped. Fails with [`Error::MalformedMessage`] when an attribute is
+    /// truncated or repeated, `User-Password` is missing or malformed, or
+    /// `State` is not a challenge ID.
+    pub fn parse(
+        attributes: &[u8],
+        authenticator: &[u8; AUTHENTICATOR_LEN],
+        secret: &[u8],
+    ) -> Result<Self, Error> {
+        let mut user_name = None;
+        let mut challenge_id = None;
+        let mut response = None;
+
+        let mut rest = attributes;
+        while !rest.is_empty() {
+            let (kind, value, tail) = take_attribute(rest)?;
+            rest = tail;
+            match kind {
+                USER_NAME => {
+                    let name = std::str::from_utf8(value)
+                        .map_err(|_| Error::MalformedMessage("User-Name is not UTF-8"))?;
+                    set_once(&mut user_name, name.to_string())?;
+                }
+                USER_PASSWORD => {
+                    let password = reveal_user_password(value, authenticator, secret)?;
+                    let password = String::from_utf8(password)
+                        .map_err(|_| Error::MalformedMessage("User-Password is not UTF-8"))?;
+                    set_once(&mut response, password)?;
+                }
+                STATE => {
+                    let bytes: [u8; CHALLENGE_ID_LEN] = value
+                        .try_into()
+                        .map_err(|_| Error::MalformedMessage("State is not a challenge ID"))?;
+                    set_once(&mut challenge_id, ChallengeId::from_bytes(bytes))?;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            user_name,
+            challenge_id,
+            response: response.ok_or(Error::MalformedMessage("User-Password is missing"))?,
+        })
+    }
+
+    /// Gets the `User-Name`, if sent
+    pub fn user_name(&self) -> Option<&str> {
+        self.user_name.as_deref()
+    }
+
+    /// Gets the challenge ID from `State`, or `None` for a first request
+    pub fn challenge_id(&self) -> Option<&ChallengeId> {
+        self.challenge_id.as_ref()
+    }
+
+    /// Gets the revealed `User-Password`, the OTP when answering a challenge
+    pub fn response(&self) -> &str {
+        &self.response
+    }
+}
+
+impl std::fmt::Debug for AccessRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AccessRequest")
+            .field("user_name", &self.user_name)
+            .field("challenge_id", &self.challenge_id)
+            .field("response", &"<redacted>")
+            .finish()
+    }
+}
+
+/// Hides a `User-Password` value with the Request Authenticator and the
+/// shared secret (RFC 2865 §5.2), as a NAS does
+///
+/// Fails with [`Error::MalformedMessage`] when the password is empty or
+/// longer than 128 bytes.
+pub fn hide_user_password(
+    password: &[u8],
+    authenticator: &[u8; AUTHENTICATOR_LEN],
+    secret: &[u8],
+) -> Result<Vec<u8>, Error> {
+    if password.is_empty() || password.len() > MAX_PASSWORD_LEN {
+        return Err(Error::MalformedMessage(
+            "User-Password must be 1 to 128 bytes",
+        ));
+    }
+    let mut hidden = password.to_vec();
+    hidden.resize(password.len().div_ceil(16) * 16, 0);
+
+    let mut previous = authenticator.to_vec();
+    for block in hidden.chunks_mut(16) {
+        let pad = Md5::new()
+            .chain_update(secret)
+            .chain_update(&previous)
+            .finalize();
+        block
+            .iter_mut()
+            .zip(pad)
+            .for_each(|(byte, pad)| *byte ^= pad);
+        previous = block.to_vec();
+    }
+    Ok(hidden)
+}
+
+/// Reveals a hidden `User-Password` value, dropping the zero padding
+///
+/// Fails with [`Error::MalformedMessage`] when the value is not a multiple
+/// of 16 bytes between 16 and 128.
+pub fn reveal_user_password(
+    hidden: &[u8],
+    authenticator: &[u8; AUTHENTICATOR_LEN],
+    secret: &[u8],
+) -> Result<Vec<u8>, Error> {
+    if hidden.is_empty() || hidden.len() > MAX_PASSWORD_LEN || !hidden.len().is_multiple_of(16) {
+        return Err(Error::MalformedMessage("malformed User-Password"));
+    }
+    let mut password = Vec::with_capacity(hidden.len());
+    let mut previous: &[u8] = authenticator;
+    for block in hidden.chunks(16) {
+        let pad = Md5::new()
+            .chain_update(secret)
+            .chain_update(previous)
+            .finalize();
+        password.extend(block.iter().zip(pad).map(|(byte, pad)| byte ^ pad));
+        previous = block;
+    }
+    while password.last() == Some(&0) {
+        password.pop();
+    }
+    Ok(password)
+}
+
+fn put_attribute(attributes: &mut Vec<u8>, kind: u8, value: &[u8]) {
+    attributes.push(kind);
+    attributes.push((value.len() + 2) as u8);
+    attributes.extend_from_slice(value);
+}
+
+fn take_attribute(bytes: &[u8]) -> Result<(u8, &[u8], &[u8]), Error> {
+    let [kind, len, ..] = *bytes else {
+        return Err(Error::MalformedMessage("truncated attribute"));
+    };
+    let len = usize::from(len);
+    if len < 2 || len > bytes.len() {
+        return Err(Error::MalformedMessage("truncated attribute"));
+    }
+    Ok((kind, &bytes[2..len], &bytes[len..]))
+}
+
+fn set_once<T>(slot: &mut Option<T>, value: T) -> Result<(), Error> {
+    if slot.is_some() {
+        return Err(Error::MalformedMessage("repeated attribute"));
+    }
+    *slot = Some(value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"xyzzy5461";
+    const AUTHENTICATOR: [u8; 16] = [
+        0x0f, 0x40, 0x3f, 0x94, 0x73, 0x97, 0x80, 0x57, 0xbd, 0x83, 0xd5, 0xcb, 0x98, 0xf4, 0x22,
+        0x7a,
+    ];
+
+    #[test]
+    fn test_user_password_rfc2865_example() {
+        // RFC 2865 §7.1: "arctangent" sent by nemo
+        let hidden = hide_user_password(b"arctangent", &AUTHENTICATOR, SECRET).unwrap();
+        assert_eq!(hex::encode(&hidden), "0dbe708d93d413ce3196e43f782a0aee");
+        assert_eq!(
+            reveal_user_password(&hidden, &AUTHENTICATOR, SECRET).unwrap(),
+            b"arctangent"
+        );
+
+        let long = [b'a'; 40];
+        let hidden = hide_user_password(&long, &AUTHENTICATOR, SECRET).unwrap();
+        assert_eq!(hidden.len(), 48);
+        assert_eq!(
+            reveal_user_password(&hidden, &AUTHENTICATOR, SECRET).unwrap(),
+            long
+        );
+        assert!(hide_user_password(&[b'a'; 129], &AUTHENTICATOR, SECRET).is_err());
+        assert!(reveal_user_password(&hidden[..20], &AUTHENTICATOR, SECRET).is_err());
+    }
+
+    fn request(attributes: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for (kind, value) in attributes {
+            put_attribute(&mut bytes, *kind, value);
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_parse_access_request() {
+        let password = hide_user_password(b"123456", &AUTHENTICATOR, SECRET).unwrap();
+        let first = request(&[
+            (USER_NAME, b"nemo"),
+            (4, &[192, 168, 1, 16]),
+            (USER_PASSWORD, &password),
+        ]);
+        let parsed = AccessRequest::parse(&first, &AUTHENTICATOR, SECRET).unwrap();
+        assert_eq!(parsed.user_name(), Some("nemo"));
+        assert_eq!(parsed.challenge_id(), None);
+        assert_eq!(parsed.response(), "123456");
+        assert!(!format!("{:?}", parsed).contains("123456"));
+
+        let id = [9u8; CHALLENGE_ID_LEN];
+        let answer = request(&[(STATE, &id), (USER_PASSWORD, &password)]);
+        let parsed = AccessRequest::parse(&answer, &AUTHENTICATOR, SECRET).unwrap();
+        assert_eq!(parsed.challenge_id(), Some(&ChallengeId::from_bytes(id)));
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed() {
+        let password = hide_user_password(b"123456", &AUTHENTICATOR, SECRET).unwrap();
+        let parse = |attributes: &[u8]| AccessRequest::parse(attributes, &AUTHENTICATOR, SECRET);
+
+        assert_eq!(
+            parse(&request(&[(USER_NAME, b"nemo")])),
+            Err(Error::MalformedMessage("User-Password is missing"))
+        );
+        assert_eq!(
+            parse(&request(&[
+                (USER_PASSWORD, &password),
+                (USER_PASSWORD, &password)
+            ])),
+            Err(Error::MalformedMessage("repeated attribute"))
+        );
+        assert_eq!(
+            parse(&request(&[(STATE, b"short"), (USER_PASSWORD, &password)])),
+            Err(Error::MalformedMessage("State is not a challenge ID"))
+        );
+        let mut truncated = request(&[(USER_PASSWORD, &password)]);
+        truncated.pop();
+        assert_eq!(
+            parse(&truncated),
+            Err(Error::MalformedMessage("truncated attribute"))
+        );
+        assert_eq!(
+            parse(&[USER_NAME, 1]),
+            Err(Error::MalformedMessage("truncated attribute"))
+        );
+    }
+
+    #[test]
+    fn test_access_challenge_attributes() {
+        let challenge =
+            crate::Passcode::new(crate::Algorithm::Sha3Kmac256, vec![1u8; 32]).issue_challenge();
+        let access_challenge = AccessChallenge::new(&challenge).with_prompt("é".repeat(150));
+        let attributes = access_challenge.to_attributes();
+
+        let (kind, state, mut rest) = take_attribute(&attributes).unwrap();
+        assert_eq!((kind, state), (STATE, challenge.id().as_bytes().as_slice()));
+        let mut reply = Vec::new();
+        while !rest.is_empty() {
+            let (kind, value, tail) = take_attribute(rest).unwrap();
+            assert_eq!(kind, REPLY_MESSAGE);
+            assert!(std::str::from_utf8(value).is_ok());
+            reply.extend_from_slice(value);
+            rest = tail;
+        }
+        assert_eq!(reply, access_challenge.reply_message().into_bytes());
+        assert!(access_challenge
+            .reply_message()
+            .ends_with(&Base64UrlUnpadded::encode_string(&challenge.message())));
+    }
+}
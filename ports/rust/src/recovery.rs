@@ -0,0 +1,262 @@
+//! Single-use recovery codes
+//!
+//! Recovery codes let a user sign in after losing their device. Each code
+//! is shown once at generation and only a keyed digest is kept, so a leaked
+//! [`RecoveryStore`] reveals no usable code without the hashing key. The
+//! store marks a code used atomically, so it is accepted at most once even
+//! when two requests race.
+//!
+//! # Example
+//! ```
+//! use passcode::recovery::RecoveryCodes;
+//!
+//! # pollster::block_on(async {
+//! let codes = RecoveryCodes::new(vec![9u8; 32]);
+//! let issued = codes.generate("alice", 10).await.unwrap();
+//! assert_eq!(issued.len(), 10);
+//!
+//! assert_eq!(codes.verify("alice", &issued[0]).await, Ok(true));
+//! assert_eq!(codes.verify("alice", &issued[0]).await, Ok(false));
+//! assert_eq!(codes.remaining("alice").await, Ok(9));
+//! # });
+//! ```
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Mutex, MutexGuard};
+
+use subtle::ConstantTimeEq;
+
+use crate::format::{OtpFormat, CROCKFORD_ALPHABET};
+use crate::kdf::{derive_subkey, labels};
+use crate::rng::{CryptoRngCore, SharedRng};
+use crate::Error;
+
+/// Default number of codes handed to a user
+pub const DEFAULT_RECOVERY_CODES: usize = 10;
+
+/// Number of Crockford base32 characters in a code, 50 bits in total
+pub const RECOVERY_CODE_LEN: usize = 10;
+
+/// Length in bytes of a stored code digest
+pub const DIGEST_LEN: usize = 32;
+
+/// Digest of a recovery code as kept at rest
+pub type CodeDigest = [u8; DIGEST_LEN];
+
+/// Storage for the digests of each account's recovery codes
+///
+/// Used codes stay in the store as a ledger until the account's codes are
+/// replaced. [`consume`](Self::consume) must test and mark atomically: when
+/// two calls race for the same code, at most one may return true.
+pub trait RecoveryStore: Send + Sync {
+    /// Replaces all of the account's codes, used or not, with new unused
+    /// ones
+    fn replace(
+        &self,
+        account: &str,
+        digests: Vec<CodeDigest>,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Marks the code as used if the account has it unused, returning
+    /// whether it did
+    fn consume(
+        &self,
+        account: &str,
+        digest: &CodeDigest,
+    ) -> impl Future<Output = Result<bool, Error>> + Send;
+
+    /// Returns how many unused codes the account has
+    fn remaining(&self, account: &str) -> impl Future<Output = Result<usize, Error>> + Send;
+}
+
+/// In-memory [`RecoveryStore`]; codes are lost when the process exits
+#[derive(Debug, Default)]
+pub struct MemoryRecoveryStore {
+    accounts: Mutex<HashMap<String, Vec<(CodeDigest, bool)>>>,
+}
+
+impl MemoryRecoveryStore {
+    /// Creates an empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Vec<(CodeDigest, bool)>>> {
+        // A panic while holding the lock cannot leave the map inconsistent
+        self.accounts
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl RecoveryStore for MemoryRecoveryStore {
+    async fn replace(&self, account: &str, digests: Vec<CodeDigest>) -> Result<(), Error> {
+        let codes = digests.into_iter().map(|digest| (digest, false)).collect();
+        self.lock().insert(account.to_string(), codes);
+        Ok(())
+    }
+
+    async fn consume(&self, account: &str, digest: &CodeDigest) -> Result<bool, Error> {
+        let mut accounts = self.lock();
+        let Some(codes) = accounts.get_mut(account) else {
+            return Ok(false);
+        };
+        let mut consumed = false;
+        for (stored, used) in codes.iter_mut() {
+            // Compare every entry so timing does not reveal the position
+            let matches = bool::from(stored.ct_eq(digest)) && !*used;
+            *used |= matches;
+            consumed |= matches;
+        }
+        Ok(consumed)
+    }
+
+    async fn remaining(&self, account: &str) -> Result<usize, Error> {
+        Ok(self
+            .lock()
+            .get(account)
+            .map_or(0, |codes| codes.iter().filter(|(_, used)| !used).count()))
+    }
+}
+
+/// Generates and verifies recovery codes
+///
+/// Codes are ten Crockford base32 characters shown as `XXXXX-XXXXX`.
+/// Verification ignores case and hyphens and maps the commonly confused
+/// `O`, `I` and `L` to digits. Digests are KMAC256 over the account and the
+/// code under the hashing key, which should be kept apart from the store,
+/// e.g. derived from the server's master key.
+pub struct RecoveryCodes<S = MemoryRecoveryStore> {
+    key: Vec<u8>,
+    store: S,
+    rng: SharedRng,
+}
+
+impl RecoveryCodes<MemoryRecoveryStore> {
+    /// Creates a generator keeping digests in a [`MemoryRecoveryStore`]
+    pub fn new(key: Vec<u8>) -> Self {
+        Self::with_store(key, MemoryRecoveryStore::new())
+    }
+}
+
+impl<S: RecoveryStore> RecoveryCodes<S> {
+    /// Creates a generator keeping digests in the given store
+    pub fn with_store(key: Vec<u8>, store: S) -> Self {
+        Self {
+            key,
+            store,
+            rng: SharedRng::os(),
+        }
+    }
+
+    /// Draws codes from `rng` instead of the operating system's CSPRNG
+    pub fn with_rng(mut self, rng: impl CryptoRngCore + Send + 'static) -> Self {
+        self.rng = SharedRng::new(rng);
+        self
+    }
+
+    /// Gets the underlying store
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Generates `n` new codes for the account, replacing any it had
+    ///
+    /// The codes are returned for display once; only their digests are
+    /// stored.
+    pub async fn generate(&self, account: &str, n: usize) -> Result<Vec<String>, Error> {
+        let mut random = vec![0u8; n * RECOVERY_CODE_LEN];
+        self.rng.fill(&mut random)?;
+
+        let codes: Vec<String> = random
+            .chunks_exact(RECOVERY_CODE_LEN)
+            .map(|chunk| {
+                let code: String = chunk
+                    .iter()
+                    .map(|&b| CROCKFORD_ALPHABET[usize::from(b & 0x1f)] as char)
+                    .collect();
+                format!("{}-{}", &code[..5], &code[5..])
+            })
+            .collect();
+        let digests = codes
+            .iter()
+            .map(|code| self.digest(account, &normalize(code)))
+            .collect();
+        self.store.replace(account, digests).await?;
+        Ok(codes)
+    }
+
+    /// Checks a code for the account and marks it used
+    ///
+    /// Returns true at most once per code. Malformed input is rejected
+    /// without consulting the store.
+    pub async fn verify(&self, account: &str, code: &str) -> Result<bool, Error> {
+        let code = normalize(code);
+        let valid = code.len() == RECOVERY_CODE_LEN
+            && code.bytes().all(|c| CROCKFORD_ALPHABET.contains(&c));
+        if !valid {
+            return Ok(false);
+        }
+        self.store
+            .consume(account, &self.digest(account, &code))
+            .await
+    }
+
+    /// Returns how many unused codes the account has
+    pub async fn remaining(&self, account: &str) -> Result<usize, Error> {
+        self.store.remaining(account).await
+    }
+
+    fn digest(&self, account: &str, code: &str) -> CodeDigest {
+        let mut context = Vec::with_capacity(4 + account.len() + code.len());
+        context.extend_from_slice(&(account.len() as u32).to_be_bytes());
+        context.extend_from_slice(account.as_bytes());
+        context.extend_from_slice(code.as_bytes());
+        derive_subkey(&self.key, labels::RECOVERY_CODE, &context, DIGEST_LEN)
+            .try_into()
+            .expect("derived DIGEST_LEN bytes")
+    }
+}
+
+/// Applies the Crockford input rules and drops surrounding whitespace
+fn normalize(code: &str) -> String {
+    OtpFormat::Crockford { check: false }.normalize(code.trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pollster::block_on;
+
+    #[test]
+    fn test_generate_and_consume() {
+        let codes = RecoveryCodes::new(vec![1u8; 32]);
+        let issued = block_on(codes.generate("alice", DEFAULT_RECOVERY_CODES)).unwrap();
+        assert_eq!(issued.len(), DEFAULT_RECOVERY_CODES);
+        assert!(issued
+            .iter()
+            .all(|code| code.len() == 11 && &code[5..6] == "-"));
+
+        let sloppy = issued[3].to_lowercase().replace('-', "").replace('0', "o");
+        assert_eq!(block_on(codes.verify("bob", &sloppy)), Ok(false));
+        assert_eq!(block_on(codes.verify("alice", &sloppy)), Ok(true));
+        assert_eq!(block_on(codes.verify("alice", &issued[3])), Ok(false));
+        assert_eq!(block_on(codes.verify("alice", "not a code")), Ok(false));
+        assert_eq!(block_on(codes.remaining("alice")), Ok(9));
+
+        // Regenerating invalidates the old codes
+        block_on(codes.generate("alice", 2)).unwrap();
+        assert_eq!(block_on(codes.verify("alice", &issued[0])), Ok(false));
+        assert_eq!(block_on(codes.remaining("alice")), Ok(2));
+    }
+
+    #[test]
+    fn test_digest_depends_on_key() {
+        let codes = RecoveryCodes::new(vec![1u8; 32]);
+        let issued = block_on(codes.generate("alice", 1)).unwrap();
+
+        let other = RecoveryCodes::with_store(vec![2u8; 32], codes.store);
+        assert_eq!(block_on(other.verify("alice", &issued[0])), Ok(false));
+    }
+}
@@ -0,0 +1,236 @@
+//! Sources of randomness
+//!
+//! Challenges, nonces, enrollment keys and recovery codes draw their random
+//! bytes from a [`SharedRng`]. Components that generate randomness take any
+//! [`CryptoRngCore`] through a `with_rng` builder, so an HSM, a hardware
+//! TRNG on an embedded target or a fuzzing harness can supply the bytes.
+//! [`OsRng`], the operating system's CSPRNG, is the default and just one
+//! such source. With the `test-util` feature, [`SeededRng`] provides a
+//! deterministic generator so integration tests can replay a run exactly.
+//!
+//! A source only has to implement [`RngCore`] and the [`CryptoRng`] marker;
+//! failures reported by `try_fill_bytes` surface as
+//! [`Error::RandomSource`] instead of panicking.
+//!
+//! # Example
+//! ```
+//! # #[cfg(feature = "test-util")]
+//! # {
+//! use passcode::challenge::ChallengeManager;
+//! use passcode::rng::SeededRng;
+//! use passcode::{Algorithm, Passcode};
+//!
+//! # pollster::block_on(async {
+//! let manager = |seed| {
+//!     ChallengeManager::new(Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]))
+//!         .with_rng(SeededRng::new(seed))
+//! };
+//! let first = manager(7).issue().await.unwrap();
+//! let second = manager(7).issue().await.unwrap();
+//! assert_eq!(first.bytes(), second.bytes());
+//! # });
+//! # }
+//! ```
+
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+pub use rand_core::{CryptoRng, CryptoRngCore, OsRng, RngCore};
+
+use crate::Error;
+
+type Generator = dyn CryptoRngCore + Send + 'static;
+
+/// Cloneable handle to a random number generator
+///
+/// Clones share the generator, so several components handed clones of one
+/// seeded generator draw from a single deterministic stream.
+#[derive(Clone)]
+pub struct SharedRng {
+    source: Source,
+}
+
+#[derive(Clone)]
+enum Source {
+    // OsRng is stateless, so it needs no lock
+    Os,
+    Custom(Arc<Mutex<Generator>>),
+}
+
+impl Default for SharedRng {
+    fn default() -> Self {
+        Self::os()
+    }
+}
+
+impl fmt::Debug for SharedRng {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedRng")
+            .field("os", &matches!(self.source, Source::Os))
+            .finish()
+    }
+}
+
+impl SharedRng {
+    /// Uses [`OsRng`], the operating system's CSPRNG
+    pub fn os() -> Self {
+        Self { source: Source::Os }
+    }
+
+    /// Uses the given generator
+    pub fn new(rng: impl CryptoRngCore + Send + 'static) -> Self {
+        Self {
+            source: Source::Custom(Arc::new(Mutex::new(rng))),
+        }
+    }
+
+    /// Fills the buffer with random bytes
+    ///
+    /// Fails with [`Error::RandomSource`] when the generator fails.
+    pub fn fill(&self, buf: &mut [u8]) -> Result<(), Error> {
+        let filled = match &self.source {
+            Source::Os => OsRng.try_fill_bytes(buf),
+            Source::Custom(rng) => lock(rng).try_fill_bytes(buf),
+        };
+        filled.map_err(|e| Error::RandomSource(e.to_string()))
+    }
+}
+
+impl RngCore for SharedRng {
+    fn next_u32(&mut self) -> u32 {
+        let mut bytes = [0u8; 4];
+        self.fill_bytes(&mut bytes);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut bytes = [0u8; 8];
+        self.fill_bytes(&mut bytes);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.fill(dest).expect("random number generator failed");
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        match &mut self.source {
+            Source::Os => OsRng.try_fill_bytes(dest),
+            Source::Custom(rng) => lock(rng).try_fill_bytes(dest),
+        }
+    }
+}
+
+// Only generators implementing CryptoRng can be wrapped
+impl CryptoRng for SharedRng {}
+
+fn lock<'a>(rng: &'a Mutex<Generator>) -> MutexGuard<'a, Generator> {
+    // A panic while holding the lock cannot leave the generator unusable
+    rng.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Deterministic generator for tests (feature `test-util`)
+///
+/// ChaCha20 keyed by the seed: the same seed always yields the same bytes.
+/// Never use it outside tests; anyone who knows the seed can predict every
+/// challenge and key.
+#[cfg(feature = "test-util")]
+#[derive(Debug, Clone)]
+pub struct SeededRng(rand_chacha::ChaCha20Rng);
+
+#[cfg(feature = "test-util")]
+impl SeededRng {
+    /// Creates a generator from a short seed
+    pub fn new(seed: u64) -> Self {
+        use rand_core::SeedableRng;
+        Self(rand_chacha::ChaCha20Rng::seed_from_u64(seed))
+    }
+
+    /// Creates a generator from a full 32-byte seed
+    pub fn from_seed(seed: [u8; 32]) -> Self {
+        use rand_core::SeedableRng;
+        Self(rand_chacha::ChaCha20Rng::from_seed(seed))
+    }
+}
+
+#[cfg(feature = "test-util")]
+impl RngCore for SeededRng {
+    fn next_u32(&mut self) -> u32 {
+        self.0.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.0.fill_bytes(dest);
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.0.try_fill_bytes(dest)
+    }
+}
+
+#[cfg(feature = "test-util")]
+impl CryptoRng for SeededRng {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Counts up from zero; predictable, for tests only
+    struct Counter(u8);
+
+    impl RngCore for Counter {
+        fn next_u32(&mut self) -> u32 {
+            rand_core::impls::next_u32_via_fill(self)
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            rand_core::impls::next_u64_via_fill(self)
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            for byte in dest {
+                *byte = self.0;
+                self.0 = self.0.wrapping_add(1);
+            }
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    impl CryptoRng for Counter {}
+
+    #[test]
+    fn test_clones_share_the_generator() {
+        let rng = SharedRng::new(Counter(0));
+        let mut clone = rng.clone();
+
+        let mut buf = [0u8; 3];
+        rng.fill(&mut buf).unwrap();
+        assert_eq!(buf, [0, 1, 2]);
+        clone.fill_bytes(&mut buf);
+        assert_eq!(buf, [3, 4, 5]);
+    }
+
+    #[test]
+    fn test_os_fills() {
+        let mut buf = [0u8; 32];
+        SharedRng::os().fill(&mut buf).unwrap();
+        assert_ne!(buf, [0u8; 32]);
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_seeded_is_deterministic() {
+        let mut a = SeededRng::new(1);
+        let mut b = SeededRng::new(1);
+        assert_eq!(a.next_u64(), b.next_u64());
+        assert_ne!(SeededRng::new(2).next_u64(), a.next_u64());
+    }
+}
@@ -0,0 +1,246 @@
+//! Key rotation with an overlapping grace window
+//!
+//! Replacing a shared key breaks every client still holding the old one
+//! until it is provisioned again. [`RotatingKey`] keeps the previous key
+//! next to the current one: new OTPs are computed with the current key, and
+//! OTPs from either key are accepted until the grace window after the
+//! rotation ends. [`verify`](RotatingKey::verify) reports which key matched,
+//! so servers can see which clients still have to pick up the new key.
+//!
+//! # Example
+//! ```
+//! use passcode::rotation::{KeySlot, RotatingKey};
+//! use passcode::{Algorithm, Passcode};
+//!
+//! let old = Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+//! let not_yet_updated = old.compute(b"challenge");
+//!
+//! let mut key = RotatingKey::new(old);
+//! key.rotate(Passcode::new(Algorithm::Sha3Kmac256, vec![2u8; 32]));
+//!
+//! assert_eq!(key.verify(b"challenge", &not_yet_updated), Some(KeySlot::Previous));
+//! let otp = key.compute(b"challenge");
+//! assert_eq!(key.verify(b"challenge", &otp), Some(KeySlot::Current));
+//! ```
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use crate::clock::{self, Clock};
+use crate::kdf::KeyId;
+use crate::Passcode;
+
+/// Default time the previous key stays valid after a rotation
+pub const DEFAULT_GRACE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Key that matched in [`RotatingKey::verify`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeySlot {
+    /// The key new OTPs are computed with
+    Current,
+    /// The key replaced by the last rotation, still inside its grace window
+    Previous,
+}
+
+/// A key together with the one it replaced
+pub struct RotatingKey {
+    current: Passcode,
+    activated_at: SystemTime,
+    previous: Option<Passcode>,
+    grace: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl fmt::Debug for RotatingKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Keys are never printed, only their fingerprints
+        f.debug_struct("RotatingKey")
+            .field("current", &self.current.key_id())
+            .field("activated_at", &self.activated_at)
+            .field("previous", &self.previous.as_ref().map(Passcode::key_id))
+            .field("grace", &self.grace)
+            .finish()
+    }
+}
+
+impl RotatingKey {
+    /// Starts with a single key, active from now
+    pub fn new(current: Passcode) -> Self {
+        let clock = clock::system();
+        Self {
+            current,
+            activated_at: clock.now(),
+            previous: None,
+            grace: DEFAULT_GRACE,
+            clock,
+        }
+    }
+
+    /// Sets how long the previous key is accepted after a rotation
+    pub fn with_grace(mut self, grace: Duration) -> Self {
+        self.grace = grace;
+        self
+    }
+
+    /// Reads the time from `clock` instead of the system clock
+    ///
+    /// The current key counts as activated at the clock's time.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self.activated_at = self.clock.now();
+        self
+    }
+
+    /// Makes `next` the current key, keeping the replaced key for the grace
+    /// window
+    ///
+    /// A key replaced earlier is dropped, even if its grace window has not
+    /// ended.
+    pub fn rotate(&mut self, next: Passcode) {
+        let now = self.clock.now();
+        self.rotate_at(next, now);
+    }
+
+    /// Makes `next` the current key as if it had been activated at
+    /// `activated_at`, e.g. when restoring a rotation recorded earlier
+    pub fn rotate_at(&mut self, next: Passcode, activated_at: SystemTime) {
+        self.previous = Some(std::mem::replace(&mut self.current, next));
+        self.activated_at = activated_at;
+    }
+
+    /// Computes an OTP with the current key
+    pub fn compute(&self, data: &[u8]) -> String {
+        self.current.compute(data)
+    }
+
+    /// Verifies an OTP against the current key and, during the grace
+    /// window, the previous one
+    ///
+    /// Returns the key that matched, or `None` if neither did. Both keys
+    /// are checked whenever the previous one is accepted, so the time taken
+    /// does not reveal which matched.
+    pub fn verify(&self, data: &[u8], otp: &str) -> Option<KeySlot> {
+        let current = self.current.verify(data, otp);
+        let previous = self
+            .accepted_previous()
+            .is_some_and(|previous| previous.verify(data, otp));
+
+        if current {
+            Some(KeySlot::Current)
+        } else if previous {
+            Some(KeySlot::Previous)
+        } else {
+            None
+        }
+    }
+
+    /// Gets the key new OTPs are computed with
+    pub fn current(&self) -> &Passcode {
+        &self.current
+    }
+
+    /// Gets the previous key while its grace window lasts
+    pub fn previous(&self) -> Option<&Passcode> {
+        self.accepted_previous()
+    }
+
+    /// Gets the fingerprint of the key in a slot, or `None` for a previous
+    /// key outside its grace window
+    pub fn key_id(&self, slot: KeySlot) -> Option<KeyId> {
+        match slot {
+            KeySlot::Current => Some(self.current.key_id()),
+            KeySlot::Previous => self.accepted_previous().map(Passcode::key_id),
+        }
+    }
+
+    /// Gets the time the current key was activated
+    pub fn activated_at(&self) -> SystemTime {
+        self.activated_at
+    }
+
+    /// Gets the time the previous key stops being accepted, or `None`
+    /// without one
+    pub fn previous_expires_at(&self) -> Option<SystemTime> {
+        self.previous
+            .as_ref()
+            .map(|_| self.activated_at + self.grace)
+    }
+
+    fn accepted_previous(&self) -> Option<&Passcode> {
+        let expires_at = self.previous_expires_at()?;
+        if self.clock.now() >= expires_at {
+            return None;
+        }
+        self.previous.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::Algorithm;
+
+    fn passcode(byte: u8) -> Passcode {
+        Passcode::new(Algorithm::Sha3Kmac256, vec![byte; 32])
+    }
+
+    #[test]
+    fn test_grace_window() {
+        let clock = Arc::new(ManualClock::new(SystemTime::UNIX_EPOCH));
+        let mut key = RotatingKey::new(passcode(1))
+            .with_grace(Duration::from_secs(60))
+            .with_clock(Arc::clone(&clock));
+        let old_otp = key.compute(b"data");
+        assert_eq!(key.verify(b"data", &old_otp), Some(KeySlot::Current));
+        assert!(key.previous_expires_at().is_none());
+
+        clock.advance(Duration::from_secs(10));
+        key.rotate(passcode(2));
+        assert_eq!(key.activated_at(), clock.now());
+        assert_eq!(
+            key.previous_expires_at(),
+            Some(clock.now() + Duration::from_secs(60))
+        );
+        assert_eq!(key.compute(b"data"), passcode(2).compute(b"data"));
+        assert_eq!(key.verify(b"data", &old_otp), Some(KeySlot::Previous));
+        assert_eq!(key.key_id(KeySlot::Previous), Some(passcode(1).key_id()));
+        assert_eq!(key.verify(b"data", "000000000000"), None);
+
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(key.verify(b"data", &old_otp), None);
+        assert!(key.previous().is_none());
+        assert_eq!(key.key_id(KeySlot::Previous), None);
+        assert_eq!(
+            key.verify(b"data", &key.compute(b"data")),
+            Some(KeySlot::Current)
+        );
+    }
+
+    #[test]
+    fn test_rotate_drops_older_keys() {
+        let mut key = RotatingKey::new(passcode(1));
+        let first = key.compute(b"data");
+        key.rotate(passcode(2));
+        key.rotate(passcode(3));
+
+        assert_eq!(key.verify(b"data", &first), None);
+        assert_eq!(
+            key.verify(b"data", &passcode(2).compute(b"data")),
+            Some(KeySlot::Previous)
+        );
+    }
+
+    #[test]
+    fn test_rotate_at_past_activation() {
+        let mut key = RotatingKey::new(passcode(1));
+        let old_otp = key.compute(b"data");
+        key.rotate_at(passcode(2), SystemTime::now() - DEFAULT_GRACE);
+        assert_eq!(key.verify(b"data", &old_otp), None);
+
+        let debug = format!("{:?}", key);
+        assert!(debug.contains("KeyId"));
+        assert!(!debug.contains("[1, 1"));
+    }
+}
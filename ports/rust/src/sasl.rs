@@ -0,0 +1,455 @@
+//! SASL mechanisms
+//!
+//! Runs the challenge-response protocol as a SASL mechanism, so servers
+//! that already authenticate through SASL (SMTP, IMAP, XMPP, LDAP) can add
+//! it as a second factor. There is one mechanism per algorithm family,
+//! e.g. `PASSCODE-CR-SHA3`; the user's key decides the security level.
+//!
+//! The exchange is client-first:
+//!
+//! 1. The client sends `authzid NUL authcid`, where the authorization
+//!    identity may be empty.
+//! 2. The server answers with a random challenge.
+//! 3. The client sends the OTP over the mechanism name, both identities and
+//!    the challenge, so a response cannot be replayed under another
+//!    identity or mechanism.
+//!
+//! Both sides implement the usual step API: feed each message from the peer
+//! to `step` and send back what it returns until it reports [`Step::Done`].
+//! Whether the authenticated user may act as the requested authorization
+//! identity is for the application to decide.
+//!
+//! # Example
+//! ```
+//! use passcode::keyring::KeyRing;
+//! use passcode::sasl::{Mechanism, SaslClient, SaslServer, Step};
+//! use passcode::{Algorithm, Passcode};
+//!
+//! let keyring = KeyRing::new();
+//! keyring.insert("alice", Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]));
+//! let key = Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+//!
+//! let mut client = SaslClient::new(&key, "alice");
+//! let mut server = SaslServer::new(client.mechanism(), &keyring);
+//! assert_eq!(client.mechanism().name(), "PASSCODE-CR-SHA3");
+//!
+//! let Step::Continue(initial) = client.step(b"").unwrap() else { unreachable!() };
+//! let Step::Continue(challenge) = server.step(&initial).unwrap() else { unreachable!() };
+//! let Step::Done(Some(response)) = client.step(&challenge).unwrap() else { unreachable!() };
+//! assert_eq!(server.step(&response).unwrap(), Step::Done(None));
+//! assert_eq!(server.authorization_id(), Some("alice"));
+//! ```
+
+use std::fmt;
+
+use crate::challenge::DEFAULT_CHALLENGE_LEN;
+use crate::keyring::KeyRing;
+use crate::rng::{CryptoRngCore, SharedRng};
+use crate::{Algorithm, Error, Passcode};
+
+/// Domain separator at the start of the bytes the OTP covers
+const SASL_CONTEXT: &[u8] = b"passcode/v1/sasl";
+
+/// SASL mechanism, one per algorithm family
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Mechanism {
+    /// `PASSCODE-CR-SHA3`: the SHA3-KMAC algorithms
+    Sha3,
+    /// `PASSCODE-CR-BLAKE3`: the BLAKE3 keyed-mode algorithms
+    Blake3,
+    /// `PASSCODE-CR-STREEBOG`: HMAC-Streebog (feature `streebog`)
+    #[cfg(feature = "streebog")]
+    Streebog,
+    /// `PASSCODE-CR-HMAC`: the HMAC-SHA algorithms
+    Hmac,
+}
+
+impl Mechanism {
+    /// Returns the registered mechanism name
+    pub fn name(&self) -> &'static str {
+        match self {
+            Mechanism::Sha3 => "PASSCODE-CR-SHA3",
+            Mechanism::Blake3 => "PASSCODE-CR-BLAKE3",
+            #[cfg(feature = "streebog")]
+            Mechanism::Streebog => "PASSCODE-CR-STREEBOG",
+            Mechanism::Hmac => "PASSCODE-CR-HMAC",
+        }
+    }
+
+    /// Looks up a mechanism by name, e.g. one a client asked for
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::all().find(|mechanism| mechanism.name() == name)
+    }
+
+    /// Returns all mechanisms enabled in this build, e.g. to advertise them
+    pub fn all() -> impl Iterator<Item = Mechanism> {
+        [
+            Mechanism::Sha3,
+            Mechanism::Blake3,
+            #[cfg(feature = "streebog")]
+            Mechanism::Streebog,
+            Mechanism::Hmac,
+        ]
+        .into_iter()
+    }
+
+    /// Returns the mechanism keys of the algorithm authenticate with
+    pub fn for_algorithm(algorithm: Algorithm) -> Self {
+        match algorithm {
+            Algorithm::Sha3Kmac128 | Algorithm::Sha3Kmac256 | Algorithm::Sha3Kmac512 => {
+                Mechanism::Sha3
+            }
+            Algorithm::Blake3KeyedMode128 | Algorithm::Blake3KeyedMode256 => Mechanism::Blake3,
+            #[cfg(feature = "streebog")]
+            Algorithm::HmacStreebog256 => Mechanism::Streebog,
+            Algorithm::HmacSha256 | Algorithm::HmacSha1 => Mechanism::Hmac,
+        }
+    }
+}
+
+impl fmt::Display for Mechanism {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Result of a step of the exchange
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// Send the bytes to the peer and wait for its next message
+    Continue(Vec<u8>),
+    /// The exchange is finished on this side; send the final bytes, if any,
+    /// as the last message
+    Done(Option<Vec<u8>>),
+}
+
+/// Server side of the mechanism
+///
+/// Keys are looked up in a [`KeyRing`] by the authentication identity. An
+/// unknown user still receives a challenge and only fails at the last step,
+/// so the exchange does not reveal which users exist.
+pub struct SaslServer<'a> {
+    mechanism: Mechanism,
+    keyring: &'a KeyRing,
+    rng: SharedRng,
+    state: ServerState,
+}
+
+enum ServerState {
+    AwaitingInitial,
+    AwaitingResponse {
+        identities: Identities,
+        challenge: Vec<u8>,
+    },
+    Authenticated {
+        identities: Identities,
+    },
+    Failed,
+}
+
+impl<'a> SaslServer<'a> {
+    /// Creates the server side of the mechanism
+    pub fn new(mechanism: Mechanism, keyring: &'a KeyRing) -> Self {
+        Self {
+            mechanism,
+            keyring,
+            rng: SharedRng::os(),
+            state: ServerState::AwaitingInitial,
+        }
+    }
+
+    /// Draws challenges from `rng` instead of the operating system's CSPRNG
+    pub fn with_rng(mut self, rng: impl CryptoRngCore + Send + 'static) -> Self {
+        self.rng = SharedRng::new(rng);
+        self
+    }
+
+    /// Gets the mechanism
+    pub fn mechanism(&self) -> Mechanism {
+        self.mechanism
+    }
+
+    /// Handles a message from the client
+    ///
+    /// Fails with [`Error::MalformedMessage`] when the message cannot be
+    /// parsed or the exchange is already finished, and with
+    /// [`Error::OtpMismatch`] when the user is unknown, holds a key of
+    /// another mechanism, or the OTP is wrong. Any failure ends the
+    /// exchange.
+    pub fn step(&mut self, input: &[u8]) -> Result<Step, Error> {
+        // Any early return leaves the exchange failed
+        match std::mem::replace(&mut self.state, ServerState::Failed) {
+            ServerState::AwaitingInitial => {
+                let identities = Identities::parse(input)?;
+                let mut challenge = vec![0u8; DEFAULT_CHALLENGE_LEN];
+                self.rng.fill(&mut challenge)?;
+                self.state = ServerState::AwaitingResponse {
+                    identities,
+                    challenge: challenge.clone(),
+                };
+                Ok(Step::Continue(challenge))
+            }
+            ServerState::AwaitingResponse {
+                identities,
+                challenge,
+            } => {
+                let otp = std::str::from_utf8(input)
+                    .map_err(|_| Error::MalformedMessage("response is not UTF-8"))?;
+                let key = self
+                    .keyring
+                    .get(&identities.authcid)
+                    .filter(|key| Mechanism::for_algorithm(key.algorithm()) == self.mechanism)
+                    .ok_or(Error::OtpMismatch)?;
+                let message = transcript(self.mechanism, &identities, &challenge);
+                if !key.verify(&message, otp) {
+                    return Err(Error::OtpMismatch);
+                }
+                self.state = ServerState::Authenticated { identities };
+                Ok(Step::Done(None))
+            }
+            ServerState::Authenticated { .. } | ServerState::Failed => {
+                Err(Error::MalformedMessage("SASL exchange is already finished"))
+            }
+        }
+    }
+
+    /// Returns true once the client has authenticated
+    pub fn is_authenticated(&self) -> bool {
+        matches!(self.state, ServerState::Authenticated { .. })
+    }
+
+    /// Gets the authenticated user, available once the client has
+    /// authenticated
+    pub fn authentication_id(&self) -> Option<&str> {
+        match &self.state {
+            ServerState::Authenticated { identities } => Some(&identities.authcid),
+            _ => None,
+        }
+    }
+
+    /// Gets the identity the client asked to act as, which defaults to the
+    /// authenticated user, available once the client has authenticated
+    pub fn authorization_id(&self) -> Option<&str> {
+        match &self.state {
+            ServerState::Authenticated { identities } => {
+                Some(identities.authzid.as_deref().unwrap_or(&identities.authcid))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for SaslServer<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SaslServer")
+            .field("mechanism", &self.mechanism)
+            .field("authenticated", &self.is_authenticated())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Client side of the mechanism
+pub struct SaslClient<'a> {
+    passcode: &'a Passcode,
+    identities: Identities,
+    state: ClientState,
+}
+
+enum ClientState {
+    Initial,
+    AwaitingChallenge,
+    Done,
+}
+
+impl<'a> SaslClient<'a> {
+    /// Creates a client authenticating as `authcid` with its key
+    pub fn new(passcode: &'a Passcode, authcid: impl Into<String>) -> Self {
+        Self {
+            passcode,
+            identities: Identities {
+                authzid: None,
+                authcid: authcid.into(),
+            },
+            state: ClientState::Initial,
+        }
+    }
+
+    /// Requests to act as another identity once authenticated
+    pub fn with_authzid(mut self, authzid: impl Into<String>) -> Self {
+        self.identities.authzid = Some(authzid.into());
+        self
+    }
+
+    /// Gets the mechanism matching the key's algorithm
+    pub fn mechanism(&self) -> Mechanism {
+        Mechanism::for_algorithm(self.passcode.algorithm())
+    }
+
+    /// Handles a message from the server; pass an empty slice to produce
+    /// the initial response
+    ///
+    /// Fails with [`Error::MalformedMessage`] when called again after the
+    /// OTP was produced.
+    pub fn step(&mut self, input: &[u8]) -> Result<Step, Error> {
+        match self.state {
+            ClientState::Initial => {
+                self.state = ClientState::AwaitingChallenge;
+                Ok(Step::Continue(self.identities.to_bytes()))
+            }
+            ClientState::AwaitingChallenge => {
+                let message = transcript(self.mechanism(), &self.identities, input);
+                self.state = ClientState::Done;
+                Ok(Step::Done(Some(
+                    self.passcode.compute(&message).into_bytes(),
+                )))
+            }
+            ClientState::Done => Err(Error::MalformedMessage("SASL exchange is already finished")),
+        }
+    }
+}
+
+impl fmt::Debug for SaslClient<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SaslClient")
+            .field("mechanism", &self.mechanism())
+            .field("authcid", &self.identities.authcid)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Authorization and authentication identities of the initial response
+struct Identities {
+    authzid: Option<String>,
+    authcid: String,
+}
+
+impl Identities {
+    fn to_bytes(&self) -> Vec<u8> {
+        let authzid = self.authzid.as_deref().unwrap_or("");
+        let mut encoded = Vec::with_capacity(authzid.len() + 1 + self.authcid.len());
+        encoded.extend_from_slice(authzid.as_bytes());
+        encoded.push(0);
+        encoded.extend_from_slice(self.authcid.as_bytes());
+        encoded
+    }
+
+    fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        let text = std::str::from_utf8(bytes)
+            .map_err(|_| Error::MalformedMessage("initial response is not UTF-8"))?;
+        let (authzid, authcid) = text.split_once('\0').ok_or(Error::MalformedMessage(
+            "initial response lacks a separator",
+        ))?;
+        if authcid.is_empty() || authcid.contains('\0') {
+            return Err(Error::MalformedMessage("invalid authentication identity"));
+        }
+        Ok(Self {
+            authzid: (!authzid.is_empty()).then(|| authzid.to_string()),
+            authcid: authcid.to_string(),
+        })
+    }
+}
+
+/// Binds the mechanism and both identities into the bytes the OTP covers
+fn transcript(mechanism: Mechanism, identities: &Identities, challenge: &[u8]) -> Vec<u8> {
+    let identities = identities.to_bytes();
+    let name = mechanism.name().as_bytes();
+    let mut message = Vec::with_capacity(
+        SASL_CONTEXT.len() + name.len() + identities.len() + challenge.len() + 2,
+    );
+    message.extend_from_slice(SASL_CONTEXT);
+    message.extend_from_slice(name);
+    message.push(0);
+    message.extend_from_slice(&identities);
+    message.push(0);
+    message.extend_from_slice(challenge);
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyring() -> KeyRing {
+        let keyring = KeyRing::new();
+        keyring.insert(
+            "alice",
+            Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]),
+        );
+        keyring.insert(
+            "bob",
+            Passcode::new(Algorithm::Blake3KeyedMode256, vec![2u8; 32]),
+        );
+        keyring
+    }
+
+    /// Runs the exchange, returning the server's result for the response
+    fn run(client: &mut SaslClient<'_>, server: &mut SaslServer<'_>) -> Result<Step, Error> {
+        let Step::Continue(initial) = client.step(b"").unwrap() else {
+            panic!("client must send an initial response");
+        };
+        let Step::Continue(challenge) = server.step(&initial)? else {
+            panic!("server must send a challenge");
+        };
+        let Step::Done(Some(response)) = client.step(&challenge).unwrap() else {
+            panic!("client must send a response");
+        };
+        server.step(&response)
+    }
+
+    #[test]
+    fn test_authenticates() {
+        let keyring = keyring();
+        let key = Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+        let mut client = SaslClient::new(&key, "alice").with_authzid("admin");
+        let mut server = SaslServer::new(Mechanism::Sha3, &keyring);
+
+        assert_eq!(run(&mut client, &mut server), Ok(Step::Done(None)));
+        assert!(server.is_authenticated());
+        assert_eq!(server.authentication_id(), Some("alice"));
+        assert_eq!(server.authorization_id(), Some("admin"));
+        assert!(server.step(b"again").is_err());
+        assert!(client.step(b"again").is_err());
+    }
+
+    #[test]
+    fn test_rejects_wrong_key_identity_and_mechanism() {
+        let keyring = keyring();
+        let wrong = Passcode::new(Algorithm::Sha3Kmac256, vec![9u8; 32]);
+        let mut client = SaslClient::new(&wrong, "alice");
+        let mut server = SaslServer::new(Mechanism::Sha3, &keyring);
+        assert_eq!(run(&mut client, &mut server), Err(Error::OtpMismatch));
+        assert!(!server.is_authenticated());
+
+        // Alice's key answering for an unknown user
+        let key = Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+        let mut client = SaslClient::new(&key, "mallory");
+        let mut server = SaslServer::new(Mechanism::Sha3, &keyring);
+        assert_eq!(run(&mut client, &mut server), Err(Error::OtpMismatch));
+
+        // Bob's key belongs to the BLAKE3 mechanism
+        let key = Passcode::new(Algorithm::Blake3KeyedMode256, vec![2u8; 32]);
+        let mut client = SaslClient::new(&key, "bob");
+        let mut server = SaslServer::new(Mechanism::Sha3, &keyring);
+        assert_eq!(run(&mut client, &mut server), Err(Error::OtpMismatch));
+        let mut client = SaslClient::new(&key, "bob");
+        let mut server = SaslServer::new(
+            Mechanism::from_name("PASSCODE-CR-BLAKE3").unwrap(),
+            &keyring,
+        );
+        assert_eq!(run(&mut client, &mut server), Ok(Step::Done(None)));
+    }
+
+    #[test]
+    fn test_rejects_malformed_initial_response() {
+        let keyring = keyring();
+        for initial in [&b"alice"[..], b"admin\0", b"\xff\0alice"] {
+            let mut server = SaslServer::new(Mechanism::Sha3, &keyring);
+            assert!(matches!(
+                server.step(initial),
+                Err(Error::MalformedMessage(_))
+            ));
+        }
+        assert!(Mechanism::all().all(|m| m.name().len() <= 20));
+    }
+}
@@ -0,0 +1,513 @@
+//! Serde representations of protocol types (feature `serde`)
+//!
+//! The layouts below are part of the public API, so HTTP services written
+//! in different languages can exchange the same JSON. Byte strings are
+//! unpadded base64url, times are milliseconds since the Unix epoch and
+//! durations are milliseconds. Optional fields are left out when unset and
+//! unknown fields are ignored.
+//!
+//! - [`Algorithm`]: its [`as_str`](Algorithm::as_str) name, e.g.
+//!   `"SHA3-KMAC-256"`
+//! - [`ChallengeId`]: 32 lowercase hex characters
+//! - [`KeyId`]: 16 lowercase hex characters
+//! - [`KeyState`]: `"active"`, `"grace_period"` or `"revoked"`
+//! - [`KeyRecord`]: `{"key_id", "algorithm", "created_at", "expires_at",
+//!   "state"}`
+//! - [`ChallengeBinding`]: `{"user_id", "device_id", "client_ip", "purpose"}`
+//! - [`Challenge`]: `{"id", "bytes", "expires_at", "binding", "difficulty"}`
+//! - [`ChallengeMessage`]: `{"algorithm", "nonce", "expires_at", "mac"}`
+//! - [`ResponseMessage`]: `{"algorithm", "nonce", "mac"}`
+//! - [`VerifyOutcome`]: `{"outcome"}`, where the outcome is `accepted`,
+//!   `rejected`, `expired`, `unknown_challenge`, `replayed`,
+//!   `puzzle_unsolved`, `rate_limited` or `locked_out`; the last two add
+//!   `"retry_after_ms"`
+//!
+//! Expiries are truncated to whole milliseconds, so a deserialized
+//! [`Challenge`] may expire up to a millisecond earlier than the issued one.
+
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::challenge::{Challenge, ChallengeBinding, ChallengeId};
+use crate::kdf::KeyId;
+use crate::keyrecord::{KeyRecord, KeyState};
+use crate::verifier::VerifyOutcome;
+use crate::wire::{ChallengeMessage, ResponseMessage};
+use crate::Algorithm;
+
+impl Serialize for Algorithm {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Algorithm {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Algorithm::all()
+            .find(|algorithm| algorithm.as_str() == name)
+            .ok_or_else(|| D::Error::custom(format!("unknown algorithm {}", name)))
+    }
+}
+
+impl Serialize for ChallengeId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ChallengeId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(|_| D::Error::custom("invalid challenge id"))
+    }
+}
+
+impl Serialize for KeyId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(|_| D::Error::custom("invalid key id"))
+    }
+}
+
+impl Serialize for KeyState {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyState {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        KeyState::from_name(&name)
+            .ok_or_else(|| D::Error::custom(format!("unknown key state {}", name)))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeyRecordRepr {
+    key_id: KeyId,
+    algorithm: Algorithm,
+    created_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
+    state: KeyState,
+}
+
+impl Serialize for KeyRecord {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        KeyRecordRepr {
+            key_id: self.key_id(),
+            algorithm: self.algorithm(),
+            created_at: to_millis(self.created_at()),
+            expires_at: self.expires_at().map(to_millis),
+            state: self.state(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyRecord {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = KeyRecordRepr::deserialize(deserializer)?;
+        let mut record = KeyRecord::new(repr.key_id, repr.algorithm, from_millis(repr.created_at))
+            .with_state(repr.state);
+        if let Some(expires_at) = repr.expires_at {
+            record = record.with_expires_at(from_millis(expires_at));
+        }
+        Ok(record)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct BindingRepr {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    user_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    device_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    client_ip: Option<IpAddr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    purpose: Option<String>,
+}
+
+impl Serialize for ChallengeBinding {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        BindingRepr {
+            user_id: self.user_id().map(str::to_string),
+            device_id: self.device_id().map(str::to_string),
+            client_ip: self.client_ip(),
+            purpose: self.purpose().map(str::to_string),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ChallengeBinding {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = BindingRepr::deserialize(deserializer)?;
+        let mut binding = ChallengeBinding::new();
+        if let Some(user_id) = repr.user_id {
+            binding = binding.with_user_id(user_id);
+        }
+        if let Some(device_id) = repr.device_id {
+            binding = binding.with_device_id(device_id);
+        }
+        if let Some(client_ip) = repr.client_ip {
+            binding = binding.with_client_ip(client_ip);
+        }
+        if let Some(purpose) = repr.purpose {
+            binding = binding.with_purpose(purpose);
+        }
+        Ok(binding)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChallengeRepr {
+    id: ChallengeId,
+    #[serde(with = "base64url")]
+    bytes: Vec<u8>,
+    expires_at: u64,
+    #[serde(default)]
+    binding: ChallengeBinding,
+    #[serde(default)]
+    difficulty: u8,
+}
+
+impl Serialize for Challenge {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ChallengeRepr {
+            id: *self.id(),
+            bytes: self.bytes().to_vec(),
+            expires_at: to_millis(self.expires_at()),
+            binding: self.binding().clone(),
+            difficulty: self.difficulty(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Challenge {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = ChallengeRepr::deserialize(deserializer)?;
+        Ok(Challenge::from_parts(
+            repr.id,
+            repr.bytes,
+            from_millis(repr.expires_at),
+            repr.binding,
+            repr.difficulty,
+        ))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChallengeMessageRepr {
+    algorithm: Algorithm,
+    #[serde(with = "base64url")]
+    nonce: Vec<u8>,
+    expires_at: u64,
+    #[serde(with = "base64url")]
+    mac: Vec<u8>,
+}
+
+impl Serialize for ChallengeMessage {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ChallengeMessageRepr {
+            algorithm: self.algorithm,
+            nonce: self.nonce.clone(),
+            expires_at: to_millis(self.expires_at),
+            mac: self.mac.clone(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ChallengeMessage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = ChallengeMessageRepr::deserialize(deserializer)?;
+        Ok(ChallengeMessage {
+            algorithm: repr.algorithm,
+            nonce: repr.nonce,
+            expires_at: from_millis(repr.expires_at),
+            mac: repr.mac,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ResponseMessageRepr {
+    algorithm: Algorithm,
+    #[serde(with = "base64url")]
+    nonce: Vec<u8>,
+    #[serde(with = "base64url")]
+    mac: Vec<u8>,
+}
+
+impl Serialize for ResponseMessage {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ResponseMessageRepr {
+            algorithm: self.algorithm,
+            nonce: self.nonce.clone(),
+            mac: self.mac.clone(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ResponseMessage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = ResponseMessageRepr::deserialize(deserializer)?;
+        Ok(ResponseMessage {
+            algorithm: repr.algorithm,
+            nonce: repr.nonce,
+            mac: repr.mac,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+enum OutcomeRepr {
+    Accepted,
+    Rejected,
+    Expired,
+    UnknownChallenge,
+    Replayed,
+    PuzzleUnsolved,
+    RateLimited { retry_after_ms: u64 },
+    LockedOut { retry_after_ms: u64 },
+}
+
+impl Serialize for VerifyOutcome {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            VerifyOutcome::Accepted => OutcomeRepr::Accepted,
+            VerifyOutcome::Rejected => OutcomeRepr::Rejected,
+            VerifyOutcome::Expired => OutcomeRepr::Expired,
+            VerifyOutcome::UnknownChallenge => OutcomeRepr::UnknownChallenge,
+            VerifyOutcome::Replayed => OutcomeRepr::Replayed,
+            VerifyOutcome::PuzzleUnsolved => OutcomeRepr::PuzzleUnsolved,
+            VerifyOutcome::RateLimited { retry_after } => OutcomeRepr::RateLimited {
+                retry_after_ms: retry_after.as_millis() as u64,
+            },
+            VerifyOutcome::LockedOut { retry_after } => OutcomeRepr::LockedOut {
+                retry_after_ms: retry_after.as_millis() as u64,
+            },
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for VerifyOutcome {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match OutcomeRepr::deserialize(deserializer)? {
+            OutcomeRepr::Accepted => VerifyOutcome::Accepted,
+            OutcomeRepr::Rejected => VerifyOutcome::Rejected,
+            OutcomeRepr::Expired => VerifyOutcome::Expired,
+            OutcomeRepr::UnknownChallenge => VerifyOutcome::UnknownChallenge,
+            OutcomeRepr::Replayed => VerifyOutcome::Replayed,
+            OutcomeRepr::PuzzleUnsolved => VerifyOutcome::PuzzleUnsolved,
+            OutcomeRepr::RateLimited { retry_after_ms } => VerifyOutcome::RateLimited {
+                retry_after: Duration::from_millis(retry_after_ms),
+            },
+            OutcomeRepr::LockedOut { retry_after_ms } => VerifyOutcome::LockedOut {
+                retry_after: Duration::from_millis(retry_after_ms),
+            },
+        })
+    }
+}
+
+/// Byte strings as unpadded base64url
+mod base64url {
+    use base64ct::{Base64UrlUnpadded, Encoding};
+    use serde::de::Error as _;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&Base64UrlUnpadded::encode_string(bytes))
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        Base64UrlUnpadded::decode_vec(&encoded).map_err(|_| D::Error::custom("invalid base64url"))
+    }
+}
+
+fn to_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+fn from_millis(millis: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::challenge::CHALLENGE_ID_LEN;
+    use serde_json::json;
+
+    #[test]
+    fn test_challenge_layout() {
+        let challenge = Challenge::from_parts(
+            ChallengeId::from_bytes([0xab; CHALLENGE_ID_LEN]),
+            vec![0xfb, 0xff],
+            UNIX_EPOCH + Duration::from_millis(1_700_000_000_123),
+            ChallengeBinding::new()
+                .with_user_id("alice")
+                .with_client_ip("192.0.2.1".parse().unwrap()),
+            3,
+        );
+        let value = serde_json::to_value(&challenge).unwrap();
+
+        assert_eq!(
+            value,
+            json!({
+                "id": "abababababababababababababababab",
+                "bytes": "-_8",
+                "expires_at": 1_700_000_000_123u64,
+                "binding": {"user_id": "alice", "client_ip": "192.0.2.1"},
+                "difficulty": 3,
+            })
+        );
+        assert_eq!(
+            serde_json::from_value::<Challenge>(value).unwrap(),
+            challenge
+        );
+
+        // Binding and difficulty may be left out
+        let minimal = json!({
+            "id": "abababababababababababababababab",
+            "bytes": "",
+            "expires_at": 0,
+        });
+        let minimal: Challenge = serde_json::from_value(minimal).unwrap();
+        assert!(minimal.binding().is_empty());
+        assert_eq!(minimal.difficulty(), 0);
+    }
+
+    #[test]
+    fn test_key_id_layout() {
+        let id = KeyId::from_bytes([0x0f; crate::kdf::KEY_ID_LEN]);
+        let value = serde_json::to_value(id).unwrap();
+        assert_eq!(value, json!("0f0f0f0f0f0f0f0f"));
+        assert_eq!(serde_json::from_value::<KeyId>(value).unwrap(), id);
+        assert!(serde_json::from_value::<KeyId>(json!("0f")).is_err());
+    }
+
+    #[test]
+    fn test_key_record_layout() {
+        let record = KeyRecord::new(
+            KeyId::from_bytes([0x0f; crate::kdf::KEY_ID_LEN]),
+            Algorithm::Blake3KeyedMode256,
+            UNIX_EPOCH + Duration::from_millis(1_000),
+        )
+        .with_state(KeyState::GracePeriod)
+        .with_expires_at(UNIX_EPOCH + Duration::from_millis(2_000));
+        let value = serde_json::to_value(&record).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "key_id": "0f0f0f0f0f0f0f0f",
+                "algorithm": "BLAKE3-Keyed-Mode-256",
+                "created_at": 1000,
+                "expires_at": 2000,
+                "state": "grace_period",
+            })
+        );
+        assert_eq!(serde_json::from_value::<KeyRecord>(value).unwrap(), record);
+
+        let active = json!({
+            "key_id": "0f0f0f0f0f0f0f0f",
+            "algorithm": "BLAKE3-Keyed-Mode-256",
+            "created_at": 1000,
+            "state": "active",
+        });
+        let active = serde_json::from_value::<KeyRecord>(active).unwrap();
+        assert_eq!(active.expires_at(), None);
+        assert!(serde_json::from_value::<KeyState>(json!("expired")).is_err());
+    }
+
+    #[test]
+    fn test_message_layout() {
+        let challenge = ChallengeMessage {
+            algorithm: Algorithm::Sha3Kmac256,
+            nonce: vec![1, 2, 3],
+            expires_at: UNIX_EPOCH + Duration::from_millis(5),
+            mac: vec![4, 5, 6],
+        };
+        let value = serde_json::to_value(&challenge).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "algorithm": "SHA3-KMAC-256",
+                "nonce": "AQID",
+                "expires_at": 5,
+                "mac": "BAUG",
+            })
+        );
+        assert_eq!(
+            serde_json::from_value::<ChallengeMessage>(value).unwrap(),
+            challenge
+        );
+
+        let response = ResponseMessage {
+            algorithm: Algorithm::Blake3KeyedMode256,
+            nonce: vec![1, 2, 3],
+            mac: vec![7],
+        };
+        let value = serde_json::to_value(&response).unwrap();
+        assert_eq!(
+            value,
+            json!({"algorithm": "BLAKE3-Keyed-Mode-256", "nonce": "AQID", "mac": "Bw"})
+        );
+        assert_eq!(
+            serde_json::from_value::<ResponseMessage>(value).unwrap(),
+            response
+        );
+
+        assert!(serde_json::from_value::<ResponseMessage>(
+            json!({"algorithm": "MD5", "nonce": "", "mac": ""})
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_outcome_layout() {
+        let cases = [
+            (VerifyOutcome::Accepted, json!({"outcome": "accepted"})),
+            (
+                VerifyOutcome::UnknownChallenge,
+                json!({"outcome": "unknown_challenge"}),
+            ),
+            (
+                VerifyOutcome::RateLimited {
+                    retry_after: Duration::from_millis(1500),
+                },
+                json!({"outcome": "rate_limited", "retry_after_ms": 1500}),
+            ),
+        ];
+        for (outcome, expected) in cases {
+            assert_eq!(serde_json::to_value(outcome).unwrap(), expected);
+            assert_eq!(
+                serde_json::from_value::<VerifyOutcome>(expected).unwrap(),
+                outcome
+            );
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! Storage for key material
+//!
+//! [`SecretBytes`] holds a [`Passcode`](crate::Passcode)'s key and
+//! overwrites it with zeros when dropped. With the `locked-memory` feature
+//! the key also lives on pages of its own that are locked into RAM with
+//! `mlock` on Unix or `VirtualLock` on Windows, so it is never written to
+//! swap or included in a hibernation image. Locking is best effort: a
+//! process over its locked-memory limit (`RLIMIT_MEMLOCK`) keeps its keys
+//! on unlocked pages, which
+//! [`Passcode::is_memory_locked`](crate::Passcode::is_memory_locked)
+//! reports.
+//!
+//! Each locked key takes at least one page, so a verifier holding many keys
+//! in locked memory needs a matching limit.
+
+use std::ops::Deref;
+use std::sync::atomic::{compiler_fence, Ordering};
+
+/// Key bytes that are scrubbed on drop
+#[cfg(not(feature = "locked-memory"))]
+pub(crate) struct SecretBytes {
+    bytes: Box<[u8]>,
+}
+
+/// Key bytes on dedicated, locked pages that are scrubbed on drop
+#[cfg(feature = "locked-memory")]
+pub(crate) struct SecretBytes {
+    // Dropped before the pages are unmapped
+    lock: Option<region::LockGuard>,
+    pages: region::Allocation,
+    len: usize,
+}
+
+// SAFETY: the pages are owned by this value alone and only written while it
+// is borrowed mutably
+#[cfg(feature = "locked-memory")]
+unsafe impl Send for SecretBytes {}
+#[cfg(feature = "locked-memory")]
+unsafe impl Sync for SecretBytes {}
+
+impl SecretBytes {
+    /// Moves a key into secret storage, scrubbing the vector it came in
+    #[cfg(not(feature = "locked-memory"))]
+    pub(crate) fn new(mut key: Vec<u8>) -> Self {
+        let mut bytes = vec![0u8; key.len()].into_boxed_slice();
+        bytes.copy_from_slice(&key);
+        scrub(&mut key);
+        Self { bytes }
+    }
+
+    /// Moves a key into secret storage, scrubbing the vector it came in
+    ///
+    /// # Panics
+    /// Panics if the operating system cannot map a page for the key.
+    #[cfg(feature = "locked-memory")]
+    pub(crate) fn new(mut key: Vec<u8>) -> Self {
+        let mut pages = region::alloc(key.len().max(1), region::Protection::READ_WRITE)
+            .expect("failed to map key pages");
+        let lock = region::lock(pages.as_ptr::<u8>(), pages.len()).ok();
+        // SAFETY: the allocation is at least `key.len()` bytes and writable
+        unsafe {
+            std::ptr::copy_nonoverlapping(key.as_ptr(), pages.as_mut_ptr::<u8>(), key.len());
+        }
+        scrub(&mut key);
+        Self {
+            lock,
+            pages,
+            len: key.len(),
+        }
+    }
+
+    /// Returns true if the key's pages are locked into RAM
+    #[cfg(feature = "locked-memory")]
+    pub(crate) fn is_locked(&self) -> bool {
+        self.lock.is_some()
+    }
+
+    #[cfg(not(feature = "locked-memory"))]
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.bytes
+    }
+
+    #[cfg(feature = "locked-memory")]
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        // SAFETY: the allocation is at least `len` bytes, writable and
+        // borrowed mutably
+        unsafe { std::slice::from_raw_parts_mut(self.pages.as_mut_ptr::<u8>(), self.len) }
+    }
+}
+
+impl Deref for SecretBytes {
+    type Target = [u8];
+
+    #[cfg(not(feature = "locked-memory"))]
+    fn deref(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    #[cfg(feature = "locked-memory")]
+    fn deref(&self) -> &[u8] {
+        // SAFETY: the allocation is at least `len` bytes and initialized
+        unsafe { std::slice::from_raw_parts(self.pages.as_ptr::<u8>(), self.len) }
+    }
+}
+
+impl Drop for SecretBytes {
+    fn drop(&mut self) {
+        scrub(self.as_mut_slice());
+    }
+}
+
+/// Overwrites `bytes` with zeros in a way the compiler does not elide
+fn scrub(bytes: &mut [u8]) {
+    for byte in bytes.iter_mut() {
+        // SAFETY: `byte` is a valid, aligned reference
+        unsafe { std::ptr::write_volatile(byte, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_holds_key() {
+        let secret = SecretBytes::new(vec![7u8; 32]);
+        assert_eq!(&*secret, &[7u8; 32]);
+        assert!(SecretBytes::new(Vec::new()).is_empty());
+
+        let long = SecretBytes::new(vec![1u8; 10_000]);
+        assert_eq!(long.len(), 10_000);
+    }
+
+    #[test]
+    fn test_scrub() {
+        let mut bytes = [0xffu8; 16];
+        scrub(&mut bytes);
+        assert_eq!(bytes, [0u8; 16]);
+    }
+
+    #[cfg(feature = "locked-memory")]
+    #[test]
+    fn test_locked() {
+        // One page is within any default locked-memory limit
+        assert!(SecretBytes::new(vec![7u8; 32]).is_locked());
+    }
+}
@@ -0,0 +1,104 @@
+//! Power-on known-answer self-test
+//!
+//! Computes fixed test vectors for every enabled algorithm and fails closed
+//! on any mismatch, so regulated deployments can demonstrate the integrity
+//! of the cryptographic implementation at runtime. With the
+//! `power-on-self-test` feature the test runs automatically (once per
+//! process) the first time a [`Passcode`] or [`Totp`](crate::totp::Totp) is
+//! constructed through a fallible constructor.
+
+use crate::hotp::{hotp, HmacAlgorithm};
+use crate::{Algorithm, Error, Passcode};
+
+/// Key shared with the cross-language test vectors
+const KAT_KEY: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+/// Challenge shared with the cross-language test vectors
+const KAT_CHALLENGE: &str = "fedcba9876543210fedcba9876543210";
+
+/// Expected OTP for the known-answer key and challenge
+fn expected_otp(algorithm: Algorithm) -> &'static str {
+    match algorithm {
+        Algorithm::Sha3Kmac128 => "2ce05573dd4e",
+        Algorithm::Sha3Kmac256 => "f391e239e588",
+        Algorithm::Blake3KeyedMode128 => "2ce4568631de",
+        Algorithm::Blake3KeyedMode256 => "2ce4568631de",
+        Algorithm::Sha3Kmac512 => "0ccfe58cac1e82f14ee940cb8e227557",
+        #[cfg(feature = "streebog")]
+        Algorithm::HmacStreebog256 => "56a4af372e36",
+        Algorithm::HmacSha256 => "10a2dd145fb2",
+        Algorithm::HmacSha1 => "55bffa743a82",
+    }
+}
+
+/// Runs the known-answer tests for all enabled algorithms
+///
+/// Returns [`Error::SelfTestFailed`] naming the first algorithm whose output
+/// does not match.
+///
+/// # Example
+/// ```
+/// passcode::self_test().expect("cryptographic self-test failed");
+/// ```
+pub fn self_test() -> Result<(), Error> {
+    let key = hex::decode(KAT_KEY).expect("valid KAT key");
+    let challenge = hex::decode(KAT_CHALLENGE).expect("valid KAT challenge");
+
+    for algorithm in Algorithm::all() {
+        let otp = Passcode::new(algorithm, key.clone()).compute(&challenge);
+        if otp != expected_otp(algorithm) {
+            return Err(Error::SelfTestFailed(algorithm.as_str()));
+        }
+    }
+
+    // RFC 6238 Appendix B, T = 59, 8 digits
+    let hmac_vectors: [(HmacAlgorithm, &[u8], &str); 3] = [
+        (HmacAlgorithm::Sha1, b"12345678901234567890", "94287082"),
+        (
+            HmacAlgorithm::Sha256,
+            b"12345678901234567890123456789012",
+            "46119246",
+        ),
+        (
+            HmacAlgorithm::Sha512,
+            b"1234567890123456789012345678901234567890123456789012345678901234",
+            "90693936",
+        ),
+    ];
+    for (algorithm, key, expected) in hmac_vectors {
+        if hotp(algorithm, key, 1, 8)? != expected {
+            return Err(Error::SelfTestFailed(algorithm.as_str()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs the self-test once per process and returns the cached result
+#[cfg(feature = "power-on-self-test")]
+pub(crate) fn ensure_self_test() -> Result<(), Error> {
+    static RESULT: std::sync::OnceLock<Result<(), Error>> = std::sync::OnceLock::new();
+    RESULT.get_or_init(self_test).clone()
+}
+
+/// The self-test only runs automatically with the `power-on-self-test` feature
+#[cfg(not(feature = "power-on-self-test"))]
+pub(crate) fn ensure_self_test() -> Result<(), Error> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_self_test_passes() {
+        assert_eq!(self_test(), Ok(()));
+    }
+
+    #[test]
+    fn test_every_algorithm_has_a_vector() {
+        for algorithm in Algorithm::all() {
+            assert!(!expected_otp(algorithm).is_empty());
+        }
+    }
+}
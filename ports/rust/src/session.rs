@@ -0,0 +1,1060 @@
+//! Session types for the challenge-response protocol
+//!
+//! # Typestate wrappers
+//!
+//! An [`IssuedChallenge`] can only be obtained by issuing a challenge, and
+//! verifying it consumes the value, so code that verifies a challenge that
+//! was never issued, or verifies one twice, does not compile. Session
+//! material is only reachable through the [`VerifiedSession`] returned by a
+//! successful verification.
+//!
+//! ```
+//! use passcode::challenge::{ChallengeBinding, ChallengeManager};
+//! use passcode::session::SESSION_KEY_LEN;
+//! use passcode::{Algorithm, Passcode};
+//!
+//! # pollster::block_on(async {
+//! let key = vec![1u8; 32];
+//! let server = ChallengeManager::new(Passcode::new(Algorithm::Sha3Kmac256, key.clone()));
+//! let issued = server.begin(ChallengeBinding::new()).await.unwrap();
+//!
+//! // Client side
+//! let client = Passcode::new(Algorithm::Sha3Kmac256, key);
+//! let message = issued.challenge().message();
+//! let otp = client.compute(&message);
+//!
+//! let session = issued.verify(&server, &otp).await.unwrap();
+//! assert_eq!(session.session_key(), client.derive_session_key(&message, SESSION_KEY_LEN));
+//! // `issued` has been moved: verifying it again does not compile
+//! # });
+//! ```
+//!
+//! # Protocol state machines
+//!
+//! [`ClientSession`] and [`ServerSession`] produce and consume the protocol
+//! [`Message`]s without doing any I/O: the challenge, the response and an
+//! optional server proof. A message that arrives out of order is rejected
+//! with [`Error::UnexpectedMessage`] and leaves the session unchanged.
+//!
+//! ```
+//! use passcode::session::{ClientSession, Message, ServerSession};
+//! use passcode::{Algorithm, Passcode};
+//!
+//! let key = Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+//! let mut server = ServerSession::new(&key).unwrap().with_server_proof();
+//! let mut client = ClientSession::new(&key).with_server_proof();
+//!
+//! let response = client.handle(server.challenge()).unwrap().unwrap();
+//! // Messages can cross the wire as bytes
+//! let response = Message::from_bytes(&response.to_bytes()).unwrap();
+//! let proof = server.handle(response).unwrap().unwrap();
+//! assert_eq!(client.handle(proof).unwrap(), None);
+//!
+//! assert!(server.is_verified() && client.is_complete());
+//! assert_eq!(server.session_key(), client.session_key());
+//! ```
+//!
+//! With [`with_rounds`](ServerSession::with_rounds) the client must answer
+//! several challenges in a row. Each challenge after the first is derived
+//! from the previous challenge and its OTP, so the rounds form a chain that
+//! neither side can reorder, and the session is only verified after the
+//! last one.
+//!
+//! # Mutual authentication
+//!
+//! [`MutualSession`] runs the two-way flow: the client sends a nonce, the
+//! server answers with its own, the client proves it holds the key over
+//! both nonces and the server proves it back. A client talking to a server
+//! without the key fails with [`Error::ServerProofMismatch`].
+//!
+//! ```
+//! use passcode::session::MutualSession;
+//! use passcode::{Algorithm, Passcode};
+//!
+//! let key = Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+//! let (mut client, client_nonce) = MutualSession::client(&key).unwrap();
+//! let mut server = MutualSession::server(&key);
+//!
+//! let server_nonce = server.handle(client_nonce).unwrap().unwrap();
+//! let client_proof = client.handle(server_nonce).unwrap().unwrap();
+//! let server_proof = server.handle(client_proof).unwrap().unwrap();
+//! assert_eq!(client.handle(server_proof).unwrap(), None);
+//!
+//! assert!(client.is_complete() && server.is_complete());
+//! assert_eq!(client.session_key(), server.session_key());
+//! ```
+
+use std::fmt;
+
+use subtle::ConstantTimeEq;
+
+use crate::challenge::{
+    Challenge, ChallengeBinding, ChallengeId, ChallengeManager, ChallengeStore,
+    DEFAULT_CHALLENGE_LEN,
+};
+use crate::kdf::labels;
+use crate::rng::{CryptoRngCore, SharedRng};
+use crate::{Error, Passcode};
+
+/// Length of the session key of a [`VerifiedSession`]
+pub const SESSION_KEY_LEN: usize = 32;
+
+/// A challenge that has been issued and not yet verified
+///
+/// The value can neither be cloned nor verified twice:
+///
+/// ```compile_fail
+/// use passcode::challenge::{ChallengeBinding, ChallengeManager};
+/// use passcode::{Algorithm, Passcode};
+///
+/// # pollster::block_on(async {
+/// let server = ChallengeManager::new(Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]));
+/// let issued = server.begin(ChallengeBinding::new()).await.unwrap();
+/// let _ = issued.verify(&server, "000000000000").await;
+/// let _ = issued.verify(&server, "000000000000").await;
+/// # });
+/// ```
+#[derive(Debug)]
+#[must_use = "an issued challenge does nothing until it is verified"]
+pub struct IssuedChallenge {
+    challenge: Challenge,
+}
+
+impl IssuedChallenge {
+    /// Gets the challenge to send to the client
+    pub fn challenge(&self) -> &Challenge {
+        &self.challenge
+    }
+
+    /// Verifies the client's OTP, consuming the issued challenge
+    ///
+    /// The response is checked against the binding the challenge was issued
+    /// with, by the manager that issued it. Errors are those of
+    /// [`ChallengeManager::verify_bound`].
+    pub async fn verify<S: ChallengeStore>(
+        self,
+        manager: &ChallengeManager<S>,
+        otp: &str,
+    ) -> Result<VerifiedSession, Error> {
+        self.accept(manager, None, otp).await
+    }
+
+    /// Verifies the client's OTP and puzzle solution for a challenge issued
+    /// with a puzzle attached, consuming the issued challenge
+    ///
+    /// Errors are those of [`ChallengeManager::verify_solved`].
+    pub async fn verify_solved<S: ChallengeStore>(
+        self,
+        manager: &ChallengeManager<S>,
+        solution: u64,
+        otp: &str,
+    ) -> Result<VerifiedSession, Error> {
+        self.accept(manager, Some(solution), otp).await
+    }
+
+    async fn accept<S: ChallengeStore>(
+        self,
+        manager: &ChallengeManager<S>,
+        solution: Option<u64>,
+        otp: &str,
+    ) -> Result<VerifiedSession, Error> {
+        let accepted = manager
+            .accept(
+                self.challenge.id(),
+                self.challenge.binding(),
+                None,
+                solution,
+                otp,
+            )
+            .await?;
+
+        Ok(VerifiedSession {
+            challenge_id: *self.challenge.id(),
+            session_key: accepted
+                .passcode
+                .derive_session_key(&accepted.message, SESSION_KEY_LEN),
+            binding: self.challenge.binding().clone(),
+        })
+    }
+}
+
+/// Proof that a challenge was verified, holding the session material
+pub struct VerifiedSession {
+    challenge_id: ChallengeId,
+    binding: ChallengeBinding,
+    session_key: Vec<u8>,
+}
+
+impl VerifiedSession {
+    /// Gets the id of the verified challenge
+    pub fn challenge_id(&self) -> &ChallengeId {
+        &self.challenge_id
+    }
+
+    /// Gets the identity and purpose the session was verified for
+    pub fn binding(&self) -> &ChallengeBinding {
+        &self.binding
+    }
+
+    /// Gets the key shared with the client
+    ///
+    /// The client derives the same key with
+    /// [`Passcode::derive_session_key`](crate::Passcode::derive_session_key)
+    /// over [`Challenge::message`] and [`SESSION_KEY_LEN`].
+    pub fn session_key(&self) -> &[u8] {
+        &self.session_key
+    }
+}
+
+impl fmt::Debug for VerifiedSession {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VerifiedSession")
+            .field("challenge_id", &self.challenge_id)
+            .field("binding", &self.binding)
+            .field("session_key", &"<redacted>")
+            .finish()
+    }
+}
+
+impl<S: ChallengeStore> ChallengeManager<S> {
+    /// Issues a challenge as an [`IssuedChallenge`], whose verification
+    /// yields a [`VerifiedSession`]
+    pub async fn begin(&self, binding: ChallengeBinding) -> Result<IssuedChallenge, Error> {
+        let challenge = self.issue_bound(binding).await?;
+        Ok(IssuedChallenge { challenge })
+    }
+}
+
+/// Context string for deriving a round's challenge from the previous one
+const ROUND_CONTEXT: &str = "passcode/v1/round";
+
+/// Domain separator at the start of a mutual session's transcript
+const MUTUAL_CONTEXT: &[u8] = b"passcode/v1/mutual";
+
+/// Length of the nonces of a [`MutualSession`]
+pub const MUTUAL_NONCE_LEN: usize = 32;
+
+/// Length of the proof in a [`Message::ServerProof`]
+pub const SERVER_PROOF_LEN: usize = 32;
+
+const TAG_CHALLENGE: u8 = 1;
+const TAG_RESPONSE: u8 = 2;
+const TAG_SERVER_PROOF: u8 = 3;
+const TAG_CLIENT_NONCE: u8 = 4;
+
+/// Kind of a protocol [`Message`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MessageKind {
+    /// [`Message::Challenge`]
+    Challenge,
+    /// [`Message::Response`]
+    Response,
+    /// [`Message::ServerProof`]
+    ServerProof,
+    /// [`Message::ClientNonce`]
+    ClientNonce,
+}
+
+impl fmt::Display for MessageKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MessageKind::Challenge => "challenge",
+            MessageKind::Response => "response",
+            MessageKind::ServerProof => "server proof",
+            MessageKind::ClientNonce => "client nonce",
+        })
+    }
+}
+
+/// Message exchanged between a client and a server session
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// Server to client: the bytes the OTP is computed over, or the server
+    /// nonce of a [`MutualSession`]
+    Challenge(Vec<u8>),
+    /// Client to server: the OTP
+    Response(String),
+    /// Server to client: proof that the server holds the key
+    ServerProof(Vec<u8>),
+    /// Client to server: the nonce opening a [`MutualSession`]
+    ClientNonce(Vec<u8>),
+}
+
+impl Message {
+    /// Gets the kind of the message
+    pub fn kind(&self) -> MessageKind {
+        match self {
+            Message::Challenge(_) => MessageKind::Challenge,
+            Message::Response(_) => MessageKind::Response,
+            Message::ServerProof(_) => MessageKind::ServerProof,
+            Message::ClientNonce(_) => MessageKind::ClientNonce,
+        }
+    }
+
+    /// Encodes the message as a one-byte tag followed by the payload
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let (tag, payload) = match self {
+            Message::Challenge(bytes) => (TAG_CHALLENGE, bytes.as_slice()),
+            Message::Response(otp) => (TAG_RESPONSE, otp.as_bytes()),
+            Message::ServerProof(proof) => (TAG_SERVER_PROOF, proof.as_slice()),
+            Message::ClientNonce(nonce) => (TAG_CLIENT_NONCE, nonce.as_slice()),
+        };
+        let mut encoded = Vec::with_capacity(1 + payload.len());
+        encoded.push(tag);
+        encoded.extend_from_slice(payload);
+        encoded
+    }
+
+    /// Decodes a message written by [`to_bytes`](Self::to_bytes)
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let (&tag, payload) = bytes
+            .split_first()
+            .ok_or(Error::MalformedMessage("empty message"))?;
+
+        match tag {
+            TAG_CHALLENGE => Ok(Message::Challenge(payload.to_vec())),
+            TAG_RESPONSE => std::str::from_utf8(payload)
+                .map(|otp| Message::Response(otp.to_string()))
+                .map_err(|_| Error::MalformedMessage("response is not UTF-8")),
+            TAG_SERVER_PROOF => Ok(Message::ServerProof(payload.to_vec())),
+            TAG_CLIENT_NONCE => Ok(Message::ClientNonce(payload.to_vec())),
+            _ => Err(Error::MalformedMessage("unknown message tag")),
+        }
+    }
+}
+
+/// Server side of the protocol
+///
+/// The server sends [`challenge`](Self::challenge), then passes the client's
+/// response to [`handle`](Self::handle), which returns the next round's
+/// challenge until all rounds are answered. A wrong OTP fails the session
+/// for good; start a new one with a fresh challenge.
+pub struct ServerSession<'a> {
+    passcode: &'a Passcode,
+    message: Vec<u8>,
+    server_proof: bool,
+    rounds: u32,
+    round: u32,
+    state: ServerState,
+}
+
+enum ServerState {
+    AwaitingResponse,
+    Verified { session_key: Vec<u8> },
+    Failed,
+}
+
+impl<'a> ServerSession<'a> {
+    /// Starts a session with a random challenge of [`DEFAULT_CHALLENGE_LEN`]
+    /// bytes
+    pub fn new(passcode: &'a Passcode) -> Result<Self, Error> {
+        Self::with_rng(passcode, SharedRng::os())
+    }
+
+    /// Starts a session with a challenge drawn from `rng`
+    pub fn with_rng(passcode: &'a Passcode, mut rng: impl CryptoRngCore) -> Result<Self, Error> {
+        let mut challenge = vec![0u8; DEFAULT_CHALLENGE_LEN];
+        rng.try_fill_bytes(&mut challenge)
+            .map_err(|e| Error::RandomSource(e.to_string()))?;
+        Ok(Self::with_challenge(passcode, challenge))
+    }
+
+    /// Starts a session for the given challenge message, e.g.
+    /// [`Challenge::message`] of a challenge issued by a manager
+    pub fn with_challenge(passcode: &'a Passcode, message: Vec<u8>) -> Self {
+        Self {
+            passcode,
+            message,
+            server_proof: false,
+            rounds: 1,
+            round: 0,
+            state: ServerState::AwaitingResponse,
+        }
+    }
+
+    /// Answers an accepted response with a [`Message::ServerProof`]
+    pub fn with_server_proof(mut self) -> Self {
+        self.server_proof = true;
+        self
+    }
+
+    /// Requires `rounds` consecutive correct responses, e.g. for high-value
+    /// operations
+    ///
+    /// The client must use the same number of rounds. Zero is treated as
+    /// one.
+    pub fn with_rounds(mut self, rounds: u32) -> Self {
+        self.rounds = rounds.max(1);
+        self
+    }
+
+    /// Returns the number of rounds answered correctly so far
+    pub fn rounds_completed(&self) -> u32 {
+        self.round
+    }
+
+    /// Gets the current round's challenge message to send to the client
+    pub fn challenge(&self) -> Message {
+        Message::Challenge(self.message.clone())
+    }
+
+    /// Gets the message the session is waiting for, or `None` once it is
+    /// finished
+    pub fn expected(&self) -> Option<MessageKind> {
+        match self.state {
+            ServerState::AwaitingResponse => Some(MessageKind::Response),
+            ServerState::Verified { .. } | ServerState::Failed => None,
+        }
+    }
+
+    /// Handles a message from the client, returning the message to send back
+    ///
+    /// Fails with [`Error::OtpMismatch`] when the response is wrong. The
+    /// session key and server proof are bound to the last round's challenge,
+    /// and so to every round before it.
+    pub fn handle(&mut self, message: Message) -> Result<Option<Message>, Error> {
+        let otp = match (&self.state, message) {
+            (ServerState::AwaitingResponse, Message::Response(otp)) => otp,
+            (_, message) => {
+                return Err(Error::UnexpectedMessage {
+                    expected: self.expected(),
+                    received: message.kind(),
+                })
+            }
+        };
+
+        if !self.passcode.verify(&self.message, &otp) {
+            self.state = ServerState::Failed;
+            return Err(Error::OtpMismatch);
+        }
+
+        self.round += 1;
+        if self.round < self.rounds {
+            // Chain from the expected OTP, which the client computed too,
+            // rather than the submitted one
+            let otp = self.passcode.compute(&self.message);
+            self.message = next_challenge(&self.message, &otp);
+            return Ok(Some(self.challenge()));
+        }
+        self.state = ServerState::Verified {
+            session_key: self
+                .passcode
+                .derive_session_key(&self.message, SESSION_KEY_LEN),
+        };
+        Ok(self
+            .server_proof
+            .then(|| Message::ServerProof(server_proof(self.passcode, &self.message))))
+    }
+
+    /// Returns true once the client's responses for all rounds were accepted
+    pub fn is_verified(&self) -> bool {
+        matches!(self.state, ServerState::Verified { .. })
+    }
+
+    /// Gets the session key, available once the response was accepted
+    pub fn session_key(&self) -> Option<&[u8]> {
+        match &self.state {
+            ServerState::Verified { session_key } => Some(session_key),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for ServerSession<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServerSession")
+            .field("expected", &self.expected())
+            .field("verified", &self.is_verified())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Client side of the protocol
+///
+/// The client passes the server's challenge to [`handle`](Self::handle) and
+/// sends back the returned response. Without a server proof the client
+/// cannot tell whether the server accepted the response; with
+/// [`with_server_proof`](Self::with_server_proof) the session only completes
+/// once the server proved it holds the key.
+pub struct ClientSession<'a> {
+    passcode: &'a Passcode,
+    server_proof: bool,
+    rounds: u32,
+    round: u32,
+    state: ClientState,
+}
+
+enum ClientState {
+    /// `next` is the challenge the transcript requires, after the first round
+    AwaitingChallenge {
+        next: Option<Vec<u8>>,
+    },
+    AwaitingProof {
+        message: Vec<u8>,
+    },
+    Complete {
+        session_key: Vec<u8>,
+    },
+    Failed,
+}
+
+impl<'a> ClientSession<'a> {
+    /// Creates a session waiting for a challenge
+    pub fn new(passcode: &'a Passcode) -> Self {
+        Self {
+            passcode,
+            server_proof: false,
+            rounds: 1,
+            round: 0,
+            state: ClientState::AwaitingChallenge { next: None },
+        }
+    }
+
+    /// Requires a [`Message::ServerProof`] before the session completes
+    pub fn with_server_proof(mut self) -> Self {
+        self.server_proof = true;
+        self
+    }
+
+    /// Answers `rounds` consecutive challenges, matching
+    /// [`ServerSession::with_rounds`]
+    ///
+    /// Zero is treated as one.
+    pub fn with_rounds(mut self, rounds: u32) -> Self {
+        self.rounds = rounds.max(1);
+        self
+    }
+
+    /// Returns the number of challenges answered so far
+    pub fn rounds_completed(&self) -> u32 {
+        self.round
+    }
+
+    /// Gets the message the session is waiting for, or `None` once it is
+    /// finished
+    pub fn expected(&self) -> Option<MessageKind> {
+        match self.state {
+            ClientState::AwaitingChallenge { .. } => Some(MessageKind::Challenge),
+            ClientState::AwaitingProof { .. } => Some(MessageKind::ServerProof),
+            ClientState::Complete { .. } | ClientState::Failed => None,
+        }
+    }
+
+    /// Handles a message from the server, returning the message to send back
+    ///
+    /// Fails with [`Error::InvalidChallenge`] when a later round's challenge
+    /// does not follow from the transcript, and with
+    /// [`Error::ServerProofMismatch`] when the server's proof is wrong.
+    pub fn handle(&mut self, message: Message) -> Result<Option<Message>, Error> {
+        match (&self.state, message) {
+            (ClientState::AwaitingChallenge { next }, Message::Challenge(challenge)) => {
+                if next.as_ref().is_some_and(|next| *next != challenge) {
+                    self.state = ClientState::Failed;
+                    return Err(Error::InvalidChallenge(
+                        "challenge does not follow the transcript",
+                    ));
+                }
+                let otp = self.passcode.compute(&challenge);
+                self.round += 1;
+                self.state = if self.round < self.rounds {
+                    ClientState::AwaitingChallenge {
+                        next: Some(next_challenge(&challenge, &otp)),
+                    }
+                } else if self.server_proof {
+                    ClientState::AwaitingProof { message: challenge }
+                } else {
+                    ClientState::Complete {
+                        session_key: self
+                            .passcode
+                            .derive_session_key(&challenge, SESSION_KEY_LEN),
+                    }
+                };
+                Ok(Some(Message::Response(otp)))
+            }
+            (ClientState::AwaitingProof { message }, Message::ServerProof(proof)) => {
+                let expected = server_proof(self.passcode, message);
+                if !bool::from(expected.ct_eq(&proof)) {
+                    self.state = ClientState::Failed;
+                    return Err(Error::ServerProofMismatch);
+                }
+                self.state = ClientState::Complete {
+                    session_key: self.passcode.derive_session_key(message, SESSION_KEY_LEN),
+                };
+                Ok(None)
+            }
+            (_, message) => Err(Error::UnexpectedMessage {
+                expected: self.expected(),
+                received: message.kind(),
+            }),
+        }
+    }
+
+    /// Returns true once the last round's response was sent and, if
+    /// required, the server's proof checked
+    pub fn is_complete(&self) -> bool {
+        matches!(self.state, ClientState::Complete { .. })
+    }
+
+    /// Gets the session key, available once the session is complete
+    pub fn session_key(&self) -> Option<&[u8]> {
+        match &self.state {
+            ClientState::Complete { session_key } => Some(session_key),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for ClientSession<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientSession")
+            .field("expected", &self.expected())
+            .field("complete", &self.is_complete())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Either side of the mutual challenge-response protocol
+///
+/// The client's proof is the OTP over the transcript of both nonces; the
+/// server's proof and the session key are derived from the same transcript
+/// under separate labels, so neither proof can stand in for the other.
+pub struct MutualSession<'a> {
+    passcode: &'a Passcode,
+    state: MutualState,
+    rng: SharedRng,
+}
+
+enum MutualState {
+    AwaitingClientNonce,
+    AwaitingServerNonce { client_nonce: Vec<u8> },
+    AwaitingClientProof { transcript: Vec<u8> },
+    AwaitingServerProof { transcript: Vec<u8> },
+    Complete { session_key: Vec<u8> },
+    Failed,
+}
+
+impl<'a> MutualSession<'a> {
+    /// Starts the client side, returning the [`Message::ClientNonce`] to send
+    pub fn client(passcode: &'a Passcode) -> Result<(Self, Message), Error> {
+        Self::client_with_rng(passcode, SharedRng::os())
+    }
+
+    /// Starts the client side with its nonce drawn from `rng`
+    pub fn client_with_rng(
+        passcode: &'a Passcode,
+        rng: impl CryptoRngCore + Send + 'static,
+    ) -> Result<(Self, Message), Error> {
+        let rng = SharedRng::new(rng);
+        let mut client_nonce = vec![0u8; MUTUAL_NONCE_LEN];
+        rng.fill(&mut client_nonce)?;
+        let message = Message::ClientNonce(client_nonce.clone());
+        let session = Self {
+            passcode,
+            state: MutualState::AwaitingServerNonce { client_nonce },
+            rng,
+        };
+        Ok((session, message))
+    }
+
+    /// Creates the server side, waiting for the client's nonce
+    pub fn server(passcode: &'a Passcode) -> Self {
+        Self {
+            passcode,
+            state: MutualState::AwaitingClientNonce,
+            rng: SharedRng::os(),
+        }
+    }
+
+    /// Creates the server side with its nonce drawn from `rng`
+    pub fn server_with_rng(
+        passcode: &'a Passcode,
+        rng: impl CryptoRngCore + Send + 'static,
+    ) -> Self {
+        Self {
+            rng: SharedRng::new(rng),
+            ..Self::server(passcode)
+        }
+    }
+
+    /// Gets the message the session is waiting for, or `None` once it is
+    /// finished
+    pub fn expected(&self) -> Option<MessageKind> {
+        match self.state {
+            MutualState::AwaitingClientNonce => Some(MessageKind::ClientNonce),
+            MutualState::AwaitingServerNonce { .. } => Some(MessageKind::Challenge),
+            MutualState::AwaitingClientProof { .. } => Some(MessageKind::Response),
+            MutualState::AwaitingServerProof { .. } => Some(MessageKind::ServerProof),
+            MutualState::Complete { .. } | MutualState::Failed => None,
+        }
+    }
+
+    /// Handles a message from the peer, returning the message to send back
+    ///
+    /// The server fails with [`Error::OtpMismatch`] when the client's proof
+    /// is wrong, and the client with [`Error::ServerProofMismatch`] when the
+    /// server's proof is wrong. Either failure ends the session.
+    pub fn handle(&mut self, message: Message) -> Result<Option<Message>, Error> {
+        match (&self.state, message) {
+            (MutualState::AwaitingClientNonce, Message::ClientNonce(client_nonce)) => {
+                let mut server_nonce = vec![0u8; MUTUAL_NONCE_LEN];
+                self.rng.fill(&mut server_nonce)?;
+                self.state = MutualState::AwaitingClientProof {
+                    transcript: mutual_transcript(&client_nonce, &server_nonce),
+                };
+                Ok(Some(Message::Challenge(server_nonce)))
+            }
+            (
+                MutualState::AwaitingServerNonce { client_nonce },
+                Message::Challenge(server_nonce),
+            ) => {
+                let transcript = mutual_transcript(client_nonce, &server_nonce);
+                let proof = self.passcode.compute(&transcript);
+                self.state = MutualState::AwaitingServerProof { transcript };
+                Ok(Some(Message::Response(proof)))
+            }
+            (MutualState::AwaitingClientProof { transcript }, Message::Response(proof)) => {
+                if !self.passcode.verify(transcript, &proof) {
+                    self.state = MutualState::Failed;
+                    return Err(Error::OtpMismatch);
+                }
+                let proof = server_proof(self.passcode, transcript);
+                self.complete();
+                Ok(Some(Message::ServerProof(proof)))
+            }
+            (MutualState::AwaitingServerProof { transcript }, Message::ServerProof(proof)) => {
+                let expected = server_proof(self.passcode, transcript);
+                if !bool::from(expected.ct_eq(&proof)) {
+                    self.state = MutualState::Failed;
+                    return Err(Error::ServerProofMismatch);
+                }
+                self.complete();
+                Ok(None)
+            }
+            (_, message) => Err(Error::UnexpectedMessage {
+                expected: self.expected(),
+                received: message.kind(),
+            }),
+        }
+    }
+
+    /// Returns true once both sides proved they hold the key
+    pub fn is_complete(&self) -> bool {
+        matches!(self.state, MutualState::Complete { .. })
+    }
+
+    /// Gets the session key, available once the session is complete
+    pub fn session_key(&self) -> Option<&[u8]> {
+        match &self.state {
+            MutualState::Complete { session_key } => Some(session_key),
+            _ => None,
+        }
+    }
+
+    fn complete(&mut self) {
+        let transcript = match &self.state {
+            MutualState::AwaitingClientProof { transcript }
+            | MutualState::AwaitingServerProof { transcript } => transcript,
+            _ => unreachable!("only called while awaiting a proof"),
+        };
+        self.state = MutualState::Complete {
+            session_key: self
+                .passcode
+                .derive_session_key(transcript, SESSION_KEY_LEN),
+        };
+    }
+}
+
+impl fmt::Debug for MutualSession<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MutualSession")
+            .field("expected", &self.expected())
+            .field("complete", &self.is_complete())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Builds the transcript both proofs of a mutual session are computed over
+fn mutual_transcript(client_nonce: &[u8], server_nonce: &[u8]) -> Vec<u8> {
+    let mut transcript =
+        Vec::with_capacity(MUTUAL_CONTEXT.len() + 8 + client_nonce.len() + server_nonce.len());
+    transcript.extend_from_slice(MUTUAL_CONTEXT);
+    for nonce in [client_nonce, server_nonce] {
+        transcript.extend_from_slice(&(nonce.len() as u32).to_be_bytes());
+        transcript.extend_from_slice(nonce);
+    }
+    transcript
+}
+
+/// Derives the next round's challenge from the previous challenge and its OTP
+fn next_challenge(challenge: &[u8], otp: &str) -> Vec<u8> {
+    let mut hasher = blake3::Hasher::new_derive_key(ROUND_CONTEXT);
+    hasher.update(&(challenge.len() as u64).to_be_bytes());
+    hasher.update(challenge);
+    hasher.update(otp.as_bytes());
+    hasher.finalize().as_bytes().to_vec()
+}
+
+fn server_proof(passcode: &Passcode, message: &[u8]) -> Vec<u8> {
+    passcode.derive_labeled(labels::SERVER_PROOF, message, SERVER_PROOF_LEN)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Algorithm, Passcode};
+    use pollster::block_on;
+
+    fn passcode() -> Passcode {
+        Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32])
+    }
+
+    #[test]
+    fn test_verified_session_key_matches_client() {
+        let manager = ChallengeManager::new(passcode());
+        let binding = ChallengeBinding::new().with_user_id("alice");
+        let issued = block_on(manager.begin(binding.clone())).unwrap();
+        let message = issued.challenge().message();
+        let id = *issued.challenge().id();
+
+        let session = block_on(issued.verify(&manager, &passcode().compute(&message))).unwrap();
+
+        assert_eq!(session.challenge_id(), &id);
+        assert_eq!(session.binding(), &binding);
+        assert_eq!(
+            session.session_key(),
+            passcode().derive_session_key(&message, SESSION_KEY_LEN)
+        );
+        assert!(!format!("{:?}", session).contains(&format!("{:?}", session.session_key())));
+    }
+
+    #[test]
+    fn test_failed_verification_yields_no_session() {
+        let manager = ChallengeManager::new(passcode());
+        let issued = block_on(manager.begin(ChallengeBinding::new())).unwrap();
+
+        assert_eq!(
+            block_on(issued.verify(&manager, "000000000000")).unwrap_err(),
+            Error::OtpMismatch
+        );
+    }
+
+    #[test]
+    fn test_protocol_round_trip() {
+        let key = passcode();
+        let mut server = ServerSession::new(&key).unwrap().with_server_proof();
+        let mut client = ClientSession::new(&key).with_server_proof();
+
+        let response = client.handle(server.challenge()).unwrap().unwrap();
+        assert_eq!(client.expected(), Some(MessageKind::ServerProof));
+        assert_eq!(client.session_key(), None);
+
+        let proof = server.handle(response).unwrap().unwrap();
+        assert_eq!(proof.kind(), MessageKind::ServerProof);
+        assert_eq!(client.handle(proof), Ok(None));
+
+        assert!(server.is_verified() && client.is_complete());
+        assert_eq!(server.session_key(), client.session_key());
+        assert_eq!(server.expected(), None);
+    }
+
+    #[test]
+    fn test_out_of_order_messages() {
+        let key = passcode();
+        let mut server = ServerSession::with_challenge(&key, b"challenge".to_vec());
+        let mut client = ClientSession::new(&key);
+
+        assert_eq!(
+            client.handle(Message::ServerProof(vec![0; SERVER_PROOF_LEN])),
+            Err(Error::UnexpectedMessage {
+                expected: Some(MessageKind::Challenge),
+                received: MessageKind::ServerProof,
+            })
+        );
+        assert_eq!(
+            server.handle(server.challenge()),
+            Err(Error::UnexpectedMessage {
+                expected: Some(MessageKind::Response),
+                received: MessageKind::Challenge,
+            })
+        );
+
+        let response = client.handle(server.challenge()).unwrap().unwrap();
+        // Without a required proof the client is done after responding
+        assert!(client.is_complete());
+        assert_eq!(server.handle(response.clone()), Ok(None));
+        assert_eq!(
+            server.handle(response),
+            Err(Error::UnexpectedMessage {
+                expected: None,
+                received: MessageKind::Response,
+            })
+        );
+    }
+
+    #[test]
+    fn test_wrong_response_and_proof_fail() {
+        let key = passcode();
+        let other = Passcode::new(Algorithm::Sha3Kmac256, vec![2u8; 32]);
+
+        let mut server = ServerSession::new(&key).unwrap();
+        let mut client = ClientSession::new(&other);
+        let response = client.handle(server.challenge()).unwrap().unwrap();
+        assert_eq!(server.handle(response), Err(Error::OtpMismatch));
+        assert_eq!(server.expected(), None);
+        assert_eq!(server.session_key(), None);
+
+        let mut client = ClientSession::new(&key).with_server_proof();
+        client
+            .handle(Message::Challenge(b"challenge".to_vec()))
+            .unwrap();
+        let proof = Message::ServerProof(server_proof(&other, b"challenge"));
+        assert_eq!(client.handle(proof), Err(Error::ServerProofMismatch));
+        assert!(!client.is_complete());
+    }
+
+    #[test]
+    fn test_multi_round() {
+        let key = passcode();
+        let mut server = ServerSession::new(&key)
+            .unwrap()
+            .with_rounds(3)
+            .with_server_proof();
+        let mut client = ClientSession::new(&key).with_rounds(3).with_server_proof();
+
+        let mut message = server.challenge();
+        let mut challenges = Vec::new();
+        for round in 0..3 {
+            assert!(!server.is_verified());
+            assert_eq!(server.rounds_completed(), round);
+            challenges.push(message.clone());
+            let response = client.handle(message).unwrap().unwrap();
+            message = server.handle(response).unwrap().unwrap();
+        }
+        assert_eq!(message.kind(), MessageKind::ServerProof);
+        assert_eq!(client.handle(message), Ok(None));
+
+        assert!(server.is_verified() && client.is_complete());
+        assert_eq!(client.rounds_completed(), 3);
+        assert_eq!(server.session_key(), client.session_key());
+        // Every round has a distinct challenge
+        assert_ne!(challenges[0], challenges[1]);
+        assert_ne!(challenges[1], challenges[2]);
+    }
+
+    #[test]
+    fn test_multi_round_rejects_broken_chain() {
+        let key = passcode();
+        let mut server = ServerSession::new(&key).unwrap().with_rounds(2);
+        let mut client = ClientSession::new(&key).with_rounds(2);
+
+        let response = client.handle(server.challenge()).unwrap().unwrap();
+        server.handle(response).unwrap().unwrap();
+        assert_eq!(
+            client.handle(Message::Challenge(b"fresh".to_vec())),
+            Err(Error::InvalidChallenge(
+                "challenge does not follow the transcript"
+            ))
+        );
+        assert!(!client.is_complete());
+
+        // A wrong answer in a later round fails the whole session
+        assert_eq!(
+            server.handle(Message::Response("000000000000".to_string())),
+            Err(Error::OtpMismatch)
+        );
+        assert!(!server.is_verified());
+        assert_eq!(server.rounds_completed(), 1);
+    }
+
+    #[test]
+    fn test_mutual_session() {
+        let key = passcode();
+        let (mut client, client_nonce) = MutualSession::client(&key).unwrap();
+        let mut server = MutualSession::server(&key);
+
+        let server_nonce = server.handle(client_nonce).unwrap().unwrap();
+        assert_eq!(server.expected(), Some(MessageKind::Response));
+        let client_proof = client.handle(server_nonce).unwrap().unwrap();
+        let server_proof = server.handle(client_proof).unwrap().unwrap();
+        assert!(server.is_complete() && !client.is_complete());
+        assert_eq!(client.handle(server_proof), Ok(None));
+
+        assert_eq!(client.session_key(), server.session_key());
+        assert_eq!(client.session_key().unwrap().len(), SESSION_KEY_LEN);
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_mutual_session_with_seeded_rng() {
+        use crate::rng::SeededRng;
+
+        let key = passcode();
+        let run = || {
+            let (mut client, client_nonce) =
+                MutualSession::client_with_rng(&key, SeededRng::new(1)).unwrap();
+            let mut server = MutualSession::server_with_rng(&key, SeededRng::new(2));
+            let server_nonce = server.handle(client_nonce).unwrap().unwrap();
+            let client_proof = client.handle(server_nonce).unwrap().unwrap();
+            server.handle(client_proof).unwrap();
+            server.session_key().unwrap().to_vec()
+        };
+        assert_eq!(run(), run());
+
+        let challenge = |seed| {
+            ServerSession::with_rng(&key, SeededRng::new(seed))
+                .unwrap()
+                .challenge()
+        };
+        assert_eq!(challenge(3), challenge(3));
+    }
+
+    #[test]
+    fn test_mutual_session_detects_fake_server() {
+        let key = passcode();
+        let fake = Passcode::new(Algorithm::Sha3Kmac256, vec![2u8; 32]);
+        let (mut client, client_nonce) = MutualSession::client(&key).unwrap();
+
+        let nonce = match &client_nonce {
+            Message::ClientNonce(nonce) => nonce.clone(),
+            _ => unreachable!(),
+        };
+        let transcript = mutual_transcript(&nonce, b"server nonce");
+        client
+            .handle(Message::Challenge(b"server nonce".to_vec()))
+            .unwrap();
+        assert_eq!(
+            client.handle(Message::ServerProof(server_proof(&fake, &transcript))),
+            Err(Error::ServerProofMismatch)
+        );
+        assert_eq!(client.session_key(), None);
+
+        // A server without the client's key rejects its proof
+        let (mut client, client_nonce) = MutualSession::client(&key).unwrap();
+        let mut server = MutualSession::server(&fake);
+        let server_nonce = server.handle(client_nonce.clone()).unwrap().unwrap();
+        let client_proof = client.handle(server_nonce).unwrap().unwrap();
+        assert_eq!(server.handle(client_proof), Err(Error::OtpMismatch));
+        assert_eq!(
+            server.handle(client_nonce),
+            Err(Error::UnexpectedMessage {
+                expected: None,
+                received: MessageKind::ClientNonce,
+            })
+        );
+    }
+
+    #[test]
+    fn test_message_encoding() {
+        for message in [
+            Message::Challenge(vec![1, 2, 3]),
+            Message::Response("abc123".to_string()),
+            Message::ServerProof(vec![]),
+            Message::ClientNonce(vec![4; 32]),
+        ] {
+            assert_eq!(Message::from_bytes(&message.to_bytes()), Ok(message));
+        }
+        assert!(Message::from_bytes(&[]).is_err());
+        assert!(Message::from_bytes(&[9]).is_err());
+        assert!(Message::from_bytes(&[TAG_RESPONSE, 0xff]).is_err());
+    }
+}
@@ -129,9 +129,12 @@ fn kmac256(
     output
 }
 
+/// KMAC customization string used for OTP computation
+pub(crate) const PASSCODE_CUSTOMIZATION: &[u8] = b"authorization";
+
 /// SHA3-KMAC128 for passcode (internal use)
 pub fn sha3_kmac128_for_passcode(key: &[u8], data: &[u8]) -> Vec<u8> {
-    kmac128(key, b"authorization", data, 32)
+    kmac128(key, PASSCODE_CUSTOMIZATION, data, 32)
 }
 
 /// SHA3-KMAC128 with customizable parameters
@@ -146,7 +149,12 @@ pub fn sha3_kmac128(
 
 /// SHA3-KMAC256 for passcode (internal use)
 pub fn sha3_kmac256_for_passcode(key: &[u8], data: &[u8]) -> Vec<u8> {
-    kmac256(key, b"authorization", data, 32)
+    kmac256(key, PASSCODE_CUSTOMIZATION, data, 32)
+}
+
+/// SHA3-KMAC256 with 512-bit output for passcode (internal use)
+pub fn sha3_kmac512_for_passcode(key: &[u8], data: &[u8]) -> Vec<u8> {
+    kmac256(key, PASSCODE_CUSTOMIZATION, data, 64)
 }
 
 /// SHA3-KMAC256 with customizable parameters
@@ -0,0 +1,420 @@
+//! Shamir secret sharing of the shared key
+//!
+//! [`split_key`] splits a key into `n` shares of which any `k` recover it
+//! with [`recover_key`], while fewer reveal nothing about it beyond its
+//! length and the [`KeyId`] fingerprint every share carries. Custodians
+//! each keep one share, so no single person can use or lose the key.
+//!
+//! Each key byte is the constant term of a random polynomial of degree
+//! `k - 1` over GF(2^8) (the AES field), and share `x` holds the values of
+//! the polynomials at `x`. Every share also carries the threshold and the
+//! [`KeyId`] of the key: shares of different keys are refused, and the
+//! recovered key is checked against the Key-ID.
+//!
+//! A share is written as `passcode-share:` followed by lowercase hex of
+//!
+//! | Field | Length |
+//! |---|---|
+//! | version, [`SHARE_VERSION`] | 1 byte |
+//! | threshold `k` | 1 byte |
+//! | index `x`, 1 to 255 | 1 byte |
+//! | [`KeyId`] of the key | 8 bytes |
+//! | share value | key length |
+//! | checksum, the first 4 bytes of BLAKE3 over the fields above | 4 bytes |
+//!
+//! so a mistyped share is caught before recovery.
+//!
+//! # Example
+//! ```
+//! use passcode::shamir::{recover_key, split_key, KeyShare};
+//!
+//! let key = [7u8; 32];
+//! let shares = split_key(&key, 5, 3).unwrap();
+//!
+//! // Any three custodians
+//! let text: Vec<String> = shares[1..4].iter().map(ToString::to_string).collect();
+//! let parsed: Vec<KeyShare> = text.iter().map(|s| s.parse().unwrap()).collect();
+//! assert_eq!(recover_key(&parsed).unwrap(), key);
+//! assert!(recover_key(&parsed[..2]).is_err());
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::kdf::{key_fingerprint, KeyId, KEY_ID_LEN};
+use crate::rng::{CryptoRngCore, SharedRng};
+use crate::Error;
+
+/// Version byte of the share encoding
+pub const SHARE_VERSION: u8 = 1;
+
+/// Prefix of an encoded share
+pub const SHARE_PREFIX: &str = "passcode-share:";
+
+/// Version, threshold and index
+const HEADER_LEN: usize = 3;
+const CHECKSUM_LEN: usize = 4;
+
+/// One custodian's share of a key
+#[derive(Clone, PartialEq, Eq)]
+pub struct KeyShare {
+    threshold: u8,
+    index: u8,
+    key_id: KeyId,
+    value: Vec<u8>,
+}
+
+impl fmt::Debug for KeyShare {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The share value is never printed
+        f.debug_struct("KeyShare")
+            .field("threshold", &self.threshold)
+            .field("index", &self.index)
+            .field("key_id", &self.key_id)
+            .finish()
+    }
+}
+
+impl KeyShare {
+    /// Gets the number of shares needed to recover the key
+    pub fn threshold(&self) -> u8 {
+        self.threshold
+    }
+
+    /// Gets the share's position, 1 to 255
+    pub fn index(&self) -> u8 {
+        self.index
+    }
+
+    /// Gets the fingerprint of the key the share belongs to
+    pub fn key_id(&self) -> KeyId {
+        self.key_id
+    }
+
+    /// Gets the share value
+    pub(crate) fn value(&self) -> &[u8] {
+        &self.value
+    }
+
+    /// Encodes the share without the text prefix
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes =
+            Vec::with_capacity(HEADER_LEN + KEY_ID_LEN + self.value.len() + CHECKSUM_LEN);
+        bytes.extend_from_slice(&[SHARE_VERSION, self.threshold, self.index]);
+        bytes.extend_from_slice(self.key_id.as_bytes());
+        bytes.extend_from_slice(&self.value);
+        let checksum = checksum(&bytes);
+        bytes.extend_from_slice(&checksum);
+        bytes
+    }
+
+    /// Decodes a share written by [`to_bytes`](Self::to_bytes)
+    ///
+    /// Fails with [`Error::MalformedMessage`] for a wrong checksum, another
+    /// version or an invalid threshold or index.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() < HEADER_LEN + KEY_ID_LEN + CHECKSUM_LEN {
+            return Err(Error::MalformedMessage("truncated message"));
+        }
+        let (body, sum) = bytes.split_at(bytes.len() - CHECKSUM_LEN);
+        if checksum(body) != sum {
+            return Err(Error::MalformedMessage("share checksum mismatch"));
+        }
+        if body[0] != SHARE_VERSION {
+            return Err(Error::MalformedMessage("unsupported share version"));
+        }
+        let (threshold, index) = (body[1], body[2]);
+        if threshold == 0 || index == 0 {
+            return Err(Error::MalformedMessage("invalid share index"));
+        }
+        let (key_id, value) = body[HEADER_LEN..].split_at(KEY_ID_LEN);
+
+        Ok(Self {
+            threshold,
+            index,
+            key_id: KeyId::from_bytes(key_id.try_into().expect("split at KEY_ID_LEN")),
+            value: value.to_vec(),
+        })
+    }
+}
+
+impl fmt::Display for KeyShare {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", SHARE_PREFIX, hex::encode(self.to_bytes()))
+    }
+}
+
+impl FromStr for KeyShare {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let encoded = s
+            .trim()
+            .strip_prefix(SHARE_PREFIX)
+            .ok_or(Error::MalformedMessage("not a passcode key share"))?;
+        let bytes = hex::decode(encoded).map_err(|_| Error::MalformedMessage("invalid hex"))?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+/// Splits a key into `n` shares, any `k` of which recover it
+///
+/// Fails with [`Error::InvalidFormat`] unless `1 <= k <= n`.
+///
+/// # Panics
+/// Panics if the operating system's CSPRNG fails.
+pub fn split_key(key: &[u8], n: u8, k: u8) -> Result<Vec<KeyShare>, Error> {
+    split_key_with_rng(key, n, k, &mut SharedRng::os())
+}
+
+/// Splits a key like [`split_key`], drawing the polynomial coefficients
+/// from `rng`
+pub fn split_key_with_rng(
+    key: &[u8],
+    n: u8,
+    k: u8,
+    rng: &mut impl CryptoRngCore,
+) -> Result<Vec<KeyShare>, Error> {
+    check_threshold(n, k)?;
+    let mut coefficients = vec![0u8; key.len() * usize::from(k - 1)];
+    rng.fill_bytes(&mut coefficients);
+    let shares = split_with_coefficients(key, n, k, &coefficients);
+    coefficients.fill(0);
+    Ok(shares)
+}
+
+pub(crate) fn check_threshold(n: u8, k: u8) -> Result<(), Error> {
+    if k == 0 || k > n {
+        return Err(Error::InvalidFormat(
+            "share threshold must be between 1 and the number of shares",
+        ));
+    }
+    Ok(())
+}
+
+/// Splits with the given non-constant coefficients, `k - 1` per key byte
+pub(crate) fn split_with_coefficients(
+    key: &[u8],
+    n: u8,
+    k: u8,
+    coefficients: &[u8],
+) -> Vec<KeyShare> {
+    let degree = usize::from(k - 1);
+    debug_assert_eq!(coefficients.len(), key.len() * degree);
+
+    let key_id = key_fingerprint(key);
+    (1..=n)
+        .map(|x| {
+            let value = key
+                .iter()
+                .enumerate()
+                .map(|(i, &secret)| {
+                    evaluate(secret, &coefficients[i * degree..(i + 1) * degree], x)
+                })
+                .collect();
+            KeyShare {
+                threshold: k,
+                index: x,
+                key_id,
+                value,
+            }
+        })
+        .collect()
+}
+
+/// Recovers a key from at least its threshold of shares
+///
+/// Extra shares beyond the threshold are ignored. Fails with
+/// [`Error::InsufficientShares`] for too few distinct shares and
+/// [`Error::MalformedMessage`] for shares of different keys or a recovered
+/// key that does not match the Key-ID.
+pub fn recover_key(shares: &[KeyShare]) -> Result<Vec<u8>, Error> {
+    let first = shares.first().ok_or(Error::InsufficientShares {
+        threshold: 1,
+        provided: 0,
+    })?;
+    if shares.iter().any(|share| {
+        share.threshold != first.threshold
+            || share.key_id != first.key_id
+            || share.value.len() != first.value.len()
+    }) {
+        return Err(Error::MalformedMessage("shares belong to different keys"));
+    }
+
+    let mut selected: Vec<&KeyShare> = Vec::with_capacity(usize::from(first.threshold));
+    for share in shares {
+        if selected.len() == usize::from(first.threshold) {
+            break;
+        }
+        if !selected.iter().any(|s| s.index == share.index) {
+            selected.push(share);
+        }
+    }
+    if selected.len() < usize::from(first.threshold) {
+        return Err(Error::InsufficientShares {
+            threshold: first.threshold,
+            provided: selected.len(),
+        });
+    }
+
+    // Lagrange basis polynomials evaluated at zero
+    let weights: Vec<u8> = selected
+        .iter()
+        .map(|share| {
+            selected
+                .iter()
+                .filter(|other| other.index != share.index)
+                .fold(1, |acc, other| {
+                    gf_mul(acc, gf_div(other.index, other.index ^ share.index))
+                })
+        })
+        .collect();
+    let key: Vec<u8> = (0..first.value.len())
+        .map(|i| {
+            selected
+                .iter()
+                .zip(&weights)
+                .fold(0, |acc, (share, &weight)| {
+                    acc ^ gf_mul(share.value[i], weight)
+                })
+        })
+        .collect();
+
+    if key_fingerprint(&key) != first.key_id {
+        return Err(Error::MalformedMessage(
+            "recovered key does not match its Key-ID",
+        ));
+    }
+    Ok(key)
+}
+
+/// Evaluates `secret + terms[0] x + terms[1] x^2 + ...` at `x` with
+/// Horner's rule
+fn evaluate(secret: u8, terms: &[u8], x: u8) -> u8 {
+    let higher = terms.iter().rev().fold(0, |acc, &c| gf_mul(acc, x) ^ c);
+    gf_mul(higher, x) ^ secret
+}
+
+fn checksum(bytes: &[u8]) -> [u8; CHECKSUM_LEN] {
+    let hash = blake3::hash(bytes);
+    hash.as_bytes()[..CHECKSUM_LEN]
+        .try_into()
+        .expect("hash is longer than the checksum")
+}
+
+/// Multiplies in GF(2^8) modulo x^8 + x^4 + x^3 + x + 1 without
+/// data-dependent branches
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    for _ in 0..8 {
+        product ^= a & 0u8.wrapping_sub(b & 1);
+        let carry = 0u8.wrapping_sub(a >> 7);
+        a = (a << 1) ^ (carry & 0x1b);
+        b >>= 1;
+    }
+    product
+}
+
+/// Divides in GF(2^8); `b` must not be zero
+fn gf_div(a: u8, b: u8) -> u8 {
+    // b^254 is the inverse of b
+    let mut inverse = 1;
+    let mut power = b;
+    for bit in 0..8 {
+        if (254u8 >> bit) & 1 == 1 {
+            inverse = gf_mul(inverse, power);
+        }
+        power = gf_mul(power, power);
+    }
+    gf_mul(a, inverse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_field_arithmetic() {
+        // FIPS 197, section 4.2
+        assert_eq!(gf_mul(0x57, 0x83), 0xc1);
+        assert_eq!(gf_mul(0x57, 0x13), 0xfe);
+        for b in 1..=u8::MAX {
+            assert_eq!(gf_mul(gf_div(1, b), b), 1);
+        }
+    }
+
+    #[test]
+    fn test_any_threshold_subset_recovers() {
+        let key: Vec<u8> = (0..32).collect();
+        let shares = split_key(&key, 5, 3).unwrap();
+        assert_eq!(shares.len(), 5);
+
+        for a in 0..5 {
+            for b in a + 1..5 {
+                for c in b + 1..5 {
+                    let subset = [shares[c].clone(), shares[a].clone(), shares[b].clone()];
+                    assert_eq!(recover_key(&subset).unwrap(), key);
+                }
+            }
+        }
+        assert_eq!(recover_key(&shares).unwrap(), key);
+    }
+
+    #[test]
+    fn test_too_few_shares() {
+        let shares = split_key(&[1u8; 16], 3, 2).unwrap();
+        assert_eq!(
+            recover_key(&[shares[0].clone(), shares[0].clone()]),
+            Err(Error::InsufficientShares {
+                threshold: 2,
+                provided: 1
+            })
+        );
+        assert!(recover_key(&[]).is_err());
+        assert!(split_key(&[1u8; 16], 2, 3).is_err());
+        assert!(split_key(&[1u8; 16], 2, 0).is_err());
+
+        // A single share of a 1-of-n split is the key itself
+        let copies = split_key(&[9u8; 16], 2, 1).unwrap();
+        assert_eq!(recover_key(&copies[1..]).unwrap(), [9u8; 16]);
+    }
+
+    #[test]
+    fn test_encoding() {
+        let shares = split_key(&[3u8; 32], 3, 2).unwrap();
+        let text = shares[2].to_string();
+        assert!(text.starts_with(SHARE_PREFIX));
+        assert_eq!(text.parse::<KeyShare>(), Ok(shares[2].clone()));
+        assert_eq!(shares[2].index(), 3);
+        assert_eq!(shares[2].threshold(), 2);
+        assert_eq!(shares[2].key_id(), key_fingerprint(&[3u8; 32]));
+
+        // Flip one hex digit of the value
+        let at = SHARE_PREFIX.len() + 2 * (HEADER_LEN + KEY_ID_LEN);
+        let mut typo = text.into_bytes();
+        typo[at] = if typo[at] == b'0' { b'1' } else { b'0' };
+        assert_eq!(
+            String::from_utf8(typo).unwrap().parse::<KeyShare>(),
+            Err(Error::MalformedMessage("share checksum mismatch"))
+        );
+    }
+
+    #[test]
+    fn test_rejects_mixed_keys() {
+        let first = split_key(&[1u8; 32], 3, 2).unwrap();
+        let second = split_key(&[2u8; 32], 3, 2).unwrap();
+        assert_eq!(
+            recover_key(&[first[0].clone(), second[1].clone()]),
+            Err(Error::MalformedMessage("shares belong to different keys"))
+        );
+
+        let mut forged = first[1].clone();
+        forged.value[0] ^= 1;
+        assert_eq!(
+            recover_key(&[first[0].clone(), forged]),
+            Err(Error::MalformedMessage(
+                "recovered key does not match its Key-ID"
+            ))
+        );
+    }
+}
@@ -0,0 +1,325 @@
+//! Split-secret keys held half by the server and half by the client
+//!
+//! In this mode the OTP key is derived from two halves: a server half
+//! stored in the server's database and a client half that only the client
+//! device keeps. The client sends its half with every response in a
+//! [`SplitResponse`] and the server derives the key from both halves to
+//! verify it, without ever storing the client half. A copy of the database
+//! therefore does not let an attacker compute OTPs.
+//!
+//! Enrollment:
+//!
+//! 1. The server creates a [`SplitServer`], stores its half and hands it to
+//!    the device, e.g. as the key of an `otpauth-cr://` URI.
+//! 2. The device creates a [`SplitClient`] from the server half, which
+//!    generates the client half, and keeps both.
+//!
+//! The key is `KMAC256(server half, client half)` with the
+//! [`labels::SPLIT_KEY`] customization, of the algorithm's recommended
+//! length. The client half travels with each response, so run the exchange
+//! over TLS like a password.
+//!
+//! # Example
+//! ```
+//! use passcode::split::{SplitClient, SplitServer};
+//! use passcode::Algorithm;
+//!
+//! let server = SplitServer::new(Algorithm::Sha3Kmac256);
+//! let client = SplitClient::new(Algorithm::Sha3Kmac256, server.server_half());
+//!
+//! let challenge = b"challenge";
+//! let response = client.respond(challenge).to_string();
+//! assert!(server.verify(challenge, &response.parse().unwrap()));
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+
+use base64ct::{Base64UrlUnpadded, Encoding};
+
+use crate::kdf::{derive_subkey, labels};
+use crate::rng::{CryptoRngCore, SharedRng};
+use crate::{Algorithm, Error, Passcode};
+
+/// Length in bytes of each key half
+pub const HALF_LEN: usize = 32;
+
+/// One half of a split key
+#[derive(Clone)]
+pub struct KeyHalf([u8; HALF_LEN]);
+
+impl fmt::Debug for KeyHalf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Keys are never printed
+        f.write_str("KeyHalf(..)")
+    }
+}
+
+impl KeyHalf {
+    /// Generates a half from the operating system's CSPRNG
+    ///
+    /// # Panics
+    /// Panics if the operating system's CSPRNG fails.
+    pub fn generate() -> Self {
+        let mut half = [0u8; HALF_LEN];
+        SharedRng::os()
+            .fill(&mut half)
+            .expect("operating system CSPRNG failed");
+        Self(half)
+    }
+
+    /// Generates a half from `rng`
+    pub fn generate_with_rng(rng: &mut impl CryptoRngCore) -> Self {
+        let mut half = [0u8; HALF_LEN];
+        rng.fill_bytes(&mut half);
+        Self(half)
+    }
+
+    /// Restores a stored half
+    ///
+    /// Fails with [`Error::MalformedMessage`] unless `bytes` is
+    /// [`HALF_LEN`] bytes long.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        bytes
+            .try_into()
+            .map(Self)
+            .map_err(|_| Error::MalformedMessage("key half must be 32 bytes"))
+    }
+
+    /// Gets the bytes of the half, for storage
+    pub fn as_bytes(&self) -> &[u8; HALF_LEN] {
+        &self.0
+    }
+}
+
+/// Derives the passcode of a split key from its two halves
+pub fn combine(algorithm: Algorithm, server_half: &KeyHalf, client_half: &KeyHalf) -> Passcode {
+    Passcode::new(
+        algorithm,
+        derive_subkey(
+            server_half.as_bytes(),
+            labels::SPLIT_KEY,
+            client_half.as_bytes(),
+            algorithm.recommended_key_len(),
+        ),
+    )
+}
+
+/// A client's OTP together with its key half
+///
+/// Encoded as `<client-half>.<otp>` with each part unpadded base64url.
+#[derive(Debug, Clone)]
+pub struct SplitResponse {
+    client_half: KeyHalf,
+    otp: String,
+}
+
+impl SplitResponse {
+    /// Creates a response from a client half and an OTP computed with the
+    /// combined key
+    pub fn new(client_half: KeyHalf, otp: impl Into<String>) -> Self {
+        Self {
+            client_half,
+            otp: otp.into(),
+        }
+    }
+
+    /// Gets the client's key half
+    pub fn client_half(&self) -> &KeyHalf {
+        &self.client_half
+    }
+
+    /// Gets the OTP
+    pub fn otp(&self) -> &str {
+        &self.otp
+    }
+}
+
+impl fmt::Display for SplitResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}.{}",
+            Base64UrlUnpadded::encode_string(self.client_half.as_bytes()),
+            Base64UrlUnpadded::encode_string(self.otp.as_bytes())
+        )
+    }
+}
+
+impl FromStr for SplitResponse {
+    type Err = Error;
+
+    /// Parses a response written by the [`Display`](fmt::Display)
+    /// implementation
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let (half, otp) = s
+            .split_once('.')
+            .ok_or(Error::MalformedMessage("split response has too few parts"))?;
+        let half = Base64UrlUnpadded::decode_vec(half)
+            .map_err(|_| Error::MalformedMessage("invalid base64url"))?;
+        let otp = Base64UrlUnpadded::decode_vec(otp)
+            .map_err(|_| Error::MalformedMessage("invalid base64url"))?;
+        Ok(Self {
+            client_half: KeyHalf::from_bytes(&half)?,
+            otp: String::from_utf8(otp).map_err(|_| Error::MalformedMessage("OTP is not UTF-8"))?,
+        })
+    }
+}
+
+/// Server side of a split key, holding only the server half
+#[derive(Debug)]
+pub struct SplitServer {
+    algorithm: Algorithm,
+    server_half: KeyHalf,
+}
+
+impl SplitServer {
+    /// Enrolls a new split key with a random server half
+    ///
+    /// # Panics
+    /// Panics if the operating system's CSPRNG fails.
+    pub fn new(algorithm: Algorithm) -> Self {
+        Self::from_half(algorithm, KeyHalf::generate())
+    }
+
+    /// Restores the server side from its stored half
+    pub fn from_half(algorithm: Algorithm, server_half: KeyHalf) -> Self {
+        Self {
+            algorithm,
+            server_half,
+        }
+    }
+
+    /// Gets the algorithm OTPs are computed with
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// Gets the server half, to store and to hand to the device at
+    /// enrollment
+    pub fn server_half(&self) -> &KeyHalf {
+        &self.server_half
+    }
+
+    /// Verifies a response by deriving the key from both halves
+    pub fn verify(&self, challenge: &[u8], response: &SplitResponse) -> bool {
+        combine(self.algorithm, &self.server_half, &response.client_half)
+            .verify(challenge, &response.otp)
+    }
+}
+
+/// Client side of a split key, holding both halves
+pub struct SplitClient {
+    passcode: Passcode,
+    client_half: KeyHalf,
+}
+
+impl fmt::Debug for SplitClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Keys are never printed
+        f.debug_struct("SplitClient")
+            .field("algorithm", &self.passcode.algorithm())
+            .finish()
+    }
+}
+
+impl SplitClient {
+    /// Completes enrollment with the server half, generating a random
+    /// client half
+    ///
+    /// # Panics
+    /// Panics if the operating system's CSPRNG fails.
+    pub fn new(algorithm: Algorithm, server_half: &KeyHalf) -> Self {
+        Self::from_halves(algorithm, server_half, KeyHalf::generate())
+    }
+
+    /// Restores the client side from both stored halves
+    pub fn from_halves(algorithm: Algorithm, server_half: &KeyHalf, client_half: KeyHalf) -> Self {
+        Self {
+            passcode: combine(algorithm, server_half, &client_half),
+            client_half,
+        }
+    }
+
+    /// Gets the client half, to store on the device
+    pub fn client_half(&self) -> &KeyHalf {
+        &self.client_half
+    }
+
+    /// Answers a challenge with an OTP and the client half
+    pub fn respond(&self, challenge: &[u8]) -> SplitResponse {
+        SplitResponse::new(self.client_half.clone(), self.passcode.compute(challenge))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_round_trip() {
+        for algorithm in Algorithm::all() {
+            let server = SplitServer::new(algorithm);
+            let client = SplitClient::new(algorithm, server.server_half());
+            let response = client.respond(b"challenge");
+            assert!(server.verify(b"challenge", &response));
+            assert!(!server.verify(b"other", &response));
+
+            let restored = SplitServer::from_half(
+                algorithm,
+                KeyHalf::from_bytes(server.server_half().as_bytes()).unwrap(),
+            );
+            assert!(restored.verify(b"challenge", &response));
+        }
+    }
+
+    #[test]
+    fn test_both_halves_are_needed() {
+        let server = SplitServer::new(Algorithm::Sha3Kmac256);
+        let client = SplitClient::new(Algorithm::Sha3Kmac256, server.server_half());
+        let otp = client.respond(b"challenge").otp().to_string();
+
+        // The server half alone does not give the key
+        let guessed = SplitResponse::new(KeyHalf::generate(), otp.clone());
+        assert!(!server.verify(b"challenge", &guessed));
+        assert_ne!(
+            Passcode::new(
+                Algorithm::Sha3Kmac256,
+                server.server_half().as_bytes().to_vec()
+            )
+            .compute(b"challenge"),
+            otp
+        );
+
+        let other = SplitServer::new(Algorithm::Sha3Kmac256);
+        assert!(!other.verify(b"challenge", &client.respond(b"challenge")));
+    }
+
+    #[test]
+    fn test_combine_is_kmac_of_halves() {
+        let server = KeyHalf::from_bytes(&[1u8; 32]).unwrap();
+        let client = KeyHalf::from_bytes(&[2u8; 32]).unwrap();
+        assert_eq!(
+            combine(Algorithm::Blake3KeyedMode128, &server, &client).key(),
+            derive_subkey(&[1u8; 32], labels::SPLIT_KEY, &[2u8; 32], 16)
+        );
+    }
+
+    #[test]
+    fn test_response_encoding() {
+        let server = SplitServer::new(Algorithm::Sha3Kmac256);
+        let client = SplitClient::new(Algorithm::Sha3Kmac256, server.server_half());
+        let text = client.respond(b"challenge").to_string();
+        let parsed: SplitResponse = text.parse().unwrap();
+        assert_eq!(
+            parsed.client_half().as_bytes(),
+            client.client_half().as_bytes()
+        );
+        assert!(server.verify(b"challenge", &parsed));
+
+        assert!("abc".parse::<SplitResponse>().is_err());
+        assert!("AAAA.AAAA".parse::<SplitResponse>().is_err());
+        assert!(KeyHalf::from_bytes(&[0u8; 16]).is_err());
+        assert_eq!(format!("{:?}", client.client_half()), "KeyHalf(..)");
+    }
+}
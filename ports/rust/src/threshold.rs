@@ -0,0 +1,256 @@
+//! Threshold approvals from k-of-n devices
+//!
+//! High-value actions can require several devices to approve. The account
+//! key is split into `n` [`KeyShare`]s with [`ThresholdKey`], one per
+//! device. Each device answers the challenge with a [`PartialResponse`], an
+//! OTP computed with its share, and the server accepts once `k` distinct
+//! devices have answered correctly.
+//!
+//! The shares are Shamir shares whose coefficients are derived from the
+//! account key under [`labels::THRESHOLD`], so the server recomputes them
+//! from the key instead of storing them, and any `k` devices can still
+//! rebuild the key with [`recover_key`](crate::shamir::recover_key). A
+//! partial response reveals nothing about the other shares; a stolen device
+//! contributes one approval, not `k`.
+//!
+//! # Example
+//! ```
+//! use passcode::threshold::{PartialResponse, ThresholdKey};
+//! use passcode::{Algorithm, Passcode};
+//!
+//! let server = ThresholdKey::new(Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]), 3, 2)
+//!     .unwrap();
+//! // Provisioning: device i keeps server.share(i)
+//! let phone = server.share(1).unwrap().clone();
+//! let laptop = server.share(3).unwrap().clone();
+//!
+//! let challenge = b"approve transfer #42";
+//! let partials = [
+//!     PartialResponse::compute(Algorithm::Sha3Kmac256, &phone, challenge),
+//!     PartialResponse::compute(Algorithm::Sha3Kmac256, &laptop, challenge),
+//! ];
+//! assert!(server.verify(challenge, &partials).is_ok());
+//! assert!(server.verify(challenge, &partials[..1]).is_err());
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::kdf::labels;
+use crate::shamir::{check_threshold, split_with_coefficients, KeyShare};
+use crate::{Algorithm, Error, Passcode};
+
+/// A device's answer to a threshold challenge
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartialResponse {
+    /// Index of the device's share
+    pub index: u8,
+    /// OTP computed with the share
+    pub otp: String,
+}
+
+impl PartialResponse {
+    /// Answers a challenge with a device's share
+    pub fn compute(algorithm: Algorithm, share: &KeyShare, challenge: &[u8]) -> Self {
+        Self {
+            index: share.index(),
+            otp: share_passcode(algorithm, share).compute(challenge),
+        }
+    }
+}
+
+impl fmt::Display for PartialResponse {
+    /// Formats the response as `<index>:<otp>`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.index, self.otp)
+    }
+}
+
+impl FromStr for PartialResponse {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let (index, otp) = s
+            .trim()
+            .split_once(':')
+            .ok_or(Error::MalformedMessage("missing share index"))?;
+        let index = index
+            .parse()
+            .map_err(|_| Error::MalformedMessage("invalid share index"))?;
+        Ok(Self {
+            index,
+            otp: otp.to_string(),
+        })
+    }
+}
+
+/// Server side of k-of-n approvals for one account key
+pub struct ThresholdKey {
+    algorithm: Algorithm,
+    shares: Vec<KeyShare>,
+}
+
+impl fmt::Debug for ThresholdKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Shares are never printed
+        f.debug_struct("ThresholdKey")
+            .field("algorithm", &self.algorithm)
+            .field("devices", &self.shares.len())
+            .field("threshold", &self.threshold())
+            .finish()
+    }
+}
+
+impl ThresholdKey {
+    /// Splits the key of `passcode` into `n` device shares, `k` of which
+    /// must approve
+    ///
+    /// The same key, `n` and `k` always yield the same shares. Fails with
+    /// [`Error::InvalidFormat`] unless `1 <= k <= n`.
+    pub fn new(passcode: Passcode, n: u8, k: u8) -> Result<Self, Error> {
+        check_threshold(n, k)?;
+        let key = passcode.key();
+        let coefficients =
+            passcode.derive_labeled(labels::THRESHOLD, &[n, k], key.len() * usize::from(k - 1));
+        Ok(Self {
+            algorithm: passcode.algorithm(),
+            shares: split_with_coefficients(key, n, k, &coefficients),
+        })
+    }
+
+    /// Gets the number of devices that must approve
+    pub fn threshold(&self) -> u8 {
+        self.shares[0].threshold()
+    }
+
+    /// Gets the share to provision on device `index`, 1 to `n`
+    pub fn share(&self, index: u8) -> Option<&KeyShare> {
+        self.shares.iter().find(|share| share.index() == index)
+    }
+
+    /// Checks that at least the threshold of distinct devices answered the
+    /// challenge
+    ///
+    /// Fails with [`Error::OtpMismatch`] if any partial response is wrong
+    /// or names an unknown device, and with [`Error::InsufficientShares`]
+    /// if fewer distinct devices than the threshold answered. A device
+    /// answering twice counts once.
+    pub fn verify(&self, challenge: &[u8], partials: &[PartialResponse]) -> Result<(), Error> {
+        let mut approved: Vec<u8> = Vec::with_capacity(partials.len());
+        for partial in partials {
+            let share = self.share(partial.index).ok_or(Error::OtpMismatch)?;
+            if !share_passcode(self.algorithm, share).verify(challenge, &partial.otp) {
+                return Err(Error::OtpMismatch);
+            }
+            if !approved.contains(&partial.index) {
+                approved.push(partial.index);
+            }
+        }
+
+        if approved.len() < usize::from(self.threshold()) {
+            return Err(Error::InsufficientShares {
+                threshold: self.threshold(),
+                provided: approved.len(),
+            });
+        }
+        Ok(())
+    }
+}
+
+fn share_passcode(algorithm: Algorithm, share: &KeyShare) -> Passcode {
+    Passcode::new(algorithm, share.value().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shamir::recover_key;
+
+    fn server() -> ThresholdKey {
+        ThresholdKey::new(Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]), 3, 2).unwrap()
+    }
+
+    fn partial(index: u8, challenge: &[u8]) -> PartialResponse {
+        PartialResponse::compute(
+            Algorithm::Sha3Kmac256,
+            server().share(index).unwrap(),
+            challenge,
+        )
+    }
+
+    #[test]
+    fn test_threshold_approval() {
+        let server = server();
+        assert_eq!(server.threshold(), 2);
+        assert!(server.share(0).is_none() && server.share(4).is_none());
+
+        for (a, b) in [(1, 2), (1, 3), (2, 3)] {
+            assert_eq!(
+                server.verify(
+                    b"challenge",
+                    &[partial(a, b"challenge"), partial(b, b"challenge")]
+                ),
+                Ok(())
+            );
+        }
+        assert_eq!(
+            server.verify(
+                b"challenge",
+                &[partial(1, b"challenge"), partial(1, b"challenge")]
+            ),
+            Err(Error::InsufficientShares {
+                threshold: 2,
+                provided: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_rejects_wrong_partials() {
+        let server = server();
+        assert_eq!(
+            server.verify(
+                b"challenge",
+                &[partial(1, b"challenge"), partial(2, b"other")]
+            ),
+            Err(Error::OtpMismatch)
+        );
+
+        let mut unknown = partial(1, b"challenge");
+        unknown.index = 9;
+        assert_eq!(
+            server.verify(b"challenge", &[unknown]),
+            Err(Error::OtpMismatch)
+        );
+
+        // A device's own OTP does not work as another device's
+        let mut swapped = partial(1, b"challenge");
+        swapped.index = 2;
+        assert_eq!(
+            server.verify(b"challenge", &[partial(1, b"challenge"), swapped]),
+            Err(Error::OtpMismatch)
+        );
+    }
+
+    #[test]
+    fn test_shares_are_deterministic_and_recover_the_key() {
+        assert_eq!(server().share(2), server().share(2));
+        assert_ne!(server().share(1), server().share(2));
+
+        let shares = [
+            server().share(3).unwrap().clone(),
+            server().share(1).unwrap().clone(),
+        ];
+        assert_eq!(recover_key(&shares).unwrap(), vec![1u8; 32]);
+    }
+
+    #[test]
+    fn test_partial_encoding() {
+        let partial = partial(2, b"challenge");
+        let text = partial.to_string();
+        assert!(text.starts_with("2:"));
+        assert_eq!(text.parse(), Ok(partial));
+        assert!("abc".parse::<PartialResponse>().is_err());
+        assert!("300:abc".parse::<PartialResponse>().is_err());
+    }
+}
@@ -0,0 +1,911 @@
+//! Throttling of verification attempts
+//!
+//! A 6-digit code falls to a million guesses, so every verification path
+//! needs a bound on attempts per identity. [`RateLimiter`] is a token bucket
+//! per identity (a user or key ID): each attempt takes a token, and tokens
+//! come back at a fixed rate up to the burst size. [`Lockout`] locks an
+//! identity out after repeated failures, for longer each time, following a
+//! [`LockoutPolicy`]. [`AnomalyDetector`] does not refuse anything; it
+//! reports identities and source addresses whose failure rate crosses an
+//! [`AnomalyPolicy`], so the application can require step-up
+//! authentication or raise an alert.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
+
+use crate::clock::{self, Clock};
+use crate::Error;
+
+/// Default number of attempts allowed in a burst
+pub const DEFAULT_BURST: u32 = 5;
+
+/// Default time for one attempt to be restored
+pub const DEFAULT_REFILL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Default maximum number of identities tracked at once
+pub const DEFAULT_MAX_IDENTITIES: usize = 100_000;
+
+/// Token-bucket rate limiter keyed by identity
+///
+/// Each identity starts with `burst` tokens and regains one every refill
+/// interval. When the limiter tracks `max_identities` buckets, a new identity
+/// takes the place of the bucket closest to full, so flooding the limiter
+/// with new identities never refuses anyone: those buckets hold all but one
+/// token and evict each other before a bucket an attacker has drained.
+///
+/// # Example
+/// ```
+/// use passcode::throttle::RateLimiter;
+/// use std::time::Duration;
+///
+/// let limiter = RateLimiter::new(3, Duration::from_secs(60));
+/// for _ in 0..3 {
+///     assert!(limiter.check("alice").is_ok());
+/// }
+/// assert!(limiter.check("alice").is_err());
+/// assert!(limiter.check("bob").is_ok());
+/// ```
+#[derive(Debug)]
+pub struct RateLimiter {
+    burst: u32,
+    refill_interval: Duration,
+    max_identities: usize,
+    buckets: Mutex<HashMap<String, Bucket>>,
+    clock: Arc<dyn Clock>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: u32,
+    refilled_at: SystemTime,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(DEFAULT_BURST, DEFAULT_REFILL_INTERVAL)
+    }
+}
+
+impl RateLimiter {
+    /// Creates a limiter allowing `burst` attempts at once and one more per
+    /// `refill_interval`
+    ///
+    /// A burst of zero is treated as one and a zero interval as one
+    /// millisecond.
+    pub fn new(burst: u32, refill_interval: Duration) -> Self {
+        Self {
+            burst: burst.max(1),
+            refill_interval: refill_interval.max(Duration::from_millis(1)),
+            max_identities: DEFAULT_MAX_IDENTITIES,
+            buckets: Mutex::new(HashMap::new()),
+            clock: clock::system(),
+        }
+    }
+
+    /// Sets how many identities are tracked at most
+    pub fn with_max_identities(mut self, max: usize) -> Self {
+        self.max_identities = max;
+        self
+    }
+
+    /// Reads the time from `clock` instead of the system clock
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    pub(crate) fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Takes one attempt for the identity
+    ///
+    /// Fails with [`Error::RateLimited`], carrying the time until the next
+    /// attempt is allowed, when the identity has no tokens left.
+    pub fn check(&self, identity: &str) -> Result<(), Error> {
+        self.check_at(identity, self.clock.now())
+    }
+
+    /// Returns the attempts the identity has left right now
+    pub fn remaining(&self, identity: &str) -> u32 {
+        let now = self.clock.now();
+        self.lock()
+            .get(identity)
+            .map_or(self.burst, |bucket| self.refill(*bucket, now).tokens)
+    }
+
+    /// Restores the full burst for the identity, e.g. after a successful
+    /// verification
+    pub fn reset(&self, identity: &str) {
+        self.lock().remove(identity);
+    }
+
+    fn check_at(&self, identity: &str, now: SystemTime) -> Result<(), Error> {
+        let mut buckets = self.lock();
+
+        if !buckets.contains_key(identity) && buckets.len() >= self.max_identities {
+            let fullest = buckets
+                .iter()
+                .map(|(id, bucket)| (id, self.refill(*bucket, now)))
+                .max_by_key(|(_, bucket)| (bucket.tokens, Reverse(bucket.refilled_at)))
+                .map(|(id, _)| id.clone());
+            if let Some(id) = fullest {
+                buckets.remove(&id);
+            }
+        }
+
+        let bucket = buckets.entry(identity.to_string()).or_insert(Bucket {
+            tokens: self.burst,
+            refilled_at: now,
+        });
+        *bucket = self.refill(*bucket, now);
+
+        if bucket.tokens == 0 {
+            let elapsed = elapsed(bucket.refilled_at, now);
+            return Err(Error::RateLimited {
+                retry_after: self.refill_interval.saturating_sub(elapsed),
+            });
+        }
+        bucket.tokens -= 1;
+        Ok(())
+    }
+
+    /// Adds the tokens earned since the last refill
+    fn refill(&self, bucket: Bucket, now: SystemTime) -> Bucket {
+        let elapsed = elapsed(bucket.refilled_at, now);
+        let earned = elapsed.as_nanos() / self.refill_interval.as_nanos();
+        let tokens = u128::from(bucket.tokens) + earned;
+
+        if tokens >= u128::from(self.burst) {
+            Bucket {
+                tokens: self.burst,
+                refilled_at: now,
+            }
+        } else {
+            Bucket {
+                tokens: tokens as u32,
+                // Keep the partial interval so it counts toward the next token
+                refilled_at: bucket.refilled_at + self.refill_interval * earned as u32,
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Bucket>> {
+        // A panic while holding the lock cannot leave the map inconsistent
+        self.buckets
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// When and for how long repeated failures lock an identity out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockoutPolicy {
+    /// Consecutive failures that trigger a lock
+    pub max_failures: u32,
+    /// Duration of the first lock
+    pub base_duration: Duration,
+    /// Upper bound on the lock duration
+    pub max_duration: Duration,
+}
+
+impl Default for LockoutPolicy {
+    /// Locks after 5 failures for 1 minute, doubling up to 1 day
+    fn default() -> Self {
+        Self {
+            max_failures: 5,
+            base_duration: Duration::from_secs(60),
+            max_duration: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+impl LockoutPolicy {
+    /// Returns the duration of the lock after `previous_locks` earlier ones
+    ///
+    /// The duration doubles with every lock since the last success.
+    pub fn lock_duration(&self, previous_locks: u32) -> Duration {
+        self.base_duration
+            .checked_mul(1u32.checked_shl(previous_locks).unwrap_or(u32::MAX))
+            .map_or(self.max_duration, |d| d.min(self.max_duration))
+    }
+}
+
+/// Notification that an identity was locked out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockoutEvent {
+    /// The locked identity
+    pub identity: String,
+    /// How long the identity is locked
+    pub duration: Duration,
+    /// Number of locks since the last success, including this one
+    pub lock_count: u32,
+}
+
+/// Lockout state of one identity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LockoutStatus {
+    /// Failures since the last success or lock
+    pub failures: u32,
+    /// Number of locks since the last success
+    pub lock_count: u32,
+    /// Time left on the current lock, if locked
+    pub locked_for: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct LockState {
+    failures: u32,
+    lock_count: u32,
+    locked_until: Option<SystemTime>,
+    failed_at: SystemTime,
+}
+
+type LockHook = Box<dyn Fn(&LockoutEvent) + Send + Sync>;
+
+/// Tracks failures per identity and enforces a [`LockoutPolicy`]
+///
+/// After `max_failures` consecutive failures the identity is locked for the
+/// policy's base duration; each further lock before a success doubles it, up
+/// to the maximum. A success clears the identity's state.
+///
+/// When the tracker holds `max_identities` states, a new identity's first
+/// failure takes the place of the unlocked state that failed longest ago, or
+/// if every identity is locked, of the locked one that failed longest ago.
+/// Identities the tracker does not know are never locked.
+///
+/// # Example
+/// ```
+/// use passcode::throttle::{Lockout, LockoutPolicy};
+/// use std::time::Duration;
+///
+/// let lockout = Lockout::new(LockoutPolicy {
+///     max_failures: 3,
+///     base_duration: Duration::from_secs(60),
+///     max_duration: Duration::from_secs(3600),
+/// })
+/// .on_lock(|event| println!("{} locked for {:?}", event.identity, event.duration));
+///
+/// for _ in 0..3 {
+///     lockout.record_failure("alice");
+/// }
+/// assert!(lockout.check("alice").is_err());
+///
+/// lockout.reset("alice");
+/// assert!(lockout.check("alice").is_ok());
+/// ```
+pub struct Lockout {
+    policy: LockoutPolicy,
+    max_identities: usize,
+    states: Mutex<HashMap<String, LockState>>,
+    hook: Option<LockHook>,
+    clock: Arc<dyn Clock>,
+}
+
+impl fmt::Debug for Lockout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Lockout")
+            .field("policy", &self.policy)
+            .field("max_identities", &self.max_identities)
+            .field("identities", &self.lock().len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Lockout {
+    /// Creates a tracker enforcing the policy
+    pub fn new(policy: LockoutPolicy) -> Self {
+        Self {
+            policy,
+            max_identities: DEFAULT_MAX_IDENTITIES,
+            states: Mutex::new(HashMap::new()),
+            hook: None,
+            clock: clock::system(),
+        }
+    }
+
+    /// Sets how many identities are tracked at most
+    pub fn with_max_identities(mut self, max: usize) -> Self {
+        self.max_identities = max;
+        self
+    }
+
+    /// Reads the time from `clock` instead of the system clock
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    pub(crate) fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Calls `hook` whenever an identity is locked, e.g. to notify the user
+    ///
+    /// The hook runs on the thread recording the failure, after the
+    /// tracker's lock is released.
+    pub fn on_lock(mut self, hook: impl Fn(&LockoutEvent) + Send + Sync + 'static) -> Self {
+        self.hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Gets the enforced policy
+    pub fn policy(&self) -> &LockoutPolicy {
+        &self.policy
+    }
+
+    /// Fails with [`Error::LockedOut`] while the identity is locked
+    pub fn check(&self, identity: &str) -> Result<(), Error> {
+        match self.status(identity).locked_for {
+            Some(retry_after) => Err(Error::LockedOut { retry_after }),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the identity's current lockout state
+    pub fn status(&self, identity: &str) -> LockoutStatus {
+        self.status_at(identity, self.clock.now())
+    }
+
+    /// Counts a failed verification, locking the identity when the policy's
+    /// threshold is reached
+    ///
+    /// Returns the lock event if this failure caused a lock.
+    pub fn record_failure(&self, identity: &str) -> Option<LockoutEvent> {
+        let event = self.record_failure_at(identity, self.clock.now());
+        if let (Some(event), Some(hook)) = (&event, &self.hook) {
+            hook(event);
+        }
+        event
+    }
+
+    /// Clears the identity's failures and lock history after a success
+    pub fn record_success(&self, identity: &str) {
+        self.reset(identity);
+    }
+
+    /// Unlocks the identity and clears its history, e.g. from an admin tool
+    pub fn reset(&self, identity: &str) {
+        self.lock().remove(identity);
+    }
+
+    fn status_at(&self, identity: &str, now: SystemTime) -> LockoutStatus {
+        self.lock()
+            .get(identity)
+            .map_or_else(LockoutStatus::default, |state| LockoutStatus {
+                failures: state.failures,
+                lock_count: state.lock_count,
+                locked_for: state
+                    .locked_until
+                    .filter(|&until| until > now)
+                    .map(|until| elapsed(now, until)),
+            })
+    }
+
+    fn record_failure_at(&self, identity: &str, now: SystemTime) -> Option<LockoutEvent> {
+        let mut states = self.lock();
+        if !states.contains_key(identity) && states.len() >= self.max_identities {
+            let oldest = states
+                .iter()
+                .min_by_key(|(_, state)| {
+                    let locked = state.locked_until.is_some_and(|until| until > now);
+                    (locked, state.failed_at)
+                })
+                .map(|(id, _)| id.clone());
+            if let Some(id) = oldest {
+                states.remove(&id);
+            }
+        }
+        let state = states.entry(identity.to_string()).or_insert(LockState {
+            failures: 0,
+            lock_count: 0,
+            locked_until: None,
+            failed_at: now,
+        });
+
+        if state.locked_until.is_some_and(|until| until > now) {
+            // Attempts during a lock are refused before they are verified
+            return None;
+        }
+        state.failures += 1;
+        state.failed_at = now;
+        if state.failures < self.policy.max_failures {
+            return None;
+        }
+
+        let duration = self.policy.lock_duration(state.lock_count);
+        state.failures = 0;
+        state.lock_count += 1;
+        state.locked_until = Some(now + duration);
+        Some(LockoutEvent {
+            identity: identity.to_string(),
+            duration,
+            lock_count: state.lock_count,
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, LockState>> {
+        // A panic while holding the lock cannot leave the map inconsistent
+        self.states
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Default window in which an [`AnomalyDetector`] counts failures
+pub const DEFAULT_ANOMALY_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// Failure rates an [`AnomalyDetector`] reports
+///
+/// A threshold of zero disables reporting for that kind of subject.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnomalyPolicy {
+    /// Length of the window failures are counted in
+    pub window: Duration,
+    /// Failures of one identity within a window that are reported
+    pub identity_threshold: u32,
+    /// Failures from one source address within a window that are reported
+    pub source_threshold: u32,
+}
+
+impl Default for AnomalyPolicy {
+    /// Reports 10 failures of one identity or 20 from one source address
+    /// within 5 minutes
+    fn default() -> Self {
+        Self {
+            window: DEFAULT_ANOMALY_WINDOW,
+            identity_threshold: 10,
+            source_threshold: 20,
+        }
+    }
+}
+
+/// What an anomaly was detected for
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AnomalySubject {
+    /// A user or key ID, e.g. targeted by guesses from many addresses
+    Identity(String),
+    /// A client address, e.g. guessing for many identities
+    Source(IpAddr),
+}
+
+/// Notification that a subject's failure rate crossed its threshold
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnomalyEvent {
+    /// The subject whose failures crossed the threshold
+    pub subject: AnomalySubject,
+    /// Failures counted in the current window
+    pub failures: u32,
+    /// Length of the window the failures were counted in
+    pub window: Duration,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct FailureWindow {
+    started: SystemTime,
+    failures: u32,
+}
+
+type AnomalyHook = Box<dyn Fn(&AnomalyEvent) + Send + Sync>;
+
+/// Counts failures per identity and per source address and reports those
+/// crossing an [`AnomalyPolicy`]
+///
+/// Failures are counted in fixed windows starting at a subject's first
+/// failure. A subject is reported once per window, when its count reaches
+/// the threshold. Subjects whose window has ended are forgotten when the
+/// detector needs room; while it tracks `max_subjects` active windows,
+/// failures of new subjects are not counted.
+///
+/// # Example
+/// ```
+/// use passcode::throttle::{AnomalyDetector, AnomalyPolicy, AnomalySubject};
+/// use std::time::Duration;
+///
+/// let detector = AnomalyDetector::new(AnomalyPolicy {
+///     window: Duration::from_secs(60),
+///     identity_threshold: 3,
+///     source_threshold: 3,
+/// })
+/// .on_anomaly(|event| println!("{:?} failed {} times", event.subject, event.failures));
+///
+/// let source = "203.0.113.7".parse().unwrap();
+/// detector.record_failure("alice", Some(source));
+/// detector.record_failure("bob", Some(source));
+/// let events = detector.record_failure("carol", Some(source));
+/// assert_eq!(events[0].subject, AnomalySubject::Source(source));
+/// ```
+pub struct AnomalyDetector {
+    policy: AnomalyPolicy,
+    max_subjects: usize,
+    windows: Mutex<HashMap<AnomalySubject, FailureWindow>>,
+    hook: Option<AnomalyHook>,
+    clock: Arc<dyn Clock>,
+}
+
+impl fmt::Debug for AnomalyDetector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AnomalyDetector")
+            .field("policy", &self.policy)
+            .field("subjects", &self.lock().len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl AnomalyDetector {
+    /// Creates a detector reporting per the policy
+    pub fn new(policy: AnomalyPolicy) -> Self {
+        Self {
+            policy,
+            max_subjects: DEFAULT_MAX_IDENTITIES,
+            windows: Mutex::new(HashMap::new()),
+            hook: None,
+            clock: clock::system(),
+        }
+    }
+
+    /// Sets how many identities and sources are tracked at most
+    pub fn with_max_subjects(mut self, max: usize) -> Self {
+        self.max_subjects = max;
+        self
+    }
+
+    /// Reads the time from `clock` instead of the system clock
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    pub(crate) fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Calls `hook` whenever a subject crosses its threshold, e.g. to
+    /// require step-up authentication or page an operator
+    ///
+    /// The hook runs on the thread recording the failure, after the
+    /// detector's lock is released.
+    pub fn on_anomaly(mut self, hook: impl Fn(&AnomalyEvent) + Send + Sync + 'static) -> Self {
+        self.hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Gets the reporting policy
+    pub fn policy(&self) -> &AnomalyPolicy {
+        &self.policy
+    }
+
+    /// Returns the failures counted for the subject in its current window
+    pub fn failures(&self, subject: &AnomalySubject) -> u32 {
+        let now = self.clock.now();
+        self.lock()
+            .get(subject)
+            .filter(|window| elapsed(window.started, now) < self.policy.window)
+            .map_or(0, |window| window.failures)
+    }
+
+    /// Counts a failed verification for the identity and, if known, the
+    /// source address
+    ///
+    /// Returns the events for the subjects this failure pushed over their
+    /// threshold.
+    pub fn record_failure(&self, identity: &str, source: Option<IpAddr>) -> Vec<AnomalyEvent> {
+        let events = self.record_failure_at(identity, source, self.clock.now());
+        if let Some(hook) = &self.hook {
+            events.iter().for_each(hook);
+        }
+        events
+    }
+
+    /// Forgets the subject's failures, e.g. once an alert was handled
+    pub fn reset(&self, subject: &AnomalySubject) {
+        self.lock().remove(subject);
+    }
+
+    fn record_failure_at(
+        &self,
+        identity: &str,
+        source: Option<IpAddr>,
+        now: SystemTime,
+    ) -> Vec<AnomalyEvent> {
+        let subjects = [(
+            AnomalySubject::Identity(identity.to_string()),
+            self.policy.identity_threshold,
+        )]
+        .into_iter()
+        .chain(source.map(|ip| (AnomalySubject::Source(ip), self.policy.source_threshold)));
+
+        let mut windows = self.lock();
+        let mut events = Vec::new();
+        for (subject, threshold) in subjects {
+            if !windows.contains_key(&subject) && windows.len() >= self.max_subjects {
+                windows.retain(|_, window| elapsed(window.started, now) < self.policy.window);
+                if windows.len() >= self.max_subjects {
+                    continue;
+                }
+            }
+
+            let fresh = FailureWindow {
+                started: now,
+                failures: 0,
+            };
+            let window = windows.entry(subject.clone()).or_insert(fresh);
+            if elapsed(window.started, now) >= self.policy.window {
+                *window = fresh;
+            }
+            window.failures = window.failures.saturating_add(1);
+            if window.failures == threshold {
+                events.push(AnomalyEvent {
+                    subject,
+                    failures: window.failures,
+                    window: self.policy.window,
+                });
+            }
+        }
+        events
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<AnomalySubject, FailureWindow>> {
+        // A panic while holding the lock cannot leave the map inconsistent
+        self.windows
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Returns the time from `earlier` to `later`, or zero if the clock went
+/// backwards
+fn elapsed(earlier: SystemTime, later: SystemTime) -> Duration {
+    later.duration_since(earlier).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_burst_then_refill() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(10));
+        let start = SystemTime::now();
+
+        assert_eq!(limiter.check_at("alice", start), Ok(()));
+        assert_eq!(limiter.check_at("alice", start), Ok(()));
+        assert_eq!(
+            limiter.check_at("alice", start + Duration::from_secs(4)),
+            Err(Error::RateLimited {
+                retry_after: Duration::from_secs(6)
+            })
+        );
+        assert_eq!(
+            limiter.check_at("alice", start + Duration::from_secs(10)),
+            Ok(())
+        );
+        assert!(limiter
+            .check_at("alice", start + Duration::from_secs(15))
+            .is_err());
+        assert_eq!(
+            limiter.check_at("alice", start + Duration::from_secs(20)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_identities_are_independent() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(60));
+
+        assert_eq!(limiter.check("alice"), Ok(()));
+        assert!(limiter.check("alice").is_err());
+        assert_eq!(limiter.remaining("alice"), 0);
+        assert_eq!(limiter.remaining("bob"), 1);
+        assert_eq!(limiter.check("bob"), Ok(()));
+
+        limiter.reset("alice");
+        assert_eq!(limiter.check("alice"), Ok(()));
+    }
+
+    #[test]
+    fn test_identity_limit() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(60)).with_max_identities(2);
+        let start = SystemTime::now();
+
+        assert_eq!(limiter.check_at("alice", start), Ok(()));
+        assert_eq!(limiter.check_at("alice", start), Ok(()));
+        // New identities evict each other rather than alice's drained bucket
+        for identity in ["bob", "carol", "dave"] {
+            assert_eq!(limiter.check_at(identity, start), Ok(()));
+        }
+        assert!(limiter.check_at("alice", start).is_err());
+        assert_eq!(limiter.lock().len(), 2);
+    }
+
+    fn lockout() -> Lockout {
+        Lockout::new(LockoutPolicy {
+            max_failures: 3,
+            base_duration: Duration::from_secs(60),
+            max_duration: Duration::from_secs(200),
+        })
+    }
+
+    #[test]
+    fn test_lock_duration_escalates() {
+        let policy = LockoutPolicy {
+            max_failures: 3,
+            base_duration: Duration::from_secs(60),
+            max_duration: Duration::from_secs(200),
+        };
+
+        assert_eq!(policy.lock_duration(0), Duration::from_secs(60));
+        assert_eq!(policy.lock_duration(1), Duration::from_secs(120));
+        assert_eq!(policy.lock_duration(2), Duration::from_secs(200));
+        assert_eq!(policy.lock_duration(40), Duration::from_secs(200));
+    }
+
+    #[test]
+    fn test_locks_after_max_failures() {
+        let lockout = lockout();
+        let start = SystemTime::now();
+
+        assert_eq!(lockout.record_failure_at("alice", start), None);
+        assert_eq!(lockout.record_failure_at("alice", start), None);
+        let event = lockout.record_failure_at("alice", start).unwrap();
+        assert_eq!(event.duration, Duration::from_secs(60));
+        assert_eq!(event.lock_count, 1);
+
+        let status = lockout.status_at("alice", start + Duration::from_secs(10));
+        assert_eq!(status.locked_for, Some(Duration::from_secs(50)));
+        assert_eq!(lockout.status_at("bob", start), LockoutStatus::default());
+
+        // Failures during the lock are not counted
+        lockout.record_failure_at("alice", start + Duration::from_secs(10));
+        assert_eq!(lockout.status_at("alice", start).failures, 0);
+
+        // The second lock is twice as long
+        let later = start + Duration::from_secs(60);
+        assert_eq!(lockout.status_at("alice", later).locked_for, None);
+        lockout.record_failure_at("alice", later);
+        lockout.record_failure_at("alice", later);
+        let event = lockout.record_failure_at("alice", later).unwrap();
+        assert_eq!(event.duration, Duration::from_secs(120));
+        assert_eq!(event.lock_count, 2);
+    }
+
+    #[test]
+    fn test_success_and_reset_clear_state() {
+        use std::sync::atomic::{AtomicU32, Ordering};
+        use std::sync::Arc;
+
+        let locks = Arc::new(AtomicU32::new(0));
+        let counter = Arc::clone(&locks);
+        let lockout = lockout().on_lock(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        lockout.record_failure("alice");
+        lockout.record_failure("alice");
+        lockout.record_success("alice");
+        lockout.record_failure("alice");
+        assert_eq!(lockout.status("alice").failures, 1);
+
+        lockout.record_failure("alice");
+        lockout.record_failure("alice");
+        assert_eq!(locks.load(Ordering::SeqCst), 1);
+        assert!(matches!(
+            lockout.check("alice"),
+            Err(Error::LockedOut { .. })
+        ));
+
+        lockout.reset("alice");
+        assert_eq!(lockout.check("alice"), Ok(()));
+        assert_eq!(lockout.status("alice"), LockoutStatus::default());
+    }
+
+    #[test]
+    fn test_lockout_identity_limit() {
+        let lockout = lockout().with_max_identities(2);
+        let start = SystemTime::now();
+
+        for _ in 0..3 {
+            lockout.record_failure_at("mallory", start);
+        }
+        lockout.record_failure_at("alice", start);
+        // A new identity evicts alice, the oldest unlocked state, and is
+        // not locked itself
+        let later = start + Duration::from_secs(1);
+        assert_eq!(lockout.status_at("bob", later), LockoutStatus::default());
+        lockout.record_failure_at("bob", later);
+        assert_eq!(lockout.status_at("bob", later).failures, 1);
+        assert_eq!(lockout.status_at("alice", later), LockoutStatus::default());
+        assert!(lockout.status_at("mallory", later).locked_for.is_some());
+
+        // With every identity locked, the one that failed longest ago goes
+        for _ in 0..2 {
+            lockout.record_failure_at("bob", later);
+        }
+        lockout.record_failure_at("carol", later);
+        assert_eq!(lockout.status_at("mallory", later), LockoutStatus::default());
+        assert!(lockout.status_at("bob", later).locked_for.is_some());
+    }
+
+    fn detector() -> AnomalyDetector {
+        AnomalyDetector::new(AnomalyPolicy {
+            window: Duration::from_secs(60),
+            identity_threshold: 3,
+            source_threshold: 2,
+        })
+    }
+
+    #[test]
+    fn test_anomaly_reported_once_per_window() {
+        let detector = detector();
+        let start = SystemTime::now();
+        let alice = AnomalySubject::Identity("alice".to_string());
+
+        assert!(detector.record_failure_at("alice", None, start).is_empty());
+        assert!(detector.record_failure_at("alice", None, start).is_empty());
+        assert_eq!(
+            detector.record_failure_at("alice", None, start),
+            vec![AnomalyEvent {
+                subject: alice.clone(),
+                failures: 3,
+                window: Duration::from_secs(60),
+            }]
+        );
+        assert!(detector.record_failure_at("alice", None, start).is_empty());
+
+        // A new window counts from zero again
+        let later = start + Duration::from_secs(60);
+        for _ in 0..2 {
+            assert!(detector.record_failure_at("alice", None, later).is_empty());
+        }
+        assert_eq!(detector.record_failure_at("alice", None, later).len(), 1);
+
+        detector.reset(&alice);
+        assert_eq!(detector.failures(&alice), 0);
+    }
+
+    #[test]
+    fn test_anomaly_per_source() {
+        use std::sync::atomic::{AtomicU32, Ordering};
+
+        let reported = Arc::new(AtomicU32::new(0));
+        let counter = Arc::clone(&reported);
+        let detector = detector().on_anomaly(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        let source: IpAddr = "203.0.113.7".parse().unwrap();
+
+        // Spraying one guess at many identities is caught by the source
+        assert!(detector.record_failure("alice", Some(source)).is_empty());
+        let events = detector.record_failure("bob", Some(source));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].subject, AnomalySubject::Source(source));
+        assert_eq!(reported.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            detector.failures(&AnomalySubject::Identity("bob".to_string())),
+            1
+        );
+    }
+
+    #[test]
+    fn test_anomaly_subject_limit() {
+        let detector = detector().with_max_subjects(1);
+        let start = SystemTime::now();
+
+        detector.record_failure_at("alice", None, start);
+        detector.record_failure_at("bob", None, start);
+        assert_eq!(
+            detector.failures(&AnomalySubject::Identity("bob".to_string())),
+            0
+        );
+        // Once alice's window ended she can be forgotten
+        detector.record_failure_at("bob", None, start + Duration::from_secs(60));
+        assert_eq!(
+            detector.failures(&AnomalySubject::Identity("bob".to_string())),
+            1
+        );
+    }
+}
@@ -0,0 +1,315 @@
+//! Session tokens minted after a successful verification (feature
+//! `session-token`)
+//!
+//! Once a user has answered a challenge, later requests carry a bearer
+//! token instead of repeating the challenge-response. Tokens are compact
+//! JWTs signed with HMAC-SHA256 (`HS256`) and carry the user, the device
+//! that answered and the authentication time.
+//!
+//! There is no separate token key to manage: each token is signed with a
+//! key derived from the user's own key in the [`KeyRing`], under the
+//! [`labels::SESSION_TOKEN`] label. Replacing or removing a user's key
+//! therefore invalidates their tokens, and tokens naming a device stop
+//! verifying once the device is revoked.
+//!
+//! # Example
+//! ```
+//! use passcode::keyring::KeyRing;
+//! use passcode::token::SessionTokens;
+//! use passcode::{Algorithm, Passcode};
+//! use std::time::SystemTime;
+//!
+//! let keyring = KeyRing::new();
+//! keyring.insert("alice", Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]));
+//!
+//! let tokens = SessionTokens::new().with_issuer("example.com");
+//! let alice = keyring.get("alice").unwrap();
+//! let token = tokens.mint(&alice, "alice", None, SystemTime::now());
+//!
+//! let claims = tokens.verify(&keyring, &token, SystemTime::now()).unwrap();
+//! assert_eq!(claims.sub, "alice");
+//! ```
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64ct::{Base64UrlUnpadded, Encoding};
+use hmac::digest::KeyInit;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::kdf::labels;
+use crate::keyring::KeyRing;
+use crate::{Error, Passcode};
+
+/// Default lifetime of a token
+pub const DEFAULT_TOKEN_TTL: Duration = Duration::from_secs(15 * 60);
+
+/// JOSE header of every token; tokens with any other header are rejected,
+/// so `alg` cannot be downgraded
+const HEADER: &str = r#"{"alg":"HS256","typ":"JWT"}"#;
+
+/// Length of the derived signing key in bytes
+const SIGNING_KEY_LEN: usize = 32;
+
+/// Claims carried by a session token
+///
+/// Times are seconds since the Unix epoch, as in JWT.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenClaims {
+    /// Issuer, when the minting [`SessionTokens`] has one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    /// User ID
+    pub sub: String,
+    /// Registered device that answered the challenge, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    /// Time the user answered the challenge
+    pub auth_time: u64,
+    /// Time the token was minted
+    pub iat: u64,
+    /// Time after which the token is refused
+    pub exp: u64,
+}
+
+/// Mints and verifies session tokens
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionTokens {
+    ttl: Duration,
+    issuer: Option<String>,
+}
+
+impl Default for SessionTokens {
+    fn default() -> Self {
+        Self {
+            ttl: DEFAULT_TOKEN_TTL,
+            issuer: None,
+        }
+    }
+}
+
+impl SessionTokens {
+    /// Creates a minter for tokens valid for [`DEFAULT_TOKEN_TTL`]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how long minted tokens remain valid
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Sets the `iss` claim of minted tokens, which verification then
+    /// requires
+    pub fn with_issuer(mut self, issuer: impl Into<String>) -> Self {
+        self.issuer = Some(issuer.into());
+        self
+    }
+
+    /// Gets the lifetime of minted tokens
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Mints a token for a user who authenticated at `auth_time`, signed
+    /// with a key derived from the user's `passcode`
+    ///
+    /// The token is issued at `auth_time` and expires [`ttl`](Self::ttl)
+    /// later.
+    pub fn mint(
+        &self,
+        passcode: &Passcode,
+        user_id: &str,
+        device_id: Option<&str>,
+        auth_time: SystemTime,
+    ) -> String {
+        let auth_time = unix_seconds(auth_time);
+        let claims = TokenClaims {
+            iss: self.issuer.clone(),
+            sub: user_id.to_string(),
+            device: device_id.map(str::to_string),
+            auth_time,
+            iat: auth_time,
+            exp: auth_time.saturating_add(self.ttl.as_secs()),
+        };
+        let payload = serde_json::to_vec(&claims).expect("claims serialize to JSON");
+
+        let mut token = Base64UrlUnpadded::encode_string(HEADER.as_bytes());
+        token.push('.');
+        token.push_str(&Base64UrlUnpadded::encode_string(&payload));
+        let signature = sign(passcode, &token).finalize().into_bytes();
+        token.push('.');
+        token.push_str(&Base64UrlUnpadded::encode_string(&signature));
+        token
+    }
+
+    /// Verifies a token against the keys in `keyring`, returning its claims
+    ///
+    /// Fails with [`Error::InvalidToken`] when the token is malformed, its
+    /// signature does not match the key of the user it names, its issuer
+    /// differs, or its device is no longer registered, and with
+    /// [`Error::TokenExpired`] once `now` reaches its expiry.
+    pub fn verify(
+        &self,
+        keyring: &KeyRing,
+        token: &str,
+        now: SystemTime,
+    ) -> Result<TokenClaims, Error> {
+        let mut parts = token.split('.');
+        let (Some(header), Some(payload), Some(signature), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(Error::InvalidToken("not a compact JWT"));
+        };
+        let header = decode(header)?;
+        if header != HEADER.as_bytes() {
+            return Err(Error::InvalidToken("unsupported header"));
+        }
+        let claims: TokenClaims = serde_json::from_slice(&decode(payload)?)
+            .map_err(|_| Error::InvalidToken("malformed claims"))?;
+
+        // The key of the user the token names is the only one that can have
+        // signed it
+        let passcode = keyring
+            .get(&claims.sub)
+            .ok_or(Error::InvalidToken("signature mismatch"))?;
+        let signed = &token[..token.len() - signature.len() - 1];
+        sign(&passcode, signed)
+            .verify_slice(&decode(signature)?)
+            .map_err(|_| Error::InvalidToken("signature mismatch"))?;
+
+        if claims.iss != self.issuer {
+            return Err(Error::InvalidToken("issuer mismatch"));
+        }
+        if !keyring.is_usable(&claims.sub, now) {
+            return Err(Error::InvalidToken("key revoked"));
+        }
+        if let Some(device) = &claims.device {
+            if !keyring.devices(&claims.sub).contains(device) {
+                return Err(Error::InvalidToken("device revoked"));
+            }
+        }
+        if unix_seconds(now) >= claims.exp {
+            return Err(Error::TokenExpired);
+        }
+        Ok(claims)
+    }
+}
+
+/// Starts the HMAC over `signed` with the user's token signing key
+fn sign(passcode: &Passcode, signed: &str) -> Hmac<Sha256> {
+    let key = passcode.derive_labeled(labels::SESSION_TOKEN, b"HS256", SIGNING_KEY_LEN);
+    let mut mac = <Hmac<Sha256> as KeyInit>::new_from_slice(&key).expect("HMAC accepts any key");
+    mac.update(signed.as_bytes());
+    mac
+}
+
+fn decode(part: &str) -> Result<Vec<u8>, Error> {
+    Base64UrlUnpadded::decode_vec(part).map_err(|_| Error::InvalidToken("invalid base64url"))
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Algorithm;
+
+    fn keyring() -> KeyRing {
+        let keyring = KeyRing::new();
+        keyring.insert(
+            "alice",
+            Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]),
+        );
+        keyring.insert("bob", Passcode::new(Algorithm::Sha3Kmac256, vec![2u8; 32]));
+        keyring
+    }
+
+    #[test]
+    fn test_mint_and_verify() {
+        let keyring = keyring();
+        let tokens = SessionTokens::new().with_issuer("example.com");
+        let now = UNIX_EPOCH + Duration::from_secs(1_000);
+        let token = tokens.mint(&keyring.get("alice").unwrap(), "alice", None, now);
+
+        let claims = tokens.verify(&keyring, &token, now).unwrap();
+        assert_eq!(claims.iss.as_deref(), Some("example.com"));
+        assert_eq!(claims.sub, "alice");
+        assert_eq!(claims.auth_time, 1_000);
+        assert_eq!(claims.exp, 1_000 + DEFAULT_TOKEN_TTL.as_secs());
+
+        assert_eq!(
+            tokens.verify(&keyring, &token, now + DEFAULT_TOKEN_TTL),
+            Err(Error::TokenExpired)
+        );
+        assert_eq!(
+            SessionTokens::new().verify(&keyring, &token, now),
+            Err(Error::InvalidToken("issuer mismatch"))
+        );
+
+        keyring.insert(
+            "alice",
+            Passcode::new(Algorithm::Sha3Kmac256, vec![3u8; 32]),
+        );
+        assert_eq!(
+            tokens.verify(&keyring, &token, now),
+            Err(Error::InvalidToken("signature mismatch"))
+        );
+    }
+
+    #[test]
+    fn test_rejects_forgeries() {
+        let keyring = keyring();
+        let tokens = SessionTokens::new();
+        let now = UNIX_EPOCH + Duration::from_secs(1_000);
+        let token = tokens.mint(&keyring.get("bob").unwrap(), "bob", None, now);
+
+        // Claiming to be alice with bob's signature
+        let (header, rest) = token.split_once('.').unwrap();
+        let (_, signature) = rest.split_once('.').unwrap();
+        let claims = r#"{"sub":"alice","auth_time":1000,"iat":1000,"exp":9999}"#;
+        let forged = format!(
+            "{}.{}.{}",
+            header,
+            Base64UrlUnpadded::encode_string(claims.as_bytes()),
+            signature
+        );
+        assert_eq!(
+            tokens.verify(&keyring, &forged, now),
+            Err(Error::InvalidToken("signature mismatch"))
+        );
+
+        let none = Base64UrlUnpadded::encode_string(br#"{"alg":"none"}"#);
+        let downgraded = format!("{}.{}", none, rest);
+        assert_eq!(
+            tokens.verify(&keyring, &downgraded, now),
+            Err(Error::InvalidToken("unsupported header"))
+        );
+        assert_eq!(
+            tokens.verify(&keyring, "a.b", now),
+            Err(Error::InvalidToken("not a compact JWT"))
+        );
+    }
+
+    #[test]
+    fn test_revoked_device() {
+        let keyring = keyring();
+        keyring.register_device("alice", "phone");
+        let tokens = SessionTokens::new();
+        let now = UNIX_EPOCH + Duration::from_secs(1_000);
+        let token = tokens.mint(&keyring.get("alice").unwrap(), "alice", Some("phone"), now);
+
+        let claims = tokens.verify(&keyring, &token, now).unwrap();
+        assert_eq!(claims.device.as_deref(), Some("phone"));
+
+        keyring.revoke_device("alice", "phone");
+        assert_eq!(
+            tokens.verify(&keyring, &token, now),
+            Err(Error::InvalidToken("device revoked"))
+        );
+    }
+}
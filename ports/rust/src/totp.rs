@@ -0,0 +1,310 @@
+//! Time-based One-Time Passwords (RFC 6238)
+//!
+//! Compatible with Google Authenticator, FreeOTP and other authenticator
+//! apps when used with the default configuration (HMAC-SHA1, 6 digits,
+//! 30 second steps).
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use subtle::ConstantTimeEq;
+
+use crate::clock::{self, Clock};
+use crate::hotp::{check_digits, hotp_unchecked, HmacAlgorithm};
+use crate::self_test::ensure_self_test;
+use crate::Error;
+
+/// Configuration for a [`Totp`] instance
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TotpConfig {
+    /// HMAC hash function
+    pub algorithm: HmacAlgorithm,
+    /// Number of decimal digits (6 to 9)
+    pub digits: u32,
+    /// Time step in seconds
+    pub step: u64,
+    /// Unix time at which counting starts
+    pub t0: u64,
+    /// Number of steps accepted on either side of the current step
+    pub window: u64,
+}
+
+impl Default for TotpConfig {
+    fn default() -> Self {
+        Self {
+            algorithm: HmacAlgorithm::Sha1,
+            digits: 6,
+            step: 30,
+            t0: 0,
+            window: 1,
+        }
+    }
+}
+
+/// A successful TOTP verification
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TotpMatch {
+    /// The time step the code was generated for
+    pub step: u64,
+    /// Offset in steps between the client and server clocks
+    pub skew: i64,
+}
+
+/// TOTP generator and verifier with clock drift tracking
+#[derive(Clone)]
+pub struct Totp {
+    key: Vec<u8>,
+    config: TotpConfig,
+    drift: i64,
+    clock: Arc<dyn Clock>,
+}
+
+impl fmt::Debug for Totp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The shared secret is never printed
+        f.debug_struct("Totp")
+            .field("config", &self.config)
+            .field("drift", &self.drift)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Totp {
+    /// Creates a new TOTP instance
+    ///
+    /// # Arguments
+    /// * `key` - The shared secret key
+    /// * `config` - Time step, digits, hash function and verification window
+    ///
+    /// # Example
+    /// ```
+    /// use passcode::totp::{Totp, TotpConfig};
+    ///
+    /// let totp = Totp::new(b"12345678901234567890".to_vec(), TotpConfig::default()).unwrap();
+    /// assert_eq!(totp.generate(59), "287082");
+    /// ```
+    pub fn new(key: Vec<u8>, config: TotpConfig) -> Result<Self, Error> {
+        ensure_self_test()?;
+        check_digits(config.digits)?;
+        if config.step == 0 {
+            return Err(Error::InvalidTimeStep);
+        }
+
+        Ok(Self {
+            key,
+            config,
+            drift: 0,
+            clock: clock::system(),
+        })
+    }
+
+    /// Reads the current time for [`generate_now`](Self::generate_now) and
+    /// [`verify_now`](Self::verify_now) from `clock` instead of the system
+    /// clock
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Gets the configuration
+    pub fn config(&self) -> &TotpConfig {
+        &self.config
+    }
+
+    /// Gets the recorded clock drift in steps
+    pub fn drift(&self) -> i64 {
+        self.drift
+    }
+
+    /// Sets the recorded clock drift in steps (e.g. restored from storage)
+    pub fn set_drift(&mut self, drift: i64) {
+        self.drift = drift;
+    }
+
+    /// Returns the time step for a Unix timestamp, without drift applied
+    pub fn time_step(&self, unix_time: u64) -> u64 {
+        unix_time.saturating_sub(self.config.t0) / self.config.step
+    }
+
+    /// Generates the code for a Unix timestamp, applying the recorded drift
+    pub fn generate(&self, unix_time: u64) -> String {
+        let step = offset_step(self.time_step(unix_time), self.drift).unwrap_or(0);
+        self.generate_at_step(step)
+    }
+
+    /// Generates the code for the current time
+    pub fn generate_now(&self) -> String {
+        self.generate(self.unix_now())
+    }
+
+    /// Generates the code for an explicit time step
+    pub fn generate_at_step(&self, step: u64) -> String {
+        hotp_unchecked(self.config.algorithm, &self.key, step, self.config.digits)
+    }
+
+    /// Verifies a code within the configured window around the drifted step
+    ///
+    /// Returns the matched step and the detected skew on success. Callers
+    /// should remember the last accepted step and reject codes for the same
+    /// or earlier steps to prevent replay.
+    pub fn verify(&self, code: &str, unix_time: u64) -> Option<TotpMatch> {
+        self.search(code, unix_time, self.drift, self.config.window)
+    }
+
+    /// Verifies a code against the current time
+    pub fn verify_now(&self, code: &str) -> Option<TotpMatch> {
+        self.verify(code, self.unix_now())
+    }
+
+    /// Re-establishes the clock drift from two consecutive codes
+    ///
+    /// Searches up to `max_skew` steps on either side of the current step for
+    /// a step where `first` matches and `second` matches the next step. On
+    /// success the drift is updated and returned.
+    pub fn resync(
+        &mut self,
+        first: &str,
+        second: &str,
+        unix_time: u64,
+        max_skew: u64,
+    ) -> Option<i64> {
+        let found = self.search(first, unix_time, 0, max_skew)?;
+        let next = found.step.checked_add(1)?;
+        if !self.matches(second, next) {
+            return None;
+        }
+
+        // The second code was generated one step later, so that is the
+        // client's notion of "now"
+        self.drift = found.skew + 1;
+        Some(self.drift)
+    }
+
+    fn search(&self, code: &str, unix_time: u64, drift: i64, window: u64) -> Option<TotpMatch> {
+        let current = self.time_step(unix_time);
+        let center = offset_step(current, drift)?;
+        let window = i64::try_from(window).unwrap_or(i64::MAX);
+
+        // Check the expected step first, then widen outwards
+        std::iter::once(0)
+            .chain((1..=window).flat_map(|distance| [distance, -distance]))
+            .filter_map(|offset| offset_step(center, offset))
+            .find(|step| self.matches(code, *step))
+            .map(|step| TotpMatch {
+                step,
+                skew: step as i64 - current as i64,
+            })
+    }
+
+    fn unix_now(&self) -> u64 {
+        self.clock
+            .now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    }
+
+    fn matches(&self, code: &str, step: u64) -> bool {
+        let expected = self.generate_at_step(step);
+        expected.as_bytes().ct_eq(code.trim().as_bytes()).into()
+    }
+}
+
+fn offset_step(step: u64, offset: i64) -> Option<u64> {
+    step.checked_add_signed(offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rfc_totp(algorithm: HmacAlgorithm) -> Totp {
+        let key: &[u8] = match algorithm {
+            HmacAlgorithm::Sha1 => b"12345678901234567890",
+            HmacAlgorithm::Sha256 => b"12345678901234567890123456789012",
+            HmacAlgorithm::Sha512 => {
+                b"1234567890123456789012345678901234567890123456789012345678901234"
+            }
+        };
+        let config = TotpConfig {
+            algorithm,
+            digits: 8,
+            ..TotpConfig::default()
+        };
+        Totp::new(key.to_vec(), config).unwrap()
+    }
+
+    #[test]
+    fn test_rfc6238_vectors() {
+        let vectors = [
+            (59, "94287082", "46119246", "90693936"),
+            (1111111109, "07081804", "68084774", "25091201"),
+            (1234567890, "89005924", "91819424", "93441116"),
+            (20000000000, "65353130", "77737706", "47863826"),
+        ];
+
+        for (time, sha1, sha256, sha512) in vectors {
+            assert_eq!(rfc_totp(HmacAlgorithm::Sha1).generate(time), sha1);
+            assert_eq!(rfc_totp(HmacAlgorithm::Sha256).generate(time), sha256);
+            assert_eq!(rfc_totp(HmacAlgorithm::Sha512).generate(time), sha512);
+        }
+    }
+
+    #[test]
+    fn test_debug_hides_key() {
+        let totp = rfc_totp(HmacAlgorithm::Sha1);
+        assert!(!format!("{:?}", totp).contains("49, 50"));
+    }
+
+    #[test]
+    fn test_verify_window() {
+        let totp = rfc_totp(HmacAlgorithm::Sha1);
+        let now = 1111111109;
+
+        let previous = totp.generate(now - 30);
+        let result = totp.verify(&previous, now).unwrap();
+        assert_eq!(result.skew, -1);
+
+        let far = totp.generate(now - 90);
+        assert!(totp.verify(&far, now).is_none());
+    }
+
+    #[test]
+    fn test_resync_records_drift() {
+        let mut totp = rfc_totp(HmacAlgorithm::Sha1);
+        let server_now = 1111111109;
+        let client_now = server_now + 5 * 30;
+
+        let first = totp.generate(client_now - 30);
+        let second = totp.generate(client_now);
+        assert!(totp.verify(&second, server_now).is_none());
+
+        assert_eq!(totp.resync(&first, &second, server_now, 10), Some(5));
+        let next = totp.generate_at_step(totp.time_step(client_now));
+        assert!(totp.verify(&next, server_now).is_some());
+    }
+
+    #[test]
+    fn test_clock() {
+        use crate::clock::ManualClock;
+        use std::time::Duration;
+
+        let totp = rfc_totp(HmacAlgorithm::Sha1)
+            .with_clock(ManualClock::new(UNIX_EPOCH + Duration::from_secs(59)));
+        assert_eq!(totp.generate_now(), "94287082");
+        assert!(totp.verify_now("94287082").is_some());
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let config = TotpConfig {
+            step: 0,
+            ..TotpConfig::default()
+        };
+        assert_eq!(
+            Totp::new(vec![0u8; 20], config).unwrap_err(),
+            Error::InvalidTimeStep
+        );
+    }
+}
@@ -0,0 +1,541 @@
+//! TPM 2.0 keys (feature `tpm`)
+//!
+//! [`TpmKey`] is a [`MacBackend`] computing HMAC-SHA-256 with a keyed-hash
+//! object inside the TPM, so the key never enters process memory. Commands
+//! go straight to the kernel's resource manager (`/dev/tpmrm0` on Linux);
+//! no TPM software stack is needed.
+//!
+//! The key has to be made persistent first, and the server needs a copy of
+//! it to verify, so import a generated key rather than creating one in the
+//! TPM, e.g. with `tpm2-tools`:
+//!
+//! ```text
+//! tpm2_createprimary -C o -c primary.ctx
+//! tpm2_create -C primary.ctx -G hmac -i key.bin -u key.pub -r key.priv
+//! tpm2_load -C primary.ctx -u key.pub -r key.priv -c key.ctx
+//! tpm2_evictcontrol -C o -c key.ctx 0x81000001
+//! ```
+//!
+//! ```no_run
+//! use passcode::tpm::TpmKey;
+//! use passcode::Passcode;
+//!
+//! let key = TpmKey::open(0x8100_0001).unwrap();
+//! let passcode = Passcode::from_backend(key).unwrap();
+//! let otp = passcode.try_compute(b"challenge").unwrap();
+//! ```
+
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::kdf::{key_fingerprint, KeyId};
+use crate::secret::SecretBytes;
+use crate::{Algorithm, Error, MacBackend};
+
+/// Device of the kernel's TPM resource manager
+pub const DEFAULT_DEVICE: &str = "/dev/tpmrm0";
+
+const TPM_ST_NO_SESSIONS: u16 = 0x8001;
+const TPM_ST_SESSIONS: u16 = 0x8002;
+const TPM_CC_SEQUENCE_COMPLETE: u32 = 0x0000_013e;
+const TPM_CC_HMAC: u32 = 0x0000_0155;
+const TPM_CC_HMAC_START: u32 = 0x0000_015b;
+const TPM_CC_SEQUENCE_UPDATE: u32 = 0x0000_015c;
+const TPM_CC_READ_PUBLIC: u32 = 0x0000_0173;
+const TPM_RS_PW: u32 = 0x4000_0009;
+const TPM_RH_NULL: u32 = 0x4000_0007;
+const TPM_ALG_HMAC: u16 = 0x0005;
+const TPM_ALG_KEYEDHASH: u16 = 0x0008;
+const TPM_ALG_SHA256: u16 = 0x000b;
+const TPM_ALG_NULL: u16 = 0x0010;
+/// Largest buffer every TPM accepts in one command
+const MAX_BUFFER: usize = 1024;
+/// Largest response of the commands sent here
+const MAX_RESPONSE: usize = 4096;
+
+/// Exchanges one command for its response
+trait Transport: Send + Sync {
+    fn transact(&self, command: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+/// TPM character device, which takes whole commands and returns whole
+/// responses
+struct Device(Mutex<File>);
+
+impl Transport for Device {
+    fn transact(&self, command: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut device = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        device
+            .write_all(command)
+            .map_err(|e| Error::Hardware(format!("TPM write failed: {}", e)))?;
+        let mut response = vec![0u8; MAX_RESPONSE];
+        let len = device
+            .read(&mut response)
+            .map_err(|e| Error::Hardware(format!("TPM read failed: {}", e)))?;
+        response.truncate(len);
+        Ok(response)
+    }
+}
+
+/// HMAC-SHA-256 key held in a TPM 2.0
+pub struct TpmKey {
+    transport: Box<dyn Transport>,
+    handle: u32,
+    auth: SecretBytes,
+    key_id: KeyId,
+}
+
+impl TpmKey {
+    /// Opens the persistent key at `handle` through [`DEFAULT_DEVICE`]
+    ///
+    /// Fails with [`Error::Hardware`] if the device cannot be opened or the
+    /// object is not an HMAC-SHA-256 key.
+    pub fn open(handle: u32) -> Result<Self, Error> {
+        Self::open_device(DEFAULT_DEVICE, handle)
+    }
+
+    /// Opens the persistent key at `handle` through another TPM device,
+    /// e.g. `/dev/tpm0` on systems without a resource manager
+    pub fn open_device(path: impl AsRef<Path>, handle: u32) -> Result<Self, Error> {
+        let device = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path.as_ref())
+            .map_err(|e| {
+                Error::Hardware(format!("cannot open {}: {}", path.as_ref().display(), e))
+            })?;
+        Self::with_transport(Box::new(Device(Mutex::new(device))), handle)
+    }
+
+    /// Reads the public area of the key and checks that it computes
+    /// HMAC-SHA-256
+    fn with_transport(transport: Box<dyn Transport>, handle: u32) -> Result<Self, Error> {
+        let command = command(TPM_CC_READ_PUBLIC, &[handle], None, &[]);
+        let response = transact(transport.as_ref(), &command, 0)?;
+        let mut reader = Reader(&response);
+        let public = reader.sized()?;
+        let name = reader.sized()?;
+        check_public(public)?;
+
+        Ok(Self {
+            transport,
+            handle,
+            auth: SecretBytes::new(Vec::new()),
+            key_id: key_fingerprint(name),
+        })
+    }
+
+    /// Sets the authorization value of the key, empty by default
+    pub fn with_auth(mut self, auth: &[u8]) -> Self {
+        self.auth = SecretBytes::new(auth.to_vec());
+        self
+    }
+
+    /// Gets the persistent handle of the key
+    pub fn handle(&self) -> u32 {
+        self.handle
+    }
+
+    /// Sends a command authorized with a password session
+    fn call(
+        &self,
+        code: u32,
+        handle: u32,
+        auth: &[u8],
+        params: &[u8],
+        handles: usize,
+    ) -> Result<Vec<u8>, Error> {
+        let command = command(code, &[handle], Some(auth), params);
+        transact(self.transport.as_ref(), &command, handles)
+    }
+
+    /// Computes the HMAC of data longer than one buffer with a sequence
+    fn hmac_sequence(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut params = sized(&[]);
+        params.extend_from_slice(&TPM_ALG_SHA256.to_be_bytes());
+        let response = self.call(TPM_CC_HMAC_START, self.handle, &self.auth, &params, 1)?;
+        let sequence = Reader(&response).u32()?;
+
+        // The last, non-empty chunk goes with the completing command
+        let (head, last) = data.split_at((data.len() - 1) / MAX_BUFFER * MAX_BUFFER);
+        for chunk in head.chunks(MAX_BUFFER) {
+            self.call(TPM_CC_SEQUENCE_UPDATE, sequence, &[], &sized(chunk), 0)?;
+        }
+        let mut params = sized(last);
+        params.extend_from_slice(&TPM_RH_NULL.to_be_bytes());
+        let response = self.call(TPM_CC_SEQUENCE_COMPLETE, sequence, &[], &params, 0)?;
+        Ok(Reader(&response).sized()?.to_vec())
+    }
+}
+
+impl MacBackend for TpmKey {
+    fn algorithm(&self) -> Algorithm {
+        Algorithm::HmacSha256
+    }
+
+    /// The fingerprint of the object's TPM name, a digest of its public area
+    fn key_id(&self) -> KeyId {
+        self.key_id
+    }
+
+    fn mac(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        if data.len() > MAX_BUFFER {
+            return self.hmac_sequence(data);
+        }
+        let mut params = sized(data);
+        params.extend_from_slice(&TPM_ALG_SHA256.to_be_bytes());
+        let response = self.call(TPM_CC_HMAC, self.handle, &self.auth, &params, 0)?;
+        Ok(Reader(&response).sized()?.to_vec())
+    }
+}
+
+impl fmt::Debug for TpmKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The authorization value is never printed
+        f.debug_struct("TpmKey")
+            .field("handle", &format_args!("{:#010x}", self.handle))
+            .field("key_id", &self.key_id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Marshals a command, with one password session if `auth` is given
+fn command(code: u32, handles: &[u32], auth: Option<&[u8]>, params: &[u8]) -> Vec<u8> {
+    let tag = match auth {
+        Some(_) => TPM_ST_SESSIONS,
+        None => TPM_ST_NO_SESSIONS,
+    };
+    let mut command = Vec::with_capacity(32 + params.len());
+    command.extend_from_slice(&tag.to_be_bytes());
+    command.extend_from_slice(&[0u8; 4]);
+    command.extend_from_slice(&code.to_be_bytes());
+    for handle in handles {
+        command.extend_from_slice(&handle.to_be_bytes());
+    }
+    if let Some(password) = auth {
+        // Empty nonce, continueSession, then the password as the HMAC
+        let mut session = TPM_RS_PW.to_be_bytes().to_vec();
+        session.extend_from_slice(&0u16.to_be_bytes());
+        session.push(0x01);
+        session.extend_from_slice(&sized(password));
+        command.extend_from_slice(&(session.len() as u32).to_be_bytes());
+        command.extend_from_slice(&session);
+    }
+    command.extend_from_slice(params);
+
+    let len = command.len() as u32;
+    command[2..6].copy_from_slice(&len.to_be_bytes());
+    command
+}
+
+/// Sends a command and returns its response handles and parameters
+///
+/// Fails with [`Error::Hardware`] for a TPM error code and with
+/// [`Error::MalformedMessage`] for a response that does not parse.
+fn transact(transport: &dyn Transport, command: &[u8], handles: usize) -> Result<Vec<u8>, Error> {
+    let response = transport.transact(command)?;
+    let mut reader = Reader(&response);
+    let tag = reader.u16()?;
+    let len = reader.u32()?;
+    let code = reader.u32()?;
+    if len as usize != response.len() {
+        return Err(Error::MalformedMessage("TPM response length mismatch"));
+    }
+    if code != 0 {
+        return Err(Error::Hardware(format!("TPM returned error {:#05x}", code)));
+    }
+
+    let handles = reader.take(4 * handles)?.to_vec();
+    let params = match tag {
+        TPM_ST_SESSIONS => {
+            let len = reader.u32()? as usize;
+            reader.take(len)?
+        }
+        _ => reader.0,
+    };
+    Ok([handles, params.to_vec()].concat())
+}
+
+/// Checks that a `TPMT_PUBLIC` describes an HMAC-SHA-256 key
+fn check_public(public: &[u8]) -> Result<(), Error> {
+    let mut reader = Reader(public);
+    let object_type = reader.u16()?;
+    reader.u16()?; // nameAlg
+    reader.u32()?; // objectAttributes
+    reader.sized()?; // authPolicy
+    let scheme = reader.u16()?;
+    let hash = match scheme {
+        TPM_ALG_HMAC => reader.u16()?,
+        _ => TPM_ALG_NULL,
+    };
+
+    if object_type != TPM_ALG_KEYEDHASH
+        || !matches!(
+            (scheme, hash),
+            (TPM_ALG_HMAC, TPM_ALG_SHA256) | (TPM_ALG_NULL, _)
+        )
+    {
+        return Err(Error::Hardware(
+            "TPM object is not an HMAC-SHA-256 key".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Prefixes `data` with its 16-bit length, as in a `TPM2B`
+fn sized(data: &[u8]) -> Vec<u8> {
+    let mut out = (data.len() as u16).to_be_bytes().to_vec();
+    out.extend_from_slice(data);
+    out
+}
+
+/// Big-endian reader over a TPM response
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.0.len() < len {
+            return Err(Error::MalformedMessage("truncated TPM response"));
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Reads a `TPM2B`, a buffer with a 16-bit length
+    fn sized(&mut self) -> Result<&'a [u8], Error> {
+        let len = self.u16()? as usize;
+        self.take(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Passcode;
+
+    const HANDLE: u32 = 0x8100_0001;
+    const SEQUENCE: u32 = 0x8000_0000;
+    const TPM_RC_AUTH_FAIL: u32 = 0x098e;
+    const TPM_RC_HANDLE: u32 = 0x008b;
+
+    /// Software TPM holding one key, answering the commands sent here
+    struct FakeTpm {
+        key: Vec<u8>,
+        auth: Vec<u8>,
+        object_type: u16,
+        sequence: Mutex<Option<Vec<u8>>>,
+    }
+
+    impl FakeTpm {
+        fn new(key: &[u8], auth: &[u8]) -> Self {
+            Self {
+                key: key.to_vec(),
+                auth: auth.to_vec(),
+                object_type: TPM_ALG_KEYEDHASH,
+                sequence: Mutex::new(None),
+            }
+        }
+
+        /// `TPMT_PUBLIC` of an HMAC-SHA-256 key
+        fn public(&self) -> Vec<u8> {
+            let mut public = self.object_type.to_be_bytes().to_vec();
+            public.extend_from_slice(&TPM_ALG_SHA256.to_be_bytes());
+            public.extend_from_slice(&0x0004_0072u32.to_be_bytes());
+            public.extend_from_slice(&sized(&[]));
+            public.extend_from_slice(&TPM_ALG_HMAC.to_be_bytes());
+            public.extend_from_slice(&TPM_ALG_SHA256.to_be_bytes());
+            public.extend_from_slice(&sized(&[0x5a; 32]));
+            public
+        }
+
+        fn hmac(&self, data: &[u8]) -> Vec<u8> {
+            crate::hotp::hmac(crate::hotp::HmacAlgorithm::Sha256, &self.key, data)
+        }
+
+        fn handle(&self, command: &[u8]) -> Result<(Vec<u32>, Vec<u8>), u32> {
+            let mut reader = Reader(command);
+            let tag = reader.u16().unwrap();
+            reader.u32().unwrap();
+            let code = reader.u32().unwrap();
+            let handle = reader.u32().unwrap();
+            let password = match tag {
+                TPM_ST_SESSIONS => {
+                    let len = reader.u32().unwrap() as usize;
+                    let mut session = Reader(reader.take(len).unwrap());
+                    assert_eq!(session.u32().unwrap(), TPM_RS_PW);
+                    session.sized().unwrap();
+                    session.take(1).unwrap();
+                    session.sized().unwrap().to_vec()
+                }
+                _ => Vec::new(),
+            };
+            let mut sequence = self.sequence.lock().unwrap();
+
+            match (code, handle) {
+                (TPM_CC_READ_PUBLIC, HANDLE) => {
+                    let mut params = sized(&self.public());
+                    params.extend_from_slice(&sized(b"name of the key"));
+                    params.extend_from_slice(&sized(b"qualified name"));
+                    Ok((vec![], params))
+                }
+                (TPM_CC_HMAC | TPM_CC_HMAC_START, HANDLE) if password != self.auth => {
+                    Err(TPM_RC_AUTH_FAIL)
+                }
+                (TPM_CC_HMAC, HANDLE) => {
+                    let data = reader.sized().unwrap();
+                    assert_eq!(reader.u16().unwrap(), TPM_ALG_SHA256);
+                    Ok((vec![], sized(&self.hmac(data))))
+                }
+                (TPM_CC_HMAC_START, HANDLE) => {
+                    *sequence = Some(Vec::new());
+                    Ok((vec![SEQUENCE], vec![]))
+                }
+                (TPM_CC_SEQUENCE_UPDATE, SEQUENCE) => {
+                    let data = reader.sized().unwrap();
+                    assert!(data.len() <= MAX_BUFFER);
+                    sequence
+                        .as_mut()
+                        .ok_or(TPM_RC_HANDLE)?
+                        .extend_from_slice(data);
+                    Ok((vec![], vec![]))
+                }
+                (TPM_CC_SEQUENCE_COMPLETE, SEQUENCE) => {
+                    let mut data = sequence.take().ok_or(TPM_RC_HANDLE)?;
+                    data.extend_from_slice(reader.sized().unwrap());
+                    assert_eq!(reader.u32().unwrap(), TPM_RH_NULL);
+                    let mut params = sized(&self.hmac(&data));
+                    // Empty hash-check ticket
+                    params.extend_from_slice(&0x8024u16.to_be_bytes());
+                    params.extend_from_slice(&TPM_RH_NULL.to_be_bytes());
+                    params.extend_from_slice(&sized(&[]));
+                    Ok((vec![], params))
+                }
+                _ => Err(TPM_RC_HANDLE),
+            }
+        }
+    }
+
+    impl Transport for FakeTpm {
+        fn transact(&self, command: &[u8]) -> Result<Vec<u8>, Error> {
+            let tag = u16::from_be_bytes([command[0], command[1]]);
+            let mut response = match self.handle(command) {
+                Ok((handles, params)) => {
+                    let mut response = tag.to_be_bytes().to_vec();
+                    response.extend_from_slice(&[0u8; 8]);
+                    for handle in handles {
+                        response.extend_from_slice(&handle.to_be_bytes());
+                    }
+                    if tag == TPM_ST_SESSIONS {
+                        response.extend_from_slice(&(params.len() as u32).to_be_bytes());
+                        response.extend_from_slice(&params);
+                        // Password session acknowledgement
+                        response.extend_from_slice(&[0, 0, 1, 0, 0]);
+                    } else {
+                        response.extend_from_slice(&params);
+                    }
+                    response
+                }
+                Err(code) => {
+                    let mut response = TPM_ST_NO_SESSIONS.to_be_bytes().to_vec();
+                    response.extend_from_slice(&[0u8; 4]);
+                    response.extend_from_slice(&code.to_be_bytes());
+                    response
+                }
+            };
+            let len = response.len() as u32;
+            response[2..6].copy_from_slice(&len.to_be_bytes());
+            Ok(response)
+        }
+    }
+
+    fn open(tpm: FakeTpm) -> Result<TpmKey, Error> {
+        TpmKey::with_transport(Box::new(tpm), HANDLE)
+    }
+
+    #[test]
+    fn test_matches_software_key() {
+        let key = [7u8; 32];
+        let tpm = open(FakeTpm::new(&key, b"")).unwrap();
+        let key_id = tpm.key_id();
+        let hardware = Passcode::from_backend(tpm).unwrap();
+        let software = Passcode::new(Algorithm::HmacSha256, key.to_vec());
+
+        assert!(hardware.has_backend());
+        assert_eq!(hardware.key_id(), key_id);
+        assert_eq!(key_id, key_fingerprint(b"name of the key"));
+        // Longer data than one buffer goes through a sequence
+        for data in [
+            vec![1u8; 32],
+            vec![2u8; MAX_BUFFER],
+            vec![3u8; 3 * MAX_BUFFER + 1],
+        ] {
+            let otp = hardware.try_compute(&data).unwrap();
+            assert_eq!(otp, software.compute(&data));
+            assert!(software.verify(&data, &otp));
+        }
+        assert_eq!(
+            hardware.derive_session_key(b"challenge", 48),
+            software.derive_session_key(b"challenge", 48)
+        );
+        assert_eq!(
+            hardware.compute_raw(b"challenge", 80),
+            software.compute_raw(b"challenge", 80)
+        );
+        assert_eq!(
+            hardware.for_device("phone").compute(b"challenge"),
+            software.for_device("phone").compute(b"challenge")
+        );
+    }
+
+    #[test]
+    fn test_authorization() {
+        let key = [7u8; 32];
+        let hardware = Passcode::from_backend(open(FakeTpm::new(&key, b"pin")).unwrap()).unwrap();
+        let otp = Passcode::new(Algorithm::HmacSha256, key.to_vec()).compute(b"challenge");
+
+        assert_eq!(
+            hardware.try_compute(b"challenge"),
+            Err(Error::Hardware("TPM returned error 0x98e".to_string()))
+        );
+        assert!(!hardware.verify(b"challenge", &otp));
+
+        let tpm = open(FakeTpm::new(&key, b"pin")).unwrap().with_auth(b"pin");
+        assert!(!format!("{:?}", tpm).contains("pin"));
+        let hardware = Passcode::from_backend(tpm).unwrap();
+        assert_eq!(hardware.try_verify(b"challenge", &otp), Ok(true));
+    }
+
+    #[test]
+    fn test_rejects_other_objects() {
+        let mut tpm = FakeTpm::new(&[7u8; 32], b"");
+        tpm.object_type = 0x0025; // TPM_ALG_SYMCIPHER
+        assert_eq!(
+            open(tpm).err(),
+            Some(Error::Hardware(
+                "TPM object is not an HMAC-SHA-256 key".to_string()
+            ))
+        );
+        assert!(matches!(
+            TpmKey::with_transport(Box::new(FakeTpm::new(&[7u8; 32], b"")), 0x8100_0002),
+            Err(Error::Hardware(_))
+        ));
+        assert!(matches!(
+            TpmKey::open_device("/nonexistent/tpm", HANDLE),
+            Err(Error::Hardware(_))
+        ));
+    }
+}
@@ -0,0 +1,151 @@
+//! Diagnostics through `tracing` (feature `tracing`) and `metrics` (feature
+//! `metrics`)
+//!
+//! Without the features every function here compiles to nothing. Events carry
+//! challenge ids, user ids, purposes and outcomes; challenge bytes, OTPs,
+//! puzzle solutions and keys are never recorded.
+
+use std::future::Future;
+use std::time::Duration;
+
+#[cfg(feature = "tracing")]
+use tracing::Instrument;
+
+use crate::challenge::{Challenge, ChallengeBinding, ChallengeId};
+use crate::throttle::{AnomalyEvent, AnomalySubject, LockoutEvent};
+use crate::Error;
+
+/// Runs a verification inside a `passcode.verify` span
+pub(crate) async fn verify<F: Future>(
+    id: &ChallengeId,
+    binding: &ChallengeBinding,
+    fut: F,
+) -> F::Output {
+    #[cfg(feature = "tracing")]
+    let fut = fut.instrument(tracing::debug_span!(
+        "passcode.verify",
+        challenge_id = %id,
+        user_id = binding.user_id(),
+        purpose = binding.purpose(),
+    ));
+    #[cfg(not(feature = "tracing"))]
+    let _ = (id, binding);
+    fut.await
+}
+
+/// Records a newly issued challenge
+pub(crate) fn issued(challenge: &Challenge) {
+    #[cfg(feature = "metrics")]
+    crate::metrics::issued(1);
+    #[cfg(feature = "tracing")]
+    tracing::debug!(
+        challenge_id = %challenge.id(),
+        user_id = challenge.binding().user_id(),
+        purpose = challenge.binding().purpose(),
+        difficulty = challenge.difficulty(),
+        "challenge issued"
+    );
+    #[cfg(not(feature = "tracing"))]
+    let _ = challenge;
+}
+
+/// Records a batch of issued challenges
+pub(crate) fn batch_issued(count: usize, ttl: Duration) {
+    #[cfg(feature = "metrics")]
+    crate::metrics::issued(count);
+    #[cfg(feature = "tracing")]
+    tracing::debug!(
+        count,
+        ttl_ms = ttl.as_millis() as u64,
+        "challenge batch issued"
+    );
+    #[cfg(not(feature = "tracing"))]
+    let _ = (count, ttl);
+}
+
+/// Records the outcome of a store operation; failures are errors, successes
+/// trace-level
+pub(crate) fn store<T>(operation: &'static str, result: &Result<T, Error>) {
+    #[cfg(feature = "tracing")]
+    match result {
+        Ok(_) => tracing::trace!(operation, "challenge store operation succeeded"),
+        Err(error) => tracing::error!(operation, %error, "challenge store operation failed"),
+    }
+    #[cfg(not(feature = "tracing"))]
+    let _ = (operation, result);
+}
+
+/// Records the outcome of a verification
+///
+/// Refusals are policy decisions rather than faults: wrong OTPs, unknown,
+/// expired or replayed challenges and unsolved puzzles are logged at info
+/// level, throttling at warn level, and anything else as an error.
+pub(crate) fn verified<T>(result: &Result<T, Error>, elapsed: Duration) {
+    #[cfg(feature = "metrics")]
+    crate::metrics::verified(result, elapsed);
+    #[cfg(feature = "tracing")]
+    match result {
+        Ok(_) => tracing::info!("verification accepted"),
+        Err(
+            error @ (Error::OtpMismatch
+            | Error::ChallengeNotFound
+            | Error::ChallengeExpired
+            | Error::ReplayDetected
+            | Error::PuzzleUnsolved),
+        ) => tracing::info!(reason = %error, "verification refused"),
+        Err(error @ (Error::RateLimited { retry_after } | Error::LockedOut { retry_after })) => {
+            tracing::warn!(
+                reason = %error,
+                retry_after_ms = retry_after.as_millis() as u64,
+                "verification throttled"
+            )
+        }
+        Err(error) => tracing::error!(%error, "verification failed"),
+    }
+    #[cfg(not(any(feature = "tracing", feature = "metrics")))]
+    let _ = result;
+    #[cfg(not(feature = "metrics"))]
+    let _ = elapsed;
+}
+
+/// Records a lockout triggered by a failed verification
+pub(crate) fn locked_out(event: &LockoutEvent) {
+    #[cfg(feature = "metrics")]
+    crate::metrics::locked_out();
+    #[cfg(feature = "tracing")]
+    tracing::warn!(
+        identity = %event.identity,
+        duration_ms = event.duration.as_millis() as u64,
+        lock_count = event.lock_count,
+        "identity locked out"
+    );
+    #[cfg(not(feature = "tracing"))]
+    let _ = event;
+}
+
+/// Records a subject whose failure rate crossed the anomaly threshold
+pub(crate) fn anomaly(event: &AnomalyEvent) {
+    let subject = match &event.subject {
+        AnomalySubject::Identity(_) => "identity",
+        AnomalySubject::Source(_) => "source",
+    };
+    #[cfg(feature = "metrics")]
+    crate::metrics::anomaly(subject);
+    #[cfg(not(feature = "metrics"))]
+    let _ = subject;
+    #[cfg(feature = "tracing")]
+    match &event.subject {
+        AnomalySubject::Identity(identity) => tracing::warn!(
+            %identity,
+            failures = event.failures,
+            window_ms = event.window.as_millis() as u64,
+            "identity failure rate anomaly"
+        ),
+        AnomalySubject::Source(source) => tracing::warn!(
+            %source,
+            failures = event.failures,
+            window_ms = event.window.as_millis() as u64,
+            "source failure rate anomaly"
+        ),
+    }
+}
@@ -0,0 +1,327 @@
+//! Cross-language test vectors (feature `test-vectors`)
+//!
+//! Every port checks itself against the same JSON file,
+//! `test/vectors.json` at the repository root, so the Rust, FFI, WASM,
+//! Node.js, Python, Go and Dart implementations are shown to produce the
+//! same bytes. The file is generated from this crate with
+//! `cargo run --example generate_vectors --features test-vectors`.
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "vectors": [
+//!     {
+//!       "name": "SHA3-KMAC-256/reference",
+//!       "algorithm": "SHA3-KMAC-256",
+//!       "algorithm_id": 1,
+//!       "key": "0123…",
+//!       "challenge": "fedc…",
+//!       "customization": "authorization",
+//!       "mac": "…",
+//!       "otp": "…"
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Byte strings are lowercase hex. `algorithm` is the
+//! [`as_str`](Algorithm::as_str) name and `algorithm_id` the
+//! [`Algorithm::id`] used by the FFI and WASM bindings. `customization` is
+//! the KMAC customization string, `null` for algorithms without one. `mac`
+//! is the full keyed hash of the challenge before truncation and `otp` the
+//! default hexadecimal OTP. Ports skip vectors for algorithms they do not
+//! support.
+//!
+//! # Example
+//! ```
+//! use passcode::vectors::TestVectors;
+//!
+//! let json = TestVectors::generate().to_json();
+//! let vectors = TestVectors::from_json(&json).unwrap();
+//! assert_eq!(vectors.check().unwrap(), vectors.vectors.len());
+//! ```
+
+use serde::{Deserialize, Serialize};
+
+use crate::sha3_kmac::PASSCODE_CUSTOMIZATION;
+use crate::{Algorithm, Error, Passcode, XofAlgorithm};
+
+/// Version of the file format
+pub const VECTORS_VERSION: u32 = 1;
+
+/// Key of the reference vectors, shared with the older cross-implementation
+/// tests
+const REFERENCE_KEY: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+/// Challenge of the reference vectors
+const REFERENCE_CHALLENGE: &str = "fedcba9876543210fedcba9876543210";
+
+/// A test-vector file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestVectors {
+    /// Format version, [`VECTORS_VERSION`]
+    pub version: u32,
+    /// The vectors
+    pub vectors: Vec<TestVector>,
+}
+
+impl TestVectors {
+    /// Wraps vectors in a file of the current version
+    pub fn new(vectors: Vec<TestVector>) -> Self {
+        Self {
+            version: VECTORS_VERSION,
+            vectors,
+        }
+    }
+
+    /// Generates the standard vectors for every algorithm in this build: the
+    /// reference key and challenge, an empty challenge, and a 256-byte
+    /// challenge with a 16-byte key
+    pub fn generate() -> Self {
+        let reference_key = hex::decode(REFERENCE_KEY).expect("valid hex");
+        let reference_challenge = hex::decode(REFERENCE_CHALLENGE).expect("valid hex");
+        let long_challenge: Vec<u8> = (0..=u8::MAX).collect();
+
+        let mut vectors = Vec::new();
+        for algorithm in Algorithm::all() {
+            let name = |case: &str| format!("{}/{}", algorithm.as_str(), case);
+            vectors.push(TestVector::generate(
+                name("reference"),
+                algorithm,
+                &reference_key,
+                &reference_challenge,
+            ));
+            vectors.push(TestVector::generate(
+                name("empty-challenge"),
+                algorithm,
+                &reference_key,
+                &[],
+            ));
+            vectors.push(TestVector::generate(
+                name("long-challenge"),
+                algorithm,
+                &[0x42; 16],
+                &long_challenge,
+            ));
+        }
+        Self::new(vectors)
+    }
+
+    /// Parses a file, failing with [`Error::MalformedMessage`] for invalid
+    /// JSON or another version
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        let vectors: Self = serde_json::from_str(json)
+            .map_err(|_| Error::MalformedMessage("invalid test vector file"))?;
+        if vectors.version != VECTORS_VERSION {
+            return Err(Error::MalformedMessage("unsupported test vector version"));
+        }
+        Ok(vectors)
+    }
+
+    /// Writes the file as pretty-printed JSON
+    pub fn to_json(&self) -> String {
+        let mut json = serde_json::to_string_pretty(self).expect("vectors serialize");
+        json.push('\n');
+        json
+    }
+
+    /// Checks every vector whose algorithm this build supports, returning
+    /// how many were checked
+    ///
+    /// Stops at the first failure of [`TestVector::check`].
+    pub fn check(&self) -> Result<usize, Error> {
+        let mut checked = 0;
+        for vector in &self.vectors {
+            if vector.algorithm().is_some() {
+                vector.check()?;
+                checked += 1;
+            }
+        }
+        Ok(checked)
+    }
+}
+
+/// One key, challenge and the outputs expected for them
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestVector {
+    /// Unique name, `<algorithm>/<case>` for generated vectors
+    pub name: String,
+    /// Algorithm name, kept as text so files with algorithms outside this
+    /// build still load
+    pub algorithm: String,
+    /// Numeric algorithm ID of the FFI and WASM bindings
+    pub algorithm_id: u8,
+    /// Secret key
+    #[serde(with = "hex_bytes")]
+    pub key: Vec<u8>,
+    /// Challenge the OTP is computed over
+    #[serde(with = "hex_bytes")]
+    pub challenge: Vec<u8>,
+    /// KMAC customization string, if the algorithm has one
+    pub customization: Option<String>,
+    /// Full keyed hash of the challenge, before truncation
+    #[serde(with = "hex_bytes")]
+    pub mac: Vec<u8>,
+    /// Default hexadecimal OTP
+    pub otp: String,
+}
+
+impl TestVector {
+    /// Computes the expected outputs for a key and challenge
+    pub fn generate(
+        name: impl Into<String>,
+        algorithm: Algorithm,
+        key: &[u8],
+        challenge: &[u8],
+    ) -> Self {
+        let customization = match algorithm.xof() {
+            XofAlgorithm::Sha3Kmac128 | XofAlgorithm::Sha3Kmac256 => Some(
+                String::from_utf8(PASSCODE_CUSTOMIZATION.to_vec()).expect("ASCII customization"),
+            ),
+            _ => None,
+        };
+        Self {
+            name: name.into(),
+            algorithm: algorithm.as_str().to_string(),
+            algorithm_id: algorithm.id(),
+            key: key.to_vec(),
+            challenge: challenge.to_vec(),
+            customization,
+            mac: (algorithm.hasher())(key, challenge),
+            otp: Passcode::new(algorithm, key.to_vec()).compute(challenge),
+        }
+    }
+
+    /// Looks up the algorithm, or `None` if this build does not support it
+    pub fn algorithm(&self) -> Option<Algorithm> {
+        Algorithm::all().find(|algorithm| algorithm.as_str() == self.algorithm)
+    }
+
+    /// Recomputes the vector and compares every field
+    ///
+    /// Fails with [`Error::MalformedMessage`] for an algorithm outside this
+    /// build and with [`Error::VectorMismatch`] naming the first field that
+    /// differs.
+    pub fn check(&self) -> Result<(), Error> {
+        let algorithm = self
+            .algorithm()
+            .ok_or(Error::MalformedMessage("unknown algorithm"))?;
+        let expected = Self::generate(self.name.clone(), algorithm, &self.key, &self.challenge);
+
+        let field = if expected.algorithm_id != self.algorithm_id {
+            "algorithm_id"
+        } else if expected.customization != self.customization {
+            "customization"
+        } else if expected.mac != self.mac {
+            "mac"
+        } else if expected.otp != self.otp {
+            "otp"
+        } else {
+            return Ok(());
+        };
+        Err(Error::VectorMismatch {
+            name: self.name.clone(),
+            field,
+        })
+    }
+}
+
+mod hex_bytes {
+    use serde::de::Error as _;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        hex::decode(String::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The shared file every port checks
+    const SHARED_VECTORS: &str = include_str!("../../../test/vectors.json");
+
+    #[test]
+    fn test_shared_file_is_current() {
+        let vectors = TestVectors::from_json(SHARED_VECTORS).unwrap();
+        let supported = vectors
+            .vectors
+            .iter()
+            .filter(|v| v.algorithm().is_some())
+            .count();
+        assert_eq!(vectors.check(), Ok(supported));
+
+        // Regenerate the file when adding cases; builds with more algorithms
+        // than the file covers only compare the ones it has
+        for generated in TestVectors::generate().vectors {
+            if vectors
+                .vectors
+                .iter()
+                .any(|v| v.algorithm == generated.algorithm)
+            {
+                assert!(vectors.vectors.contains(&generated), "{}", generated.name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_ffi_matches_vectors() {
+        let vectors = TestVectors::from_json(SHARED_VECTORS).unwrap();
+        // Vectors of algorithms outside this build are skipped
+        for vector in vectors.vectors.iter().filter(|v| v.algorithm().is_some()) {
+            let mut out = [0u8; 64];
+            // SAFETY: the pointers come from live slices with their lengths,
+            // and the passcode is freed exactly once
+            let len = unsafe {
+                let passcode =
+                    crate::passcode_new(vector.algorithm_id, vector.key.as_ptr(), vector.key.len());
+                let len = crate::passcode_compute(
+                    passcode,
+                    vector.challenge.as_ptr(),
+                    vector.challenge.len(),
+                    out.as_mut_ptr(),
+                    out.len(),
+                );
+                crate::passcode_free(passcode);
+                len
+            };
+            assert_eq!(
+                &out[..len as usize],
+                vector.otp.as_bytes(),
+                "{}",
+                vector.name
+            );
+        }
+    }
+
+    #[test]
+    fn test_reference_outputs() {
+        // Published in PORTS_SUMMARY.md
+        let vectors = TestVectors::generate();
+        let otp = |name: &str| &vectors.vectors.iter().find(|v| v.name == name).unwrap().otp;
+        assert_eq!(otp("SHA3-KMAC-128/reference"), "2ce05573dd4e");
+        assert_eq!(otp("SHA3-KMAC-256/reference"), "f391e239e588");
+    }
+
+    #[test]
+    fn test_check_reports_field() {
+        let mut vector = TestVector::generate("case", Algorithm::Sha3Kmac256, b"key", b"challenge");
+        vector.otp = "000000000000".to_string();
+        assert_eq!(
+            vector.check(),
+            Err(Error::VectorMismatch {
+                name: "case".to_string(),
+                field: "otp",
+            })
+        );
+        vector.algorithm = "SHA3-KMAC-1024".to_string();
+        assert_eq!(TestVectors::new(vec![vector]).check(), Ok(0));
+
+        assert!(TestVectors::from_json(r#"{"version": 2, "vectors": []}"#).is_err());
+    }
+}
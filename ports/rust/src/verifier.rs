@@ -0,0 +1,949 @@
+//! High-level server-side verification
+//!
+//! [`Verifier`] wires a [`KeyRing`], a [`ChallengeManager`], rate limiting
+//! and lockout together behind two calls: issue a challenge for a user, then
+//! check the user's response. Security outcomes are reported as a
+//! [`VerifyOutcome`]; only infrastructure failures (storage, randomness,
+//! hardware keys) and policy violations are errors.
+//!
+//! # Example
+//! ```
+//! use passcode::keyring::KeyRing;
+//! use passcode::verifier::{Verifier, VerifyOutcome};
+//! use passcode::{Algorithm, Passcode};
+//!
+//! # pollster::block_on(async {
+//! let keyring = KeyRing::new();
+//! keyring.insert("alice", Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]));
+//! let verifier = Verifier::new(keyring);
+//!
+//! let challenge = verifier.issue_challenge("alice").await.unwrap();
+//!
+//! // Client side, holding alice's key
+//! let client = Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+//! let otp = client.compute(&challenge.message());
+//!
+//! let outcome = verifier.check("alice", challenge.id(), &otp).await.unwrap();
+//! assert_eq!(outcome, VerifyOutcome::Accepted);
+//! # });
+//! ```
+//!
+//! Users with several devices register each one on the key ring, and
+//! [`Verifier::check_device`] reports which device answered. A key ring
+//! created with [`KeyRing::with_master`] derives each user's key from one
+//! master key. With the `session-token` feature, `check_session` also mints
+//! a JWT signed with a key derived from the user's key, so rotating the key
+//! or revoking the device invalidates it.
+//!
+//! [`AuthEvents`] reports issued challenges, outcomes and lockouts to audit
+//! logs without OTPs or keys. The `tracing` and `metrics` features report
+//! the same points as spans and as metrics (see the `metrics` module).
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::challenge::{
+    Accepted, Challenge, ChallengeBinding, ChallengeId, ChallengeManager, ChallengePolicy,
+    ChallengeStore, ChannelBinding, MemoryStore, ReplayGuard,
+};
+use crate::clock::Clock;
+use crate::keyring::KeyRing;
+use crate::rng::CryptoRngCore;
+use crate::throttle::{AnomalyDetector, Lockout, LockoutEvent, LockoutPolicy, RateLimiter};
+#[cfg(feature = "session-token")]
+use crate::token::{SessionTokens, TokenClaims};
+use crate::Error;
+
+/// Result of checking a response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyOutcome {
+    /// The OTP is correct
+    Accepted,
+    /// The OTP is wrong, was issued for another user, or the user has no key
+    Rejected,
+    /// The challenge expired before the response arrived
+    Expired,
+    /// The challenge is unknown or was already used
+    UnknownChallenge,
+    /// The challenge was already accepted once
+    Replayed,
+    /// The puzzle attached to the challenge was not solved
+    PuzzleUnsolved,
+    /// Too many attempts for the user
+    RateLimited {
+        /// Time until the next attempt is allowed
+        retry_after: Duration,
+    },
+    /// The user is locked out after repeated failures
+    LockedOut {
+        /// Time until the lock ends
+        retry_after: Duration,
+    },
+}
+
+impl VerifyOutcome {
+    /// Returns true only for [`VerifyOutcome::Accepted`]
+    pub fn is_accepted(&self) -> bool {
+        *self == VerifyOutcome::Accepted
+    }
+}
+
+/// Receives security events from a [`Verifier`], e.g. to feed an audit log
+/// or a SIEM
+///
+/// Every method does nothing by default. Methods run on the task that
+/// issued or checked the challenge, so slow sinks should queue events.
+/// Events carry user and challenge ids but never OTPs or keys. Store and
+/// randomness failures are returned to the caller and not reported here.
+pub trait AuthEvents: Send + Sync {
+    /// A challenge was issued to the user
+    fn challenge_issued(&self, _user_id: &str, _challenge: &Challenge) {}
+
+    /// The user's response was accepted
+    fn verify_succeeded(&self, _user_id: &str, _challenge_id: &ChallengeId) {}
+
+    /// The user's response was refused; `reason` is never
+    /// [`VerifyOutcome::Accepted`]
+    fn verify_failed(&self, _user_id: &str, _challenge_id: &ChallengeId, _reason: VerifyOutcome) {}
+
+    /// A failed response locked the user out
+    ///
+    /// Called after [`verify_failed`](Self::verify_failed) for the failure
+    /// that caused the lock.
+    fn locked_out(&self, _event: &LockoutEvent) {}
+}
+
+impl<E: AuthEvents + ?Sized> AuthEvents for Arc<E> {
+    fn challenge_issued(&self, user_id: &str, challenge: &Challenge) {
+        (**self).challenge_issued(user_id, challenge);
+    }
+
+    fn verify_succeeded(&self, user_id: &str, challenge_id: &ChallengeId) {
+        (**self).verify_succeeded(user_id, challenge_id);
+    }
+
+    fn verify_failed(&self, user_id: &str, challenge_id: &ChallengeId, reason: VerifyOutcome) {
+        (**self).verify_failed(user_id, challenge_id, reason);
+    }
+
+    fn locked_out(&self, event: &LockoutEvent) {
+        (**self).locked_out(event);
+    }
+}
+
+/// Issues challenges to users and checks their responses
+///
+/// By default a verifier keeps challenges in a [`MemoryStore`], rate limits
+/// each user with [`RateLimiter::default`] and locks users out per
+/// [`LockoutPolicy::default`]. Challenges are issued for unknown users too,
+/// so responses do not reveal which users exist; checking them yields
+/// [`VerifyOutcome::Rejected`].
+pub struct Verifier<S = MemoryStore> {
+    manager: ChallengeManager<S>,
+    events: Option<Arc<dyn AuthEvents>>,
+    #[cfg(feature = "session-token")]
+    tokens: SessionTokens,
+}
+
+impl Verifier<MemoryStore> {
+    /// Creates a verifier for the users in the key ring
+    pub fn new(keyring: KeyRing) -> Self {
+        Self::with_store(keyring, MemoryStore::new())
+    }
+}
+
+impl<S: ChallengeStore> Verifier<S> {
+    /// Creates a verifier keeping challenges in the given store
+    pub fn with_store(keyring: KeyRing, store: S) -> Self {
+        Self {
+            manager: ChallengeManager::with_keyring(keyring, store)
+                .with_rate_limiter(RateLimiter::default())
+                .with_lockout(Lockout::new(LockoutPolicy::default())),
+            events: None,
+            #[cfg(feature = "session-token")]
+            tokens: SessionTokens::default(),
+        }
+    }
+
+    /// Sets how long issued challenges remain valid
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.manager = self.manager.with_ttl(ttl);
+        self
+    }
+
+    /// Replaces the default rate limiter
+    pub fn with_rate_limiter(mut self, limiter: RateLimiter) -> Self {
+        self.manager = self.manager.with_rate_limiter(limiter);
+        self
+    }
+
+    /// Replaces the default lockout tracker
+    pub fn with_lockout(mut self, lockout: Lockout) -> Self {
+        self.manager = self.manager.with_lockout(lockout);
+        self
+    }
+
+    /// Reports users with unusually many wrong OTPs to an
+    /// [`AnomalyDetector`]
+    ///
+    /// See [`ChallengeManager::with_anomaly_detector`].
+    pub fn with_anomaly_detector(mut self, detector: AnomalyDetector) -> Self {
+        self.manager = self.manager.with_anomaly_detector(detector);
+        self
+    }
+
+    /// Attaches a proof-of-work puzzle to issued challenges; responses must
+    /// then be checked with [`check_solved`](Self::check_solved)
+    ///
+    /// See [`ChallengeManager::with_puzzle`].
+    pub fn with_puzzle(mut self, difficulty: u8) -> Self {
+        self.manager = self.manager.with_puzzle(difficulty);
+        self
+    }
+
+    /// Verifies OTPs over large messages on Tokio's blocking thread pool
+    /// (feature `tokio`)
+    ///
+    /// See [`ChallengeManager::with_offload_threshold`].
+    #[cfg(feature = "tokio")]
+    pub fn with_offload_threshold(mut self, bytes: usize) -> Self {
+        self.manager = self.manager.with_offload_threshold(bytes);
+        self
+    }
+
+    /// Expects time-bound OTPs, accepting `window` time steps on either
+    /// side; check them with [`check_timed`](Self::check_timed)
+    ///
+    /// See [`ChallengeManager::with_time_steps`].
+    pub fn with_time_steps(mut self, step: Duration, window: u64) -> Self {
+        self.manager = self.manager.with_time_steps(step, window);
+        self
+    }
+
+    /// Enforces a [`ChallengePolicy`] on issued and verified challenges
+    ///
+    /// See [`ChallengeManager::with_policy`].
+    pub fn with_challenge_policy(mut self, policy: ChallengePolicy) -> Self {
+        self.manager = self.manager.with_policy(policy);
+        self
+    }
+
+    /// Makes unknown, expired and wrong responses take the same hash work
+    ///
+    /// See [`ChallengeManager::with_uniform_timing`].
+    pub fn with_uniform_timing(mut self, enabled: bool) -> Self {
+        self.manager = self.manager.with_uniform_timing(enabled);
+        self
+    }
+
+    /// Remembers accepted challenges in a [`ReplayGuard`]
+    pub fn with_replay_guard(mut self, guard: ReplayGuard) -> Self {
+        self.manager = self.manager.with_replay_guard(guard);
+        self
+    }
+
+    /// Reads the time for expiry, rate limiting and lockout from `clock`
+    ///
+    /// See [`ChallengeManager::with_clock`].
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.manager = self.manager.with_clock(clock);
+        self
+    }
+
+    /// Draws challenges from `rng` instead of the operating system's
+    /// CSPRNG, e.g. a seeded generator in tests
+    pub fn with_rng(mut self, rng: impl CryptoRngCore + Send + 'static) -> Self {
+        self.manager = self.manager.with_rng(rng);
+        self
+    }
+
+    /// Reports issued challenges and verification results to `events`
+    pub fn with_events(mut self, events: impl AuthEvents + 'static) -> Self {
+        self.events = Some(Arc::new(events));
+        self
+    }
+
+    /// Sets the lifetime and issuer of the session tokens minted by
+    /// [`check_session`](Self::check_session) (feature `session-token`)
+    #[cfg(feature = "session-token")]
+    pub fn with_session_tokens(mut self, tokens: SessionTokens) -> Self {
+        self.tokens = tokens;
+        self
+    }
+
+    /// Gets the key ring, e.g. to enroll or remove users
+    pub fn keyring(&self) -> &KeyRing {
+        self.manager
+            .keyring()
+            .expect("verifier managers always use a key ring")
+    }
+
+    /// Gets the lockout tracker, to query or reset a user's state
+    pub fn lockout(&self) -> &Lockout {
+        self.manager
+            .lockout()
+            .expect("verifier managers always have a lockout")
+    }
+
+    /// Gets the underlying challenge manager
+    pub fn manager(&self) -> &ChallengeManager<S> {
+        &self.manager
+    }
+
+    /// Issues a challenge bound to the user
+    ///
+    /// The client computes its OTP over [`Challenge::message`].
+    pub async fn issue_challenge(&self, user_id: &str) -> Result<Challenge, Error> {
+        let challenge = self.manager.issue_bound(binding(user_id)).await?;
+        if let Some(events) = &self.events {
+            events.challenge_issued(user_id, &challenge);
+        }
+        Ok(challenge)
+    }
+
+    /// Checks the user's OTP for a challenge, consuming the challenge
+    ///
+    /// Returns an error rather than an outcome when the challenge store
+    /// fails, when the request or the stored challenge breaks the
+    /// [`ChallengePolicy`] ([`Error::BindingFieldMissing`],
+    /// [`Error::ChallengeTooShort`], [`Error::ChallengeLifetimeTooLong`]),
+    /// when the user's key breaks its [`PolicyMode`](crate::policy::PolicyMode)
+    /// ([`Error::AlgorithmNotApproved`], [`Error::KeyTooShort`]), or when a
+    /// hardware-backed key fails ([`Error::Hardware`]).
+    pub async fn check(
+        &self,
+        user_id: &str,
+        challenge_id: &ChallengeId,
+        otp: &str,
+    ) -> Result<VerifyOutcome, Error> {
+        self.check_response(user_id, challenge_id, None, None, otp)
+            .await
+            .map(|(outcome, _)| outcome)
+    }
+
+    /// Checks the user's OTP and puzzle solution for a challenge issued with
+    /// a puzzle attached, consuming the challenge
+    ///
+    /// Errors are those of [`check`](Self::check).
+    pub async fn check_solved(
+        &self,
+        user_id: &str,
+        challenge_id: &ChallengeId,
+        solution: u64,
+        otp: &str,
+    ) -> Result<VerifyOutcome, Error> {
+        self.check_response(user_id, challenge_id, None, Some(solution), otp)
+            .await
+            .map(|(outcome, _)| outcome)
+    }
+
+    /// Checks the user's time-bound OTP for a challenge, consuming the
+    /// challenge, and reports the client's clock skew in time steps
+    ///
+    /// The skew is returned for accepted responses only. A positive skew
+    /// means the client's clock is ahead; a non-zero skew is a cue to
+    /// resynchronize the client before it drifts out of the window. Errors
+    /// are those of [`check`](Self::check).
+    pub async fn check_timed(
+        &self,
+        user_id: &str,
+        challenge_id: &ChallengeId,
+        otp: &str,
+    ) -> Result<(VerifyOutcome, Option<i64>), Error> {
+        let (outcome, accepted) = self
+            .check_response(user_id, challenge_id, None, None, otp)
+            .await?;
+        Ok((outcome, accepted.map(|accepted| accepted.skew)))
+    }
+
+    /// Checks the user's OTP for a challenge, consuming the challenge, and
+    /// reports which registered device answered
+    ///
+    /// For users with devices registered in the key ring, each device's key
+    /// is tried in turn; the device is returned for accepted responses from
+    /// a device key and is `None` otherwise. Errors are those of
+    /// [`check`](Self::check).
+    pub async fn check_device(
+        &self,
+        user_id: &str,
+        challenge_id: &ChallengeId,
+        otp: &str,
+    ) -> Result<(VerifyOutcome, Option<String>), Error> {
+        let (outcome, accepted) = self
+            .check_response(user_id, challenge_id, None, None, otp)
+            .await?;
+        Ok((outcome, accepted.and_then(|accepted| accepted.device_id)))
+    }
+
+    /// Checks the user's OTP computed over the message bound to the TLS
+    /// channel, consuming the challenge
+    ///
+    /// `channel` comes from the server's end of the connection the response
+    /// arrived on; see [`ChannelBinding`]. An OTP computed for another
+    /// channel is [`VerifyOutcome::Rejected`]. Errors are those of
+    /// [`check`](Self::check), and [`Error::MalformedMessage`] for empty
+    /// binding data.
+    pub async fn check_channel(
+        &self,
+        user_id: &str,
+        challenge_id: &ChallengeId,
+        channel: &ChannelBinding,
+        otp: &str,
+    ) -> Result<VerifyOutcome, Error> {
+        self.check_response(user_id, challenge_id, Some(channel), None, otp)
+            .await
+            .map(|(outcome, _)| outcome)
+    }
+
+    /// Checks the user's OTP like [`check_device`](Self::check_device) and
+    /// mints a session token for accepted responses (feature
+    /// `session-token`)
+    ///
+    /// The token names the user and the device that answered, and is
+    /// signed with a key derived from the user's key; see
+    /// [`token`](crate::token). Errors are those of [`check`](Self::check).
+    #[cfg(feature = "session-token")]
+    pub async fn check_session(
+        &self,
+        user_id: &str,
+        challenge_id: &ChallengeId,
+        otp: &str,
+    ) -> Result<(VerifyOutcome, Option<String>), Error> {
+        let (outcome, device_id) = self.check_device(user_id, challenge_id, otp).await?;
+        // A key removed while the response was checked yields no token
+        let token = match self.keyring().get(user_id) {
+            Some(passcode) if outcome.is_accepted() => Some(self.tokens.mint(
+                &passcode,
+                user_id,
+                device_id.as_deref(),
+                self.manager.clock().now(),
+            )),
+            _ => None,
+        };
+        Ok((outcome, token))
+    }
+
+    /// Verifies a session token minted by
+    /// [`check_session`](Self::check_session), returning its claims
+    /// (feature `session-token`)
+    ///
+    /// See [`SessionTokens::verify`] for the errors.
+    #[cfg(feature = "session-token")]
+    pub fn verify_token(&self, token: &str) -> Result<TokenClaims, Error> {
+        self.tokens
+            .verify(self.keyring(), token, self.manager.clock().now())
+    }
+
+    /// Verifies a response and reports the outcome, returning the details
+    /// of an accepted response
+    async fn check_response(
+        &self,
+        user_id: &str,
+        challenge_id: &ChallengeId,
+        channel: Option<&ChannelBinding>,
+        solution: Option<u64>,
+        otp: &str,
+    ) -> Result<(VerifyOutcome, Option<Accepted>), Error> {
+        let (result, locked_out) = self
+            .manager
+            .accept_reporting(challenge_id, &binding(user_id), channel, solution, otp)
+            .await;
+        let (result, accepted) = match result {
+            Ok(accepted) => (Ok(()), Some(accepted)),
+            Err(e) => (Err(e), None),
+        };
+        let outcome = outcome(result)?;
+        self.report(user_id, challenge_id, outcome, locked_out.as_ref());
+        Ok((outcome, accepted))
+    }
+
+    /// Passes the outcome of a check, and the lock it caused, to the event
+    /// sink
+    fn report(
+        &self,
+        user_id: &str,
+        challenge_id: &ChallengeId,
+        outcome: VerifyOutcome,
+        locked_out: Option<&LockoutEvent>,
+    ) {
+        let Some(events) = &self.events else {
+            return;
+        };
+        if outcome.is_accepted() {
+            events.verify_succeeded(user_id, challenge_id);
+            return;
+        }
+        events.verify_failed(user_id, challenge_id, outcome);
+        if let Some(event) = locked_out {
+            events.locked_out(event);
+        }
+    }
+}
+
+fn binding(user_id: &str) -> ChallengeBinding {
+    ChallengeBinding::new().with_user_id(user_id)
+}
+
+/// Maps security failures to outcomes, passing other errors through
+fn outcome(result: Result<(), Error>) -> Result<VerifyOutcome, Error> {
+    match result {
+        Ok(()) => Ok(VerifyOutcome::Accepted),
+        Err(Error::OtpMismatch) => Ok(VerifyOutcome::Rejected),
+        Err(Error::ChallengeExpired) => Ok(VerifyOutcome::Expired),
+        Err(Error::ChallengeNotFound) => Ok(VerifyOutcome::UnknownChallenge),
+        Err(Error::ReplayDetected) => Ok(VerifyOutcome::Replayed),
+        Err(Error::PuzzleUnsolved) => Ok(VerifyOutcome::PuzzleUnsolved),
+        Err(Error::RateLimited { retry_after }) => Ok(VerifyOutcome::RateLimited { retry_after }),
+        Err(Error::LockedOut { retry_after }) => Ok(VerifyOutcome::LockedOut { retry_after }),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Algorithm, Passcode};
+    use pollster::block_on;
+
+    fn key(user: u8) -> Passcode {
+        Passcode::new(Algorithm::Sha3Kmac256, vec![user; 32])
+    }
+
+    fn verifier() -> Verifier {
+        let keyring = KeyRing::new();
+        keyring.insert("alice", key(1));
+        keyring.insert("bob", key(2));
+        Verifier::new(keyring)
+    }
+
+    #[test]
+    fn test_channel_binding() {
+        let verifier = verifier();
+        let server_end = ChannelBinding::tls_exporter([1u8; 32]);
+
+        // Relayed through a proxy, the client sees the proxy's channel
+        let challenge = block_on(verifier.issue_challenge("alice")).unwrap();
+        let proxied = ChannelBinding::tls_exporter([2u8; 32]);
+        let otp = key(1).compute(&proxied.bind(&challenge.message()));
+        assert_eq!(
+            block_on(verifier.check_channel("alice", challenge.id(), &server_end, &otp)),
+            Ok(VerifyOutcome::Rejected)
+        );
+
+        let challenge = block_on(verifier.issue_challenge("alice")).unwrap();
+        let otp = key(1).compute(&server_end.bind(&challenge.message()));
+        assert!(block_on(verifier.check_channel(
+            "alice",
+            challenge.id(),
+            &ChannelBinding::tls_exporter(Vec::new()),
+            &otp
+        ))
+        .is_err());
+        assert_eq!(
+            block_on(verifier.check_channel("alice", challenge.id(), &server_end, &otp)),
+            Ok(VerifyOutcome::Accepted)
+        );
+    }
+
+    #[test]
+    fn test_accepts_once() {
+        let verifier = verifier();
+        let challenge = block_on(verifier.issue_challenge("alice")).unwrap();
+        let otp = key(1).compute(&challenge.message());
+
+        assert_eq!(
+            block_on(verifier.check("alice", challenge.id(), &otp)),
+            Ok(VerifyOutcome::Accepted)
+        );
+        assert_eq!(
+            block_on(verifier.check("alice", challenge.id(), &otp)),
+            Ok(VerifyOutcome::UnknownChallenge)
+        );
+    }
+
+    #[test]
+    fn test_rejects_other_users_and_unknown_users() {
+        let verifier = verifier();
+
+        let challenge = block_on(verifier.issue_challenge("alice")).unwrap();
+        let otp = key(1).compute(&challenge.message());
+        assert_eq!(
+            block_on(verifier.check("bob", challenge.id(), &otp)),
+            Ok(VerifyOutcome::Rejected)
+        );
+
+        let challenge = block_on(verifier.issue_challenge("mallory")).unwrap();
+        let otp = key(3).compute(&challenge.message());
+        assert_eq!(
+            block_on(verifier.check("mallory", challenge.id(), &otp)),
+            Ok(VerifyOutcome::Rejected)
+        );
+    }
+
+    #[test]
+    fn test_policy_violation_is_an_error() {
+        let verifier = verifier().with_challenge_policy(
+            ChallengePolicy::new().with_max_age(Duration::from_secs(60)),
+        );
+
+        // Issued under a laxer lifetime, e.g. by another instance sharing
+        // the store
+        let lax = ChallengeManager::new(key(1)).with_ttl(Duration::from_secs(3600));
+        let challenge = block_on(lax.issue_bound(binding("alice"))).unwrap();
+        block_on(verifier.manager().store().put(challenge.clone())).unwrap();
+
+        let otp = key(1).compute(&challenge.message());
+        assert!(matches!(
+            block_on(verifier.check("alice", challenge.id(), &otp)),
+            Err(Error::ChallengeLifetimeTooLong { .. })
+        ));
+    }
+
+    #[test]
+    fn test_locks_out_after_failures() {
+        let verifier = verifier().with_lockout(Lockout::new(LockoutPolicy {
+            max_failures: 2,
+            ..LockoutPolicy::default()
+        }));
+
+        for _ in 0..2 {
+            let challenge = block_on(verifier.issue_challenge("alice")).unwrap();
+            let outcome = block_on(verifier.check("alice", challenge.id(), "000000000000"));
+            assert_eq!(outcome, Ok(VerifyOutcome::Rejected));
+        }
+
+        let challenge = block_on(verifier.issue_challenge("alice")).unwrap();
+        let otp = key(1).compute(&challenge.message());
+        let outcome = block_on(verifier.check("alice", challenge.id(), &otp)).unwrap();
+        assert!(matches!(outcome, VerifyOutcome::LockedOut { .. }));
+        assert!(!outcome.is_accepted());
+
+        verifier.lockout().reset("alice");
+        assert_eq!(
+            block_on(verifier.check("alice", challenge.id(), &otp)),
+            Ok(VerifyOutcome::Accepted)
+        );
+    }
+
+    #[test]
+    fn test_rate_limited() {
+        let verifier = verifier().with_rate_limiter(RateLimiter::new(1, Duration::from_secs(60)));
+
+        let challenge = block_on(verifier.issue_challenge("bob")).unwrap();
+        block_on(verifier.check("bob", challenge.id(), "000000000000")).unwrap();
+        let challenge = block_on(verifier.issue_challenge("bob")).unwrap();
+        let otp = key(2).compute(&challenge.message());
+
+        assert!(matches!(
+            block_on(verifier.check("bob", challenge.id(), &otp)),
+            Ok(VerifyOutcome::RateLimited { .. })
+        ));
+    }
+
+    #[test]
+    fn test_puzzle() {
+        let verifier = verifier().with_puzzle(8);
+        let challenge = block_on(verifier.issue_challenge("alice")).unwrap();
+        assert_eq!(challenge.difficulty(), 8);
+        let otp = key(1).compute(&challenge.message());
+
+        assert_eq!(
+            block_on(verifier.check("alice", challenge.id(), &otp)),
+            Ok(VerifyOutcome::PuzzleUnsolved)
+        );
+
+        let challenge = block_on(verifier.issue_challenge("alice")).unwrap();
+        let otp = key(1).compute(&challenge.message());
+        let solution = challenge.solve_puzzle();
+        assert_eq!(
+            block_on(verifier.check_solved("alice", challenge.id(), solution, &otp)),
+            Ok(VerifyOutcome::Accepted)
+        );
+    }
+
+    #[test]
+    fn test_events() {
+        use crate::clock::ManualClock;
+        use std::sync::Mutex;
+        use std::time::UNIX_EPOCH;
+
+        #[derive(Default)]
+        struct Recorder(Mutex<Vec<String>>);
+
+        impl AuthEvents for Recorder {
+            fn challenge_issued(&self, user_id: &str, _challenge: &Challenge) {
+                self.0.lock().unwrap().push(format!("issued {user_id}"));
+            }
+
+            fn verify_succeeded(&self, user_id: &str, _challenge_id: &ChallengeId) {
+                self.0.lock().unwrap().push(format!("succeeded {user_id}"));
+            }
+
+            fn verify_failed(
+                &self,
+                user_id: &str,
+                _challenge_id: &ChallengeId,
+                reason: VerifyOutcome,
+            ) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("failed {user_id} {reason:?}"));
+            }
+
+            fn locked_out(&self, event: &LockoutEvent) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("locked {} {:?}", event.identity, event.duration));
+            }
+        }
+
+        let recorder = Arc::new(Recorder::default());
+        let verifier = verifier()
+            .with_lockout(Lockout::new(LockoutPolicy {
+                max_failures: 1,
+                ..LockoutPolicy::default()
+            }))
+            .with_clock(ManualClock::new(UNIX_EPOCH))
+            .with_events(Arc::clone(&recorder));
+
+        let challenge = block_on(verifier.issue_challenge("alice")).unwrap();
+        let otp = key(1).compute(&challenge.message());
+        block_on(verifier.check("alice", challenge.id(), &otp)).unwrap();
+        block_on(verifier.check("alice", challenge.id(), &otp)).unwrap();
+        let challenge = block_on(verifier.issue_challenge("bob")).unwrap();
+        block_on(verifier.check("bob", challenge.id(), "000000000000")).unwrap();
+        block_on(verifier.check("bob", challenge.id(), "000000000000")).unwrap();
+
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            [
+                "issued alice",
+                "succeeded alice",
+                "failed alice UnknownChallenge",
+                "issued bob",
+                "failed bob Rejected",
+                "locked bob 60s",
+                "failed bob LockedOut { retry_after: 60s }",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_time_steps_report_skew() {
+        use crate::challenge::time_step;
+        use crate::clock::ManualClock;
+        use std::sync::Arc;
+        use std::time::UNIX_EPOCH;
+
+        let step = Duration::from_secs(30);
+        let clock = Arc::new(ManualClock::new(UNIX_EPOCH + Duration::from_secs(3_000)));
+        let verifier = verifier()
+            .with_time_steps(step, 1)
+            .with_clock(Arc::clone(&clock));
+        let server_step = time_step(clock.now(), step);
+
+        let challenge = block_on(verifier.issue_challenge("alice")).unwrap();
+        let otp = key(1).compute(&challenge.message_at(server_step + 1));
+        assert_eq!(
+            block_on(verifier.check_timed("alice", challenge.id(), &otp)),
+            Ok((VerifyOutcome::Accepted, Some(1)))
+        );
+
+        let challenge = block_on(verifier.issue_challenge("alice")).unwrap();
+        let otp = key(1).compute(&challenge.message_at(server_step - 2));
+        assert_eq!(
+            block_on(verifier.check_timed("alice", challenge.id(), &otp)),
+            Ok((VerifyOutcome::Rejected, None))
+        );
+
+        // Untimed responses no longer verify
+        let challenge = block_on(verifier.issue_challenge("alice")).unwrap();
+        let otp = key(1).compute(&challenge.message());
+        assert_eq!(
+            block_on(verifier.check("alice", challenge.id(), &otp)),
+            Ok(VerifyOutcome::Rejected)
+        );
+    }
+
+    #[test]
+    fn test_device_reported() {
+        let verifier = verifier();
+        verifier.keyring().register_device("alice", "phone");
+        verifier.keyring().register_device("alice", "tablet");
+
+        let challenge = block_on(verifier.issue_challenge("alice")).unwrap();
+        let otp = key(1).for_device("tablet").compute(&challenge.message());
+        assert_eq!(
+            block_on(verifier.check_device("alice", challenge.id(), &otp)),
+            Ok((VerifyOutcome::Accepted, Some("tablet".to_string())))
+        );
+
+        verifier.keyring().revoke_device("alice", "tablet");
+        let challenge = block_on(verifier.issue_challenge("alice")).unwrap();
+        let otp = key(1).for_device("tablet").compute(&challenge.message());
+        assert_eq!(
+            block_on(verifier.check_device("alice", challenge.id(), &otp)),
+            Ok((VerifyOutcome::Rejected, None))
+        );
+
+        let challenge = block_on(verifier.issue_challenge("bob")).unwrap();
+        let otp = key(2).compute(&challenge.message());
+        assert_eq!(
+            block_on(verifier.check_device("bob", challenge.id(), &otp)),
+            Ok((VerifyOutcome::Accepted, None))
+        );
+    }
+
+    #[cfg(feature = "session-token")]
+    #[test]
+    fn test_session_tokens() {
+        use crate::clock::ManualClock;
+        use std::time::UNIX_EPOCH;
+
+        let clock = Arc::new(ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_000)));
+        let verifier = verifier()
+            .with_session_tokens(SessionTokens::new().with_ttl(Duration::from_secs(60)))
+            .with_clock(Arc::clone(&clock));
+        verifier.keyring().register_device("alice", "phone");
+
+        let challenge = block_on(verifier.issue_challenge("alice")).unwrap();
+        let otp = key(1).for_device("phone").compute(&challenge.message());
+        let (outcome, token) =
+            block_on(verifier.check_session("alice", challenge.id(), &otp)).unwrap();
+        assert_eq!(outcome, VerifyOutcome::Accepted);
+
+        let claims = verifier.verify_token(&token.unwrap()).unwrap();
+        assert_eq!(claims.sub, "alice");
+        assert_eq!(claims.device.as_deref(), Some("phone"));
+        assert_eq!(claims.auth_time, 1_000);
+
+        let challenge = block_on(verifier.issue_challenge("alice")).unwrap();
+        assert_eq!(
+            block_on(verifier.check_session("alice", challenge.id(), "00")),
+            Ok((VerifyOutcome::Rejected, None))
+        );
+    }
+
+    #[test]
+    fn test_clock_drives_expiry_and_lockout() {
+        use crate::clock::ManualClock;
+        use std::sync::Arc;
+        use std::time::UNIX_EPOCH;
+
+        let clock = Arc::new(ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_000)));
+        let verifier = verifier()
+            .with_lockout(Lockout::new(LockoutPolicy {
+                max_failures: 1,
+                ..LockoutPolicy::default()
+            }))
+            .with_clock(Arc::clone(&clock));
+
+        let challenge = block_on(verifier.issue_challenge("alice")).unwrap();
+        assert_eq!(
+            challenge.expires_at(),
+            UNIX_EPOCH + Duration::from_secs(1_000) + crate::challenge::DEFAULT_TTL
+        );
+        clock.advance(crate::challenge::DEFAULT_TTL);
+        let otp = key(1).compute(&challenge.message());
+        assert_eq!(
+            block_on(verifier.check("alice", challenge.id(), &otp)),
+            Ok(VerifyOutcome::Expired)
+        );
+
+        let challenge = block_on(verifier.issue_challenge("alice")).unwrap();
+        block_on(verifier.check("alice", challenge.id(), "000000000000")).unwrap();
+        let challenge = block_on(verifier.issue_challenge("alice")).unwrap();
+        let otp = key(1).compute(&challenge.message());
+        assert_eq!(
+            block_on(verifier.check("alice", challenge.id(), &otp)),
+            Ok(VerifyOutcome::LockedOut {
+                retry_after: Duration::from_secs(60)
+            })
+        );
+
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(
+            block_on(verifier.check("alice", challenge.id(), &otp)),
+            Ok(VerifyOutcome::Accepted)
+        );
+    }
+
+    #[test]
+    fn test_concurrent_failures_report_one_lock() {
+        use std::future::Future;
+        use std::pin::pin;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::task::{Context, Poll, Waker};
+        use std::time::SystemTime;
+
+        /// Store that suspends every lookup once, so that checks interleave
+        struct Suspending(MemoryStore);
+
+        impl ChallengeStore for Suspending {
+            async fn put(&self, challenge: Challenge) -> Result<(), Error> {
+                self.0.put(challenge).await
+            }
+
+            async fn get_and_delete(&self, id: &ChallengeId) -> Result<Option<Challenge>, Error> {
+                let mut suspended = false;
+                std::future::poll_fn(|cx| {
+                    if suspended {
+                        return Poll::Ready(());
+                    }
+                    suspended = true;
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                })
+                .await;
+                self.0.get_and_delete(id).await
+            }
+
+            async fn expire(&self, now: SystemTime) -> Result<usize, Error> {
+                self.0.expire(now).await
+            }
+        }
+
+        #[derive(Default)]
+        struct Locks(AtomicUsize);
+
+        impl AuthEvents for Locks {
+            fn locked_out(&self, _event: &LockoutEvent) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let keyring = KeyRing::new();
+        keyring.insert("bob", key(2));
+        let locks = Arc::new(Locks::default());
+        let verifier = Verifier::with_store(keyring, Suspending(MemoryStore::new()))
+            .with_lockout(Lockout::new(LockoutPolicy {
+                max_failures: 1,
+                ..LockoutPolicy::default()
+            }))
+            .with_events(Arc::clone(&locks));
+        let first = block_on(verifier.issue_challenge("bob")).unwrap();
+        let second = block_on(verifier.issue_challenge("bob")).unwrap();
+
+        // Both checks pass the lockout check before either one fails
+        let mut checks = [
+            pin!(verifier.check("bob", first.id(), "000000000000")),
+            pin!(verifier.check("bob", second.id(), "000000000000")),
+        ];
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut outcomes = [None, None];
+        while outcomes.iter().any(Option::is_none) {
+            for (check, outcome) in checks.iter_mut().zip(&mut outcomes) {
+                if outcome.is_none() {
+                    if let Poll::Ready(result) = check.as_mut().poll(&mut cx) {
+                        *outcome = Some(result.unwrap());
+                    }
+                }
+            }
+        }
+
+        assert_eq!(outcomes, [Some(VerifyOutcome::Rejected); 2]);
+        assert_eq!(locks.0.load(Ordering::SeqCst), 1);
+    }
+}
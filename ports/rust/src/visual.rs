@@ -0,0 +1,227 @@
+//! Visual fingerprints for comparing codes between two screens
+//!
+//! In pairing flows both devices display the same code and the user confirms
+//! that they match. Comparing a short row of emoji is faster and less error
+//! prone than reading characters, so [`VisualFingerprint`] derives one from
+//! the canonical textual code, which should still be shown alongside it.
+//!
+//! # Example
+//! ```
+//! use passcode::visual::VisualFingerprint;
+//!
+//! let fingerprint = VisualFingerprint::new("3f9a2c81d0e4", 5).unwrap();
+//! println!("{}", fingerprint); // e.g. "🐙 🔑 🌵 🚲 🎩"
+//! assert_eq!(fingerprint.symbols().len(), 5);
+//! ```
+
+use crate::Error;
+
+/// Domain-separation context for the fingerprint hash
+const VISUAL_CONTEXT: &str = "passcode/v1/visual";
+
+/// Maximum number of symbols in a fingerprint
+pub const MAX_SYMBOLS: usize = 16;
+
+/// Emoji and their names, indexed by 6-bit value
+const EMOJI: [(&str, &str); 64] = [
+    ("🐶", "dog"),
+    ("🐱", "cat"),
+    ("🦁", "lion"),
+    ("🐎", "horse"),
+    ("🦄", "unicorn"),
+    ("🐷", "pig"),
+    ("🐘", "elephant"),
+    ("🐰", "rabbit"),
+    ("🐼", "panda"),
+    ("🐓", "rooster"),
+    ("🐧", "penguin"),
+    ("🐢", "turtle"),
+    ("🐟", "fish"),
+    ("🐙", "octopus"),
+    ("🦋", "butterfly"),
+    ("🌷", "flower"),
+    ("🌳", "tree"),
+    ("🌵", "cactus"),
+    ("🍄", "mushroom"),
+    ("🌏", "globe"),
+    ("🌙", "moon"),
+    ("☁️", "cloud"),
+    ("🔥", "fire"),
+    ("🍌", "banana"),
+    ("🍎", "apple"),
+    ("🍓", "strawberry"),
+    ("🌽", "corn"),
+    ("🍕", "pizza"),
+    ("🎂", "cake"),
+    ("❤️", "heart"),
+    ("😀", "smiley"),
+    ("🤖", "robot"),
+    ("🎩", "hat"),
+    ("👓", "glasses"),
+    ("🔧", "spanner"),
+    ("🎅", "santa"),
+    ("👍", "thumbs up"),
+    ("☂️", "umbrella"),
+    ("⌛", "hourglass"),
+    ("⏰", "clock"),
+    ("🎁", "gift"),
+    ("💡", "light bulb"),
+    ("📕", "book"),
+    ("✏️", "pencil"),
+    ("📎", "paperclip"),
+    ("✂️", "scissors"),
+    ("🔒", "lock"),
+    ("🔑", "key"),
+    ("🔨", "hammer"),
+    ("☎️", "telephone"),
+    ("🏁", "flag"),
+    ("🚂", "train"),
+    ("🚲", "bicycle"),
+    ("✈️", "aeroplane"),
+    ("🚀", "rocket"),
+    ("🏆", "trophy"),
+    ("⚽", "ball"),
+    ("🎸", "guitar"),
+    ("🎺", "trumpet"),
+    ("🔔", "bell"),
+    ("⚓", "anchor"),
+    ("🎧", "headphones"),
+    ("📁", "folder"),
+    ("📌", "pin"),
+];
+
+/// Background colors and their names, indexed by 3-bit value
+const COLORS: [(&str, [u8; 3]); 8] = [
+    ("red", [0xe6, 0x19, 0x4b]),
+    ("green", [0x3c, 0xb4, 0x4b]),
+    ("yellow", [0xff, 0xe1, 0x19]),
+    ("blue", [0x43, 0x63, 0xd8]),
+    ("orange", [0xf5, 0x82, 0x31]),
+    ("purple", [0x91, 0x1e, 0xb4]),
+    ("cyan", [0x42, 0xd4, 0xf4]),
+    ("pink", [0xf0, 0x32, 0xe6]),
+];
+
+/// One symbol of a visual fingerprint: an emoji on a colored background
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Symbol {
+    /// The emoji character sequence
+    pub emoji: &'static str,
+    /// English name of the emoji, for screen readers and verbal comparison
+    pub name: &'static str,
+    /// Name of the background color
+    pub color_name: &'static str,
+    /// Background color as RGB
+    pub color: [u8; 3],
+}
+
+/// A short emoji/color sequence derived from an OTP
+///
+/// Each symbol carries 9 bits (6 for the emoji, 3 for the color) of a
+/// BLAKE3 hash of the code, so a five-symbol fingerprint distinguishes
+/// 2^45 codes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VisualFingerprint {
+    symbols: Vec<Symbol>,
+}
+
+impl VisualFingerprint {
+    /// Derives a fingerprint of `len` symbols (1 to [`MAX_SYMBOLS`]) from a code
+    pub fn new(code: &str, len: usize) -> Result<Self, Error> {
+        if len == 0 || len > MAX_SYMBOLS {
+            return Err(Error::InvalidFormat(
+                "fingerprint length must be between 1 and 16 symbols",
+            ));
+        }
+
+        let mut hasher = blake3::Hasher::new_derive_key(VISUAL_CONTEXT);
+        hasher.update(code.as_bytes());
+        let digest = hasher.finalize();
+        let bits = digest.as_bytes();
+
+        let symbols = (0..len)
+            .map(|i| {
+                let index = read_bits(bits, i * 9, 9);
+                let (emoji, name) = EMOJI[index >> 3];
+                let (color_name, color) = COLORS[index & 0x7];
+                Symbol {
+                    emoji,
+                    name,
+                    color_name,
+                    color,
+                }
+            })
+            .collect();
+
+        Ok(Self { symbols })
+    }
+
+    /// Gets the symbols in display order
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    /// Gets the emoji names, e.g. for reading the fingerprint aloud
+    pub fn names(&self) -> Vec<&'static str> {
+        self.symbols.iter().map(|symbol| symbol.name).collect()
+    }
+}
+
+impl std::fmt::Display for VisualFingerprint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let emoji: Vec<&str> = self.symbols.iter().map(|symbol| symbol.emoji).collect();
+        write!(f, "{}", emoji.join(" "))
+    }
+}
+
+/// Reads `count` bits starting at bit `offset` (MSB first)
+fn read_bits(bytes: &[u8], offset: usize, count: usize) -> usize {
+    (offset..offset + count).fold(0, |acc, bit| {
+        let set = (bytes[bit / 8] >> (7 - bit % 8)) & 1;
+        (acc << 1) | usize::from(set)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint_is_deterministic() {
+        let a = VisualFingerprint::new("3f9a2c81d0e4", 5).unwrap();
+        let b = VisualFingerprint::new("3f9a2c81d0e4", 5).unwrap();
+        let c = VisualFingerprint::new("3f9a2c81d0e5", 5).unwrap();
+
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_eq!(a.symbols().len(), 5);
+        assert_eq!(a.names().len(), 5);
+        assert_eq!(a.to_string().split(' ').count(), 5);
+
+        // A longer fingerprint extends the shorter one
+        let long = VisualFingerprint::new("3f9a2c81d0e4", MAX_SYMBOLS).unwrap();
+        assert_eq!(&long.symbols()[..5], a.symbols());
+    }
+
+    #[test]
+    fn test_fingerprint_length_range() {
+        assert!(VisualFingerprint::new("code", 0).is_err());
+        assert!(VisualFingerprint::new("code", MAX_SYMBOLS + 1).is_err());
+    }
+
+    #[test]
+    fn test_read_bits() {
+        let bytes = [0b1010_0000, 0b0111_1111];
+        assert_eq!(read_bits(&bytes, 0, 3), 0b101);
+        assert_eq!(read_bits(&bytes, 7, 3), 0b001);
+        assert_eq!(read_bits(&bytes, 9, 7), 0b111_1111);
+    }
+
+    #[test]
+    fn test_emoji_are_distinct() {
+        let mut emoji: Vec<&str> = EMOJI.iter().map(|(e, _)| *e).collect();
+        emoji.sort_unstable();
+        emoji.dedup();
+        assert_eq!(emoji.len(), EMOJI.len());
+    }
+}
@@ -0,0 +1,346 @@
+//! WebSocket handshake driver
+//!
+//! Runs the challenge-response protocol as the first messages of a
+//! WebSocket connection, before any application traffic. The driver does
+//! no I/O and reads no clock, so the same code serves tungstenite on a
+//! server and the WASM port in a browser:
+//!
+//! 1. The client offers the [`SUBPROTOCOL`] in `Sec-WebSocket-Protocol`;
+//!    the server selects it when [`offers_subprotocol`] accepts the header.
+//! 2. Once the connection is open, the server sends the frame returned by
+//!    [`ServerHandshake::start`] and both sides arm a timer for
+//!    [`timeout`](ServerHandshake::timeout).
+//! 3. Each side passes every binary message it receives to `on_message`
+//!    and sends back the frame it returns, until `is_complete` is true.
+//!
+//! Every handshake frame is a binary message holding one
+//! [`Message`](crate::session::Message) in its byte encoding; text
+//! messages during the handshake are a protocol error. When the timer
+//! fires, call `on_timeout`. On any error, close the connection with
+//! [`close_code`] for the error.
+//!
+//! # Example
+//! ```
+//! use passcode::websocket::{ClientHandshake, ServerHandshake, SUBPROTOCOL};
+//! use passcode::{Algorithm, Passcode};
+//!
+//! let key = Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+//! let mut server = ServerHandshake::new(&key).unwrap().with_server_proof();
+//! let mut client = ClientHandshake::new(&key).with_server_proof();
+//! assert!(passcode::websocket::offers_subprotocol(&format!("chat, {}", SUBPROTOCOL)));
+//!
+//! let challenge = server.start();
+//! let response = client.on_message(&challenge).unwrap().unwrap();
+//! let proof = server.on_message(&response).unwrap().unwrap();
+//! assert_eq!(client.on_message(&proof).unwrap(), None);
+//!
+//! assert!(server.is_complete() && client.is_complete());
+//! assert_eq!(server.session_key(), client.session_key());
+//! ```
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::session::{ClientSession, Message, ServerSession};
+use crate::{Error, Passcode};
+
+/// WebSocket subprotocol name for `Sec-WebSocket-Protocol`
+pub const SUBPROTOCOL: &str = "passcode.v1";
+
+/// Time either side allows for the handshake unless configured otherwise
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Close code for a malformed or out-of-order handshake message
+pub const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+
+/// Close code for a rejected response or server proof
+pub const CLOSE_AUTHENTICATION_FAILED: u16 = 4401;
+
+/// Close code for a handshake that did not finish in time
+pub const CLOSE_TIMEOUT: u16 = 4408;
+
+/// Returns true if a `Sec-WebSocket-Protocol` header lists the
+/// [`SUBPROTOCOL`]
+pub fn offers_subprotocol(header: &str) -> bool {
+    header
+        .split(',')
+        .any(|protocol| protocol.trim() == SUBPROTOCOL)
+}
+
+/// Returns the close code to end the connection with after `error`
+pub fn close_code(error: &Error) -> u16 {
+    match error {
+        Error::MalformedMessage(_)
+        | Error::UnexpectedMessage { .. }
+        | Error::UnsupportedVersion { .. } => CLOSE_PROTOCOL_ERROR,
+        Error::HandshakeTimeout => CLOSE_TIMEOUT,
+        _ => CLOSE_AUTHENTICATION_FAILED,
+    }
+}
+
+/// Server side of the handshake
+pub struct ServerHandshake<'a> {
+    session: ServerSession<'a>,
+    timeout: Duration,
+    state: State,
+}
+
+/// Progress shared by both sides
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Pending,
+    Complete,
+    Failed,
+}
+
+impl<'a> ServerHandshake<'a> {
+    /// Creates the server side with a random challenge
+    pub fn new(passcode: &'a Passcode) -> Result<Self, Error> {
+        Ok(Self::from_session(ServerSession::new(passcode)?))
+    }
+
+    /// Creates the server side around a configured session, e.g. one with
+    /// several rounds or a challenge issued by a manager
+    pub fn from_session(session: ServerSession<'a>) -> Self {
+        Self {
+            session,
+            timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            state: State::Pending,
+        }
+    }
+
+    /// Answers an accepted response with a server proof
+    pub fn with_server_proof(mut self) -> Self {
+        self.session = self.session.with_server_proof();
+        self
+    }
+
+    /// Sets the time allowed for the handshake
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Gets the time allowed for the handshake, for the caller's timer
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Returns the first frame, to send as soon as the connection opens
+    pub fn start(&self) -> Vec<u8> {
+        self.session.challenge().to_bytes()
+    }
+
+    /// Handles a binary message from the client, returning the frame to
+    /// send back
+    ///
+    /// Errors are those of [`ServerSession::handle`] plus
+    /// [`Error::MalformedMessage`] for an undecodable frame or once the
+    /// handshake has finished. Any error fails the handshake.
+    pub fn on_message(&mut self, frame: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        let reply = check(self.state)
+            .and_then(|()| Message::from_bytes(frame))
+            .and_then(|message| self.session.handle(message));
+        self.state = match &reply {
+            Ok(_) if self.session.is_verified() => State::Complete,
+            Ok(_) => State::Pending,
+            Err(_) => State::Failed,
+        };
+        Ok(reply?.map(|message| message.to_bytes()))
+    }
+
+    /// Handles the caller's timer firing, failing the handshake with
+    /// [`Error::HandshakeTimeout`] unless it has completed
+    pub fn on_timeout(&mut self) -> Result<(), Error> {
+        on_timeout(&mut self.state)
+    }
+
+    /// Returns true once the client has authenticated and application
+    /// traffic may start
+    pub fn is_complete(&self) -> bool {
+        self.state == State::Complete
+    }
+
+    /// Gets the session key, available once the handshake is complete
+    pub fn session_key(&self) -> Option<&[u8]> {
+        self.session.session_key()
+    }
+}
+
+impl fmt::Debug for ServerHandshake<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServerHandshake")
+            .field("state", &self.state)
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Client side of the handshake
+pub struct ClientHandshake<'a> {
+    session: ClientSession<'a>,
+    timeout: Duration,
+    state: State,
+}
+
+impl<'a> ClientHandshake<'a> {
+    /// Creates the client side
+    pub fn new(passcode: &'a Passcode) -> Self {
+        Self::from_session(ClientSession::new(passcode))
+    }
+
+    /// Creates the client side around a configured session, e.g. one
+    /// answering several rounds
+    pub fn from_session(session: ClientSession<'a>) -> Self {
+        Self {
+            session,
+            timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            state: State::Pending,
+        }
+    }
+
+    /// Requires a server proof before the handshake completes
+    pub fn with_server_proof(mut self) -> Self {
+        self.session = self.session.with_server_proof();
+        self
+    }
+
+    /// Sets the time allowed for the handshake
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Gets the time allowed for the handshake, for the caller's timer
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Handles a binary message from the server, returning the frame to
+    /// send back
+    ///
+    /// Errors are those of [`ClientSession::handle`] plus
+    /// [`Error::MalformedMessage`] for an undecodable frame or once the
+    /// handshake has finished. Any error fails the handshake.
+    pub fn on_message(&mut self, frame: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        let reply = check(self.state)
+            .and_then(|()| Message::from_bytes(frame))
+            .and_then(|message| self.session.handle(message));
+        self.state = match &reply {
+            Ok(_) if self.session.is_complete() => State::Complete,
+            Ok(_) => State::Pending,
+            Err(_) => State::Failed,
+        };
+        Ok(reply?.map(|message| message.to_bytes()))
+    }
+
+    /// Handles the caller's timer firing, failing the handshake with
+    /// [`Error::HandshakeTimeout`] unless it has completed
+    pub fn on_timeout(&mut self) -> Result<(), Error> {
+        on_timeout(&mut self.state)
+    }
+
+    /// Returns true once the handshake is complete and application traffic
+    /// may start
+    ///
+    /// Without a server proof this only means the response was sent; the
+    /// server closes the connection if it rejected it.
+    pub fn is_complete(&self) -> bool {
+        self.state == State::Complete
+    }
+
+    /// Gets the session key, available once the handshake is complete
+    pub fn session_key(&self) -> Option<&[u8]> {
+        self.session.session_key()
+    }
+}
+
+impl fmt::Debug for ClientHandshake<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientHandshake")
+            .field("state", &self.state)
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Rejects messages once the handshake has finished
+fn check(state: State) -> Result<(), Error> {
+    match state {
+        State::Pending => Ok(()),
+        State::Complete | State::Failed => Err(Error::MalformedMessage(
+            "WebSocket handshake is already finished",
+        )),
+    }
+}
+
+fn on_timeout(state: &mut State) -> Result<(), Error> {
+    if *state == State::Complete {
+        return Ok(());
+    }
+    *state = State::Failed;
+    Err(Error::HandshakeTimeout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Algorithm;
+
+    fn key(byte: u8) -> Passcode {
+        Passcode::new(Algorithm::Sha3Kmac256, vec![byte; 32])
+    }
+
+    #[test]
+    fn test_rounds_complete() {
+        let key = key(1);
+        let mut server =
+            ServerHandshake::from_session(ServerSession::new(&key).unwrap().with_rounds(2));
+        let mut client = ClientHandshake::from_session(ClientSession::new(&key).with_rounds(2));
+
+        let mut frame = server.start();
+        while let Some(response) = client.on_message(&frame).unwrap() {
+            match server.on_message(&response).unwrap() {
+                Some(next) => frame = next,
+                None => break,
+            }
+        }
+        assert!(server.is_complete() && client.is_complete());
+        assert_eq!(server.session_key(), client.session_key());
+        assert_eq!(server.on_timeout(), Ok(()));
+        assert!(server.on_message(&frame).is_err());
+    }
+
+    #[test]
+    fn test_failures_map_to_close_codes() {
+        let (key, wrong) = (key(1), key(2));
+        let mut server = ServerHandshake::new(&key).unwrap();
+        let mut client = ClientHandshake::new(&wrong);
+        let response = client.on_message(&server.start()).unwrap().unwrap();
+        let error = server.on_message(&response).unwrap_err();
+        assert_eq!(close_code(&error), CLOSE_AUTHENTICATION_FAILED);
+        assert!(!server.is_complete());
+
+        // The client must not answer its own kind of message
+        let mut client = ClientHandshake::new(&key);
+        let error = client.on_message(&response).unwrap_err();
+        assert_eq!(close_code(&error), CLOSE_PROTOCOL_ERROR);
+        let error = ClientHandshake::new(&key).on_message(b"").unwrap_err();
+        assert_eq!(close_code(&error), CLOSE_PROTOCOL_ERROR);
+
+        let mut server = ServerHandshake::new(&key)
+            .unwrap()
+            .with_timeout(Duration::from_secs(5));
+        assert_eq!(server.timeout(), Duration::from_secs(5));
+        let error = server.on_timeout().unwrap_err();
+        assert_eq!(close_code(&error), CLOSE_TIMEOUT);
+        assert!(server.on_message(&response).is_err());
+    }
+
+    #[test]
+    fn test_offers_subprotocol() {
+        assert!(offers_subprotocol("passcode.v1"));
+        assert!(offers_subprotocol("graphql-ws ,passcode.v1"));
+        assert!(!offers_subprotocol("passcode.v2, passcode"));
+    }
+}
@@ -0,0 +1,586 @@
+//! Versioned binary encoding of protocol messages
+//!
+//! [`ChallengeMessage`] and [`ResponseMessage`] carry a
+//! [`SignedChallenges`](crate::challenge::SignedChallenges) exchange in a
+//! compact, self-describing form, so every port exchanges the same bytes.
+//! Integers are big-endian and every message starts with the format
+//! version and its type:
+//!
+//! | Field | Challenge | Response |
+//! |---|---|---|
+//! | version | 1 byte, [`WIRE_VERSION`] | 1 byte, [`WIRE_VERSION`] |
+//! | type | 1 byte, `1` | 1 byte, `2` |
+//! | algorithm | 1 byte, [`Algorithm::id`] | 1 byte, [`Algorithm::id`] |
+//! | nonce | 1 byte length, then the nonce | 1 byte length, then the nonce of the answered challenge |
+//! | expiry | 8 bytes, milliseconds since the Unix epoch | — |
+//! | MAC | 1 byte length, then the server's MAC | 1 byte length, then [`Passcode::compute_mac`] over the challenge |
+//!
+//! Decoders reject trailing bytes and unknown algorithms with
+//! [`Error::MalformedMessage`], and versions outside the accepted
+//! [`WireVersions`] with [`Error::UnsupportedVersion`].
+//!
+//! # Migrating between versions
+//! A format change rolls out without a flag day. Verifiers first switch to
+//! [`WireVersions::transitional`], accepting the new version N and the
+//! previous version N-1 through
+//! [`ChallengeMessage::decode_with`] and [`ResponseMessage::decode_with`].
+//! Clients then move to N, either unconditionally or after
+//! [`WireVersions::negotiate`] with the range the server advertises, and
+//! [`encode_version`](ResponseMessage::encode_version) lets them keep
+//! writing N-1 to servers that are not upgraded yet. Once no client writes
+//! N-1 the verifiers return to [`WireVersions::current`].
+//!
+//! # Example
+//! ```
+//! use passcode::challenge::SignedChallenges;
+//! use passcode::wire::{ChallengeMessage, ResponseMessage};
+//! use passcode::{Algorithm, Passcode};
+//!
+//! let passcode = || Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+//! let server = SignedChallenges::new(passcode(), b"server key");
+//! let challenge = server.issue(b"").unwrap();
+//! let sent = ChallengeMessage::from_challenge(&challenge).unwrap().encode();
+//!
+//! // Client
+//! let received = ChallengeMessage::decode(&sent).unwrap();
+//! let response = ResponseMessage::compute(&passcode(), &received).encode();
+//!
+//! // Server
+//! let response = ResponseMessage::decode(&response).unwrap();
+//! assert!(server.verify_response(&received, &response, b"").is_ok());
+//! ```
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::challenge::{Challenge, NONCE_LEN};
+use crate::{Algorithm, Error, Passcode};
+
+/// Version byte of the current encoding
+pub const WIRE_VERSION: u8 = 1;
+
+/// Oldest version this build still reads and writes
+pub const MIN_WIRE_VERSION: u8 = 1;
+
+pub(crate) const TYPE_CHALLENGE: u8 = 1;
+pub(crate) const TYPE_RESPONSE: u8 = 2;
+
+/// Range of wire versions a peer accepts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WireVersions {
+    min: u8,
+    max: u8,
+}
+
+impl WireVersions {
+    /// Accepts `min` to `max` inclusive
+    ///
+    /// Fails with [`Error::UnsupportedVersion`] if either bound is outside
+    /// [`MIN_WIRE_VERSION`] to [`WIRE_VERSION`], and with
+    /// [`Error::InvalidFormat`] if `min` is greater than `max`.
+    pub fn new(min: u8, max: u8) -> Result<Self, Error> {
+        if min > max {
+            return Err(Error::InvalidFormat("empty wire version range"));
+        }
+        let supported = Self::supported();
+        supported.check(min)?;
+        supported.check(max)?;
+        Ok(Self { min, max })
+    }
+
+    /// Accepts only [`WIRE_VERSION`]
+    pub fn current() -> Self {
+        Self {
+            min: WIRE_VERSION,
+            max: WIRE_VERSION,
+        }
+    }
+
+    /// Accepts [`WIRE_VERSION`] and the version before it, if this build
+    /// still supports it
+    pub fn transitional() -> Self {
+        Self {
+            min: WIRE_VERSION.saturating_sub(1).max(MIN_WIRE_VERSION),
+            max: WIRE_VERSION,
+        }
+    }
+
+    /// Accepts every version this build supports, [`MIN_WIRE_VERSION`] to
+    /// [`WIRE_VERSION`]
+    pub fn supported() -> Self {
+        Self {
+            min: MIN_WIRE_VERSION,
+            max: WIRE_VERSION,
+        }
+    }
+
+    /// Gets the oldest accepted version
+    pub fn min(&self) -> u8 {
+        self.min
+    }
+
+    /// Gets the newest accepted version
+    pub fn max(&self) -> u8 {
+        self.max
+    }
+
+    /// Returns true if `version` is in the range
+    pub fn accepts(&self, version: u8) -> bool {
+        (self.min..=self.max).contains(&version)
+    }
+
+    /// Fails with [`Error::UnsupportedVersion`] if `version` is outside the
+    /// range
+    pub fn check(&self, version: u8) -> Result<(), Error> {
+        if !self.accepts(version) {
+            return Err(Error::UnsupportedVersion {
+                version,
+                min: self.min,
+                max: self.max,
+            });
+        }
+        Ok(())
+    }
+
+    /// Picks the newest version both this range and the peer's accept
+    ///
+    /// Fails with [`Error::UnsupportedVersion`] for the peer's newest
+    /// version when the ranges do not overlap.
+    pub fn negotiate(&self, peer: &WireVersions) -> Result<u8, Error> {
+        let version = self.max.min(peer.max);
+        if version < self.min.max(peer.min) {
+            return Err(Error::UnsupportedVersion {
+                version: peer.max,
+                min: self.min,
+                max: self.max,
+            });
+        }
+        Ok(version)
+    }
+}
+
+impl Default for WireVersions {
+    fn default() -> Self {
+        Self::supported()
+    }
+}
+
+/// Reads the version of an encoded message without decoding it
+///
+/// Fails with [`Error::MalformedMessage`] for an empty message.
+pub fn peek_version(bytes: &[u8]) -> Result<u8, Error> {
+    bytes
+        .first()
+        .copied()
+        .ok_or(Error::MalformedMessage("truncated message"))
+}
+
+/// Challenge sent from the server to the client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChallengeMessage {
+    /// OTP algorithm the challenge was issued for
+    pub algorithm: Algorithm,
+    /// Random nonce, which also identifies the challenge
+    pub nonce: Vec<u8>,
+    /// Time after which the challenge is no longer accepted, with
+    /// millisecond precision
+    pub expires_at: SystemTime,
+    /// The server's MAC over the other fields
+    pub mac: Vec<u8>,
+}
+
+impl ChallengeMessage {
+    /// Splits a challenge issued by
+    /// [`SignedChallenges`](crate::challenge::SignedChallenges) into its
+    /// fields
+    ///
+    /// Fails with [`Error::InvalidChallenge`] for challenges from other
+    /// issuers.
+    pub fn from_challenge(challenge: &Challenge) -> Result<Self, Error> {
+        let algorithm = challenge
+            .token_algorithm()
+            .ok_or(Error::InvalidChallenge("not a signed challenge"))?;
+        let bytes = challenge.bytes();
+        let (nonce, rest) = bytes.split_at(NONCE_LEN);
+        // Skip the algorithm ID
+        let (expiry, mac) = rest[1..].split_at(8);
+
+        Ok(Self {
+            algorithm,
+            nonce: nonce.to_vec(),
+            expires_at: from_millis(expiry.try_into().expect("split at 8")),
+            mac: mac.to_vec(),
+        })
+    }
+
+    /// Rebuilds the challenge bytes the OTP is computed over and
+    /// [`SignedChallenges::verify`](crate::challenge::SignedChallenges::verify)
+    /// expects
+    pub fn challenge_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.nonce.len() + 9 + self.mac.len());
+        bytes.extend_from_slice(&self.nonce);
+        bytes.push(self.algorithm.id());
+        bytes.extend_from_slice(&to_millis(self.expires_at).to_be_bytes());
+        bytes.extend_from_slice(&self.mac);
+        bytes
+    }
+
+    /// Encodes the message as [`WIRE_VERSION`]
+    ///
+    /// # Panics
+    /// Panics if the nonce or MAC is longer than 255 bytes.
+    pub fn encode(&self) -> Vec<u8> {
+        self.encode_as(WIRE_VERSION)
+    }
+
+    /// Encodes the message as an older or newer supported version, for
+    /// peers that do not accept [`WIRE_VERSION`]
+    ///
+    /// Fails with [`Error::UnsupportedVersion`] outside
+    /// [`WireVersions::supported`].
+    ///
+    /// # Panics
+    /// Panics if the nonce or MAC is longer than 255 bytes.
+    pub fn encode_version(&self, version: u8) -> Result<Vec<u8>, Error> {
+        WireVersions::supported().check(version)?;
+        Ok(self.encode_as(version))
+    }
+
+    fn encode_as(&self, version: u8) -> Vec<u8> {
+        let mut encoded = header(version, TYPE_CHALLENGE, self.algorithm);
+        put_field(&mut encoded, &self.nonce);
+        encoded.extend_from_slice(&to_millis(self.expires_at).to_be_bytes());
+        put_field(&mut encoded, &self.mac);
+        encoded
+    }
+
+    /// Decodes a message of any version this build supports
+    pub fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        Self::decode_with(bytes, &WireVersions::supported())
+    }
+
+    /// Decodes a message, failing with [`Error::UnsupportedVersion`] if its
+    /// version is outside `versions`
+    pub fn decode_with(bytes: &[u8], versions: &WireVersions) -> Result<Self, Error> {
+        let mut reader = Reader::new(bytes, TYPE_CHALLENGE, versions)?;
+        let algorithm = reader.algorithm()?;
+        let nonce = reader.field()?.to_vec();
+        let expiry = reader.take(8)?;
+        let mac = reader.field()?.to_vec();
+        reader.finish()?;
+
+        Ok(Self {
+            algorithm,
+            nonce,
+            expires_at: from_millis(expiry.try_into().expect("took 8")),
+            mac,
+        })
+    }
+}
+
+/// Response sent from the client to the server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseMessage {
+    /// OTP algorithm the MAC was computed with
+    pub algorithm: Algorithm,
+    /// Nonce of the answered challenge
+    pub nonce: Vec<u8>,
+    /// Raw OTP bytes over the challenge, see [`Passcode::compute_mac`]
+    pub mac: Vec<u8>,
+}
+
+impl ResponseMessage {
+    /// Answers a challenge with the client's key
+    pub fn compute(passcode: &Passcode, challenge: &ChallengeMessage) -> Self {
+        Self {
+            algorithm: passcode.algorithm(),
+            nonce: challenge.nonce.clone(),
+            mac: passcode.compute_mac(&challenge.challenge_bytes()),
+        }
+    }
+
+    /// Answers a challenge and authenticates `telemetry` with the same MAC,
+    /// computed over the challenge bytes followed by the telemetry
+    ///
+    /// The server checks it with
+    /// [`SignedChallenges::verify_telemetry`](crate::challenge::SignedChallenges::verify_telemetry).
+    /// Without telemetry this is [`compute`](Self::compute).
+    pub fn compute_telemetry(
+        passcode: &Passcode,
+        challenge: &ChallengeMessage,
+        telemetry: &[u8],
+    ) -> Self {
+        let mut message = challenge.challenge_bytes();
+        message.extend_from_slice(telemetry);
+        Self {
+            algorithm: passcode.algorithm(),
+            nonce: challenge.nonce.clone(),
+            mac: passcode.compute_mac(&message),
+        }
+    }
+
+    /// Encodes the message as [`WIRE_VERSION`]
+    ///
+    /// # Panics
+    /// Panics if the nonce or MAC is longer than 255 bytes.
+    pub fn encode(&self) -> Vec<u8> {
+        self.encode_as(WIRE_VERSION)
+    }
+
+    /// Encodes the message as an older or newer supported version, for
+    /// servers that do not accept [`WIRE_VERSION`]
+    ///
+    /// Fails with [`Error::UnsupportedVersion`] outside
+    /// [`WireVersions::supported`].
+    ///
+    /// # Panics
+    /// Panics if the nonce or MAC is longer than 255 bytes.
+    pub fn encode_version(&self, version: u8) -> Result<Vec<u8>, Error> {
+        WireVersions::supported().check(version)?;
+        Ok(self.encode_as(version))
+    }
+
+    fn encode_as(&self, version: u8) -> Vec<u8> {
+        let mut encoded = header(version, TYPE_RESPONSE, self.algorithm);
+        put_field(&mut encoded, &self.nonce);
+        put_field(&mut encoded, &self.mac);
+        encoded
+    }
+
+    /// Decodes a message of any version this build supports
+    pub fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        Self::decode_with(bytes, &WireVersions::supported())
+    }
+
+    /// Decodes a message, failing with [`Error::UnsupportedVersion`] if its
+    /// version is outside `versions`
+    pub fn decode_with(bytes: &[u8], versions: &WireVersions) -> Result<Self, Error> {
+        let mut reader = Reader::new(bytes, TYPE_RESPONSE, versions)?;
+        let algorithm = reader.algorithm()?;
+        let nonce = reader.field()?.to_vec();
+        let mac = reader.field()?.to_vec();
+        reader.finish()?;
+
+        Ok(Self {
+            algorithm,
+            nonce,
+            mac,
+        })
+    }
+}
+
+fn header(version: u8, kind: u8, algorithm: Algorithm) -> Vec<u8> {
+    vec![version, kind, algorithm.id()]
+}
+
+/// Appends a field with its one-byte length
+fn put_field(encoded: &mut Vec<u8>, field: &[u8]) {
+    let len = u8::try_from(field.len()).expect("wire fields are at most 255 bytes");
+    encoded.push(len);
+    encoded.extend_from_slice(field);
+}
+
+/// Reads the fields of a message after checking its version and type
+struct Reader<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8], kind: u8, versions: &WireVersions) -> Result<Self, Error> {
+        let mut reader = Self { rest: bytes };
+        let header = reader.take(2)?;
+        versions.check(header[0])?;
+        if header[1] != kind {
+            return Err(Error::MalformedMessage("unexpected message type"));
+        }
+        Ok(reader)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.rest.len() < len {
+            return Err(Error::MalformedMessage("truncated message"));
+        }
+        let (taken, rest) = self.rest.split_at(len);
+        self.rest = rest;
+        Ok(taken)
+    }
+
+    fn algorithm(&mut self) -> Result<Algorithm, Error> {
+        Algorithm::from_id(self.take(1)?[0]).ok_or(Error::MalformedMessage("unknown algorithm"))
+    }
+
+    fn field(&mut self) -> Result<&'a [u8], Error> {
+        let len = self.take(1)?[0];
+        self.take(usize::from(len))
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        if !self.rest.is_empty() {
+            return Err(Error::MalformedMessage("trailing bytes"));
+        }
+        Ok(())
+    }
+}
+
+fn to_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+fn from_millis(bytes: [u8; 8]) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(u64::from_be_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn challenge() -> ChallengeMessage {
+        ChallengeMessage {
+            algorithm: Algorithm::Sha3Kmac256,
+            nonce: vec![0xaa; 16],
+            expires_at: UNIX_EPOCH + Duration::from_millis(0x0102_0304_0506),
+            mac: vec![0xbb; 32],
+        }
+    }
+
+    #[test]
+    fn test_challenge_layout() {
+        let encoded = challenge().encode();
+        let mut expected = vec![WIRE_VERSION, 1, Algorithm::Sha3Kmac256.id(), 16];
+        expected.extend_from_slice(&[0xaa; 16]);
+        expected.extend_from_slice(&[0, 0, 1, 2, 3, 4, 5, 6]);
+        expected.push(32);
+        expected.extend_from_slice(&[0xbb; 32]);
+
+        assert_eq!(encoded, expected);
+        assert_eq!(ChallengeMessage::decode(&encoded), Ok(challenge()));
+    }
+
+    #[test]
+    fn test_response_round_trip() {
+        let passcode = Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+        let response = ResponseMessage::compute(&passcode, &challenge());
+        let encoded = response.encode();
+
+        assert_eq!(
+            encoded[..4],
+            [WIRE_VERSION, 2, Algorithm::Sha3Kmac256.id(), 16]
+        );
+        assert_eq!(response.mac.len(), Algorithm::Sha3Kmac256.otp_bytes());
+        assert_eq!(ResponseMessage::decode(&encoded), Ok(response));
+    }
+
+    #[test]
+    fn test_rejects_malformed() {
+        let encoded = challenge().encode();
+
+        let mut version = encoded.clone();
+        version[0] = 2;
+        assert_eq!(
+            ChallengeMessage::decode(&version),
+            Err(Error::UnsupportedVersion {
+                version: 2,
+                min: MIN_WIRE_VERSION,
+                max: WIRE_VERSION,
+            })
+        );
+        assert_eq!(
+            ResponseMessage::decode(&encoded),
+            Err(Error::MalformedMessage("unexpected message type"))
+        );
+        assert_eq!(
+            ChallengeMessage::decode(&encoded[..encoded.len() - 1]),
+            Err(Error::MalformedMessage("truncated message"))
+        );
+        let mut trailing = encoded.clone();
+        trailing.push(0);
+        assert_eq!(
+            ChallengeMessage::decode(&trailing),
+            Err(Error::MalformedMessage("trailing bytes"))
+        );
+        let mut algorithm = encoded;
+        algorithm[2] = 0xff;
+        assert_eq!(
+            ChallengeMessage::decode(&algorithm),
+            Err(Error::MalformedMessage("unknown algorithm"))
+        );
+    }
+
+    #[test]
+    fn test_version_ranges() {
+        assert_eq!(WireVersions::new(1, 1), Ok(WireVersions::current()));
+        assert_eq!(
+            WireVersions::new(0, 1),
+            Err(Error::UnsupportedVersion {
+                version: 0,
+                min: MIN_WIRE_VERSION,
+                max: WIRE_VERSION,
+            })
+        );
+        assert!(WireVersions::new(1, WIRE_VERSION + 1).is_err());
+        assert_eq!(
+            WireVersions::new(WIRE_VERSION, WIRE_VERSION - 1),
+            Err(Error::InvalidFormat("empty wire version range"))
+        );
+
+        let transitional = WireVersions::transitional();
+        assert!(transitional.accepts(WIRE_VERSION));
+        assert!(transitional.min() >= MIN_WIRE_VERSION);
+        assert!(transitional.max() - transitional.min() <= 1);
+        assert!(!transitional.accepts(WIRE_VERSION + 1));
+        assert_eq!(WireVersions::default(), WireVersions::supported());
+    }
+
+    #[test]
+    fn test_negotiate() {
+        // Ranges are built directly to model versions this build lacks
+        let old = WireVersions { min: 1, max: 2 };
+        let new = WireVersions { min: 2, max: 3 };
+        let newest = WireVersions { min: 3, max: 3 };
+
+        assert_eq!(old.negotiate(&new), Ok(2));
+        assert_eq!(new.negotiate(&old), Ok(2));
+        assert_eq!(new.negotiate(&newest), Ok(3));
+        assert_eq!(
+            old.negotiate(&newest),
+            Err(Error::UnsupportedVersion {
+                version: 3,
+                min: 1,
+                max: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn test_decode_with_versions() {
+        let encoded = challenge().encode_version(WIRE_VERSION).unwrap();
+        assert_eq!(peek_version(&encoded), Ok(WIRE_VERSION));
+        assert_eq!(
+            ChallengeMessage::decode_with(&encoded, &WireVersions::transitional()),
+            Ok(challenge())
+        );
+        assert!(challenge().encode_version(WIRE_VERSION + 1).is_err());
+        assert!(peek_version(&[]).is_err());
+
+        let passcode = Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+        let response = ResponseMessage::compute(&passcode, &challenge());
+        let mut newer = response.encode();
+        newer[0] = WIRE_VERSION + 1;
+        let future = WireVersions {
+            min: WIRE_VERSION,
+            max: WIRE_VERSION + 1,
+        };
+        assert_eq!(
+            ResponseMessage::decode_with(&newer, &future),
+            Ok(response.clone())
+        );
+        assert_eq!(
+            ResponseMessage::decode_with(&newer, &WireVersions::current()),
+            Err(Error::UnsupportedVersion {
+                version: WIRE_VERSION + 1,
+                min: WIRE_VERSION,
+                max: WIRE_VERSION,
+            })
+        );
+    }
+}
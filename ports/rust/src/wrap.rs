@@ -0,0 +1,301 @@
+//! AES key wrapping for keys at rest (feature `key-wrap`)
+//!
+//! Databases holding OTP secrets should store them wrapped under a key
+//! encryption key kept in a KMS or HSM. The server fetches or unseals the
+//! [`WrappingKey`] at startup, [`wrap_key`] seals each user's key before it
+//! is written, and [`KeyRing::load_wrapped`] unwraps the stored keys when
+//! the key ring is loaded. A database dump alone reveals no secret.
+//!
+//! Keys are wrapped with AES-256 Key Wrap with Padding (RFC 5649). The
+//! wrapped key is
+//!
+//! | Field | Length |
+//! |---|---|
+//! | version, [`WRAP_VERSION`] | 1 byte |
+//! | [`KeyId`] of the wrapping key | 8 bytes |
+//! | AES-KWP of the [`Algorithm::id`], the user digest and the key | 8 bytes + 33 + key length rounded up to 8 |
+//!
+//! The Key-ID tells which wrapping key to unwrap with while wrapping keys
+//! are rotated, see [`wrapping_key_id`]. The user digest is KMAC256 of the
+//! user ID under [`labels::WRAP_USER`], so a key copied into another user's
+//! row fails to unwrap rather than becoming that user's key.
+//!
+//! # Example
+//! ```
+//! use passcode::keyring::KeyRing;
+//! use passcode::wrap::{wrap_key, WrappingKey};
+//! use passcode::{Algorithm, Passcode};
+//!
+//! let kek = WrappingKey::new(&[9u8; 32]).unwrap();
+//! let alice = Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+//! let stored = wrap_key(&kek, "alice", &alice);
+//!
+//! let keyring = KeyRing::load_wrapped(&kek, [("alice", stored)]).unwrap();
+//! assert_eq!(keyring.get("alice").unwrap().key_id(), alice.key_id());
+//! ```
+
+use std::fmt;
+use std::sync::Arc;
+
+use aes_kw::KekAes256;
+
+use crate::kdf::{derive_subkey, key_fingerprint, labels, KeyId, KEY_ID_LEN};
+use crate::keyring::KeyRing;
+use crate::{Algorithm, Error, Passcode};
+
+/// Version byte of the wrapped key format
+pub const WRAP_VERSION: u8 = 2;
+
+/// Length in bytes of a [`WrappingKey`]
+pub const WRAPPING_KEY_LEN: usize = 32;
+
+/// Version and wrapping Key-ID
+const HEADER_LEN: usize = 1 + KEY_ID_LEN;
+/// Integrity check value prepended by AES-KWP
+const ICV_LEN: usize = 8;
+/// Digest of the user ID in the wrapped plaintext
+const USER_DIGEST_LEN: usize = 32;
+
+/// AES-256 key encryption key
+pub struct WrappingKey {
+    kek: KekAes256,
+    id: KeyId,
+}
+
+impl fmt::Debug for WrappingKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Keys are never printed
+        f.debug_struct("WrappingKey")
+            .field("key_id", &self.id)
+            .finish()
+    }
+}
+
+impl WrappingKey {
+    /// Creates a wrapping key from 32 bytes of key material
+    ///
+    /// Fails with [`Error::InvalidFormat`] for any other length.
+    pub fn new(key: &[u8]) -> Result<Self, Error> {
+        let kek = KekAes256::try_from(key)
+            .map_err(|_| Error::InvalidFormat("wrapping key must be 32 bytes"))?;
+        Ok(Self {
+            kek,
+            id: key_fingerprint(key),
+        })
+    }
+
+    /// Gets the fingerprint of the wrapping key, stored with every key it
+    /// wraps
+    pub fn key_id(&self) -> KeyId {
+        self.id
+    }
+}
+
+/// Wraps the algorithm and key of `passcode` for storage as `user_id`'s key
+///
+/// Output formatting options are not part of the wrapped key.
+///
+/// # Panics
+/// Panics if the key is held by a [`MacBackend`](crate::MacBackend), which
+/// never reveals it; check [`Passcode::has_backend`] first.
+pub fn wrap_key(wrapping_key: &WrappingKey, user_id: &str, passcode: &Passcode) -> Vec<u8> {
+    let mut plaintext = Vec::with_capacity(1 + USER_DIGEST_LEN + passcode.key().len());
+    plaintext.push(passcode.algorithm().id());
+    plaintext.extend_from_slice(&user_digest(user_id));
+    plaintext.extend_from_slice(passcode.key());
+
+    let mut wrapped = Vec::with_capacity(HEADER_LEN + ICV_LEN + plaintext.len() + 7);
+    wrapped.push(WRAP_VERSION);
+    wrapped.extend_from_slice(wrapping_key.id.as_bytes());
+    wrapped.extend_from_slice(
+        &wrapping_key
+            .kek
+            .wrap_with_padding_vec(&plaintext)
+            .expect("plaintext is not empty"),
+    );
+    wrapped
+}
+
+/// Unwraps `user_id`'s key written by [`wrap_key`]
+///
+/// Fails with [`Error::MalformedMessage`] for a damaged or unsupported
+/// wrapped key and [`Error::DecryptionFailed`] if it was wrapped under
+/// another wrapping key or for another user, or altered. The unwrapped key
+/// is checked like [`Passcode::try_new`].
+pub fn unwrap_key(
+    wrapping_key: &WrappingKey,
+    user_id: &str,
+    wrapped: &[u8],
+) -> Result<Passcode, Error> {
+    if wrapping_key_id(wrapped)? != wrapping_key.id {
+        return Err(Error::DecryptionFailed);
+    }
+    let plaintext = wrapping_key
+        .kek
+        .unwrap_with_padding_vec(&wrapped[HEADER_LEN..])
+        .map_err(|_| Error::DecryptionFailed)?;
+    let (&id, rest) = plaintext
+        .split_first()
+        .ok_or(Error::MalformedMessage("truncated message"))?;
+    let (digest, key) = rest
+        .split_at_checked(USER_DIGEST_LEN)
+        .ok_or(Error::MalformedMessage("truncated message"))?;
+    if digest != user_digest(user_id) {
+        return Err(Error::DecryptionFailed);
+    }
+    let algorithm = Algorithm::from_id(id).ok_or(Error::MalformedMessage("unknown algorithm"))?;
+    Passcode::try_new(algorithm, key.to_vec())
+}
+
+/// Digests the user ID a key is wrapped for
+fn user_digest(user_id: &str) -> Vec<u8> {
+    derive_subkey(user_id.as_bytes(), labels::WRAP_USER, b"", USER_DIGEST_LEN)
+}
+
+/// Gets the Key-ID of the wrapping key a key was wrapped under
+pub fn wrapping_key_id(wrapped: &[u8]) -> Result<KeyId, Error> {
+    if wrapped.len() < HEADER_LEN + 2 * ICV_LEN {
+        return Err(Error::MalformedMessage("truncated message"));
+    }
+    if wrapped[0] != WRAP_VERSION {
+        return Err(Error::MalformedMessage("unsupported wrapped key version"));
+    }
+    Ok(KeyId::from_bytes(
+        wrapped[1..HEADER_LEN].try_into().expect("8 bytes"),
+    ))
+}
+
+impl KeyRing {
+    /// Creates a key ring from wrapped keys by user ID, as stored in a
+    /// database
+    ///
+    /// Fails on the first key that does not unwrap, see [`unwrap_key`].
+    pub fn load_wrapped<I, U, W>(wrapping_key: &WrappingKey, records: I) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = (U, W)>,
+        U: Into<String>,
+        W: AsRef<[u8]>,
+    {
+        let keyring = KeyRing::new();
+        for (user_id, wrapped) in records {
+            keyring.insert_wrapped(user_id, wrapped.as_ref(), wrapping_key)?;
+        }
+        Ok(keyring)
+    }
+
+    /// Unwraps a key and sets it as the user's passcode, returning the one
+    /// it replaces
+    ///
+    /// Like [`insert`](Self::insert), registered devices stay registered. A
+    /// key wrapped for another user fails with [`Error::DecryptionFailed`].
+    pub fn insert_wrapped(
+        &self,
+        user_id: impl Into<String>,
+        wrapped: &[u8],
+        wrapping_key: &WrappingKey,
+    ) -> Result<Option<Arc<Passcode>>, Error> {
+        let user_id = user_id.into();
+        let passcode = unwrap_key(wrapping_key, &user_id, wrapped)?;
+        Ok(self.insert(user_id, passcode))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kek() -> WrappingKey {
+        WrappingKey::new(&[9u8; 32]).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for algorithm in Algorithm::all() {
+            let passcode = Passcode::new(algorithm, algorithm.random_key());
+            let wrapped = wrap_key(&kek(), "alice", &passcode);
+            assert_eq!(wrapping_key_id(&wrapped), Ok(kek().key_id()));
+
+            let unwrapped = unwrap_key(&kek(), "alice", &wrapped).unwrap();
+            assert_eq!(unwrapped.algorithm(), algorithm);
+            assert_eq!(unwrapped.key(), passcode.key());
+        }
+        assert_eq!(
+            wrap_key(
+                &kek(),
+                "alice",
+                &Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32])
+            )
+            .len(),
+            HEADER_LEN + ICV_LEN + 72
+        );
+    }
+
+    #[test]
+    fn test_rejects_wrong_key_and_tampering() {
+        let wrapped = wrap_key(
+            &kek(),
+            "alice",
+            &Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]),
+        );
+        let other = WrappingKey::new(&[8u8; 32]).unwrap();
+        assert_eq!(
+            unwrap_key(&other, "alice", &wrapped).err(),
+            Some(Error::DecryptionFailed)
+        );
+        assert_eq!(
+            unwrap_key(&kek(), "bob", &wrapped).err(),
+            Some(Error::DecryptionFailed)
+        );
+
+        // A forged Key-ID does not get past the integrity check
+        let mut forged = wrapped.clone();
+        forged[1..HEADER_LEN].copy_from_slice(other.key_id().as_bytes());
+        assert_eq!(
+            unwrap_key(&other, "alice", &forged).err(),
+            Some(Error::DecryptionFailed)
+        );
+
+        let mut tampered = wrapped.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert_eq!(
+            unwrap_key(&kek(), "alice", &tampered).err(),
+            Some(Error::DecryptionFailed)
+        );
+
+        let mut version = wrapped.clone();
+        version[0] = 1;
+        assert!(matches!(
+            unwrap_key(&kek(), "alice", &version),
+            Err(Error::MalformedMessage(_))
+        ));
+        assert!(unwrap_key(&kek(), "alice", &wrapped[..HEADER_LEN + 8]).is_err());
+        assert!(WrappingKey::new(&[9u8; 16]).is_err());
+    }
+
+    #[test]
+    fn test_load_wrapped() {
+        let alice = Passcode::new(Algorithm::Sha3Kmac256, vec![1u8; 32]);
+        let bob = Passcode::new(Algorithm::Blake3KeyedMode256, vec![2u8; 32]);
+        let records = vec![
+            ("alice".to_string(), wrap_key(&kek(), "alice", &alice)),
+            ("bob".to_string(), wrap_key(&kek(), "bob", &bob)),
+        ];
+
+        let keyring = KeyRing::load_wrapped(&kek(), records.clone()).unwrap();
+        assert_eq!(keyring.len(), 2);
+        assert_eq!(keyring.get("bob").unwrap().key_id(), bob.key_id());
+
+        let other = WrappingKey::new(&[8u8; 32]).unwrap();
+        assert_eq!(
+            KeyRing::load_wrapped(&other, records.clone()).err(),
+            Some(Error::DecryptionFailed)
+        );
+
+        // Bob cannot take over alice's row with his own wrapped key
+        let swapped = vec![("alice".to_string(), records[1].1.clone())];
+        assert_eq!(
+            KeyRing::load_wrapped(&kek(), swapped).err(),
+            Some(Error::DecryptionFailed)
+        );
+    }
+}
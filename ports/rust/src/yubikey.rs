@@ -0,0 +1,592 @@
+//! YubiKey challenge-response (feature `yubikey`)
+//!
+//! [`YubiKey`] is a [`MacBackend`] computing HMAC-SHA-1 in a slot of the
+//! YubiKey's OTP application, so the secret lives on the token. It talks to
+//! the key over USB HID feature reports through libusb; no YubiKey software
+//! is needed at runtime. On Linux the process needs access to the USB device,
+//! e.g. through the udev rules that come with `yubikey-personalization`.
+//!
+//! The slot has to be programmed for variable-length HMAC-SHA1
+//! challenge-response with a key the server also holds, e.g.
+//!
+//! ```text
+//! ykman otp chalresp 2 <hex key>
+//! ```
+//!
+//! ```no_run
+//! use passcode::yubikey::{Slot, YubiKey};
+//! use passcode::Passcode;
+//!
+//! let key = YubiKey::open(Slot::Two).unwrap();
+//! let passcode = Passcode::from_backend(key).unwrap();
+//! let otp = passcode.try_compute(b"challenge").unwrap();
+//! ```
+//!
+//! A slot takes challenges of at most 63 bytes, so longer data fails with
+//! [`Error::Hardware`]. A slot that requires touch blocks each computation
+//! until the button is pressed, for at most 15 seconds.
+
+use std::fmt;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rusb::{Direction, Recipient, RequestType};
+
+use crate::kdf::{key_fingerprint, KeyId};
+use crate::{Algorithm, Error, MacBackend};
+
+const YUBICO_VENDOR_ID: u16 = 0x1050;
+const HID_GET_REPORT: u8 = 0x01;
+const HID_SET_REPORT: u8 = 0x09;
+const FEATURE_REPORT: u16 = 0x0300;
+const INTERFACE_CLASS_HID: u8 = 3;
+const INTERFACE_PROTOCOL_KEYBOARD: u8 = 1;
+const USB_TIMEOUT: Duration = Duration::from_secs(1);
+
+const CMD_DEVICE_SERIAL: u8 = 0x10;
+const CMD_CHALLENGE_HMAC_1: u8 = 0x30;
+const CMD_CHALLENGE_HMAC_2: u8 = 0x38;
+const SLOT_WRITE_FLAG: u8 = 0x80;
+const RESP_PENDING_FLAG: u8 = 0x40;
+const RESP_TIMEOUT_WAIT_FLAG: u8 = 0x20;
+const SEQUENCE_MASK: u8 = 0x1f;
+const DUMMY_REPORT_WRITE: u8 = 0x8f;
+/// CRC of data followed by its complemented CRC
+const CRC_OK_RESIDUAL: u16 = 0xf0b8;
+
+const PAYLOAD_LEN: usize = 64;
+/// Payload, command, CRC and filler, sent seven bytes per report
+const FRAME_LEN: usize = 70;
+const REPORT_DATA_LEN: usize = 7;
+/// Longest challenge the slot can tell apart from its padding
+const MAX_CHALLENGE: usize = PAYLOAD_LEN - 1;
+const HMAC_RESPONSE_LEN: usize = 20;
+const SERIAL_RESPONSE_LEN: usize = 4;
+const WRITE_TIMEOUT: Duration = Duration::from_millis(1000);
+const TOUCH_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Challenge-response slot of the OTP application
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Slot {
+    /// Slot 1, the short touch
+    One,
+    /// Slot 2, the long touch
+    Two,
+}
+
+impl Slot {
+    fn command(self) -> u8 {
+        match self {
+            Slot::One => CMD_CHALLENGE_HMAC_1,
+            Slot::Two => CMD_CHALLENGE_HMAC_2,
+        }
+    }
+
+    fn number(self) -> u8 {
+        match self {
+            Slot::One => 1,
+            Slot::Two => 2,
+        }
+    }
+}
+
+/// Exchanges the 8-byte feature reports of the OTP application
+trait Reports: Send {
+    /// Takes the interface for the duration of one command
+    fn claim(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn release(&mut self) {}
+
+    fn read(&mut self) -> Result<[u8; 8], Error>;
+
+    fn write(&mut self, report: &[u8; 8]) -> Result<(), Error>;
+}
+
+/// OTP interface of a YubiKey on the USB bus
+struct Usb {
+    handle: rusb::DeviceHandle<rusb::GlobalContext>,
+    interface: u8,
+}
+
+impl Usb {
+    /// Opens the OTP interface, the keyboard one, of a Yubico device
+    fn open(device: &rusb::Device<rusb::GlobalContext>) -> Result<Option<Self>, rusb::Error> {
+        if device.device_descriptor()?.vendor_id() != YUBICO_VENDOR_ID {
+            return Ok(None);
+        }
+        let config = device.active_config_descriptor()?;
+        let interface = config
+            .interfaces()
+            .flat_map(|interface| interface.descriptors())
+            .find(|descriptor| {
+                descriptor.class_code() == INTERFACE_CLASS_HID
+                    && descriptor.protocol_code() == INTERFACE_PROTOCOL_KEYBOARD
+            })
+            .map(|descriptor| descriptor.interface_number());
+        let Some(interface) = interface else {
+            return Ok(None);
+        };
+
+        let handle = device.open()?;
+        // Not every platform can detach the keyboard driver; claiming the
+        // interface reports the failure then
+        let _ = handle.set_auto_detach_kernel_driver(true);
+        Ok(Some(Self { handle, interface }))
+    }
+}
+
+fn usb_error(e: rusb::Error) -> Error {
+    Error::Hardware(format!("YubiKey USB transfer failed: {}", e))
+}
+
+impl Reports for Usb {
+    fn claim(&mut self) -> Result<(), Error> {
+        self.handle
+            .claim_interface(self.interface)
+            .map_err(usb_error)
+    }
+
+    fn release(&mut self) {
+        // Gives the keyboard interface back to the kernel
+        let _ = self.handle.release_interface(self.interface);
+    }
+
+    fn read(&mut self) -> Result<[u8; 8], Error> {
+        let mut report = [0u8; 8];
+        let request_type =
+            rusb::request_type(Direction::In, RequestType::Class, Recipient::Interface);
+        let len = self
+            .handle
+            .read_control(
+                request_type,
+                HID_GET_REPORT,
+                FEATURE_REPORT,
+                self.interface.into(),
+                &mut report,
+                USB_TIMEOUT,
+            )
+            .map_err(usb_error)?;
+        if len != report.len() {
+            return Err(Error::Hardware("YubiKey sent a short report".to_string()));
+        }
+        Ok(report)
+    }
+
+    fn write(&mut self, report: &[u8; 8]) -> Result<(), Error> {
+        let request_type =
+            rusb::request_type(Direction::Out, RequestType::Class, Recipient::Interface);
+        self.handle
+            .write_control(
+                request_type,
+                HID_SET_REPORT,
+                FEATURE_REPORT,
+                self.interface.into(),
+                report,
+                USB_TIMEOUT,
+            )
+            .map_err(usb_error)?;
+        Ok(())
+    }
+}
+
+/// HMAC-SHA-1 key held in a YubiKey challenge-response slot
+pub struct YubiKey {
+    reports: Mutex<Box<dyn Reports>>,
+    slot: Slot,
+    serial: u32,
+    key_id: KeyId,
+}
+
+impl YubiKey {
+    /// Opens a slot of the first YubiKey found
+    ///
+    /// Fails with [`Error::Hardware`] if no YubiKey is connected or its
+    /// serial number cannot be read.
+    pub fn open(slot: Slot) -> Result<Self, Error> {
+        Self::find(slot, |_| true)
+    }
+
+    /// Opens a slot of the YubiKey with the serial number
+    pub fn open_serial(serial: u32, slot: Slot) -> Result<Self, Error> {
+        Self::find(slot, |found| found == serial)
+    }
+
+    fn find(slot: Slot, matches: impl Fn(u32) -> bool) -> Result<Self, Error> {
+        let devices = rusb::devices().map_err(usb_error)?;
+        let mut last_error = None;
+        for device in devices.iter() {
+            let usb = match Usb::open(&device) {
+                Ok(Some(usb)) => usb,
+                Ok(None) => continue,
+                Err(e) => {
+                    last_error = Some(usb_error(e));
+                    continue;
+                }
+            };
+            match Self::with_reports(Box::new(usb), slot) {
+                Ok(key) if matches(key.serial) => return Ok(key),
+                Ok(_) => {}
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| Error::Hardware("no YubiKey found".to_string())))
+    }
+
+    /// Reads the serial number of the key behind the reports
+    fn with_reports(reports: Box<dyn Reports>, slot: Slot) -> Result<Self, Error> {
+        let reports = Mutex::new(reports);
+        let response = command(&reports, CMD_DEVICE_SERIAL, &[], SERIAL_RESPONSE_LEN)?;
+        let serial = u32::from_be_bytes([response[0], response[1], response[2], response[3]]);
+
+        let mut identity = serial.to_be_bytes().to_vec();
+        identity.push(slot.number());
+        Ok(Self {
+            reports,
+            slot,
+            serial,
+            key_id: key_fingerprint(&identity),
+        })
+    }
+
+    /// Gets the serial number of the YubiKey
+    pub fn serial(&self) -> u32 {
+        self.serial
+    }
+
+    /// Gets the slot computing the MACs
+    pub fn slot(&self) -> Slot {
+        self.slot
+    }
+}
+
+impl MacBackend for YubiKey {
+    fn algorithm(&self) -> Algorithm {
+        Algorithm::HmacSha1
+    }
+
+    /// The fingerprint of the serial number and slot, since the key inside
+    /// the slot cannot be read back
+    fn key_id(&self) -> KeyId {
+        self.key_id
+    }
+
+    fn mac(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        if data.len() > MAX_CHALLENGE {
+            return Err(Error::Hardware(format!(
+                "YubiKey challenges are at most {} bytes",
+                MAX_CHALLENGE
+            )));
+        }
+        command(&self.reports, self.slot.command(), data, HMAC_RESPONSE_LEN).map_err(|e| match e {
+            Error::Hardware(message) if message == NO_RESPONSE => Error::Hardware(format!(
+                "YubiKey slot {} is not configured for challenge-response",
+                self.slot.number()
+            )),
+            e => e,
+        })
+    }
+}
+
+impl fmt::Debug for YubiKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("YubiKey")
+            .field("serial", &self.serial)
+            .field("slot", &self.slot)
+            .field("key_id", &self.key_id)
+            .finish()
+    }
+}
+
+const NO_RESPONSE: &str = "YubiKey did not respond to the command";
+
+/// Sends a command and reads the `expected` bytes of its response
+fn command(
+    reports: &Mutex<Box<dyn Reports>>,
+    command: u8,
+    payload: &[u8],
+    expected: usize,
+) -> Result<Vec<u8>, Error> {
+    let mut reports = reports.lock().unwrap_or_else(|e| e.into_inner());
+    reports.claim()?;
+    let result =
+        write_frame(reports.as_mut(), command, payload).and_then(|()| read_frame(reports.as_mut()));
+    reports.release();
+
+    let response = result?;
+    if response.len() < expected + 2 || crc16(&response[..expected + 2]) != CRC_OK_RESIDUAL {
+        return Err(Error::Hardware(
+            "YubiKey response failed its CRC".to_string(),
+        ));
+    }
+    Ok(response[..expected].to_vec())
+}
+
+/// Writes the framed command, skipping all-zero reports in the middle
+fn write_frame(reports: &mut dyn Reports, command: u8, payload: &[u8]) -> Result<(), Error> {
+    let mut frame = [0u8; FRAME_LEN];
+    frame[..payload.len()].copy_from_slice(payload);
+    // The slot strips trailing bytes equal to the last one, so the padding
+    // has to differ from the challenge's last byte
+    if payload.len() < PAYLOAD_LEN && payload.last() == Some(&0) {
+        frame[payload.len()..PAYLOAD_LEN].fill(1);
+    }
+    frame[PAYLOAD_LEN] = command;
+    let crc = crc16(&frame[..PAYLOAD_LEN]);
+    frame[PAYLOAD_LEN + 1..PAYLOAD_LEN + 3].copy_from_slice(&crc.to_le_bytes());
+
+    let last = FRAME_LEN / REPORT_DATA_LEN - 1;
+    for (seq, chunk) in frame.chunks(REPORT_DATA_LEN).enumerate() {
+        if seq != 0 && seq != last && chunk.iter().all(|&b| b == 0) {
+            continue;
+        }
+        wait_until_writable(reports)?;
+        let mut report = [0u8; 8];
+        report[..REPORT_DATA_LEN].copy_from_slice(chunk);
+        report[REPORT_DATA_LEN] = SLOT_WRITE_FLAG | seq as u8;
+        reports.write(&report)?;
+    }
+    Ok(())
+}
+
+fn wait_until_writable(reports: &mut dyn Reports) -> Result<(), Error> {
+    let deadline = Instant::now() + WRITE_TIMEOUT;
+    while reports.read()?[REPORT_DATA_LEN] & SLOT_WRITE_FLAG != 0 {
+        if Instant::now() >= deadline {
+            return Err(Error::Hardware(
+                "YubiKey did not accept the command".to_string(),
+            ));
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    Ok(())
+}
+
+/// Reads response reports until their sequence number wraps to zero
+fn read_frame(reports: &mut dyn Reports) -> Result<Vec<u8>, Error> {
+    let deadline = Instant::now() + TOUCH_TIMEOUT;
+    let mut response = Vec::new();
+    loop {
+        let report = reports.read()?;
+        let status = report[REPORT_DATA_LEN];
+        if status & RESP_PENDING_FLAG != 0 {
+            let seq = usize::from(status & SEQUENCE_MASK);
+            if seq == response.len() / REPORT_DATA_LEN && response.len() < FRAME_LEN {
+                response.extend_from_slice(&report[..REPORT_DATA_LEN]);
+            } else if seq == 0 {
+                // Leaves read mode
+                let mut reset = [0u8; 8];
+                reset[REPORT_DATA_LEN] = DUMMY_REPORT_WRITE;
+                reports.write(&reset)?;
+                return Ok(response);
+            } else {
+                return Err(Error::Hardware(
+                    "YubiKey response is out of sequence".to_string(),
+                ));
+            }
+        } else if status == 0 {
+            // A plain status report: the command produced nothing
+            return Err(Error::Hardware(NO_RESPONSE.to_string()));
+        } else if Instant::now() >= deadline {
+            return Err(Error::Hardware(
+                "YubiKey timed out waiting for touch".to_string(),
+            ));
+        } else if status & RESP_TIMEOUT_WAIT_FLAG != 0 {
+            thread::sleep(Duration::from_millis(100));
+        } else {
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+}
+
+/// CRC-16/MCRF4XX, the checksum of YubiKey frames
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xffffu16;
+    for &byte in data {
+        crc ^= u16::from(byte);
+        for _ in 0..8 {
+            let carry = crc & 1;
+            crc >>= 1;
+            if carry != 0 {
+                crc ^= 0x8408;
+            }
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hotp::{hmac, HmacAlgorithm};
+    use crate::Passcode;
+    use std::collections::VecDeque;
+
+    const SERIAL: u32 = 12_345_678;
+
+    /// Software YubiKey answering the OTP application protocol
+    struct FakeYubiKey {
+        slots: [Option<Vec<u8>>; 2],
+        frame: [u8; FRAME_LEN],
+        pending: VecDeque<[u8; 8]>,
+        touch: bool,
+    }
+
+    impl FakeYubiKey {
+        fn new(slot_two: &[u8]) -> Self {
+            Self {
+                slots: [None, Some(slot_two.to_vec())],
+                frame: [0u8; FRAME_LEN],
+                pending: VecDeque::new(),
+                touch: false,
+            }
+        }
+
+        fn process(&mut self) {
+            let frame = std::mem::replace(&mut self.frame, [0u8; FRAME_LEN]);
+            let payload = &frame[..PAYLOAD_LEN];
+            let crc = u16::from_le_bytes([frame[PAYLOAD_LEN + 1], frame[PAYLOAD_LEN + 2]]);
+            assert_eq!(crc, crc16(payload));
+
+            let key = match frame[PAYLOAD_LEN] {
+                CMD_DEVICE_SERIAL => {
+                    return self.respond(&SERIAL.to_be_bytes());
+                }
+                CMD_CHALLENGE_HMAC_1 => &self.slots[0],
+                CMD_CHALLENGE_HMAC_2 => &self.slots[1],
+                other => panic!("unexpected command {:#x}", other),
+            };
+            let Some(key) = key else {
+                return;
+            };
+            // Variable-length challenges end before the padding
+            let last = payload[PAYLOAD_LEN - 1];
+            let len = payload
+                .iter()
+                .rposition(|&b| b != last)
+                .map_or(0, |i| i + 1);
+            let response = hmac(HmacAlgorithm::Sha1, key, &payload[..len]);
+            if self.touch {
+                let mut waiting = [0u8; 8];
+                waiting[REPORT_DATA_LEN] = RESP_TIMEOUT_WAIT_FLAG;
+                self.pending.push_back(waiting);
+            }
+            self.respond(&response);
+        }
+
+        fn respond(&mut self, data: &[u8]) {
+            let mut response = data.to_vec();
+            response.extend_from_slice(&(!crc16(data)).to_le_bytes());
+            for (seq, chunk) in response.chunks(REPORT_DATA_LEN).enumerate() {
+                let mut report = [0u8; 8];
+                report[..chunk.len()].copy_from_slice(chunk);
+                report[REPORT_DATA_LEN] = RESP_PENDING_FLAG | seq as u8;
+                self.pending.push_back(report);
+            }
+            let mut done = [0u8; 8];
+            done[REPORT_DATA_LEN] = RESP_PENDING_FLAG;
+            self.pending.push_back(done);
+        }
+    }
+
+    impl Reports for FakeYubiKey {
+        fn read(&mut self) -> Result<[u8; 8], Error> {
+            Ok(self.pending.pop_front().unwrap_or([0u8; 8]))
+        }
+
+        fn write(&mut self, report: &[u8; 8]) -> Result<(), Error> {
+            let status = report[REPORT_DATA_LEN];
+            if status == DUMMY_REPORT_WRITE {
+                self.pending.clear();
+                return Ok(());
+            }
+            assert_ne!(status & SLOT_WRITE_FLAG, 0);
+            let seq = usize::from(status & SEQUENCE_MASK);
+            self.frame[seq * REPORT_DATA_LEN..(seq + 1) * REPORT_DATA_LEN]
+                .copy_from_slice(&report[..REPORT_DATA_LEN]);
+            if seq == FRAME_LEN / REPORT_DATA_LEN - 1 {
+                self.process();
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_crc16() {
+        assert_eq!(crc16(b"123456789"), 0x6f91);
+        let mut data = b"123456789".to_vec();
+        data.extend_from_slice(&(!crc16(&data)).to_le_bytes());
+        assert_eq!(crc16(&data), CRC_OK_RESIDUAL);
+    }
+
+    #[test]
+    fn test_matches_software_key() {
+        let key = [7u8; 20];
+        let yubikey = YubiKey::with_reports(Box::new(FakeYubiKey::new(&key)), Slot::Two).unwrap();
+        assert_eq!(yubikey.serial(), SERIAL);
+        assert_ne!(
+            yubikey.key_id(),
+            YubiKey::with_reports(Box::new(FakeYubiKey::new(&key)), Slot::One)
+                .unwrap()
+                .key_id()
+        );
+
+        let hardware = Passcode::from_backend(yubikey).unwrap();
+        let software = Passcode::new(Algorithm::HmacSha1, key.to_vec());
+        // Challenges ending in the padding byte must survive the padding
+        for data in [
+            Vec::new(),
+            b"challenge".to_vec(),
+            vec![0u8; 10],
+            vec![1u8; 10],
+            vec![9u8; MAX_CHALLENGE],
+        ] {
+            let otp = hardware.try_compute(&data).unwrap();
+            assert_eq!(otp, software.compute(&data));
+            assert!(software.verify(&data, &otp));
+        }
+        assert_eq!(
+            hardware.derive_session_key(b"challenge", 32),
+            software.derive_session_key(b"challenge", 32)
+        );
+        assert_eq!(
+            hardware.try_compute(&[0u8; MAX_CHALLENGE + 1]),
+            Err(Error::Hardware(
+                "YubiKey challenges are at most 63 bytes".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_touch() {
+        let key = [7u8; 20];
+        let mut yubikey = FakeYubiKey::new(&key);
+        yubikey.touch = true;
+        let hardware =
+            Passcode::from_backend(YubiKey::with_reports(Box::new(yubikey), Slot::Two).unwrap())
+                .unwrap();
+        let software = Passcode::new(Algorithm::HmacSha1, key.to_vec());
+
+        assert_eq!(
+            hardware.try_verify(b"challenge", &software.compute(b"challenge")),
+            Ok(true)
+        );
+    }
+
+    #[test]
+    fn test_unconfigured_slot() {
+        let yubikey =
+            YubiKey::with_reports(Box::new(FakeYubiKey::new(&[7u8; 20])), Slot::One).unwrap();
+        let hardware = Passcode::from_backend(yubikey).unwrap();
+
+        assert_eq!(
+            hardware.try_compute(b"challenge"),
+            Err(Error::Hardware(
+                "YubiKey slot 1 is not configured for challenge-response".to_string()
+            ))
+        );
+        assert!(!hardware.verify(b"challenge", "000000"));
+    }
+}
@@ -201,4 +201,5 @@ fn test_algorithm_display() {
     assert_eq!(Algorithm::Sha3Kmac256.to_string(), "SHA3-KMAC-256");
     assert_eq!(Algorithm::Blake3KeyedMode128.to_string(), "BLAKE3-Keyed-Mode-128");
     assert_eq!(Algorithm::Blake3KeyedMode256.to_string(), "BLAKE3-Keyed-Mode-256");
+    assert_eq!(Algorithm::Sha3Kmac512.to_string(), "SHA3-KMAC-512");
 }
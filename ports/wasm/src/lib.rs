@@ -9,6 +9,7 @@ pub enum Algorithm {
     Sha3Kmac256,
     Blake3KeyedMode128,
     Blake3KeyedMode256,
+    Sha3Kmac512,
 }
 
 impl From<Algorithm> for RustAlgorithm {
@@ -18,6 +19,7 @@ impl From<Algorithm> for RustAlgorithm {
             Algorithm::Sha3Kmac256 => RustAlgorithm::Sha3Kmac256,
             Algorithm::Blake3KeyedMode128 => RustAlgorithm::Blake3KeyedMode128,
             Algorithm::Blake3KeyedMode256 => RustAlgorithm::Blake3KeyedMode256,
+            Algorithm::Sha3Kmac512 => RustAlgorithm::Sha3Kmac512,
         }
     }
 }
@@ -49,7 +51,7 @@ impl Passcode {
     /// * `data` - The challenge data as a Uint8Array
     ///
     /// # Returns
-    /// A 12-character hexadecimal OTP string
+    /// A 12-character hexadecimal OTP string (32 characters for Sha3Kmac512)
     #[wasm_bindgen]
     pub fn compute(&self, data: &[u8]) -> String {
         self.inner.compute(data)
@@ -62,6 +64,12 @@ impl Passcode {
     }
 }
 
+/// Utility function: recommended secret key length in bytes for an algorithm
+#[wasm_bindgen(js_name = recommendedKeyLength)]
+pub fn recommended_key_length(algorithm: Algorithm) -> usize {
+    RustAlgorithm::from(algorithm).recommended_key_len()
+}
+
 /// Utility function: BLAKE3 keyed mode with 256-bit output
 #[wasm_bindgen(js_name = blake3KeyedMode256)]
 pub fn blake3_keyed_mode256(key: &[u8], data: &[u8]) -> Vec<u8> {
@@ -123,6 +123,36 @@ else
     all_match=false
 fi
 
+# Check every implementation against the shared test vectors
+echo ""
+echo "========================================"
+echo "Shared Test Vectors (vectors.json)"
+echo "========================================"
+echo ""
+
+run_vectors() {
+    local name="$1"
+    shift
+    if "$@"; then
+        results["$name vectors"]="✅"
+    else
+        echo -e "${RED}❌ $name does not match the shared test vectors${NC}"
+        results["$name vectors"]="❌"
+        all_match=false
+    fi
+}
+
+run_vectors "Go" go test -run TestVectors .
+run_vectors "Rust" ~/.cargo/bin/cargo test --quiet --manifest-path ../ports/rust/Cargo.toml --features test-vectors --lib vectors
+run_vectors "Node.js-WASM" node vectors_node.js wasm
+run_vectors "Node.js-Lib" node vectors_node.js lib
+if python3 vectors_test.py; then
+    results["Python vectors"]="✅"
+else
+    echo -e "${YELLOW}⚠️  Python vectors failed (may need wasmtime installation)${NC}"
+    results["Python vectors"]="⚠️"
+fi
+
 # Cleanup
 rm -f go_output.txt rust_output.txt node_output.txt nodejs_lib_output.txt python_output.txt
 rm -f go_otps.txt rust_otps.txt node_otps.txt nodejs_lib_otps.txt python_otps.txt
@@ -132,7 +162,7 @@ echo "========================================"
 echo "Summary"
 echo "========================================"
 for impl in "Go" "Rust" "Node.js-WASM" "Node.js-Lib" "Python"; do
-    echo -e "$impl: ${results[$impl]}"
+    echo -e "$impl: ${results[$impl]} (vectors: ${results[$impl vectors]})"
 done
 echo "========================================"
 
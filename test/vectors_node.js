@@ -0,0 +1,37 @@
+// Checks the Node.js bindings against the shared test vectors
+//
+// Usage: node vectors_node.js [wasm|lib]
+const fs = require('fs');
+const path = require('path');
+
+const target = process.argv[2] || 'lib';
+const { Passcode } = target === 'wasm'
+    ? require('../ports/wasm/pkg-node/passcode_wasm')
+    : require('../ports/nodejs');
+
+// Algorithm IDs each binding supports
+const supported = target === 'wasm' ? [0, 1, 2, 3, 4] : [0, 1, 2, 3];
+
+const file = JSON.parse(fs.readFileSync(path.join(__dirname, 'vectors.json'), 'utf8'));
+if (file.version !== 1) {
+    console.error(`Unsupported test vector version ${file.version}`);
+    process.exit(1);
+}
+
+let checked = 0;
+let failed = 0;
+for (const vector of file.vectors) {
+    if (!supported.includes(vector.algorithm_id)) {
+        continue;
+    }
+    const passcode = new Passcode(vector.algorithm_id, Buffer.from(vector.key, 'hex'));
+    const otp = passcode.compute(Buffer.from(vector.challenge, 'hex'));
+    checked++;
+    if (otp !== vector.otp) {
+        console.log(`FAIL ${vector.name}: expected ${vector.otp}, got ${otp}`);
+        failed++;
+    }
+}
+
+console.log(`Node.js (${target}): ${checked - failed}/${checked} vectors passed`);
+process.exit(failed === 0 && checked > 0 ? 0 : 1);
@@ -0,0 +1,48 @@
+import 'dart:convert';
+import 'dart:io';
+import 'dart:typed_data';
+import 'package:passcode/passcode.dart';
+
+/// Checks the Dart binding against the shared test vectors
+void main() {
+  final file = jsonDecode(File('vectors.json').readAsStringSync());
+  if (file['version'] != 1) {
+    print('Unsupported test vector version ${file['version']}');
+    exit(1);
+  }
+
+  // Algorithm IDs in the order of the binding's enum
+  const algorithms = [
+    Algorithm.sha3Kmac128,
+    Algorithm.sha3Kmac256,
+    Algorithm.blake3KeyedMode128,
+    Algorithm.blake3KeyedMode256,
+  ];
+
+  var checked = 0;
+  var failed = 0;
+  for (final vector in file['vectors']) {
+    final id = vector['algorithm_id'] as int;
+    if (id >= algorithms.length) {
+      continue;
+    }
+    final passcode = Passcode(algorithms[id], _hexToBytes(vector['key']));
+    final otp = passcode.compute(_hexToBytes(vector['challenge']));
+    checked++;
+    if (otp != vector['otp']) {
+      print('FAIL ${vector['name']}: expected ${vector['otp']}, got $otp');
+      failed++;
+    }
+  }
+
+  print('Dart: ${checked - failed}/$checked vectors passed');
+  exit(failed == 0 && checked > 0 ? 0 : 1);
+}
+
+Uint8List _hexToBytes(String hex) {
+  final bytes = <int>[];
+  for (var i = 0; i < hex.length; i += 2) {
+    bytes.add(int.parse(hex.substring(i, i + 2), radix: 16));
+  }
+  return Uint8List.fromList(bytes);
+}
@@ -0,0 +1,54 @@
+package main
+
+import (
+	"encoding/hex"
+	"encoding/json"
+	"os"
+	"testing"
+
+	"github.com/snowmerak/passcode"
+)
+
+type testVectors struct {
+	Version int `json:"version"`
+	Vectors []struct {
+		Name      string `json:"name"`
+		Algorithm string `json:"algorithm"`
+		Key       string `json:"key"`
+		Challenge string `json:"challenge"`
+		OTP       string `json:"otp"`
+	} `json:"vectors"`
+}
+
+// TestVectors checks the Go implementation against the shared test vectors
+func TestVectors(t *testing.T) {
+	data, err := os.ReadFile("vectors.json")
+	if err != nil {
+		t.Fatal(err)
+	}
+	var file testVectors
+	if err := json.Unmarshal(data, &file); err != nil {
+		t.Fatal(err)
+	}
+	if file.Version != 1 {
+		t.Fatalf("unsupported test vector version %d", file.Version)
+	}
+
+	checked := 0
+	for _, vector := range file.Vectors {
+		key, _ := hex.DecodeString(vector.Key)
+		challenge, _ := hex.DecodeString(vector.Challenge)
+		pc, err := passcode.NewPasscode(passcode.Algorithm(vector.Algorithm), key)
+		if err != nil {
+			// Algorithm not implemented in Go
+			continue
+		}
+		checked++
+		if otp := pc.Compute(challenge); otp != vector.OTP {
+			t.Errorf("%s: expected %s, got %s", vector.Name, vector.OTP, otp)
+		}
+	}
+	if checked == 0 {
+		t.Fatal("no vectors checked")
+	}
+}
@@ -0,0 +1,36 @@
+#!/usr/bin/env python3
+"""Checks the Python binding against the shared test vectors"""
+
+import json
+import sys
+from pathlib import Path
+
+sys.path.insert(0, str(Path(__file__).parent.parent / "ports" / "python"))
+
+from passcode_py import Passcode, Algorithm
+
+
+def main():
+    file = json.loads((Path(__file__).parent / "vectors.json").read_text())
+    if file["version"] != 1:
+        print(f"Unsupported test vector version {file['version']}")
+        return 1
+
+    supported = {algorithm.value for algorithm in Algorithm}
+    checked = failed = 0
+    for vector in file["vectors"]:
+        if vector["algorithm_id"] not in supported:
+            continue
+        passcode = Passcode(Algorithm(vector["algorithm_id"]), bytes.fromhex(vector["key"]))
+        otp = passcode.compute(bytes.fromhex(vector["challenge"]))
+        checked += 1
+        if otp != vector["otp"]:
+            print(f"FAIL {vector['name']}: expected {vector['otp']}, got {otp}")
+            failed += 1
+
+    print(f"Python: {checked - failed}/{checked} vectors passed")
+    return 0 if failed == 0 and checked > 0 else 1
+
+
+if __name__ == "__main__":
+    sys.exit(main())